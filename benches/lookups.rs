@@ -0,0 +1,107 @@
+//! Throughput of the barcode and position lookups made on every scan, with and without the
+//! statement cache of the connection.
+//!
+//! Run with `cargo bench --bench lookups`. Without the cache, each lookup prepares its statement
+//! again; with it, the statement is prepared once and reused.
+use criterion::{criterion_group, criterion_main, Criterion};
+use labwhere::db::{connect_options, init_db_with};
+use labwhere::models::labware::Labware;
+use labwhere::models::layout::{coordinate, Layout};
+use labwhere::models::location::Location;
+use labwhere::models::location_type::LocationType;
+use sqlx::SqliteConnection;
+use std::hint::black_box;
+use tokio::runtime::Runtime;
+
+/// The rows and the columns of the rack the labwares are in, e.g. a 384-well plate.
+const ROWS: u32 = 16;
+const COLUMNS: u32 = 24;
+
+/// How many labwares the database is filled with, one in each well of the rack.
+const LABWARES: u32 = ROWS * COLUMNS;
+
+/// A database with a rack of labwares, each in a well, whose connection keeps up to
+/// `statement_cache_capacity` prepared statements.
+async fn setup(statement_cache_capacity: usize) -> (SqliteConnection, Location) {
+    let options = connect_options("sqlite::memory:")
+        .unwrap()
+        .statement_cache_capacity(statement_cache_capacity);
+    let mut connection = init_db_with(options).await.unwrap();
+    let location_type = LocationType::create("Rack".to_string(), &mut connection)
+        .await
+        .unwrap();
+    let rack = Location::create("rack".to_string(), location_type.id, &mut connection)
+        .await
+        .unwrap();
+    Layout::resize(&rack, ROWS, COLUMNS, &mut connection)
+        .await
+        .unwrap();
+    let rack = Location::find(rack.id, &mut connection).await.unwrap();
+    for i in 0..LABWARES {
+        let (row, column) = well(i);
+        Layout::place(
+            &rack,
+            &coordinate(row, column),
+            format!("lw-{}", i),
+            None,
+            &mut connection,
+        )
+        .await
+        .unwrap();
+    }
+    (connection, rack)
+}
+
+/// The row and the column of the well of the i-th labware.
+fn well(i: u32) -> (u32, u32) {
+    (i / COLUMNS + 1, i % COLUMNS + 1)
+}
+
+fn lookups(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    for (name, statement_cache_capacity) in [("uncached", 0), ("cached", 100)] {
+        let (mut connection, rack) = runtime.block_on(setup(statement_cache_capacity));
+        let barcode = rack.barcode.clone().unwrap();
+        let mut group = c.benchmark_group(name);
+        let mut i = 0;
+        group.bench_function("labware by barcode", |b| {
+            b.iter(|| {
+                i = (i + 1) % LABWARES;
+                runtime
+                    .block_on(Labware::find_by_barcode(
+                        black_box(format!("lw-{}", i)),
+                        &mut connection,
+                    ))
+                    .unwrap()
+            })
+        });
+        group.bench_function("location by barcode", |b| {
+            b.iter(|| {
+                runtime
+                    .block_on(Location::find_by_barcode(
+                        black_box(barcode.clone()),
+                        &mut connection,
+                    ))
+                    .unwrap()
+            })
+        });
+        group.bench_function("labware at a position", |b| {
+            b.iter(|| {
+                i = (i + 1) % LABWARES;
+                let (row, column) = well(i);
+                runtime
+                    .block_on(Layout::labware_at(
+                        &rack,
+                        black_box(row),
+                        black_box(column),
+                        &mut connection,
+                    ))
+                    .unwrap()
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
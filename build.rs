@@ -0,0 +1,4 @@
+// The migrations are embedded by `sqlx::migrate!`, so the crate is rebuilt whenever they change.
+fn main() {
+    println!("cargo:rerun-if-changed=src/db/migrations");
+}
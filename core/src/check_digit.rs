@@ -0,0 +1,195 @@
+use crate::Problem;
+
+/// The character set used by Code 39 mod-43 check characters, in value order.
+/// The position of a character in this string is its value.
+///
+/// Besides letters and digits a check character can be one of `-. $/+%`, so a checked barcode is
+/// percent-encoded when it is put in a URL, and a trailing space is part of it.
+const MOD_43_CHARSET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%";
+
+/// The separator placed between a barcode and its check character, e.g. `lw-freezer-1.7`.
+///
+/// A period can never appear in a generated location barcode otherwise (names are restricted to
+/// alphanumerics, hyphens, spaces and parentheses), so its presence in the second-to-last
+/// position is what marks a barcode as claiming to carry a check character.
+pub const SEPARATOR: char = '.';
+
+/// The check digit algorithms supported for generated barcodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckDigitScheme {
+    /// Weighted modulo 10 (weights 3 and 1 alternating from the right) over the Code 39 value of
+    /// each character. Produces a single decimal digit.
+    Mod10,
+    /// Code 39 modulo 43. Produces a single character from the Code 39 character set.
+    Mod43,
+}
+
+impl CheckDigitScheme {
+    /// Parses a scheme from its configuration name (`mod10` or `mod43`, case-insensitive).
+    pub fn from_name(name: &str) -> Option<CheckDigitScheme> {
+        match name.trim().to_lowercase().as_str() {
+            "mod10" | "mod-10" => Some(CheckDigitScheme::Mod10),
+            "mod43" | "mod-43" => Some(CheckDigitScheme::Mod43),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the Code 39 value of a character, ignoring case.
+fn value_of(c: char) -> Option<u32> {
+    MOD_43_CHARSET
+        .find(c.to_ascii_uppercase())
+        .map(|position| position as u32)
+}
+
+/// Computes the check character for a payload.
+///
+/// Returns `None` if the payload contains a character that cannot be represented in Code 39.
+/// Letters in the returned check character are lowercase so they match generated barcodes.
+///
+/// # Examples
+/// ```
+/// use labwhere_core::check_digit::{compute, CheckDigitScheme};
+/// assert_eq!(compute(CheckDigitScheme::Mod43, "lw-freezer-1"), Some('e'));
+/// ```
+pub fn compute(scheme: CheckDigitScheme, payload: &str) -> Option<char> {
+    let values = payload
+        .chars()
+        .map(value_of)
+        .collect::<Option<Vec<u32>>>()?;
+    match scheme {
+        CheckDigitScheme::Mod10 => {
+            let sum: u32 = values
+                .iter()
+                .rev()
+                .enumerate()
+                .map(|(i, v)| if i % 2 == 0 { v * 3 } else { *v })
+                .sum();
+            char::from_digit((10 - sum % 10) % 10, 10)
+        }
+        CheckDigitScheme::Mod43 => {
+            let sum: u32 = values.iter().sum();
+            MOD_43_CHARSET
+                .chars()
+                .nth((sum % 43) as usize)
+                .map(|c| c.to_ascii_lowercase())
+        }
+    }
+}
+
+/// Appends a check character to a barcode using the separator, e.g. `lw-freezer-1` becomes
+/// `lw-freezer-1.e` with mod-43.
+///
+/// The barcode is returned unchanged if it contains characters which cannot carry a check digit.
+pub fn append(scheme: CheckDigitScheme, barcode: &str) -> String {
+    match compute(scheme, barcode) {
+        Some(check) => format!("{}{}{}", barcode, SEPARATOR, check),
+        None => barcode.to_string(),
+    }
+}
+
+/// Splits a barcode which claims to carry a check character into its payload and check character.
+///
+/// Returns `None` if the barcode does not carry a check character.
+pub fn split(barcode: &str) -> Option<(&str, char)> {
+    let mut chars = barcode.char_indices().rev();
+    let (_, check) = chars.next()?;
+    let (separator_index, separator) = chars.next()?;
+    if separator != SEPARATOR || separator_index == 0 {
+        return None;
+    }
+    Some((&barcode[..separator_index], check))
+}
+
+/// Validates a barcode which claims to carry a check character.
+///
+/// Barcodes without a check character are accepted as they are, so labels printed before check
+/// digits were enabled keep scanning.
+///
+/// # Examples
+/// ```
+/// use labwhere_core::check_digit::{validate, CheckDigitScheme};
+/// assert!(validate(CheckDigitScheme::Mod43, "lw-freezer-1.e").is_ok());
+/// assert!(validate(CheckDigitScheme::Mod43, "lw-freezer-7.e").is_err());
+/// assert!(validate(CheckDigitScheme::Mod43, "lw-freezer-7").is_ok());
+/// ```
+pub fn validate(scheme: CheckDigitScheme, barcode: &str) -> Result<(), Problem> {
+    let Some((payload, check)) = split(barcode) else {
+        return Ok(());
+    };
+    match compute(scheme, payload) {
+        Some(expected) if expected.eq_ignore_ascii_case(&check) => Ok(()),
+        _ => Err(Problem::new("barcode-invalid-check-digit").arg("barcode", barcode)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::check_digit::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            CheckDigitScheme::from_name("mod10"),
+            Some(CheckDigitScheme::Mod10)
+        );
+        assert_eq!(
+            CheckDigitScheme::from_name("MOD-43"),
+            Some(CheckDigitScheme::Mod43)
+        );
+        assert_eq!(CheckDigitScheme::from_name("luhn"), None);
+    }
+
+    #[test]
+    fn test_compute_mod10() {
+        // 0*1 + 1*3 + 2*1 + 3*3 = 14, so the check digit is 6
+        assert_eq!(compute(CheckDigitScheme::Mod10, "0123"), Some('6'));
+        assert_eq!(compute(CheckDigitScheme::Mod10, "lw-freezer-1"), Some('0'));
+    }
+
+    #[test]
+    fn test_compute_mod43() {
+        // Values: 1 + 2 + 3 + 10 (A) = 16 which is G
+        assert_eq!(compute(CheckDigitScheme::Mod43, "123A"), Some('g'));
+        assert_eq!(compute(CheckDigitScheme::Mod43, "lw-freezer-1"), Some('e'));
+        // Values: 36 (-) + 2 = 38 which is a space
+        assert_eq!(compute(CheckDigitScheme::Mod43, "-2"), Some(' '));
+    }
+
+    #[test]
+    fn test_compute_with_unsupported_characters() {
+        assert_eq!(compute(CheckDigitScheme::Mod43, "lw-(box)-1"), None);
+        assert_eq!(
+            append(CheckDigitScheme::Mod43, "lw-(box)-1"),
+            "lw-(box)-1".to_string()
+        );
+    }
+
+    #[test]
+    fn test_append_and_validate() {
+        for scheme in [CheckDigitScheme::Mod10, CheckDigitScheme::Mod43] {
+            let barcode = append(scheme, "lw-location-1-12");
+            assert!(validate(scheme, &barcode).is_ok());
+            assert!(validate(scheme, &barcode.to_uppercase()).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_validate_detects_misreads() {
+        let barcode = append(CheckDigitScheme::Mod10, "lw-freezer-12");
+        let misread = barcode.replace("12", "13");
+        assert!(validate(CheckDigitScheme::Mod10, &misread).is_err());
+
+        let barcode = append(CheckDigitScheme::Mod43, "lw-freezer-12");
+        let misread = barcode.replace("12", "18");
+        assert!(validate(CheckDigitScheme::Mod43, &misread).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("lw-freezer-1.e"), Some(("lw-freezer-1", 'e')));
+        assert_eq!(split("lw-freezer-1.."), Some(("lw-freezer-1", '.')));
+        assert_eq!(split("lw-freezer-1"), None);
+        assert_eq!(split(".h"), None);
+    }
+}
@@ -0,0 +1,48 @@
+//! The domain logic of LabWhere which needs neither a database nor an async runtime: parsing
+//! scanned barcodes, their check digits, and working out a scan before it is sent.
+//!
+//! The server uses this crate for the same rules, and it compiles to `wasm32-unknown-unknown`, so
+//! an offline scan client (e.g. a PWA) can validate and queue scans without a connection and send
+//! them to `POST /scan` once it is back online.
+//!
+//! Nothing which needs a secret belongs here: barcode signatures are checked by the server only.
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+// Every module must keep to the dependencies of Cargo.toml, which all compile to wasm32.
+pub mod check_digit;
+pub mod parser;
+pub mod scan_plan;
+
+/// Why a value was rejected: the key of a message in the catalogs of the server (`locales/` of
+/// LabWhere) and the arguments it is rendered with, which clients translate themselves.
+///
+/// Displaying a problem shows its key.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Problem {
+    /// The key of the message e.g. `barcode-invalid-check-digit`
+    pub key: &'static str,
+    /// The named arguments of the message
+    pub args: Vec<(&'static str, String)>,
+}
+
+impl Problem {
+    pub fn new(key: &'static str) -> Problem {
+        Problem { key, args: vec![] }
+    }
+
+    /// Adds a named argument to the problem
+    pub fn arg(mut self, name: &'static str, value: impl ToString) -> Problem {
+        self.args.push((name, value.to_string()));
+        self
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.key)
+    }
+}
+
+impl Error for Problem {}
@@ -0,0 +1,250 @@
+use crate::check_digit;
+use crate::Problem;
+use percent_encoding::{percent_decode_str, AsciiSet, NON_ALPHANUMERIC};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// The path of the location info page of the server, which the deep links of older QR code labels
+/// point to relative to its base URL.
+pub const LOCATION_PATH: &str = "/locations/";
+
+/// The path of the short links of the server, which redirect any barcode to the page of its
+/// location or labware, and which QR code labels point to relative to its base URL.
+pub const SHORT_LINK_PATH: &str = "/b/";
+
+/// The characters of a barcode which are percent-encoded when it is a path segment of a link, i.e.
+/// all but the unreserved ones. Mod-43 check characters include a space, `/`, `$`, `+` and `%`.
+pub const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The start of an AIM symbology identifier, e.g. `]C1`.
+const AIM_FLAG: char = ']';
+
+/// The symbology a barcode was read from, as reported by an AIM symbology identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Symbology {
+    /// `]A` - Code 39
+    Code39,
+    /// `]C` - Code 128 (including GS1-128)
+    Code128,
+    /// `]d` - DataMatrix
+    DataMatrix,
+    /// `]E` - EAN / UPC
+    Ean,
+    /// `]I` - Interleaved 2 of 5
+    Interleaved2Of5,
+    /// `]L` - PDF417
+    Pdf417,
+    /// `]Q` - QR Code
+    QrCode,
+    /// Any other symbology, keyed by its AIM code character
+    Other(char),
+}
+
+impl Symbology {
+    /// Maps an AIM code character to a symbology.
+    pub fn from_code(code: char) -> Symbology {
+        match code {
+            'A' => Symbology::Code39,
+            'C' => Symbology::Code128,
+            'd' => Symbology::DataMatrix,
+            'E' => Symbology::Ean,
+            'I' => Symbology::Interleaved2Of5,
+            'L' => Symbology::Pdf417,
+            'Q' => Symbology::QrCode,
+            other => Symbology::Other(other),
+        }
+    }
+
+    /// Parses a symbology from its configuration name e.g. `datamatrix` or `code128`.
+    pub fn from_name(name: &str) -> Option<Symbology> {
+        match name.trim().to_lowercase().as_str() {
+            "code39" => Some(Symbology::Code39),
+            "code128" => Some(Symbology::Code128),
+            "datamatrix" => Some(Symbology::DataMatrix),
+            "ean" | "upc" => Some(Symbology::Ean),
+            "itf" | "interleaved2of5" => Some(Symbology::Interleaved2Of5),
+            "pdf417" => Some(Symbology::Pdf417),
+            "qr" | "qrcode" => Some(Symbology::QrCode),
+            _ => None,
+        }
+    }
+}
+
+/// The result of running a raw scanned value through the parsing pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedBarcode {
+    /// The barcode with all prefixes removed. This is the value used for database lookups.
+    pub barcode: String,
+    /// The symbology reported by the scanner, if it sent an AIM symbology identifier
+    pub symbology: Option<Symbology>,
+    /// The site prefix which was stripped from the barcode, if any
+    pub site_prefix: Option<String>,
+    /// The labware type inferred from the symbology, if one is configured for it
+    pub labware_type: Option<String>,
+}
+
+/// A configurable parsing pipeline for scanned barcodes.
+///
+/// Scanners can be configured to prepend AIM symbology identifiers (e.g. `]C1`) or site prefixes
+/// to the data they send. The pipeline runs in the following order:
+///     1. Surrounding whitespace and control characters (e.g. a trailing carriage return) are trimmed
+///     2. An AIM symbology identifier is stripped and recorded
+///     3. A deep link from a QR code label is stripped back to the location barcode
+///     4. A configured site prefix is stripped and recorded
+///     5. The labware type is inferred from the symbology
+///     6. The check digit is validated if the barcode claims to carry one
+///
+/// Every barcode should go through this pipeline before it is looked up in the database.
+///
+/// The settings are those of the server's configuration (`LABWHERE_BASE_URL`,
+/// `LABWHERE_BARCODE_SITE_PREFIXES`, `LABWHERE_SYMBOLOGY_LABWARE_TYPES` and
+/// `LABWHERE_BARCODE_CHECK_DIGIT`), which a client has to be given to parse barcodes the same way.
+#[derive(Debug, Clone, Default)]
+pub struct BarcodeParser {
+    /// The base URL of the server, which deep links start with
+    pub base_url: String,
+    /// The site prefixes to strip, tried in order
+    pub site_prefixes: Vec<String>,
+    /// The labware type of barcodes read from each symbology
+    pub labware_types: HashMap<Symbology, String>,
+    /// The scheme of the check characters barcodes may carry
+    pub check_digit: Option<check_digit::CheckDigitScheme>,
+}
+
+impl BarcodeParser {
+    /// Runs a raw scanned value through the pipeline.
+    pub fn parse(&self, raw: &str) -> Result<ParsedBarcode, Problem> {
+        let mut barcode = trim(raw);
+
+        let mut symbology = None;
+        if let Some(rest) = barcode.strip_prefix(AIM_FLAG) {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(code), Some(modifier)) if modifier.is_ascii_alphanumeric() => {
+                    symbology = Some(Symbology::from_code(code));
+                    barcode = chars.as_str();
+                }
+                _ => return Err(Problem::new("barcode-malformed-symbology").arg("barcode", raw)),
+            }
+        }
+
+        let location_barcode;
+        if let Some(stripped) = strip_deep_link(&self.base_url, barcode) {
+            location_barcode = stripped;
+            barcode = &location_barcode;
+        }
+
+        let mut site_prefix = None;
+        if let Some(prefix) = self
+            .site_prefixes
+            .iter()
+            .find(|prefix| barcode.starts_with(prefix.as_str()))
+        {
+            barcode = &barcode[prefix.len()..];
+            site_prefix = Some(prefix.clone());
+        }
+
+        // Whitespace between a stripped prefix and the barcode is not part of the barcode either
+        barcode = trim(barcode);
+
+        if barcode.is_empty() {
+            return Err(Problem::new("barcode-empty").arg("barcode", format!("{:?}", raw)));
+        }
+
+        if let Some(scheme) = self.check_digit {
+            check_digit::validate(scheme, barcode)?;
+        }
+
+        Ok(ParsedBarcode {
+            barcode: barcode.to_string(),
+            labware_type: symbology.and_then(|s| self.labware_types.get(&s).cloned()),
+            symbology,
+            site_prefix,
+        })
+    }
+}
+
+/// Whether a character is whitespace or a control character a scanner pads the data with.
+fn is_padding(c: char) -> bool {
+    c.is_whitespace() || c.is_control()
+}
+
+/// Trims the padding around a scanned value.
+///
+/// A space is also a mod-43 check character, so one right after the check digit separator is kept.
+fn trim(raw: &str) -> &str {
+    let trimmed = raw.trim_start_matches(is_padding);
+    let end = trimmed.trim_end_matches(is_padding).len();
+    if trimmed[..end].ends_with(check_digit::SEPARATOR) && trimmed[end..].starts_with(' ') {
+        &trimmed[..end + 1]
+    } else {
+        &trimmed[..end]
+    }
+}
+
+/// Strips the deep link prefix from a scanned QR code, either a short link or a link to a location
+/// info page, leaving the barcode, which is percent-decoded.
+///
+/// Returns `None` if no base URL is configured or the value is not a deep link.
+pub fn strip_deep_link<'a>(base_url: &str, scanned: &'a str) -> Option<Cow<'a, str>> {
+    if base_url.is_empty() {
+        return None;
+    }
+    let path = scanned.strip_prefix(base_url.trim_end_matches('/'))?;
+    let barcode = path
+        .strip_prefix(SHORT_LINK_PATH)
+        .or_else(|| path.strip_prefix(LOCATION_PATH))?;
+    Some(percent_decode_str(barcode).decode_utf8_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::check_digit::CheckDigitScheme;
+    use crate::parser::*;
+
+    #[test]
+    fn test_parse() {
+        let parser = BarcodeParser {
+            base_url: "https://labwhere.example.com".to_string(),
+            site_prefixes: vec!["SNG:".to_string()],
+            labware_types: HashMap::from([(Symbology::DataMatrix, "tube".to_string())]),
+            check_digit: Some(CheckDigitScheme::Mod43),
+        };
+        let parsed = parser.parse("]d2SNG: FR1234\r\n").unwrap();
+        assert_eq!(parsed.barcode, "FR1234");
+        assert_eq!(parsed.site_prefix.as_deref(), Some("SNG:"));
+        assert_eq!(parsed.labware_type.as_deref(), Some("tube"));
+        assert_eq!(
+            parser
+                .parse("]Q1https://labwhere.example.com/locations/lw-freezer-1.e")
+                .unwrap()
+                .barcode,
+            "lw-freezer-1.e"
+        );
+        assert_eq!(
+            parser
+                .parse("]Q1https://labwhere.example.com/b/lw-freezer-999.%2F")
+                .unwrap()
+                .barcode,
+            "lw-freezer-999./"
+        );
+        assert_eq!(
+            parser.parse("lw-freezer-799. \r\n").unwrap().barcode,
+            "lw-freezer-799. "
+        );
+
+        assert_eq!(
+            parser.parse("]C").unwrap_err().key,
+            "barcode-malformed-symbology"
+        );
+        assert_eq!(parser.parse("SNG:").unwrap_err().key, "barcode-empty");
+        assert_eq!(
+            parser.parse("lw-freezer-7.e").unwrap_err().key,
+            "barcode-invalid-check-digit"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use crate::parser::{BarcodeParser, ParsedBarcode};
+use crate::Problem;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Why the labwares of a scan cannot be worked out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanPlanError {
+    /// A barcode could not be parsed
+    InvalidBarcode(Problem),
+    /// No labware barcodes were scanned
+    NoLabwares,
+}
+
+impl ScanPlanError {
+    /// The problem to show, whose key is that of the catalogs of the server
+    pub fn problem(&self) -> Problem {
+        match self {
+            ScanPlanError::InvalidBarcode(problem) => problem.clone(),
+            ScanPlanError::NoLabwares => Problem::new("scan-no-labwares"),
+        }
+    }
+}
+
+impl Display for ScanPlanError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.problem())
+    }
+}
+
+impl Error for ScanPlanError {}
+
+/// Parses the labware barcodes of a scan, leaving out those scanned more than once, in the order
+/// they were first scanned. The server scans labwares in this order too.
+/// # Examples
+/// ```
+/// use labwhere_core::parser::BarcodeParser;
+/// use labwhere_core::scan_plan::labware_barcodes;
+/// let parsed = labware_barcodes(&BarcodeParser::default(), ["lw-1\r\n", "lw-2", "lw-1"]).unwrap();
+/// assert_eq!(parsed.len(), 2);
+/// ```
+pub fn labware_barcodes<I>(
+    parser: &BarcodeParser,
+    barcodes: I,
+) -> Result<Vec<ParsedBarcode>, ScanPlanError>
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut parsed: Vec<ParsedBarcode> = vec![];
+    for barcode in barcodes {
+        let barcode = parser
+            .parse(barcode.as_ref())
+            .map_err(ScanPlanError::InvalidBarcode)?;
+        if !parsed.iter().any(|b| b.barcode == barcode.barcode) {
+            parsed.push(barcode);
+        }
+    }
+    if parsed.is_empty() {
+        return Err(ScanPlanError::NoLabwares);
+    }
+    Ok(parsed)
+}
+
+/// A labware a scan is expected to move.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedMove {
+    /// The barcode of the labware
+    pub barcode: String,
+    /// The barcode of the location the labware was last known to be in, or none if it is not
+    /// known and will be registered
+    pub from: Option<String>,
+    /// The barcode of the location the labware is scanned into
+    pub to: String,
+}
+
+/// A scan worked out on a client, e.g. to be queued while offline.
+///
+/// A plan serializes to the payload of `POST /scan`. The barcodes are sent as they were scanned,
+/// so the server still sees their symbology and site prefix, and the server checks them again:
+/// a plan only tells that a scan is well-formed, not that it will be accepted (e.g. a location may
+/// be locked by then).
+/// # Examples
+/// ```
+/// use labwhere_core::parser::BarcodeParser;
+/// use labwhere_core::scan_plan::ScanPlan;
+/// let labwares = vec!["lw-1".to_string(), "lw-1".to_string()];
+/// let plan = ScanPlan::new(&BarcodeParser::default(), "lw-freezer-1", &labwares).unwrap();
+/// assert_eq!(plan.labware_barcodes, vec!["lw-1"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanPlan {
+    /// The barcode of the location to scan the labwares into
+    pub location_barcode: String,
+    /// The barcodes of the labwares as they were scanned, each labware once
+    pub labware_barcodes: Vec<String>,
+    /// The labware barcodes, parsed
+    #[serde(skip)]
+    pub labwares: Vec<ParsedBarcode>,
+}
+
+impl ScanPlan {
+    /// Works out a scan of labwares into a location.
+    ///
+    /// The location barcode is not rejected if it does not parse, as the server also finds
+    /// locations by the old barcodes they had, which a client cannot know about.
+    pub fn new(
+        parser: &BarcodeParser,
+        location_barcode: &str,
+        labware_barcodes: &[String],
+    ) -> Result<ScanPlan, ScanPlanError> {
+        let location = match parser.parse(location_barcode) {
+            Ok(parsed) => parsed.barcode,
+            Err(problem) if location_barcode.trim().is_empty() => {
+                return Err(ScanPlanError::InvalidBarcode(problem))
+            }
+            Err(_) => location_barcode.trim().to_string(),
+        };
+        let labwares = self::labware_barcodes(parser, labware_barcodes)?;
+        // The first scan of each labware, as scanned
+        let scanned = labwares
+            .iter()
+            .filter_map(|labware| {
+                labware_barcodes
+                    .iter()
+                    .find(|raw| {
+                        parser
+                            .parse(raw)
+                            .is_ok_and(|p| p.barcode == labware.barcode)
+                    })
+                    .cloned()
+            })
+            .collect();
+        Ok(ScanPlan {
+            location_barcode: location,
+            labware_barcodes: scanned,
+            labwares,
+        })
+    }
+
+    /// The labwares the scan is expected to move, given the locations labwares were last known
+    /// to be in (by labware barcode), e.g. from the responses of earlier scans. Labwares already
+    /// in the location are left out.
+    pub fn moves(&self, known: &HashMap<String, String>) -> Vec<PlannedMove> {
+        self.labwares
+            .iter()
+            .filter(|labware| known.get(&labware.barcode) != Some(&self.location_barcode))
+            .map(|labware| PlannedMove {
+                barcode: labware.barcode.clone(),
+                from: known.get(&labware.barcode).cloned(),
+                to: self.location_barcode.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scan_plan::*;
+
+    #[test]
+    fn test_labware_barcodes() {
+        let parser = BarcodeParser::default();
+        let parsed = labware_barcodes(&parser, ["]C1lw-1", "lw-2", " lw-1 "]).unwrap();
+        let barcodes: Vec<_> = parsed.iter().map(|p| p.barcode.as_str()).collect();
+        assert_eq!(barcodes, vec!["lw-1", "lw-2"]);
+
+        let none: [&str; 0] = [];
+        assert_eq!(
+            labware_barcodes(&parser, none).unwrap_err(),
+            ScanPlanError::NoLabwares
+        );
+        assert_eq!(
+            labware_barcodes(&parser, ["lw-1", " "])
+                .unwrap_err()
+                .problem()
+                .key,
+            "barcode-empty"
+        );
+    }
+
+    #[test]
+    fn test_scan_plan() {
+        let parser = BarcodeParser::default();
+        let labwares = vec![
+            "]C1lw-1".to_string(),
+            "lw-2".to_string(),
+            "lw-1".to_string(),
+            "lw-3".to_string(),
+        ];
+        let plan = ScanPlan::new(&parser, " lw-shelf-2\r\n", &labwares).unwrap();
+        assert_eq!(plan.location_barcode, "lw-shelf-2");
+        assert_eq!(plan.labware_barcodes, vec!["]C1lw-1", "lw-2", "lw-3"]);
+
+        let known = HashMap::from([
+            ("lw-1".to_string(), "lw-shelf-1".to_string()),
+            ("lw-3".to_string(), "lw-shelf-2".to_string()),
+        ]);
+        let moves = plan.moves(&known);
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].from.as_deref(), Some("lw-shelf-1"));
+        assert_eq!(moves[1].barcode, "lw-2");
+        assert_eq!(moves[1].from, None);
+
+        assert!(ScanPlan::new(&parser, " ", &labwares).is_err());
+        assert_eq!(
+            ScanPlan::new(&parser, "lw-shelf-2", &[]).unwrap_err(),
+            ScanPlanError::NoLabwares
+        );
+    }
+}
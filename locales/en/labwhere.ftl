@@ -0,0 +1,302 @@
+## Barcodes
+
+barcode-invalid-check-digit = Barcode { $barcode } has an invalid check digit
+barcode-invalid-signature = Barcode { $barcode } is not a genuine LabWhere barcode
+barcode-malformed-symbology = Barcode { $barcode } has a malformed symbology identifier
+barcode-empty = Barcode { $barcode } is empty once prefixes are removed
+barcode-in-use = Barcode { $barcode } is already in use
+barcode-reserved = Barcode { $barcode } is reserved for location { $location }
+barcode-not-found = Barcode { $barcode } not found
+barcode-is-primary = Barcode { $barcode } is the primary barcode
+
+## Labwares and locations
+
+labware-not-found = Labware not found
+labware-status-transition = Labware { $barcode } cannot go from { $from } to { $to }
+location-not-found = Location not found
+location-type-name-taken = A location type named { $name } already exists
+labware-none-found = None of the labwares were found
+location-empty = Location { $location } is empty
+location-reparent-inside-itself = Location { $location } cannot be put inside of itself
+location-rename-unknown = The unknown location cannot be renamed
+time-invalid = { $time } is not a time or a date, e.g. 2024-03-03T12:00Z or 2024-03-03
+
+## Auth
+
+auth-api-key-required = An API key is required in the X-Api-Key header to make changes
+auth-api-key-invalid = The API key is not valid
+auth-quota-exceeded = { $name } has used up its daily quota of { $quota } requests
+auth-api-key-not-found = API key not found
+auth-api-key-name-taken = An API key named { $name } already exists
+auth-api-key-revoked = API key { $name } has been revoked
+auth-api-key-expiry-past = The expiry date must be in the future
+auth-api-key-invalid-grace = grace_hours must be a number of hours between 0 and { $max }
+auth-admin-required = Only an admin can do this
+
+## Users
+
+user-not-found = User not found
+user-login-taken = A user with login { $login } already exists
+user-swipe-code-taken = The swipe code belongs to another user
+user-deactivated = User { $login } has been deactivated
+user-act-as-admin-required = Only an active admin can act as another user, with their own swipe card
+user-unknown-role = must be admin, technician or viewer
+user-unknown-credential = must be swipe_code or api_key
+
+## Devices
+
+device-not-found = Device not found
+device-name-taken = A device named { $name } is already registered
+device-key-required = The X-Device-Key header is required
+device-key-invalid = Unknown device key
+device-disabled = Device { $name } is disabled
+
+## Layouts
+
+layout-not-coordinated = Location { $location } has no rows and columns
+layout-invalid-position = { $position } is not a position in { $location }
+layout-position-taken = { $position } in { $location } is taken by { $barcode }
+layout-position-outside = Cannot resize { $location } while { $position } is occupied
+layout-remap-outside = Cannot remap { $location }: { $barcode } in { $position } would be past the last of its { $wells } wells
+layout-remap-reserved = Cannot remap { $location }: the reserved well { $position } would be past the last of its { $wells } wells
+layout-transfer-same = Cannot transfer the labwares of { $location } to itself
+layout-transfer-full = { $location } has { $free } free wells, not enough for { $count } labwares
+layout-transfer-destination-required = The location to transfer to is required in the `to` parameter
+
+## Location trees
+
+tree-title = Map of { $location }
+tree-summary = { $locations } locations, { $labwares } labwares. Printed { $printed_at }
+tree-occupancy = { $labwares } labwares
+tree-occupancy-wells = { $labwares } of { $capacity } wells
+
+## Location locks
+
+lock-duration = Locks must last between 1 and { $max } seconds
+lock-held = Location is locked by { $holder } until { $expires_at }
+lock-not-found = Lock not found
+lock-not-locked = Location is not locked
+lock-token-required = The X-Lock-Token header is required
+
+## Confirmations
+
+confirmation-invalid = The confirmation token is not valid or has expired
+confirmation-changed = Things have changed since this was summarised, please ask again
+
+## Location flags
+
+flag-quarantined = Location { $location } is quarantined: { $note }
+flag-not-found = Flag not found
+flag-admin-required = Only an admin can clear a quarantine
+
+## Subscriptions
+
+subscription-not-found = Subscription not found
+subscription-invalid-target = { $target } is not a valid { $channel } target
+dead-letter-not-found = Dead letter not found
+dead-letter-redriven = The dead letter has already been re-driven
+
+## Print jobs
+
+print-job-no-locations = A print job needs at least one location
+print-job-not-found = Print job not found
+print-job-locations-not-found = Print job locations not found
+print-job-invalid-transition = Print job { $id } is { $from } and cannot become { $to }
+print-job-unknown-state = Unknown print job state { $state }
+print-job-error-required = A failed print job needs an error
+
+## Custody
+
+custody-checked-out = Labware { $barcode } is already checked out by { $holder }
+custody-not-checked-out = Labware { $barcode } is not checked out
+custody-not-found = Checkout not found
+custody-in-transit = In transit with { $holder }
+
+## Stocktakes
+
+stocktake-in-progress = A stocktake of { $location } is already in progress
+stocktake-not-found = Stocktake not found
+stocktake-outside = Location { $location } is not part of the stocktake
+stocktake-completed = The stocktake is already completed
+stocktake-not-completed = The stocktake is not completed yet
+
+## Stats
+
+stats-invalid-days = days must be a number between 1 and { $max }
+
+## Search
+
+search-missing-query = Search for something with ?q=, e.g. ?q=freezer
+search-invalid-limit = limit must be a number between 1 and { $max }
+search-invalid-status = status must be all, active, exhausted, destroyed or shipped-out
+search-invalid-sort = sort must be relevance, name or name-desc
+saved-search-not-found = Saved search not found
+saved-search-name-taken = You already have a saved search named { $name }
+
+## Labels
+
+label-content-too-long = { $content } is too long to fit on a label
+
+## Scans
+
+scan-no-labwares = No labware barcodes were scanned
+scan-created = { $count } labwares scanned into { $location }
+scan-created-queued = { $count } labwares scanned into { $location }, { $queued } queued for review
+scan-labware-not-registered = Labware { $barcode } is not registered and cannot be scanned into { $location }
+scan-warning-lock-overridden = { $location } is locked by { $holder }
+scan-warning-near-capacity = { $location } is { $percent }% full (threshold { $threshold }%)
+barcode-validation-location = { $barcode } is a location, not a labware
+scan-image-unreadable = The image could not be read
+scan-image-no-location = No location barcode was found in the image
+scan-image-too-large = Images must be smaller than { $max } MB
+scan-batch-too-large = A batch can have at most { $max } scans
+scan-offline-invalid-uuid = { $uuid } is not a valid UUID
+scan-offline-conflict = { $barcode } changed after the scan was recorded and is now in { $location }
+scan-payload-not-object = A scan must be a JSON object
+scan-unsupported-content-type = Scans must be sent as JSON or form data
+
+## Pending labwares
+
+pending-labware-not-found = Pending labware not found
+pending-labware-reviewed = The labware has already been reviewed
+pending-labware-exists = Labware { $barcode } already exists
+pending-labware-unknown-status = Unknown pending labware status { $status }
+
+## Manifests
+
+manifest-not-found = Manifest not found
+manifest-empty = The manifest is empty
+manifest-missing-column = The manifest has no { $column } column in its header
+manifest-invalid-utf8 = Line { $line } of the manifest is not valid UTF-8
+upload-mapping-not-found = No upload mapping is named { $name }
+upload-mapping-name-taken = An upload mapping named { $name } already exists
+shipment-not-found = Shipment not found
+shipment-empty = A shipment needs at least one labware
+shipment-labware-not-found = Labware { $barcode } is not in storage, so it cannot be shipped
+receipt-not-found = Receipt not found
+receipt-empty = A receipt needs at least one expected labware
+receipt-completed = The receipt is already completed
+receipt-no-receiving-location = No receiving location is configured, set LABWHERE_RECEIVING_LOCATION to receive labwares
+reservation-not-found = Reservation not found
+reservation-expired = A reservation has to expire in the future
+reservation-not-enough-space = Only { $free } of the { $wells } wells asked for are free in { $location }
+reservation-location-not-empty = { $location } has to be empty to reserve all of it
+reservation-location-reserved = { $location } is already reserved for { $team }
+reservation-held = Space is reserved for { $team } until { $expires_at }
+reservation-token-required = The reservation's token has to be sent in the X-Lock-Token header
+misplacement-not-found = Misplacement not found
+misplacement-reviewed = The misplacement was already reviewed by { $user }
+
+## Kiosk
+
+kiosk-title = Scan station
+kiosk-location = Location
+kiosk-location-hint = Scan the location first, or leave empty on a pinned station
+kiosk-labware = Labware
+kiosk-recent = Recent scans
+kiosk-offline = LabWhere could not be reached
+
+## Short links
+
+link-not-found-title = Unknown barcode
+link-not-found = No location or labware has the barcode { $barcode }
+link-register = Register it by scanning it into a location
+
+## Notifications
+
+notification-capacity-threshold-subject = { $location } is { $percent }% full
+notification-capacity-threshold-body = { $location } holds { $count } of { $capacity } labwares, over its { $threshold }% threshold.
+notification-stocktake-completed-subject = Stocktake of { $location } completed
+notification-stocktake-completed-body = The stocktake of { $location } started by { $user } was completed with { $discrepancies } discrepancies. The report can be downloaded from { $report }
+notification-print-job-failed-subject = Print job on { $printer } failed
+notification-print-job-failed-body = Print job { $uuid } on { $printer } failed after { $attempts } attempts: { $error }
+notification-retention-report-ready-subject = Retention report ready
+notification-retention-report-ready-body = The retention report can be downloaded from { $report }
+notification-location-changed-subject = Activity in { $subscribed }: { $type } { $record }
+notification-location-changed-body = { $type } { $record } ({ $action }) is now in { $location }. You are subscribed to changes in { $subscribed }.
+notification-location-flagged-subject = { $severity } flag raised on { $location }
+notification-location-flagged-body = { $user } raised a { $severity } flag on { $location }: { $note }
+notification-transit-threshold-subject = { $barcode } has been in transit for { $minutes } minutes
+notification-transit-threshold-body = { $barcode } was checked out of { $location } by { $holder } { $minutes } minutes ago and has not been checked in yet.
+
+## Validation
+
+field-name = Name
+field-location-type = Location type
+field-barcode = Barcode
+field-barcodes = Barcodes
+field-holder = Holder
+field-seconds = Seconds
+field-printer = Printer
+field-locations = Locations
+field-location-barcode = Location barcode
+field-labware-barcodes = Labware barcodes
+field-user = User
+field-note = Note
+field-severity = Severity
+field-subscriber = Subscriber
+field-target = Target
+validation-required = is required
+validation-blank = can't be blank
+validation-empty = can't be empty
+validation-length = must be between { $min } and { $max } characters
+validation-range = must be between { $min } and { $max }
+validation-email = must be an email address
+validation-location-name-format = must only contain letters, numbers, hyphens, spaces and parentheses
+validation-name-format = must match { $format }
+validation-name-reserved = is reserved
+validation-invalid-barcode = is invalid: { $reason }
+validation-invalid = is invalid
+validation-expected-string = must be a string
+validation-expected-strings = must be a list of strings
+validation-repeated = was given more than once
+validation-unknown-field = is not allowed
+
+## Snapshots
+
+snapshot-version = Snapshots of version { $version } cannot be imported, only version { $supported }
+snapshot-unknown-table = The snapshot has an unknown table { $table }
+snapshot-unknown-column = The snapshot has an unknown column { $column } in { $table }
+snapshot-broken-reference = Row { $id } of { $table } refers to a missing row of { $parent }
+
+## Admin queries
+
+admin-query-not-select = Only a single SELECT statement can be run
+admin-query-timeout = The query took longer than { $seconds } seconds
+admin-query-failed = The query failed: { $error }
+
+## Debug recorder
+
+admin-debug-recorder-disabled = Requests are not being recorded; set LABWHERE_DEBUG_RECORDER_CAPACITY to record them
+
+## Analytics
+
+analytics-dataset-not-found = Analytics dataset { $dataset } not found
+
+## Events
+
+event-schema-not-found = There is no version { $version } of the { $type } event
+
+## Timestamps
+
+timestamp-too-far-ahead = { $timestamp } is more than { $seconds } seconds ahead of the server's clock
+timestamp-too-far-behind = { $timestamp } is more than { $seconds } seconds behind the server's clock
+timestamp-clamped = { $timestamp } is outside of the allowed clock skew and was recorded as { $clamped }
+
+## Sync
+
+sync-unknown-location = Location { $location } is not known on this instance
+sync-unsupported = { $type } { $action } changes cannot be synced
+sync-invalid-since = since must be the number of an audit
+sync-invalid-limit = limit must be a number between 1 and { $max }
+sync-conflict-not-found = Sync conflict not found
+sync-conflict-resolved = The conflict has already been resolved
+sync-conflict-unknown-status = Unknown sync conflict status { $status }
+sync-conflict-invalid-resolution = must be apply or discard
+
+## Errors
+
+error-internal = Internal server error
+request-timeout = The request took longer than { $seconds } seconds
+database-busy = The database is busy, please try again
+database-unavailable = The database is unavailable, please try again in { $seconds } seconds
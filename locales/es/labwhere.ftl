@@ -0,0 +1,302 @@
+## Barcodes
+
+barcode-invalid-check-digit = El código de barras { $barcode } tiene un dígito de control no válido
+barcode-invalid-signature = El código de barras { $barcode } no es un código de barras auténtico de LabWhere
+barcode-malformed-symbology = El código de barras { $barcode } tiene un identificador de simbología mal formado
+barcode-empty = El código de barras { $barcode } queda vacío al quitar los prefijos
+barcode-in-use = El código de barras { $barcode } ya está en uso
+barcode-reserved = El código de barras { $barcode } está reservado para la ubicación { $location }
+barcode-not-found = No se encontró el código de barras { $barcode }
+barcode-is-primary = El código de barras { $barcode } es el código de barras principal
+
+## Labwares and locations
+
+labware-not-found = No se encontró el labware
+labware-status-transition = El labware { $barcode } no puede pasar de { $from } a { $to }
+location-not-found = No se encontró la ubicación
+location-type-name-taken = Ya existe un tipo de ubicación llamado { $name }
+labware-none-found = No se encontró ninguno de los labwares
+location-empty = La ubicación { $location } está vacía
+location-reparent-inside-itself = La ubicación { $location } no se puede poner dentro de sí misma
+location-rename-unknown = La ubicación desconocida no se puede renombrar
+time-invalid = { $time } no es una hora ni una fecha, p. ej. 2024-03-03T12:00Z o 2024-03-03
+
+## Auth
+
+auth-api-key-required = Se necesita una clave de API en la cabecera X-Api-Key para hacer cambios
+auth-api-key-invalid = La clave de API no es válida
+auth-quota-exceeded = { $name } ha agotado su cuota diaria de { $quota } peticiones
+auth-api-key-not-found = Clave de API no encontrada
+auth-api-key-name-taken = Ya existe una clave de API llamada { $name }
+auth-api-key-revoked = La clave de API { $name } ha sido revocada
+auth-api-key-expiry-past = La fecha de caducidad debe estar en el futuro
+auth-api-key-invalid-grace = grace_hours debe ser un número de horas entre 0 y { $max }
+auth-admin-required = Solo un administrador puede hacer esto
+
+## Users
+
+user-not-found = Usuario no encontrado
+user-login-taken = Ya existe un usuario con el login { $login }
+user-swipe-code-taken = El código de tarjeta pertenece a otro usuario
+user-deactivated = El usuario { $login } ha sido desactivado
+user-act-as-admin-required = Solo un administrador activo puede actuar como otro usuario, con su propia tarjeta
+user-unknown-role = debe ser admin, technician o viewer
+user-unknown-credential = debe ser swipe_code o api_key
+
+## Devices
+
+device-not-found = Dispositivo no encontrado
+device-name-taken = Ya hay un dispositivo registrado con el nombre { $name }
+device-key-required = La cabecera X-Device-Key es obligatoria
+device-key-invalid = Clave de dispositivo desconocida
+device-disabled = El dispositivo { $name } está desactivado
+
+## Layouts
+
+layout-not-coordinated = La ubicación { $location } no tiene filas ni columnas
+layout-invalid-position = { $position } no es una posición de { $location }
+layout-position-taken = { $position } en { $location } está ocupada por { $barcode }
+layout-position-outside = No se puede redimensionar { $location } mientras { $position } está ocupada
+layout-remap-outside = No se puede reasignar { $location }: { $barcode } en { $position } quedaría más allá del último de sus { $wells } pocillos
+layout-remap-reserved = No se puede reasignar { $location }: el pocillo reservado { $position } quedaría más allá del último de sus { $wells } pocillos
+layout-transfer-same = No se pueden transferir los labwares de { $location } a sí misma
+layout-transfer-full = { $location } tiene { $free } pocillos libres, no son suficientes para { $count } labwares
+layout-transfer-destination-required = La ubicación de destino es obligatoria en el parámetro `to`
+
+## Location trees
+
+tree-title = Mapa de { $location }
+tree-summary = { $locations } ubicaciones, { $labwares } labwares. Impreso el { $printed_at }
+tree-occupancy = { $labwares } labwares
+tree-occupancy-wells = { $labwares } de { $capacity } posiciones
+
+## Location locks
+
+lock-duration = Los bloqueos deben durar entre 1 y { $max } segundos
+lock-held = La ubicación está bloqueada por { $holder } hasta { $expires_at }
+lock-not-found = No se encontró el bloqueo
+lock-not-locked = La ubicación no está bloqueada
+lock-token-required = La cabecera X-Lock-Token es obligatoria
+
+## Confirmations
+
+confirmation-invalid = El token de confirmación no es válido o ha caducado
+confirmation-changed = Algo ha cambiado desde que se resumió, vuelva a pedirlo
+
+## Location flags
+
+flag-quarantined = La ubicación { $location } está en cuarentena: { $note }
+flag-not-found = No se encontró el aviso
+flag-admin-required = Solo un administrador puede levantar una cuarentena
+
+## Subscriptions
+
+subscription-not-found = Suscripción no encontrada
+subscription-invalid-target = { $target } no es un destino { $channel } válido
+dead-letter-not-found = Mensaje fallido no encontrado
+dead-letter-redriven = El mensaje fallido ya se ha reenviado
+
+## Print jobs
+
+print-job-no-locations = Un trabajo de impresión necesita al menos una ubicación
+print-job-not-found = No se encontró el trabajo de impresión
+print-job-locations-not-found = No se encontraron las ubicaciones del trabajo de impresión
+print-job-invalid-transition = El trabajo de impresión { $id } está en estado { $from } y no puede pasar a { $to }
+print-job-unknown-state = Estado de trabajo de impresión desconocido { $state }
+print-job-error-required = Un trabajo de impresión fallido necesita un error
+
+## Custody
+
+custody-checked-out = El labware { $barcode } ya está retirado por { $holder }
+custody-not-checked-out = El labware { $barcode } no está retirado
+custody-not-found = No se encontró el retiro
+custody-in-transit = En tránsito con { $holder }
+
+## Stocktakes
+
+stocktake-in-progress = Ya hay un inventario de { $location } en curso
+stocktake-not-found = No se encontró el inventario
+stocktake-outside = La ubicación { $location } no forma parte del inventario
+stocktake-completed = El inventario ya está completado
+stocktake-not-completed = El inventario aún no está completado
+
+## Stats
+
+stats-invalid-days = days debe ser un número entre 1 y { $max }
+
+## Search
+
+search-missing-query = Busque algo con ?q=, p. ej. ?q=freezer
+search-invalid-limit = limit debe ser un número entre 1 y { $max }
+search-invalid-status = status debe ser all, active, exhausted, destroyed o shipped-out
+search-invalid-sort = sort debe ser relevance, name o name-desc
+saved-search-not-found = Búsqueda guardada no encontrada
+saved-search-name-taken = Ya tiene una búsqueda guardada llamada { $name }
+
+## Labels
+
+label-content-too-long = { $content } es demasiado largo para caber en una etiqueta
+
+## Scans
+
+scan-no-labwares = No se escaneó ningún código de barras de labware
+scan-created = { $count } labwares escaneados en { $location }
+scan-created-queued = { $count } labwares escaneados en { $location }, { $queued } pendientes de revisión
+scan-labware-not-registered = El labware { $barcode } no está registrado y no se puede escanear en { $location }
+scan-warning-lock-overridden = { $location } está bloqueada por { $holder }
+scan-warning-near-capacity = { $location } está llena al { $percent }% (umbral { $threshold }%)
+barcode-validation-location = { $barcode } es una ubicación, no un labware
+scan-image-unreadable = No se pudo leer la imagen
+scan-image-no-location = No se encontró ningún código de barras de ubicación en la imagen
+scan-image-too-large = Las imágenes deben ocupar menos de { $max } MB
+scan-batch-too-large = Un lote puede tener como máximo { $max } escaneos
+scan-offline-invalid-uuid = { $uuid } no es un UUID válido
+scan-offline-conflict = { $barcode } cambió después de registrarse el escaneo y ahora está en { $location }
+scan-payload-not-object = Un escaneo debe ser un objeto JSON
+scan-unsupported-content-type = Los escaneos deben enviarse como JSON o datos de formulario
+
+## Pending labwares
+
+pending-labware-not-found = Labware pendiente no encontrado
+pending-labware-reviewed = El labware ya se ha revisado
+pending-labware-exists = El labware { $barcode } ya existe
+pending-labware-unknown-status = Estado de labware pendiente desconocido { $status }
+
+## Manifests
+
+manifest-not-found = Manifiesto no encontrado
+manifest-empty = El manifiesto está vacío
+manifest-missing-column = El manifiesto no tiene una columna { $column } en su encabezado
+manifest-invalid-utf8 = La línea { $line } del manifiesto no es UTF-8 válido
+upload-mapping-not-found = Ningún mapeo de carga se llama { $name }
+upload-mapping-name-taken = Ya existe un mapeo de carga llamado { $name }
+shipment-not-found = Envío no encontrado
+shipment-empty = Un envío necesita al menos un labware
+shipment-labware-not-found = El labware { $barcode } no está almacenado, así que no se puede enviar
+receipt-not-found = Recepción no encontrada
+receipt-empty = Una recepción necesita al menos un labware esperado
+receipt-completed = La recepción ya está completada
+receipt-no-receiving-location = No hay una ubicación de recepción configurada, defina LABWHERE_RECEIVING_LOCATION para recibir labwares
+reservation-not-found = Reserva no encontrada
+reservation-expired = Una reserva tiene que vencer en el futuro
+reservation-not-enough-space = Solo { $free } de los { $wells } pocillos pedidos están libres en { $location }
+reservation-location-not-empty = { $location } tiene que estar vacía para reservarla entera
+reservation-location-reserved = { $location } ya está reservada para { $team }
+reservation-held = El espacio está reservado para { $team } hasta { $expires_at }
+reservation-token-required = El token de la reserva tiene que enviarse en la cabecera X-Lock-Token
+misplacement-not-found = Extravío no encontrado
+misplacement-reviewed = El extravío ya fue revisado por { $user }
+
+## Kiosk
+
+kiosk-title = Estación de escaneo
+kiosk-location = Ubicación
+kiosk-location-hint = Escanee primero la ubicación, o déjela vacía en una estación fijada
+kiosk-labware = Labware
+kiosk-recent = Escaneos recientes
+kiosk-offline = No se pudo contactar con LabWhere
+
+## Short links
+
+link-not-found-title = Código de barras desconocido
+link-not-found = Ninguna ubicación ni labware tiene el código de barras { $barcode }
+link-register = Regístrelo escaneándolo en una ubicación
+
+## Notifications
+
+notification-capacity-threshold-subject = { $location } está al { $percent }% de su capacidad
+notification-capacity-threshold-body = { $location } contiene { $count } de { $capacity } labwares, por encima de su umbral del { $threshold }%.
+notification-stocktake-completed-subject = Inventario de { $location } completado
+notification-stocktake-completed-body = El inventario de { $location } iniciado por { $user } se completó con { $discrepancies } discrepancias. El informe se puede descargar en { $report }
+notification-print-job-failed-subject = Falló el trabajo de impresión en { $printer }
+notification-print-job-failed-body = El trabajo de impresión { $uuid } en { $printer } falló tras { $attempts } intentos: { $error }
+notification-retention-report-ready-subject = Informe de retención disponible
+notification-retention-report-ready-body = El informe de retención se puede descargar en { $report }
+notification-location-changed-subject = Actividad en { $subscribed }: { $type } { $record }
+notification-location-changed-body = { $type } { $record } ({ $action }) está ahora en { $location }. Está suscrito a los cambios en { $subscribed }.
+notification-location-flagged-subject = Alerta { $severity } en { $location }
+notification-location-flagged-body = { $user } levantó una alerta { $severity } en { $location }: { $note }
+notification-transit-threshold-subject = { $barcode } lleva { $minutes } minutos en tránsito
+notification-transit-threshold-body = { $holder } sacó { $barcode } de { $location } hace { $minutes } minutos y aún no lo ha devuelto.
+
+## Validation
+
+field-name = Nombre
+field-location-type = Tipo de ubicación
+field-barcode = Código de barras
+field-barcodes = Códigos de barras
+field-holder = Titular
+field-seconds = Segundos
+field-printer = Impresora
+field-locations = Ubicaciones
+field-location-barcode = Código de barras de la ubicación
+field-labware-barcodes = Códigos de barras de los labwares
+field-user = Usuario
+field-note = Nota
+field-severity = Gravedad
+field-subscriber = Suscriptor
+field-target = Destino
+validation-required = es obligatorio
+validation-blank = no puede estar en blanco
+validation-empty = no puede estar vacío
+validation-length = debe tener entre { $min } y { $max } caracteres
+validation-range = debe estar entre { $min } y { $max }
+validation-email = debe ser una dirección de correo electrónico
+validation-location-name-format = solo puede contener letras, números, guiones, espacios y paréntesis
+validation-name-format = debe coincidir con { $format }
+validation-name-reserved = está reservado
+validation-invalid-barcode = no es válido: { $reason }
+validation-invalid = no es válido
+validation-expected-string = debe ser un texto
+validation-expected-strings = debe ser una lista de textos
+validation-repeated = se indicó más de una vez
+validation-unknown-field = no está permitido
+
+## Snapshots
+
+snapshot-version = No se pueden importar instantáneas de la versión { $version }, solo de la versión { $supported }
+snapshot-unknown-table = La instantánea tiene una tabla desconocida { $table }
+snapshot-unknown-column = La instantánea tiene una columna desconocida { $column } en { $table }
+snapshot-broken-reference = La fila { $id } de { $table } hace referencia a una fila inexistente de { $parent }
+
+## Admin queries
+
+admin-query-not-select = Solo se puede ejecutar una única sentencia SELECT
+admin-query-timeout = La consulta tardó más de { $seconds } segundos
+admin-query-failed = La consulta falló: { $error }
+
+## Debug recorder
+
+admin-debug-recorder-disabled = Las peticiones no se están registrando; defina LABWHERE_DEBUG_RECORDER_CAPACITY para registrarlas
+
+## Analytics
+
+analytics-dataset-not-found = Conjunto de datos analíticos { $dataset } no encontrado
+
+## Events
+
+event-schema-not-found = No existe la versión { $version } del evento { $type }
+
+## Timestamps
+
+timestamp-too-far-ahead = { $timestamp } va más de { $seconds } segundos por delante del reloj del servidor
+timestamp-too-far-behind = { $timestamp } va más de { $seconds } segundos por detrás del reloj del servidor
+timestamp-clamped = { $timestamp } está fuera del desfase de reloj permitido y se registró como { $clamped }
+
+## Sync
+
+sync-unknown-location = La ubicación { $location } no se conoce en esta instancia
+sync-unsupported = Los cambios { $action } de { $type } no se pueden sincronizar
+sync-invalid-since = since debe ser el número de una auditoría
+sync-invalid-limit = limit debe ser un número entre 1 y { $max }
+sync-conflict-not-found = Conflicto de sincronización no encontrado
+sync-conflict-resolved = El conflicto ya se ha resuelto
+sync-conflict-unknown-status = Estado de conflicto de sincronización desconocido { $status }
+sync-conflict-invalid-resolution = debe ser apply o discard
+
+## Errors
+
+error-internal = Error interno del servidor
+request-timeout = La solicitud tardó más de { $seconds } segundos
+database-busy = La base de datos está ocupada, inténtelo de nuevo
+database-unavailable = La base de datos no está disponible, inténtelo de nuevo en { $seconds } segundos
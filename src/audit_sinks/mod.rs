@@ -0,0 +1,419 @@
+//! Mirrors the audits to append-only sinks outside the database, for quality systems which require
+//! an external copy of the audit trail.
+//!
+//! Each sink gets every audit once it is committed, oldest first, as a line of JSON with its
+//! `sequence` (the id of the audit) and the name of the instance. The lines of a sink form a hash
+//! chain: each carries the `hash` of the line before it as `previous_hash`, and its own `hash` is
+//! the SHA-256 of the line without it, so a line which is changed, dropped or reordered after the
+//! fact breaks the chain (see `verify_chain`). How far each sink has got, and the last hash it was
+//! sent, are kept in `audit_sink_cursors`, so mirroring carries on from where it left off after a
+//! restart. Audits are mirrored at least once: a batch which was sent but could not be recorded as
+//! sent is sent again, with the same sequences and hashes.
+use crate::config::{Config, CONFIG};
+use crate::metrics::acquire;
+use crate::models::audit::Audit;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The most audits mirrored to a sink in one go.
+pub const BATCH_SIZE: u32 = 500;
+
+/// How often the audits are checked for ones to mirror.
+const MIRROR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The priority of the syslog messages: facility 13 (log audit), severity 6 (informational).
+const SYSLOG_PRIORITY: u8 = 13 * 8 + 6;
+
+/// The HTTP client audits are posted to collectors with.
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+});
+
+/// Where audits are mirrored to.
+///
+/// Sinks are configured as `file:{path}` for a local JSONL file, `syslog:udp://{host}:{port}` or
+/// `syslog:{socket path}` (e.g. `syslog:/dev/log`) for syslog, or the `https://` (or `http://`)
+/// URL of an external collector.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditSink {
+    /// A local file the lines are appended to, rotated once it reaches
+    /// `Config::audit_file_max_bytes`
+    File(PathBuf),
+    /// A syslog server listening on UDP, as `host:port`
+    SyslogUdp(String),
+    /// A syslog daemon listening on a local datagram socket
+    SyslogSocket(PathBuf),
+    /// An HTTP collector the lines are posted to as `application/x-ndjson`
+    Http(String),
+}
+
+impl AuditSink {
+    /// Parses a sink from its configuration e.g. `file:/var/log/labwhere/audits.jsonl`.
+    /// # Examples
+    /// ```
+    /// use labwhere::audit_sinks::AuditSink;
+    /// let sink = AuditSink::from_name("syslog:udp://10.0.0.5:514").unwrap();
+    /// assert_eq!(sink, AuditSink::SyslogUdp("10.0.0.5:514".to_string()));
+    /// assert_eq!(sink.name(), "syslog:udp://10.0.0.5:514");
+    /// assert_eq!(AuditSink::from_name("ftp://example.com/audits"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<AuditSink> {
+        let name = name.trim();
+        if let Some(path) = name.strip_prefix("file:") {
+            return (!path.is_empty()).then(|| AuditSink::File(PathBuf::from(path)));
+        }
+        if let Some(target) = name.strip_prefix("syslog:") {
+            return match target.strip_prefix("udp://") {
+                Some(address) if !address.is_empty() => {
+                    Some(AuditSink::SyslogUdp(address.to_string()))
+                }
+                Some(_) => None,
+                None if target.starts_with('/') => {
+                    Some(AuditSink::SyslogSocket(PathBuf::from(target)))
+                }
+                None => None,
+            };
+        }
+        if name.starts_with("https://") || name.starts_with("http://") {
+            return Some(AuditSink::Http(name.to_string()));
+        }
+        None
+    }
+
+    /// The sink as configured, which also identifies it in `audit_sink_cursors`.
+    pub fn name(&self) -> String {
+        match self {
+            AuditSink::File(path) => format!("file:{}", path.display()),
+            AuditSink::SyslogUdp(address) => format!("syslog:udp://{}", address),
+            AuditSink::SyslogSocket(path) => format!("syslog:{}", path.display()),
+            AuditSink::Http(url) => url.clone(),
+        }
+    }
+
+    /// Sends lines of the chain to the sink, in order.
+    async fn write(
+        &self,
+        lines: &[String],
+        config: &Config,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            AuditSink::File(path) => append(
+                path,
+                lines,
+                config.audit_file_max_bytes,
+                config.audit_file_keep,
+            )?,
+            AuditSink::SyslogUdp(address) => {
+                let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+                for line in lines {
+                    socket
+                        .send_to(syslog_message(line, config).as_bytes(), address.as_str())
+                        .await?;
+                }
+            }
+            AuditSink::SyslogSocket(path) => {
+                let socket = tokio::net::UnixDatagram::unbound()?;
+                for line in lines {
+                    socket
+                        .send_to(syslog_message(line, config).as_bytes(), path)
+                        .await?;
+                }
+            }
+            AuditSink::Http(url) => {
+                let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+                CLIENT
+                    .post(url)
+                    .header("Content-Type", "application/x-ndjson")
+                    .body(body)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The SHA-256 of a line of the chain without its `hash`, in hex.
+fn hash(line: &Value) -> String {
+    let mut unhashed = line.clone();
+    if let Some(fields) = unhashed.as_object_mut() {
+        fields.remove("hash");
+    }
+    Sha256::digest(unhashed.to_string())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The lines of the chain for audits following the line with the previous hash (empty for the
+/// first line of a sink), along with the hash of the last one.
+pub fn chain(audits: &[Audit], instance: &str, previous_hash: &str) -> (Vec<String>, String) {
+    let mut previous_hash = previous_hash.to_string();
+    let mut lines = vec![];
+    for audit in audits {
+        let mut line = json!({
+            "sequence": audit.id,
+            "instance": instance,
+            "audit": audit,
+            "previous_hash": previous_hash,
+        });
+        previous_hash = hash(&line);
+        line["hash"] = json!(previous_hash);
+        lines.push(line.to_string());
+    }
+    (lines, previous_hash)
+}
+
+/// Checks the lines of a chain, e.g. a mirrored file, returning the index of the first line which
+/// was changed or does not follow the line before it, if any.
+/// # Examples
+/// ```
+/// use labwhere::audit_sinks::verify_chain;
+/// assert_eq!(verify_chain(&[]), None);
+/// assert_eq!(verify_chain(&[r#"{"previous_hash":"","hash":"forged"}"#]), Some(0));
+/// ```
+pub fn verify_chain(lines: &[&str]) -> Option<usize> {
+    let mut previous_hash: Option<String> = None;
+    for (index, line) in lines.iter().enumerate() {
+        let Ok(line) = serde_json::from_str::<Value>(line) else {
+            return Some(index);
+        };
+        let follows = previous_hash
+            .as_deref()
+            .is_none_or(|previous_hash| line["previous_hash"] == previous_hash);
+        if !follows || line["hash"] != hash(&line) {
+            return Some(index);
+        }
+        previous_hash = line["hash"].as_str().map(String::from);
+    }
+    None
+}
+
+/// Formats a line as an RFC 5424 syslog message from the instance.
+fn syslog_message(line: &str, config: &Config) -> String {
+    format!(
+        "<{}>1 {} {} labwhere - audit - {}",
+        SYSLOG_PRIORITY,
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        config.instance_name,
+        line
+    )
+}
+
+/// Appends lines to a file, first rotating it if they would take it past `max_bytes` (0 for no
+/// limit). The file is synced to disk before returning.
+fn append(path: &Path, lines: &[String], max_bytes: u64, keep: u32) -> io::Result<()> {
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    if max_bytes > 0 && size > 0 && size + text.len() as u64 > max_bytes {
+        rotate(path, keep)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_data()
+}
+
+/// Moves a file to `{path}.1`, shifting older rotations along and dropping those beyond `keep`.
+fn rotate(path: &Path, keep: u32) -> io::Result<()> {
+    let rotated = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    if rotated(keep).exists() {
+        fs::remove_file(rotated(keep))?;
+    }
+    for n in (1..keep).rev() {
+        if rotated(n).exists() {
+            fs::rename(rotated(n), rotated(n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(1))
+}
+
+/// Mirrors the next batch of audits the sink has not had yet, and returns how many were mirrored.
+pub async fn mirror(
+    sink: &AuditSink,
+    config: &Config,
+    connection: &mut SqliteConnection,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let name = sink.name();
+    let (last_audit_id, last_hash) = sqlx::query_as::<_, (u32, String)>(
+        "SELECT last_audit_id, last_hash FROM audit_sink_cursors WHERE sink = ?",
+    )
+    .bind(&name)
+    .fetch_optional(&mut *connection)
+    .await?
+    .unwrap_or_default();
+    let audits =
+        sqlx::query_as::<_, Audit>("SELECT * FROM audits WHERE id > ? ORDER BY id LIMIT ?")
+            .bind(last_audit_id)
+            .bind(BATCH_SIZE)
+            .fetch_all(&mut *connection)
+            .await?;
+    let Some(last) = audits.last() else {
+        return Ok(0);
+    };
+    let (lines, hash) = chain(&audits, &config.instance_name, &last_hash);
+    sink.write(&lines, config).await?;
+    sqlx::query(
+        "INSERT INTO audit_sink_cursors (sink, last_audit_id, last_hash) VALUES (?, ?, ?)
+            ON CONFLICT (sink) DO UPDATE SET last_audit_id = excluded.last_audit_id,
+                last_hash = excluded.last_hash, updated_at = CURRENT_TIMESTAMP",
+    )
+    .bind(&name)
+    .bind(last.id)
+    .bind(hash)
+    .execute(&mut *connection)
+    .await?;
+    Ok(audits.len())
+}
+
+/// Mirrors the audits to the configured sinks as they are recorded, forever.
+///
+/// Meant to be spawned once when the server starts, if `LABWHERE_AUDIT_SINKS` is set. While a sink
+/// cannot be written to, failures are logged and the mirroring is retried from where it left off,
+/// without holding up the other sinks.
+pub async fn mirror_audits(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(MIRROR_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut connection = match acquire(&pool).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Could not mirror audits: {}", e);
+                continue;
+            }
+        };
+        for sink in &CONFIG.audit_sinks {
+            match mirror(sink, &CONFIG, &mut connection).await {
+                Ok(0) => {}
+                Ok(mirrored) => info!("Mirrored {} audits to {}", mirrored, sink.name()),
+                Err(e) => warn!("Could not mirror audits to {}: {}", sink.name(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit_sinks::*;
+    use crate::db::init_db;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::new_uuid;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            AuditSink::from_name("file:/var/log/labwhere/audits.jsonl"),
+            Some(AuditSink::File(PathBuf::from(
+                "/var/log/labwhere/audits.jsonl"
+            )))
+        );
+        assert_eq!(
+            AuditSink::from_name("syslog:/dev/log"),
+            Some(AuditSink::SyslogSocket(PathBuf::from("/dev/log")))
+        );
+        assert_eq!(
+            AuditSink::from_name(" https://collector.example.com/audits "),
+            Some(AuditSink::Http(
+                "https://collector.example.com/audits".to_string()
+            ))
+        );
+        for name in ["file:", "syslog:udp://", "syslog:dev/log", "audits.jsonl"] {
+            assert_eq!(AuditSink::from_name(name), None);
+        }
+    }
+
+    #[test]
+    fn test_rotate() {
+        let path = std::env::temp_dir().join(format!("labwhere-audits-{}.jsonl", new_uuid()));
+        let line = vec!["x".repeat(9)];
+        for _ in 0..4 {
+            append(&path, &line, 15, 2).unwrap();
+        }
+        let rotated = |n: u32| PathBuf::from(format!("{}.{}", path.display(), n));
+        assert_eq!(fs::read_to_string(&path).unwrap().len(), 10);
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap().len(), 10);
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap().len(), 10);
+        assert!(!rotated(3).exists());
+        for path in [path.clone(), rotated(1), rotated(2)] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mirror_to_file() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("labwhere-audits-{}.jsonl", new_uuid()));
+        let sink = AuditSink::File(path.clone());
+        let config = Config {
+            instance_name: "labwhere".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(mirror(&sink, &config, &mut conn).await.unwrap(), 1);
+        assert_eq!(mirror(&sink, &config, &mut conn).await.unwrap(), 0);
+        Location::create("freezer2".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(mirror(&sink, &config, &mut conn).await.unwrap(), 1);
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(verify_chain(&lines), None);
+        let line: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(line["sequence"], 2);
+        assert_eq!(line["instance"], "labwhere");
+        assert_eq!(line["audit"]["record_data"]["name"], "freezer2");
+
+        let tampered = lines[0].replace("freezer1", "freezer9");
+        assert_eq!(verify_chain(&[&tampered, lines[1]]), Some(0));
+        assert_eq!(verify_chain(&[lines[1], lines[0]]), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_to_syslog() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        Audit::create("Labware", 1, "create", None, &json!({}), &mut conn)
+            .await
+            .unwrap();
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sink = AuditSink::SyslogUdp(server.local_addr().unwrap().to_string());
+        let config = Config {
+            instance_name: "satellite-1".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(mirror(&sink, &config, &mut conn).await.unwrap(), 1);
+        let mut message = [0; 4096];
+        let read = server.recv(&mut message).await.unwrap();
+        let message = String::from_utf8_lossy(&message[..read]);
+        assert!(message.starts_with("<110>1 "));
+        assert!(message.contains(" satellite-1 labwhere - audit - {"));
+        assert!(message.contains(r#""sequence":1"#));
+    }
+}
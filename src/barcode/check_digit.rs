@@ -0,0 +1,15 @@
+use crate::errors::InvalidBarcodeError;
+pub use labwhere_core::check_digit::{append, compute, split, CheckDigitScheme, SEPARATOR};
+
+/// Validates a barcode which claims to carry a check character, see
+/// `labwhere_core::check_digit::validate`.
+///
+/// # Examples
+/// ```
+/// use labwhere::barcode::check_digit::{validate, CheckDigitScheme};
+/// assert!(validate(CheckDigitScheme::Mod43, "lw-freezer-1.e").is_ok());
+/// assert!(validate(CheckDigitScheme::Mod43, "lw-freezer-7.e").is_err());
+/// ```
+pub fn validate(scheme: CheckDigitScheme, barcode: &str) -> Result<(), InvalidBarcodeError> {
+    Ok(labwhere_core::check_digit::validate(scheme, barcode)?)
+}
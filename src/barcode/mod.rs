@@ -0,0 +1,147 @@
+// Module hierarchy of this module is as follows.
+// lib -> barcode -> (descendant e.g., check_digit)
+//
+// Barcode logic that does not depend on the database lives here so that it can be reused by the
+// models and the services alike.
+pub mod check_digit;
+pub mod parser;
+pub mod reader;
+pub mod signature;
+
+use crate::barcode::check_digit::CheckDigitScheme;
+use crate::barcode::parser::BarcodeParser;
+use crate::barcode::signature::SigningKey;
+use crate::config::Config;
+use crate::errors::InvalidBarcodeError;
+
+/// Generates the barcode of a location from its name and id, e.g. `lw-freezer-1`.
+///
+/// The name is trimmed, lowercased and has its spaces replaced with hyphens. The barcode is signed
+/// if a key is given, and then gets a check character if a scheme is given.
+///
+/// # Examples
+/// ```
+/// use labwhere::barcode::check_digit::CheckDigitScheme;
+/// use labwhere::barcode::generate;
+/// assert_eq!(generate("Freezer 1", 2, None, None), "lw-freezer-1-2");
+/// assert_eq!(generate("freezer", 1, Some(CheckDigitScheme::Mod43), None), "lw-freezer-1.e");
+/// ```
+pub fn generate(
+    name: &str,
+    id: u32,
+    check_digit: Option<CheckDigitScheme>,
+    signing_key: Option<&SigningKey>,
+) -> String {
+    let mut barcode = format!("lw-{}-{}", name.trim().replace(" ", "-").to_lowercase(), id);
+    if let Some(key) = signing_key {
+        barcode = signature::append(key, &barcode, id);
+    }
+    if let Some(scheme) = check_digit {
+        barcode = check_digit::append(scheme, &barcode);
+    }
+    barcode
+}
+
+/// Normalizes a scanned value to the barcode it is looked up by, running it through the
+/// `BarcodeParser` pipeline configured by `config`.
+///
+/// # Examples
+/// ```
+/// use labwhere::barcode::normalize;
+/// use labwhere::config::Config;
+/// assert_eq!(normalize(&Config::default(), "]C0lw-freezer-1\r\n").unwrap(), "lw-freezer-1");
+/// ```
+pub fn normalize(config: &Config, raw: &str) -> Result<String, InvalidBarcodeError> {
+    Ok(BarcodeParser::new(config).parse(raw)?.barcode)
+}
+
+/// Validates a scanned location barcode as configured: it must normalize, carry a valid check
+/// character if it claims one, and be signed by a configured key if there are any.
+pub fn validate(config: &Config, raw: &str) -> Result<(), InvalidBarcodeError> {
+    signature::verify_configured(config, &normalize(config, raw)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::barcode::check_digit::CheckDigitScheme;
+    use crate::barcode::signature::SigningKey;
+    use crate::barcode::*;
+    use proptest::prelude::*;
+
+    fn config(check_digit: Option<CheckDigitScheme>, signing_key: Option<&SigningKey>) -> Config {
+        Config {
+            barcode_check_digit: check_digit,
+            barcode_signing_keys: signing_key.into_iter().cloned().collect(),
+            ..Default::default()
+        }
+    }
+
+    fn check_digits() -> impl Strategy<Value = Option<CheckDigitScheme>> {
+        prop_oneof![
+            Just(None),
+            Just(Some(CheckDigitScheme::Mod10)),
+            Just(Some(CheckDigitScheme::Mod43)),
+        ]
+    }
+
+    fn signing_keys() -> impl Strategy<Value = Option<SigningKey>> {
+        proptest::option::of("[a-z0-9]{1,32}".prop_map(SigningKey::new))
+    }
+
+    #[test]
+    fn test_generate() {
+        let key = SigningKey::new("secret");
+        assert_eq!(generate(" Shelf 1 ", 3, None, None), "lw-shelf-1-3");
+        assert_eq!(
+            generate("freezer", 1, None, Some(&key)),
+            format!("lw-freezer-1-{}", signature::sign(&key, 1))
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let key = SigningKey::new("secret");
+        let config = config(Some(CheckDigitScheme::Mod43), Some(&key));
+        assert!(validate(&config, &generate("freezer", 1, None, Some(&key))).is_ok());
+        assert!(validate(&config, "lw-freezer-1").is_err());
+        assert!(validate(&config, "lw-freezer-1.x").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_barcodes_normalize_to_themselves(
+            name in crate::validation::tests::location_names(),
+            id in any::<u32>(),
+            check_digit in check_digits(),
+            signing_key in signing_keys(),
+        ) {
+            let config = config(check_digit, signing_key.as_ref());
+            let barcode = generate(&name, id, check_digit, signing_key.as_ref());
+            prop_assert_eq!(normalize(&config, &barcode).unwrap(), barcode.clone());
+            let scanned = format!("]C1{}\r\n", barcode);
+            prop_assert_eq!(normalize(&config, &scanned).unwrap(), barcode);
+        }
+
+        #[test]
+        fn test_generated_barcodes_validate(
+            name in crate::validation::tests::location_names(),
+            id in any::<u32>(),
+            check_digit in check_digits(),
+            signing_key in signing_keys(),
+        ) {
+            let config = config(check_digit, signing_key.as_ref());
+            let barcode = generate(&name, id, check_digit, signing_key.as_ref());
+            prop_assert!(validate(&config, &barcode).is_ok(), "{} is invalid", barcode);
+        }
+
+        #[test]
+        fn test_normalize_is_stable(raw in "(\\][A-Za-z][0-9])?\\s?\\PC{0,40}") {
+            let config = config(Some(CheckDigitScheme::Mod43), None);
+            if let Ok(barcode) = normalize(&config, &raw) {
+                if !barcode.starts_with(']') {
+                    prop_assert_eq!(normalize(&config, &barcode).unwrap(), barcode);
+                }
+            }
+        }
+    }
+}
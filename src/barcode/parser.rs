@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::errors::InvalidBarcodeError;
+pub use labwhere_core::parser::{ParsedBarcode, Symbology};
+
+/// The parsing pipeline for scanned barcodes (see `labwhere_core::parser::BarcodeParser`), set up
+/// with the configuration of the server.
+///
+/// Every barcode should go through this pipeline before it is looked up in the database.
+#[derive(Debug, Clone, Default)]
+pub struct BarcodeParser(labwhere_core::parser::BarcodeParser);
+
+impl BarcodeParser {
+    /// Creates a parser from the application configuration.
+    /// # Examples
+    /// ```
+    /// use labwhere::barcode::parser::BarcodeParser;
+    /// use labwhere::config::CONFIG;
+    /// let parsed = BarcodeParser::new(&CONFIG).parse("]C0lw-freezer-1\r\n").unwrap();
+    /// assert_eq!(parsed.barcode, "lw-freezer-1");
+    /// ```
+    pub fn new(config: &Config) -> BarcodeParser {
+        BarcodeParser(labwhere_core::parser::BarcodeParser {
+            base_url: config.base_url.clone(),
+            site_prefixes: config.barcode_site_prefixes.clone(),
+            labware_types: config.symbology_labware_types.clone(),
+            check_digit: config.barcode_check_digit,
+        })
+    }
+
+    /// Runs a raw scanned value through the pipeline.
+    pub fn parse(&self, raw: &str) -> Result<ParsedBarcode, InvalidBarcodeError> {
+        Ok(self.0.parse(raw)?)
+    }
+
+    /// The parser of the core, which e.g. works out scan plans
+    pub fn core(&self) -> &labwhere_core::parser::BarcodeParser {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::barcode::check_digit::CheckDigitScheme;
+    use crate::barcode::parser::*;
+    use std::collections::HashMap;
+
+    fn parser() -> BarcodeParser {
+        let config = Config {
+            barcode_site_prefixes: vec!["SNG:".to_string()],
+            symbology_labware_types: HashMap::from([
+                (Symbology::DataMatrix, "tube".to_string()),
+                (Symbology::Code128, "plate".to_string()),
+            ]),
+            ..Default::default()
+        };
+        BarcodeParser::new(&config)
+    }
+
+    #[test]
+    fn test_parse_plain_barcode() {
+        let parsed = parser().parse("lw-freezer-1").unwrap();
+        assert_eq!(parsed.barcode, "lw-freezer-1");
+        assert_eq!(parsed.symbology, None);
+        assert_eq!(parsed.site_prefix, None);
+        assert_eq!(parsed.labware_type, None);
+    }
+
+    #[test]
+    fn test_parse_trims_whitespace_and_control_characters() {
+        let parsed = parser().parse("  lw-freezer-1\r\n").unwrap();
+        assert_eq!(parsed.barcode, "lw-freezer-1");
+    }
+
+    #[test]
+    fn test_parse_trims_whitespace_after_prefixes() {
+        assert_eq!(
+            parser().parse("]C1 lw-freezer-1").unwrap().barcode,
+            "lw-freezer-1"
+        );
+        assert_eq!(parser().parse("SNG: DN1234").unwrap().barcode, "DN1234");
+        assert!(parser().parse("]C1 ").is_err());
+    }
+
+    #[test]
+    fn test_parse_strips_symbology_identifier() {
+        let parsed = parser().parse("]C1DN1234").unwrap();
+        assert_eq!(parsed.barcode, "DN1234");
+        assert_eq!(parsed.symbology, Some(Symbology::Code128));
+        assert_eq!(parsed.labware_type, Some("plate".to_string()));
+
+        let parsed = parser().parse("]Q1lw-freezer-1").unwrap();
+        assert_eq!(parsed.symbology, Some(Symbology::QrCode));
+        assert_eq!(parsed.labware_type, None);
+    }
+
+    #[test]
+    fn test_parse_strips_site_prefix_after_symbology() {
+        let parsed = parser().parse("]d2SNG:FR1234").unwrap();
+        assert_eq!(parsed.barcode, "FR1234");
+        assert_eq!(parsed.symbology, Some(Symbology::DataMatrix));
+        assert_eq!(parsed.site_prefix, Some("SNG:".to_string()));
+        assert_eq!(parsed.labware_type, Some("tube".to_string()));
+    }
+
+    #[test]
+    fn test_parse_strips_deep_link() {
+        let config = Config {
+            base_url: "https://labwhere.example.com".to_string(),
+            ..Default::default()
+        };
+        let parsed = BarcodeParser::new(&config)
+            .parse("]Q1https://labwhere.example.com/locations/lw-freezer-1")
+            .unwrap();
+        assert_eq!(parsed.barcode, "lw-freezer-1");
+        assert_eq!(parsed.symbology, Some(Symbology::QrCode));
+
+        let parsed = parser()
+            .parse("https://labwhere.example.com/locations/lw-freezer-1")
+            .unwrap();
+        assert_eq!(
+            parsed.barcode,
+            "https://labwhere.example.com/locations/lw-freezer-1"
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_input() {
+        assert!(parser().parse("]C").is_err());
+        assert!(parser().parse("]C1").is_err());
+        assert!(parser().parse("SNG:").is_err());
+        assert!(parser().parse("   ").is_err());
+    }
+
+    #[test]
+    fn test_parse_validates_check_digit() {
+        let config = Config {
+            barcode_check_digit: Some(CheckDigitScheme::Mod43),
+            ..Default::default()
+        };
+        let parser = BarcodeParser::new(&config);
+        assert!(parser.parse("]C0lw-freezer-1.e").is_ok());
+        assert!(parser.parse("]C0lw-freezer-7.e").is_err());
+    }
+
+    #[test]
+    fn test_symbology_names() {
+        assert_eq!(
+            Symbology::from_name("DataMatrix"),
+            Some(Symbology::DataMatrix)
+        );
+        assert_eq!(Symbology::from_name("qr"), Some(Symbology::QrCode));
+        assert_eq!(Symbology::from_name("aztec"), None);
+        assert_eq!(Symbology::from_code('z'), Symbology::Other('z'));
+    }
+}
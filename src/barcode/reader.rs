@@ -0,0 +1,115 @@
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use rxing::{BarcodeFormat, Exceptions};
+
+/// Decodes every 1D and 2D barcode visible in an image, e.g. a phone photo of a box of tubes.
+///
+/// The image can be a PNG or a JPEG. Each barcode is returned as a scanner would send it, prefixed
+/// with the AIM symbology identifier of its format (e.g. `]d0` for DataMatrix), so the values can
+/// be fed through the `BarcodeParser` pipeline like scanned barcodes. Barcodes are returned in the
+/// order they were found, without repeats.
+///
+/// Returns a `ValidationError` if the image cannot be read. An image without barcodes decodes to
+/// an empty list.
+pub fn decode(image: &[u8]) -> Result<Vec<String>, ValidationError> {
+    let luma = image::load_from_memory(image)
+        .map_err(|_| ValidationError {
+            message: Message::new("scan-image-unreadable"),
+        })?
+        .into_luma8();
+    let (width, height) = luma.dimensions();
+    let results = match rxing::helpers::detect_multiple_in_luma(luma.into_raw(), width, height) {
+        Ok(results) => results,
+        Err(Exceptions::NotFoundException(_)) => vec![],
+        Err(_) => {
+            return Err(ValidationError {
+                message: Message::new("scan-image-unreadable"),
+            })
+        }
+    };
+
+    let mut barcodes: Vec<String> = vec![];
+    for result in results {
+        let barcode = match aim_code(result.getBarcodeFormat()) {
+            Some(code) => format!("]{}0{}", code, result.getText()),
+            None => result.getText().to_string(),
+        };
+        if !barcodes.contains(&barcode) {
+            barcodes.push(barcode);
+        }
+    }
+    Ok(barcodes)
+}
+
+/// The AIM code character of a barcode format, as sent by scanners in symbology identifiers.
+fn aim_code(format: &BarcodeFormat) -> Option<char> {
+    match format {
+        BarcodeFormat::CODE_39 => Some('A'),
+        BarcodeFormat::CODE_128 => Some('C'),
+        BarcodeFormat::DATA_MATRIX => Some('d'),
+        BarcodeFormat::EAN_8
+        | BarcodeFormat::EAN_13
+        | BarcodeFormat::UPC_A
+        | BarcodeFormat::UPC_E => Some('E'),
+        BarcodeFormat::ITF => Some('I'),
+        BarcodeFormat::PDF_417 => Some('L'),
+        BarcodeFormat::QR_CODE => Some('Q'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::barcode::reader::*;
+    use image::{GrayImage, ImageFormat, Luma};
+    use qrcode::{Color, QrCode};
+    use std::io::Cursor;
+
+    /// Draws QR codes of the values side by side and encodes the picture as a PNG.
+    fn photo(values: &[&str]) -> Vec<u8> {
+        const SCALE: u32 = 6;
+        const MARGIN: u32 = 8;
+        let codes: Vec<QrCode> = values.iter().map(|v| QrCode::new(v).unwrap()).collect();
+        let size = codes.iter().map(|c| c.width() as u32).max().unwrap_or(0) * SCALE;
+        let mut picture = GrayImage::from_pixel(
+            (size + MARGIN * SCALE) * codes.len() as u32 + MARGIN * SCALE,
+            size + 2 * MARGIN * SCALE,
+            Luma([255]),
+        );
+        for (i, code) in codes.iter().enumerate() {
+            let left = MARGIN * SCALE + i as u32 * (size + MARGIN * SCALE);
+            for (index, color) in code.to_colors().iter().enumerate() {
+                if *color == Color::Dark {
+                    let x = left + (index % code.width()) as u32 * SCALE;
+                    let y = MARGIN * SCALE + (index / code.width()) as u32 * SCALE;
+                    for dx in 0..SCALE {
+                        for dy in 0..SCALE {
+                            picture.put_pixel(x + dx, y + dy, Luma([0]));
+                        }
+                    }
+                }
+            }
+        }
+        let mut png = Cursor::new(vec![]);
+        picture.write_to(&mut png, ImageFormat::Png).unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut barcodes = decode(&photo(&["lw-freezer-1", "lw-1", "lw-2"])).unwrap();
+        barcodes.sort();
+        assert_eq!(barcodes, vec!["]Q0lw-1", "]Q0lw-2", "]Q0lw-freezer-1"]);
+    }
+
+    #[test]
+    fn test_decode_without_barcodes() {
+        assert!(decode(&photo(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_unreadable_image() {
+        let error = decode(b"not an image").unwrap_err();
+        assert_eq!(error.message, "The image could not be read");
+    }
+}
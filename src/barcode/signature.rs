@@ -0,0 +1,215 @@
+use crate::barcode::check_digit;
+use crate::config::Config;
+use crate::errors::InvalidBarcodeError;
+use crate::i18n::Message;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::fmt::{Debug, Formatter};
+
+/// The number of hex characters of the HMAC kept in a signed barcode.
+///
+/// 8 characters (32 bits) keeps labels short while making a forged or mistyped barcode which
+/// still verifies a one in four billion chance.
+pub const SIGNATURE_LENGTH: usize = 8;
+
+/// The separator placed between a barcode and its signature, e.g. `lw-freezer-1-3f9a2c1b`.
+pub const SEPARATOR: char = '-';
+
+/// A secret key used to sign location barcodes.
+///
+/// The key is never printed, so configuration can be logged safely.
+#[derive(Clone, PartialEq)]
+pub struct SigningKey(String);
+
+impl SigningKey {
+    pub fn new(secret: impl Into<String>) -> SigningKey {
+        SigningKey(secret.into())
+    }
+
+    fn mac(&self, id: u32) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(id.to_string().as_bytes());
+        mac
+    }
+}
+
+impl Debug for SigningKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SigningKey(..)")
+    }
+}
+
+/// Computes the truncated HMAC-SHA256 of a location id, as lowercase hex.
+///
+/// # Examples
+/// ```
+/// use labwhere::barcode::signature::{sign, SigningKey};
+/// assert_eq!(sign(&SigningKey::new("secret"), 1).len(), 8);
+/// ```
+pub fn sign(key: &SigningKey, id: u32) -> String {
+    key.mac(id)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .take(SIGNATURE_LENGTH / 2)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Appends the signature of a location id to its barcode using the separator, e.g.
+/// `lw-freezer-1` becomes `lw-freezer-1-3f9a2c1b`.
+pub fn append(key: &SigningKey, barcode: &str, id: u32) -> String {
+    format!("{}{}{}", barcode, SEPARATOR, sign(key, id))
+}
+
+/// Splits a signed barcode into the id it was generated for and its signature.
+///
+/// Returns `None` if the barcode does not end with an id followed by a signature.
+fn split(barcode: &str) -> Option<(u32, &str)> {
+    let (rest, signature) = barcode.rsplit_once(SEPARATOR)?;
+    let (_, id) = rest.rsplit_once(SEPARATOR)?;
+    let is_signature =
+        signature.len() == SIGNATURE_LENGTH && signature.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_signature {
+        return None;
+    }
+    Some((id.parse().ok()?, signature))
+}
+
+/// Decodes a hex signature into bytes.
+fn decode(signature: &str) -> Option<Vec<u8>> {
+    (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Verifies a signed location barcode, without its check character if it has one.
+///
+/// The signature is checked against every key, so labels signed with a key which is being
+/// rotated out keep scanning until they are reprinted. Unsigned, forged and mistyped barcodes are
+/// rejected.
+///
+/// # Examples
+/// ```
+/// use labwhere::barcode::signature::{append, verify, SigningKey};
+/// let keys = vec![SigningKey::new("new"), SigningKey::new("old")];
+/// let barcode = append(&keys[1], "lw-freezer-1", 1);
+/// assert!(verify(&keys, &barcode).is_ok());
+/// assert!(verify(&keys, "lw-freezer-1").is_err());
+/// assert!(verify(&keys, &barcode.replace("-1-", "-2-")).is_err());
+/// ```
+pub fn verify(keys: &[SigningKey], barcode: &str) -> Result<(), InvalidBarcodeError> {
+    let verified = split(barcode).is_some_and(|(id, signature)| {
+        decode(signature).is_some_and(|signature| {
+            keys.iter()
+                .any(|key| key.mac(id).verify_truncated_left(&signature).is_ok())
+        })
+    });
+    if verified {
+        Ok(())
+    } else {
+        Err(InvalidBarcodeError {
+            message: Message::new("barcode-invalid-signature").arg("barcode", barcode),
+        })
+    }
+}
+
+/// Verifies a location barcode as configured: barcodes are only verified if signing keys are
+/// configured, and the check character (if check digits are enabled) is not part of the
+/// signed barcode.
+pub fn verify_configured(config: &Config, barcode: &str) -> Result<(), InvalidBarcodeError> {
+    if config.barcode_signing_keys.is_empty() {
+        return Ok(());
+    }
+    let signed = match config.barcode_check_digit {
+        Some(_) => check_digit::split(barcode).map_or(barcode, |(payload, _)| payload),
+        None => barcode,
+    };
+    verify(&config.barcode_signing_keys, signed).map_err(|_| InvalidBarcodeError {
+        message: Message::new("barcode-invalid-signature").arg("barcode", barcode),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::barcode::signature::*;
+
+    #[test]
+    fn test_sign() {
+        let key = SigningKey::new("secret");
+        let signature = sign(&key, 1);
+        assert_eq!(signature.len(), SIGNATURE_LENGTH);
+        assert!(signature.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(signature, sign(&key, 1));
+        assert_ne!(signature, sign(&key, 2));
+        assert_ne!(signature, sign(&SigningKey::new("other"), 1));
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("lw-freezer-12-3f9a2c1b"), Some((12, "3f9a2c1b")));
+        assert_eq!(split("lw-freezer-12"), None);
+        assert_eq!(split("lw-freezer-3f9a2c1b"), None);
+        assert_eq!(split("lw-freezer-12-3f9a2c1"), None);
+        assert_eq!(split("lw-freezer-12-3f9a2c1z"), None);
+    }
+
+    #[test]
+    fn test_verify() {
+        let key = SigningKey::new("secret");
+        let barcode = append(&key, "lw-freezer-12", 12);
+        let keys = vec![key];
+        assert!(verify(&keys, &barcode).is_ok());
+
+        // Forged and mistyped barcodes
+        assert!(verify(&[SigningKey::new("guess")], &barcode).is_err());
+        assert!(verify(&keys, "lw-freezer-13-00000000").is_err());
+        assert!(verify(&keys, &barcode.replace("-12-", "-13-")).is_err());
+        assert!(verify(&keys, "lw-freezer-12").is_err());
+        assert!(verify(&[], &barcode).is_err());
+    }
+
+    #[test]
+    fn test_verify_with_rotated_keys() {
+        let old = SigningKey::new("old");
+        let new = SigningKey::new("new");
+        let old_barcode = append(&old, "lw-freezer-1", 1);
+        let new_barcode = append(&new, "lw-freezer-2", 2);
+
+        let keys = vec![new.clone(), old.clone()];
+        assert!(verify(&keys, &old_barcode).is_ok());
+        assert!(verify(&keys, &new_barcode).is_ok());
+
+        // Once the old key is retired its labels no longer scan
+        assert!(verify(&[new], &old_barcode).is_err());
+    }
+
+    #[test]
+    fn test_verify_configured() {
+        let key = SigningKey::new("secret");
+        let signed = append(&key, "lw-freezer-1", 1);
+        let unsigned = Config::default();
+        assert!(verify_configured(&unsigned, "lw-freezer-1").is_ok());
+
+        let config = Config {
+            barcode_signing_keys: vec![key],
+            ..Default::default()
+        };
+        assert!(verify_configured(&config, &signed).is_ok());
+        assert!(verify_configured(&config, "lw-freezer-1").is_err());
+
+        let config = Config {
+            barcode_check_digit: Some(check_digit::CheckDigitScheme::Mod43),
+            ..config
+        };
+        let checked = check_digit::append(check_digit::CheckDigitScheme::Mod43, &signed);
+        assert!(verify_configured(&config, &checked).is_ok());
+    }
+
+    #[test]
+    fn test_signing_key_is_not_printed() {
+        assert_eq!(format!("{:?}", SigningKey::new("secret")), "SigningKey(..)");
+    }
+}
@@ -0,0 +1,143 @@
+use crate::cache::redis::Redis;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The most responses kept in this process. When full, expired responses are dropped first, then
+/// the oldest.
+const CAPACITY: usize = 10000;
+
+/// The responses to requests which carried an idempotency key, by key, each of which expires after
+/// a time to live.
+///
+/// Responses are kept in this process, or in Redis if the store is shared with other replicas (see
+/// `with_redis`), so a retry is recognised whichever replica it reaches. Should Redis fail, nothing
+/// is found and nothing is stored, so the request is handled as if it had no key.
+pub struct IdempotencyStore {
+    /// How long a response is kept for
+    ttl: Duration,
+    /// When each response was stored, and the response
+    entries: Mutex<HashMap<String, (Instant, Vec<u8>)>>,
+    /// The Redis server holding the responses instead, if the store is shared
+    redis: Option<&'static Redis>,
+}
+
+impl IdempotencyStore {
+    /// Create a new IdempotencyStore. A time to live of zero disables the store.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::cache::idempotency::IdempotencyStore;
+    /// use std::time::Duration;
+    /// let store = IdempotencyStore::new(Duration::from_secs(60));
+    /// store.put("lims:4b1c6a0e", b"201\n...".to_vec()).await;
+    /// assert!(store.get("lims:4b1c6a0e").await.is_some());
+    /// # }
+    /// ```
+    pub fn new(ttl: Duration) -> IdempotencyStore {
+        IdempotencyStore {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            redis: None,
+        }
+    }
+
+    /// Keeps the responses in Redis, shared by every replica, rather than in this process.
+    pub fn with_redis(mut self, redis: &'static Redis) -> IdempotencyStore {
+        self.redis = Some(redis);
+        self
+    }
+
+    /// The key of a response in Redis.
+    fn redis_key(key: &str) -> String {
+        format!("idempotency:{}", key)
+    }
+
+    /// The response stored for the key, if it has not expired.
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        if let Some(redis) = self.redis {
+            return redis
+                .get(&IdempotencyStore::redis_key(key))
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Could not look up idempotency key {} in Redis: {}", key, e);
+                    None
+                });
+        }
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some((stored, response)) if stored.elapsed() < self.ttl => Some(response.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores the response for the key.
+    pub async fn put(&self, key: &str, response: Vec<u8>) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        if let Some(redis) = self.redis {
+            if let Err(e) = redis
+                .set(&IdempotencyStore::redis_key(key), &response, self.ttl)
+                .await
+            {
+                warn!("Could not store idempotency key {} in Redis: {}", key, e);
+            }
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= CAPACITY && !entries.contains_key(key) {
+            entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+            if entries.len() >= CAPACITY {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, (stored, _))| *stored)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key.to_string(), (Instant::now(), response));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::idempotency::*;
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        let store = IdempotencyStore::new(Duration::from_millis(20));
+        assert_eq!(store.get("lims:1").await, None);
+        store.put("lims:1", b"201".to_vec()).await;
+        assert_eq!(store.get("lims:1").await, Some(b"201".to_vec()));
+        assert_eq!(store.get("robot:1").await, None);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(store.get("lims:1").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let store = IdempotencyStore::new(Duration::ZERO);
+        store.put("lims:1", b"201".to_vec()).await;
+        assert_eq!(store.get("lims:1").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis() {
+        let redis = Box::leak(Box::new(Redis::new("redis://127.0.0.1:1").unwrap()));
+        let store = IdempotencyStore::new(Duration::from_secs(60)).with_redis(redis);
+        store.put("lims:1", b"201".to_vec()).await;
+        assert_eq!(store.get("lims:1").await, None);
+    }
+}
@@ -0,0 +1,77 @@
+// Module hierarchy of this module is as follows.
+// lib -> cache -> (descendant e.g., negative)
+//
+// Caches used by the models and the services. Each cache is a lazily initialised static, sized
+// from the configuration. They are kept in this process, unless
+// `LABWHERE_REDIS_URL` is set, in which case they are kept in Redis and shared by every replica.
+use crate::cache::idempotency::IdempotencyStore;
+use crate::cache::negative::NegativeCache;
+use crate::cache::recorder::RequestRecorder;
+use crate::cache::redis::Redis;
+use crate::config::CONFIG;
+use log::error;
+use once_cell::sync::Lazy;
+use std::time::Duration;
+
+pub mod idempotency;
+pub mod negative;
+pub mod recorder;
+pub mod redis;
+
+/// The Redis server shared by every replica, if one is configured with `LABWHERE_REDIS_URL`.
+pub static REDIS: Lazy<Option<Redis>> = Lazy::new(|| {
+    let url = CONFIG.redis_url.as_ref()?;
+    match Redis::new(url) {
+        Ok(redis) => Some(redis),
+        Err(e) => {
+            error!(
+                "Keeping caches in this process, as LABWHERE_REDIS_URL is invalid: {}",
+                e
+            );
+            None
+        }
+    }
+});
+
+/// Barcodes which were recently looked up and not found, keyed by entity e.g. `location:lw-x-1`.
+///
+/// Scan storms of unregistered barcodes (e.g. from a misconfigured scanner) are answered from this
+/// cache instead of the database. Entries are removed as soon as a location or labware with the
+/// barcode is created.
+pub static NOT_FOUND_BARCODES: Lazy<NegativeCache> = Lazy::new(|| {
+    let cache = NegativeCache::new(
+        Duration::from_secs(CONFIG.negative_cache_seconds),
+        CONFIG.negative_cache_capacity,
+    );
+    match REDIS.as_ref() {
+        Some(redis) => cache.with_redis(redis),
+        None => cache,
+    }
+});
+
+/// The responses to requests made with an `Idempotency-Key` header, so that a request which is
+/// retried (e.g. after a timeout) is not handled twice.
+pub static IDEMPOTENT_RESPONSES: Lazy<IdempotencyStore> = Lazy::new(|| {
+    let store = IdempotencyStore::new(Duration::from_secs(CONFIG.idempotency_key_seconds));
+    match REDIS.as_ref() {
+        Some(redis) => store.with_redis(redis),
+        None => store,
+    }
+});
+
+/// The latest requests and their responses, if `LABWHERE_DEBUG_RECORDER_CAPACITY` is set. Always
+/// kept in this process.
+pub static RECORDED_REQUESTS: Lazy<RequestRecorder> =
+    Lazy::new(|| RequestRecorder::new(CONFIG.debug_recorder_capacity));
+
+/// The key of a location barcode in `NOT_FOUND_BARCODES`. Barcodes are matched regardless of
+/// case, so the key is lowercase.
+pub fn location_key(barcode: &str) -> String {
+    format!("location:{}", barcode.to_ascii_lowercase())
+}
+
+/// The key of a labware barcode in `NOT_FOUND_BARCODES`. Barcodes are matched regardless of case,
+/// so the key is lowercase.
+pub fn labware_key(barcode: &str) -> String {
+    format!("labware:{}", barcode.to_ascii_lowercase())
+}
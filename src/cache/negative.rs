@@ -0,0 +1,201 @@
+use crate::cache::redis::Redis;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A small cache of keys which are known not to exist, each of which expires after a time to live.
+///
+/// A time to live of zero disables the cache: nothing is stored and every lookup misses.
+///
+/// The entries are kept in this process, or in Redis if the cache is shared with other replicas
+/// (see `with_redis`). Should Redis fail, lookups miss and nothing is stored, so the database is
+/// asked instead.
+pub struct NegativeCache {
+    /// How long an entry is valid for
+    ttl: Duration,
+    /// The maximum number of entries. When full, expired entries are dropped first, then the oldest.
+    capacity: usize,
+    /// When each entry was inserted
+    entries: Mutex<HashMap<String, Instant>>,
+    /// The Redis server holding the entries instead, if the cache is shared
+    redis: Option<&'static Redis>,
+}
+
+impl NegativeCache {
+    /// Create a new NegativeCache
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::cache::negative::NegativeCache;
+    /// use std::time::Duration;
+    /// let cache = NegativeCache::new(Duration::from_secs(5), 1000);
+    /// cache.insert("labware:lw-1").await;
+    /// assert!(cache.contains("labware:lw-1").await);
+    /// # }
+    /// ```
+    pub fn new(ttl: Duration, capacity: usize) -> NegativeCache {
+        NegativeCache {
+            ttl,
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+            redis: None,
+        }
+    }
+
+    /// Keeps the entries in Redis, shared by every replica, rather than in this process. Redis
+    /// evicts the expired entries itself, so the capacity no longer applies.
+    pub fn with_redis(mut self, redis: &'static Redis) -> NegativeCache {
+        self.redis = Some(redis);
+        self
+    }
+
+    /// The key of an entry in Redis.
+    fn redis_key(key: &str) -> String {
+        format!("not-found:{}", key)
+    }
+
+    /// Whether the cache stores anything at all
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero() && self.capacity > 0
+    }
+
+    /// Whether the key is known not to exist. Expired entries are removed.
+    pub async fn contains(&self, key: &str) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        if let Some(redis) = self.redis {
+            return redis
+                .exists(&NegativeCache::redis_key(key))
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Could not look up {} in Redis: {}", key, e);
+                    false
+                });
+        }
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(inserted) if inserted.elapsed() < self.ttl => true,
+            Some(_) => {
+                entries.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Records that the key does not exist
+    pub async fn insert(&self, key: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(redis) = self.redis {
+            if let Err(e) = redis
+                .set(&NegativeCache::redis_key(key), b"1", self.ttl)
+                .await
+            {
+                warn!("Could not store {} in Redis: {}", key, e);
+            }
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(key) {
+            entries.retain(|_, inserted| inserted.elapsed() < self.ttl);
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, inserted)| **inserted)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key.to_string(), Instant::now());
+    }
+
+    /// Forgets the key e.g. because a record with it has just been created
+    pub async fn remove(&self, key: &str) {
+        if let Some(redis) = self.redis {
+            if let Err(e) = redis.delete(&NegativeCache::redis_key(key)).await {
+                warn!("Could not remove {} from Redis: {}", key, e);
+            }
+            return;
+        }
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    /// The number of entries kept in this process, including expired entries which have not been
+    /// removed yet
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether the cache has no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::negative::*;
+
+    #[tokio::test]
+    async fn test_insert_and_remove() {
+        let cache = NegativeCache::new(Duration::from_secs(60), 10);
+        assert!(!cache.contains("labware:lw-1").await);
+
+        cache.insert("labware:lw-1").await;
+        assert!(cache.contains("labware:lw-1").await);
+        assert!(!cache.contains("location:lw-1").await);
+
+        cache.remove("labware:lw-1").await;
+        assert!(!cache.contains("labware:lw-1").await);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expiry() {
+        let cache = NegativeCache::new(Duration::from_millis(10), 10);
+        cache.insert("labware:lw-1").await;
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!cache.contains("labware:lw-1").await);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_capacity() {
+        let cache = NegativeCache::new(Duration::from_secs(60), 2);
+        cache.insert("labware:lw-1").await;
+        std::thread::sleep(Duration::from_millis(1));
+        cache.insert("labware:lw-2").await;
+        std::thread::sleep(Duration::from_millis(1));
+        cache.insert("labware:lw-3").await;
+
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains("labware:lw-1").await);
+        assert!(cache.contains("labware:lw-2").await);
+        assert!(cache.contains("labware:lw-3").await);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let cache = NegativeCache::new(Duration::ZERO, 10);
+        assert!(!cache.is_enabled());
+        cache.insert("labware:lw-1").await;
+        assert!(!cache.contains("labware:lw-1").await);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis() {
+        let redis = Box::leak(Box::new(Redis::new("redis://127.0.0.1:1").unwrap()));
+        let cache = NegativeCache::new(Duration::from_secs(60), 10).with_redis(redis);
+        cache.insert("labware:lw-1").await;
+        assert!(!cache.contains("labware:lw-1").await);
+        cache.remove("labware:lw-1").await;
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+/// The most bytes of a request or response body which are kept. Longer bodies are cut short and
+/// marked as truncated.
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// A request the server handled and the response it sent, as kept by the `RequestRecorder`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordedExchange {
+    /// When the request was received
+    pub received_at: DateTime<Utc>,
+    /// The method of the request e.g. `POST`
+    pub method: String,
+    /// The path of the request, with its query string e.g. `/scan?user_code=jd12`
+    pub path: String,
+    /// The headers of the request, without those carrying credentials
+    pub request_headers: BTreeMap<String, String>,
+    /// The body of the request, or none if it was streamed to the endpoint without being recorded
+    pub request_body: Option<RecordedBody>,
+    /// The status of the response
+    pub status: u16,
+    /// The body of the response, or none if it was streamed to the client without being recorded
+    pub response_body: Option<RecordedBody>,
+    /// How long the request took to handle, in milliseconds
+    pub duration_ms: u64,
+}
+
+/// A body as it is kept: as text, with bytes which are not UTF-8 replaced.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordedBody {
+    /// The body, at most `MAX_BODY_BYTES` of it
+    pub content: String,
+    /// Whether the body was longer than `MAX_BODY_BYTES`
+    pub truncated: bool,
+}
+
+impl RecordedBody {
+    /// Keeps the first `MAX_BODY_BYTES` of a body
+    pub fn new(body: &[u8]) -> RecordedBody {
+        let truncated = body.len() > MAX_BODY_BYTES;
+        RecordedBody {
+            content: String::from_utf8_lossy(&body[..body.len().min(MAX_BODY_BYTES)]).into_owned(),
+            truncated,
+        }
+    }
+}
+
+/// The latest requests handled by this process and their responses, for diagnosing clients
+/// (e.g. scanner integrations which send malformed payloads) without reproducing their setup.
+///
+/// Only the last `capacity` exchanges are kept, the oldest being dropped first. Exchanges are
+/// never shared with other replicas, as they are only kept for a short while to be looked at.
+pub struct RequestRecorder {
+    /// How many exchanges are kept
+    capacity: usize,
+    /// The exchanges, oldest first
+    exchanges: Mutex<VecDeque<RecordedExchange>>,
+}
+
+impl RequestRecorder {
+    /// Create a new RequestRecorder. A capacity of zero disables the recorder.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::cache::recorder::RequestRecorder;
+    /// let recorder = RequestRecorder::new(100);
+    /// recorder.record(exchange);
+    /// assert_eq!(recorder.recent().len(), 1);
+    /// # }
+    /// ```
+    pub fn new(capacity: usize) -> RequestRecorder {
+        RequestRecorder {
+            capacity,
+            exchanges: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Whether exchanges are recorded at all
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Keeps an exchange, dropping the oldest if the recorder is full
+    pub fn record(&self, exchange: RecordedExchange) {
+        if !self.enabled() {
+            return;
+        }
+        let mut exchanges = self.exchanges.lock().unwrap();
+        if exchanges.len() == self.capacity {
+            exchanges.pop_front();
+        }
+        exchanges.push_back(exchange);
+    }
+
+    /// The exchanges which are kept, newest first
+    pub fn recent(&self) -> Vec<RecordedExchange> {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets every exchange
+    pub fn clear(&self) {
+        self.exchanges.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::recorder::*;
+
+    fn exchange(path: &str) -> RecordedExchange {
+        RecordedExchange {
+            received_at: Utc::now(),
+            method: "POST".to_string(),
+            path: path.to_string(),
+            request_headers: BTreeMap::new(),
+            request_body: Some(RecordedBody::new(b"{}")),
+            status: 200,
+            response_body: None,
+            duration_ms: 1,
+        }
+    }
+
+    #[test]
+    fn test_keeps_the_latest_exchanges() {
+        let recorder = RequestRecorder::new(2);
+        for path in ["/scan", "/labwares", "/locations"] {
+            recorder.record(exchange(path));
+        }
+        let paths: Vec<_> = recorder.recent().into_iter().map(|e| e.path).collect();
+        assert_eq!(paths, vec!["/locations", "/labwares"]);
+        recorder.clear();
+        assert!(recorder.recent().is_empty());
+
+        let disabled = RequestRecorder::new(0);
+        disabled.record(exchange("/scan"));
+        assert!(disabled.recent().is_empty());
+
+        let body = RecordedBody::new(&vec![b'a'; MAX_BODY_BYTES + 1]);
+        assert!(body.truncated);
+        assert_eq!(body.content.len(), MAX_BODY_BYTES);
+    }
+}
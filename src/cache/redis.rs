@@ -0,0 +1,88 @@
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::{Client, RedisResult};
+use std::time::Duration;
+
+/// How long a command, or connecting to the server, may take before it fails. Redis stands in
+/// for in-process state, so it must never hold up a request for long.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The prefix of every key LabWhere stores in Redis.
+const KEY_PREFIX: &str = "labwhere";
+
+/// A Redis server shared by every replica of LabWhere behind a load balancer, so that they agree
+/// on the barcodes which were not found, the requests an API key has made and the responses to
+/// idempotent requests.
+///
+/// The connection is made on first use and remade whenever it drops.
+pub struct Redis {
+    connection: ConnectionManager,
+}
+
+impl Redis {
+    /// Connects (lazily) to the server at a URL, e.g. `redis://:password@redis.example.com:6379/0`.
+    ///
+    /// Fails if the URL is invalid. Must be called from within the Tokio runtime.
+    pub fn new(url: &str) -> RedisResult<Redis> {
+        let config = ConnectionManagerConfig::new()
+            .set_number_of_retries(1)
+            .set_connection_timeout(Some(TIMEOUT))
+            .set_response_timeout(Some(TIMEOUT));
+        Ok(Redis {
+            connection: Client::open(url)?.get_connection_manager_lazy(config)?,
+        })
+    }
+
+    /// The full key of an entry, e.g. `labwhere:not-found:labware:lw-1`.
+    pub fn key(name: &str) -> String {
+        format!("{}:{}", KEY_PREFIX, name)
+    }
+
+    /// Whether there is an entry for the key.
+    pub async fn exists(&self, name: &str) -> RedisResult<bool> {
+        redis::cmd("EXISTS")
+            .arg(Redis::key(name))
+            .query_async(&mut self.connection.clone())
+            .await
+    }
+
+    /// The value of the entry for the key, if there is one.
+    pub async fn get(&self, name: &str) -> RedisResult<Option<Vec<u8>>> {
+        redis::cmd("GET")
+            .arg(Redis::key(name))
+            .query_async(&mut self.connection.clone())
+            .await
+    }
+
+    /// Stores a value for the key, which expires after the time to live.
+    pub async fn set(&self, name: &str, value: &[u8], ttl: Duration) -> RedisResult<()> {
+        redis::cmd("SET")
+            .arg(Redis::key(name))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl.as_millis().max(1) as u64)
+            .query_async(&mut self.connection.clone())
+            .await
+    }
+
+    /// Removes the entry for the key, if there is one.
+    pub async fn delete(&self, name: &str) -> RedisResult<()> {
+        redis::cmd("DEL")
+            .arg(Redis::key(name))
+            .query_async(&mut self.connection.clone())
+            .await
+    }
+
+    /// Adds one to the counter of the key and returns the new count. The counter expires once it
+    /// has not been counted for the time to live.
+    pub async fn increment(&self, name: &str, ttl: Duration) -> RedisResult<u64> {
+        let key = Redis::key(name);
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .incr(&key, 1)
+            .expire(&key, ttl.as_secs().max(1) as i64)
+            .ignore()
+            .query_async(&mut self.connection.clone())
+            .await?;
+        Ok(count)
+    }
+}
@@ -0,0 +1,369 @@
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+use sqlx::{Row, SqliteConnection};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// How many rows are read from the database and written as one row group at a time, so that
+/// exporting a long history never holds more than this many rows in memory.
+pub const ROW_GROUP_SIZE: u32 = 10_000;
+
+/// How a column is stored in Parquet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    /// A 64-bit integer
+    Integer,
+    /// A UTF-8 string
+    Text,
+    /// A UTC timestamp, in milliseconds since the epoch
+    Timestamp,
+    /// A day, in days since the epoch
+    Date,
+}
+
+/// A column of a dataset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+    /// The name of the column
+    pub name: &'static str,
+    /// How the column is stored
+    pub column_type: ColumnType,
+    /// Whether the column can be empty
+    pub optional: bool,
+}
+
+impl Column {
+    const fn new(name: &'static str, column_type: ColumnType, optional: bool) -> Column {
+        Column {
+            name,
+            column_type,
+            optional,
+        }
+    }
+
+    /// The Parquet schema of the column e.g. `REQUIRED INT64 created_at (TIMESTAMP(MILLIS,true));`
+    fn schema(&self) -> String {
+        let repetition = if self.optional {
+            "OPTIONAL"
+        } else {
+            "REQUIRED"
+        };
+        let physical = match self.column_type {
+            ColumnType::Integer => "INT64",
+            ColumnType::Text => "BYTE_ARRAY",
+            ColumnType::Timestamp => "INT64",
+            ColumnType::Date => "INT32",
+        };
+        let logical = match self.column_type {
+            ColumnType::Integer => "",
+            ColumnType::Text => " (UTF8)",
+            ColumnType::Timestamp => " (TIMESTAMP(MILLIS,true))",
+            ColumnType::Date => " (DATE)",
+        };
+        format!("{} {} {}{};", repetition, physical, self.name, logical)
+    }
+
+    /// The SQL which reads the column in the way it is stored, as SQLite keeps dates as text.
+    fn select(&self) -> String {
+        match self.column_type {
+            ColumnType::Integer | ColumnType::Text => self.name.to_string(),
+            ColumnType::Timestamp => format!(
+                "CAST(strftime('%s', {0}) AS INTEGER) * 1000 AS {0}",
+                self.name
+            ),
+            ColumnType::Date => format!(
+                "CAST(julianday({0}) - 2440587.5 AS INTEGER) AS {0}",
+                self.name
+            ),
+        }
+    }
+}
+
+/// A table exported for the data warehouse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dataset {
+    /// The name of the dataset, which is the name of its table and of its file
+    pub name: &'static str,
+    /// The columns of the dataset
+    pub columns: &'static [Column],
+}
+
+/// The datasets exported for the data warehouse: every scan, every audit and the daily occupancy
+/// snapshots, both overall and per location.
+pub const DATASETS: [Dataset; 4] = [
+    Dataset {
+        name: "scans",
+        columns: &[
+            Column::new("id", ColumnType::Integer, false),
+            Column::new("uuid", ColumnType::Text, false),
+            Column::new("location_id", ColumnType::Integer, false),
+            Column::new("message", ColumnType::Text, false),
+            Column::new("device_id", ColumnType::Integer, true),
+            Column::new("created_at", ColumnType::Timestamp, false),
+        ],
+    },
+    Dataset {
+        name: "audits",
+        columns: &[
+            Column::new("id", ColumnType::Integer, false),
+            Column::new("uuid", ColumnType::Text, false),
+            Column::new("auditable_type", ColumnType::Text, false),
+            Column::new("auditable_id", ColumnType::Integer, false),
+            Column::new("action", ColumnType::Text, false),
+            Column::new("location_id", ColumnType::Integer, true),
+            Column::new("record_data", ColumnType::Text, false),
+            Column::new("created_at", ColumnType::Timestamp, false),
+            Column::new("origin", ColumnType::Text, true),
+        ],
+    },
+    Dataset {
+        name: "occupancy_snapshots",
+        columns: &[
+            Column::new("day", ColumnType::Date, false),
+            Column::new("scope", ColumnType::Text, false),
+            Column::new("scope_id", ColumnType::Integer, false),
+            Column::new("name", ColumnType::Text, false),
+            Column::new("capacity", ColumnType::Integer, false),
+            Column::new("occupied", ColumnType::Integer, false),
+            Column::new("labwares", ColumnType::Integer, false),
+        ],
+    },
+    Dataset {
+        name: "snapshots",
+        columns: &[
+            Column::new("day", ColumnType::Date, false),
+            Column::new("location_id", ColumnType::Integer, false),
+            Column::new("labwares", ColumnType::Integer, false),
+            Column::new("capacity", ColumnType::Integer, false),
+        ],
+    },
+];
+
+impl Dataset {
+    /// Finds a dataset by its name e.g. `audits`.
+    /// # Examples
+    /// ```
+    /// use labwhere::db::analytics::Dataset;
+    /// assert_eq!(Dataset::find("audits").unwrap().name, "audits");
+    /// assert!(Dataset::find("labwares").is_none());
+    /// ```
+    pub fn find(name: &str) -> Option<Dataset> {
+        DATASETS.into_iter().find(|dataset| dataset.name == name)
+    }
+
+    /// The name of the file the dataset is exported to e.g. `audits.parquet`.
+    pub fn filename(&self) -> String {
+        format!("{}.parquet", self.name)
+    }
+
+    /// The Parquet schema of the dataset.
+    fn schema(&self) -> String {
+        let columns: Vec<String> = self.columns.iter().map(Column::schema).collect();
+        format!("message {} {{ {} }}", self.name, columns.join(" "))
+    }
+
+    /// Writes every row of the dataset as a Snappy-compressed Parquet file, returning the number
+    /// of rows written
+    ///
+    /// Rows are read and written `ROW_GROUP_SIZE` at a time (each batch becomes a row group), so
+    /// the history can be far larger than memory.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::db::analytics::Dataset;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let rows = Dataset::find("scans").unwrap().write(File::create("scans.parquet")?, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn write<W: Write + Send>(
+        &self,
+        out: W,
+        connection: &mut SqliteConnection,
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let schema = Arc::new(parse_message_type(&self.schema())?);
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+        let mut writer = SerializedFileWriter::new(out, schema, properties)?;
+        let columns: Vec<String> = self.columns.iter().map(Column::select).collect();
+        let query = format!(
+            "SELECT rowid, {} FROM {} WHERE rowid > ? ORDER BY rowid LIMIT ?",
+            columns.join(", "),
+            self.name
+        );
+
+        let mut last_rowid = 0_i64;
+        let mut written = 0_u64;
+        loop {
+            let rows = sqlx::query(&query)
+                .bind(last_rowid)
+                .bind(ROW_GROUP_SIZE)
+                .fetch_all(&mut *connection)
+                .await?;
+            let Some(last) = rows.last() else {
+                break;
+            };
+            last_rowid = last.try_get(0)?;
+
+            let mut row_group = writer.next_row_group()?;
+            for (index, column) in self.columns.iter().enumerate() {
+                let mut column_writer = row_group
+                    .next_column()?
+                    .ok_or("the schema has fewer columns than the dataset")?;
+                let index = index + 1;
+                match column.column_type {
+                    ColumnType::Integer | ColumnType::Timestamp => {
+                        let values = rows
+                            .iter()
+                            .map(|row| row.try_get::<Option<i64>, _>(index))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        write_column::<Int64Type>(&mut column_writer, values, column.optional)?;
+                    }
+                    ColumnType::Date => {
+                        let values = rows
+                            .iter()
+                            .map(|row| row.try_get::<Option<i32>, _>(index))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        write_column::<Int32Type>(&mut column_writer, values, column.optional)?;
+                    }
+                    ColumnType::Text => {
+                        let values = rows
+                            .iter()
+                            .map(|row| {
+                                row.try_get::<Option<String>, _>(index)
+                                    .map(|value| value.map(|value| ByteArray::from(value.as_str())))
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        write_column::<ByteArrayType>(&mut column_writer, values, column.optional)?;
+                    }
+                }
+                column_writer.close()?;
+            }
+            row_group.close()?;
+
+            written += rows.len() as u64;
+            if rows.len() < ROW_GROUP_SIZE as usize {
+                break;
+            }
+        }
+        writer.close()?;
+        Ok(written)
+    }
+}
+
+/// Writes the values of a column of a row group, marking the empty values of an optional column.
+fn write_column<T: DataType>(
+    column_writer: &mut SerializedColumnWriter,
+    values: Vec<Option<T::T>>,
+    optional: bool,
+) -> Result<(), parquet::errors::ParquetError> {
+    let definition_levels: Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+    let present: Vec<T::T> = values.into_iter().flatten().collect();
+    column_writer.typed::<T>().write_batch(
+        &present,
+        optional.then_some(definition_levels.as_slice()),
+        None,
+    )?;
+    Ok(())
+}
+
+/// Exports every dataset into a folder as `{dataset}.parquet`, returning the number of rows
+/// written to each.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::db::analytics::export;
+/// let mut connection = init_db("sqlite::memory:").await.unwrap();
+/// let written = export("analytics", &mut connection).await.unwrap();
+/// # }
+/// ```
+pub async fn export(
+    folder: impl AsRef<Path>,
+    connection: &mut SqliteConnection,
+) -> Result<Vec<(Dataset, u64)>, Box<dyn Error + Send + Sync>> {
+    std::fs::create_dir_all(&folder)?;
+    let mut written = vec![];
+    for dataset in DATASETS {
+        let file = File::create(folder.as_ref().join(dataset.filename()))?;
+        let rows = dataset
+            .write(BufWriter::new(file), &mut *connection)
+            .await?;
+        written.push((dataset, rows));
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::analytics::*;
+    use crate::db::init_db;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use hyper::body::Bytes;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn test_schema() {
+        assert_eq!(
+            Dataset::find("scans").unwrap().schema(),
+            "message scans { REQUIRED INT64 id; REQUIRED BYTE_ARRAY uuid (UTF8); \
+            REQUIRED INT64 location_id; REQUIRED BYTE_ARRAY message (UTF8); \
+            OPTIONAL INT64 device_id; REQUIRED INT64 created_at (TIMESTAMP(MILLIS,true)); }"
+        );
+        for dataset in DATASETS {
+            assert!(parse_message_type(&dataset.schema()).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+
+        let mut out = vec![];
+        let rows = Dataset::find("audits")
+            .unwrap()
+            .write(&mut out, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(rows, 2);
+
+        let reader = SerializedFileReader::new(Bytes::from(out)).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        let names: Vec<&str> = metadata
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| column.name())
+            .collect();
+        assert_eq!(names[..3], ["id", "uuid", "auditable_type"]);
+
+        let mut out = vec![];
+        let rows = Dataset::find("scans")
+            .unwrap()
+            .write(&mut out, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+        let reader = SerializedFileReader::new(Bytes::from(out)).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 0);
+    }
+}
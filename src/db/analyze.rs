@@ -0,0 +1,230 @@
+use crate::db::statements;
+use sqlx::SqliteConnection;
+use std::fmt::{Display, Formatter};
+
+/// The indexes the lookups made on every scan rely on, as the table and its indexed columns.
+/// They are created by the migrations.
+pub const EXPECTED_INDEXES: [(&str, &[&str]); 7] = [
+    ("labwares", &["barcode"]),
+    ("labwares", &["location_id"]),
+    ("locations", &["barcode"]),
+    ("locations", &["parent_id"]),
+    ("labware_barcodes", &["labware_id"]),
+    ("audits", &["auditable_type", "auditable_id"]),
+    ("audits", &["location_id"]),
+];
+
+/// The barcode-heavy queries whose query plans are checked, by name.
+pub const QUERIES: [(&str, &str); 9] = [
+    ("labware by barcode", statements::LABWARE_BY_BARCODE),
+    ("location by barcode", statements::LOCATION_BY_BARCODE),
+    ("location by alias", statements::LOCATION_BY_ALIAS),
+    ("labware at a position", statements::LABWARE_AT_POSITION),
+    (
+        "labwares in a location",
+        "SELECT * FROM labwares WHERE location_id = ? AND status = 'active'",
+    ),
+    (
+        "locations in a location",
+        "SELECT * FROM locations WHERE parent_id = ?",
+    ),
+    (
+        "barcodes of a labware",
+        "SELECT * FROM labware_barcodes WHERE labware_id = ?",
+    ),
+    (
+        "audits of a record",
+        "SELECT * FROM audits WHERE auditable_type = ? AND auditable_id = ? ORDER BY id DESC",
+    ),
+    (
+        "audits of a location",
+        "SELECT * FROM audits WHERE location_id = ? ORDER BY id DESC",
+    ),
+];
+
+/// An index which the lookups rely on but which the database does not have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingIndex {
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+/// A query which reads through a whole table rather than searching an index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowQuery {
+    pub name: String,
+    /// The steps of the `EXPLAIN QUERY PLAN` which scan a table e.g. `SCAN labwares`
+    pub scans: Vec<String>,
+}
+
+/// What `labwhere db analyze` found in a database.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub missing_indexes: Vec<MissingIndex>,
+    pub slow_queries: Vec<SlowQuery>,
+}
+
+impl Analysis {
+    /// Checks a database for missing indexes and for queries which scan whole tables.
+    ///
+    /// The database is not changed, so this shows the state of a database the migrations have not
+    /// been applied to yet.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// let mut connection = SqliteConnection::connect("sqlite://development.db").await.unwrap();
+    /// let analysis = Analysis::run(&mut connection).await.unwrap();
+    /// println!("{}", analysis);
+    /// # }
+    /// ```
+    pub async fn run(connection: &mut SqliteConnection) -> Result<Analysis, sqlx::Error> {
+        let mut analysis = Analysis::default();
+        for (table, columns) in EXPECTED_INDEXES {
+            if !has_index(table, columns, &mut *connection).await? {
+                analysis.missing_indexes.push(MissingIndex {
+                    table: table.to_string(),
+                    columns: columns.iter().map(|column| column.to_string()).collect(),
+                });
+            }
+        }
+        for (name, sql) in QUERIES {
+            // Unbound parameters are treated as NULL, which does not change the plan.
+            let scans: Vec<String> = sqlx::query_as::<_, (i64, i64, i64, String)>(&format!(
+                "EXPLAIN QUERY PLAN {}",
+                sql
+            ))
+            .fetch_all(&mut *connection)
+            .await?
+            .into_iter()
+            .map(|(_, _, _, detail)| detail)
+            .filter(|detail| is_table_scan(detail))
+            .collect();
+            if !scans.is_empty() {
+                analysis.slow_queries.push(SlowQuery {
+                    name: name.to_string(),
+                    scans,
+                });
+            }
+        }
+        Ok(analysis)
+    }
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        if self.missing_indexes.is_empty() {
+            writeln!(f, "No missing indexes.")?;
+        } else {
+            writeln!(f, "Missing indexes:")?;
+            for index in &self.missing_indexes {
+                writeln!(f, "  {} ({})", index.table, index.columns.join(", "))?;
+            }
+        }
+        if self.slow_queries.is_empty() {
+            writeln!(f, "No slow queries.")?;
+        } else {
+            writeln!(f, "Slow queries:")?;
+            for query in &self.slow_queries {
+                writeln!(f, "  {}: {}", query.name, query.scans.join("; "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a table has an index (including the ones of `UNIQUE` constraints) which starts with the
+/// columns, in order.
+async fn has_index(
+    table: &str,
+    columns: &[&str],
+    connection: &mut SqliteConnection,
+) -> Result<bool, sqlx::Error> {
+    let indexes: Vec<(String,)> = sqlx::query_as("SELECT name FROM pragma_index_list(?)")
+        .bind(table)
+        .fetch_all(&mut *connection)
+        .await?;
+    for (index,) in indexes {
+        let indexed: Vec<(String,)> =
+            sqlx::query_as("SELECT name FROM pragma_index_info(?) ORDER BY seqno")
+                .bind(&index)
+                .fetch_all(&mut *connection)
+                .await?;
+        if indexed.len() >= columns.len()
+            && indexed
+                .iter()
+                .zip(columns)
+                .all(|((indexed,), column)| indexed == column)
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether a step of a query plan reads through a whole table, e.g. `SCAN labwares`. Scanning the
+/// result of a subquery or a constant row is not a table scan.
+fn is_table_scan(detail: &str) -> bool {
+    detail.starts_with("SCAN ") && !detail.starts_with("SCAN CONSTANT ROW")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::analyze::*;
+    use crate::db::init_db;
+
+    #[tokio::test]
+    async fn test_run_with_migrations() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let analysis = Analysis::run(&mut conn).await.unwrap();
+        assert_eq!(analysis, Analysis::default());
+        assert_eq!(
+            analysis.to_string(),
+            "No missing indexes.\nNo slow queries.\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_without_indexes() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let indexes: Vec<(String,)> = sqlx::query_as(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL",
+        )
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+        for (index,) in indexes {
+            sqlx::query(&format!("DROP INDEX {}", index))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+
+        let analysis = Analysis::run(&mut conn).await.unwrap();
+        assert_eq!(analysis.missing_indexes.len(), EXPECTED_INDEXES.len());
+        assert_eq!(
+            analysis.missing_indexes[0],
+            MissingIndex {
+                table: "labwares".to_string(),
+                columns: vec!["barcode".to_string()],
+            }
+        );
+        let location_by_barcode = analysis
+            .slow_queries
+            .iter()
+            .find(|query| query.name == "location by barcode")
+            .unwrap();
+        assert_eq!(location_by_barcode.scans, vec!["SCAN locations"]);
+        assert!(analysis
+            .to_string()
+            .contains("Missing indexes:\n  labwares (barcode)\n"));
+    }
+
+    #[test]
+    fn test_is_table_scan() {
+        assert!(is_table_scan("SCAN labwares"));
+        assert!(!is_table_scan("SCAN CONSTANT ROW"));
+        assert!(!is_table_scan(
+            "SEARCH labwares USING INDEX index_labwares_on_barcode (barcode=?)"
+        ));
+    }
+}
@@ -19,16 +19,27 @@ use sqlx::migrate::MigrateDatabase;
 /// }
 /// ```
 pub async fn create_db(path: Option<&str>, environment: &str) -> Result<(), sqlx::Error> {
-    let url = match path {
+    sqlx::Sqlite::create_database(&database_url(path, environment)).await?;
+    Ok(())
+}
+
+/// Returns the url of the SQLite database for an environment.
+///
+/// # Examples
+/// ```
+/// use labwhere::db::create_db::database_url;
+/// assert_eq!(database_url(Some("src/db"), "test"), "sqlite://src/db/test.db");
+/// assert_eq!(database_url(None, "test"), "sqlite://test.db");
+/// ```
+pub fn database_url(path: Option<&str>, environment: &str) -> String {
+    match path {
         Some(path) => {
             format!("sqlite://{}/{}.db", path, environment)
         }
         None => {
             format!("sqlite://{}.db", environment)
         }
-    };
-    sqlx::Sqlite::create_database(&url).await?;
-    Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -41,7 +52,7 @@ mod tests {
     async fn test_create_db() {
         let result = create_db(None, "test").await;
         init_db("sqlite://test.db").await.unwrap();
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         sqlx::Sqlite::drop_database("sqlite://test.db")
             .await
             .unwrap();
@@ -51,7 +62,7 @@ mod tests {
     async fn test_create_db_with_path() {
         let result = create_db(Some("src/db"), "test").await;
         init_db("sqlite://src/db/test.db").await.unwrap();
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         sqlx::Sqlite::drop_database("sqlite://src/db/test.db")
             .await
             .unwrap();
@@ -0,0 +1,229 @@
+//! Recovering from a database which is busy or down.
+//!
+//! SQLite fails with `database is locked` when another connection holds the write lock for longer
+//! than the busy timeout. Such errors pass, so the operation is tried again after a while (see
+//! `Backoff`). Errors which mean the database cannot be reached at all (disk I/O errors, a file
+//! which cannot be opened, a pool which timed out) count towards the `DB_CIRCUIT` breaker instead,
+//! which answers requests with a 503 straight away while the database is down.
+
+use crate::config::{Config, CONFIG};
+use log::{error, warn};
+use once_cell::sync::Lazy;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The primary result codes of SQLite which are checked for. Extended codes carry the primary code
+/// in their lowest byte.
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+const SQLITE_IOERR: i32 = 10;
+const SQLITE_CANTOPEN: i32 = 14;
+
+/// The circuit breaker of the server's database, set up with `LABWHERE_DB_CIRCUIT_FAILURES` and
+/// `LABWHERE_DB_CIRCUIT_OPEN_SECONDS`.
+pub static DB_CIRCUIT: Lazy<CircuitBreaker> = Lazy::new(|| {
+    CircuitBreaker::new(
+        CONFIG.db_circuit_failures,
+        Duration::from_secs(CONFIG.db_circuit_open_seconds),
+    )
+});
+
+/// The primary SQLite result code of an error, if it came from SQLite.
+fn sqlite_code(err: &sqlx::Error) -> Option<i32> {
+    match err {
+        sqlx::Error::Database(e) => e.code()?.parse::<i32>().ok().map(|code| code & 0xff),
+        _ => None,
+    }
+}
+
+/// Whether an error is the database being locked by another connection, which passes
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    matches!(sqlite_code(err), Some(SQLITE_BUSY | SQLITE_LOCKED))
+}
+
+/// Whether an error means the database cannot be reached at all
+pub fn is_unavailable(err: &sqlx::Error) -> bool {
+    matches!(
+        err,
+        sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_)
+    ) || matches!(sqlite_code(err), Some(SQLITE_IOERR | SQLITE_CANTOPEN))
+}
+
+/// Tries an operation again, waiting longer each time, for as long as it fails because the
+/// database is locked.
+///
+/// Only whole operations are tried again, e.g. a transaction which was rolled back, so nothing is
+/// done twice.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::db::health::Backoff;
+/// let mut backoff = Backoff::new(&CONFIG);
+/// let scan = loop {
+///     match Scan::create(barcode.clone(), labwares.clone(), None, None, &mut connection).await {
+///         Err(e) if backoff.retry(&*e).await => continue,
+///         result => break result,
+///     }
+/// };
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Backoff {
+    /// How many more times the operation may be tried
+    attempts: u32,
+    /// How long to wait before the next attempt
+    delay: Duration,
+}
+
+impl Backoff {
+    /// The backoff of the configuration (`LABWHERE_DB_RETRY_ATTEMPTS` and
+    /// `LABWHERE_DB_RETRY_BACKOFF_MILLIS`)
+    pub fn new(config: &Config) -> Backoff {
+        Backoff {
+            attempts: config.db_retry_attempts,
+            delay: Duration::from_millis(config.db_retry_backoff_millis),
+        }
+    }
+
+    /// Waits before the next attempt and returns true, if the error is the database being locked
+    /// and attempts are left. Returns false straight away otherwise.
+    pub async fn retry(&mut self, err: &(dyn Error + Send + Sync + 'static)) -> bool {
+        let transient = err.downcast_ref::<sqlx::Error>().is_some_and(is_transient);
+        if !transient || self.attempts == 0 {
+            return false;
+        }
+        warn!("Trying again in {:?} as {}", self.delay, err);
+        tokio::time::sleep(self.delay).await;
+        self.attempts -= 1;
+        self.delay *= 2;
+        true
+    }
+}
+
+/// Whether the database is up, judged by the outcome of the latest attempts to reach it.
+///
+/// After `threshold` failures in a row the circuit opens: requests are refused without trying the
+/// database for `open_for`. Once that has passed, requests go through again; the first one to
+/// reach the database closes the circuit, and another failure opens it again straight away.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// The failures in a row which open the circuit. Zero never opens it.
+    threshold: u32,
+    /// How long the circuit stays open
+    open_for: Duration,
+    /// The failures in a row, and until when the circuit is open
+    state: Mutex<(u32, Option<Instant>)>,
+}
+
+impl CircuitBreaker {
+    /// Create a new CircuitBreaker, which is closed.
+    pub fn new(threshold: u32, open_for: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            open_for,
+            state: Mutex::new((0, None)),
+        }
+    }
+
+    /// How long until the database is tried again, if the circuit is open
+    pub fn retry_after(&self) -> Option<Duration> {
+        let (_, open_until) = *self.state.lock().unwrap();
+        open_until.and_then(|until| until.checked_duration_since(Instant::now()))
+    }
+
+    /// Records that the database was reached, closing the circuit
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = (0, None);
+    }
+
+    /// Records an error, which counts towards opening the circuit if it means the database
+    /// cannot be reached
+    pub fn record_failure(&self, err: &sqlx::Error) {
+        if self.threshold == 0 || !is_unavailable(err) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        if state.0 >= self.threshold {
+            error!(
+                "The database could not be reached {} times in a row, refusing requests for {:?}: {}",
+                state.0, self.open_for, err
+            );
+            state.1 = Some(Instant::now() + self.open_for);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::health::*;
+    use sqlx::sqlite::SqliteConnectOptions;
+    use sqlx::{ConnectOptions, Connection};
+
+    #[tokio::test]
+    async fn test_retries_a_locked_database() {
+        let path = std::env::temp_dir().join(format!("labwhere-{}.db", crate::models::new_uuid()));
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .busy_timeout(Duration::ZERO);
+        let mut holder = options.connect().await.unwrap();
+        let mut other = options.connect().await.unwrap();
+        sqlx::query("CREATE TABLE t (id INTEGER)")
+            .execute(&mut holder)
+            .await
+            .unwrap();
+        let mut transaction = holder.begin().await.unwrap();
+        sqlx::query("INSERT INTO t VALUES (1)")
+            .execute(&mut *transaction)
+            .await
+            .unwrap();
+
+        let error = sqlx::query("INSERT INTO t VALUES (2)")
+            .execute(&mut other)
+            .await
+            .unwrap_err();
+        assert!(is_transient(&error));
+        assert!(!is_unavailable(&error));
+
+        let config = Config {
+            db_retry_attempts: 2,
+            db_retry_backoff_millis: 1,
+            ..Default::default()
+        };
+        let mut backoff = Backoff::new(&config);
+        assert!(backoff.retry(&error).await);
+        assert!(backoff.retry(&error).await);
+        assert!(!backoff.retry(&error).await);
+        assert!(!Backoff::new(&config).retry(&sqlx::Error::RowNotFound).await);
+
+        transaction.commit().await.unwrap();
+        sqlx::query("INSERT INTO t VALUES (2)")
+            .execute(&mut other)
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(30));
+        breaker.record_failure(&sqlx::Error::PoolTimedOut);
+        assert_eq!(breaker.retry_after(), None);
+        // Errors of the query rather than of the database do not count
+        breaker.record_failure(&sqlx::Error::RowNotFound);
+        assert_eq!(breaker.retry_after(), None);
+        breaker.record_failure(&sqlx::Error::PoolTimedOut);
+        assert!(breaker
+            .retry_after()
+            .is_some_and(|retry_after| retry_after <= Duration::from_secs(30)));
+
+        breaker.record_success();
+        assert_eq!(breaker.retry_after(), None);
+
+        let disabled = CircuitBreaker::new(0, Duration::from_secs(30));
+        disabled.record_failure(&sqlx::Error::PoolTimedOut);
+        assert_eq!(disabled.retry_after(), None);
+    }
+}
@@ -1,3 +1,6 @@
+-- The tables LabWhere started with. Databases created before migrations were recorded already
+-- have them, so they are created only if they do not exist, and every column added since is added
+-- by a later migration.
 CREATE TABLE IF NOT EXISTS location_types  (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     name VARCHAR(255) NOT NULL
@@ -17,4 +20,3 @@ CREATE TABLE IF NOT EXISTS labwares (
     location_id INT NOT NULL,
     FOREIGN KEY (location_id) REFERENCES locations(id)
 );
-
@@ -0,0 +1,39 @@
+-- The columns added to the tables LabWhere started with.
+ALTER TABLE location_types ADD COLUMN uuid VARCHAR(36);
+
+ALTER TABLE locations ADD COLUMN uuid VARCHAR(36);
+ALTER TABLE locations ADD COLUMN parent_id INT REFERENCES locations(id);
+ALTER TABLE locations ADD COLUMN rows INT;
+ALTER TABLE locations ADD COLUMN columns INT;
+ALTER TABLE locations ADD COLUMN labwares_count INT NOT NULL DEFAULT 0;
+ALTER TABLE locations ADD COLUMN origin VARCHAR(255);
+ALTER TABLE locations ADD COLUMN owner VARCHAR(255);
+ALTER TABLE locations ADD COLUMN contact_email VARCHAR(255);
+ALTER TABLE locations ADD COLUMN notes TEXT;
+
+ALTER TABLE labwares ADD COLUMN uuid VARCHAR(36);
+ALTER TABLE labwares ADD COLUMN origin VARCHAR(255);
+ALTER TABLE labwares ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'active';
+ALTER TABLE labwares ADD COLUMN status_reason VARCHAR(255);
+
+-- Existing records are given a (version 4) UUID as their public identifier, which is then unique.
+-- SQLite cannot add a UNIQUE column, so uniqueness is enforced by an index.
+UPDATE location_types SET uuid = lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4'
+    || substr(lower(hex(randomblob(2))), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1)
+    || substr(lower(hex(randomblob(2))), 2) || '-' || lower(hex(randomblob(6)))
+WHERE uuid IS NULL;
+UPDATE locations SET uuid = lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4'
+    || substr(lower(hex(randomblob(2))), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1)
+    || substr(lower(hex(randomblob(2))), 2) || '-' || lower(hex(randomblob(6)))
+WHERE uuid IS NULL;
+UPDATE labwares SET uuid = lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4'
+    || substr(lower(hex(randomblob(2))), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1)
+    || substr(lower(hex(randomblob(2))), 2) || '-' || lower(hex(randomblob(6)))
+WHERE uuid IS NULL;
+CREATE UNIQUE INDEX index_location_types_on_uuid ON location_types (uuid);
+CREATE UNIQUE INDEX index_locations_on_uuid ON locations (uuid);
+CREATE UNIQUE INDEX index_labwares_on_uuid ON labwares (uuid);
+
+-- Existing labwares are all active, and counted in their locations.
+UPDATE locations
+SET labwares_count = (SELECT COUNT(*) FROM labwares WHERE labwares.location_id = locations.id);
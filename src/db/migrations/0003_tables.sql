@@ -0,0 +1,471 @@
+-- The tables added since LabWhere started.
+CREATE TABLE IF NOT EXISTS labware_positions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    labware_id INT NOT NULL UNIQUE,
+    location_id INT NOT NULL,
+    row_index INT NOT NULL,
+    column_index INT NOT NULL,
+    UNIQUE (location_id, row_index, column_index),
+    FOREIGN KEY (labware_id) REFERENCES labwares(id),
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS labware_barcodes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    labware_id INT NOT NULL,
+    barcode VARCHAR(255) NOT NULL UNIQUE,
+    is_primary BOOLEAN NOT NULL DEFAULT 0,
+    FOREIGN KEY (labware_id) REFERENCES labwares(id)
+);
+
+-- The barcodes of existing labwares are their primary barcodes. Should two labwares share one, it
+-- is kept by the first.
+INSERT OR IGNORE INTO labware_barcodes (uuid, labware_id, barcode, is_primary)
+SELECT lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4'
+        || substr(lower(hex(randomblob(2))), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1)
+        || substr(lower(hex(randomblob(2))), 2) || '-' || lower(hex(randomblob(6))),
+    id, barcode, 1
+FROM labwares
+ORDER BY id;
+
+CREATE TABLE IF NOT EXISTS location_barcode_aliases (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    location_id INT NOT NULL,
+    barcode VARCHAR(255) NOT NULL UNIQUE,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS print_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    printer VARCHAR(255) NOT NULL,
+    template VARCHAR(20) NOT NULL DEFAULT 'barcode',
+    state VARCHAR(20) NOT NULL DEFAULT 'queued',
+    error TEXT,
+    attempts INT NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS print_job_locations (
+    print_job_id INT NOT NULL,
+    location_id INT NOT NULL,
+    FOREIGN KEY (print_job_id) REFERENCES print_jobs(id),
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS audits (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    auditable_type VARCHAR(50) NOT NULL,
+    auditable_id INT NOT NULL,
+    action VARCHAR(50) NOT NULL,
+    location_id INT,
+    record_data TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    origin VARCHAR(255),
+    real_user VARCHAR(255),
+    effective_user VARCHAR(255),
+    previous_hash VARCHAR(64),
+    hash VARCHAR(64),
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS location_locks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    location_id INT NOT NULL UNIQUE,
+    holder VARCHAR(255) NOT NULL,
+    token VARCHAR(32) NOT NULL,
+    expires_at DATETIME NOT NULL,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS devices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    name VARCHAR(255) NOT NULL UNIQUE,
+    location_id INT,
+    api_key_digest VARCHAR(64) NOT NULL UNIQUE,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    last_seen_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    location_id INT NOT NULL,
+    message TEXT NOT NULL,
+    device_id INT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (location_id) REFERENCES locations(id),
+    FOREIGN KEY (device_id) REFERENCES devices(id)
+);
+
+CREATE TABLE IF NOT EXISTS checkouts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    labware_id INT NOT NULL,
+    holder VARCHAR(255) NOT NULL,
+    from_location_id INT NOT NULL,
+    to_location_id INT,
+    checked_out_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    due_at DATETIME,
+    checked_in_at DATETIME,
+    FOREIGN KEY (labware_id) REFERENCES labwares(id),
+    FOREIGN KEY (from_location_id) REFERENCES locations(id),
+    FOREIGN KEY (to_location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS location_flags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    location_id INT NOT NULL,
+    severity VARCHAR(20) NOT NULL,
+    note TEXT NOT NULL,
+    raised_by VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    cleared_by VARCHAR(255),
+    cleared_at DATETIME,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS stocktakes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    location_id INT NOT NULL,
+    started_by VARCHAR(255) NOT NULL,
+    state VARCHAR(20) NOT NULL DEFAULT 'open',
+    corrected BOOLEAN NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at DATETIME,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS stocktake_items (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    stocktake_id INT NOT NULL,
+    barcode VARCHAR(255) NOT NULL,
+    labware_id INT,
+    location_id INT NOT NULL,
+    scanned_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (stocktake_id, barcode),
+    FOREIGN KEY (stocktake_id) REFERENCES stocktakes(id),
+    FOREIGN KEY (labware_id) REFERENCES labwares(id),
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS stocktake_discrepancies (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    stocktake_id INT NOT NULL,
+    kind VARCHAR(20) NOT NULL,
+    labware_barcode VARCHAR(255) NOT NULL,
+    expected_location_id INT,
+    found_location_id INT,
+    corrected BOOLEAN NOT NULL DEFAULT 0,
+    FOREIGN KEY (stocktake_id) REFERENCES stocktakes(id),
+    FOREIGN KEY (expected_location_id) REFERENCES locations(id),
+    FOREIGN KEY (found_location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    location_id INT NOT NULL,
+    subscriber VARCHAR(255) NOT NULL,
+    channel VARCHAR(20) NOT NULL,
+    target VARCHAR(2048) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS subscription_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id INT NOT NULL,
+    audit_id INT NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    delivered_at DATETIME,
+    UNIQUE (subscription_id, audit_id),
+    FOREIGN KEY (subscription_id) REFERENCES subscriptions(id),
+    FOREIGN KEY (audit_id) REFERENCES audits(id)
+);
+
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    subscription_event_id INT NOT NULL,
+    error TEXT NOT NULL,
+    attempts INT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    redriven_by VARCHAR(255),
+    redriven_at DATETIME,
+    FOREIGN KEY (subscription_event_id) REFERENCES subscription_events(id)
+);
+
+CREATE TABLE IF NOT EXISTS audit_sink_cursors (
+    sink VARCHAR(255) PRIMARY KEY,
+    last_audit_id INT NOT NULL,
+    last_hash VARCHAR(64) NOT NULL,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS api_key_usage (
+    api_key VARCHAR(255) NOT NULL,
+    day DATE NOT NULL,
+    requests INT NOT NULL DEFAULT 0,
+    rejected INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (api_key, day)
+);
+
+CREATE TABLE IF NOT EXISTS occupancy_snapshots (
+    day DATE NOT NULL,
+    scope VARCHAR(20) NOT NULL,
+    scope_id INT NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    capacity INT NOT NULL,
+    occupied INT NOT NULL,
+    labwares INT NOT NULL,
+    PRIMARY KEY (day, scope, scope_id)
+);
+
+CREATE TABLE IF NOT EXISTS snapshots (
+    day DATE NOT NULL,
+    location_id INT NOT NULL,
+    labwares INT NOT NULL,
+    capacity INT NOT NULL,
+    PRIMARY KEY (location_id, day),
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS capacity_alerts (
+    location_id INT NOT NULL PRIMARY KEY,
+    percent INT NOT NULL,
+    threshold INT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS transit_alerts (
+    checkout_id INT NOT NULL PRIMARY KEY,
+    minutes INT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (checkout_id) REFERENCES checkouts(id)
+);
+
+CREATE TABLE IF NOT EXISTS confirmations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token VARCHAR(32) NOT NULL UNIQUE,
+    action VARCHAR(50) NOT NULL,
+    digest VARCHAR(64) NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS sync_peers (
+    name VARCHAR(255) NOT NULL PRIMARY KEY,
+    pushed_audit_id INT NOT NULL DEFAULT 0,
+    pulled_audit_id INT NOT NULL DEFAULT 0,
+    synced_at DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS sync_conflicts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    change_uuid VARCHAR(36) NOT NULL UNIQUE,
+    change_data TEXT NOT NULL,
+    location_uuid VARCHAR(36),
+    status VARCHAR(20) NOT NULL,
+    resolved_by VARCHAR(255),
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    resolved_at DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS pending_labwares (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    barcode VARCHAR(255) NOT NULL,
+    location_id INT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    reviewed_by VARCHAR(255),
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    reviewed_at DATETIME,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    login VARCHAR(255) NOT NULL UNIQUE,
+    role VARCHAR(20) NOT NULL,
+    team VARCHAR(255),
+    swipe_code_digest VARCHAR(64) NOT NULL UNIQUE,
+    api_key_digest VARCHAR(64) NOT NULL UNIQUE,
+    active BOOLEAN NOT NULL DEFAULT 1,
+    last_active_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    name VARCHAR(255) NOT NULL UNIQUE,
+    user_id INT,
+    key_digest VARCHAR(64) NOT NULL UNIQUE,
+    previous_key_digest VARCHAR(64) UNIQUE,
+    previous_key_expires_at DATETIME,
+    expires_at DATETIME,
+    revoked_at DATETIME,
+    last_used_at DATETIME,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE TABLE IF NOT EXISTS manifests (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    state VARCHAR(20) NOT NULL DEFAULT 'processing',
+    rows_processed INT NOT NULL DEFAULT 0,
+    rows_imported INT NOT NULL DEFAULT 0,
+    rows_failed INT NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS manifest_errors (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    manifest_id INT NOT NULL,
+    line INT NOT NULL,
+    barcode VARCHAR(255) NOT NULL,
+    location VARCHAR(255) NOT NULL DEFAULT '',
+    message_key VARCHAR(255) NOT NULL,
+    message_args TEXT NOT NULL DEFAULT '{}',
+    FOREIGN KEY (manifest_id) REFERENCES manifests(id)
+);
+
+CREATE TABLE IF NOT EXISTS shipments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    destination VARCHAR(255) NOT NULL,
+    courier_reference VARCHAR(255),
+    shipped_on DATE NOT NULL,
+    shipped_by VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS shipment_labwares (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    shipment_id INT NOT NULL,
+    labware_id INT NOT NULL,
+    barcode VARCHAR(255) NOT NULL,
+    location_id INT NOT NULL,
+    UNIQUE (shipment_id, labware_id),
+    FOREIGN KEY (shipment_id) REFERENCES shipments(id),
+    FOREIGN KEY (labware_id) REFERENCES labwares(id),
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS receipts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    reference VARCHAR(255) NOT NULL,
+    received_by VARCHAR(255) NOT NULL,
+    state VARCHAR(20) NOT NULL DEFAULT 'open',
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS receipt_items (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    receipt_id INT NOT NULL,
+    barcode VARCHAR(255) NOT NULL COLLATE NOCASE,
+    expected BOOLEAN NOT NULL DEFAULT 1,
+    received_at DATETIME,
+    UNIQUE (receipt_id, barcode),
+    FOREIGN KEY (receipt_id) REFERENCES receipts(id)
+);
+
+CREATE TABLE IF NOT EXISTS reservations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    location_id INT NOT NULL,
+    team VARCHAR(255) NOT NULL,
+    purpose VARCHAR(255),
+    whole_location BOOLEAN NOT NULL DEFAULT 0,
+    token VARCHAR(64) NOT NULL UNIQUE,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS reservation_positions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reservation_id INT NOT NULL,
+    row_index INT NOT NULL,
+    column_index INT NOT NULL,
+    UNIQUE (reservation_id, row_index, column_index),
+    FOREIGN KEY (reservation_id) REFERENCES reservations(id)
+);
+
+CREATE TABLE IF NOT EXISTS misplacements (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    scan_id INT NOT NULL,
+    labware_id INT NOT NULL,
+    labware_barcode VARCHAR(255) NOT NULL,
+    recorded_location_id INT NOT NULL,
+    found_location_id INT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    reviewed_by VARCHAR(255),
+    reviewed_at DATETIME,
+    FOREIGN KEY (scan_id) REFERENCES scans(id),
+    FOREIGN KEY (labware_id) REFERENCES labwares(id),
+    FOREIGN KEY (recorded_location_id) REFERENCES locations(id),
+    FOREIGN KEY (found_location_id) REFERENCES locations(id)
+);
+
+CREATE TABLE IF NOT EXISTS upload_mappings (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR(255) NOT NULL UNIQUE,
+    labware_column VARCHAR(255) NOT NULL,
+    location_column VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS saved_searches (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    owner VARCHAR(255) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    query VARCHAR(255) NOT NULL,
+    status VARCHAR(20),
+    result_limit INT NOT NULL,
+    sort VARCHAR(20) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (owner, name)
+);
+
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid VARCHAR(36) NOT NULL UNIQUE,
+    event_type VARCHAR(50) NOT NULL,
+    record_type VARCHAR(50) NOT NULL,
+    record_id INT NOT NULL,
+    data TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    real_user VARCHAR(255),
+    effective_user VARCHAR(255)
+);
+
+-- The event log is append-only. Only the time of an event can be corrected, e.g. to when a synced
+-- change happened on its origin.
+CREATE TRIGGER IF NOT EXISTS events_are_append_only
+BEFORE UPDATE OF uuid, event_type, record_type, record_id, data ON events
+BEGIN
+    SELECT RAISE(ABORT, 'events are append-only');
+END;
@@ -0,0 +1,9 @@
+-- Indexes for the lookups made on every scan: labwares and locations by barcode, the labwares
+-- in a location, the locations inside of a location and the audits of a record or location.
+CREATE INDEX IF NOT EXISTS index_labwares_on_barcode ON labwares (barcode);
+CREATE INDEX IF NOT EXISTS index_labwares_on_location_id ON labwares (location_id);
+CREATE INDEX IF NOT EXISTS index_locations_on_barcode ON locations (barcode);
+CREATE INDEX IF NOT EXISTS index_locations_on_parent_id ON locations (parent_id);
+CREATE INDEX IF NOT EXISTS index_labware_barcodes_on_labware_id ON labware_barcodes (labware_id);
+CREATE INDEX IF NOT EXISTS index_audits_on_auditable ON audits (auditable_type, auditable_id);
+CREATE INDEX IF NOT EXISTS index_audits_on_location_id ON audits (location_id);
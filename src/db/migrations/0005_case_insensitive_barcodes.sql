@@ -0,0 +1,5 @@
+-- Scanners and people disagree on case (`LW-BOX-1` and `lw-box-1`), so barcodes are looked up with
+-- `COLLATE NOCASE`. These indexes serve those lookups; the stored barcodes are left as they are.
+CREATE INDEX IF NOT EXISTS index_labwares_on_barcode_nocase ON labwares (barcode COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS index_locations_on_barcode_nocase ON locations (barcode COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS index_labware_barcodes_on_barcode_nocase ON labware_barcodes (barcode COLLATE NOCASE);
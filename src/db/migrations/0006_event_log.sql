@@ -0,0 +1,20 @@
+-- The events of a labware or a location are replayed to answer where it was at a point in time.
+CREATE INDEX IF NOT EXISTS index_events_on_record ON events (record_type, record_id);
+
+-- Locations and labwares which predate the event log are recorded as created where they are now,
+-- so that replaying the log reconstructs them.
+INSERT INTO events (uuid, event_type, record_type, record_id, data)
+SELECT lower(hex(randomblob(16))), 'LocationCreated', 'Location', id,
+    json_object('type', 'LocationCreated', 'location_id', id, 'name', name,
+        'location_type_id', location_type_id, 'parent_id', parent_id)
+FROM locations
+WHERE NOT EXISTS (SELECT 1 FROM events WHERE record_type = 'Location' AND record_id = locations.id)
+ORDER BY id;
+
+INSERT INTO events (uuid, event_type, record_type, record_id, data)
+SELECT lower(hex(randomblob(16))), 'LabwareCreated', 'Labware', id,
+    json_object('type', 'LabwareCreated', 'labware_id', id, 'barcode', barcode,
+        'location_id', location_id)
+FROM labwares
+WHERE NOT EXISTS (SELECT 1 FROM events WHERE record_type = 'Labware' AND record_id = labwares.id)
+ORDER BY id;
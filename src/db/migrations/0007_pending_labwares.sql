@@ -0,0 +1,4 @@
+-- Scans look for a pending labware with each unknown barcode they quarantine, so that a labware
+-- scanned again before it is reviewed is quarantined once.
+CREATE INDEX IF NOT EXISTS index_pending_labwares_on_barcode_nocase
+ON pending_labwares (barcode COLLATE NOCASE, status);
@@ -0,0 +1,4 @@
+-- Locations which are not found by their barcode are looked for by the barcodes they had before
+-- their barcodes were regenerated, so old labels keep scanning.
+CREATE INDEX IF NOT EXISTS index_location_barcode_aliases_on_barcode_nocase
+ON location_barcode_aliases (barcode COLLATE NOCASE);
@@ -1,10 +1,36 @@
-use sqlx::{Connection, Error, SqliteConnection};
-use std::fs;
+use crate::config::CONFIG;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{ConnectOptions, Error, SqliteConnection, SqlitePool};
+use std::str::FromStr;
 
+pub mod analytics;
+pub mod analyze;
 pub mod create_db;
+pub mod health;
+pub mod query;
 pub mod savable;
+pub mod snapshot;
+pub mod statements;
 
-/// Initializes a test database and injects the schemas.
+/// The migrations which create and change the schema, in the order of their versions.
+///
+/// They are embedded in the binary, so it can run from any directory. The versions which have been
+/// applied to a database are recorded in its `_sqlx_migrations` table, so each migration is applied
+/// once; a migration must never be edited once released, only followed by another.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./src/db/migrations");
+
+/// The options of connections to the database at a URL, with statement caching sized from the
+/// configuration.
+///
+/// Every query the models make is prepared once per connection and then reused from the cache, as
+/// long as its SQL text is identical (see `statements`).
+pub fn connect_options(url: &str) -> Result<SqliteConnectOptions, Error> {
+    Ok(SqliteConnectOptions::from_str(url)?
+        .statement_cache_capacity(CONFIG.statement_cache_capacity))
+}
+
+/// Initializes a test database and applies the migrations it has not had yet.
 ///
 /// The visibility of this function **cannot** be made `pub(crate)`` as the ancestry hierarchy of this module is is follows:
 ///     `db -> labwhere (lib)``.
@@ -31,9 +57,133 @@ pub mod savable;
 ///     assert_eq!(location_types.len(), 1);
 /// }
 pub async fn init_db(url: &str) -> Result<SqliteConnection, Error> {
-    let mut connection = SqliteConnection::connect(url).await?;
-    let schemas =
-        fs::read_to_string("./src/db/schema.sql").expect("Something went wrong reading the file");
-    sqlx::query(&schemas).execute(&mut connection).await?;
+    init_db_with(connect_options(url)?).await
+}
+
+/// Like `init_db`, with the options of the connection e.g. to size its statement cache.
+///
+/// Example usage:
+/// ```
+/// # #[cfg(doctest)] {
+/// let options = connect_options("sqlite::memory:").unwrap().statement_cache_capacity(0);
+/// let mut connection = init_db_with(options).await.unwrap();
+/// # }
+/// ```
+pub async fn init_db_with(options: SqliteConnectOptions) -> Result<SqliteConnection, Error> {
+    let mut connection = options.connect().await?;
+    MIGRATOR.run(&mut connection).await?;
     Ok(connection)
 }
+
+/// Initializes a connection pool and applies the migrations the database has not had yet.
+///
+/// The server shares this pool between all of its connections; each request acquires a connection
+/// from it and passes `&mut *connection` to the models, which accept a `SqliteConnection`.
+///
+/// An in-memory database only lives as long as the connection that created it, so an in-memory pool
+/// is restricted to a single connection which is never closed. Connections are checked before they
+/// are handed out, so one whose file went away is replaced rather than failing the request.
+///
+/// Example usage:
+/// ```
+/// # #[cfg(doctest)] {
+/// let pool = init_pool("sqlite::memory:").await.unwrap();
+/// let mut connection = pool.acquire().await.unwrap();
+/// let location_type = LocationType::create("Freezer".to_string(), &mut connection).await.unwrap();
+/// # }
+/// ```
+pub async fn init_pool(url: &str) -> Result<SqlitePool, Error> {
+    let options = if url.contains(":memory:") {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+    } else {
+        SqlitePoolOptions::new()
+    }
+    .test_before_acquire(true);
+    let pool = options.connect_with(connect_options(url)?).await?;
+    MIGRATOR.run(&pool).await?;
+    Ok(pool)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{init_db, MIGRATOR};
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use sqlx::migrate::MigrateDatabase;
+    use sqlx::{Connection, SqliteConnection};
+
+    #[tokio::test]
+    async fn test_migrations_are_applied_once() {
+        let url = "sqlite://migrations-test.db";
+        sqlx::Sqlite::create_database(url).await.unwrap();
+        init_db(url).await.unwrap().close().await.unwrap();
+        let mut conn = init_db(url).await.unwrap();
+        let applied = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM _sqlx_migrations")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(applied, MIGRATOR.iter().count() as i64);
+        conn.close().await.unwrap();
+        sqlx::Sqlite::drop_database(url).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrate_a_database_with_the_original_tables() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::raw_sql(
+            "CREATE TABLE location_types (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name VARCHAR(255) NOT NULL);
+            CREATE TABLE locations (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name VARCHAR(255) NOT NULL, barcode VARCHAR(255), location_type_id INT NOT NULL);
+            CREATE TABLE labwares (id INTEGER PRIMARY KEY AUTOINCREMENT,
+                barcode VARCHAR(255) NOT NULL, location_id INT NOT NULL);
+            INSERT INTO location_types (name) VALUES ('Freezer');
+            INSERT INTO locations (name, barcode, location_type_id)
+                VALUES ('freezer1', 'lw-freezer1-1', 1), ('freezer2', 'lw-freezer2-2', 1);
+            INSERT INTO labwares (barcode, location_id) VALUES ('trac-1', 1), ('trac-2', 1);",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let uuids = sqlx::query_scalar::<_, String>(
+            "SELECT uuid FROM location_types UNION ALL SELECT uuid FROM locations
+                UNION ALL SELECT uuid FROM labwares",
+        )
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+        assert_eq!(uuids.len(), 5);
+        assert!(uuids
+            .iter()
+            .all(|uuid| uuid::Uuid::parse_str(uuid).unwrap().get_version_num() == 4));
+        let error = sqlx::query(
+            "UPDATE labwares SET uuid = (SELECT uuid FROM labwares WHERE id = 1) WHERE id = 2",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("UNIQUE constraint failed"));
+
+        let freezer = Location::find_by_barcode("lw-freezer1-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(freezer.labwares_count, 2);
+        assert_eq!(freezer.parent_id, None);
+        let mut labware = Labware::find_by_barcode("trac-2".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, freezer.id);
+        labware.location_id = 2;
+        Labware::update(&labware, None, &mut conn).await.unwrap();
+        let freezer = Location::find_by_barcode("lw-freezer1-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(freezer.labwares_count, 1);
+    }
+}
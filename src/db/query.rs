@@ -0,0 +1,318 @@
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Column, Executor, Row, SqliteConnection, Statement, TypeInfo, ValueRef};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// How many virtual machine instructions SQLite runs between checks of the time limit.
+const PROGRESS_OPS: i32 = 1000;
+
+/// A single `SELECT` (or `WITH ... SELECT`) statement, checked so it cannot change the database.
+///
+/// The statement is run as a subquery, `SELECT * FROM (...) LIMIT ?`, which SQLite only accepts
+/// for a query, and with `PRAGMA query_only` on, so anything that slips past the checks still
+/// cannot write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadOnlyQuery {
+    sql: String,
+}
+
+/// The rows a `ReadOnlyQuery` returned.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryResult {
+    /// The names of the columns, in order
+    pub columns: Vec<String>,
+    /// The rows, each with a value per column
+    pub rows: Vec<Vec<Value>>,
+    /// Whether the query had more rows than the limit, which were left out
+    pub truncated: bool,
+}
+
+impl ReadOnlyQuery {
+    /// Checks that the SQL is a single `SELECT` statement
+    ///
+    /// A trailing `;` is allowed. Comments, strings and quoted names are skipped, so e.g. a `;` in
+    /// a `LIKE` pattern is fine.
+    /// # Examples
+    /// ```
+    /// use labwhere::db::query::ReadOnlyQuery;
+    /// assert!(ReadOnlyQuery::parse("SELECT * FROM labwares WHERE barcode LIKE '%;%';").is_ok());
+    /// assert!(ReadOnlyQuery::parse("DELETE FROM labwares").is_err());
+    /// assert!(ReadOnlyQuery::parse("SELECT 1; DROP TABLE labwares").is_err());
+    /// ```
+    pub fn parse(sql: &str) -> Result<ReadOnlyQuery, ValidationError> {
+        let not_select = || ValidationError {
+            message: Message::new("admin-query-not-select"),
+        };
+        let code = without_literals(sql);
+        let (sql, code) = match code.trim_end().strip_suffix(';') {
+            Some(statement) => (&sql[..statement.len()], statement),
+            None => (sql, code.as_str()),
+        };
+        if code.contains(';') {
+            return Err(not_select());
+        }
+        let keyword: String = code
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect();
+        if !["SELECT", "WITH"].contains(&keyword.to_uppercase().as_str()) {
+            return Err(not_select());
+        }
+        Ok(ReadOnlyQuery {
+            sql: sql.to_string(),
+        })
+    }
+
+    /// Runs the query, returning at most `max_rows` rows
+    ///
+    /// The connection refuses to write while the query runs. SQLite is interrupted if the query
+    /// runs for longer than `timeout`, and a `ValidationError` is returned, as it is if the query
+    /// is not valid SQL.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::db::query::ReadOnlyQuery;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let query = ReadOnlyQuery::parse("SELECT barcode FROM labwares").unwrap();
+    /// let result = query.run(100, Duration::from_secs(5), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn run(
+        &self,
+        max_rows: u32,
+        timeout: Duration,
+        connection: &mut SqliteConnection,
+    ) -> Result<QueryResult, Box<dyn Error + Send + Sync>> {
+        let deadline = Instant::now() + timeout;
+        sqlx::query("PRAGMA query_only = ON")
+            .execute(&mut *connection)
+            .await?;
+        connection
+            .lock_handle()
+            .await?
+            .set_progress_handler(PROGRESS_OPS, move || Instant::now() < deadline);
+        let result = self.fetch(max_rows, &mut *connection).await;
+        connection.lock_handle().await?.remove_progress_handler();
+        sqlx::query("PRAGMA query_only = OFF")
+            .execute(&mut *connection)
+            .await?;
+
+        result.map_err(|e| match e {
+            sqlx::Error::Database(_) if Instant::now() >= deadline => Box::new(ValidationError {
+                message: Message::new("admin-query-timeout").arg("seconds", timeout.as_secs_f64()),
+            })
+                as Box<dyn Error + Send + Sync>,
+            sqlx::Error::Database(e) => Box::new(ValidationError {
+                message: Message::new("admin-query-failed").arg("error", e.message()),
+            }),
+            e => Box::new(e),
+        })
+    }
+
+    async fn fetch(
+        &self,
+        max_rows: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<QueryResult, sqlx::Error> {
+        // The newline stops a trailing `--` comment from swallowing the closing parenthesis.
+        let sql = format!("SELECT * FROM (\n{}\n) LIMIT ?", self.sql);
+        let statement = (&mut *connection).prepare(sql.as_str()).await?;
+        let columns = statement
+            .columns()
+            .iter()
+            .map(|column| column.name().to_string())
+            .collect();
+        let mut rows = statement
+            .query()
+            .bind(max_rows as i64 + 1)
+            .fetch_all(&mut *connection)
+            .await?;
+        let truncated = rows.len() > max_rows as usize;
+        rows.truncate(max_rows as usize);
+        let rows = rows
+            .iter()
+            .map(|row| (0..row.len()).map(|index| to_json(row, index)).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(QueryResult {
+            columns,
+            rows,
+            truncated,
+        })
+    }
+}
+
+/// Reads a value of a row as JSON by its storage class, as the columns of a query have no declared
+/// types. Blobs are written in hexadecimal.
+fn to_json(row: &sqlx::sqlite::SqliteRow, index: usize) -> Result<Value, sqlx::Error> {
+    let value = row.try_get_raw(index)?;
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+    let type_name = value.type_info().name().to_string();
+    Ok(match type_name.as_str() {
+        "INTEGER" => Value::from(row.try_get_unchecked::<i64, _>(index)?),
+        "REAL" => Value::from(row.try_get_unchecked::<f64, _>(index)?),
+        "BLOB" => Value::from(
+            row.try_get_unchecked::<Vec<u8>, _>(index)?
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>(),
+        ),
+        _ => Value::from(row.try_get_unchecked::<String, _>(index)?),
+    })
+}
+
+/// Blanks out the comments, strings and quoted names of SQL, keeping the position of everything
+/// else, so the statement itself can be checked.
+fn without_literals(sql: &str) -> String {
+    let mut code = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let blank = |code: &mut String, c: char| code.extend(std::iter::repeat_n(' ', c.len_utf8()));
+    while let Some(c) = chars.next() {
+        let end = match (c, chars.peek()) {
+            ('-', Some('-')) => "\n",
+            ('/', Some('*')) => "*/",
+            ('\'', _) => "'",
+            ('"', _) => "\"",
+            ('`', _) => "`",
+            ('[', _) => "]",
+            _ => {
+                code.push(c);
+                continue;
+            }
+        };
+        blank(&mut code, c);
+        if end.len() == 2 || c == '-' {
+            // Skip the second character of the opening, so `/*/` does not close the comment.
+            if let Some(c) = chars.next() {
+                blank(&mut code, c);
+            }
+        }
+        let mut previous = None;
+        for c in chars.by_ref() {
+            blank(&mut code, c);
+            let closed = match end {
+                "*/" => previous == Some('*') && c == '/',
+                _ => end.starts_with(c),
+            };
+            if closed {
+                break;
+            }
+            previous = Some(c);
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::db::query::*;
+
+    #[test]
+    fn test_parse() {
+        for sql in [
+            "select 1",
+            "  WITH t AS (SELECT 1 AS n) SELECT n FROM t;  ",
+            "-- the labwares\nSELECT * FROM labwares",
+            "SELECT ';' AS \"a;b\" /* ; */",
+        ] {
+            assert!(ReadOnlyQuery::parse(sql).is_ok(), "{}", sql);
+        }
+        for sql in [
+            "",
+            "UPDATE labwares SET barcode = 'x'",
+            "PRAGMA query_only = OFF",
+            "SELECT 1; DELETE FROM labwares",
+            "/* SELECT */ DROP TABLE labwares",
+        ] {
+            assert!(ReadOnlyQuery::parse(sql).is_err(), "{}", sql);
+        }
+        assert_eq!(
+            ReadOnlyQuery::parse("SELECT 1; -- done").unwrap().sql,
+            "SELECT 1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let query = ReadOnlyQuery::parse(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5)
+                SELECT i, i * 0.5 AS half, 'lw-' || i AS barcode, NULL AS missing FROM n",
+        )
+        .unwrap();
+        let result = query
+            .run(3, Duration::from_secs(5), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(result.columns, ["i", "half", "barcode", "missing"]);
+        assert_eq!(result.rows.len(), 3);
+        assert_eq!(
+            result.rows[0],
+            [
+                Value::from(1),
+                Value::from(0.5),
+                Value::from("lw-1"),
+                Value::Null
+            ]
+        );
+        assert!(result.truncated);
+
+        let err = ReadOnlyQuery::parse("SELECT * FROM nowhere")
+            .unwrap()
+            .run(3, Duration::from_secs(5), &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "The query failed: no such table: nowhere");
+    }
+
+    #[tokio::test]
+    async fn test_run_is_query_only() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let result = ReadOnlyQuery::parse("SELECT query_only FROM pragma_query_only")
+            .unwrap()
+            .run(3, Duration::from_secs(5), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(result.rows, [[Value::from(1)]]);
+
+        // The connection can write again once the query is done, even if it failed.
+        ReadOnlyQuery::parse("SELECT * FROM nowhere")
+            .unwrap()
+            .run(3, Duration::from_secs(5), &mut conn)
+            .await
+            .unwrap_err();
+        sqlx::query("INSERT INTO location_types (uuid, name) VALUES ('lt-1', 'Freezer')")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_times_out() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let query = ReadOnlyQuery::parse(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n)
+                SELECT count(*) FROM n",
+        )
+        .unwrap();
+        let err = query
+            .run(3, Duration::from_millis(100), &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "The query took longer than 0.1 seconds");
+
+        // The time limit does not outlive the query.
+        let result = ReadOnlyQuery::parse("SELECT 1")
+            .unwrap()
+            .run(3, Duration::from_secs(5), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(result.rows, [[Value::from(1)]]);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::cache::{labware_key, location_key, NOT_FOUND_BARCODES};
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Connection, Row, SqliteConnection, TypeInfo, ValueRef};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// The version of the snapshot format, bumped whenever a snapshot could not be imported as it was.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The tables in a snapshot, in an order where every table comes after the tables it refers to.
+pub const TABLES: [&str; 43] = [
+    "location_types",
+    "locations",
+    "labwares",
+    "labware_positions",
+    "labware_barcodes",
+    "location_barcode_aliases",
+    "print_jobs",
+    "print_job_locations",
+    "audits",
+    "location_locks",
+    "devices",
+    "scans",
+    "checkouts",
+    "location_flags",
+    "stocktakes",
+    "stocktake_items",
+    "stocktake_discrepancies",
+    "subscriptions",
+    "subscription_events",
+    "dead_letters",
+    "audit_sink_cursors",
+    "api_key_usage",
+    "occupancy_snapshots",
+    "snapshots",
+    "capacity_alerts",
+    "transit_alerts",
+    "sync_peers",
+    "sync_conflicts",
+    "pending_labwares",
+    "users",
+    "api_keys",
+    "manifests",
+    "manifest_errors",
+    "upload_mappings",
+    "saved_searches",
+    "shipments",
+    "shipment_labwares",
+    "receipts",
+    "receipt_items",
+    "reservations",
+    "reservation_positions",
+    "misplacements",
+    "events",
+];
+
+/// Tables which are left out of snapshots: `confirmations`, as what they hold is only meaningful
+/// for a few minutes, and `_sqlx_migrations`, as the migrations belong to the database a snapshot
+/// is imported into.
+pub const EXCLUDED_TABLES: [&str; 2] = ["confirmations", "_sqlx_migrations"];
+
+/// Every record in a database, used to clone an environment (e.g. production into staging) or to
+/// rehearse recovering from a disaster.
+///
+/// Rows keep their ids, so the references between them survive the round trip. Snapshots are
+/// stored as gzipped JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The version of the snapshot format
+    pub version: u32,
+    /// When the snapshot was taken
+    pub exported_at: DateTime<Utc>,
+    /// The rows of each table, by the name of the table
+    pub tables: BTreeMap<String, Vec<Map<String, Value>>>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of every table in `TABLES`.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// let mut connection = init_db("sqlite://development.db").await.unwrap();
+    /// let snapshot = Snapshot::export(&mut connection).await.unwrap();
+    /// snapshot.write("snapshot.json.gz").unwrap();
+    /// # }
+    /// ```
+    pub async fn export(connection: &mut SqliteConnection) -> Result<Snapshot, sqlx::Error> {
+        let mut tables = BTreeMap::new();
+        for table in TABLES {
+            let rows = sqlx::query(&format!("SELECT * FROM {} ORDER BY rowid", table))
+                .fetch_all(&mut *connection)
+                .await?;
+            let mut values = vec![];
+            for row in rows {
+                let mut record = Map::new();
+                for (index, column) in row.columns().iter().enumerate() {
+                    record.insert(sqlx::Column::name(column).to_string(), value(&row, index)?);
+                }
+                values.push(record);
+            }
+            tables.insert(table.to_string(), values);
+        }
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            exported_at: Utc::now(),
+            tables,
+        })
+    }
+
+    /// Replaces every record in the database with the ones in the snapshot.
+    ///
+    /// Everything happens in one transaction, so the database is left as it was if the snapshot
+    /// cannot be imported: e.g. it is of another version, has tables or columns this version of
+    /// LabWhere does not know about, or has rows which refer to rows it does not have. The barcodes
+    /// of the imported locations and labwares are removed from the negative lookup cache.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// let mut connection = init_db("sqlite://staging.db").await.unwrap();
+    /// let snapshot = Snapshot::read("snapshot.json.gz").unwrap();
+    /// snapshot.import(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn import(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(Box::new(ValidationError {
+                message: Message::new("snapshot-version")
+                    .arg("version", self.version)
+                    .arg("supported", SNAPSHOT_VERSION),
+            }));
+        }
+        if let Some(table) = self.tables.keys().find(|t| !TABLES.contains(&t.as_str())) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("snapshot-unknown-table").arg("table", table),
+            }));
+        }
+
+        let mut transaction = connection.begin().await?;
+        // Rows are checked against the rows they refer to once they are all in
+        sqlx::query("PRAGMA defer_foreign_keys = ON")
+            .execute(&mut *transaction)
+            .await?;
+        for table in TABLES.iter().rev() {
+            sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut *transaction)
+                .await?;
+        }
+        for table in TABLES {
+            let Some(rows) = self.tables.get(table) else {
+                continue;
+            };
+            let columns: Vec<(String,)> = sqlx::query_as("SELECT name FROM pragma_table_info(?)")
+                .bind(table)
+                .fetch_all(&mut *transaction)
+                .await?;
+            for row in rows {
+                if let Some(column) = row.keys().find(|c| !columns.iter().any(|(k,)| k == *c)) {
+                    return Err(Box::new(ValidationError {
+                        message: Message::new("snapshot-unknown-column")
+                            .arg("table", table)
+                            .arg("column", column),
+                    }));
+                }
+                let names: Vec<&str> = row.keys().map(String::as_str).collect();
+                let sql = format!(
+                    "INSERT INTO {} ({}) VALUES ({})",
+                    table,
+                    names.join(", "),
+                    vec!["?"; names.len()].join(", ")
+                );
+                let mut query = sqlx::query(&sql);
+                for value in row.values() {
+                    query = match value {
+                        Value::Null => query.bind(None::<i64>),
+                        Value::Bool(value) => query.bind(*value),
+                        Value::Number(number) => match number.as_i64() {
+                            Some(value) => query.bind(value),
+                            None => query.bind(number.as_f64()),
+                        },
+                        Value::String(value) => query.bind(value.clone()),
+                        value => query.bind(value.to_string()),
+                    };
+                }
+                query.execute(&mut *transaction).await?;
+            }
+        }
+        let broken = sqlx::query_as::<_, (String, Option<i64>, String)>(
+            "SELECT \"table\", rowid, parent FROM pragma_foreign_key_check",
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        if let Some((table, id, parent)) = broken {
+            return Err(Box::new(ValidationError {
+                message: Message::new("snapshot-broken-reference")
+                    .arg("table", table)
+                    .arg("id", id.unwrap_or_default())
+                    .arg("parent", parent),
+            }));
+        }
+        transaction.commit().await?;
+
+        // Barcodes looked up before the import may be found now
+        for (table, key) in [
+            ("locations", location_key as fn(&str) -> String),
+            ("location_barcode_aliases", location_key),
+            ("labwares", labware_key),
+            ("labware_barcodes", labware_key),
+        ] {
+            let barcodes = self.tables.get(table).into_iter().flatten();
+            for barcode in barcodes.filter_map(|row| row.get("barcode")?.as_str()) {
+                NOT_FOUND_BARCODES.remove(&key(barcode)).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of rows in the snapshot
+    pub fn len(&self) -> usize {
+        self.tables.values().map(Vec::len).sum()
+    }
+
+    /// Whether the snapshot has no rows at all
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the snapshot to a file as gzipped JSON.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut encoder =
+            GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+        serde_json::to_writer(&mut encoder, self)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Reads a snapshot from a file of gzipped JSON.
+    pub fn read(path: impl AsRef<Path>) -> Result<Snapshot, Box<dyn Error + Send + Sync>> {
+        let decoder = GzDecoder::new(BufReader::new(File::open(path)?));
+        Ok(serde_json::from_reader(decoder)?)
+    }
+}
+
+/// Reads a column of a row as JSON, according to how SQLite stored it.
+fn value(row: &sqlx::sqlite::SqliteRow, index: usize) -> Result<Value, sqlx::Error> {
+    let raw = row.try_get_raw(index)?;
+    if raw.is_null() {
+        return Ok(Value::Null);
+    }
+    let value = match raw.type_info().name() {
+        "INTEGER" => Value::from(row.try_get_unchecked::<i64, _>(index)?),
+        "REAL" => Value::from(row.try_get_unchecked::<f64, _>(index)?),
+        _ => Value::from(row.try_get_unchecked::<String, _>(index)?),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::db::snapshot::*;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+
+    async fn create_labware(connection: &mut SqliteConnection) -> Labware {
+        let location_type = LocationType::create("Freezer".to_string(), &mut *connection)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut *connection)
+            .await
+            .unwrap();
+        let shelf = Location::create_with_parent(
+            "shelf1".to_string(),
+            location_type.id,
+            Some(freezer.id),
+            &mut *connection,
+        )
+        .await
+        .unwrap();
+        Labware::create("lw-1".to_string(), shelf.id, &mut *connection)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tables_cover_schema() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let tables: Vec<(String,)> = sqlx::query_as(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        )
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+        for (table,) in tables {
+            assert!(
+                TABLES.contains(&table.as_str()) || EXCLUDED_TABLES.contains(&table.as_str()),
+                "{} is not in snapshots",
+                table
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let labware = create_labware(&mut conn).await;
+        let snapshot = Snapshot::export(&mut conn).await.unwrap();
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.tables["locations"].len(), 2);
+        assert_eq!(snapshot.tables["locations"][1]["parent_id"], 1);
+        assert_eq!(snapshot.tables["labwares"][0]["barcode"], "lw-1");
+
+        let path = std::env::temp_dir().join(format!("{}.json.gz", labware.uuid));
+        snapshot.write(&path).unwrap();
+        let read = Snapshot::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, snapshot);
+
+        // Importing replaces whatever was there
+        let mut other = init_db("sqlite::memory:").await.unwrap();
+        LocationType::create("Bench".to_string(), &mut other)
+            .await
+            .unwrap();
+        // Barcodes not found before the import are found after it
+        Labware::find_by_barcode("lw-1".to_string(), &mut other)
+            .await
+            .unwrap_err();
+        read.import(&mut other).await.unwrap();
+        let imported = Labware::find_by_barcode("lw-1".to_string(), &mut other)
+            .await
+            .unwrap();
+        assert_eq!(imported, labware);
+        let location = Location::find(imported.location_id, &mut other)
+            .await
+            .unwrap();
+        assert_eq!(location.name, "shelf1");
+        assert_eq!(location.labwares_count, 1);
+        assert_eq!(
+            Snapshot::export(&mut other).await.unwrap().len(),
+            snapshot.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_with_broken_reference() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        create_labware(&mut conn).await;
+        let mut snapshot = Snapshot::export(&mut conn).await.unwrap();
+        snapshot.tables.get_mut("locations").unwrap().remove(1);
+
+        let mut other = init_db("sqlite::memory:").await.unwrap();
+        LocationType::create("Bench".to_string(), &mut other)
+            .await
+            .unwrap();
+        let error = snapshot.import(&mut other).await.unwrap_err();
+        assert!(error.is::<ValidationError>());
+        assert!(error
+            .to_string()
+            .ends_with("refers to a missing row of locations"));
+
+        // Nothing was imported
+        let names: Vec<(String,)> = sqlx::query_as("SELECT name FROM location_types")
+            .fetch_all(&mut other)
+            .await
+            .unwrap();
+        assert_eq!(names, vec![("Bench".to_string(),)]);
+    }
+
+    #[tokio::test]
+    async fn test_import_with_unknown_column() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        create_labware(&mut conn).await;
+        let mut snapshot = Snapshot::export(&mut conn).await.unwrap();
+        snapshot.tables.get_mut("labwares").unwrap()[0]
+            .insert("colour".to_string(), Value::from("red"));
+
+        let error = snapshot.import(&mut conn).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The snapshot has an unknown column colour in labwares"
+        );
+    }
+}
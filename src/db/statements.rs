@@ -0,0 +1,32 @@
+//! The SQL of the lookups made on every scan and move.
+//!
+//! sqlx prepares each distinct SQL text once per connection and keeps the prepared statement in the
+//! connection's statement cache (sized with `LABWHERE_STATEMENT_CACHE_CAPACITY`), so a lookup made
+//! in several places is written once here: copies which differ by as little as their whitespace
+//! would be prepared, and take a place in the cache, each. `db analyze` checks the query plans of
+//! the same text.
+
+/// A labware by its barcode or one of its aliases, regardless of case.
+pub const LABWARE_BY_BARCODE: &str = "SELECT * FROM labwares WHERE status = 'active'
+    AND (barcode = ?1 COLLATE NOCASE
+        OR id IN (SELECT labware_id FROM labware_barcodes WHERE barcode = ?1 COLLATE NOCASE))";
+
+/// The location a labware is in, by the labware's id.
+pub const LABWARE_LOCATION_ID: &str = "SELECT location_id FROM labwares WHERE id = ?";
+
+/// A location by its barcode, regardless of case.
+pub const LOCATION_BY_BARCODE: &str = "SELECT * FROM locations WHERE barcode = ? COLLATE NOCASE";
+
+/// A location by a barcode it had before its barcode was regenerated, regardless of case.
+pub const LOCATION_BY_ALIAS: &str = "SELECT l.* FROM locations l
+    JOIN location_barcode_aliases a ON a.location_id = l.id
+    WHERE a.barcode = ? COLLATE NOCASE";
+
+/// A location by its id.
+pub const LOCATION_BY_ID: &str = "SELECT * FROM locations WHERE id = ?";
+
+/// The labware at a position of a coordinated location, by the location's id, the row and the
+/// column.
+pub const LABWARE_AT_POSITION: &str = "SELECT l.id, l.barcode FROM labware_positions p
+    JOIN labwares l ON l.id = p.labware_id
+    WHERE p.location_id = ? AND p.row_index = ? AND p.column_index = ?";
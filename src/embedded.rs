@@ -0,0 +1,218 @@
+//! LabWhere as a library, for services which keep track of labware themselves rather than calling
+//! a LabWhere server.
+//!
+//! `Labwhere` offers the operations of the server's endpoints as methods on a pool of connections
+//! to a LabWhere database, with the same rules: scans respect locks, reservations and registration
+//! policies, and every change is audited and recorded in the event log. Errors are the domain
+//! errors of `errors` (e.g. a `NotFoundError` for an unknown barcode), boxed, so they can be told
+//! apart with `downcast_ref`.
+//!
+//! Background tasks of the server (e.g. occupancy snapshots or search indexing) are not run;
+//! embedding services can spawn them from `models` and `search` if they need them.
+use crate::config::CONFIG;
+use crate::db::init_pool;
+use crate::metrics::acquire;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::location::Location;
+use crate::models::location_tree::LocationTree;
+use crate::models::occupancy::OccupancyStats;
+use crate::models::scan::{Scan, ScanOperation};
+use crate::models::stocktake::Stocktake;
+use crate::models::stocktake_report::StocktakeReport;
+use crate::search::{self, SearchResults};
+use crate::storage::{LabwareStore, LocationStore, ScanStore, SqliteStorage};
+use sqlx::SqlitePool;
+use std::error::Error;
+
+/// The inventory of a LabWhere database, without the HTTP server.
+///
+/// Cloning is cheap, as clones share the pool.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::Labwhere;
+/// let labwhere = Labwhere::connect("sqlite://labwhere.db").await.unwrap();
+/// let scan = labwhere.scan("lw-freezer-1", vec!["lw-1".to_string()], None).await.unwrap();
+/// let results = labwhere.search("freezer", 20).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Labwhere {
+    pool: SqlitePool,
+}
+
+impl Labwhere {
+    /// Create a new Labwhere on a pool whose database has been initialised, e.g. with `init_pool`
+    pub fn new(pool: SqlitePool) -> Labwhere {
+        Labwhere { pool }
+    }
+
+    /// Opens the database at a URL, creating and migrating it if needed
+    pub async fn connect(url: &str) -> Result<Labwhere, sqlx::Error> {
+        Ok(Labwhere::new(init_pool(url).await?))
+    }
+
+    /// The pool of the database, for the operations which have no method
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Finds an active labware by any of its barcodes, like `GET /labwares/{barcode}`
+    pub async fn labware(&self, barcode: &str) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        SqliteStorage::new(&mut connection)
+            .find_labware_by_barcode(barcode)
+            .await
+    }
+
+    /// Finds a location by its barcode, like `GET /locations/{barcode}`
+    pub async fn location(&self, barcode: &str) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        SqliteStorage::new(&mut connection)
+            .find_location_by_barcode(barcode)
+            .await
+    }
+
+    /// Scans labwares into a location, like `POST /scan`. If the location (or the current
+    /// location of a labware) is locked, the lock's token has to be given.
+    pub async fn scan(
+        &self,
+        location_barcode: &str,
+        labware_barcodes: Vec<String>,
+        lock_token: Option<&str>,
+    ) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        SqliteStorage::new(&mut connection)
+            .scan(location_barcode, labware_barcodes, lock_token, None)
+            .await
+    }
+
+    /// Performs a batch of scans, like `POST /scan/batch`, returning the result of each in order
+    pub async fn scan_batch(
+        &self,
+        operations: Vec<ScanOperation>,
+        lock_token: Option<&str>,
+    ) -> Result<Vec<Result<Scan, Box<dyn Error + Send + Sync>>>, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        Ok(Scan::create_batch(operations, lock_token, None, &mut connection).await?)
+    }
+
+    /// Moves a location inside of another location, or to the top level if no parent is given,
+    /// like `POST /locations/{barcode}/move`
+    pub async fn move_location(
+        &self,
+        barcode: &str,
+        parent_barcode: Option<&str>,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        let location = Location::find_by_barcode(barcode.to_string(), &mut connection).await?;
+        let parent_id = match parent_barcode {
+            Some(parent) => Some(
+                Location::find_by_barcode(parent.to_string(), &mut connection)
+                    .await?
+                    .id,
+            ),
+            None => None,
+        };
+        location.reparent(parent_id, &mut connection).await
+    }
+
+    /// Searches the locations and active labwares whose name or barcode match a query, like
+    /// `GET /search`
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+    ) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        search::search(
+            &CONFIG,
+            query,
+            limit,
+            Some(LabwareStatus::Active),
+            &mut connection,
+        )
+        .await
+    }
+
+    /// How full the storage is, with the trend over the last days, like `GET /stats/occupancy`
+    pub async fn occupancy(
+        &self,
+        days: u32,
+    ) -> Result<OccupancyStats, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        Ok(OccupancyStats::build(days, &mut connection).await?)
+    }
+
+    /// The locations beneath a location and how full each is, as printed by
+    /// `GET /locations/{barcode}/tree.pdf`
+    pub async fn location_tree(
+        &self,
+        barcode: &str,
+    ) -> Result<LocationTree, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        let location = Location::find_by_barcode(barcode.to_string(), &mut connection).await?;
+        Ok(LocationTree::build(&location, &mut connection).await?)
+    }
+
+    /// The discrepancies a completed stocktake found, like `GET /stocktakes/{uuid}/report`
+    pub async fn stocktake_report(
+        &self,
+        uuid: &str,
+    ) -> Result<StocktakeReport, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(&self.pool).await?;
+        let stocktake = Stocktake::find_by_uuid(uuid, &mut connection).await?;
+        StocktakeReport::build(&stocktake, &mut connection).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::embedded::*;
+    use crate::errors::NotFoundError;
+    use crate::models::location_type::LocationType;
+
+    #[tokio::test]
+    async fn test_labwhere() {
+        let labwhere = Labwhere::connect("sqlite::memory:").await.unwrap();
+        let mut conn = labwhere.pool().acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create("shelf1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        let freezer_barcode = freezer.barcode.unwrap();
+        let shelf_barcode = shelf.barcode.unwrap();
+
+        let scan = labwhere
+            .scan(&shelf_barcode, vec!["lw-1".to_string()], None)
+            .await
+            .unwrap();
+        assert_eq!(scan.message, "1 labwares scanned into shelf1");
+        assert_eq!(
+            labwhere.labware("lw-1").await.unwrap().location_id,
+            shelf.id
+        );
+
+        let moved = labwhere
+            .move_location(&shelf_barcode, Some(&freezer_barcode))
+            .await
+            .unwrap();
+        assert_eq!(moved.parent_id, Some(freezer.id));
+        let tree = labwhere.location_tree(&freezer_barcode).await.unwrap();
+        assert_eq!(tree.nodes.len(), 2);
+
+        let results = labwhere.search("shelf", 20).await.unwrap();
+        assert_eq!(results.locations.len(), 1);
+        let stats = labwhere.occupancy(7).await.unwrap();
+        assert_eq!(stats.location_types[0].labwares, 1);
+
+        let error = labwhere.labware("lw-404").await.unwrap_err();
+        assert!(error.downcast_ref::<NotFoundError>().is_some());
+    }
+}
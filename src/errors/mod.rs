@@ -1,20 +1,275 @@
+use crate::i18n::{Message, DEFAULT_LOCALE};
+use fluent_templates::LanguageIdentifier;
+use labwhere_core::Problem;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
+use validator::ValidationErrors;
 
 pub struct NotFoundError {
-    pub message: String,
+    pub message: Message,
 }
 
 impl Display for NotFoundError {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.message.to_string())
+        write!(f, "{}", self.message)
     }
 }
 
 impl Debug for NotFoundError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message.to_string())
+        write!(f, "{}", self.message)
     }
 }
 
 impl Error for NotFoundError {}
+
+/// Error for barcodes which are malformed, e.g. a barcode whose check digit does not match.
+pub struct InvalidBarcodeError {
+    pub message: Message,
+}
+
+impl Display for InvalidBarcodeError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Debug for InvalidBarcodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for InvalidBarcodeError {}
+
+/// Converts a barcode rejected by the core, e.g. by `labwhere_core::parser::BarcodeParser`
+impl From<Problem> for InvalidBarcodeError {
+    fn from(problem: Problem) -> InvalidBarcodeError {
+        InvalidBarcodeError {
+            message: problem.into(),
+        }
+    }
+}
+
+/// Error for requests which are well-formed but break a rule of the domain, e.g. registering a
+/// barcode which is already in use.
+pub struct ValidationError {
+    pub message: Message,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Debug for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Error for changes to a location which is locked by somebody else.
+pub struct LockedError {
+    pub message: Message,
+}
+
+impl Display for LockedError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Debug for LockedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for LockedError {}
+
+/// Error for changes which only an admin is allowed to make, e.g. clearing a quarantine.
+pub struct ForbiddenError {
+    pub message: Message,
+}
+
+impl Display for ForbiddenError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Debug for ForbiddenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ForbiddenError {}
+
+/// The message of an error which breaks a rule of the domain, e.g. a `LockedError`, or `None` for
+/// an internal error such as a database error.
+pub fn domain_message<'a>(err: &'a (dyn Error + Send + Sync + 'static)) -> Option<&'a Message> {
+    if let Some(err) = err.downcast_ref::<NotFoundError>() {
+        Some(&err.message)
+    } else if let Some(err) = err.downcast_ref::<ValidationError>() {
+        Some(&err.message)
+    } else if let Some(err) = err.downcast_ref::<InvalidBarcodeError>() {
+        Some(&err.message)
+    } else if let Some(err) = err.downcast_ref::<ForbiddenError>() {
+        Some(&err.message)
+    } else if let Some(err) = err.downcast_ref::<LockedError>() {
+        Some(&err.message)
+    } else {
+        None
+    }
+}
+
+/// Error for values which fail validation, holding the messages for each invalid field.
+///
+/// Displays the full message of every field, e.g. `Name must be between 1 and 60 characters`.
+pub struct FieldValidationError {
+    pub fields: BTreeMap<String, Vec<Message>>,
+}
+
+impl FieldValidationError {
+    /// An error for a single field, e.g. a required field which is missing.
+    pub fn field(field: &str, message: Message) -> FieldValidationError {
+        FieldValidationError {
+            fields: BTreeMap::from([(field.to_string(), vec![message])]),
+        }
+    }
+
+    /// The full message for each invalid field in the given locale, e.g. `Name is required`.
+    pub fn messages(&self, locale: &LanguageIdentifier) -> Vec<String> {
+        self.fields
+            .iter()
+            .flat_map(|(field, messages)| {
+                let name = field_name(field, locale);
+                messages
+                    .iter()
+                    .map(move |message| format!("{} {}", name, message.localize(locale)))
+            })
+            .collect()
+    }
+
+    /// The messages of each invalid field in the given locale, e.g. `{"name": ["is required"]}`.
+    pub fn localize_fields(&self, locale: &LanguageIdentifier) -> BTreeMap<String, Vec<String>> {
+        self.fields
+            .iter()
+            .map(|(field, messages)| {
+                let messages = messages.iter().map(|m| m.localize(locale)).collect();
+                (field.clone(), messages)
+            })
+            .collect()
+    }
+}
+
+/// The name of a field at the start of a sentence, e.g. `location_barcode` becomes
+/// `Location barcode`. Fields without a `field-` message in the catalogs are humanized.
+fn field_name(field: &str, locale: &LanguageIdentifier) -> String {
+    let key = format!("field-{}", field.replace('_', "-"));
+    if let Some(name) = Message::new(key).try_localize(locale) {
+        return name;
+    }
+    let field = field.replace('_', " ");
+    let mut chars = field.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => field,
+    }
+}
+
+/// Converts the errors of a `validator` derive. The `message` of each validation is the key of
+/// its message in the catalogs, and the parameters of the validation (e.g. `min` and `max`) are
+/// its arguments.
+impl From<ValidationErrors> for FieldValidationError {
+    fn from(errors: ValidationErrors) -> FieldValidationError {
+        let fields = errors
+            .field_errors()
+            .into_iter()
+            .map(|(field, errors)| {
+                let messages = errors
+                    .iter()
+                    .map(|error| {
+                        let key = error.message.clone().unwrap_or("validation-invalid".into());
+                        error
+                            .params
+                            .iter()
+                            .filter(|(name, _)| *name != "value")
+                            .fold(Message::new(key), |message, (name, value)| match value {
+                                serde_json::Value::String(value) => {
+                                    message.arg(name.clone(), value)
+                                }
+                                value => message.arg(name.clone(), value),
+                            })
+                    })
+                    .collect();
+                (field.to_string(), messages)
+            })
+            .collect();
+        FieldValidationError { fields }
+    }
+}
+
+impl Display for FieldValidationError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.messages(&DEFAULT_LOCALE).join(", "))
+    }
+}
+
+impl Debug for FieldValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Error for FieldValidationError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::FieldValidationError;
+    use crate::i18n::Message;
+    use fluent_templates::langid;
+    use validator::Validate;
+
+    #[derive(Validate)]
+    struct Payload {
+        #[validate(length(min = 1, message = "validation-blank"))]
+        location_barcode: String,
+        #[validate(range(min = 1, max = 10, message = "validation-range"))]
+        count: u32,
+    }
+
+    #[test]
+    fn test_field_validation_error_from_validation_errors() {
+        let payload = Payload {
+            location_barcode: "".to_string(),
+            count: 11,
+        };
+        let error = FieldValidationError::from(payload.validate().unwrap_err());
+        assert_eq!(error.fields["location_barcode"][0], "can't be blank");
+        assert_eq!(error.fields["count"][0], "must be between 1 and 10");
+        assert_eq!(
+            error.to_string(),
+            "Count must be between 1 and 10, Location barcode can't be blank"
+        );
+        assert_eq!(
+            error.messages(&langid!("es"))[1],
+            "Código de barras de la ubicación no puede estar en blanco"
+        );
+    }
+
+    #[test]
+    fn test_field_validation_error_for_field() {
+        let error = FieldValidationError::field("name", Message::new("validation-required"));
+        assert_eq!(error.to_string(), "Name is required");
+        assert_eq!(
+            error.localize_fields(&langid!("es"))["name"],
+            vec!["es obligatorio"]
+        );
+    }
+}
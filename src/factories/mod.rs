@@ -0,0 +1,322 @@
+//! Factories which create locations and labwares with sensible defaults.
+//!
+//! Only what matters to the caller has to be given; names and barcodes are made unique from a
+//! sequence, and location types are found by name or created. Used by the test suites, the
+//! `labwhere seed` command and by integration tests written against the library.
+//!
+//! # Examples
+//! ```
+//! # #[cfg(doctest)] {
+//! use labwhere::factories::{LabwareFactory, LocationFactory};
+//! let freezer = LocationFactory::new()
+//!     .with_type("Freezer")
+//!     .with_children(3)
+//!     .create(&pool)
+//!     .await
+//!     .unwrap();
+//! let labware = LabwareFactory::new().with_location(&freezer).create(&pool).await.unwrap();
+//! # }
+//! ```
+
+use crate::metrics::acquire;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::location_type::LocationType;
+use crate::{LabwareBuilder, LocationBuilder};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::error::Error;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Numbers the names and barcodes made up by the factories, so they are unique.
+static SEQUENCE: AtomicU32 = AtomicU32::new(1);
+
+fn next() -> u32 {
+    SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Finds the location type with the given name, creating it if there is none.
+async fn location_type(
+    name: &str,
+    connection: &mut SqliteConnection,
+) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    let id = sqlx::query_scalar::<_, u32>("SELECT id FROM location_types WHERE name = ? LIMIT 1")
+        .bind(name)
+        .fetch_optional(&mut *connection)
+        .await?;
+    match id {
+        Some(id) => Ok(id),
+        None => Ok(LocationType::create(name.to_string(), connection).await?.id),
+    }
+}
+
+/// Creates a location, and optionally the locations and labwares inside of it.
+#[derive(Debug, Clone)]
+pub struct LocationFactory {
+    name: Option<String>,
+    location_type: String,
+    parent_id: Option<u32>,
+    children: u32,
+    labwares: u32,
+}
+
+impl Default for LocationFactory {
+    fn default() -> LocationFactory {
+        LocationFactory {
+            name: None,
+            location_type: "Building".to_string(),
+            parent_id: None,
+            children: 0,
+            labwares: 0,
+        }
+    }
+}
+
+impl LocationFactory {
+    pub fn new() -> LocationFactory {
+        Default::default()
+    }
+
+    /// The name of the location. Defaults to `location-{n}`.
+    pub fn with_name(mut self, name: impl Into<String>) -> LocationFactory {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// The name of the location type, created if there is none. Defaults to `Building`.
+    pub fn with_type(mut self, location_type: impl Into<String>) -> LocationFactory {
+        self.location_type = location_type.into();
+        self
+    }
+
+    /// The location this location is inside of.
+    pub fn with_parent(mut self, parent: &Location) -> LocationFactory {
+        self.parent_id = Some(parent.id);
+        self
+    }
+
+    /// The number of locations of the same type to create inside of the location, named after it
+    /// with `-1`, `-2` and so on.
+    pub fn with_children(mut self, children: u32) -> LocationFactory {
+        self.children = children;
+        self
+    }
+
+    /// The number of labwares to create in the location.
+    pub fn with_labwares(mut self, labwares: u32) -> LocationFactory {
+        self.labwares = labwares;
+        self
+    }
+
+    /// Creates the location, its children and its labwares with a connection from the pool.
+    pub async fn create(self, pool: &SqlitePool) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(pool).await?;
+        self.create_with_connection(&mut connection).await
+    }
+
+    /// Creates the location, its children and its labwares.
+    ///
+    /// Responds with the location as saved, so its `labwares_count` includes the labwares.
+    pub async fn create_with_connection(
+        self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let location_type_id = location_type(&self.location_type, connection).await?;
+        let name = self.name.unwrap_or_else(|| format!("location-{}", next()));
+        let mut builder = LocationBuilder::new()
+            .name(&name)
+            .location_type_id(location_type_id);
+        if let Some(parent_id) = self.parent_id {
+            builder = builder.parent_id(parent_id);
+        }
+        let location = builder.create(connection).await?;
+        for child in 1..=self.children {
+            LocationBuilder::new()
+                .name(format!("{}-{}", name, child))
+                .location_type_id(location_type_id)
+                .parent_id(location.id)
+                .create(connection)
+                .await?;
+        }
+        for _ in 0..self.labwares {
+            LabwareBuilder::new()
+                .barcode(format!("labware-{}", next()))
+                .location_id(location.id)
+                .create(connection)
+                .await?;
+        }
+        Ok(Location::find(location.id, connection).await?)
+    }
+}
+
+/// Creates a labware.
+#[derive(Debug, Default, Clone)]
+pub struct LabwareFactory {
+    barcode: Option<String>,
+    location_id: Option<u32>,
+}
+
+impl LabwareFactory {
+    pub fn new() -> LabwareFactory {
+        Default::default()
+    }
+
+    /// The barcode of the labware. Defaults to `labware-{n}`.
+    pub fn with_barcode(mut self, barcode: impl Into<String>) -> LabwareFactory {
+        self.barcode = Some(barcode.into());
+        self
+    }
+
+    /// The location of the labware. Defaults to a new location made by `LocationFactory`.
+    pub fn with_location(mut self, location: &Location) -> LabwareFactory {
+        self.location_id = Some(location.id);
+        self
+    }
+
+    /// Creates the labware with a connection from the pool.
+    pub async fn create(self, pool: &SqlitePool) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let mut connection = acquire(pool).await?;
+        self.create_with_connection(&mut connection).await
+    }
+
+    /// Creates the labware.
+    pub async fn create_with_connection(
+        self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let barcode = self
+            .barcode
+            .unwrap_or_else(|| format!("labware-{}", next()));
+        let location_id = match self.location_id {
+            Some(location_id) => location_id,
+            None => {
+                LocationFactory::new()
+                    .create_with_connection(connection)
+                    .await?
+                    .id
+            }
+        };
+        LabwareBuilder::new()
+            .barcode(barcode)
+            .location_id(location_id)
+            .create(connection)
+            .await
+    }
+}
+
+/// Fills a database with a building of two rooms, each with two freezers of four shelves holding
+/// five labwares each, to try the server out with.
+pub async fn seed(pool: &SqlitePool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let building = LocationFactory::new()
+        .with_name("Building 1")
+        .with_type("Building")
+        .create(pool)
+        .await?;
+    for room in 1..=2 {
+        let room = LocationFactory::new()
+            .with_name(format!("Room {}", room))
+            .with_type("Room")
+            .with_parent(&building)
+            .create(pool)
+            .await?;
+        for freezer in 1..=2 {
+            let freezer = LocationFactory::new()
+                .with_name(format!("{} Freezer {}", room.name, freezer))
+                .with_type("Freezer")
+                .with_parent(&room)
+                .create(pool)
+                .await?;
+            for shelf in 1..=4 {
+                LocationFactory::new()
+                    .with_name(format!("{} Shelf {}", freezer.name, shelf))
+                    .with_type("Shelf")
+                    .with_parent(&freezer)
+                    .with_labwares(5)
+                    .create(pool)
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_pool;
+    use crate::factories::*;
+
+    #[tokio::test]
+    async fn test_location_factory() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let freezer = LocationFactory::new()
+            .with_type("Freezer")
+            .with_children(3)
+            .with_labwares(2)
+            .create(&pool)
+            .await
+            .unwrap();
+        assert!(freezer.name.starts_with("location-"));
+        assert_eq!(freezer.labwares_count, 2);
+
+        let shelf = LocationFactory::new()
+            .with_name("shelf")
+            .with_type("Freezer")
+            .with_parent(&freezer)
+            .create(&pool)
+            .await
+            .unwrap();
+        assert_eq!(shelf.parent_id, Some(freezer.id));
+
+        let mut connection = pool.acquire().await.unwrap();
+        let children: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM locations WHERE parent_id = ? ORDER BY id")
+                .bind(freezer.id)
+                .fetch_all(&mut *connection)
+                .await
+                .unwrap();
+        assert_eq!(
+            children,
+            vec![
+                format!("{}-1", freezer.name),
+                format!("{}-2", freezer.name),
+                format!("{}-3", freezer.name),
+                "shelf".to_string()
+            ]
+        );
+        let types: u32 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM location_types WHERE name = 'Freezer'")
+                .fetch_one(&mut *connection)
+                .await
+                .unwrap();
+        assert_eq!(types, 1);
+    }
+
+    #[tokio::test]
+    async fn test_labware_factory() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let location = LocationFactory::new().create(&pool).await.unwrap();
+        let labware = LabwareFactory::new()
+            .with_barcode("lw-1")
+            .with_location(&location)
+            .create(&pool)
+            .await
+            .unwrap();
+        assert_eq!(labware.barcode, "lw-1");
+        assert_eq!(labware.location_id, location.id);
+
+        let labware = LabwareFactory::new().create(&pool).await.unwrap();
+        assert!(labware.barcode.starts_with("labware-"));
+        assert_ne!(labware.location_id, location.id);
+    }
+
+    #[tokio::test]
+    async fn test_seed() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        seed(&pool).await.unwrap();
+        let mut connection = pool.acquire().await.unwrap();
+        let labwares: u32 = sqlx::query_scalar("SELECT COUNT(*) FROM labwares")
+            .fetch_one(&mut *connection)
+            .await
+            .unwrap();
+        assert_eq!(labwares, 2 * 2 * 4 * 5);
+    }
+}
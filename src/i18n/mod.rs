@@ -0,0 +1,178 @@
+//! Translations of the messages LabWhere responds with.
+//!
+//! Messages are kept in [Fluent](https://projectfluent.org) catalogs under `locales/{language}`,
+//! which are compiled into the binary. Errors and responses carry a `Message` (a catalog key and
+//! its arguments) rather than a finished string, so they can be rendered in the locale the client
+//! asked for through the `Accept-Language` header.
+use fluent_templates::fluent_bundle::FluentValue;
+use fluent_templates::{langid, LanguageIdentifier, Loader};
+use labwhere_core::Problem;
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+fluent_templates::static_loader! {
+    static LOCALES = {
+        locales: "./locales",
+        fallback_language: "en",
+        // Placeables are not wrapped in unicode isolation marks, which would end up in the JSON.
+        customise: |bundle| bundle.set_use_isolating(false),
+    };
+}
+
+/// The locale used when the client does not ask for one LabWhere has a catalog for.
+pub static DEFAULT_LOCALE: Lazy<LanguageIdentifier> = Lazy::new(|| langid!("en"));
+
+/// A translatable message: the key of a message in the catalogs and the arguments it is
+/// rendered with.
+///
+/// Displaying a message renders it in the default locale.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::i18n::Message;
+/// let message = Message::new("barcode-in-use").arg("barcode", "lw-1");
+/// assert_eq!(message.to_string(), "Barcode lw-1 is already in use");
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// The key of the message in the catalogs, e.g. `location-not-found`
+    pub key: Cow<'static, str>,
+    /// The named arguments of the message
+    pub args: Vec<(Cow<'static, str>, String)>,
+}
+
+impl Message {
+    pub fn new(key: impl Into<Cow<'static, str>>) -> Message {
+        Message {
+            key: key.into(),
+            args: vec![],
+        }
+    }
+
+    /// Adds a named argument to the message
+    pub fn arg(mut self, name: impl Into<Cow<'static, str>>, value: impl ToString) -> Message {
+        self.args.push((name.into(), value.to_string()));
+        self
+    }
+
+    /// Renders the message in the given locale, falling back to the default locale for messages
+    /// which have not been translated. Unknown keys are rendered as the key itself.
+    pub fn localize(&self, locale: &LanguageIdentifier) -> String {
+        self.try_localize(locale)
+            .unwrap_or_else(|| self.key.to_string())
+    }
+
+    /// Renders the message in the given locale, or `None` if no catalog has the key.
+    pub fn try_localize(&self, locale: &LanguageIdentifier) -> Option<String> {
+        let args: HashMap<Cow<'static, str>, FluentValue> = self
+            .args
+            .iter()
+            .map(|(name, value)| (name.clone(), FluentValue::from(value.clone())))
+            .collect();
+        LOCALES.try_lookup_with_args(locale, &self.key, &args)
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.localize(&DEFAULT_LOCALE))
+    }
+}
+
+/// The message of a problem found by the core, whose key and arguments are those of the catalogs
+impl From<Problem> for Message {
+    fn from(problem: Problem) -> Message {
+        problem
+            .args
+            .into_iter()
+            .fold(Message::new(problem.key), |message, (name, value)| {
+                message.arg(name, value)
+            })
+    }
+}
+
+impl PartialEq<&str> for Message {
+    fn eq(&self, other: &&str) -> bool {
+        self.localize(&DEFAULT_LOCALE) == *other
+    }
+}
+
+/// Picks the locale to respond in from an `Accept-Language` header.
+///
+/// Languages are tried in order of their quality value, e.g. `fr;q=0.5, es-MX, en;q=0.8` tries
+/// Mexican Spanish, then English, then French. A language matches a catalog for the same language
+/// regardless of region. The default locale is used if no language matches.
+pub fn negotiate(accept_language: Option<&str>) -> LanguageIdentifier {
+    let mut requested: Vec<(LanguageIdentifier, f32)> = accept_language
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let locale = parts.next()?.trim().parse::<LanguageIdentifier>().ok()?;
+            let quality = parts
+                .find_map(|part| part.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            Some((locale, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    // A stable sort keeps the order of languages with the same quality.
+    requested.sort_by(|a, b| b.1.total_cmp(&a.1));
+    requested
+        .into_iter()
+        .find(|(locale, _)| {
+            LOCALES
+                .locales()
+                .any(|available| available.language == locale.language)
+        })
+        .map(|(locale, _)| locale)
+        .unwrap_or_else(|| DEFAULT_LOCALE.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i18n::*;
+
+    #[test]
+    fn test_localize() {
+        let message = Message::new("barcode-in-use").arg("barcode", "lw-1");
+        assert_eq!(
+            message.localize(&langid!("en")),
+            "Barcode lw-1 is already in use"
+        );
+        assert_eq!(
+            message.localize(&langid!("es")),
+            "El código de barras lw-1 ya está en uso"
+        );
+        assert_eq!(
+            message.localize(&langid!("es-MX")),
+            "El código de barras lw-1 ya está en uso"
+        );
+        assert_eq!(message.to_string(), "Barcode lw-1 is already in use");
+    }
+
+    #[test]
+    fn test_localize_falls_back() {
+        let message = Message::new("location-not-found");
+        assert_eq!(message.localize(&langid!("de")), "Location not found");
+
+        let message = Message::new("no-such-message");
+        assert_eq!(message.localize(&langid!("en")), "no-such-message");
+        assert_eq!(message.try_localize(&langid!("en")), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(None), langid!("en"));
+        assert_eq!(negotiate(Some("")), langid!("en"));
+        assert_eq!(negotiate(Some("es")), langid!("es"));
+        assert_eq!(negotiate(Some("es-MX,es;q=0.9")), langid!("es-MX"));
+        assert_eq!(negotiate(Some("fr;q=0.9, es;q=0.5")), langid!("es"));
+        assert_eq!(negotiate(Some("es;q=0.5, en;q=0.8")), langid!("en"));
+        assert_eq!(negotiate(Some("es;q=0, de")), langid!("en"));
+        assert_eq!(negotiate(Some("not a locale")), langid!("en"));
+    }
+}
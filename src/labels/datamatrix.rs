@@ -0,0 +1,125 @@
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use datamatrix::{DataMatrix, SymbolList};
+
+/// The share of the cap diameter taken up by the symbol, leaving room for the text underneath
+/// inside the round cap.
+const SYMBOL_SHARE: f64 = 0.55;
+
+/// The font size of the human-readable text, as a share of the cap diameter.
+const TEXT_SHARE: f64 = 0.11;
+
+/// The width of the quiet zone around the symbol, in modules.
+const QUIET_ZONE: usize = 1;
+
+/// Size presets for the caps of common tubes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CapSize {
+    /// 6 mm caps e.g. 0.5 ml 2D-coded storage tubes
+    Micro,
+    /// 10 mm caps e.g. 1 ml cryovials
+    Standard,
+    /// 13 mm caps e.g. 2 ml and 5 ml cryovials
+    Large,
+}
+
+impl CapSize {
+    /// The diameter of the cap, in millimetres.
+    pub fn diameter_mm(self) -> u32 {
+        match self {
+            CapSize::Micro => 6,
+            CapSize::Standard => 10,
+            CapSize::Large => 13,
+        }
+    }
+
+    /// Parses a preset from its diameter e.g. `10mm`.
+    pub fn from_name(name: &str) -> Option<CapSize> {
+        [CapSize::Micro, CapSize::Standard, CapSize::Large]
+            .into_iter()
+            .find(|size| format!("{}mm", size.diameter_mm()) == name.trim().to_lowercase())
+    }
+}
+
+/// Renders a tube-cap label: a square DataMatrix of the data with the data as human-readable text
+/// underneath, sized in millimetres for the cap.
+///
+/// Returns a `ValidationError` if the data is too long to fit in a DataMatrix.
+pub fn render_svg(data: &str, size: CapSize) -> Result<String, ValidationError> {
+    let code =
+        DataMatrix::encode_str(data, SymbolList::default().enforce_square()).map_err(|_| {
+            ValidationError {
+                message: Message::new("label-content-too-long").arg("content", data),
+            }
+        })?;
+    let bitmap = code.bitmap();
+
+    let diameter = f64::from(size.diameter_mm());
+    let symbol = diameter * SYMBOL_SHARE;
+    let module = symbol / (bitmap.width() + 2 * QUIET_ZONE) as f64;
+    let left = (diameter - symbol) / 2.0 + module * QUIET_ZONE as f64;
+    let top = diameter * 0.1 + module * QUIET_ZONE as f64;
+    let path: String = bitmap
+        .pixels()
+        .map(|(x, y)| format!("M{} {}h1v1h-1z", x, y))
+        .collect();
+    let font_size = diameter * TEXT_SHARE;
+
+    Ok(format!(
+        concat!(
+            r#"<?xml version="1.0" standalone="yes"?>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{d}mm" height="{d}mm" viewBox="0 0 {d} {d}">"#,
+            r#"<g transform="translate({left:.3} {top:.3}) scale({module:.4})" shape-rendering="crispEdges"><path d="{path}"/></g>"#,
+            r#"<text x="{center}" y="{baseline:.3}" font-family="monospace" font-size="{font_size:.3}" text-anchor="middle" textLength="{text_length:.3}" lengthAdjust="spacingAndGlyphs">{text}</text>"#,
+            "</svg>"
+        ),
+        d = size.diameter_mm(),
+        left = left,
+        top = top,
+        module = module,
+        path = path,
+        center = diameter / 2.0,
+        baseline = top + symbol + font_size,
+        font_size = font_size,
+        // The text is squeezed to fit the width of the cap under the symbol
+        text_length = (diameter * 0.7).min(font_size * 0.6 * data.chars().count() as f64),
+        text = escape(data),
+    ))
+}
+
+/// Escapes text for use in SVG.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::labels::datamatrix::*;
+
+    #[test]
+    fn test_cap_size_from_name() {
+        assert_eq!(CapSize::from_name("10mm"), Some(CapSize::Standard));
+        assert_eq!(CapSize::from_name("13MM"), Some(CapSize::Large));
+        assert_eq!(CapSize::from_name("11mm"), None);
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let svg = render_svg("lw-tube-1", CapSize::Large).unwrap();
+        assert!(svg.contains(r#"width="13mm" height="13mm""#));
+        assert!(svg.contains("<path"));
+        assert!(svg.contains(">lw-tube-1</text>"));
+
+        let svg = render_svg("a<b", CapSize::Micro).unwrap();
+        assert!(svg.contains(r#"width="6mm""#));
+        assert!(svg.contains(">a&lt;b</text>"));
+    }
+
+    #[test]
+    fn test_render_svg_with_too_much_data() {
+        assert!(render_svg(&"x".repeat(5000), CapSize::Large).is_err());
+    }
+}
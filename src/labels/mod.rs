@@ -0,0 +1,292 @@
+//! Rendering of location labels.
+//!
+//! A print job is printed with a label template, chosen per printer in the configuration or per
+//! job. Barcode labels carry the location barcode only and are rendered by the printer itself;
+//! QR code labels encode a short link (`/b/{barcode}`) which redirects to the location info page,
+//! so the same label can be scanned into LabWhere or opened on a phone. DataMatrix labels are sized
+//! for tube caps and combine the symbol with the barcode as human-readable text.
+pub mod datamatrix;
+pub mod qr;
+
+use crate::config::Config;
+use crate::errors::ValidationError;
+use datamatrix::CapSize;
+use percent_encoding::utf8_percent_encode;
+use serde::{Deserialize, Serialize};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef};
+use sqlx::{Decode, Encode, Sqlite, Type};
+use std::fmt::{Display, Formatter};
+
+pub use labwhere_core::parser::{strip_deep_link, LOCATION_PATH, PATH_SEGMENT, SHORT_LINK_PATH};
+
+/// The layout a label is printed with.
+///
+/// Templates are stored and sent by name, e.g. `qr` or `datamatrix-10mm`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum LabelTemplate {
+    /// A 1D barcode of the location barcode, rendered by the printer
+    #[default]
+    Barcode,
+    /// A QR code of the deep link to the location
+    Qr,
+    /// A DataMatrix of the barcode with the barcode as text, sized for a tube cap
+    DataMatrix(CapSize),
+}
+
+impl LabelTemplate {
+    /// Parses a template from its name e.g. `qr` or `datamatrix-13mm`.
+    pub fn from_name(name: &str) -> Option<LabelTemplate> {
+        let name = name.trim().to_lowercase();
+        match name.as_str() {
+            "barcode" => Some(LabelTemplate::Barcode),
+            "qr" | "qrcode" => Some(LabelTemplate::Qr),
+            _ => name
+                .strip_prefix("datamatrix-")
+                .and_then(CapSize::from_name)
+                .map(LabelTemplate::DataMatrix),
+        }
+    }
+
+    /// The template configured for a printer, or the default template if none is configured.
+    pub fn for_printer(config: &Config, printer: &str) -> LabelTemplate {
+        config
+            .printer_label_templates
+            .get(printer)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Display for LabelTemplate {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            LabelTemplate::Barcode => write!(f, "barcode"),
+            LabelTemplate::Qr => write!(f, "qr"),
+            LabelTemplate::DataMatrix(size) => write!(f, "datamatrix-{}mm", size.diameter_mm()),
+        }
+    }
+}
+
+impl From<LabelTemplate> for String {
+    fn from(template: LabelTemplate) -> String {
+        template.to_string()
+    }
+}
+
+impl TryFrom<String> for LabelTemplate {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<LabelTemplate, String> {
+        LabelTemplate::from_name(&name).ok_or_else(|| format!("unknown label template {}", name))
+    }
+}
+
+impl Type<Sqlite> for LabelTemplate {
+    fn type_info() -> SqliteTypeInfo {
+        <String as Type<Sqlite>>::type_info()
+    }
+}
+
+impl<'q> Encode<'q, Sqlite> for LabelTemplate {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> Result<IsNull, BoxDynError> {
+        <String as Encode<Sqlite>>::encode(self.to_string(), buf)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for LabelTemplate {
+    fn decode(value: SqliteValueRef<'r>) -> Result<LabelTemplate, BoxDynError> {
+        let name = <String as Decode<Sqlite>>::decode(value)?;
+        Ok(LabelTemplate::try_from(name)?)
+    }
+}
+
+/// A rendered location label, as sent to a printer.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Label {
+    /// The barcode of the location
+    pub barcode: String,
+    /// The name of the location, printed as text
+    pub name: String,
+    /// The template the label was rendered with
+    pub template: LabelTemplate,
+    /// The data encoded in the label's symbol
+    pub content: String,
+    /// The label as an SVG image, for templates which are not rendered by the printer
+    pub svg: Option<String>,
+}
+
+impl Label {
+    /// Renders the label of a location with the given template.
+    ///
+    /// Returns a `ValidationError` if the content is too long to fit in the symbol.
+    /// # Examples
+    /// ```
+    /// use labwhere::config::Config;
+    /// use labwhere::labels::{Label, LabelTemplate};
+    /// let config = Config { base_url: "https://labwhere.example.com".to_string(), ..Default::default() };
+    /// let label = Label::render(&config, "lw-freezer-1", "freezer", LabelTemplate::Qr).unwrap();
+    /// assert_eq!(label.content, "https://labwhere.example.com/b/lw-freezer-1");
+    /// assert!(label.svg.unwrap().starts_with("<?xml"));
+    /// ```
+    pub fn render(
+        config: &Config,
+        barcode: &str,
+        name: &str,
+        template: LabelTemplate,
+    ) -> Result<Label, ValidationError> {
+        let (content, svg) = match template {
+            LabelTemplate::Barcode => (barcode.to_string(), None),
+            LabelTemplate::Qr => {
+                let content = deep_link(config, barcode);
+                let svg = qr::render_svg(&content)?;
+                (content, Some(svg))
+            }
+            LabelTemplate::DataMatrix(size) => (
+                barcode.to_string(),
+                Some(datamatrix::render_svg(barcode, size)?),
+            ),
+        };
+        Ok(Label {
+            barcode: barcode.to_string(),
+            name: name.to_string(),
+            template,
+            content,
+            svg,
+        })
+    }
+}
+
+/// The short link of a barcode, which redirects to the page of its location or labware. The
+/// barcode is percent-encoded, as a mod-43 check character may be e.g. a space or a `/`.
+pub fn deep_link(config: &Config, barcode: &str) -> String {
+    format!(
+        "{}{}{}",
+        config.base_url.trim_end_matches('/'),
+        SHORT_LINK_PATH,
+        utf8_percent_encode(barcode, PATH_SEGMENT)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::labels::*;
+    use std::collections::HashMap;
+
+    fn config() -> Config {
+        Config {
+            base_url: "https://labwhere.example.com/".to_string(),
+            printer_label_templates: HashMap::from([("printer-2".to_string(), LabelTemplate::Qr)]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_template_from_name() {
+        assert_eq!(LabelTemplate::from_name("QR"), Some(LabelTemplate::Qr));
+        assert_eq!(
+            LabelTemplate::from_name("barcode"),
+            Some(LabelTemplate::Barcode)
+        );
+        assert_eq!(
+            LabelTemplate::from_name("datamatrix-10mm"),
+            Some(LabelTemplate::DataMatrix(CapSize::Standard))
+        );
+        assert_eq!(LabelTemplate::from_name("datamatrix-11mm"), None);
+        assert_eq!(LabelTemplate::from_name("hologram"), None);
+    }
+
+    #[test]
+    fn test_template_names_round_trip() {
+        for template in [
+            LabelTemplate::Barcode,
+            LabelTemplate::Qr,
+            LabelTemplate::DataMatrix(CapSize::Micro),
+        ] {
+            assert_eq!(
+                LabelTemplate::from_name(&template.to_string()),
+                Some(template)
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&LabelTemplate::DataMatrix(CapSize::Large)).unwrap(),
+            r#""datamatrix-13mm""#
+        );
+    }
+
+    #[test]
+    fn test_template_for_printer() {
+        let config = config();
+        assert_eq!(
+            LabelTemplate::for_printer(&config, "printer-1"),
+            LabelTemplate::Barcode
+        );
+        assert_eq!(
+            LabelTemplate::for_printer(&config, "printer-2"),
+            LabelTemplate::Qr
+        );
+    }
+
+    #[test]
+    fn test_render_barcode_label() {
+        let label =
+            Label::render(&config(), "lw-freezer-1", "freezer", LabelTemplate::Barcode).unwrap();
+        assert_eq!(label.content, "lw-freezer-1");
+        assert_eq!(label.svg, None);
+    }
+
+    #[test]
+    fn test_render_qr_label() {
+        let label = Label::render(&config(), "lw-freezer-1", "freezer", LabelTemplate::Qr).unwrap();
+        assert_eq!(label.content, "https://labwhere.example.com/b/lw-freezer-1");
+        let svg = label.svg.unwrap();
+        assert!(svg.contains("<svg"));
+        assert!(svg.contains("<path"));
+
+        let label = Label::render(&config(), "lw-freezer-999./", "freezer", LabelTemplate::Qr);
+        assert_eq!(
+            label.unwrap().content,
+            "https://labwhere.example.com/b/lw-freezer-999.%2F"
+        );
+    }
+
+    #[test]
+    fn test_render_datamatrix_label() {
+        let label = Label::render(
+            &config(),
+            "lw-tube-1",
+            "tube",
+            LabelTemplate::DataMatrix(CapSize::Standard),
+        )
+        .unwrap();
+        assert_eq!(label.content, "lw-tube-1");
+        let svg = label.svg.unwrap();
+        assert!(svg.contains(r#"width="10mm""#));
+        assert!(svg.contains(">lw-tube-1</text>"));
+    }
+
+    #[test]
+    fn test_strip_deep_link() {
+        let base_url = "https://labwhere.example.com/";
+        assert_eq!(
+            strip_deep_link(
+                base_url,
+                "https://labwhere.example.com/locations/lw-freezer-1"
+            )
+            .as_deref(),
+            Some("lw-freezer-1")
+        );
+        assert_eq!(
+            strip_deep_link(base_url, "https://labwhere.example.com/b/lw-1").as_deref(),
+            Some("lw-1")
+        );
+        assert_eq!(
+            strip_deep_link(base_url, "https://labwhere.example.com/b/lw-1.%20").as_deref(),
+            Some("lw-1. ")
+        );
+        assert_eq!(strip_deep_link(base_url, "lw-freezer-1"), None);
+        assert_eq!(strip_deep_link("", "/locations/lw-freezer-1"), None);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use qrcode::render::svg;
+use qrcode::{EcLevel, QrCode};
+
+/// The smallest size a QR code is rendered at, in pixels.
+const MIN_DIMENSIONS: u32 = 128;
+
+/// Renders data as a QR code in an SVG image.
+///
+/// Returns a `ValidationError` if the data is too long to fit in a QR code.
+pub fn render_svg(data: &str) -> Result<String, ValidationError> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M).map_err(|_| {
+        ValidationError {
+            message: Message::new("label-content-too-long").arg("content", data),
+        }
+    })?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(MIN_DIMENSIONS, MIN_DIMENSIONS)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::labels::qr::*;
+
+    #[test]
+    fn test_render_svg() {
+        let svg = render_svg("https://labwhere.example.com/locations/lw-freezer-1").unwrap();
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("<path"));
+
+        assert!(render_svg(&"x".repeat(8000)).is_err());
+    }
+}
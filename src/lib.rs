@@ -6,6 +6,31 @@
 // Both of these crates have the same name as the package listed in Cargo.toml.
 //
 // For more info, check https://doc.rust-lang.org/book/ch07-01-packages-and-crates.html.
+pub mod audit_sinks;
+pub mod barcode;
+pub mod cache;
+pub mod config;
 pub mod db;
+pub mod embedded;
 pub mod errors;
+pub mod factories;
+pub mod i18n;
+pub mod labels;
+pub mod loadtest;
+pub mod metrics;
 pub mod models;
+pub mod notifications;
+pub mod pdf;
+pub mod search;
+pub mod storage;
+pub mod sync;
+pub mod timestamps;
+pub mod validation;
+
+// The facade for embedding LabWhere in another service without the HTTP server.
+pub use embedded::Labwhere;
+
+// Builders are the public API for constructing models.
+pub use models::labware::LabwareBuilder;
+pub use models::location::LocationBuilder;
+pub use models::location_type::LocationTypeBuilder;
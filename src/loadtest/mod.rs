@@ -0,0 +1,565 @@
+//! Driving a LabWhere server with scan and search traffic to plan its capacity, for
+//! `labwhere loadtest --scenario scan --rate 200/s --duration 60s`.
+//!
+//! Requests are sent at a fixed rate whether or not earlier ones have been answered, as scanners
+//! in a lab would, so a server which falls behind shows it in its latencies rather than by slowing
+//! the test down. Scans register labwares named `loadtest-...` on the target, so point it at a
+//! test or staging server rather than production.
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
+
+/// The most labwares a scan of the scan scenario puts into a location, e.g. a handful of tubes.
+const MAX_LABWARES_PER_SCAN: u64 = 8;
+
+/// How long a request may take before it counts as an error.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The traffic a load test sends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scenario {
+    /// `POST /scan`s of new and already scanned labwares into locations of the target
+    Scan,
+    /// `GET /search`es for the labwares and locations of the target
+    Search,
+    /// Four scans for each search, like a lab at work
+    Mixed,
+}
+
+impl FromStr for Scenario {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scan" => Ok(Scenario::Scan),
+            "search" => Ok(Scenario::Search),
+            "mixed" => Ok(Scenario::Mixed),
+            _ => Err(format!(
+                "Unknown scenario {} (expected scan, search or mixed)",
+                s
+            )),
+        }
+    }
+}
+
+/// A load test to run against a server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadTest {
+    /// The URL of the server, e.g. `http://127.0.0.1:3000`
+    pub target: String,
+    pub scenario: Scenario,
+    /// How many requests are sent each second
+    pub rate: f64,
+    /// How long requests are sent for
+    pub duration: Duration,
+    /// The location barcodes to scan into. Found with a search of the target if none are given.
+    pub locations: Vec<String>,
+    /// The API key sent with each request, if the target needs one
+    pub api_key: Option<String>,
+}
+
+impl LoadTest {
+    /// Reads a load test from the options of the command, e.g. `--scenario scan --rate 200/s
+    /// --duration 60s --target http://labwhere-staging:3000`.
+    ///
+    /// The rate is per second (`200/s` or `200`) or per minute (`600/m`); the duration in seconds
+    /// (`60s` or `60`), minutes (`5m`) or hours (`1h`). `--location` may be repeated, and
+    /// `--api-key` is sent in the `X-Api-Key` header.
+    /// # Examples
+    /// ```
+    /// use labwhere::loadtest::{LoadTest, Scenario};
+    /// let test = LoadTest::parse(&["--scenario", "search", "--rate", "50/s", "--duration", "1m"]).unwrap();
+    /// assert_eq!(test.scenario, Scenario::Search);
+    /// assert_eq!(test.duration.as_secs(), 60);
+    /// ```
+    pub fn parse(options: &[&str]) -> Result<LoadTest, String> {
+        let mut test = LoadTest {
+            target: "http://127.0.0.1:3000".to_string(),
+            scenario: Scenario::Scan,
+            rate: 10.0,
+            duration: Duration::from_secs(60),
+            locations: vec![],
+            api_key: None,
+        };
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let value = options
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", option))?;
+            match *option {
+                "--target" => test.target = value.trim_end_matches('/').to_string(),
+                "--scenario" => test.scenario = value.parse()?,
+                "--rate" => test.rate = parse_rate(value)?,
+                "--duration" => test.duration = parse_duration(value)?,
+                "--location" => test.locations.push(value.to_string()),
+                "--api-key" => test.api_key = Some(value.to_string()),
+                _ => return Err(format!("Unknown option {}", option)),
+            }
+        }
+        Ok(test)
+    }
+
+    /// Sends the traffic of the scenario to the target for the duration, and reports how the
+    /// target coped.
+    ///
+    /// Fails if the scan scenario has no locations to scan into.
+    pub async fn run(&self) -> Result<Report, String> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let locations = if self.locations.is_empty() {
+            self.find_locations(&client).await
+        } else {
+            self.locations.clone()
+        };
+        if locations.is_empty() && self.scenario != Scenario::Search {
+            return Err(format!(
+                "{} has no locations to scan into; create some or give them with --location",
+                self.target
+            ));
+        }
+
+        let mut traffic = Traffic::new(self.scenario, locations);
+        let mut requests = JoinSet::new();
+        let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / self.rate));
+        let started = Instant::now();
+        while started.elapsed() < self.duration {
+            ticks.tick().await;
+            let request = self.request(&client, traffic.next());
+            requests.spawn(async move {
+                let sent = Instant::now();
+                let ok = match request.send().await {
+                    Ok(response) => response.status().is_success(),
+                    Err(_) => false,
+                };
+                (sent.elapsed(), ok)
+            });
+        }
+        let mut report = Report::default();
+        while let Some(result) = requests.join_next().await {
+            let (latency, ok) = result.unwrap_or((Duration::ZERO, false));
+            report.latencies.push(latency);
+            if !ok {
+                report.errors += 1;
+            }
+        }
+        report.elapsed = started.elapsed();
+        report.latencies.sort_unstable();
+        Ok(report)
+    }
+
+    /// Builds the request for a bit of traffic.
+    fn request(&self, client: &reqwest::Client, request: Request) -> reqwest::RequestBuilder {
+        let builder = match request {
+            Request::Scan {
+                location_barcode,
+                labware_barcodes,
+            } => client.post(format!("{}/scan", self.target)).json(&json!({
+                "location_barcode": location_barcode,
+                "labware_barcodes": labware_barcodes,
+            })),
+            Request::Search { query, limit } => {
+                let mut params = form_urlencoded::Serializer::new(String::new());
+                params.append_pair("q", &query);
+                if let Some(limit) = limit {
+                    params.append_pair("limit", &limit.to_string());
+                }
+                client.get(format!("{}/search?{}", self.target, params.finish()))
+            }
+        };
+        match &self.api_key {
+            Some(api_key) => builder.header("x-api-key", api_key),
+            None => builder,
+        }
+    }
+
+    /// The barcodes of (up to 100) locations of the target, whose barcodes all start with `lw-`.
+    async fn find_locations(&self, client: &reqwest::Client) -> Vec<String> {
+        let response = self
+            .request(
+                client,
+                Request::Search {
+                    query: "lw-".to_string(),
+                    limit: Some(100),
+                },
+            )
+            .send()
+            .await;
+        let results = match response {
+            Ok(response) => response.json::<SearchResultsBody>().await.ok(),
+            Err(_) => None,
+        };
+        results
+            .map(|results| {
+                results
+                    .locations
+                    .into_iter()
+                    .filter_map(|location| location.barcode)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// The part of a `GET /search` response (`SearchResults`) the locations are read from.
+#[derive(Debug, Deserialize)]
+struct SearchResultsBody {
+    locations: Vec<LocationBody>,
+}
+
+/// A location of a `GET /search` response.
+#[derive(Debug, Deserialize)]
+struct LocationBody {
+    barcode: Option<String>,
+}
+
+/// A request of a load test.
+#[derive(Debug, Clone, PartialEq)]
+enum Request {
+    Scan {
+        location_barcode: String,
+        labware_barcodes: Vec<String>,
+    },
+    Search {
+        query: String,
+        limit: Option<u32>,
+    },
+}
+
+/// Makes up the requests of a scenario: scans of a few labwares at a time, about half of them
+/// already scanned before (so they move) and the rest new, and searches for those labwares and
+/// the locations.
+struct Traffic {
+    scenario: Scenario,
+    locations: Vec<String>,
+    /// Where the barcodes of the labwares start, so that runs do not collide
+    prefix: String,
+    /// How many labwares have been scanned so far
+    labwares: u64,
+    /// How many requests have been made so far
+    requests: u64,
+    rng: Rng,
+}
+
+impl Traffic {
+    fn new(scenario: Scenario, locations: Vec<String>) -> Traffic {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(1);
+        Traffic {
+            scenario,
+            locations,
+            prefix: format!("loadtest-{:x}", seed % 0x1000000),
+            labwares: 0,
+            requests: 0,
+            rng: Rng(seed | 1),
+        }
+    }
+
+    /// The next request to send.
+    fn next(&mut self) -> Request {
+        self.requests += 1;
+        let scan = match self.scenario {
+            Scenario::Scan => true,
+            Scenario::Search => false,
+            Scenario::Mixed => !self.requests.is_multiple_of(5),
+        };
+        if scan && !self.locations.is_empty() {
+            self.scan()
+        } else {
+            self.search()
+        }
+    }
+
+    fn scan(&mut self) -> Request {
+        let location_barcode =
+            self.locations[self.rng.below(self.locations.len() as u64) as usize].clone();
+        let count = 1 + self.rng.below(MAX_LABWARES_PER_SCAN);
+        let labware_barcodes = (0..count)
+            .map(|_| {
+                let labware = if self.labwares > 0 && self.rng.below(2) == 0 {
+                    self.rng.below(self.labwares)
+                } else {
+                    self.labwares += 1;
+                    self.labwares - 1
+                };
+                format!("{}-{}", self.prefix, labware)
+            })
+            .collect();
+        Request::Scan {
+            location_barcode,
+            labware_barcodes,
+        }
+    }
+
+    fn search(&mut self) -> Request {
+        let query = if self.labwares > 0 && (self.locations.is_empty() || self.rng.below(2) == 0) {
+            format!("{}-{}", self.prefix, self.rng.below(self.labwares))
+        } else if !self.locations.is_empty() {
+            self.locations[self.rng.below(self.locations.len() as u64) as usize].clone()
+        } else {
+            self.prefix.clone()
+        };
+        Request::Search { query, limit: None }
+    }
+}
+
+/// A xorshift generator: random enough to vary the traffic, without a dependency.
+struct Rng(u64);
+
+impl Rng {
+    /// A number below the bound, which must not be zero.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+/// How a server coped with a load test.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// How long each request took to be answered, fastest first
+    pub latencies: Vec<Duration>,
+    /// How many requests failed, were not answered in time or were answered with an error status
+    pub errors: usize,
+    /// How long the test took, including waiting for the last answers
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// How many requests were sent.
+    pub fn requests(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// The share of requests which failed, from 0 to 1.
+    pub fn error_rate(&self) -> f64 {
+        match self.requests() {
+            0 => 0.0,
+            requests => self.errors as f64 / requests as f64,
+        }
+    }
+
+    /// The latency under which the given percentage of requests were answered, e.g. the 99th
+    /// percentile.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let elapsed = self.elapsed.as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.requests() as f64 / elapsed
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "Requests: {} in {:.1}s ({:.1}/s)",
+            self.requests(),
+            elapsed,
+            rate
+        )?;
+        writeln!(
+            f,
+            "Errors: {} ({:.2}%)",
+            self.errors,
+            self.error_rate() * 100.0
+        )?;
+        writeln!(f, "Latency:")?;
+        for (name, percent) in [("p50", 50.0), ("p90", 90.0), ("p95", 95.0), ("p99", 99.0)] {
+            writeln!(f, "  {}: {:.1} ms", name, millis(self.percentile(percent)))?;
+        }
+        writeln!(f, "  max: {:.1} ms", millis(self.percentile(100.0)))
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Parses a rate e.g. `200/s`, `200` (per second) or `600/m`, into requests per second.
+fn parse_rate(rate: &str) -> Result<f64, String> {
+    let (count, seconds) = match rate.split_once('/') {
+        None => (rate, 1.0),
+        Some((count, "s")) => (count, 1.0),
+        Some((count, "m")) => (count, 60.0),
+        Some(_) => return Err(format!("Invalid rate {} (expected e.g. 200/s)", rate)),
+    };
+    match count.parse::<f64>() {
+        Ok(count) if count > 0.0 && count.is_finite() => Ok(count / seconds),
+        _ => Err(format!("Invalid rate {} (expected e.g. 200/s)", rate)),
+    }
+}
+
+/// Parses a duration e.g. `60s`, `60` (seconds), `5m` or `1h`.
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (count, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(format!("Invalid duration {} (expected e.g. 60s)", duration)),
+    };
+    match count.parse::<u64>() {
+        Ok(count) if count > 0 => Ok(Duration::from_secs(count * seconds)),
+        _ => Err(format!("Invalid duration {} (expected e.g. 60s)", duration)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loadtest::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every request with 200, or with 404 for searches, until the test ends.
+    async fn serve(listener: TcpListener) {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut request = vec![];
+                let mut chunk = [0; 4096];
+                loop {
+                    let read = socket.read(&mut chunk).await.unwrap_or(0);
+                    request.extend_from_slice(&chunk[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, content)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                line.to_lowercase()
+                                    .strip_prefix("content-length: ")?
+                                    .parse()
+                                    .ok()
+                            })
+                            .unwrap_or(0);
+                        if read == 0 || content.len() >= length {
+                            break;
+                        }
+                    }
+                    if read == 0 {
+                        break;
+                    }
+                }
+                let status = if request.starts_with(b"GET /search") {
+                    "404 Not Found"
+                } else {
+                    "200 OK"
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{{}}",
+                    status
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let test = LoadTest::parse(&[
+            "--scenario",
+            "mixed",
+            "--rate",
+            "600/m",
+            "--duration",
+            "2m",
+            "--target",
+            "http://staging:3000/",
+            "--location",
+            "lw-freezer-1",
+        ])
+        .unwrap();
+        assert_eq!(test.scenario, Scenario::Mixed);
+        assert_eq!(test.rate, 10.0);
+        assert_eq!(test.duration, Duration::from_secs(120));
+        assert_eq!(test.target, "http://staging:3000");
+        assert_eq!(test.locations, vec!["lw-freezer-1"]);
+
+        assert_eq!(LoadTest::parse(&["--rate", "200/s"]).unwrap().rate, 200.0);
+        assert!(LoadTest::parse(&["--rate", "0/s"]).is_err());
+        assert!(LoadTest::parse(&["--duration", "1d"]).is_err());
+        assert!(LoadTest::parse(&["--scenario", "print"]).is_err());
+        assert!(LoadTest::parse(&["--scenario"]).is_err());
+        assert!(LoadTest::parse(&["--colour", "red"]).is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let report = Report {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            errors: 5,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(report.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(report.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(report.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(report.error_rate(), 0.05);
+        assert!(report
+            .to_string()
+            .starts_with("Requests: 100 in 2.0s (50.0/s)\nErrors: 5 (5.00%)\n"));
+        assert_eq!(Report::default().percentile(99.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_traffic() {
+        let mut traffic = Traffic::new(Scenario::Mixed, vec!["lw-freezer-1".to_string()]);
+        let requests: Vec<Request> = (0..10).map(|_| traffic.next()).collect();
+        let searches = requests
+            .iter()
+            .filter(|request| matches!(request, Request::Search { .. }))
+            .count();
+        assert_eq!(searches, 2);
+        match &requests[0] {
+            Request::Scan {
+                location_barcode,
+                labware_barcodes,
+            } => {
+                assert_eq!(location_barcode, "lw-freezer-1");
+                assert!(!labware_barcodes.is_empty());
+                assert!(labware_barcodes[0].starts_with("loadtest-"));
+            }
+            request => panic!("Expected a scan, got {:?}", request),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener));
+        let mut test = LoadTest {
+            target,
+            scenario: Scenario::Mixed,
+            rate: 50.0,
+            duration: Duration::from_millis(200),
+            locations: vec!["lw-freezer-1".to_string()],
+            api_key: None,
+        };
+        let report = test.run().await.unwrap();
+        assert!(report.requests() >= 5);
+        // Every fifth request is a search, which the fake server does not know
+        assert_eq!(report.errors, report.requests() / 5);
+
+        // Without locations, the target is searched for some
+        test.locations = vec![];
+        assert!(test.run().await.unwrap_err().contains("no locations"));
+    }
+}
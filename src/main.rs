@@ -5,12 +5,32 @@
 // name listed in Cargo.toml); because stuff from library crate are imported in line 1 and 2.
 
 use hyper::server::conn::http1;
-use hyper::service::service_fn;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::service::TowerToHyperService;
+use labwhere::audit_sinks::mirror_audits;
+use labwhere::config::CONFIG;
+use labwhere::db::analytics;
+use labwhere::db::analyze::Analysis;
+use labwhere::db::create_db::{create_db, database_url};
+use labwhere::db::snapshot::Snapshot;
+use labwhere::db::{init_db, init_pool};
+use labwhere::factories::seed;
+use labwhere::loadtest::LoadTest;
+use labwhere::metrics::OPEN_CONNECTIONS;
+use labwhere::models::audit::Audit;
+use labwhere::models::event::State;
+use labwhere::models::location::{reconcile_labware_counts, Location};
+use labwhere::models::occupancy::record_snapshots;
+use labwhere::models::print_job::PrintJob;
+use labwhere::notifications::{deliver_subscriptions, watch_capacity, watch_transit};
+use labwhere::search::opensearch::index_events;
+use labwhere::sync::sync_with_hub;
 use log::{error, info, warn};
+use sqlx::{Connection, SqliteConnection};
 use std::env;
 use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::net::TcpSocket;
 
 pub mod services;
 
@@ -22,6 +42,142 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Set the logging level to INFO by default
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
+    // Commands run against the database of the environment, and exit:
+    // - `labwhere db analyze` reports its missing indexes and slow queries, without changing it.
+    // - `labwhere export --out snapshot.json.gz` writes a snapshot of every record in it.
+    // - `labwhere import snapshot.json.gz` replaces every record in it with those of a snapshot.
+    // - `labwhere verify-audit-chain` checks that no audit was changed or deleted since it was
+    //   sealed into the hash chain, exiting with an error if one was.
+    // - `labwhere rebuild-from-events` brings its locations and labwares in line with its event log.
+    // - `labwhere regenerate-barcodes --printer printer-1` gives its locations barcodes in the
+    //   configured format, keeping the old ones as aliases, and queues the new labels on the
+    //   printer. `--dry-run` lists the barcodes which would change instead.
+    // - `labwhere seed` fills it with a building of rooms, freezers, shelves and labwares to try the
+    //   server out with.
+    // - `labwhere export-analytics --format parquet --out analytics` writes its scans, audits and
+    //   occupancy snapshots as Parquet files for the data warehouse.
+    // And against a running server:
+    // - `labwhere loadtest --scenario scan --rate 200/s --duration 60s --target http://...` drives
+    //   it with scan (or search, or mixed) traffic and reports its latencies and error rate.
+    // Without a command, the server is started.
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => {}
+        ["db", "analyze"] => {
+            let mut connection =
+                SqliteConnection::connect(&database_url(None, &CONFIG.environment)).await?;
+            print!("{}", Analysis::run(&mut connection).await?);
+            return Ok(());
+        }
+        ["export", "--out", path] => {
+            let mut connection = init_db(&database_url(None, &CONFIG.environment)).await?;
+            let snapshot = Snapshot::export(&mut connection).await?;
+            snapshot.write(path)?;
+            info!("Exported {} records to {}", snapshot.len(), path);
+            return Ok(());
+        }
+        ["import", path] => {
+            create_db(None, &CONFIG.environment).await?;
+            let mut connection = init_db(&database_url(None, &CONFIG.environment)).await?;
+            let snapshot = Snapshot::read(path)?;
+            snapshot.import(&mut connection).await?;
+            info!("Imported {} records from {}", snapshot.len(), path);
+            return Ok(());
+        }
+        ["verify-audit-chain"] => {
+            let mut connection = init_db(&database_url(None, &CONFIG.environment)).await?;
+            let verification = Audit::verify_chain(&mut connection).await?;
+            if verification.broken_at.is_some() {
+                return Err(verification.to_string().into());
+            }
+            info!("Verified the audit chain: {}", verification);
+            return Ok(());
+        }
+        ["rebuild-from-events"] => {
+            let mut connection = init_db(&database_url(None, &CONFIG.environment)).await?;
+            let state = State::at(None, &mut connection).await?;
+            let rebuild = state.rebuild(&mut connection).await?;
+            info!("Rebuilt from the event log: {}", rebuild);
+            return Ok(());
+        }
+        ["regenerate-barcodes", "--dry-run"] => {
+            let mut connection = init_db(&database_url(None, &CONFIG.environment)).await?;
+            let changes = Location::regenerate_barcodes(&CONFIG, true, &mut connection).await?;
+            for change in &changes {
+                println!("{}", change);
+            }
+            info!("{} location barcodes would change", changes.len());
+            return Ok(());
+        }
+        ["regenerate-barcodes", "--printer", printer] => {
+            let mut connection = init_db(&database_url(None, &CONFIG.environment)).await?;
+            let changes = Location::regenerate_barcodes(&CONFIG, false, &mut connection).await?;
+            for change in &changes {
+                println!("{}", change);
+            }
+            info!("Regenerated {} location barcodes", changes.len());
+            if !changes.is_empty() {
+                let print_job = PrintJob::create(
+                    printer.to_string(),
+                    changes
+                        .into_iter()
+                        .map(|change| change.new_barcode)
+                        .collect(),
+                    None,
+                    &mut connection,
+                )
+                .await?;
+                info!(
+                    "Queued print job {} for {} labels on {}",
+                    print_job.uuid,
+                    print_job.location_barcodes.len(),
+                    printer
+                );
+            }
+            return Ok(());
+        }
+        ["seed"] => {
+            create_db(None, &CONFIG.environment).await?;
+            let pool = init_pool(&database_url(None, &CONFIG.environment)).await?;
+            seed(&pool).await?;
+            info!("Seeded the {} database", CONFIG.environment);
+            return Ok(());
+        }
+        ["export-analytics", "--format", "parquet", "--out", folder] => {
+            let mut connection = init_db(&database_url(None, &CONFIG.environment)).await?;
+            for (dataset, rows) in analytics::export(folder, &mut connection).await? {
+                info!(
+                    "Exported {} rows to {}/{}",
+                    rows,
+                    folder,
+                    dataset.filename()
+                );
+            }
+            return Ok(());
+        }
+        ["loadtest", options @ ..] => {
+            let test = LoadTest::parse(options)?;
+            info!(
+                "Sending {:?} traffic to {} at {}/s for {}s",
+                test.scenario,
+                test.target,
+                test.rate,
+                test.duration.as_secs()
+            );
+            print!("{}", test.run().await?);
+            return Ok(());
+        }
+        ["export-analytics", "--format", format, ..] => {
+            return Err(format!("Unsupported analytics format: {}", format).into())
+        }
+        _ => return Err(format!("Unknown command: {}", args.join(" ")).into()),
+    }
+
     // Read environment variable key PORT and set the value.
     // If no PORT environment varibale is set, the default is set, which is 3000.
     let port: u16 = env::var("PORT").map_or_else(
@@ -32,25 +188,84 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         |v| v.parse().unwrap(),
     );
 
+    // Create the database for the environment (if it does not exist yet) and a connection pool
+    // shared by all incoming connections.
+    create_db(None, &CONFIG.environment).await?;
+    let pool = init_pool(&database_url(None, &CONFIG.environment)).await?;
+
+    // Read (or create) the unknown location once, rather than for each labware put in it.
+    Location::cache_unknown(&mut *pool.acquire().await?).await?;
+
+    // Deliver the notifications of location subscriptions in the background.
+    tokio::spawn(deliver_subscriptions(pool.clone()));
+
+    // Snapshot the occupancy of the storage, so that its trend can be shown.
+    tokio::spawn(record_snapshots(pool.clone()));
+
+    // Correct the labware counts of locations, should they drift.
+    tokio::spawn(reconcile_labware_counts(pool.clone()));
+
+    // Alert when locations are fuller than their capacity thresholds.
+    tokio::spawn(watch_capacity(pool.clone()));
+
+    // Alert when labwares have been in transit for too long.
+    tokio::spawn(watch_transit(pool.clone()));
+
+    // Index the event log into the search cluster, if there is one.
+    if CONFIG.search_url.is_some() {
+        tokio::spawn(index_events(pool.clone()));
+    }
+
+    // Mirror the audits to the external sinks, if there are any.
+    if !CONFIG.audit_sinks.is_empty() {
+        tokio::spawn(mirror_audits(pool.clone()));
+    }
+
+    // Sync with the hub, if this instance is a satellite.
+    if CONFIG.sync_hub_url.is_some() {
+        tokio::spawn(sync_with_hub(pool.clone()));
+    }
+
     // Bind the server to an address
     let address = SocketAddr::from(([127, 0, 00, 1], port));
 
-    // Create a TcpListener and bind the address to it.
-    let listener = TcpListener::bind(address).await?;
+    // Create a TcpListener and bind the address to it, with room for the configured number of
+    // connections waiting to be accepted.
+    let socket = TcpSocket::new_v4()?;
+    socket.set_reuseaddr(true)?;
+    socket.bind(address)?;
+    let listener = socket.listen(CONFIG.accept_backlog)?;
 
     info!("Server running on port: {:?}", port);
 
+    // Every connection is served by a clone of the same stack of middleware and endpoints.
+    let service = services::service(pool);
+
+    // Every connection is also served with the same tuning of the HTTP/1 protocol.
+    let header_read_timeout = Duration::from_secs(CONFIG.header_read_timeout_seconds);
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .header_read_timeout((!header_read_timeout.is_zero()).then_some(header_read_timeout))
+        .max_headers(CONFIG.max_headers);
+
     loop {
         let (stream, _) = listener.accept().await?;
+        if let Err(err) = stream.set_nodelay(CONFIG.tcp_nodelay) {
+            warn!("Could not set TCP_NODELAY on a connection: {:?}", err);
+        }
 
         let io = TokioIo::new(stream);
+        let service = TowerToHyperService::new(service.clone());
+
+        let builder = builder.clone();
 
         // Spawn tokio task for concurrent processing of incoming streams
         tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new()
-                // This is the global service handler.
-                // This service handler should delegate the request to the relevant endpoint
-                .serve_connection(io, service_fn(services::scan::scan))
+            let _connection = OPEN_CONNECTIONS.track();
+            if let Err(err) = builder
+                // The service passes each request through the middleware to the relevant endpoint
+                .serve_connection(io, service)
                 .await
             {
                 error!("Error serving the connection: {:?}", err);
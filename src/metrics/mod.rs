@@ -0,0 +1,257 @@
+//! Gauges of how saturated the server is, exposed in the Prometheus text format at `GET /metrics`.
+//!
+//! Open HTTP connections and tasks waiting for a database connection are counted as they come and
+//! go. The size of the connection pool and the depth of the background job queues are read when
+//! the metrics are gathered, as are the burn rates of the latency objectives (see `slo`).
+
+use crate::db::health::DB_CIRCUIT;
+use crate::metrics::slo::{SloStatus, LATENCY_SLOS};
+use sqlx::pool::PoolConnection;
+use sqlx::{Sqlite, SqlitePool};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+pub mod slo;
+
+/// The open HTTP connections.
+pub static OPEN_CONNECTIONS: Gauge = Gauge::new();
+
+/// The tasks waiting for a connection from the database pool.
+pub static DB_POOL_WAITERS: Gauge = Gauge::new();
+
+/// The background job queues, and the query counting the jobs waiting in each.
+const QUEUES: [(&str, &str); 4] = [
+    (
+        "print_jobs",
+        "SELECT COUNT(*) FROM print_jobs WHERE state IN ('queued', 'printing')",
+    ),
+    (
+        "subscription_notifications",
+        "SELECT COUNT(*) FROM subscription_events WHERE delivered_at IS NULL
+            AND id NOT IN (SELECT subscription_event_id FROM dead_letters WHERE redriven_at IS NULL)",
+    ),
+    (
+        "dead_letters",
+        "SELECT COUNT(*) FROM dead_letters WHERE redriven_at IS NULL",
+    ),
+    (
+        "manifests",
+        "SELECT COUNT(*) FROM manifests WHERE state = 'processing'",
+    ),
+];
+
+/// A value which goes up and down, such as the number of open connections.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub const fn new() -> Gauge {
+        Gauge(AtomicI64::new(0))
+    }
+
+    /// The current value of the gauge.
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Counts something until the returned guard is dropped.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::metrics::OPEN_CONNECTIONS;
+    /// let connection = OPEN_CONNECTIONS.track();
+    /// assert_eq!(OPEN_CONNECTIONS.get(), 1);
+    /// drop(connection);
+    /// # }
+    /// ```
+    pub fn track(&'static self) -> Tracked {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Tracked(self)
+    }
+}
+
+/// Counts towards a `Gauge` for as long as it lives.
+#[derive(Debug)]
+pub struct Tracked(&'static Gauge);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Acquires a connection from the pool, counting the task in `DB_POOL_WAITERS` while it waits.
+///
+/// Whether a connection could be acquired is recorded in the circuit breaker of the database (see
+/// `DB_CIRCUIT`).
+pub async fn acquire(pool: &SqlitePool) -> Result<PoolConnection<Sqlite>, sqlx::Error> {
+    let _waiting = DB_POOL_WAITERS.track();
+    let connection = pool.acquire().await;
+    match &connection {
+        Ok(_) => DB_CIRCUIT.record_success(),
+        Err(e) => DB_CIRCUIT.record_failure(e),
+    }
+    connection
+}
+
+/// A reading of every gauge.
+#[derive(Debug, PartialEq)]
+pub struct Metrics {
+    /// The open HTTP connections
+    pub open_connections: i64,
+    /// The most connections the database pool opens
+    pub db_pool_max: u32,
+    /// The connections the database pool has open
+    pub db_pool_size: u32,
+    /// The open connections which are in use
+    pub db_pool_in_use: u32,
+    /// The tasks waiting for a connection
+    pub db_pool_waiters: i64,
+    /// The jobs waiting in each background job queue
+    pub queue_depths: Vec<(&'static str, u32)>,
+    /// How each latency objective is doing
+    pub slos: Vec<SloStatus>,
+}
+
+impl Metrics {
+    /// Reads every gauge. The pool is read before a connection for counting the queued jobs is
+    /// taken from it.
+    pub async fn gather(pool: &SqlitePool) -> Result<Metrics, sqlx::Error> {
+        let mut metrics = Metrics {
+            open_connections: OPEN_CONNECTIONS.get(),
+            db_pool_max: pool.options().get_max_connections(),
+            db_pool_size: pool.size(),
+            db_pool_in_use: pool.size().saturating_sub(pool.num_idle() as u32),
+            db_pool_waiters: DB_POOL_WAITERS.get(),
+            queue_depths: vec![],
+            slos: LATENCY_SLOS.statuses(),
+        };
+        let mut connection = acquire(pool).await?;
+        for (queue, query) in QUEUES {
+            let depth = sqlx::query_scalar::<_, u32>(query)
+                .fetch_one(&mut *connection)
+                .await?;
+            metrics.queue_depths.push((queue, depth));
+        }
+        Ok(metrics)
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let gauges: [(&str, &str, i64); 5] = [
+            (
+                "labwhere_http_open_connections",
+                "Open HTTP connections",
+                self.open_connections,
+            ),
+            (
+                "labwhere_db_pool_max_connections",
+                "The most connections the database pool opens",
+                self.db_pool_max.into(),
+            ),
+            (
+                "labwhere_db_pool_connections",
+                "Connections the database pool has open",
+                self.db_pool_size.into(),
+            ),
+            (
+                "labwhere_db_pool_connections_in_use",
+                "Open database connections which are in use",
+                self.db_pool_in_use.into(),
+            ),
+            (
+                "labwhere_db_pool_waiters",
+                "Tasks waiting for a database connection",
+                self.db_pool_waiters,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = write!(
+                text,
+                "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+            );
+        }
+        text.push_str("# HELP labwhere_job_queue_depth Background jobs waiting to be done\n");
+        text.push_str("# TYPE labwhere_job_queue_depth gauge\n");
+        for (queue, depth) in &self.queue_depths {
+            let _ = writeln!(
+                text,
+                "labwhere_job_queue_depth{{queue=\"{queue}\"}} {depth}"
+            );
+        }
+        if !self.slos.is_empty() {
+            text.push_str(
+                "# HELP labwhere_slo_burn_rate How fast a latency objective uses up its error budget\n",
+            );
+            text.push_str("# TYPE labwhere_slo_burn_rate gauge\n");
+            for slo in &self.slos {
+                let _ = writeln!(
+                    text,
+                    "labwhere_slo_burn_rate{{route=\"{}\",objective=\"{}\"}} {}",
+                    slo.route, slo.objective, slo.burn_rate
+                );
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_pool;
+    use crate::metrics::*;
+
+    static GAUGE: Gauge = Gauge::new();
+
+    #[test]
+    fn test_gauge() {
+        let first = GAUGE.track();
+        let second = GAUGE.track();
+        assert_eq!(GAUGE.get(), 2);
+        drop(first);
+        assert_eq!(GAUGE.get(), 1);
+        drop(second);
+        assert_eq!(GAUGE.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_gather() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        sqlx::query("INSERT INTO print_jobs (uuid, printer) VALUES ('1', 'printer-1')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut metrics = Metrics::gather(&pool).await.unwrap();
+        assert_eq!(metrics.db_pool_max, 1);
+        assert_eq!(metrics.db_pool_size, 1);
+        assert_eq!(
+            metrics.queue_depths,
+            vec![
+                ("print_jobs", 1),
+                ("subscription_notifications", 0),
+                ("dead_letters", 0),
+                ("manifests", 0)
+            ]
+        );
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE labwhere_db_pool_max_connections gauge\n"));
+        assert!(text.contains("\nlabwhere_db_pool_max_connections 1\n"));
+        assert!(text.ends_with("labwhere_job_queue_depth{queue=\"manifests\"} 0\n"));
+
+        metrics.slos.push(SloStatus {
+            route: "POST /scan".to_string(),
+            objective: "p99<200ms".to_string(),
+            requests: 200,
+            slow: 1,
+            compliance: 99.5,
+            burn_rate: 0.5,
+            met: true,
+        });
+        assert!(metrics.render().ends_with(
+            "labwhere_slo_burn_rate{route=\"POST /scan\",objective=\"p99<200ms\"} 0.5\n"
+        ));
+    }
+}
@@ -0,0 +1,279 @@
+//! Latency objectives of routes, e.g. that 99% of scans are answered within 200 ms, and how fast
+//! each is using up its error budget over a sliding window.
+//!
+//! The burn rate of an objective is the share of its requests which were too slow, over the share
+//! its objective allows: at a burn rate of 1 the budget lasts exactly the window, above 1 the
+//! objective is being missed.
+
+use crate::config::CONFIG;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The number of buckets the window is split into. Buckets older than the window are dropped
+/// whole, so the window slides in steps of a sixtieth of its length.
+const BUCKETS: u64 = 60;
+
+/// The latency objectives configured with `LABWHERE_LATENCY_SLOS`, tracked over
+/// `LABWHERE_SLO_WINDOW_SECONDS`.
+pub static LATENCY_SLOS: Lazy<SloTracker> = Lazy::new(|| {
+    SloTracker::new(
+        CONFIG.latency_slos.clone(),
+        Duration::from_secs(CONFIG.slo_window_seconds),
+    )
+});
+
+/// A latency objective of a route: `percentile` percent of its requests are answered within
+/// `threshold_ms` milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencySlo {
+    /// The method of the requests e.g. `POST`
+    pub method: String,
+    /// The segments of the path of the requests, `*` matching any segment e.g. `["labwares", "*"]`
+    pub segments: Vec<String>,
+    /// The percentage of requests which have to be answered in time e.g. 99
+    pub percentile: f64,
+    /// How long a request may take to be answered in time, in milliseconds
+    pub threshold_ms: u64,
+}
+
+impl LatencySlo {
+    /// Parses an objective e.g. `POST /scan=p99<200ms` or `GET /labwares/*=p95<500ms`.
+    pub fn parse(value: &str) -> Option<LatencySlo> {
+        let (route, objective) = value.split_once('=')?;
+        let (method, path) = route.trim().split_once(' ')?;
+        let (percentile, threshold) = objective.trim().strip_prefix('p')?.split_once('<')?;
+        let percentile = percentile
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|percentile| *percentile > 0.0 && *percentile < 100.0)?;
+        let threshold_ms = threshold.trim().strip_suffix("ms")?.trim().parse().ok()?;
+        Some(LatencySlo {
+            method: method.trim().to_uppercase(),
+            segments: path
+                .trim()
+                .trim_matches('/')
+                .split('/')
+                .map(str::to_string)
+                .collect(),
+            percentile,
+            threshold_ms,
+        })
+    }
+
+    /// The route of the objective e.g. `GET /labwares/*`
+    pub fn route(&self) -> String {
+        format!("{} /{}", self.method, self.segments.join("/"))
+    }
+
+    /// The objective as it is configured e.g. `p99<200ms`
+    pub fn objective(&self) -> String {
+        format!("p{}<{}ms", self.percentile, self.threshold_ms)
+    }
+
+    /// Whether a request is made to the route of the objective
+    fn matches(&self, method: &str, segments: &[&str]) -> bool {
+        self.method == method
+            && self.segments.len() == segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(segments)
+                .all(|(expected, segment)| expected == "*" || expected == segment)
+    }
+}
+
+/// The requests of an objective in one bucket of the window.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// The number of the bucket since the tracker was created
+    index: u64,
+    /// The requests made
+    requests: u64,
+    /// The requests which were not answered in time
+    slow: u64,
+}
+
+/// How an objective is doing over the window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SloStatus {
+    /// The route of the objective e.g. `POST /scan`
+    pub route: String,
+    /// The objective e.g. `p99<200ms`
+    pub objective: String,
+    /// The requests made to the route within the window
+    pub requests: u64,
+    /// The requests which were not answered in time
+    pub slow: u64,
+    /// The percentage of requests answered in time, 100 if none were made
+    pub compliance: f64,
+    /// The share of requests which were too slow over the share the objective allows
+    pub burn_rate: f64,
+    /// Whether the objective is being met, i.e. the burn rate is at most 1
+    pub met: bool,
+}
+
+/// Counts the requests made to the route of each objective, and those which were too slow, in
+/// buckets over a sliding window.
+pub struct SloTracker {
+    /// The objectives tracked
+    slos: Vec<LatencySlo>,
+    /// How long each bucket lasts
+    bucket: Duration,
+    /// When the tracker was created, which buckets are numbered from
+    started: Instant,
+    /// The buckets of each objective, oldest first
+    buckets: Mutex<Vec<VecDeque<Bucket>>>,
+}
+
+impl SloTracker {
+    /// Create a new SloTracker for the objectives over a window, which is at least a minute long.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::metrics::slo::{LatencySlo, SloTracker};
+    /// let slo = LatencySlo::parse("POST /scan=p99<200ms").unwrap();
+    /// let tracker = SloTracker::new(vec![slo], Duration::from_secs(3600));
+    /// tracker.observe("POST", &["scan"], Duration::from_millis(120));
+    /// assert!(tracker.statuses()[0].met);
+    /// # }
+    /// ```
+    pub fn new(slos: Vec<LatencySlo>, window: Duration) -> SloTracker {
+        SloTracker {
+            buckets: Mutex::new(vec![VecDeque::new(); slos.len()]),
+            slos,
+            bucket: window.max(Duration::from_secs(BUCKETS)) / BUCKETS as u32,
+            started: Instant::now(),
+        }
+    }
+
+    /// Whether there is nothing to track
+    pub fn is_empty(&self) -> bool {
+        self.slos.is_empty()
+    }
+
+    /// Counts a request against the objectives of its route
+    pub fn observe(&self, method: &str, segments: &[&str], latency: Duration) {
+        self.observe_at(method, segments, latency, Instant::now());
+    }
+
+    /// How each objective is doing over the window, in the order they were configured
+    pub fn statuses(&self) -> Vec<SloStatus> {
+        self.statuses_at(Instant::now())
+    }
+
+    /// The number of the bucket an instant falls in
+    fn index(&self, now: Instant) -> u64 {
+        (now.duration_since(self.started).as_millis() / self.bucket.as_millis()) as u64
+    }
+
+    fn observe_at(&self, method: &str, segments: &[&str], latency: Duration, now: Instant) {
+        let index = self.index(now);
+        let mut buckets = self.buckets.lock().unwrap();
+        for (slo, buckets) in self.slos.iter().zip(buckets.iter_mut()) {
+            if !slo.matches(method, segments) {
+                continue;
+            }
+            while buckets
+                .front()
+                .is_some_and(|bucket| bucket.index + BUCKETS <= index)
+            {
+                buckets.pop_front();
+            }
+            if buckets.back().is_none_or(|bucket| bucket.index != index) {
+                buckets.push_back(Bucket {
+                    index,
+                    requests: 0,
+                    slow: 0,
+                });
+            }
+            if let Some(bucket) = buckets.back_mut() {
+                bucket.requests += 1;
+                if latency.as_millis() > u128::from(slo.threshold_ms) {
+                    bucket.slow += 1;
+                }
+            }
+        }
+    }
+
+    fn statuses_at(&self, now: Instant) -> Vec<SloStatus> {
+        let index = self.index(now);
+        let buckets = self.buckets.lock().unwrap();
+        self.slos
+            .iter()
+            .zip(buckets.iter())
+            .map(|(slo, buckets)| {
+                let (requests, slow) = buckets
+                    .iter()
+                    .filter(|bucket| bucket.index + BUCKETS > index)
+                    .fold((0, 0), |(requests, slow), bucket| {
+                        (requests + bucket.requests, slow + bucket.slow)
+                    });
+                let too_slow = match requests {
+                    0 => 0.0,
+                    _ => slow as f64 / requests as f64,
+                };
+                let burn_rate = too_slow / (1.0 - slo.percentile / 100.0);
+                SloStatus {
+                    route: slo.route(),
+                    objective: slo.objective(),
+                    requests,
+                    slow,
+                    compliance: 100.0 * (1.0 - too_slow),
+                    burn_rate,
+                    met: burn_rate <= 1.0,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::slo::*;
+
+    #[test]
+    fn test_parse() {
+        let slo = LatencySlo::parse(" get /labwares/*/ = p99.5 < 250ms").unwrap();
+        assert_eq!(slo.method, "GET");
+        assert_eq!(slo.segments, vec!["labwares", "*"]);
+        assert_eq!(slo.route(), "GET /labwares/*");
+        assert_eq!(slo.objective(), "p99.5<250ms");
+        assert!(slo.matches("GET", &["labwares", "lw-1"]));
+        assert!(!slo.matches("GET", &["labwares"]));
+        assert!(!slo.matches("POST", &["labwares", "lw-1"]));
+
+        assert!(LatencySlo::parse("POST /scan=p100<200ms").is_none());
+        assert!(LatencySlo::parse("POST /scan=p99<200").is_none());
+        assert!(LatencySlo::parse("/scan=p99<200ms").is_none());
+    }
+
+    #[test]
+    fn test_burn_rate_over_window() {
+        let slo = LatencySlo::parse("POST /scan=p90<200ms").unwrap();
+        let tracker = SloTracker::new(vec![slo], Duration::from_secs(3600));
+        let start = tracker.started;
+        for i in 0..10 {
+            let latency = Duration::from_millis(if i < 8 { 100 } else { 300 });
+            tracker.observe_at("POST", &["scan"], latency, start);
+        }
+        tracker.observe_at("GET", &["scan"], Duration::from_secs(1), start);
+
+        let status = &tracker.statuses_at(start)[0];
+        assert_eq!(status.route, "POST /scan");
+        assert_eq!((status.requests, status.slow), (10, 2));
+        assert!((status.compliance - 80.0).abs() < 1e-9);
+        assert!((status.burn_rate - 2.0).abs() < 1e-9);
+        assert!(!status.met);
+
+        // Once the window has slid past them, the slow requests no longer count
+        let later = start + Duration::from_secs(3600);
+        tracker.observe_at("POST", &["scan"], Duration::from_millis(100), later);
+        let status = &tracker.statuses_at(later)[0];
+        assert_eq!((status.requests, status.slow), (1, 0));
+        assert!(status.met);
+    }
+}
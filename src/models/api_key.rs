@@ -0,0 +1,385 @@
+use crate::config::CONFIG;
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::{new_secret, new_uuid, secret_digest};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// How long the old key of a rotated API key keeps working by default, so the integration can be
+/// switched over without downtime.
+pub const DEFAULT_GRACE_HOURS: i64 = 24;
+
+/// The number of random bytes in a secret, which is twice as many hex characters long.
+const SECRET_BYTES: u32 = 24;
+
+/// An API key managed through the API, rather than set with `LABWHERE_API_KEYS`.
+///
+/// Every key has a unique name (usually the integration it is for) and may belong to a user. Keys
+/// can expire, be rotated (the old key keeps working during a grace window) and be revoked, and
+/// record when they were last used.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct ApiKey {
+    /// The unique identifier for the ApiKey
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the ApiKey, used in URLs
+    pub uuid: String,
+    /// The unique name of the ApiKey e.g. `lims`, which its usage is counted under
+    pub name: String,
+    /// The ID of the user the ApiKey belongs to, if any
+    #[serde(skip_serializing)]
+    pub user_id: Option<u32>,
+    /// The SHA-256 digest of the secret sent in the `X-Api-Key` header
+    #[serde(skip_serializing)]
+    pub key_digest: String,
+    /// The digest of the secret the ApiKey had before it was last rotated
+    #[serde(skip_serializing)]
+    pub previous_key_digest: Option<String>,
+    /// The secret itself, which is only known (and shown) when it is created or rotated
+    #[serde(skip_serializing)]
+    #[sqlx(skip)]
+    pub key: Option<String>,
+    /// Until when the previous secret keeps working
+    pub previous_key_expires_at: Option<DateTime<Utc>>,
+    /// When the ApiKey stops working, if ever
+    pub expires_at: Option<DateTime<Utc>>,
+    /// When the ApiKey was revoked
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// When the ApiKey was last used
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// When the ApiKey was created
+    pub created_at: DateTime<Utc>,
+}
+
+/// Implementation of the ApiKey struct
+impl ApiKey {
+    /// Creates an API key, generating its secret, which is only known to the returned `ApiKey`
+    ///
+    /// Returns a `ValidationError` if another key (including one set with `LABWHERE_API_KEYS`) has
+    /// the same name, or if it would expire in the past.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use api_key::ApiKey;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let api_key = ApiKey::create("lims".to_string(), Some(user.id), None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        name: String,
+        user_id: Option<u32>,
+        expires_at: Option<DateTime<Utc>>,
+        connection: &mut SqliteConnection,
+    ) -> Result<ApiKey, Box<dyn Error + Send + Sync>> {
+        ensure_future(expires_at)?;
+        let taken = sqlx::query_scalar::<_, u32>("SELECT COUNT(*) FROM api_keys WHERE name = ?")
+            .bind(&name)
+            .fetch_one(&mut *connection)
+            .await?;
+        if taken > 0 || CONFIG.api_keys.contains_key(&name) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("auth-api-key-name-taken").arg("name", &name),
+            }));
+        }
+
+        let key = new_secret(SECRET_BYTES, &mut *connection).await?;
+        let insert_query_result = sqlx::query(
+            "INSERT INTO api_keys (uuid, name, user_id, key_digest, expires_at)
+                VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(name)
+        .bind(user_id)
+        .bind(secret_digest(&key))
+        .bind(expires_at)
+        .execute(&mut *connection)
+        .await?;
+        let mut api_key =
+            ApiKey::find(insert_query_result.last_insert_rowid() as u32, connection).await?;
+        api_key.key = Some(key);
+        Ok(api_key)
+    }
+
+    /// Lists every API key, ordered by name
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<ApiKey>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys ORDER BY name")
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// Find an API key by its public identifier
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<ApiKey, NotFoundError> {
+        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("auth-api-key-not-found"),
+            })
+    }
+
+    /// Identifies the API key a secret belongs to, by its digest, and records that it was used
+    ///
+    /// Returns `None` if the secret is unknown, or its key is revoked or expired. The previous
+    /// secret of a rotated key is accepted until its grace window ends.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use api_key::ApiKey;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let api_key = ApiKey::authenticate(&secret, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn authenticate(
+        key: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<ApiKey>, sqlx::Error> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            "SELECT * FROM api_keys
+                WHERE (key_digest = ?1
+                        OR (previous_key_digest = ?1 AND previous_key_expires_at > ?2))
+                    AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > ?2)",
+        )
+        .bind(secret_digest(key))
+        .bind(Utc::now())
+        .fetch_optional(&mut *connection)
+        .await?;
+        let Some(mut api_key) = api_key else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        sqlx::query("UPDATE api_keys SET last_used_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(api_key.id)
+            .execute(&mut *connection)
+            .await?;
+        api_key.last_used_at = Some(now);
+        Ok(Some(api_key))
+    }
+
+    /// Changes when an API key expires, or makes it never expire
+    ///
+    /// Returns a `ValidationError` if the key is revoked or the expiry is in the past.
+    pub async fn set_expiry(
+        uuid: &str,
+        expires_at: Option<DateTime<Utc>>,
+        connection: &mut SqliteConnection,
+    ) -> Result<ApiKey, Box<dyn Error + Send + Sync>> {
+        ensure_future(expires_at)?;
+        let api_key = ApiKey::find_active(uuid, &mut *connection).await?;
+        sqlx::query("UPDATE api_keys SET expires_at = ? WHERE id = ?")
+            .bind(expires_at)
+            .bind(api_key.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(ApiKey::find(api_key.id, connection).await?)
+    }
+
+    /// Generates a new secret for an API key, which is only known to the returned `ApiKey`. The
+    /// old secret keeps working for `grace`, so the integration can be switched over.
+    ///
+    /// Returns a `ValidationError` if the key is revoked.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use api_key::ApiKey;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let api_key = ApiKey::rotate(&api_key.uuid, Duration::hours(24), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn rotate(
+        uuid: &str,
+        grace: Duration,
+        connection: &mut SqliteConnection,
+    ) -> Result<ApiKey, Box<dyn Error + Send + Sync>> {
+        let api_key = ApiKey::find_active(uuid, &mut *connection).await?;
+        let key = new_secret(SECRET_BYTES, &mut *connection).await?;
+        sqlx::query(
+            "UPDATE api_keys SET previous_key_digest = key_digest, previous_key_expires_at = ?,
+                key_digest = ? WHERE id = ?",
+        )
+        .bind(Utc::now() + grace)
+        .bind(secret_digest(&key))
+        .bind(api_key.id)
+        .execute(&mut *connection)
+        .await?;
+        let mut api_key = ApiKey::find(api_key.id, connection).await?;
+        api_key.key = Some(key);
+        Ok(api_key)
+    }
+
+    /// Revokes an API key, and its previous secret, for good
+    pub async fn revoke(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<ApiKey, Box<dyn Error + Send + Sync>> {
+        let api_key = ApiKey::find_active(uuid, &mut *connection).await?;
+        sqlx::query("UPDATE api_keys SET revoked_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(api_key.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(ApiKey::find(api_key.id, connection).await?)
+    }
+
+    /// Find an API key which has not been revoked by its public identifier
+    async fn find_active(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<ApiKey, Box<dyn Error + Send + Sync>> {
+        let api_key = ApiKey::find_by_uuid(uuid, connection).await?;
+        if api_key.revoked_at.is_some() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("auth-api-key-revoked").arg("name", &api_key.name),
+            }));
+        }
+        Ok(api_key)
+    }
+
+    /// Find an API key by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<ApiKey, NotFoundError> {
+        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("auth-api-key-not-found"),
+            })
+    }
+}
+
+/// Returns a `ValidationError` if an expiry is in the past.
+fn ensure_future(expires_at: Option<DateTime<Utc>>) -> Result<(), ValidationError> {
+    match expires_at {
+        Some(expires_at) if expires_at <= Utc::now() => Err(ValidationError {
+            message: Message::new("auth-api-key-expiry-past"),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::api_key::*;
+
+    #[tokio::test]
+    async fn test_create_and_authenticate() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let api_key = ApiKey::create("lims".to_string(), None, None, &mut conn)
+            .await
+            .unwrap();
+        let key = api_key.key.clone().unwrap();
+        assert_eq!(key.len(), 48);
+        assert!(api_key.last_used_at.is_none());
+        assert!(serde_json::to_value(&api_key).unwrap().get("key").is_none());
+        // Only the digest of the secret is stored
+        let stored: String = sqlx::query_scalar("SELECT key_digest FROM api_keys")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(stored, secret_digest(&key));
+        assert!(ApiKey::find_by_uuid(&api_key.uuid, &mut conn)
+            .await
+            .unwrap()
+            .key
+            .is_none());
+
+        let used = ApiKey::authenticate(&key, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(used.name, "lims");
+        assert!(used.last_used_at.is_some());
+        assert!(ApiKey::authenticate("guess", &mut conn)
+            .await
+            .unwrap()
+            .is_none());
+
+        let error = ApiKey::create("lims".to_string(), None, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "An API key named lims already exists");
+        let error = ApiKey::create(
+            "robot".to_string(),
+            None,
+            Some(Utc::now() - Duration::hours(1)),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_expiry() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let api_key = ApiKey::create(
+            "lims".to_string(),
+            None,
+            Some(Utc::now() + Duration::days(30)),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        sqlx::query("UPDATE api_keys SET expires_at = ? WHERE id = ?")
+            .bind(Utc::now() - Duration::seconds(1))
+            .bind(api_key.id)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let key = api_key.key.unwrap();
+        assert!(ApiKey::authenticate(&key, &mut conn)
+            .await
+            .unwrap()
+            .is_none());
+
+        ApiKey::set_expiry(&api_key.uuid, None, &mut conn)
+            .await
+            .unwrap();
+        assert!(ApiKey::authenticate(&key, &mut conn)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_and_revoke() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let api_key = ApiKey::create("lims".to_string(), None, None, &mut conn)
+            .await
+            .unwrap();
+        let rotated = ApiKey::rotate(&api_key.uuid, Duration::hours(1), &mut conn)
+            .await
+            .unwrap();
+        let (key, rotated_key) = (api_key.key.unwrap(), rotated.key.unwrap());
+        assert_ne!(rotated_key, key);
+        for key in [&key, &rotated_key] {
+            assert!(ApiKey::authenticate(key, &mut conn)
+                .await
+                .unwrap()
+                .is_some());
+        }
+
+        // Once the grace window is over, only the new secret works
+        ApiKey::rotate(&api_key.uuid, Duration::zero(), &mut conn)
+            .await
+            .unwrap();
+        assert!(ApiKey::authenticate(&rotated_key, &mut conn)
+            .await
+            .unwrap()
+            .is_none());
+
+        let revoked = ApiKey::revoke(&api_key.uuid, &mut conn).await.unwrap();
+        assert!(revoked.revoked_at.is_some());
+        let error = ApiKey::rotate(&api_key.uuid, Duration::hours(1), &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "API key lims has been revoked");
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+use sqlx::SqliteConnection;
+
+/// How many days of usage are kept for each API key.
+pub const USAGE_HISTORY_DAYS: u32 = 30;
+
+/// The requests made with an API key on one day (UTC).
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct ApiKeyUsage {
+    /// The name of the API key
+    pub api_key: String,
+    /// The day the requests were made on
+    pub day: NaiveDate,
+    /// The number of requests which were let through
+    pub requests: u32,
+    /// The number of requests which were rejected because the daily quota was used up
+    pub rejected: u32,
+}
+
+/// Implementation of the ApiKeyUsage struct
+impl ApiKeyUsage {
+    /// Counts a request made with an API key today. Returns `false`, counting the request as
+    /// rejected, if the key has already made `quota` requests today.
+    ///
+    /// The check and the count happen in one statement, so concurrent requests cannot exceed the
+    /// quota.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use api_key_usage::ApiKeyUsage;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let allowed = ApiKeyUsage::record("lims", Some(1000), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn record(
+        api_key: &str,
+        quota: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<bool, sqlx::Error> {
+        let quota = quota.unwrap_or(u32::MAX);
+        let counted = sqlx::query(
+            "INSERT INTO api_key_usage (api_key, day, requests)
+                SELECT ?1, date('now'), 1 WHERE ?2 > 0
+                ON CONFLICT (api_key, day) DO UPDATE SET requests = requests + 1
+                    WHERE requests < ?2",
+        )
+        .bind(api_key)
+        .bind(quota)
+        .execute(&mut *connection)
+        .await?
+        .rows_affected();
+        if counted > 0 {
+            return Ok(true);
+        }
+
+        sqlx::query(
+            "INSERT INTO api_key_usage (api_key, day, rejected) VALUES (?, date('now'), 1)
+                ON CONFLICT (api_key, day) DO UPDATE SET rejected = rejected + 1",
+        )
+        .bind(api_key)
+        .execute(&mut *connection)
+        .await?;
+        Ok(false)
+    }
+
+    /// Lists the usage of an API key over the last `USAGE_HISTORY_DAYS` days, newest first.
+    /// Days without requests are left out.
+    pub async fn history(
+        api_key: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<ApiKeyUsage>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKeyUsage>(
+            "SELECT * FROM api_key_usage WHERE api_key = ? AND day > date('now', ?)
+                ORDER BY day DESC",
+        )
+        .bind(api_key)
+        .bind(format!("-{} days", USAGE_HISTORY_DAYS))
+        .fetch_all(&mut *connection)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::api_key_usage::*;
+
+    #[tokio::test]
+    async fn test_record_within_quota() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        assert!(ApiKeyUsage::record("lims", Some(2), &mut conn)
+            .await
+            .unwrap());
+        assert!(ApiKeyUsage::record("lims", Some(2), &mut conn)
+            .await
+            .unwrap());
+        assert!(!ApiKeyUsage::record("lims", Some(2), &mut conn)
+            .await
+            .unwrap());
+        assert!(ApiKeyUsage::record("robot", None, &mut conn).await.unwrap());
+
+        let history = ApiKeyUsage::history("lims", &mut conn).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].requests, history[0].rejected), (2, 1));
+        assert_eq!(history[0].day, chrono::Utc::now().date_naive());
+    }
+
+    #[tokio::test]
+    async fn test_record_with_zero_quota() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        assert!(!ApiKeyUsage::record("lims", Some(0), &mut conn)
+            .await
+            .unwrap());
+        let history = ApiKeyUsage::history("lims", &mut conn).await.unwrap();
+        assert_eq!((history[0].requests, history[0].rejected), (0, 1));
+    }
+}
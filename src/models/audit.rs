@@ -0,0 +1,473 @@
+use crate::config::CONFIG;
+use crate::models::new_uuid;
+use crate::models::subscription::Subscription;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, SqliteConnection};
+use std::fmt;
+
+/// An audit records an action performed on a location or a labware.
+///
+/// Every audit is tied to the location the action happened in, so that the activity within a
+/// location (and everything beneath it) can be reviewed in one query.
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Audit {
+    /// The unique identifier for the Audit
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Audit, used in URLs and event payloads
+    pub uuid: String,
+    /// The type of the audited record e.g. `Location` or `Labware`
+    pub auditable_type: String,
+    /// The ID of the audited record
+    pub auditable_id: u32,
+    /// The action that was performed e.g. `create` or `update`
+    pub action: String,
+    /// The location the action happened in. For a labware this is the location it is in after
+    /// the action.
+    pub location_id: Option<u32>,
+    /// A JSON snapshot of the record after the action
+    #[serde(serialize_with = "serialize_record_data")]
+    pub record_data: String,
+    /// When the action was performed
+    pub created_at: DateTime<Utc>,
+    /// The admin who performed the action while acting as another user, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub real_user: Option<String>,
+    /// The user the admin was acting as, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_user: Option<String>,
+}
+
+/// The outcome of checking the hash chain of the audits (see `Audit::verify_chain`).
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ChainVerification {
+    /// How many audits are in the chain and were checked
+    pub checked: usize,
+    /// How many audits are not in the chain, e.g. those recorded before it was turned on
+    pub unsealed: usize,
+    /// The first audit which was changed after it was recorded, or which does not follow the
+    /// audit before it in the chain, e.g. because that one was deleted, if any
+    pub broken_at: Option<ChainBreak>,
+}
+
+/// An audit where the hash chain breaks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainBreak {
+    /// The id of the audit
+    pub audit_id: u32,
+    /// The public identifier of the audit
+    pub uuid: String,
+    /// Whether the content of the audit no longer matches its hash, rather than the audit not
+    /// following the audit before it
+    pub modified: bool,
+}
+
+impl fmt::Display for ChainVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} audits checked, {} not in the chain",
+            self.checked, self.unsealed
+        )?;
+        match &self.broken_at {
+            Some(broken) if broken.modified => write!(
+                f,
+                "; audit {} ({}) was modified after it was recorded",
+                broken.audit_id, broken.uuid
+            ),
+            Some(broken) => write!(
+                f,
+                "; audit {} ({}) does not follow the audit before it",
+                broken.audit_id, broken.uuid
+            ),
+            None => write!(f, "; the chain is intact"),
+        }
+    }
+}
+
+/// An audit as stored, with the hashes sealing it into the chain.
+#[derive(Debug, sqlx::FromRow)]
+struct SealedAudit {
+    id: u32,
+    uuid: String,
+    auditable_type: String,
+    auditable_id: u32,
+    action: String,
+    location_id: Option<u32>,
+    record_data: String,
+    /// As stored, so the hash does not depend on how timestamps are parsed
+    created_at: String,
+    origin: Option<String>,
+    real_user: Option<String>,
+    effective_user: Option<String>,
+    previous_hash: Option<String>,
+    hash: Option<String>,
+}
+
+impl SealedAudit {
+    /// Reads the audits after an id, oldest first.
+    async fn after(
+        audit_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<SealedAudit>, sqlx::Error> {
+        sqlx::query_as::<_, SealedAudit>(
+            "SELECT id, uuid, auditable_type, auditable_id, action, location_id, record_data,
+                CAST(created_at AS TEXT) AS created_at, origin, real_user, effective_user,
+                previous_hash, hash
+                FROM audits WHERE id > ? ORDER BY id",
+        )
+        .bind(audit_id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// The SHA-256 of the content of the audit and the hash of the audit before it in the chain,
+    /// in hex.
+    fn content_hash(&self, previous_hash: &str) -> String {
+        let mut content = serde_json::json!([
+            previous_hash,
+            self.uuid,
+            self.auditable_type,
+            self.auditable_id,
+            self.action,
+            self.location_id,
+            self.record_data,
+            self.created_at,
+            self.origin,
+        ]);
+        // Only audits of an admin acting as another user seal who they were, so the hashes of
+        // every other audit stay as they were
+        if let (Some(array), Some(real_user)) = (content.as_array_mut(), &self.real_user) {
+            array.push(real_user.as_str().into());
+            array.push(self.effective_user.as_deref().into());
+        }
+        Sha256::digest(content.to_string())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Serializes the stored JSON snapshot as JSON rather than as a string.
+fn serialize_record_data<S: Serializer>(
+    record_data: &str,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match serde_json::from_str::<serde_json::Value>(record_data) {
+        Ok(value) => value.serialize(serializer),
+        Err(_) => serializer.serialize_str(record_data),
+    }
+}
+
+/// Implementation of the Audit struct
+impl Audit {
+    /// Records an action performed on a record, queueing it for the subscribers to its location.
+    ///
+    /// If `LABWHERE_AUDIT_HASH_CHAIN` is set, the audit is sealed into the hash chain (see
+    /// `Audit::seal_after`).
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use audit::Audit;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let audit = Audit::create("Labware", labware.id, "create", Some(labware.location_id), &labware, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create<T: Serialize>(
+        auditable_type: &str,
+        auditable_id: u32,
+        action: &str,
+        location_id: Option<u32>,
+        record: &T,
+        connection: &mut SqliteConnection,
+    ) -> Result<Audit, sqlx::Error> {
+        let record_data =
+            serde_json::to_string(record).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+        // The audit is inserted and sealed at once, so no other audit can slip in between
+        let mut transaction = connection.begin().await?;
+        let insert_query_result = sqlx::query(
+            "INSERT INTO audits (uuid, auditable_type, auditable_id, action, location_id, record_data)
+                VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(auditable_type)
+        .bind(auditable_id)
+        .bind(action)
+        .bind(location_id)
+        .bind(record_data)
+        .execute(&mut *transaction)
+        .await?;
+        let id = insert_query_result.last_insert_rowid();
+        if CONFIG.audit_hash_chain {
+            Audit::seal_after(id as u32 - 1, &mut transaction).await?;
+        }
+        if let Some(location_id) = location_id {
+            Subscription::fan_out(id as u32, location_id, &mut transaction).await?;
+        }
+
+        let audit = sqlx::query_as::<_, Audit>("SELECT * FROM audits WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(audit)
+    }
+
+    /// Seals the audits after an id into the hash chain: each stores the hash of the audit before
+    /// it in the chain as `previous_hash`, and the hash of its own content and `previous_hash` as
+    /// `hash`, so an audit which is modified or deleted afterwards breaks the chain.
+    ///
+    /// Audits which are completed after they are created, e.g. backdated to when a synced change
+    /// was made on its origin, are sealed again within the same transaction. Only audits already in
+    /// the chain are sealed again, so this does nothing if the chain is not turned on.
+    pub async fn reseal_after(
+        audit_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        let sealed = sqlx::query_scalar::<_, Option<u32>>(
+            "SELECT MIN(id) FROM audits WHERE id > ? AND hash IS NOT NULL",
+        )
+        .bind(audit_id)
+        .fetch_one(&mut *connection)
+        .await?;
+        match sealed {
+            Some(_) => Audit::seal_after(audit_id, connection).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Seals every audit after an id into the hash chain, following the last audit before them
+    /// which is in the chain, if any.
+    pub async fn seal_after(
+        audit_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        let mut previous_hash = sqlx::query_scalar::<_, String>(
+            "SELECT hash FROM audits WHERE id <= ? AND hash IS NOT NULL ORDER BY id DESC LIMIT 1",
+        )
+        .bind(audit_id)
+        .fetch_optional(&mut *connection)
+        .await?
+        .unwrap_or_default();
+        for audit in SealedAudit::after(audit_id, &mut *connection).await? {
+            let hash = audit.content_hash(&previous_hash);
+            sqlx::query("UPDATE audits SET previous_hash = ?, hash = ? WHERE id = ?")
+                .bind(&previous_hash)
+                .bind(&hash)
+                .bind(audit.id)
+                .execute(&mut *connection)
+                .await?;
+            previous_hash = hash;
+        }
+        Ok(())
+    }
+
+    /// Checks the hash chain of the audits, oldest first, stopping at the first audit which was
+    /// modified after it was sealed or does not follow the audit before it in the chain.
+    ///
+    /// Audits which are not in the chain are counted but not checked. Deleting the latest audits
+    /// cannot be told apart from them never having been recorded.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use audit::Audit;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let verification = Audit::verify_chain(&mut connection).await.unwrap();
+    /// assert_eq!(verification.broken_at, None);
+    /// # }
+    /// ```
+    pub async fn verify_chain(
+        connection: &mut SqliteConnection,
+    ) -> Result<ChainVerification, sqlx::Error> {
+        let mut verification = ChainVerification::default();
+        let mut last_hash = String::new();
+        for audit in SealedAudit::after(0, &mut *connection).await? {
+            let (Some(previous_hash), Some(hash)) = (&audit.previous_hash, &audit.hash) else {
+                verification.unsealed += 1;
+                continue;
+            };
+            let modified = audit.content_hash(previous_hash) != *hash;
+            if modified || *previous_hash != last_hash {
+                verification.broken_at = Some(ChainBreak {
+                    audit_id: audit.id,
+                    uuid: audit.uuid.clone(),
+                    modified,
+                });
+                break;
+            }
+            verification.checked += 1;
+            last_hash = hash.clone();
+        }
+        Ok(verification)
+    }
+
+    /// Lists the audits of a location, newest first
+    ///
+    /// If `include_descendants` is set, the audits of every location beneath it (at any depth) and
+    /// of the labwares in them are included as well.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use audit::Audit;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let audits = Audit::for_location(freezer.id, true, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn for_location(
+        location_id: u32,
+        include_descendants: bool,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Audit>, sqlx::Error> {
+        sqlx::query_as::<_, Audit>(
+            "WITH RECURSIVE subtree(id) AS (
+                SELECT ?1
+                UNION
+                SELECT locations.id FROM locations
+                    JOIN subtree ON locations.parent_id = subtree.id
+                    WHERE ?2
+            )
+            SELECT * FROM audits WHERE location_id IN (SELECT id FROM subtree)
+                ORDER BY id DESC",
+        )
+        .bind(location_id)
+        .bind(include_descendants)
+        .fetch_all(&mut *connection)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::audit::*;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+
+    #[tokio::test]
+    async fn test_create_audit() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let record = serde_json::json!({ "barcode": "lw-1" });
+        let audit = Audit::create("Labware", 1, "create", None, &record, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audit.auditable_type, "Labware");
+        assert_eq!(audit.auditable_id, 1);
+        assert_eq!(audit.action, "create");
+        assert_eq!(audit.record_data, r#"{"barcode":"lw-1"}"#);
+
+        let json = serde_json::to_value(&audit).unwrap();
+        assert_eq!(json["record_data"]["barcode"], "lw-1");
+    }
+
+    #[tokio::test]
+    async fn test_audits_for_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create_with_parent(
+            "shelf".to_string(),
+            location_type.id,
+            Some(freezer.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let rack = Location::create_with_parent(
+            "rack".to_string(),
+            location_type.id,
+            Some(shelf.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let other = Location::create("other".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), rack.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), other.id, &mut conn)
+            .await
+            .unwrap();
+
+        let audits = Audit::for_location(freezer.id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits.len(), 1);
+        assert_eq!(audits[0].auditable_id, freezer.id);
+
+        let audits = Audit::for_location(freezer.id, true, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits.len(), 4);
+        assert_eq!(audits[0].auditable_type, "Labware");
+        assert_eq!(audits[1].auditable_id, rack.id);
+        assert_eq!(audits[2].auditable_id, shelf.id);
+        assert_eq!(audits[3].auditable_id, freezer.id);
+
+        let audits = Audit::for_location(shelf.id, true, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_verify_chain() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let record = serde_json::json!({ "barcode": "lw-1" });
+        // Audits recorded before the chain was turned on are left out of it
+        Audit::create("Labware", 1, "create", None, &record, &mut conn)
+            .await
+            .unwrap();
+        for action in ["update", "exhaust", "reactivate"] {
+            let audit = Audit::create("Labware", 1, action, None, &record, &mut conn)
+                .await
+                .unwrap();
+            Audit::seal_after(audit.id - 1, &mut conn).await.unwrap();
+        }
+        let verification = Audit::verify_chain(&mut conn).await.unwrap();
+        assert_eq!(verification.checked, 3);
+        assert_eq!(verification.unsealed, 1);
+        assert_eq!(verification.broken_at, None);
+
+        // Completing an audit within its transaction keeps the chain intact
+        sqlx::query("UPDATE audits SET created_at = '2024-01-01 00:00:00' WHERE id > 3")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        Audit::reseal_after(3, &mut conn).await.unwrap();
+        assert_eq!(
+            Audit::verify_chain(&mut conn).await.unwrap().broken_at,
+            None
+        );
+
+        sqlx::query("UPDATE audits SET action = 'destroy' WHERE id = 3")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let verification = Audit::verify_chain(&mut conn).await.unwrap();
+        let broken_at = verification.broken_at.clone().unwrap();
+        assert_eq!((broken_at.audit_id, broken_at.modified), (3, true));
+        assert!(verification
+            .to_string()
+            .ends_with("was modified after it was recorded"));
+
+        sqlx::query("DELETE FROM audits WHERE id = 3")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let broken_at = Audit::verify_chain(&mut conn)
+            .await
+            .unwrap()
+            .broken_at
+            .unwrap();
+        assert_eq!((broken_at.audit_id, broken_at.modified), (4, false));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::config::CONFIG;
+use crate::errors::domain_message;
+use crate::i18n::Message;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::location::Location;
+use crate::models::location_flag::LocationFlag;
+use crate::models::location_lock::LocationLock;
+use crate::models::pending_labware::RegistrationPolicy;
+use crate::models::reservation::Reservation;
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// What a checked barcode turned out to be.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BarcodeKind {
+    Labware,
+    Location,
+    Unknown,
+}
+
+/// What is known about a barcode, and why it could not be scanned into the target location.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BarcodeCheck {
+    /// The barcode as it was sent
+    pub barcode: String,
+    /// Whether the barcode is a labware, a location or neither
+    pub kind: BarcodeKind,
+    /// The status of the labware, for labware barcodes
+    pub status: Option<LabwareStatus>,
+    /// The name of the location the labware is in, for labware barcodes
+    pub location: Option<String>,
+    /// Why the barcode cannot be scanned. Empty if it can.
+    #[serde(skip)]
+    pub problems: Vec<Message>,
+}
+
+/// The barcodes a client is about to scan, checked without changing anything.
+///
+/// Each barcode is parsed and looked up. When a target location is given, the checks a scan into
+/// it would make are run as well: the location is not quarantined, locked or reserved, and each
+/// barcode is an active labware which can leave where it is, or a labware the registration policy
+/// of the location accepts (a new barcode, or one whose labware was exhausted, destroyed or shipped
+/// out).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BarcodeValidation {
+    /// The name of the target location, if one was given
+    pub location: Option<String>,
+    /// Why nothing can be scanned into the target location. Empty if labwares can be.
+    #[serde(skip)]
+    pub location_problems: Vec<Message>,
+    /// The checks of each barcode, in the order they were sent
+    pub barcodes: Vec<BarcodeCheck>,
+}
+
+/// Implementation of the BarcodeValidation struct
+impl BarcodeValidation {
+    /// Checks barcodes, and whether they could be scanned into a location if one is given
+    ///
+    /// Returns a `NotFoundError` if the target location does not exist.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use barcode_validation::BarcodeValidation;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let validation = BarcodeValidation::check(vec!["lw-1".to_string()], Some("lw-freezer-1".to_string()), None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn check(
+        barcodes: Vec<String>,
+        location_barcode: Option<String>,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<BarcodeValidation, Box<dyn Error + Send + Sync>> {
+        let target = match location_barcode {
+            Some(barcode) => Some(Location::find_by_barcode(barcode, &mut *connection).await?),
+            None => None,
+        };
+        let mut location_problems = vec![];
+        let mut location_type = None;
+        if let Some(target) = &target {
+            let checks = [
+                LocationFlag::ensure_not_quarantined(target, &mut *connection).await,
+                LocationLock::ensure_unlocked(target.id, lock_token, &mut *connection).await,
+                Reservation::ensure_fillable(target.id, None, lock_token, &mut *connection).await,
+            ];
+            for check in checks {
+                location_problems.extend(problem(check)?);
+            }
+            location_type = Some(
+                sqlx::query_scalar::<_, String>(
+                    "SELECT t.name FROM location_types t
+                        JOIN locations l ON l.location_type_id = t.id WHERE l.id = ?",
+                )
+                .bind(target.id)
+                .fetch_one(&mut *connection)
+                .await?,
+            );
+        }
+
+        let parser = BarcodeParser::new(&CONFIG);
+        let mut checks = vec![];
+        for barcode in barcodes {
+            let mut check = BarcodeCheck {
+                barcode: barcode.clone(),
+                kind: BarcodeKind::Unknown,
+                status: None,
+                location: None,
+                problems: vec![],
+            };
+            let parsed = match parser.parse(&barcode) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    check.problems.push(e.message);
+                    checks.push(check);
+                    continue;
+                }
+            };
+            if Location::find_by_barcode(parsed.barcode.clone(), &mut *connection)
+                .await
+                .is_ok()
+            {
+                check.kind = BarcodeKind::Location;
+                if target.is_some() {
+                    check
+                        .problems
+                        .push(Message::new("barcode-validation-location").arg("barcode", &barcode));
+                }
+            } else {
+                let labware = Labware::find_with_history(&parsed.barcode, &mut *connection).await;
+                let active = labware
+                    .as_ref()
+                    .is_ok_and(|labware| labware.status == LabwareStatus::Active);
+                if let Ok(labware) = &labware {
+                    check.kind = BarcodeKind::Labware;
+                    check.status = Some(labware.status);
+                    check.location = Some(
+                        sqlx::query_scalar::<_, String>("SELECT name FROM locations WHERE id = ?")
+                            .bind(labware.location_id)
+                            .fetch_one(&mut *connection)
+                            .await?,
+                    );
+                }
+                match (&target, &location_type, labware) {
+                    (Some(_), _, Ok(labware)) if active => {
+                        check.problems.extend(problem(
+                            LocationLock::ensure_unlocked(
+                                labware.location_id,
+                                lock_token,
+                                &mut *connection,
+                            )
+                            .await,
+                        )?);
+                    }
+                    // A barcode which is new, or whose labware is gone, is registered by the scan
+                    (Some(target), Some(location_type), _) => {
+                        let policy = CONFIG
+                            .registration_policy(location_type, parsed.site_prefix.as_deref());
+                        if matches!(policy, RegistrationPolicy::Reject) {
+                            check.problems.push(
+                                Message::new("scan-labware-not-registered")
+                                    .arg("barcode", &parsed.barcode)
+                                    .arg("location", &target.name),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            checks.push(check);
+        }
+        Ok(BarcodeValidation {
+            location: target.map(|target| target.name),
+            location_problems,
+            barcodes: checks,
+        })
+    }
+
+    /// Whether every barcode could be scanned into the target location
+    pub fn valid(&self) -> bool {
+        self.location_problems.is_empty()
+            && self.barcodes.iter().all(|check| check.problems.is_empty())
+    }
+}
+
+/// The message of a check which failed because of a rule of the domain, or the error if the check
+/// could not be made at all.
+fn problem(
+    check: Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<Option<Message>, Box<dyn Error + Send + Sync>> {
+    match check {
+        Ok(()) => Ok(None),
+        Err(e) => match domain_message(&*e) {
+            Some(message) => Ok(Some(message.clone())),
+            None => Err(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::barcode_validation::*;
+    use crate::models::location_type::LocationType;
+
+    #[tokio::test]
+    async fn test_check() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer1 = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let freezer2 = Location::create("freezer2".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), freezer1.id, &mut conn)
+            .await
+            .unwrap()
+            .change_status(LabwareStatus::Exhausted, None, None, &mut conn)
+            .await
+            .unwrap();
+        let barcodes = vec![
+            "lw-1".to_string(),
+            "lw-2".to_string(),
+            "lw-3".to_string(),
+            freezer1.barcode.clone().unwrap(),
+        ];
+
+        let validation = BarcodeValidation::check(barcodes.clone(), None, None, &mut conn)
+            .await
+            .unwrap();
+        assert!(validation.valid());
+        let kinds: Vec<_> = validation.barcodes.iter().map(|check| check.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                BarcodeKind::Labware,
+                BarcodeKind::Labware,
+                BarcodeKind::Unknown,
+                BarcodeKind::Location
+            ]
+        );
+        assert_eq!(validation.barcodes[0].location.as_deref(), Some("freezer1"));
+
+        LocationLock::acquire(freezer2.id, "jd12".to_string(), 60, &mut conn)
+            .await
+            .unwrap();
+        let validation =
+            BarcodeValidation::check(barcodes, freezer2.barcode.clone(), None, &mut conn)
+                .await
+                .unwrap();
+        assert!(!validation.valid());
+        assert_eq!(validation.location_problems.len(), 1);
+        let problems: Vec<_> = validation
+            .barcodes
+            .iter()
+            .map(|check| check.problems.len())
+            .collect();
+        assert_eq!(problems, vec![0, 0, 0, 1]);
+        assert_eq!(
+            validation.barcodes[1].status,
+            Some(LabwareStatus::Exhausted)
+        );
+        assert_eq!(
+            validation.barcodes[3].problems[0].to_string(),
+            format!("{} is a location, not a labware", freezer1.barcode.unwrap())
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use crate::config::Config;
+use crate::notifications::{Notification, Trigger};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+
+/// A location which has become fuller than its capacity threshold.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CapacityAlert {
+    /// The name of the location
+    pub location: String,
+    /// The barcode of the location
+    pub barcode: Option<String>,
+    /// The number of labwares in the wells of the location and the locations beneath it
+    pub occupied: u32,
+    /// The number of wells in the location and the locations beneath it
+    pub capacity: u32,
+    /// How full the location is, in percent
+    pub percent: u32,
+    /// The threshold it crossed, in percent
+    pub threshold: u32,
+    /// The user or team responsible for the location, who is notified of the alert
+    pub owner: Option<String>,
+    /// The contact email of the location, which is notified of the alert
+    pub contact_email: Option<String>,
+}
+
+/// The occupancy of a location, counting everything beneath it.
+#[derive(Debug, sqlx::FromRow)]
+struct LocationOccupancy {
+    id: u32,
+    name: String,
+    barcode: Option<String>,
+    location_type: String,
+    owner: Option<String>,
+    contact_email: Option<String>,
+    capacity: u32,
+    occupied: u32,
+    alerted: bool,
+}
+
+/// Implementation of the CapacityAlert struct
+impl CapacityAlert {
+    /// Compares the occupancy of every location which has a threshold against it, returning an
+    /// alert for each location which has crossed its threshold since the last check
+    ///
+    /// Once a location has raised an alert it does not raise another until its occupancy has
+    /// dropped `capacity_hysteresis` percent below its threshold, so labwares being scanned in and
+    /// out near the threshold raise one alert rather than one per scan.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use capacity_alert::CapacityAlert;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let alerts = CapacityAlert::check(&CONFIG, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn check(
+        config: &Config,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<CapacityAlert>, sqlx::Error> {
+        if config.capacity_thresholds.is_empty() && config.location_capacity_thresholds.is_empty() {
+            return Ok(vec![]);
+        }
+        let occupancies = occupancies(None, &mut *connection).await?;
+
+        let mut alerts = vec![];
+        for occupancy in occupancies {
+            let Some(threshold) = occupancy.threshold(config) else {
+                continue;
+            };
+            let percent = occupancy.occupied * 100 / occupancy.capacity;
+            if percent >= threshold && !occupancy.alerted {
+                sqlx::query(
+                    "INSERT INTO capacity_alerts (location_id, percent, threshold) VALUES (?, ?, ?)",
+                )
+                .bind(occupancy.id)
+                .bind(percent)
+                .bind(threshold)
+                .execute(&mut *connection)
+                .await?;
+                alerts.push(CapacityAlert {
+                    location: occupancy.name,
+                    barcode: occupancy.barcode,
+                    occupied: occupancy.occupied,
+                    capacity: occupancy.capacity,
+                    percent,
+                    threshold,
+                    owner: occupancy.owner,
+                    contact_email: occupancy.contact_email,
+                });
+            } else if occupancy.alerted
+                && percent < threshold.saturating_sub(config.capacity_hysteresis)
+            {
+                sqlx::query("DELETE FROM capacity_alerts WHERE location_id = ?")
+                    .bind(occupancy.id)
+                    .execute(&mut *connection)
+                    .await?;
+            }
+        }
+        Ok(alerts)
+    }
+
+    /// The locations which are at or over their threshold among a location and those it is inside
+    /// of, innermost first, whether or not they have raised an alert
+    ///
+    /// Nothing is recorded, so the alerts are still raised by the next `check`.
+    pub async fn over_threshold(
+        location_id: u32,
+        config: &Config,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<CapacityAlert>, sqlx::Error> {
+        if config.capacity_thresholds.is_empty() && config.location_capacity_thresholds.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut alerts = vec![];
+        for occupancy in occupancies(Some(location_id), connection).await? {
+            let Some(threshold) = occupancy.threshold(config) else {
+                continue;
+            };
+            let percent = occupancy.occupied * 100 / occupancy.capacity;
+            if percent >= threshold {
+                alerts.push(CapacityAlert {
+                    location: occupancy.name,
+                    barcode: occupancy.barcode,
+                    occupied: occupancy.occupied,
+                    capacity: occupancy.capacity,
+                    percent,
+                    threshold,
+                    owner: occupancy.owner,
+                    contact_email: occupancy.contact_email,
+                });
+            }
+        }
+        Ok(alerts)
+    }
+
+    /// The notification sent about the alert, to the teams configured for `capacity-threshold` and
+    /// to the owner and contact of the location
+    pub fn notification(&self) -> Notification {
+        Notification::new(Trigger::CapacityThreshold)
+            .arg("location", &self.location)
+            .arg("percent", self.percent)
+            .arg("count", self.occupied)
+            .arg("capacity", self.capacity)
+            .arg("threshold", self.threshold)
+            .owned_by(self.owner.as_deref(), self.contact_email.as_deref())
+    }
+}
+
+impl LocationOccupancy {
+    /// The threshold of the location, or else of its type, if either has one
+    fn threshold(&self, config: &Config) -> Option<u32> {
+        self.barcode
+            .as_ref()
+            .and_then(|barcode| config.location_capacity_thresholds.get(barcode))
+            .or_else(|| config.capacity_thresholds.get(&self.location_type))
+            .copied()
+    }
+}
+
+/// The occupancy of every location which has wells beneath it, or with a `location_id` only of
+/// that location and those it is inside of, innermost first.
+async fn occupancies(
+    location_id: Option<u32>,
+    connection: &mut SqliteConnection,
+) -> Result<Vec<LocationOccupancy>, sqlx::Error> {
+    sqlx::query_as::<_, LocationOccupancy>(
+        "WITH RECURSIVE above(id, depth) AS (
+            SELECT ?1, 0
+            UNION ALL
+            SELECT locations.parent_id, above.depth + 1 FROM locations
+                JOIN above ON locations.id = above.id
+                WHERE locations.parent_id IS NOT NULL
+        ),
+        below(ancestor_id, id) AS (
+            SELECT id, id FROM locations
+                WHERE ?1 IS NULL OR id IN (SELECT id FROM above)
+            UNION ALL
+            SELECT below.ancestor_id, locations.id FROM locations
+                JOIN below ON locations.parent_id = below.id
+        )
+        SELECT a.id, a.name, a.barcode, t.name AS location_type, a.owner, a.contact_email,
+            COALESCE(SUM(l.rows * l.columns), 0) AS capacity,
+            COALESCE(SUM(CASE WHEN l.rows * l.columns > 0 THEN l.labwares_count END), 0)
+                AS occupied,
+            EXISTS (SELECT 1 FROM capacity_alerts WHERE location_id = a.id) AS alerted
+            FROM below
+            JOIN locations a ON a.id = below.ancestor_id
+            JOIN location_types t ON t.id = a.location_type_id
+            JOIN locations l ON l.id = below.id
+            GROUP BY a.id HAVING capacity > 0
+            ORDER BY (SELECT MIN(depth) FROM above WHERE above.id = a.id), a.id",
+    )
+    .bind(location_id)
+    .fetch_all(&mut *connection)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::capacity_alert::*;
+    use crate::models::labware::Labware;
+    use crate::models::layout::Layout;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_check_with_hysteresis() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Rack".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let rack = Location::create("rack1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Layout::resize(&rack, 1, 10, &mut conn).await.unwrap();
+        rack.update_ownership(None, Some("racks@example.com".to_string()), None, &mut conn)
+            .await
+            .unwrap();
+        let config = Config {
+            capacity_thresholds: HashMap::from([("Rack".to_string(), 80)]),
+            capacity_hysteresis: 10,
+            ..Default::default()
+        };
+        let mut labwares = vec![];
+        for i in 0..8 {
+            labwares.push(
+                Labware::create(format!("lw-{}", i), rack.id, &mut conn)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let alerts = CapacityAlert::check(&config, &mut conn).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].percent, 80);
+        assert_eq!(
+            alerts[0].notification().subject().to_string(),
+            "rack1 is 80% full"
+        );
+        assert_eq!(
+            alerts[0].notification().recipients(&config),
+            vec!["racks@example.com"]
+        );
+        // Still over the threshold, so no new alert
+        assert!(CapacityAlert::check(&config, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Dropping to 70% is within the hysteresis, so going back to 80% does not alert again
+        let other = Location::create("bench".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut labware = labwares.pop().unwrap();
+        labware.location_id = other.id;
+        Labware::update(&labware, None, &mut conn).await.unwrap();
+        assert!(CapacityAlert::check(&config, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+        labware.location_id = rack.id;
+        Labware::update(&labware, None, &mut conn).await.unwrap();
+        assert!(CapacityAlert::check(&config, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Dropping below 70% re-arms the alert
+        for mut labware in labwares.drain(..2) {
+            labware.location_id = other.id;
+            Labware::update(&labware, None, &mut conn).await.unwrap();
+        }
+        assert!(CapacityAlert::check(&config, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+        for i in 8..10 {
+            Labware::create(format!("lw-{}", i), rack.id, &mut conn)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            CapacityAlert::check(&config, &mut conn)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,707 @@
+use crate::cache::{location_key, NOT_FOUND_BARCODES};
+use crate::errors::{LockedError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::location::Location;
+use crate::models::location_type::LocationType;
+use crate::models::sync_conflict::{ConflictStatus, SyncConflict};
+use crate::timestamps::STORAGE_FORMAT;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// A change made on one instance which other instances can apply, read from its audit.
+///
+/// Ids are local to each instance, so records are referred to by their UUID (and barcode).
+/// A change keeps the UUID of the audit it was recorded in and the name of the instance it was
+/// made on, so it is only applied once and never sent back to where it came from.
+///
+/// Labwares being created, moved and exhausted and locations being created are synced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// The UUID of the audit the change was recorded in
+    pub uuid: String,
+    /// The name of the instance the change was made on
+    pub origin: String,
+    /// The type of the changed record, `Labware` or `Location`
+    pub record_type: String,
+    /// What was done to it e.g. `create`, `update` or `exhaust`
+    pub action: String,
+    /// The UUID of the changed record
+    pub record_uuid: String,
+    /// The barcode of the changed record
+    pub barcode: Option<String>,
+    /// The UUID of the location a labware is in, or of the parent of a location
+    pub location_uuid: Option<String>,
+    /// The UUID of the location a labware was in before it was moved, as the instance the change
+    /// was made on knew it
+    #[serde(default)]
+    pub previous_location_uuid: Option<String>,
+    /// The name of a location
+    pub name: Option<String>,
+    /// The name of the type of a location
+    pub location_type: Option<String>,
+    /// When the change was made
+    pub created_at: DateTime<Utc>,
+}
+
+/// A page of the changes made on an instance, oldest first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeFeed {
+    /// The changes
+    pub changes: Vec<Change>,
+    /// Where the next page starts, to be sent back as `since`
+    pub cursor: u32,
+}
+
+/// Whose changes are read from the feed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origins<'a> {
+    /// Only the changes made on this instance
+    Local,
+    /// The changes made anywhere except on the named instance, which already has them
+    Except(&'a str),
+}
+
+/// How a change to a labware which was also changed here since is resolved.
+///
+/// A change conflicts when the instance it was made on had the labware somewhere other than where
+/// it is here, i.e. both moved it while they were apart, or when the labware changed here after it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ConflictPolicy {
+    /// The latest of the two changes wins. The conflict is logged, already resolved.
+    #[default]
+    LastWriterWins,
+    /// Neither wins until an admin resolves the conflict, which is queued for review.
+    ManualReview,
+}
+
+impl ConflictPolicy {
+    /// Parses a conflict policy from its name e.g. `manual-review`.
+    pub fn from_name(name: &str) -> Option<ConflictPolicy> {
+        match name.trim().to_lowercase().as_str() {
+            "last-writer-wins" => Some(ConflictPolicy::LastWriterWins),
+            "manual-review" => Some(ConflictPolicy::ManualReview),
+            _ => None,
+        }
+    }
+}
+
+/// A change which could not be applied, e.g. because the location it refers to is not known here.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    /// The UUID of the change
+    pub uuid: String,
+    /// Why it could not be applied
+    pub message: Message,
+}
+
+/// What came of applying a batch of changes.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SyncReport {
+    /// How many changes were applied
+    pub applied: u32,
+    /// How many changes were applied before, or lost a conflict to a later change
+    pub skipped: u32,
+    /// How many changes conflict and were queued for review
+    pub queued: u32,
+    /// The changes which could not be applied
+    pub rejected: Vec<Rejection>,
+}
+
+/// An audit in the feed, with the UUID of the location it happened in.
+#[derive(Debug, sqlx::FromRow)]
+struct FeedRow {
+    id: u32,
+    uuid: String,
+    origin: String,
+    auditable_type: String,
+    action: String,
+    record_data: String,
+    created_at: DateTime<Utc>,
+    location_uuid: Option<String>,
+    previous_location_uuid: Option<String>,
+}
+
+/// What came of applying a single change.
+enum Outcome {
+    Applied,
+    Skipped,
+    Queued,
+    Rejected(Message),
+}
+
+/// How conflicts are handled while applying a change.
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    /// Conflicts are resolved by the policy
+    Policy(ConflictPolicy),
+    /// The change is applied regardless, as an admin resolved its conflict in its favour
+    Resolved,
+}
+
+/// Implementation of the Change struct
+impl Change {
+    /// Reads the changes recorded after the audit `since`, oldest first
+    ///
+    /// Changes made on this instance are tagged with `instance_name`. The cursor of the feed is the
+    /// last audit read, so it can be passed as `since` to read the next page.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use change::{Change, Origins};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let feed = Change::feed(0, 500, "hub", Origins::Except("satellite"), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn feed(
+        since: u32,
+        limit: u32,
+        instance_name: &str,
+        origins: Origins<'_>,
+        connection: &mut SqliteConnection,
+    ) -> Result<ChangeFeed, sqlx::Error> {
+        let (local_only, except) = match origins {
+            Origins::Local => (true, None),
+            Origins::Except(name) => (false, Some(name)),
+        };
+        let rows = sqlx::query_as::<_, FeedRow>(
+            "SELECT audits.id, audits.uuid, COALESCE(audits.origin, ?1) AS origin,
+                    audits.auditable_type, audits.action, audits.record_data, audits.created_at,
+                    locations.uuid AS location_uuid,
+                    (SELECT previous.uuid FROM audits AS earlier
+                        JOIN locations AS previous ON previous.id = earlier.location_id
+                        WHERE earlier.auditable_type = audits.auditable_type
+                            AND earlier.auditable_id = audits.auditable_id
+                            AND earlier.action IN ('create', 'update')
+                            AND earlier.id < audits.id
+                        ORDER BY earlier.id DESC LIMIT 1) AS previous_location_uuid
+                FROM audits LEFT JOIN locations ON locations.id = audits.location_id
+                WHERE audits.id > ?2
+                    AND ((audits.auditable_type = 'Labware'
+                            AND audits.action IN ('create', 'update', 'exhaust'))
+                        OR (audits.auditable_type = 'Location' AND audits.action = 'create'))
+                    AND (NOT ?3 OR audits.origin IS NULL)
+                    AND (?4 IS NULL OR COALESCE(audits.origin, ?1) != ?4)
+                ORDER BY audits.id LIMIT ?5",
+        )
+        .bind(instance_name)
+        .bind(since)
+        .bind(local_only)
+        .bind(except)
+        .bind(limit)
+        .fetch_all(&mut *connection)
+        .await?;
+
+        let cursor = rows.last().map_or(since, |row| row.id);
+        let mut changes = Vec::with_capacity(rows.len());
+        for row in rows {
+            changes.push(Change::from_row(row, &mut *connection).await?);
+        }
+        Ok(ChangeFeed { changes, cursor })
+    }
+
+    /// Builds a change from its audit. The snapshot of a location refers to its parent and type by
+    /// their ids, which are looked up.
+    async fn from_row(
+        row: FeedRow,
+        connection: &mut SqliteConnection,
+    ) -> Result<Change, sqlx::Error> {
+        let record: Value = serde_json::from_str(&row.record_data).unwrap_or_default();
+        let text = |key: &str| record[key].as_str().map(String::from);
+        let mut change = Change {
+            uuid: row.uuid,
+            origin: row.origin,
+            record_type: row.auditable_type,
+            action: row.action,
+            record_uuid: text("uuid").unwrap_or_default(),
+            barcode: text("barcode"),
+            location_uuid: row.location_uuid,
+            previous_location_uuid: row.previous_location_uuid,
+            name: None,
+            location_type: None,
+            created_at: row.created_at,
+        };
+        if change.record_type == "Location" {
+            change.name = text("name");
+            change.previous_location_uuid = None;
+            change.location_uuid =
+                sqlx::query_scalar::<_, String>("SELECT uuid FROM locations WHERE id = ?")
+                    .bind(record["parent_id"].as_i64())
+                    .fetch_optional(&mut *connection)
+                    .await?;
+            change.location_type =
+                sqlx::query_scalar::<_, String>("SELECT name FROM location_types WHERE id = ?")
+                    .bind(record["location_type_id"].as_i64())
+                    .fetch_optional(&mut *connection)
+                    .await?;
+        }
+        Ok(change)
+    }
+
+    /// Applies changes made on other instances, in order
+    ///
+    /// Changes which were applied before are skipped. A change to a labware which was also changed
+    /// here is resolved by the conflict policy: the latest change wins, or the change is queued for
+    /// an admin to review. Records created by a change keep the UUID they were given on the
+    /// instance they were created on, and the audits of a change keep its UUID, origin and time.
+    /// A change which cannot be applied, e.g. because its location is not known here or is locked,
+    /// is rejected and the others are still applied.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use change::{Change, ConflictPolicy};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let report = Change::apply(&feed.changes, ConflictPolicy::LastWriterWins, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn apply(
+        changes: &[Change],
+        policy: ConflictPolicy,
+        connection: &mut SqliteConnection,
+    ) -> Result<SyncReport, Box<dyn Error + Send + Sync>> {
+        let mut report = SyncReport::default();
+        for change in changes {
+            match change
+                .apply_one(Mode::Policy(policy), &mut *connection)
+                .await?
+            {
+                Outcome::Applied => report.applied += 1,
+                Outcome::Skipped => report.skipped += 1,
+                Outcome::Queued => report.queued += 1,
+                Outcome::Rejected(message) => report.rejected.push(Rejection {
+                    uuid: change.uuid.clone(),
+                    message,
+                }),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Applies a change whose conflict an admin resolved in its favour, overriding the local change.
+    /// A `ValidationError` is returned if it cannot be applied.
+    pub(crate) async fn apply_resolved(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.apply_one(Mode::Resolved, connection).await? {
+            Outcome::Rejected(message) => Err(Box::new(ValidationError { message })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies a single change in a transaction, tagging the audits it creates.
+    async fn apply_one(
+        &self,
+        mode: Mode,
+        connection: &mut SqliteConnection,
+    ) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        let seen: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM audits WHERE uuid = ?1)
+                OR (?2 AND EXISTS(SELECT 1 FROM sync_conflicts WHERE change_uuid = ?1))",
+        )
+        .bind(&self.uuid)
+        .bind(matches!(mode, Mode::Policy(_)))
+        .fetch_one(&mut *transaction)
+        .await?;
+        if seen {
+            return Ok(Outcome::Skipped);
+        }
+        let last_audit_id: u32 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM audits")
+            .fetch_one(&mut *transaction)
+            .await?;
+        let last_event_id: u32 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM events")
+            .fetch_one(&mut *transaction)
+            .await?;
+
+        let outcome = match (self.record_type.as_str(), self.action.as_str()) {
+            ("Labware", "create" | "update") => self.move_labware(mode, &mut transaction).await,
+            ("Labware", "exhaust") => self.exhaust_labware(mode, &mut transaction).await,
+            ("Location", "create") => self.create_location(&mut transaction).await,
+            _ => Ok(Outcome::Rejected(
+                Message::new("sync-unsupported")
+                    .arg("type", &self.record_type)
+                    .arg("action", &self.action),
+            )),
+        };
+        let outcome = match outcome {
+            Err(e) => match e.downcast::<LockedError>() {
+                Ok(locked) => return Ok(Outcome::Rejected(locked.message)),
+                Err(e) => return Err(e),
+            },
+            Ok(outcome) => outcome,
+        };
+        if let Outcome::Applied = outcome {
+            sqlx::query("UPDATE audits SET origin = ?, created_at = ? WHERE id > ?")
+                .bind(&self.origin)
+                .bind(self.created_at.format(STORAGE_FORMAT).to_string())
+                .bind(last_audit_id)
+                .execute(&mut *transaction)
+                .await?;
+            sqlx::query(
+                "UPDATE audits SET uuid = ? WHERE id = (SELECT MAX(id) FROM audits) AND id > ?",
+            )
+            .bind(&self.uuid)
+            .bind(last_audit_id)
+            .execute(&mut *transaction)
+            .await?;
+            // The events happened when the change was made on its origin, not when it arrived
+            sqlx::query("UPDATE events SET created_at = ? WHERE id > ?")
+                .bind(self.created_at.format(STORAGE_FORMAT).to_string())
+                .bind(last_event_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        // The audits were completed after they were sealed
+        Audit::reseal_after(last_audit_id, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(outcome)
+    }
+
+    /// Creates the labware of the change, or moves it if it exists.
+    async fn move_labware(
+        &self,
+        mode: Mode,
+        connection: &mut SqliteConnection,
+    ) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let Some(location) = self.location(&mut *connection).await? else {
+            return Ok(Outcome::Rejected(
+                Message::new("sync-unknown-location").arg(
+                    "location",
+                    self.location_uuid.as_deref().unwrap_or_default(),
+                ),
+            ));
+        };
+        match self.labware(&mut *connection).await? {
+            Some(labware) if labware.location_id == location.id => Ok(Outcome::Skipped),
+            Some(mut labware) => {
+                let current = self.location_uuid_of(&labware, &mut *connection).await?;
+                // A labware created on both sides diverged, as did one moved from elsewhere
+                let diverged = self.action == "create"
+                    || self
+                        .previous_location_uuid
+                        .as_ref()
+                        .is_some_and(|previous| Some(previous) != current.as_ref());
+                if let Some(outcome) = self
+                    .resolve_conflict(&labware, current, diverged, mode, &mut *connection)
+                    .await?
+                {
+                    return Ok(outcome);
+                }
+                labware.location_id = location.id;
+                Labware::update(&labware, None, connection).await?;
+                Ok(Outcome::Applied)
+            }
+            None => {
+                let barcode = self.barcode.clone().unwrap_or_default();
+                let labware = Labware::create(barcode, location.id, &mut *connection).await?;
+                self.adopt("labwares", labware.id, connection).await?;
+                Ok(Outcome::Applied)
+            }
+        }
+    }
+
+    /// Exhausts the labware of the change, unless it is already gone or no longer active.
+    async fn exhaust_labware(
+        &self,
+        mode: Mode,
+        connection: &mut SqliteConnection,
+    ) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let Some(labware) = self.labware(&mut *connection).await? else {
+            return Ok(Outcome::Skipped);
+        };
+        if labware.status != LabwareStatus::Active {
+            return Ok(Outcome::Skipped);
+        }
+        let current = self.location_uuid_of(&labware, &mut *connection).await?;
+        let diverged = current != self.location_uuid;
+        if let Some(outcome) = self
+            .resolve_conflict(&labware, current, diverged, mode, &mut *connection)
+            .await?
+        {
+            return Ok(outcome);
+        }
+        Labware::exhaust(&[labware], None, connection).await?;
+        Ok(Outcome::Applied)
+    }
+
+    /// Resolves a conflict between the change and the local changes to the labware, if there is
+    /// one: the labware `diverged` from where the change expected it, or changed here after it.
+    ///
+    /// Returns the outcome of the change if it is not to be applied. Conflicts are logged.
+    async fn resolve_conflict(
+        &self,
+        labware: &Labware,
+        current: Option<String>,
+        diverged: bool,
+        mode: Mode,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<Outcome>, sqlx::Error> {
+        let Mode::Policy(policy) = mode else {
+            return Ok(None);
+        };
+        let outdated = self.is_outdated(labware, &mut *connection).await?;
+        if !diverged && !outdated {
+            return Ok(None);
+        }
+        let (status, outcome) = match policy {
+            ConflictPolicy::ManualReview => (ConflictStatus::Pending, Some(Outcome::Queued)),
+            ConflictPolicy::LastWriterWins if outdated => {
+                (ConflictStatus::Discarded, Some(Outcome::Skipped))
+            }
+            ConflictPolicy::LastWriterWins => (ConflictStatus::Applied, None),
+        };
+        SyncConflict::log(self, current, status, connection).await?;
+        Ok(outcome)
+    }
+
+    /// Creates the location of the change with the barcode it was given, unless it exists.
+    async fn create_location(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Outcome, Box<dyn Error + Send + Sync>> {
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM locations WHERE uuid = ?)")
+                .bind(&self.record_uuid)
+                .fetch_one(&mut *connection)
+                .await?;
+        if exists {
+            return Ok(Outcome::Skipped);
+        }
+        let parent = self.location(&mut *connection).await?;
+        if self.location_uuid.is_some() && parent.is_none() {
+            return Ok(Outcome::Rejected(
+                Message::new("sync-unknown-location").arg(
+                    "location",
+                    self.location_uuid.as_deref().unwrap_or_default(),
+                ),
+            ));
+        }
+        let barcode = self.barcode.clone().unwrap_or_default();
+        let taken: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM locations WHERE barcode = ? COLLATE NOCASE)",
+        )
+        .bind(&barcode)
+        .fetch_one(&mut *connection)
+        .await?;
+        if taken {
+            return Ok(Outcome::Rejected(
+                Message::new("barcode-in-use").arg("barcode", &barcode),
+            ));
+        }
+
+        let type_name = self.location_type.clone().unwrap_or_default();
+        let location_type_id =
+            match sqlx::query_scalar::<_, u32>("SELECT id FROM location_types WHERE name = ?")
+                .bind(&type_name)
+                .fetch_optional(&mut *connection)
+                .await?
+            {
+                Some(id) => id,
+                None => LocationType::create(type_name, &mut *connection).await?.id,
+            };
+        let location = Location::create_with_parent(
+            self.name.clone().unwrap_or_default(),
+            location_type_id,
+            parent.map(|parent| parent.id),
+            &mut *connection,
+        )
+        .await?;
+        sqlx::query("UPDATE locations SET barcode = ? WHERE id = ?")
+            .bind(&barcode)
+            .bind(location.id)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("UPDATE audits SET record_data = json_set(record_data, '$.barcode', ?) WHERE auditable_type = 'Location' AND auditable_id = ?")
+            .bind(&barcode)
+            .bind(location.id)
+            .execute(&mut *connection)
+            .await?;
+        NOT_FOUND_BARCODES.remove(&location_key(&barcode)).await;
+        self.adopt("locations", location.id, connection).await?;
+        Ok(Outcome::Applied)
+    }
+
+    /// Gives a record created by the change the UUID and origin it has on other instances, in the
+    /// record and in its audits.
+    async fn adopt(
+        &self,
+        table: &str,
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "UPDATE {} SET uuid = ?, origin = ? WHERE id = ?",
+            table
+        ))
+        .bind(&self.record_uuid)
+        .bind(&self.origin)
+        .bind(id)
+        .execute(&mut *connection)
+        .await?;
+        sqlx::query(
+            "UPDATE audits SET record_data = json_set(record_data, '$.uuid', ?, '$.origin', ?)
+                WHERE auditable_type = ? AND auditable_id = ?",
+        )
+        .bind(&self.record_uuid)
+        .bind(&self.origin)
+        .bind(&self.record_type)
+        .bind(id)
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+
+    /// The local location the change refers to, by its UUID.
+    async fn location(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<Location>, sqlx::Error> {
+        sqlx::query_as::<_, Location>("SELECT * FROM locations WHERE uuid = ?")
+            .bind(&self.location_uuid)
+            .fetch_optional(&mut *connection)
+            .await
+    }
+
+    /// The UUID of the location a labware is in here.
+    async fn location_uuid_of(
+        &self,
+        labware: &Labware,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT uuid FROM locations WHERE id = ?")
+            .bind(labware.location_id)
+            .fetch_optional(&mut *connection)
+            .await
+    }
+
+    /// The local labware the change refers to, by its UUID or else by its barcode, in case the
+    /// same labware was created on two instances.
+    async fn labware(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<Labware>, sqlx::Error> {
+        sqlx::query_as::<_, Labware>(
+            "SELECT * FROM labwares
+                WHERE uuid = ?1 OR (barcode = ?2 COLLATE NOCASE AND status = 'active')
+                ORDER BY uuid = ?1 DESC LIMIT 1",
+        )
+        .bind(&self.record_uuid)
+        .bind(&self.barcode)
+        .fetch_optional(&mut *connection)
+        .await
+    }
+
+    /// Whether the labware has changed here since the change was made elsewhere.
+    async fn is_outdated(
+        &self,
+        labware: &Labware,
+        connection: &mut SqliteConnection,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM audits
+                WHERE auditable_type = 'Labware' AND auditable_id = ? AND created_at > ?)",
+        )
+        .bind(labware.id)
+        .bind(self.created_at.format(STORAGE_FORMAT).to_string())
+        .fetch_one(&mut *connection)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::change::*;
+    use crate::models::location_type::LocationType;
+    use chrono::Duration;
+
+    async fn create_labware(connection: &mut SqliteConnection) -> (Location, Labware) {
+        let location_type = LocationType::create("Freezer".to_string(), &mut *connection)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut *connection)
+            .await
+            .unwrap();
+        let labware = Labware::create("lw-1".to_string(), freezer.id, &mut *connection)
+            .await
+            .unwrap();
+        (freezer, labware)
+    }
+
+    #[tokio::test]
+    async fn test_feed_origins() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (_, labware) = create_labware(&mut conn).await;
+        let feed = Change::feed(0, 10, "hub", Origins::Local, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(feed.changes.len(), 2);
+        assert_eq!(feed.changes[1].record_uuid, labware.uuid);
+        assert_eq!(feed.changes[1].origin, "hub");
+
+        let next = Change::feed(feed.cursor, 10, "hub", Origins::Local, &mut conn)
+            .await
+            .unwrap();
+        assert!(next.changes.is_empty());
+        assert_eq!(next.cursor, feed.cursor);
+
+        let feed = Change::feed(0, 10, "hub", Origins::Except("hub"), &mut conn)
+            .await
+            .unwrap();
+        assert!(feed.changes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply_latest_change_wins() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer, labware) = create_labware(&mut conn).await;
+        let exhaust = Change {
+            uuid: "exhaust-1".to_string(),
+            origin: "satellite".to_string(),
+            record_type: "Labware".to_string(),
+            action: "exhaust".to_string(),
+            record_uuid: labware.uuid.clone(),
+            barcode: Some(labware.barcode.clone()),
+            location_uuid: Some(freezer.uuid.clone()),
+            previous_location_uuid: Some(freezer.uuid.clone()),
+            name: None,
+            location_type: None,
+            created_at: Utc::now() - Duration::days(1),
+        };
+        let report = Change::apply(
+            std::slice::from_ref(&exhaust),
+            ConflictPolicy::LastWriterWins,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.skipped, 1);
+        assert!(Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .is_ok());
+
+        let exhaust = Change {
+            uuid: "exhaust-2".to_string(),
+            created_at: Utc::now() + Duration::days(1),
+            ..exhaust
+        };
+        let report = Change::apply(&[exhaust], ConflictPolicy::LastWriterWins, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(report.applied, 1);
+        assert!(Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .is_err());
+        let origin: Option<String> =
+            sqlx::query_scalar("SELECT origin FROM audits WHERE uuid = 'exhaust-2'")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+        assert_eq!(origin.as_deref(), Some("satellite"));
+    }
+}
@@ -0,0 +1,463 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::location_flag::LocationFlag;
+use crate::models::location_lock::LocationLock;
+use crate::models::new_uuid;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// Selects checkouts along with the primary barcode of their labware.
+const SELECT_CHECKOUTS: &str = "SELECT checkouts.*, labwares.barcode AS labware_barcode
+    FROM checkouts JOIN labwares ON labwares.id = checkouts.labware_id";
+
+/// A labware in somebody's custody, e.g. while it is carried to another lab.
+///
+/// Checking a labware out takes it out of its location until it is checked in at a new one. While
+/// the checkout is open the labware is in transit with its holder; the labware itself keeps the
+/// location it was checked out of as its last known location. A checkout stays open until the
+/// labware is checked in or scanned into a location.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Checkout {
+    /// The unique identifier for the Checkout
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Checkout, used in URLs and event payloads
+    pub uuid: String,
+    /// The ID of the checked out labware
+    pub labware_id: u32,
+    /// The primary barcode of the checked out labware
+    pub labware_barcode: String,
+    /// Who has the labware
+    pub holder: String,
+    /// The ID of the location the labware was checked out of
+    pub from_location_id: u32,
+    /// The ID of the location the labware was checked in at, once it has been
+    pub to_location_id: Option<u32>,
+    /// When the labware was checked out
+    pub checked_out_at: DateTime<Utc>,
+    /// When the labware should be returned, if it has to be
+    pub due_at: Option<DateTime<Utc>>,
+    /// When the labware was checked in, once it has been
+    pub checked_in_at: Option<DateTime<Utc>>,
+}
+
+/// Implementation of the Checkout struct
+impl Checkout {
+    /// Checks a labware out to a user
+    ///
+    /// The labware leaves its location (so the lock's token has to be given if the location is
+    /// locked) and is in transit with the user until it is checked in. Returns a `ValidationError`
+    /// if the labware is already checked out.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use checkout::Checkout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let checkout = Checkout::checkout("lw-1".to_string(), "jane".to_string(), None, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn checkout(
+        labware_barcode: String,
+        holder: String,
+        due_at: Option<DateTime<Utc>>,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Checkout, Box<dyn Error + Send + Sync>> {
+        let labware = Labware::find_by_barcode(labware_barcode, &mut *connection).await?;
+        if let Some(checkout) = Checkout::open(labware.id, &mut *connection).await? {
+            return Err(Box::new(ValidationError {
+                message: Message::new("custody-checked-out")
+                    .arg("barcode", &labware.barcode)
+                    .arg("holder", &checkout.holder),
+            }));
+        }
+
+        let mut transaction = connection.begin().await?;
+        LocationLock::ensure_unlocked(labware.location_id, lock_token, &mut transaction).await?;
+        let insert_query_result = sqlx::query(
+            "INSERT INTO checkouts (uuid, labware_id, holder, from_location_id, due_at)
+                VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(labware.id)
+        .bind(holder)
+        .bind(labware.location_id)
+        .bind(due_at)
+        .execute(&mut *transaction)
+        .await?;
+        let checkout = Checkout::find(
+            insert_query_result.last_insert_rowid() as u32,
+            &mut transaction,
+        )
+        .await?;
+        Audit::create(
+            "Labware",
+            labware.id,
+            "checkout",
+            Some(labware.location_id),
+            &checkout,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+
+        Ok(checkout)
+    }
+
+    /// Checks a labware in at a location, closing its checkout
+    ///
+    /// Returns a `ValidationError` if the labware is not checked out.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use checkout::Checkout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let checkout = Checkout::checkin("lw-1".to_string(), "lw-freezer-1".to_string(), None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn checkin(
+        labware_barcode: String,
+        location_barcode: String,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Checkout, Box<dyn Error + Send + Sync>> {
+        let mut labware = Labware::find_by_barcode(labware_barcode, &mut *connection).await?;
+        let checkout = Checkout::open(labware.id, &mut *connection)
+            .await?
+            .ok_or_else(|| ValidationError {
+                message: Message::new("custody-not-checked-out").arg("barcode", &labware.barcode),
+            })?;
+        let location = Location::find_by_barcode(location_barcode, &mut *connection).await?;
+        LocationFlag::ensure_not_quarantined(&location, &mut *connection).await?;
+
+        let mut transaction = connection.begin().await?;
+        labware.location_id = location.id;
+        // Moving the labware closes the checkout
+        Labware::update(&labware, lock_token, &mut transaction).await?;
+        let checkout = Checkout::find(checkout.id, &mut transaction).await?;
+        Audit::create(
+            "Labware",
+            labware.id,
+            "checkin",
+            Some(location.id),
+            &checkout,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+
+        Ok(checkout)
+    }
+
+    /// Lists the labwares which are checked out, soonest due first. If `overdue` is set, only
+    /// those which should have been returned by now are listed.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use checkout::Checkout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let overdue = Checkout::all_open(true, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn all_open(
+        overdue: bool,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Checkout>, sqlx::Error> {
+        sqlx::query_as::<_, Checkout>(&format!(
+            "{} WHERE checkouts.checked_in_at IS NULL
+                AND (?1 = 0 OR checkouts.due_at < ?2)
+                ORDER BY checkouts.due_at IS NULL, checkouts.due_at, checkouts.id",
+            SELECT_CHECKOUTS
+        ))
+        .bind(overdue)
+        .bind(Utc::now())
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Lists the labwares which are in transit (checked out), the longest in transit first.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use checkout::Checkout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let in_transit = Checkout::in_transit(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn in_transit(
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Checkout>, sqlx::Error> {
+        sqlx::query_as::<_, Checkout>(&format!(
+            "{} WHERE checkouts.checked_in_at IS NULL
+                ORDER BY checkouts.checked_out_at, checkouts.id",
+            SELECT_CHECKOUTS
+        ))
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// How long the labware has been in transit, in minutes, or was until it was checked in
+    pub fn minutes_in_transit(&self) -> i64 {
+        (self.checked_in_at.unwrap_or_else(Utc::now) - self.checked_out_at).num_minutes()
+    }
+
+    /// Returns the open checkout of a labware, if it is checked out
+    pub async fn open(
+        labware_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<Checkout>, sqlx::Error> {
+        sqlx::query_as::<_, Checkout>(&format!(
+            "{} WHERE checkouts.labware_id = ? AND checkouts.checked_in_at IS NULL",
+            SELECT_CHECKOUTS
+        ))
+        .bind(labware_id)
+        .fetch_optional(&mut *connection)
+        .await
+    }
+
+    /// Closes the open checkout of a labware, if any, now that it has been put in a location.
+    /// This is called whenever a labware is moved.
+    pub async fn close(
+        labware_id: u32,
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE checkouts SET checked_in_at = CURRENT_TIMESTAMP, to_location_id = ?
+                WHERE labware_id = ? AND checked_in_at IS NULL",
+        )
+        .bind(location_id)
+        .bind(labware_id)
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Where the labware is while it is checked out, e.g. `In transit with jane`
+    pub fn whereabouts(&self) -> Option<Message> {
+        match self.checked_in_at {
+            None => Some(Message::new("custody-in-transit").arg("holder", &self.holder)),
+            Some(_) => None,
+        }
+    }
+
+    /// Find a checkout by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<Checkout, NotFoundError> {
+        sqlx::query_as::<_, Checkout>(&format!("{} WHERE checkouts.id = ?", SELECT_CHECKOUTS))
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("custody-not-found"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::errors::LockedError;
+    use crate::models::checkout::*;
+    use crate::models::location_type::LocationType;
+    use crate::models::scan::Scan;
+
+    async fn create_labware(connection: &mut SqliteConnection) -> (Labware, Location) {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let location1 = Location::create("freezer1".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        let location2 = Location::create("freezer2".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        let labware = Labware::create("lw-1".to_string(), location1.id, connection)
+            .await
+            .unwrap();
+        (labware, location2)
+    }
+
+    #[tokio::test]
+    async fn test_checkout_and_checkin() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (labware, location2) = create_labware(&mut conn).await;
+
+        let checkout = Checkout::checkout(
+            "lw-1".to_string(),
+            "jane".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(checkout.labware_barcode, "lw-1");
+        assert_eq!(checkout.holder, "jane");
+        assert_eq!(checkout.from_location_id, labware.location_id);
+        assert_eq!(
+            checkout.whereabouts().unwrap().to_string(),
+            "In transit with jane"
+        );
+        assert_eq!(
+            Checkout::open(labware.id, &mut conn).await.unwrap(),
+            Some(checkout.clone())
+        );
+
+        let error = Checkout::checkout(
+            "lw-1".to_string(),
+            "john".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Labware lw-1 is already checked out by jane"
+        );
+
+        let checkin = Checkout::checkin(
+            "lw-1".to_string(),
+            location2.barcode.clone().unwrap(),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(checkin.uuid, checkout.uuid);
+        assert_eq!(checkin.to_location_id, Some(location2.id));
+        assert!(checkin.checked_in_at.is_some());
+        assert!(checkin.whereabouts().is_none());
+        let moved = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(moved.location_id, location2.id);
+
+        let audits = Audit::for_location(location2.id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits[0].action, "checkin");
+
+        let error = Checkout::checkin(
+            "lw-1".to_string(),
+            location2.barcode.unwrap(),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_checkout_from_locked_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (labware, _) = create_labware(&mut conn).await;
+        let lock = LocationLock::acquire(labware.location_id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+
+        let error = Checkout::checkout(
+            "lw-1".to_string(),
+            "john".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<LockedError>());
+        Checkout::checkout(
+            "lw-1".to_string(),
+            "jane".to_string(),
+            None,
+            Some(&lock.token),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_closes_checkout() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (_, location2) = create_labware(&mut conn).await;
+        Checkout::checkout(
+            "lw-1".to_string(),
+            "jane".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        Scan::create(
+            location2.barcode.unwrap(),
+            vec!["lw-1".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert!(Checkout::all_open(false, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_all_open_and_overdue() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (_, location2) = create_labware(&mut conn).await;
+        Labware::create("lw-2".to_string(), location2.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-3".to_string(), location2.id, &mut conn)
+            .await
+            .unwrap();
+        let now = Utc::now();
+        Checkout::checkout(
+            "lw-1".to_string(),
+            "jane".to_string(),
+            Some(now + chrono::Duration::hours(1)),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Checkout::checkout(
+            "lw-2".to_string(),
+            "john".to_string(),
+            Some(now - chrono::Duration::hours(1)),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Checkout::checkout(
+            "lw-3".to_string(),
+            "jane".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let open = Checkout::all_open(false, &mut conn).await.unwrap();
+        let barcodes: Vec<&str> = open.iter().map(|c| c.labware_barcode.as_str()).collect();
+        assert_eq!(barcodes, vec!["lw-2", "lw-1", "lw-3"]);
+
+        let overdue = Checkout::all_open(true, &mut conn).await.unwrap();
+        assert_eq!(overdue.len(), 1);
+        assert_eq!(overdue[0].holder, "john");
+    }
+}
@@ -0,0 +1,203 @@
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// How long a confirmation token can be replayed for, in seconds.
+pub const CONFIRMATION_SECONDS: u32 = 120;
+
+/// A short-lived token confirming a destructive operation, e.g. exhausting many labwares at once.
+///
+/// Asking for the operation without a token issues one, alongside a summary of what would happen.
+/// The operation is only performed when the token is replayed before it expires, for exactly the
+/// same subject (e.g. the same set of labwares) as was summarised. Each token can be used once.
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Confirmation {
+    /// The unique identifier for the Confirmation
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The secret which has to be replayed to perform the operation
+    pub token: String,
+    /// The operation which is confirmed e.g. `exhaust`
+    pub action: String,
+    /// The SHA-256 digest of what the operation applies to
+    #[serde(skip_serializing)]
+    pub digest: String,
+    /// When the token expires
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Implementation of the Confirmation struct
+impl Confirmation {
+    /// Issues a token confirming an action on a subject
+    ///
+    /// The subject is anything which identifies what the action applies to, such as the ids of
+    /// the records it changes. Only its digest is stored.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use confirmation::Confirmation;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let confirmation = Confirmation::issue("exhaust", &vec![1, 2], &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn issue<T: Serialize>(
+        action: &str,
+        subject: &T,
+        connection: &mut SqliteConnection,
+    ) -> Result<Confirmation, sqlx::Error> {
+        Confirmation::delete_expired(&mut *connection).await?;
+        let insert_query_result = sqlx::query(
+            "INSERT INTO confirmations (token, action, digest, expires_at)
+                VALUES (lower(hex(randomblob(16))), ?, ?, datetime('now', '+' || ? || ' seconds'))",
+        )
+        .bind(action)
+        .bind(digest(subject)?)
+        .bind(CONFIRMATION_SECONDS)
+        .execute(&mut *connection)
+        .await?;
+
+        sqlx::query_as::<_, Confirmation>("SELECT * FROM confirmations WHERE id = ?")
+            .bind(insert_query_result.last_insert_rowid())
+            .fetch_one(&mut *connection)
+            .await
+    }
+
+    /// Uses up a token, checking that it confirms the action on the subject
+    ///
+    /// Returns a `ValidationError` if the token is unknown, has expired or was already used, or if
+    /// the subject has changed since the token was issued (in which case the token is used up and
+    /// a new one has to be asked for). Call it in the same transaction as the action, so that the
+    /// token is only used up if the action succeeds.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use confirmation::Confirmation;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// Confirmation::redeem(&confirmation.token, "exhaust", &vec![1, 2], &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn redeem<T: Serialize>(
+        token: &str,
+        action: &str,
+        subject: &T,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let confirmation = sqlx::query_as::<_, Confirmation>(
+            "SELECT * FROM confirmations WHERE token = ? AND expires_at > datetime('now')",
+        )
+        .bind(token)
+        .fetch_optional(&mut *connection)
+        .await?
+        .filter(|confirmation| confirmation.action == action)
+        .ok_or(ValidationError {
+            message: Message::new("confirmation-invalid"),
+        })?;
+        sqlx::query("DELETE FROM confirmations WHERE id = ?")
+            .bind(confirmation.id)
+            .execute(&mut *connection)
+            .await?;
+        if confirmation.digest != digest(subject)? {
+            return Err(Box::new(ValidationError {
+                message: Message::new("confirmation-changed"),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Removes tokens which have expired
+    async fn delete_expired(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM confirmations WHERE expires_at <= datetime('now')")
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The SHA-256 digest of the JSON of a subject, as lowercase hex.
+fn digest<T: Serialize>(subject: &T) -> Result<String, sqlx::Error> {
+    let json = serde_json::to_vec(subject).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    Ok(Sha256::digest(json)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::confirmation::*;
+
+    #[tokio::test]
+    async fn test_issue_and_redeem() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+
+        let confirmation = Confirmation::issue("exhaust", &vec![1, 2], &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(confirmation.action, "exhaust");
+        assert_eq!(confirmation.token.len(), 32);
+        let expires_in = confirmation.expires_at - Utc::now();
+        assert!(expires_in > chrono::Duration::seconds(110));
+        assert!(expires_in <= chrono::Duration::seconds(120));
+
+        Confirmation::redeem(&confirmation.token, "exhaust", &vec![1, 2], &mut conn)
+            .await
+            .unwrap();
+
+        // Each token can only be used once
+        let error = Confirmation::redeem(&confirmation.token, "exhaust", &vec![1, 2], &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_redeem_for_another_action_or_subject() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+
+        let confirmation = Confirmation::issue("exhaust", &vec![1, 2], &mut conn)
+            .await
+            .unwrap();
+        let error = Confirmation::redeem(&confirmation.token, "delete", &vec![1, 2], &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+
+        // The subject changed, so the token is used up
+        let error = Confirmation::redeem(&confirmation.token, "exhaust", &vec![1, 3], &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Things have changed since this was summarised, please ask again"
+        );
+        let error = Confirmation::redeem(&confirmation.token, "exhaust", &vec![1, 2], &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The confirmation token is not valid or has expired"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redeem_expired() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+
+        let confirmation = Confirmation::issue("exhaust", &vec![1], &mut conn)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE confirmations SET expires_at = datetime('now', '-1 seconds')")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let error = Confirmation::redeem(&confirmation.token, "exhaust", &vec![1], &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+}
@@ -0,0 +1,239 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::new_uuid;
+use crate::models::subscription::{SubscriptionChannel, MAX_DELIVERY_ATTEMPTS};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// The dead letters, with the subscription and the audit each failed to deliver.
+const SELECT_DEAD_LETTERS: &str = "SELECT d.id, d.uuid, s.uuid AS subscription_uuid,
+        s.channel, s.target, a.uuid AS audit_uuid, d.error, d.attempts, d.created_at,
+        d.redriven_by, d.redriven_at
+    FROM dead_letters d
+    JOIN subscription_events e ON e.id = d.subscription_event_id
+    JOIN subscriptions s ON s.id = e.subscription_id
+    JOIN audits a ON a.id = e.audit_id";
+
+/// A notification to a subscriber which could not be delivered and is no longer retried: it failed
+/// `MAX_DELIVERY_ATTEMPTS` times, or the target refused it outright (e.g. a webhook responding
+/// with 404).
+///
+/// Dead letters are kept for an admin to look into, e.g. to fix the target of the subscription,
+/// and re-drive, which queues the notification again with a fresh set of attempts.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct DeadLetter {
+    /// The unique identifier for the DeadLetter
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the DeadLetter, used in URLs
+    pub uuid: String,
+    /// The UUID of the subscription the notification was for
+    pub subscription_uuid: String,
+    /// How the subscriber was to be notified
+    pub channel: SubscriptionChannel,
+    /// The email address or webhook the notification was sent to
+    pub target: String,
+    /// The UUID of the audit the notification was about
+    pub audit_uuid: String,
+    /// Why the last attempt failed
+    pub error: String,
+    /// How many times delivering the notification was attempted
+    pub attempts: u32,
+    /// When the notification was given up on
+    pub created_at: DateTime<Utc>,
+    /// Who re-drove the notification, if it was
+    pub redriven_by: Option<String>,
+    /// When the notification was re-driven, if it was
+    pub redriven_at: Option<DateTime<Utc>>,
+}
+
+/// Implementation of the DeadLetter struct
+impl DeadLetter {
+    /// Gives up on delivering a queued notification, after the error of its last attempt.
+    pub(crate) async fn record(
+        subscription_event_id: u32,
+        error: &str,
+        attempts: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO dead_letters (uuid, subscription_event_id, error, attempts)
+                VALUES (?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(subscription_event_id)
+        .bind(error)
+        .bind(attempts)
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Gives up on the queued notifications which ran out of attempts before dead letters were
+    /// kept, so they are not left undelivered unnoticed.
+    pub(crate) async fn sweep(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        let exhausted = sqlx::query_as::<_, (u32, Option<String>, u32)>(
+            "SELECT id, last_error, attempts FROM subscription_events
+                WHERE delivered_at IS NULL AND attempts >= ?
+                AND id NOT IN (SELECT subscription_event_id FROM dead_letters
+                    WHERE redriven_at IS NULL)",
+        )
+        .bind(MAX_DELIVERY_ATTEMPTS)
+        .fetch_all(&mut *connection)
+        .await?;
+        for (id, error, attempts) in exhausted {
+            DeadLetter::record(id, &error.unwrap_or_default(), attempts, connection).await?;
+        }
+        Ok(())
+    }
+
+    /// Lists the dead letters which have not been re-driven, newest first
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use dead_letter::DeadLetter;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let dead_letters = DeadLetter::pending(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn pending(
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        sqlx::query_as::<_, DeadLetter>(&format!(
+            "{} WHERE d.redriven_at IS NULL ORDER BY d.id DESC",
+            SELECT_DEAD_LETTERS
+        ))
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Queues the notification of a dead letter for delivery again, with a fresh set of attempts.
+    ///
+    /// Returns a `NotFoundError` if there is no such dead letter, and a `ValidationError` if it
+    /// was already re-driven.
+    pub async fn redrive(
+        uuid: &str,
+        redriven_by: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<DeadLetter, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        let dead_letter = DeadLetter::find_by_uuid(uuid, &mut transaction).await?;
+        if dead_letter.redriven_at.is_some() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("dead-letter-redriven"),
+            }));
+        }
+        sqlx::query(
+            "UPDATE subscription_events SET attempts = 0, last_error = NULL
+                WHERE id = (SELECT subscription_event_id FROM dead_letters WHERE id = ?)",
+        )
+        .bind(dead_letter.id)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(
+            "UPDATE dead_letters SET redriven_by = ?, redriven_at = CURRENT_TIMESTAMP
+                WHERE id = ?",
+        )
+        .bind(redriven_by)
+        .bind(dead_letter.id)
+        .execute(&mut *transaction)
+        .await?;
+        let dead_letter = DeadLetter::find_by_uuid(uuid, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(dead_letter)
+    }
+
+    /// Find a dead letter by uuid
+    async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<DeadLetter, NotFoundError> {
+        sqlx::query_as::<_, DeadLetter>(&format!("{} WHERE d.uuid = ?", SELECT_DEAD_LETTERS))
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("dead-letter-not-found"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::dead_letter::*;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::subscription::Subscription;
+
+    #[tokio::test]
+    async fn test_dead_letter_and_redrive() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let subscription = Subscription::create(
+            freezer.id,
+            "jane".to_string(),
+            SubscriptionChannel::Email,
+            "jane@example.com".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Labware::create("lw-dead-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+
+        // No SMTP server is configured in tests, so every attempt fails
+        for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+            Subscription::deliver_pending(&mut conn).await.unwrap();
+            let dead_letters = DeadLetter::pending(&mut conn).await.unwrap();
+            assert_eq!(dead_letters.is_empty(), attempt < MAX_DELIVERY_ATTEMPTS);
+        }
+        Subscription::deliver_pending(&mut conn).await.unwrap();
+        let dead_letters = DeadLetter::pending(&mut conn).await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].subscription_uuid, subscription.uuid);
+        assert_eq!(dead_letters[0].attempts, MAX_DELIVERY_ATTEMPTS);
+        assert_eq!(dead_letters[0].error, "no SMTP server is configured");
+
+        let redriven = DeadLetter::redrive(&dead_letters[0].uuid, "jane".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(redriven.redriven_by.as_deref(), Some("jane"));
+        assert!(DeadLetter::pending(&mut conn).await.unwrap().is_empty());
+        let attempts = sqlx::query_scalar::<_, u32>("SELECT attempts FROM subscription_events")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(attempts, 0);
+        let error = DeadLetter::redrive(&redriven.uuid, "jane".to_string(), &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The dead letter has already been re-driven"
+        );
+
+        // Notifications which ran out of attempts before dead letters were kept are swept up
+        sqlx::query("UPDATE subscription_events SET attempts = ?")
+            .bind(MAX_DELIVERY_ATTEMPTS)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        Subscription::deliver_pending(&mut conn).await.unwrap();
+        assert_eq!(DeadLetter::pending(&mut conn).await.unwrap().len(), 1);
+
+        Subscription::delete(freezer.id, &subscription.uuid, &mut conn)
+            .await
+            .unwrap();
+        assert!(DeadLetter::pending(&mut conn).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::errors::{ForbiddenError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::location::Location;
+use crate::models::scan::Scan;
+use crate::models::{new_secret, new_uuid, secret_digest};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// The number of scans shown with a device.
+pub const RECENT_SCANS: u32 = 20;
+
+/// A physical scan station, e.g. the handheld scanner on the goods-in bench.
+///
+/// Devices send their API key in the `X-Device-Key` header with their scans and heartbeats, so
+/// every scan can be traced back to the station it came from. A misbehaving station can be
+/// disabled, after which its scans are refused until it is enabled again.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Device {
+    /// The unique identifier for the Device
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Device, used in URLs and event payloads
+    pub uuid: String,
+    /// The unique name of the Device e.g. `goods-in-1`
+    pub name: String,
+    /// The ID of the location the device is pinned to, if any. Scans from the device which do not
+    /// name a location go there, e.g. to the goods-in bench.
+    pub location_id: Option<u32>,
+    /// The SHA-256 digest of the secret the device identifies itself with
+    #[serde(skip_serializing)]
+    pub api_key_digest: String,
+    /// The secret itself, which is only known (and shown) when the device is registered
+    #[serde(skip_serializing)]
+    #[sqlx(skip)]
+    pub api_key: Option<String>,
+    /// Whether the device may scan
+    pub enabled: bool,
+    /// When the device last scanned or sent a heartbeat
+    pub last_seen_at: Option<DateTime<Utc>>,
+    /// When the device was registered
+    pub created_at: DateTime<Utc>,
+}
+
+/// Implementation of the Device struct
+impl Device {
+    /// Registers a device, generating its API key, which is only known to the returned `Device`
+    ///
+    /// Returns a `ValidationError` if another device has the same name.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use device::Device;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let device = Device::create("goods-in-1".to_string(), Some(1), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        name: String,
+        location_id: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Device, Box<dyn Error + Send + Sync>> {
+        let taken = sqlx::query_scalar::<_, u32>("SELECT COUNT(*) FROM devices WHERE name = ?")
+            .bind(&name)
+            .fetch_one(&mut *connection)
+            .await?;
+        if taken > 0 {
+            return Err(Box::new(ValidationError {
+                message: Message::new("device-name-taken").arg("name", &name),
+            }));
+        }
+
+        let api_key = new_secret(16, &mut *connection).await?;
+        let insert_query_result = sqlx::query(
+            "INSERT INTO devices (uuid, name, location_id, api_key_digest) VALUES (?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(name)
+        .bind(location_id)
+        .bind(secret_digest(&api_key))
+        .execute(&mut *connection)
+        .await?;
+        let mut device =
+            Device::find(insert_query_result.last_insert_rowid() as u32, connection).await?;
+        device.api_key = Some(api_key);
+        Ok(device)
+    }
+
+    /// Lists every device, ordered by name
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<Device>, sqlx::Error> {
+        sqlx::query_as::<_, Device>("SELECT * FROM devices ORDER BY name")
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// Find a device by its public identifier
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Device, NotFoundError> {
+        sqlx::query_as::<_, Device>("SELECT * FROM devices WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("device-not-found"),
+            })
+    }
+
+    /// Identifies the device an API key belongs to, by its digest, and records that it was seen
+    ///
+    /// Returns a `ForbiddenError` if the key is unknown or the device is disabled.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use device::Device;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let device = Device::authenticate(&device.api_key, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn authenticate(
+        api_key: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Device, Box<dyn Error + Send + Sync>> {
+        let device = sqlx::query_as::<_, Device>("SELECT * FROM devices WHERE api_key_digest = ?")
+            .bind(secret_digest(api_key))
+            .fetch_optional(&mut *connection)
+            .await?
+            .ok_or_else(|| ForbiddenError {
+                message: Message::new("device-key-invalid"),
+            })?;
+        if !device.enabled {
+            return Err(Box::new(ForbiddenError {
+                message: Message::new("device-disabled").arg("name", &device.name),
+            }));
+        }
+
+        sqlx::query("UPDATE devices SET last_seen_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(device.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(Device::find(device.id, connection).await?)
+    }
+
+    /// Enables or disables a device
+    pub async fn set_enabled(
+        uuid: &str,
+        enabled: bool,
+        connection: &mut SqliteConnection,
+    ) -> Result<Device, Box<dyn Error + Send + Sync>> {
+        let device = Device::find_by_uuid(uuid, &mut *connection).await?;
+        sqlx::query("UPDATE devices SET enabled = ? WHERE id = ?")
+            .bind(enabled)
+            .bind(device.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(Device::find(device.id, connection).await?)
+    }
+
+    /// Pins a device to a location, or unpins it
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use device::Device;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let device = Device::pin(&device.uuid, Some(bench.id), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn pin(
+        uuid: &str,
+        location_id: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Device, Box<dyn Error + Send + Sync>> {
+        let device = Device::find_by_uuid(uuid, &mut *connection).await?;
+        sqlx::query("UPDATE devices SET location_id = ? WHERE id = ?")
+            .bind(location_id)
+            .bind(device.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(Device::find(device.id, connection).await?)
+    }
+
+    /// The location the device is pinned to, if any
+    pub async fn pinned_location(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<Location>, NotFoundError> {
+        match self.location_id {
+            Some(location_id) => Ok(Some(Location::find(location_id, connection).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Lists the latest `RECENT_SCANS` scans from the device, newest first
+    pub async fn recent_scans(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Scan>, sqlx::Error> {
+        sqlx::query_as::<_, Scan>(
+            "SELECT * FROM scans WHERE device_id = ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(self.id)
+        .bind(RECENT_SCANS)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Find a device by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<Device, NotFoundError> {
+        sqlx::query_as::<_, Device>("SELECT * FROM devices WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("device-not-found"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::device::*;
+
+    async fn create_device(name: &str, connection: &mut SqliteConnection) -> Device {
+        Device::create(name.to_string(), None, connection)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        create_device("goods-in-1", &mut conn).await;
+        let error = Device::create("goods-in-1".to_string(), None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let device = create_device("goods-in-1", &mut conn).await;
+        let api_key = device.api_key.clone().unwrap();
+        assert_eq!(api_key.len(), 32);
+        assert_eq!(device.api_key_digest, secret_digest(&api_key));
+        assert!(device.last_seen_at.is_none());
+
+        let seen = Device::authenticate(&api_key, &mut conn).await.unwrap();
+        assert_eq!(seen.uuid, device.uuid);
+        assert!(seen.last_seen_at.is_some());
+
+        let error = Device::authenticate("guess", &mut conn).await.unwrap_err();
+        assert_eq!(error.to_string(), "Unknown device key");
+
+        sqlx::query("UPDATE devices SET enabled = 0 WHERE id = ?")
+            .bind(device.id)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let error = Device::authenticate(&api_key, &mut conn).await.unwrap_err();
+        assert_eq!(error.to_string(), "Device goods-in-1 is disabled");
+    }
+}
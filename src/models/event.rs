@@ -0,0 +1,869 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::cache::{labware_key, NOT_FOUND_BARCODES};
+use crate::config::CONFIG;
+use crate::db::statements;
+use crate::errors::NotFoundError;
+use crate::i18n::Message;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::location::Location;
+use crate::models::new_uuid;
+use crate::timestamps::STORAGE_FORMAT;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// A change to the storage, appended to the event log as it happens.
+///
+/// Where an audit snapshots a record after an action, an event says what happened to it, so
+/// replaying the events in order reconstructs where every labware was, and which location every
+/// location was inside of, at any point in time. The event log is append-only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Event {
+    /// A location was created, inside of its parent if it has one
+    LocationCreated {
+        location_id: u32,
+        name: String,
+        location_type_id: u32,
+        parent_id: Option<u32>,
+    },
+    /// A location was moved inside of another location, or to the top level
+    LocationReparented {
+        location_id: u32,
+        parent_id: Option<u32>,
+        previous_parent_id: Option<u32>,
+    },
+    /// A location was renamed
+    LocationRenamed {
+        location_id: u32,
+        name: String,
+        previous_name: String,
+    },
+    /// A labware was registered in a location
+    LabwareCreated {
+        labware_id: u32,
+        barcode: String,
+        location_id: u32,
+    },
+    /// A labware was moved to another location
+    LabwareMoved {
+        labware_id: u32,
+        location_id: u32,
+        previous_location_id: u32,
+    },
+    /// A labware was used up and thrown away
+    LabwareExhausted { labware_id: u32, location_id: u32 },
+    /// A labware was destroyed or shipped out
+    LabwareRetired {
+        labware_id: u32,
+        location_id: u32,
+        status: LabwareStatus,
+    },
+    /// A labware which was shipped out is back in its location
+    LabwareReactivated {
+        labware_id: u32,
+        barcode: String,
+        location_id: u32,
+    },
+}
+
+impl Event {
+    /// The name of the event e.g. `LabwareMoved`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::LocationCreated { .. } => "LocationCreated",
+            Event::LocationReparented { .. } => "LocationReparented",
+            Event::LocationRenamed { .. } => "LocationRenamed",
+            Event::LabwareCreated { .. } => "LabwareCreated",
+            Event::LabwareMoved { .. } => "LabwareMoved",
+            Event::LabwareExhausted { .. } => "LabwareExhausted",
+            Event::LabwareRetired { .. } => "LabwareRetired",
+            Event::LabwareReactivated { .. } => "LabwareReactivated",
+        }
+    }
+
+    /// The type and the id of the record the event happened to
+    pub fn record(&self) -> (&'static str, u32) {
+        match *self {
+            Event::LocationCreated { location_id, .. }
+            | Event::LocationReparented { location_id, .. }
+            | Event::LocationRenamed { location_id, .. } => ("Location", location_id),
+            Event::LabwareCreated { labware_id, .. }
+            | Event::LabwareMoved { labware_id, .. }
+            | Event::LabwareExhausted { labware_id, .. }
+            | Event::LabwareRetired { labware_id, .. }
+            | Event::LabwareReactivated { labware_id, .. } => ("Labware", labware_id),
+        }
+    }
+
+    /// Appends the event to the event log
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use event::Event;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// Event::LabwareMoved { labware_id: 1, location_id: 2, previous_location_id: 1 }
+    ///     .append(&mut connection)
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn append(&self, connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        let data = serde_json::to_string(self).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+        let (record_type, record_id) = self.record();
+        sqlx::query(
+            "INSERT INTO events (uuid, event_type, record_type, record_id, data)
+                VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(self.name())
+        .bind(record_type)
+        .bind(record_id)
+        .bind(data)
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+}
+
+/// An event in the event log.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LoggedEvent {
+    /// The position of the event in the log
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the event
+    pub uuid: String,
+    /// What happened
+    #[serde(flatten)]
+    pub event: Event,
+    /// When it happened
+    pub created_at: DateTime<Utc>,
+    /// The admin who made it happen while acting as another user, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub real_user: Option<String>,
+    /// The user the admin was acting as, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_user: Option<String>,
+}
+
+/// An event as stored: its id, uuid, data, time and the users it was made by.
+type EventRow = (
+    u32,
+    String,
+    String,
+    DateTime<Utc>,
+    Option<String>,
+    Option<String>,
+);
+
+impl LoggedEvent {
+    /// Reads the events which happened up to and including `until` (all of them if it is `None`)
+    /// in the order they were logged, optionally only those of one record e.g. `("Labware", 1)`.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use event::LoggedEvent;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let events = LoggedEvent::all(None, Some(("Labware", 1)), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn all(
+        until: Option<DateTime<Utc>>,
+        record: Option<(&str, u32)>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<LoggedEvent>, Box<dyn Error + Send + Sync>> {
+        let rows = sqlx::query_as::<_, EventRow>(
+            "SELECT id, uuid, data, created_at, real_user, effective_user FROM events
+                WHERE (?1 IS NULL OR created_at <= ?1)
+                    AND (?2 IS NULL OR (record_type = ?2 AND record_id = ?3))
+                ORDER BY id",
+        )
+        .bind(until.map(|until| until.format(STORAGE_FORMAT).to_string()))
+        .bind(record.map(|(record_type, _)| record_type))
+        .bind(record.map(|(_, record_id)| record_id))
+        .fetch_all(&mut *connection)
+        .await?;
+        LoggedEvent::decode(rows)
+    }
+
+    /// Reads up to `limit` events logged after the one with the given id, in the order they were
+    /// logged, to follow the event log as it grows.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use event::LoggedEvent;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let events = LoggedEvent::after(0, 500, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn after(
+        id: u32,
+        limit: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<LoggedEvent>, Box<dyn Error + Send + Sync>> {
+        let rows = sqlx::query_as::<_, EventRow>(
+            "SELECT id, uuid, data, created_at, real_user, effective_user FROM events WHERE id > ? ORDER BY id LIMIT ?",
+        )
+        .bind(id)
+        .bind(limit)
+        .fetch_all(&mut *connection)
+        .await?;
+        LoggedEvent::decode(rows)
+    }
+
+    /// Reads the events which happened up to and including `until` to the labwares which were
+    /// ever in a location and to the locations which were ever inside of it, in the order they were
+    /// logged.
+    pub async fn around_location(
+        location_id: u32,
+        until: DateTime<Utc>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<LoggedEvent>, Box<dyn Error + Send + Sync>> {
+        let rows = sqlx::query_as::<_, EventRow>(
+            "SELECT id, uuid, data, created_at, real_user, effective_user FROM events
+                WHERE created_at <= ?1 AND (record_type, record_id) IN (
+                    SELECT record_type, record_id FROM events
+                        WHERE (record_type = 'Labware' AND json_extract(data, '$.location_id') = ?2)
+                            OR (record_type = 'Location' AND json_extract(data, '$.parent_id') = ?2)
+                )
+                ORDER BY id",
+        )
+        .bind(until.format(STORAGE_FORMAT).to_string())
+        .bind(location_id)
+        .fetch_all(&mut *connection)
+        .await?;
+        LoggedEvent::decode(rows)
+    }
+
+    fn decode(rows: Vec<EventRow>) -> Result<Vec<LoggedEvent>, Box<dyn Error + Send + Sync>> {
+        let mut events = Vec::with_capacity(rows.len());
+        for (id, uuid, data, created_at, real_user, effective_user) in rows {
+            events.push(LoggedEvent {
+                id,
+                uuid,
+                event: serde_json::from_str(&data)?,
+                created_at,
+                real_user,
+                effective_user,
+            });
+        }
+        Ok(events)
+    }
+}
+
+/// A labware as the event log has it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabwareState {
+    /// The barcode the labware was registered with
+    pub barcode: String,
+    /// The location the labware is in
+    pub location_id: u32,
+}
+
+/// The storage as the event log has it at a point in time, built by replaying the events up to
+/// then.
+#[derive(Debug, Default, PartialEq)]
+pub struct State {
+    /// The location every location is inside of, if any, by location id
+    pub parents: BTreeMap<u32, Option<u32>>,
+    /// Every labware which is active, by labware id
+    pub labwares: BTreeMap<u32, LabwareState>,
+    /// The status of every labware which is no longer active, by labware id
+    pub retired: BTreeMap<u32, LabwareStatus>,
+}
+
+impl State {
+    /// Applies an event to the state.
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::LocationCreated {
+                location_id,
+                parent_id,
+                ..
+            }
+            | Event::LocationReparented {
+                location_id,
+                parent_id,
+                ..
+            } => {
+                self.parents.insert(*location_id, *parent_id);
+            }
+            // Where things are does not depend on what they are called
+            Event::LocationRenamed { .. } => {}
+            Event::LabwareCreated {
+                labware_id,
+                barcode,
+                location_id,
+            }
+            | Event::LabwareReactivated {
+                labware_id,
+                barcode,
+                location_id,
+            } => {
+                self.retired.remove(labware_id);
+                self.labwares.insert(
+                    *labware_id,
+                    LabwareState {
+                        barcode: barcode.clone(),
+                        location_id: *location_id,
+                    },
+                );
+            }
+            Event::LabwareMoved {
+                labware_id,
+                location_id,
+                ..
+            } => {
+                if let Some(labware) = self.labwares.get_mut(labware_id) {
+                    labware.location_id = *location_id;
+                }
+            }
+            Event::LabwareExhausted { labware_id, .. } => {
+                self.labwares.remove(labware_id);
+                self.retired.insert(*labware_id, LabwareStatus::Exhausted);
+            }
+            Event::LabwareRetired {
+                labware_id, status, ..
+            } => {
+                self.labwares.remove(labware_id);
+                self.retired.insert(*labware_id, *status);
+            }
+        }
+    }
+
+    /// Replays events, in order, from an empty storage.
+    pub fn replay<'a>(events: impl IntoIterator<Item = &'a Event>) -> State {
+        let mut state = State::default();
+        for event in events {
+            state.apply(event);
+        }
+        state
+    }
+
+    /// Replays the event log up to and including `until`, or all of it if it is `None`.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use event::State;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let state = State::at(None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn at(
+        until: Option<DateTime<Utc>>,
+        connection: &mut SqliteConnection,
+    ) -> Result<State, Box<dyn Error + Send + Sync>> {
+        let events = LoggedEvent::all(until, None, connection).await?;
+        Ok(State::replay(events.iter().map(|logged| &logged.event)))
+    }
+
+    /// Finds where the labware with the given barcode was at a point in time.
+    ///
+    /// The barcode is run through the `BarcodeParser` pipeline and matched against the barcodes
+    /// labwares were registered with, so labwares which are no longer active are found too.
+    /// Responds with `None` if the labware was not registered yet or was not active by then, and
+    /// with a `NotFoundError` if no labware was ever registered with the barcode.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use event::State;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let march_3rd = Utc.with_ymd_and_hms(2026, 3, 3, 23, 59, 59).unwrap();
+    /// let location = State::labware_location("trac-1", march_3rd, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn labware_location(
+        barcode: &str,
+        at: DateTime<Utc>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<Location>, Box<dyn Error + Send + Sync>> {
+        let not_found = || NotFoundError {
+            message: Message::new("labware-not-found"),
+        };
+        let parsed = BarcodeParser::new(&CONFIG)
+            .parse(barcode)
+            .map_err(|_| not_found())?;
+        let labware_id = sqlx::query_scalar::<_, u32>(
+            "SELECT record_id FROM events WHERE event_type = 'LabwareCreated'
+                AND json_extract(data, '$.barcode') = ? COLLATE NOCASE
+                ORDER BY id DESC LIMIT 1",
+        )
+        .bind(parsed.barcode)
+        .fetch_optional(&mut *connection)
+        .await?
+        .ok_or_else(not_found)?;
+        let events = LoggedEvent::all(Some(at), Some(("Labware", labware_id)), connection).await?;
+        let state = State::replay(events.iter().map(|logged| &logged.event));
+        match state.labwares.get(&labware_id) {
+            Some(labware) => Ok(Some(Location::find(labware.location_id, connection).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Finds what was directly inside of a location at a point in time: its labwares, by the
+    /// barcode they were registered with, and the locations inside of it.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use event::State;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let noon = Utc.with_ymd_and_hms(2024, 3, 3, 12, 0, 0).unwrap();
+    /// let contents = State::contents(&freezer, noon, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn contents(
+        location: &Location,
+        at: DateTime<Utc>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Contents, Box<dyn Error + Send + Sync>> {
+        let events = LoggedEvent::around_location(location.id, at, connection).await?;
+        let state = State::replay(events.iter().map(|logged| &logged.event));
+        let labwares = state
+            .labwares
+            .values()
+            .filter(|labware| labware.location_id == location.id)
+            .map(|labware| labware.barcode.clone())
+            .collect();
+        let mut locations = vec![];
+        for (id, parent_id) in &state.parents {
+            if *parent_id == Some(location.id) {
+                locations.push(Location::find(*id, connection).await?);
+            }
+        }
+        Ok(Contents {
+            labwares,
+            locations,
+        })
+    }
+
+    /// Brings the locations and labwares in line with the state, in a transaction.
+    ///
+    /// Locations are put back inside of their parents and labwares back in their locations.
+    /// Labwares missing from the database are restored with the barcode they were registered
+    /// with, and labwares which are no longer active are retired again. Records the event log knows
+    /// nothing about are
+    /// left as they are. The labware counts of the locations are reconciled afterwards.
+    pub async fn rebuild(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Rebuild, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        let mut rebuild = Rebuild::default();
+        for (id, parent_id) in &self.parents {
+            let result = sqlx::query(
+                "UPDATE locations SET parent_id = ?1 WHERE id = ?2 AND parent_id IS NOT ?1",
+            )
+            .bind(parent_id)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+            rebuild.locations_reparented += result.rows_affected() as u32;
+        }
+        for (id, labware) in &self.labwares {
+            let status =
+                sqlx::query_scalar::<_, LabwareStatus>("SELECT status FROM labwares WHERE id = ?")
+                    .bind(id)
+                    .fetch_optional(&mut *transaction)
+                    .await?;
+            if status.is_some_and(|status| status != LabwareStatus::Active) {
+                sqlx::query(
+                    "UPDATE labwares SET status = 'active', status_reason = NULL WHERE id = ?",
+                )
+                .bind(id)
+                .execute(&mut *transaction)
+                .await?;
+                sqlx::query(
+                    "INSERT INTO labware_barcodes (uuid, labware_id, barcode, is_primary)
+                        VALUES (?, ?, ?, 1)",
+                )
+                .bind(new_uuid())
+                .bind(id)
+                .bind(&labware.barcode)
+                .execute(&mut *transaction)
+                .await?;
+                rebuild.labwares_restored += 1;
+            }
+            let location_id = sqlx::query_scalar::<_, u32>(statements::LABWARE_LOCATION_ID)
+                .bind(id)
+                .fetch_optional(&mut *transaction)
+                .await?;
+            match location_id {
+                Some(location_id) if location_id == labware.location_id => {}
+                Some(_) => {
+                    sqlx::query("UPDATE labwares SET location_id = ? WHERE id = ?")
+                        .bind(labware.location_id)
+                        .bind(id)
+                        .execute(&mut *transaction)
+                        .await?;
+                    sqlx::query(
+                        "DELETE FROM labware_positions WHERE labware_id = ? AND location_id != ?",
+                    )
+                    .bind(id)
+                    .bind(labware.location_id)
+                    .execute(&mut *transaction)
+                    .await?;
+                    rebuild.labwares_moved += 1;
+                }
+                None => {
+                    sqlx::query(
+                        "INSERT INTO labwares (id, uuid, barcode, location_id) VALUES (?, ?, ?, ?)",
+                    )
+                    .bind(id)
+                    .bind(new_uuid())
+                    .bind(&labware.barcode)
+                    .bind(labware.location_id)
+                    .execute(&mut *transaction)
+                    .await?;
+                    sqlx::query(
+                        "INSERT INTO labware_barcodes (uuid, labware_id, barcode, is_primary)
+                            VALUES (?, ?, ?, 1)",
+                    )
+                    .bind(new_uuid())
+                    .bind(id)
+                    .bind(&labware.barcode)
+                    .execute(&mut *transaction)
+                    .await?;
+                    NOT_FOUND_BARCODES
+                        .remove(&labware_key(&labware.barcode))
+                        .await;
+                    rebuild.labwares_restored += 1;
+                }
+            }
+        }
+        for (id, status) in &self.retired {
+            let result =
+                sqlx::query("UPDATE labwares SET status = ?1 WHERE id = ?2 AND status != ?1")
+                    .bind(status)
+                    .bind(id)
+                    .execute(&mut *transaction)
+                    .await?;
+            if result.rows_affected() > 0 {
+                Labware::release(*id, &mut transaction).await?;
+                rebuild.labwares_retired += 1;
+            }
+        }
+        Location::reconcile_labware_counts(&mut transaction).await?;
+        transaction.commit().await?;
+        Ok(rebuild)
+    }
+}
+
+/// What was directly inside of a location at a point in time.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Contents {
+    /// The barcodes of the labwares in the location, in the order they were registered
+    pub labwares: Vec<String>,
+    /// The locations inside of the location
+    pub locations: Vec<Location>,
+}
+
+/// What `State::rebuild` corrected.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Rebuild {
+    /// The locations put back inside of their parents
+    pub locations_reparented: u32,
+    /// The labwares put back in their locations
+    pub labwares_moved: u32,
+    /// The labwares which were missing and were restored
+    pub labwares_restored: u32,
+    /// The labwares which were active but should not be, and were retired
+    pub labwares_retired: u32,
+}
+
+impl Display for Rebuild {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} locations reparented, {} labwares moved, {} labwares restored, {} labwares retired",
+            self.locations_reparented,
+            self.labwares_moved,
+            self.labwares_restored,
+            self.labwares_retired
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::event::*;
+    use crate::models::labware::Labware;
+    use crate::models::location_type::LocationType;
+    use chrono::TimeZone;
+
+    /// Moves the events logged so far to the given day.
+    async fn happened_on(day: u32, connection: &mut SqliteConnection) {
+        sqlx::query("UPDATE events SET created_at = ? WHERE created_at NOT LIKE '2026-03-0%'")
+            .bind(format!("2026-03-0{} 12:00:00", day))
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_replay() {
+        let state = State::replay(&[
+            Event::LocationCreated {
+                location_id: 1,
+                name: "freezer".to_string(),
+                location_type_id: 1,
+                parent_id: None,
+            },
+            Event::LocationCreated {
+                location_id: 2,
+                name: "shelf".to_string(),
+                location_type_id: 1,
+                parent_id: None,
+            },
+            Event::LocationReparented {
+                location_id: 2,
+                parent_id: Some(1),
+                previous_parent_id: None,
+            },
+            Event::LabwareCreated {
+                labware_id: 1,
+                barcode: "lw-1".to_string(),
+                location_id: 1,
+            },
+            Event::LabwareMoved {
+                labware_id: 1,
+                location_id: 2,
+                previous_location_id: 1,
+            },
+            Event::LabwareCreated {
+                labware_id: 2,
+                barcode: "lw-2".to_string(),
+                location_id: 1,
+            },
+            Event::LabwareExhausted {
+                labware_id: 2,
+                location_id: 1,
+            },
+            Event::LabwareCreated {
+                labware_id: 3,
+                barcode: "lw-3".to_string(),
+                location_id: 1,
+            },
+            Event::LabwareRetired {
+                labware_id: 3,
+                location_id: 1,
+                status: LabwareStatus::ShippedOut,
+            },
+            Event::LabwareReactivated {
+                labware_id: 3,
+                barcode: "lw-3".to_string(),
+                location_id: 1,
+            },
+        ]);
+        assert_eq!(state.parents, BTreeMap::from([(1, None), (2, Some(1))]));
+        assert_eq!(state.labwares[&1].location_id, 2);
+        assert!(!state.labwares.contains_key(&2));
+        assert_eq!(state.labwares[&3].location_id, 1);
+        assert_eq!(
+            state.retired,
+            BTreeMap::from([(2, LabwareStatus::Exhausted)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_are_logged_and_append_only() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create("shelf".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        shelf.reparent(Some(freezer.id), &mut conn).await.unwrap();
+        let mut labware = Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        labware.location_id = shelf.id;
+        let labware = Labware::update(&labware, None, &mut conn).await.unwrap();
+        Labware::exhaust(&[labware], None, &mut conn).await.unwrap();
+
+        let events = LoggedEvent::all(None, None, &mut conn).await.unwrap();
+        let names: Vec<&str> = events.iter().map(|logged| logged.event.name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "LocationCreated",
+                "LocationCreated",
+                "LocationReparented",
+                "LabwareCreated",
+                "LabwareMoved",
+                "LabwareExhausted"
+            ]
+        );
+
+        let tampered = sqlx::query("UPDATE events SET data = '{}'")
+            .execute(&mut conn)
+            .await;
+        assert!(tampered.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_labware_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let fridge = Location::create("fridge".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut labware = Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        happened_on(2, &mut conn).await;
+        labware.location_id = fridge.id;
+        let labware = Labware::update(&labware, None, &mut conn).await.unwrap();
+        happened_on(4, &mut conn).await;
+        Labware::exhaust(&[labware], None, &mut conn).await.unwrap();
+        happened_on(6, &mut conn).await;
+
+        let on = |day| Utc.with_ymd_and_hms(2026, 3, day, 23, 59, 59).unwrap();
+        for (day, location_id) in [
+            (1, None),
+            (3, Some(freezer.id)),
+            (5, Some(fridge.id)),
+            (7, None),
+        ] {
+            let location = State::labware_location("LW-1", on(day), &mut conn)
+                .await
+                .unwrap();
+            assert_eq!(location.map(|location| location.id), location_id);
+        }
+
+        let error = State::labware_location("lw-2", on(7), &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<NotFoundError>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_contents() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let fridge = Location::create("fridge".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut mixed_up = Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create_with_parent(
+            "shelf".to_string(),
+            location_type.id,
+            Some(freezer.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        happened_on(2, &mut conn).await;
+        mixed_up.location_id = fridge.id;
+        Labware::update(&mixed_up, None, &mut conn).await.unwrap();
+        let used = Labware::create("lw-2".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        shelf.reparent(Some(fridge.id), &mut conn).await.unwrap();
+        happened_on(4, &mut conn).await;
+        Labware::exhaust(&[used], None, &mut conn).await.unwrap();
+        happened_on(6, &mut conn).await;
+
+        let on = |day| Utc.with_ymd_and_hms(2026, 3, day, 23, 59, 59).unwrap();
+        let contents = State::contents(&freezer, on(3), &mut conn).await.unwrap();
+        assert_eq!(contents.labwares, vec!["lw-1"]);
+        assert_eq!(contents.locations.len(), 1);
+        assert_eq!(contents.locations[0].name, "shelf");
+
+        let contents = State::contents(&freezer, on(5), &mut conn).await.unwrap();
+        assert_eq!(contents.labwares, vec!["lw-2"]);
+        assert!(contents.locations.is_empty());
+
+        let contents = State::contents(&freezer, on(7), &mut conn).await.unwrap();
+        assert!(contents.labwares.is_empty());
+        let contents = State::contents(&fridge, on(7), &mut conn).await.unwrap();
+        assert_eq!(contents.labwares, vec!["lw-1"]);
+        assert_eq!(contents.locations[0].name, "shelf");
+    }
+
+    #[tokio::test]
+    async fn test_rebuild() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create("shelf".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        shelf.reparent(Some(freezer.id), &mut conn).await.unwrap();
+        let moved = Labware::create("lw-1".to_string(), shelf.id, &mut conn)
+            .await
+            .unwrap();
+        let lost = Labware::create("lw-2".to_string(), shelf.id, &mut conn)
+            .await
+            .unwrap();
+        let destroyed = Labware::create("lw-3".to_string(), shelf.id, &mut conn)
+            .await
+            .unwrap();
+        destroyed
+            .change_status(LabwareStatus::Destroyed, Some("dropped"), None, &mut conn)
+            .await
+            .unwrap();
+
+        // The tables drift from the event log, e.g. after being edited by hand
+        for query in [
+            "UPDATE locations SET parent_id = NULL",
+            "UPDATE labwares SET location_id = 1",
+            "DELETE FROM labware_barcodes WHERE labware_id = 2",
+            "DELETE FROM labwares WHERE id = 2",
+            "UPDATE labwares SET status = 'active' WHERE id = 3",
+        ] {
+            sqlx::query(query).execute(&mut conn).await.unwrap();
+        }
+
+        let state = State::at(None, &mut conn).await.unwrap();
+        let rebuild = state.rebuild(&mut conn).await.unwrap();
+        assert_eq!(
+            rebuild,
+            Rebuild {
+                locations_reparented: 1,
+                labwares_moved: 1,
+                labwares_restored: 1,
+                labwares_retired: 1
+            }
+        );
+        let shelf = Location::find(shelf.id, &mut conn).await.unwrap();
+        assert_eq!(shelf.parent_id, Some(freezer.id));
+        assert_eq!(shelf.labwares_count, 2);
+        let labwares = Labware::in_location(shelf.id, &mut conn).await.unwrap();
+        let barcodes: Vec<&str> = labwares.iter().map(|l| l.barcode.as_str()).collect();
+        assert_eq!(
+            barcodes,
+            vec![moved.barcode.as_str(), lost.barcode.as_str()]
+        );
+        let destroyed = Labware::find_with_history("lw-3", &mut conn).await.unwrap();
+        assert_eq!(destroyed.status, LabwareStatus::Destroyed);
+
+        // Rebuilding again changes nothing
+        let rebuild = state.rebuild(&mut conn).await.unwrap();
+        assert_eq!(rebuild, Rebuild::default());
+    }
+}
@@ -0,0 +1,268 @@
+//! The JSON Schemas of the payloads of the events, by event type and version, for consumers of the
+//! event log to validate against.
+//!
+//! Each version of a payload has its schema in `event_schemas/{type}.v{version}.json`. A published
+//! version never changes: a change which would break consumers (removing, renaming or retyping a
+//! field) is a new version with a new schema, while the payload of the previous version keeps a
+//! serde type of its own in a `v{version}` module of this one, for reading the events logged
+//! before. `Event` always serializes the latest version. The tests check the payloads `Event`
+//! emits against the latest schemas, and that payloads logged before still deserialize.
+use crate::models::event::Event;
+
+/// The schema of a version of the payload of an event type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventSchema {
+    /// The name of the event e.g. `LabwareMoved`
+    pub event_type: &'static str,
+    /// The version of the payload, counting from 1
+    pub version: u32,
+    /// The JSON Schema of the payload
+    pub schema: &'static str,
+}
+
+/// Every schema of every event type, oldest version first.
+pub const SCHEMAS: &[EventSchema] = &[
+    EventSchema {
+        event_type: "LocationCreated",
+        version: 1,
+        schema: include_str!("event_schemas/LocationCreated.v1.json"),
+    },
+    EventSchema {
+        event_type: "LocationReparented",
+        version: 1,
+        schema: include_str!("event_schemas/LocationReparented.v1.json"),
+    },
+    EventSchema {
+        event_type: "LocationRenamed",
+        version: 1,
+        schema: include_str!("event_schemas/LocationRenamed.v1.json"),
+    },
+    EventSchema {
+        event_type: "LabwareCreated",
+        version: 1,
+        schema: include_str!("event_schemas/LabwareCreated.v1.json"),
+    },
+    EventSchema {
+        event_type: "LabwareMoved",
+        version: 1,
+        schema: include_str!("event_schemas/LabwareMoved.v1.json"),
+    },
+    EventSchema {
+        event_type: "LabwareExhausted",
+        version: 1,
+        schema: include_str!("event_schemas/LabwareExhausted.v1.json"),
+    },
+    EventSchema {
+        event_type: "LabwareRetired",
+        version: 1,
+        schema: include_str!("event_schemas/LabwareRetired.v1.json"),
+    },
+    EventSchema {
+        event_type: "LabwareReactivated",
+        version: 1,
+        schema: include_str!("event_schemas/LabwareReactivated.v1.json"),
+    },
+];
+
+/// Finds the schema of a version of the payload of an event type.
+/// # Examples
+/// ```
+/// use labwhere::models::event_schema;
+/// let schema = event_schema::find("LabwareMoved", 1).unwrap();
+/// assert!(schema.schema.contains("previous_location_id"));
+/// assert_eq!(event_schema::find("LabwareMoved", 99), None);
+/// ```
+pub fn find(event_type: &str, version: u32) -> Option<&'static EventSchema> {
+    SCHEMAS
+        .iter()
+        .find(|schema| schema.event_type == event_type && schema.version == version)
+}
+
+/// The version of the payload of an event type which is emitted now, if the type is known.
+pub fn current_version(event_type: &str) -> Option<u32> {
+    SCHEMAS
+        .iter()
+        .filter(|schema| schema.event_type == event_type)
+        .map(|schema| schema.version)
+        .max()
+}
+
+impl Event {
+    /// The version of the payload of the event, as emitted now
+    pub fn version(&self) -> u32 {
+        current_version(self.name()).unwrap_or(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::event_schema::*;
+    use crate::models::labware::LabwareStatus;
+    use serde_json::Value;
+
+    /// One event of each type.
+    fn samples() -> Vec<Event> {
+        vec![
+            Event::LocationCreated {
+                location_id: 1,
+                name: "freezer1".to_string(),
+                location_type_id: 1,
+                parent_id: None,
+            },
+            Event::LocationReparented {
+                location_id: 2,
+                parent_id: Some(1),
+                previous_parent_id: None,
+            },
+            Event::LocationRenamed {
+                location_id: 1,
+                name: "freezer2".to_string(),
+                previous_name: "freezer1".to_string(),
+            },
+            Event::LabwareCreated {
+                labware_id: 1,
+                barcode: "lw-1".to_string(),
+                location_id: 1,
+            },
+            Event::LabwareMoved {
+                labware_id: 1,
+                location_id: 2,
+                previous_location_id: 1,
+            },
+            Event::LabwareExhausted {
+                labware_id: 1,
+                location_id: 2,
+            },
+            Event::LabwareRetired {
+                labware_id: 1,
+                location_id: 2,
+                status: LabwareStatus::ShippedOut,
+            },
+            Event::LabwareReactivated {
+                labware_id: 1,
+                barcode: "lw-1".to_string(),
+                location_id: 2,
+            },
+        ]
+    }
+
+    /// The ways a value does not conform to a schema, for the keywords the event schemas use.
+    fn violations(value: &Value, schema: &Value, path: &str) -> Vec<String> {
+        let mut violations = vec![];
+        if let Some(expected) = schema.get("type") {
+            let types: Vec<&str> = match expected {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                expected => expected.as_str().into_iter().collect(),
+            };
+            let actual = match value {
+                Value::Null => "null",
+                Value::Bool(_) => "boolean",
+                Value::Number(n) if n.is_u64() || n.is_i64() => "integer",
+                Value::Number(_) => "number",
+                Value::String(_) => "string",
+                Value::Array(_) => "array",
+                Value::Object(_) => "object",
+            };
+            if !types.contains(&actual) {
+                violations.push(format!("{} is a {}, not {:?}", path, actual, types));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                violations.push(format!("{} is {}, not {}", path, value, expected));
+            }
+        }
+        if let Some(Value::Array(allowed)) = schema.get("enum") {
+            if !allowed.contains(value) {
+                violations.push(format!("{} is {}, not one of {:?}", path, value, allowed));
+            }
+        }
+        if let (Some(minimum), Some(n)) = (schema.get("minimum"), value.as_f64()) {
+            if minimum.as_f64().is_some_and(|minimum| n < minimum) {
+                violations.push(format!("{} is less than {}", path, minimum));
+            }
+        }
+        if let Value::Object(fields) = value {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                if !fields.contains_key(required.as_str().unwrap_or_default()) {
+                    violations.push(format!("{}.{} is missing", path, required));
+                }
+            }
+            for (name, field) in fields {
+                match schema["properties"].get(name) {
+                    Some(field_schema) => violations.extend(self::violations(
+                        field,
+                        field_schema,
+                        &format!("{}.{}", path, name),
+                    )),
+                    None if schema["additionalProperties"] == Value::Bool(false) => {
+                        violations.push(format!("{}.{} is not in the schema", path, name))
+                    }
+                    None => {}
+                }
+            }
+        }
+        violations
+    }
+
+    #[test]
+    fn test_schemas() {
+        for schema in SCHEMAS {
+            let parsed: Value = serde_json::from_str(schema.schema).unwrap();
+            assert_eq!(parsed["title"], schema.event_type);
+            assert_eq!(
+                parsed["$id"],
+                format!("/events/schema/{}/{}", schema.event_type, schema.version)
+            );
+            assert_eq!(parsed["properties"]["type"]["const"], schema.event_type);
+        }
+        assert_eq!(current_version("LabwareMoved"), Some(1));
+        assert_eq!(current_version("LabwareLost"), None);
+    }
+
+    #[test]
+    fn test_every_event_has_a_schema() {
+        let mut sampled: Vec<&str> = samples().iter().map(Event::name).collect();
+        let mut registered: Vec<&str> = SCHEMAS.iter().map(|s| s.event_type).collect();
+        sampled.sort_unstable();
+        registered.sort_unstable();
+        registered.dedup();
+        assert_eq!(sampled, registered);
+    }
+
+    #[test]
+    fn test_emitted_payloads_conform() {
+        for event in samples() {
+            let schema = find(event.name(), event.version()).unwrap();
+            let schema: Value = serde_json::from_str(schema.schema).unwrap();
+            let payload = serde_json::to_value(&event).unwrap();
+            assert_eq!(
+                violations(&payload, &schema, event.name()),
+                Vec::<String>::new()
+            );
+        }
+    }
+
+    #[test]
+    fn test_logged_payloads_deserialize() {
+        // Payloads as version 1 logged them, which must keep reading back as they were
+        let logged = [
+            r#"{"type":"LocationCreated","location_id":1,"name":"freezer1","location_type_id":1,"parent_id":null}"#,
+            r#"{"type":"LocationReparented","location_id":2,"parent_id":1,"previous_parent_id":null}"#,
+            r#"{"type":"LocationRenamed","location_id":1,"name":"freezer2","previous_name":"freezer1"}"#,
+            r#"{"type":"LabwareCreated","labware_id":1,"barcode":"lw-1","location_id":1}"#,
+            r#"{"type":"LabwareMoved","labware_id":1,"location_id":2,"previous_location_id":1}"#,
+            r#"{"type":"LabwareExhausted","labware_id":1,"location_id":2}"#,
+            r#"{"type":"LabwareRetired","labware_id":1,"location_id":2,"status":"shipped-out"}"#,
+            r#"{"type":"LabwareReactivated","labware_id":1,"barcode":"lw-1","location_id":2}"#,
+        ];
+        for (payload, sample) in logged.iter().zip(samples()) {
+            let event: Event = serde_json::from_str(payload).unwrap();
+            assert_eq!(event, sample);
+            assert_eq!(
+                serde_json::to_value(&event).unwrap(),
+                serde_json::from_str::<Value>(payload).unwrap()
+            );
+        }
+    }
+}
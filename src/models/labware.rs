@@ -1,19 +1,116 @@
-use super::location::UNKNOWN_LOCATION;
-use crate::errors::NotFoundError;
+use crate::barcode::parser::BarcodeParser;
+use crate::cache::{labware_key, NOT_FOUND_BARCODES};
+use crate::config::CONFIG;
+use crate::db::statements;
+use crate::errors::{FieldValidationError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::checkout::Checkout;
+use crate::models::event::Event;
+use crate::models::labware_barcode::LabwareBarcode;
 use crate::models::location::Location;
-use sqlx::SqliteConnection;
+use crate::models::location_lock::LocationLock;
+use crate::models::new_uuid;
+use crate::models::reservation::Reservation;
+use crate::models::subscription::Subscription;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// Where a labware is in its lifecycle.
+///
+/// Labwares are `active` until they are used up (`exhausted`), thrown away (`destroyed`) or sent
+/// elsewhere (`shipped-out`). Only active labwares are found by their barcode, counted in their
+/// location or scanned; the others are kept so their history can be looked up. A shipped out
+/// labware can come back and be active again, while exhausted and destroyed labwares are gone for
+/// good.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "TEXT", rename_all = "kebab-case")]
+pub enum LabwareStatus {
+    #[default]
+    Active,
+    Exhausted,
+    Destroyed,
+    ShippedOut,
+}
+
+impl LabwareStatus {
+    /// Parses a status from its name e.g. `shipped-out`.
+    pub fn from_name(name: &str) -> Option<LabwareStatus> {
+        match name {
+            "active" => Some(LabwareStatus::Active),
+            "exhausted" => Some(LabwareStatus::Exhausted),
+            "destroyed" => Some(LabwareStatus::Destroyed),
+            "shipped-out" => Some(LabwareStatus::ShippedOut),
+            _ => None,
+        }
+    }
+
+    /// The name of the status e.g. `shipped-out`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LabwareStatus::Active => "active",
+            LabwareStatus::Exhausted => "exhausted",
+            LabwareStatus::Destroyed => "destroyed",
+            LabwareStatus::ShippedOut => "shipped-out",
+        }
+    }
+
+    /// Whether a labware can go from this status to another.
+    ///
+    /// # Examples
+    /// ```
+    /// use labwhere::models::labware::LabwareStatus;
+    /// assert!(LabwareStatus::Active.can_become(LabwareStatus::ShippedOut));
+    /// assert!(LabwareStatus::ShippedOut.can_become(LabwareStatus::Active));
+    /// assert!(!LabwareStatus::Destroyed.can_become(LabwareStatus::Active));
+    /// ```
+    pub fn can_become(&self, status: LabwareStatus) -> bool {
+        matches!(
+            (self, status),
+            (
+                LabwareStatus::Active,
+                LabwareStatus::Exhausted | LabwareStatus::Destroyed | LabwareStatus::ShippedOut
+            ) | (LabwareStatus::ShippedOut, LabwareStatus::Active)
+        )
+    }
+
+    /// The action the audit of a change to the status is recorded as, e.g. `ship_out`.
+    fn action(&self) -> &'static str {
+        match self {
+            LabwareStatus::Active => "reactivate",
+            LabwareStatus::Exhausted => "exhaust",
+            LabwareStatus::Destroyed => "destroy",
+            LabwareStatus::ShippedOut => "ship_out",
+        }
+    }
+}
 
 /// Labware is stored in a location.
 /// LabWhere needs to know nothing about it apart from its barcode and where it is.
 /// If a labware has no location it's location will be set to unknown automatically
-#[derive(Debug, PartialEq, sqlx::FromRow)]
-struct Labware {
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Labware {
     /// The unique identifier for the Labware
-    id: u32,
-    /// The unique barcode of the Labware
-    barcode: String,
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Labware, used in URLs and event payloads
+    pub uuid: String,
+    /// The unique primary barcode of the Labware. Any aliases are stored as `LabwareBarcode`s.
+    pub barcode: String,
     /// The location ID of the Labware
-    location_id: u32,
+    pub location_id: u32,
+    /// The instance the Labware was created on, if it was synced from another instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Where the Labware is in its lifecycle. A labware which is not active stays in the location
+    /// it was last in, for the record.
+    pub status: LabwareStatus,
+    /// Why the status of the Labware was last changed, if a reason was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
 }
 
 /// Implementation of the Labware struct
@@ -24,19 +121,26 @@ impl Labware {
     /// # #[cfg(doctest)] {
     /// use labware::Labware;
     /// let location:Location = Default::default();
-    /// let labware = Labware::new(1, "trac-1".to_string(), location);
+    /// let labware = Labware::new(1, "trac-1".to_string(), &location);
     /// # }
     /// ```
     ///
-    fn new(id: u32, barcode: String, location: Option<&Location>) -> Labware {
+    fn new(id: u32, barcode: String, location: &Location) -> Labware {
         Labware {
             id,
+            uuid: new_uuid(),
             barcode,
-            location_id: location.unwrap_or(&UNKNOWN_LOCATION).id,
+            location_id: location.id,
+            origin: None,
+            status: LabwareStatus::Active,
+            status_reason: None,
         }
     }
 
     /// Create a new Labware
+    ///
+    /// Returns a `ValidationError` if the barcode is already carried by an active labware, or is
+    /// reserved for a location. Nothing is written unless the labware is created in full.
     /// # Examples
     /// ```
     /// # #[cfg(doctest)] {
@@ -45,28 +149,71 @@ impl Labware {
     /// let labware = Labware::create("trac-1".to_string(), 1, &mut connection);
     /// # }
     /// ```
-    pub(crate) async fn create(
+    pub async fn create(
         barcode: String,
         location_id: u32,
         connection: &mut SqliteConnection,
-    ) -> Result<Labware, sqlx::Error> {
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        LabwareBarcode::ensure_not_in_use(&barcode, &mut transaction).await?;
+        Location::ensure_barcode_not_reserved(&barcode, &mut transaction).await?;
+        let uuid = new_uuid();
         let insert_labware_result =
-            sqlx::query("INSERT INTO labwares (barcode, location_id) VALUES (?, ?)")
+            sqlx::query("INSERT INTO labwares (uuid, barcode, location_id) VALUES (?, ?, ?)")
+                .bind(&uuid)
                 .bind(barcode.clone())
                 .bind(location_id)
-                .execute(&mut *connection)
+                .execute(&mut *transaction)
                 .await?;
         let id = insert_labware_result.last_insert_rowid();
+        Location::count_labwares(location_id, 1, &mut transaction).await?;
+
+        sqlx::query(
+            "INSERT INTO labware_barcodes (uuid, labware_id, barcode, is_primary) VALUES (?, ?, ?, 1)",
+        )
+        .bind(new_uuid())
+        .bind(id)
+        .bind(barcode.clone())
+        .execute(&mut *transaction)
+        .await?;
 
-        let location = sqlx::query_as::<_, Location>("SELECT * FROM locations WHERE id = ?")
+        let location = sqlx::query_as::<_, Location>(statements::LOCATION_BY_ID)
             .bind(location_id)
-            .fetch_one(&mut *connection)
+            .fetch_one(&mut *transaction)
             .await?;
 
-        Ok(Labware::new(id as u32, barcode, Some(&location)))
+        let mut labware = Labware::new(id as u32, barcode, &location);
+        labware.uuid = uuid;
+        Audit::create(
+            "Labware",
+            labware.id,
+            "create",
+            Some(labware.location_id),
+            &labware,
+            &mut transaction,
+        )
+        .await?;
+        Event::LabwareCreated {
+            labware_id: labware.id,
+            barcode: labware.barcode.clone(),
+            location_id: labware.location_id,
+        }
+        .append(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        NOT_FOUND_BARCODES
+            .remove(&labware_key(&labware.barcode))
+            .await;
+
+        Ok(labware)
     }
 
     /// Updates the location of the Labware
+    ///
+    /// If the labware's current location or its new location is locked, the lock's token has to be
+    /// given, otherwise a `LockedError` is returned. Putting a checked out labware in a location
+    /// closes its checkout. The move is made in one transaction, so a failure leaves the labware
+    /// and the counts of both locations as they were.
     /// # Examples
     /// ```
     /// # #[cfg(doctest)] {
@@ -78,32 +225,84 @@ impl Labware {
     /// let location2 = Location::create("location1".to_string(), location_type.id, &mut conn).await.unwrap();
     /// // Update the labware now
     /// labware.location_id = location2.id;
-    /// let updated_labware = Labware::update(&labware, &mut connection);
+    /// let updated_labware = Labware::update(&labware, None, &mut connection);
     /// # }
-    pub(crate) async fn update(
+    pub async fn update(
         labware: &Labware,
+        lock_token: Option<&str>,
         connection: &mut SqliteConnection,
-    ) -> Result<Labware, sqlx::Error> {
-        let update_labware_result = sqlx::query("UPDATE labwares SET location_id = ? WHERE id = ?")
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        let current_location_id = sqlx::query_scalar::<_, u32>(statements::LABWARE_LOCATION_ID)
+            .bind(labware.id)
+            .fetch_one(&mut *transaction)
+            .await?;
+        LocationLock::ensure_unlocked(current_location_id, lock_token, &mut transaction).await?;
+        LocationLock::ensure_unlocked(labware.location_id, lock_token, &mut transaction).await?;
+        if current_location_id != labware.location_id {
+            Reservation::ensure_fillable(labware.location_id, None, lock_token, &mut transaction)
+                .await?;
+        }
+
+        sqlx::query("UPDATE labwares SET location_id = ? WHERE id = ?")
             .bind(labware.location_id)
             .bind(labware.id)
-            .execute(&mut *connection)
+            .execute(&mut *transaction)
+            .await?;
+        if current_location_id != labware.location_id {
+            Location::count_labwares(current_location_id, -1, &mut transaction).await?;
+            Location::count_labwares(labware.location_id, 1, &mut transaction).await?;
+        }
+        Checkout::close(labware.id, labware.location_id, &mut transaction).await?;
+        // A labware taken out of a coordinated location leaves its position free
+        sqlx::query("DELETE FROM labware_positions WHERE labware_id = ? AND location_id != ?")
+            .bind(labware.id)
+            .bind(labware.location_id)
+            .execute(&mut *transaction)
             .await?;
-        let id = update_labware_result.last_insert_rowid();
 
-        let location = sqlx::query_as::<_, Location>("SELECT * FROM locations WHERE id = ?")
+        let location = sqlx::query_as::<_, Location>(statements::LOCATION_BY_ID)
             .bind(labware.location_id)
-            .fetch_one(&mut *connection)
+            .fetch_one(&mut *transaction)
             .await?;
 
-        Ok(Labware::new(
-            id as u32,
-            labware.barcode.clone(),
-            Some(&location),
-        ))
+        let mut updated_labware = Labware::new(labware.id, labware.barcode.clone(), &location);
+        updated_labware.uuid = labware.uuid.clone();
+        updated_labware.origin = labware.origin.clone();
+        let audit = Audit::create(
+            "Labware",
+            updated_labware.id,
+            "update",
+            Some(updated_labware.location_id),
+            &updated_labware,
+            &mut transaction,
+        )
+        .await?;
+        // Subscribers to where the labware was are told it moved out
+        if current_location_id != labware.location_id {
+            Subscription::fan_out(audit.id, current_location_id, &mut transaction).await?;
+            Event::LabwareMoved {
+                labware_id: labware.id,
+                location_id: labware.location_id,
+                previous_location_id: current_location_id,
+            }
+            .append(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(updated_labware)
     }
 
     /// Find labware by barcode
+    ///
+    /// The barcode is run through the `BarcodeParser` pipeline before the database is queried, and
+    /// one which does not parse (e.g. a misread check digit) is an `InvalidBarcodeError`. Only a
+    /// barcode which matches no labware is a `NotFoundError`; database errors are returned as they
+    /// are, so that they can be retried.
+    /// Both the primary barcode and any aliases of the labware are matched, regardless of case.
+    /// Only active labwares are found; see `find_with_history` for the others.
+    /// Barcodes which were recently not found are answered from the negative lookup cache.
     /// # Examples
     /// ```
     /// # #[cfg(doctest)] {
@@ -111,20 +310,347 @@ impl Labware {
     /// let mut connection = init_db("sqlite::memory:").await.unwrap();
     /// let labware = Labware::find_by_barcode("lw-location-1", &mut connection);
     /// # }
-    pub(crate) async fn find_by_barcode(
+    pub async fn find_by_barcode(
         barcode: String,
         connection: &mut SqliteConnection,
-    ) -> Result<Labware, NotFoundError> {
-        match sqlx::query_as::<_, Labware>("SELECT * FROM labwares WHERE barcode = ?")
-            .bind(barcode)
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let parsed = BarcodeParser::new(&CONFIG).parse(&barcode)?;
+        let key = labware_key(&parsed.barcode);
+        if NOT_FOUND_BARCODES.contains(&key).await {
+            return Err(Box::new(NotFoundError {
+                message: Message::new("labware-not-found"),
+            }));
+        }
+        match sqlx::query_as::<_, Labware>(statements::LABWARE_BY_BARCODE)
+            .bind(parsed.barcode)
             .fetch_one(&mut *connection)
             .await
         {
             Ok(labware) => Ok(labware),
-            Err(_) => Err(NotFoundError {
-                message: "Labware not found".to_string(),
-            }),
+            Err(sqlx::Error::RowNotFound) => {
+                NOT_FOUND_BARCODES.insert(&key).await;
+                Err(Box::new(NotFoundError {
+                    message: Message::new("labware-not-found"),
+                }))
+            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Lists the active labwares in a location, by barcode
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware::Labware;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let labwares = Labware::in_location(1, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn in_location(
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Labware>, sqlx::Error> {
+        sqlx::query_as::<_, Labware>(
+            "SELECT * FROM labwares WHERE location_id = ? AND status = 'active'
+                ORDER BY barcode, id",
+        )
+        .bind(location_id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Streams every labware, oldest first, without holding them all in memory
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware::Labware;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let mut labwares = Labware::all(&mut connection);
+    /// while let Some(labware) = labwares.next().await {
+    ///     println!("{}", labware.unwrap().barcode);
+    /// }
+    /// # }
+    /// ```
+    pub fn all(connection: &mut SqliteConnection) -> BoxStream<'_, Result<Labware, sqlx::Error>> {
+        sqlx::query_as::<_, Labware>("SELECT * FROM labwares ORDER BY id").fetch(connection)
+    }
+
+    /// Exhausts labwares, i.e. they are used up and removed from storage
+    ///
+    /// The labwares are kept as `exhausted`, but lose their aliases, positions and checkouts; an
+    /// `exhaust` audit keeps a record of each. Stocktakes keep the barcodes which were scanned. If
+    /// the location of a labware is locked, the lock's token has to be given, otherwise a
+    /// `LockedError` is returned and nothing is exhausted.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware::Labware;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let labwares = Labware::in_location(1, &mut connection).await.unwrap();
+    /// Labware::exhaust(&labwares, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn exhaust(
+        labwares: &[Labware],
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        for labware in labwares {
+            labware
+                .change_status(LabwareStatus::Exhausted, None, lock_token, &mut transaction)
+                .await?;
         }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Finds the labware with a barcode whatever its status: the active labware if there is one,
+    /// or else the labware which carried the barcode last.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware::Labware;
+    /// let labware = Labware::find_with_history("lw-1", &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find_with_history(
+        barcode: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Labware, NotFoundError> {
+        let parsed = BarcodeParser::new(&CONFIG)
+            .parse(barcode)
+            .map_err(|e| NotFoundError { message: e.message })?;
+        sqlx::query_as::<_, Labware>(
+            "SELECT * FROM labwares WHERE barcode = ?1 COLLATE NOCASE
+                OR id IN (SELECT labware_id FROM labware_barcodes WHERE barcode = ?1 COLLATE NOCASE)
+                ORDER BY status = 'active' DESC, id DESC LIMIT 1",
+        )
+        .bind(parsed.barcode)
+        .fetch_optional(&mut *connection)
+        .await
+        .ok()
+        .flatten()
+        .ok_or(NotFoundError {
+            message: Message::new("labware-not-found"),
+        })
+    }
+
+    /// Changes the status of the labware, with the reason why if one is given.
+    ///
+    /// A labware which stops being active is no longer counted in its location, and loses its
+    /// aliases, position and checkout, so its barcode can be used again. A labware which is active
+    /// again is counted in its location again, unless another labware has its barcode by then.
+    ///
+    /// Returns a `ValidationError` if the labware cannot go from its status to the new one (see
+    /// `LabwareStatus::can_become`), and a `LockedError` if its location is locked and the lock's
+    /// token is not given.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware::{Labware, LabwareStatus};
+    /// let labware = labware.change_status(LabwareStatus::Destroyed, Some("dropped"), None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn change_status(
+        &self,
+        status: LabwareStatus,
+        reason: Option<&str>,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        // The labware may have changed since it was read
+        let current: LabwareStatus = sqlx::query_scalar("SELECT status FROM labwares WHERE id = ?")
+            .bind(self.id)
+            .fetch_one(&mut *connection)
+            .await?;
+        if !current.can_become(status) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("labware-status-transition")
+                    .arg("barcode", &self.barcode)
+                    .arg("from", current.name())
+                    .arg("to", status.name()),
+            }));
+        }
+        LocationLock::ensure_unlocked(self.location_id, lock_token, &mut *connection).await?;
+        if status == LabwareStatus::Active {
+            let in_use: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM labwares
+                    WHERE barcode = ? COLLATE NOCASE AND status = 'active')",
+            )
+            .bind(&self.barcode)
+            .fetch_one(&mut *connection)
+            .await?;
+            if in_use {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("barcode-in-use").arg("barcode", &self.barcode),
+                }));
+            }
+        }
+
+        let mut transaction = connection.begin().await?;
+        sqlx::query("UPDATE labwares SET status = ?, status_reason = ? WHERE id = ?")
+            .bind(status)
+            .bind(reason)
+            .bind(self.id)
+            .execute(&mut *transaction)
+            .await?;
+        if status == LabwareStatus::Active {
+            Location::count_labwares(self.location_id, 1, &mut transaction).await?;
+            sqlx::query(
+                "INSERT INTO labware_barcodes (uuid, labware_id, barcode, is_primary)
+                    VALUES (?, ?, ?, 1)",
+            )
+            .bind(new_uuid())
+            .bind(self.id)
+            .bind(&self.barcode)
+            .execute(&mut *transaction)
+            .await?;
+            NOT_FOUND_BARCODES.remove(&labware_key(&self.barcode)).await;
+        } else {
+            Labware::release(self.id, &mut transaction).await?;
+            Location::count_labwares(self.location_id, -1, &mut transaction).await?;
+        }
+        let labware = Labware {
+            uuid: self.uuid.clone(),
+            barcode: self.barcode.clone(),
+            origin: self.origin.clone(),
+            status,
+            status_reason: reason.map(str::to_string),
+            ..*self
+        };
+        Audit::create(
+            "Labware",
+            labware.id,
+            status.action(),
+            Some(labware.location_id),
+            &labware,
+            &mut transaction,
+        )
+        .await?;
+        match status {
+            LabwareStatus::Active => Event::LabwareReactivated {
+                labware_id: labware.id,
+                barcode: labware.barcode.clone(),
+                location_id: labware.location_id,
+            },
+            LabwareStatus::Exhausted => Event::LabwareExhausted {
+                labware_id: labware.id,
+                location_id: labware.location_id,
+            },
+            _ => Event::LabwareRetired {
+                labware_id: labware.id,
+                location_id: labware.location_id,
+                status,
+            },
+        }
+        .append(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(labware)
+    }
+
+    /// Frees what a labware which is no longer active held: its barcodes, so they can be used
+    /// again, its position and its checkout. The stocktakes it was counted in keep it.
+    pub(crate) async fn release(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        for query in [
+            "DELETE FROM labware_positions WHERE labware_id = ?",
+            "DELETE FROM labware_barcodes WHERE labware_id = ?",
+            "DELETE FROM checkouts WHERE labware_id = ?",
+        ] {
+            sqlx::query(query)
+                .bind(id)
+                .execute(&mut *connection)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a `Labware`, validating it on `build`
+///
+/// A labware without a location is put in the unknown location (see `Location::unknown`) when it
+/// is created.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::LabwareBuilder;
+/// let labware = LabwareBuilder::new()
+///     .barcode("trac-1")
+///     .location_id(location.id)
+///     .create(&mut connection)
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct LabwareBuilder {
+    id: u32,
+    barcode: Option<String>,
+    location_id: Option<u32>,
+}
+
+impl LabwareBuilder {
+    pub fn new() -> LabwareBuilder {
+        Default::default()
+    }
+
+    /// The id of an existing labware. Ignored by `create`.
+    pub fn id(mut self, id: u32) -> LabwareBuilder {
+        self.id = id;
+        self
+    }
+
+    pub fn barcode(mut self, barcode: impl Into<String>) -> LabwareBuilder {
+        self.barcode = Some(barcode.into());
+        self
+    }
+
+    pub fn location_id(mut self, location_id: u32) -> LabwareBuilder {
+        self.location_id = Some(location_id);
+        self
+    }
+
+    /// Builds the labware without saving it
+    ///
+    /// The barcode is run through the `BarcodeParser` pipeline. Returns a `FieldValidationError`
+    /// if the barcode is missing or invalid. The location id of a labware without a location is
+    /// left at 0 until it is created.
+    pub fn build(self) -> Result<Labware, FieldValidationError> {
+        let barcode = self.barcode.ok_or_else(|| {
+            FieldValidationError::field("barcode", Message::new("validation-required"))
+        })?;
+        let parsed = BarcodeParser::new(&CONFIG).parse(&barcode).map_err(|e| {
+            FieldValidationError::field(
+                "barcode",
+                Message::new("validation-invalid-barcode").arg("reason", e),
+            )
+        })?;
+        Ok(Labware {
+            id: self.id,
+            uuid: new_uuid(),
+            barcode: parsed.barcode,
+            location_id: self.location_id.unwrap_or_default(),
+            origin: None,
+            status: LabwareStatus::Active,
+            status_reason: None,
+        })
+    }
+
+    /// Validates and saves the labware
+    pub async fn create(
+        self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let location_id = match self.location_id {
+            Some(location_id) => location_id,
+            None => Location::unknown(&mut *connection).await?.id,
+        };
+        let labware = self.location_id(location_id).build()?;
+        Labware::create(labware.barcode, labware.location_id, connection).await
     }
 }
 
@@ -132,25 +658,67 @@ impl Labware {
 mod tests {
     use crate::db::init_db;
     use crate::models::labware::*;
+    use crate::models::location::UNKNOWN_LOCATION_NAME;
     use crate::models::location_type::LocationType;
+    use futures_util::StreamExt;
 
     #[test]
     fn test_labware_new() {
         let location: Location = Default::default();
-        let labware = Labware::new(1, "lw-1".to_string(), Some(&location));
+        let labware = Labware::new(1, "lw-1".to_string(), &location);
         assert_eq!(labware.id, 1);
         assert_eq!(labware.barcode, "lw-1");
         assert_eq!(labware.location_id, location.id);
     }
 
-    // We should have an equal function for the Labware struct that relies on the attributes of the
-    // struct. This will allow us to compare two Labware structs and check if they are equal.
     #[test]
-    fn test_labware_no_location() {
-        let labware = Labware::new(1, "lw-1".to_string(), None);
+    fn test_labware_builder() {
+        let labware = LabwareBuilder::new()
+            .id(1)
+            .barcode(" ]C1lw-1\r\n")
+            .location_id(2)
+            .build()
+            .unwrap();
         assert_eq!(labware.id, 1);
         assert_eq!(labware.barcode, "lw-1");
-        assert_eq!(labware.location_id, UNKNOWN_LOCATION.as_ref().id);
+        assert_eq!(labware.location_id, 2);
+
+        let labware = LabwareBuilder::new().barcode("lw-1").build().unwrap();
+        assert_eq!(labware.location_id, 0);
+
+        assert!(LabwareBuilder::new().location_id(2).build().is_err());
+        assert!(LabwareBuilder::new().barcode(" ").build().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_labware_builder_create() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let labware = LabwareBuilder::new()
+            .barcode("lw-1")
+            .location_id(location.id)
+            .create(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.barcode, "lw-1");
+        assert_eq!(labware.location_id, location.id);
+
+        // A labware without a location is put in the unknown location
+        let labware = LabwareBuilder::new()
+            .barcode("lw-2")
+            .create(&mut conn)
+            .await
+            .unwrap();
+        let unknown = Location::find(labware.location_id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(unknown.name, UNKNOWN_LOCATION_NAME);
+        assert_eq!(unknown.labwares_count, 1);
     }
 
     #[tokio::test]
@@ -170,6 +738,73 @@ mod tests {
         assert_eq!(labware.location_id, location.id);
     }
 
+    #[tokio::test]
+    async fn test_create_labware_with_reserved_barcode() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("reserved".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+
+        let error = Labware::create("LW-RESERVED-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Barcode LW-RESERVED-1 is reserved for location reserved"
+        );
+
+        // The barcode generated for the location's id is reserved even if it kept its old barcode
+        let location = location
+            .rename("renamed".to_string(), false, &mut conn)
+            .await
+            .unwrap();
+        for barcode in ["lw-reserved-1", "lw-renamed-1", "lw-renamed-1-3f9a2c1b.e"] {
+            assert!(Labware::create(barcode.to_string(), location.id, &mut conn)
+                .await
+                .is_err());
+        }
+        for barcode in ["lw-renamed-2", "lw-other-1", "lw-1"] {
+            assert!(Labware::create(barcode.to_string(), location.id, &mut conn)
+                .await
+                .is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_labware_with_barcode_in_use() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let labware = Labware::create("lw-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        LabwareBarcode::create(labware.id, "2D-1".to_string(), false, &mut conn)
+            .await
+            .unwrap();
+
+        for barcode in ["2D-1", "LW-1"] {
+            let error = Labware::create(barcode.to_string(), location.id, &mut conn)
+                .await
+                .unwrap_err();
+            assert!(error.downcast_ref::<ValidationError>().is_some());
+        }
+        // Nothing is left behind by the rejected labwares
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM labwares")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        let location = Location::find(location.id, &mut conn).await.unwrap();
+        assert_eq!(location.labwares_count, 1);
+    }
+
     #[tokio::test]
     async fn update_labware() {
         let mut conn = init_db("sqlite::memory:").await.unwrap();
@@ -190,13 +825,45 @@ mod tests {
 
         // Update the location of the labware
         labware.location_id = location2.id;
-        let updated_labware = Labware::update(&labware, &mut conn).await.unwrap();
+        let updated_labware = Labware::update(&labware, None, &mut conn).await.unwrap();
 
         assert_eq!(updated_labware.barcode, "lw-1");
         assert_eq!(updated_labware.id, labware.id);
         assert_eq!(updated_labware.location_id, location2.id);
     }
 
+    #[tokio::test]
+    async fn update_labware_with_locked_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location1 = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let location2 = Location::create("location2".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut labware = Labware::create("lw-1".to_string(), location1.id, &mut conn)
+            .await
+            .unwrap();
+        let lock = LocationLock::acquire(location1.id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+
+        // Moving out of the locked location requires the token
+        labware.location_id = location2.id;
+        let error = Labware::update(&labware, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<crate::errors::LockedError>());
+
+        let updated_labware = Labware::update(&labware, Some(&lock.token), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(updated_labware.location_id, location2.id);
+    }
+
     #[tokio::test]
     async fn test_find_by_barcode() {
         let mut conn = init_db("sqlite::memory:").await.unwrap();
@@ -214,7 +881,153 @@ mod tests {
             .await
             .unwrap();
 
-        assert_eq!(labware.barcode, fetched_labware.barcode)
+        assert_eq!(labware.barcode, fetched_labware.barcode);
+
+        let fetched_labware = Labware::find_by_barcode("]d2lw-1\r".to_string(), &mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(labware.barcode, fetched_labware.barcode);
+
+        // Matched regardless of case, without changing the stored barcode
+        let fetched_labware = Labware::find_by_barcode("LW-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(fetched_labware.barcode, "lw-1")
+    }
+
+    #[tokio::test]
+    async fn test_all() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        for barcode in ["lw-2", "lw-1"] {
+            Labware::create(barcode.to_string(), location.id, &mut conn)
+                .await
+                .unwrap();
+        }
+
+        let labwares: Vec<String> = Labware::all(&mut conn)
+            .map(|labware| labware.unwrap().barcode)
+            .collect()
+            .await;
+        assert_eq!(labwares, vec!["lw-2", "lw-1"]);
+    }
+
+    #[tokio::test]
+    async fn test_exhaust() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        Checkout::checkout(
+            "lw-1".to_string(),
+            "jane".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let labwares = Labware::in_location(location.id, &mut conn).await.unwrap();
+        assert_eq!(labwares[0].barcode, "lw-1");
+        assert_eq!(labwares[1].barcode, "lw-2");
+        Labware::exhaust(&labwares[..1], None, &mut conn)
+            .await
+            .unwrap();
+
+        Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .expect_err("Labware exhausted");
+        let location = Location::find(location.id, &mut conn).await.unwrap();
+        assert_eq!(location.labwares_count, 1);
+        let audits = Audit::for_location(location.id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits[0].action, "exhaust");
+        assert_eq!(audits[0].auditable_id, labwares[0].id);
+        let exhausted = Labware::find_with_history("lw-1", &mut conn).await.unwrap();
+        assert_eq!(exhausted.status, LabwareStatus::Exhausted);
+    }
+
+    #[tokio::test]
+    async fn test_change_status() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let labware = Labware::create("lw-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+
+        let shipped = labware
+            .change_status(
+                LabwareStatus::ShippedOut,
+                Some("to Hinxton"),
+                None,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        assert_eq!(shipped.status_reason.as_deref(), Some("to Hinxton"));
+        assert!(Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .is_err());
+        let location = Location::find(location.id, &mut conn).await.unwrap();
+        assert_eq!(location.labwares_count, 0);
+
+        // Its barcode can be used while it is away, so it cannot come back until it is free again
+        let stand_in = Labware::create("LW-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        let error = shipped
+            .change_status(LabwareStatus::Active, None, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<ValidationError>().is_some());
+        stand_in
+            .change_status(LabwareStatus::Destroyed, None, None, &mut conn)
+            .await
+            .unwrap();
+
+        let back = shipped
+            .change_status(LabwareStatus::Active, None, None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(back.status, LabwareStatus::Active);
+        let found = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(found.id, labware.id);
+        let location = Location::find(location.id, &mut conn).await.unwrap();
+        assert_eq!(location.labwares_count, 1);
+
+        let error = stand_in
+            .change_status(LabwareStatus::Destroyed, None, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Labware LW-1 cannot go from destroyed to destroyed"
+        );
     }
 
     #[tokio::test]
@@ -224,4 +1037,18 @@ mod tests {
             .await
             .expect_err("Labware not found");
     }
+
+    #[tokio::test]
+    async fn test_find_by_barcode_returns_database_errors() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        sqlx::query("ALTER TABLE labwares RENAME TO old_labwares")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let e = Labware::find_by_barcode("lw-db-error-1".to_string(), &mut conn)
+            .await
+            .unwrap_err();
+        assert!(e.downcast_ref::<sqlx::Error>().is_some(), "{}", e);
+        assert!(e.downcast_ref::<NotFoundError>().is_none());
+    }
 }
@@ -0,0 +1,389 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::cache::{labware_key, NOT_FOUND_BARCODES};
+use crate::config::CONFIG;
+use crate::db::statements;
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::location::Location;
+use crate::models::new_uuid;
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// A barcode carried by a labware.
+///
+/// Labware can carry several barcodes e.g. a plate with both a human-readable and a 2D barcode.
+/// Exactly one of them is the primary barcode, which is also stored on the labware itself.
+/// Every other barcode is an alias which can be used to find the labware.
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+pub struct LabwareBarcode {
+    /// The unique identifier for the LabwareBarcode
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the LabwareBarcode, used in URLs and event payloads
+    pub uuid: String,
+    /// The ID of the labware carrying the barcode
+    pub labware_id: u32,
+    /// The unique barcode
+    pub barcode: String,
+    /// Whether this is the primary barcode of the labware
+    pub is_primary: bool,
+}
+
+/// Implementation of the LabwareBarcode struct
+impl LabwareBarcode {
+    /// Create a new LabwareBarcode
+    fn new(id: u32, labware_id: u32, barcode: String, is_primary: bool) -> LabwareBarcode {
+        LabwareBarcode {
+            id,
+            uuid: new_uuid(),
+            labware_id,
+            barcode,
+            is_primary,
+        }
+    }
+
+    /// Adds a barcode to a labware
+    ///
+    /// The barcode is run through the `BarcodeParser` pipeline so that it is stored in the same form
+    /// it will be looked up in. If `is_primary` is set, the barcode replaces the current primary
+    /// barcode of the labware, which is kept as an alias. An alias of the labware can be made its
+    /// primary barcode this way too.
+    ///
+    /// Returns a `ValidationError` if the barcode is already carried by any labware.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware_barcode::LabwareBarcode;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let alias = LabwareBarcode::create(1, "2D-1234".to_string(), false, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        labware_id: u32,
+        barcode: String,
+        is_primary: bool,
+        connection: &mut SqliteConnection,
+    ) -> Result<LabwareBarcode, Box<dyn Error + Send + Sync>> {
+        let barcode = BarcodeParser::new(&CONFIG).parse(&barcode)?.barcode;
+
+        let mut transaction = connection.begin().await?;
+        let alias = sqlx::query_as::<_, LabwareBarcode>(
+            "SELECT * FROM labware_barcodes
+                WHERE labware_id = ? AND barcode = ? COLLATE NOCASE AND NOT is_primary",
+        )
+        .bind(labware_id)
+        .bind(&barcode)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        if let (Some(mut alias), true) = (alias, is_primary) {
+            LabwareBarcode::make_primary(&alias, &mut transaction).await?;
+            alias.is_primary = true;
+            LabwareBarcode::audit(&alias, "promote_barcode", &mut transaction).await?;
+            transaction.commit().await?;
+            return Ok(alias);
+        }
+        LabwareBarcode::ensure_not_in_use(&barcode, &mut transaction).await?;
+        Location::ensure_barcode_not_reserved(&barcode, &mut transaction).await?;
+
+        let mut labware_barcode = LabwareBarcode::new(0, labware_id, barcode, false);
+        let insert_query_result = sqlx::query(
+            "INSERT INTO labware_barcodes (uuid, labware_id, barcode, is_primary) VALUES (?, ?, ?, 0)",
+        )
+        .bind(&labware_barcode.uuid)
+        .bind(labware_id)
+        .bind(&labware_barcode.barcode)
+        .execute(&mut *transaction)
+        .await?;
+        labware_barcode.id = insert_query_result.last_insert_rowid() as u32;
+        if is_primary {
+            LabwareBarcode::make_primary(&labware_barcode, &mut transaction).await?;
+            labware_barcode.is_primary = true;
+        }
+        LabwareBarcode::audit(&labware_barcode, "add_barcode", &mut transaction).await?;
+        transaction.commit().await?;
+        NOT_FOUND_BARCODES
+            .remove(&labware_key(&labware_barcode.barcode))
+            .await;
+
+        Ok(labware_barcode)
+    }
+
+    /// Makes a barcode the primary barcode of its labware, keeping the previous one as an alias
+    async fn make_primary(
+        labware_barcode: &LabwareBarcode,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE labware_barcodes SET is_primary = (id = ?) WHERE labware_id = ?")
+            .bind(labware_barcode.id)
+            .bind(labware_barcode.labware_id)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("UPDATE labwares SET barcode = ? WHERE id = ?")
+            .bind(&labware_barcode.barcode)
+            .bind(labware_barcode.labware_id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns a `ValidationError` if an active labware carries the barcode, as its primary barcode
+    /// or as an alias, regardless of case.
+    pub(crate) async fn ensure_not_in_use(
+        barcode: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let in_use = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM labwares WHERE status = 'active' AND (barcode = ?1 COLLATE NOCASE
+                OR id IN (SELECT labware_id FROM labware_barcodes WHERE barcode = ?1 COLLATE NOCASE))",
+        )
+        .bind(barcode)
+        .fetch_one(&mut *connection)
+        .await?;
+        if in_use > 0 {
+            return Err(Box::new(ValidationError {
+                message: Message::new("barcode-in-use").arg("barcode", barcode),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Lists the barcodes of a labware, primary barcode first
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware_barcode::LabwareBarcode;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let barcodes = LabwareBarcode::for_labware(1, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn for_labware(
+        labware_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<LabwareBarcode>, sqlx::Error> {
+        sqlx::query_as::<_, LabwareBarcode>(
+            "SELECT * FROM labware_barcodes WHERE labware_id = ? ORDER BY is_primary DESC, id",
+        )
+        .bind(labware_id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Removes an alias from a labware
+    ///
+    /// The primary barcode cannot be removed; another barcode has to be made primary first.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labware_barcode::LabwareBarcode;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// LabwareBarcode::delete(1, "2D-1234".to_string(), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn delete(
+        labware_id: u32,
+        barcode: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let labware_barcode = sqlx::query_as::<_, LabwareBarcode>(
+            "SELECT * FROM labware_barcodes WHERE labware_id = ? AND barcode = ? COLLATE NOCASE",
+        )
+        .bind(labware_id)
+        .bind(barcode.clone())
+        .fetch_optional(&mut *connection)
+        .await?
+        .ok_or(NotFoundError {
+            message: Message::new("barcode-not-found").arg("barcode", &barcode),
+        })?;
+
+        if labware_barcode.is_primary {
+            return Err(Box::new(ValidationError {
+                message: Message::new("barcode-is-primary").arg("barcode", barcode),
+            }));
+        }
+
+        sqlx::query("DELETE FROM labware_barcodes WHERE id = ?")
+            .bind(labware_barcode.id)
+            .execute(&mut *connection)
+            .await?;
+        LabwareBarcode::audit(&labware_barcode, "remove_barcode", connection).await?;
+        Ok(())
+    }
+
+    /// Records a change to the barcodes of a labware against the labware and its location
+    async fn audit(
+        labware_barcode: &LabwareBarcode,
+        action: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Audit, sqlx::Error> {
+        let location_id = sqlx::query_scalar::<_, u32>(statements::LABWARE_LOCATION_ID)
+            .bind(labware_barcode.labware_id)
+            .fetch_optional(&mut *connection)
+            .await?;
+        Audit::create(
+            "Labware",
+            labware_barcode.labware_id,
+            action,
+            location_id,
+            labware_barcode,
+            connection,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::labware::Labware;
+    use crate::models::labware_barcode::*;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+
+    async fn create_labware(connection: &mut SqliteConnection) -> Labware {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), location.id, connection)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_labware_barcode_new() {
+        let labware_barcode = LabwareBarcode::new(1, 2, "2D-1".to_string(), true);
+        assert_eq!(labware_barcode.id, 1);
+        assert_eq!(labware_barcode.labware_id, 2);
+        assert_eq!(labware_barcode.barcode, "2D-1");
+        assert!(labware_barcode.is_primary);
+    }
+
+    #[tokio::test]
+    async fn test_create_alias() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let labware = create_labware(&mut conn).await;
+
+        let alias = LabwareBarcode::create(labware.id, " 2D-1\r\n".to_string(), false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(alias.barcode, "2D-1");
+        assert!(!alias.is_primary);
+
+        let barcodes = LabwareBarcode::for_labware(labware.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(barcodes.len(), 2);
+        assert_eq!(barcodes[0].barcode, "lw-1");
+        assert!(barcodes[0].is_primary);
+        assert_eq!(barcodes[1].barcode, "2D-1");
+
+        let found_labware = Labware::find_by_barcode("2D-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(found_labware.id, labware.id);
+        assert_eq!(found_labware.barcode, "lw-1");
+
+        let audits = Audit::for_location(labware.location_id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits[0].action, "add_barcode");
+        assert_eq!(audits[0].auditable_id, labware.id);
+    }
+
+    #[tokio::test]
+    async fn test_create_primary_alias() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let labware = create_labware(&mut conn).await;
+
+        LabwareBarcode::create(labware.id, "2D-1".to_string(), true, &mut conn)
+            .await
+            .unwrap();
+
+        let barcodes = LabwareBarcode::for_labware(labware.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(barcodes[0].barcode, "2D-1");
+        assert!(barcodes[0].is_primary);
+        assert!(!barcodes[1].is_primary);
+
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.barcode, "2D-1");
+
+        // The previous primary barcode can be promoted back
+        let promoted = LabwareBarcode::create(labware.id, "LW-1".to_string(), true, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(promoted.barcode, "lw-1");
+        assert!(promoted.is_primary);
+        let barcodes = LabwareBarcode::for_labware(labware.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(barcodes.len(), 2);
+        assert_eq!(barcodes[0].barcode, "lw-1");
+        assert!(!barcodes[1].is_primary);
+        let labware = Labware::find_by_barcode("2D-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.barcode, "lw-1");
+        let audits = Audit::for_location(labware.location_id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits[0].action, "promote_barcode");
+    }
+
+    #[tokio::test]
+    async fn test_create_duplicate_alias() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let labware = create_labware(&mut conn).await;
+
+        LabwareBarcode::create(labware.id, "2D-1".to_string(), false, &mut conn)
+            .await
+            .unwrap();
+        let error = LabwareBarcode::create(labware.id, "2D-1".to_string(), false, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+
+        let error = LabwareBarcode::create(labware.id, "lw-1".to_string(), false, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+
+        let error = LabwareBarcode::create(labware.id, "2d-1".to_string(), false, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_delete_alias() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let labware = create_labware(&mut conn).await;
+        LabwareBarcode::create(labware.id, "2D-1".to_string(), false, &mut conn)
+            .await
+            .unwrap();
+
+        LabwareBarcode::delete(labware.id, "2D-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        Labware::find_by_barcode("2D-1".to_string(), &mut conn)
+            .await
+            .expect_err("Labware not found");
+
+        let error = LabwareBarcode::delete(labware.id, "2D-1".to_string(), &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<NotFoundError>());
+
+        let error = LabwareBarcode::delete(labware.id, "lw-1".to_string(), &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+}
@@ -0,0 +1,1009 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::config::CONFIG;
+use crate::db::statements;
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::location_flag::LocationFlag;
+use crate::models::location_lock::LocationLock;
+use crate::models::reservation::Reservation;
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+
+/// The most rows a coordinated location can have, one per letter of the alphabet.
+pub const MAX_ROWS: u32 = 26;
+
+/// The most columns a coordinated location can have.
+pub const MAX_COLUMNS: u32 = 48;
+
+/// The size of a well in the SVG rendering, in pixels.
+const WELL_SIZE: u32 = 40;
+
+/// The space left for the title and the row and column headers in the SVG rendering, in pixels.
+const MARGIN: u32 = 24;
+
+/// The fill of a well holding a labware.
+const OCCUPIED_FILL: &str = "#4caf50";
+
+/// The fill of an empty well.
+const FREE_FILL: &str = "#eeeeee";
+
+/// The longest label drawn in a well; longer barcodes are shortened, and shown in full in the
+/// tooltip of the well.
+const MAX_LABEL_LENGTH: usize = 8;
+
+/// A position in a coordinated location, e.g. `B3` is the third column of the second row.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Well {
+    /// The coordinate of the well e.g. `B3`
+    pub coordinate: String,
+    /// The row of the well, from 1
+    pub row: u32,
+    /// The column of the well, from 1
+    pub column: u32,
+    /// The barcode of the labware in the well, if it is occupied
+    pub labware_barcode: Option<String>,
+}
+
+/// Where a labware ended up in a transfer between coordinated locations.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransferredWell {
+    /// The barcode of the labware
+    pub labware_barcode: String,
+    /// The coordinate of the well the labware was put in, in the destination
+    pub position: String,
+}
+
+/// The labwares moved from one coordinated location to another, keyed by the coordinate of the well
+/// each was taken from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Transfer {
+    /// The name of the location the labwares were taken from
+    pub source: String,
+    /// The name of the location the labwares were put in
+    pub destination: String,
+    /// The well each labware was put in, by the well it was taken from
+    pub positions: BTreeMap<String, TransferredWell>,
+}
+
+/// The grid of a coordinated location such as a rack or a box, with the labware in each well.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Layout {
+    /// The name of the location
+    pub location: String,
+    /// The barcode of the location
+    pub barcode: Option<String>,
+    /// The number of rows
+    pub rows: u32,
+    /// The number of columns
+    pub columns: u32,
+    /// Every well of the location, row by row
+    pub wells: Vec<Well>,
+}
+
+/// The layout of a coordinated location as a grid, which web frontends can render without
+/// working out coordinates.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Grid {
+    /// The name of the location
+    pub location: String,
+    /// The barcode of the location
+    pub barcode: Option<String>,
+    /// The number of rows
+    pub rows: u32,
+    /// The number of columns
+    pub columns: u32,
+    /// One list per row, holding the barcode of the labware in each column, or `null` if the
+    /// well is free
+    pub cells: Vec<Vec<Option<String>>>,
+}
+
+/// Implementation of the Layout struct
+impl Layout {
+    /// Builds the layout of a coordinated location
+    ///
+    /// Returns a `ValidationError` if the location has no rows and columns.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use layout::Layout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let layout = Layout::build(&rack, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn build(
+        location: &Location,
+        connection: &mut SqliteConnection,
+    ) -> Result<Layout, Box<dyn Error + Send + Sync>> {
+        let (rows, columns) = dimensions(location)?;
+        // Every well of the grid, joined with the labware in it if there is one
+        let wells = sqlx::query_as::<_, (u32, u32, Option<String>)>(
+            "WITH RECURSIVE
+                grid_rows(row_index) AS (
+                    SELECT 1 UNION ALL SELECT row_index + 1 FROM grid_rows WHERE row_index < ?1
+                ),
+                grid_columns(column_index) AS (
+                    SELECT 1 UNION ALL
+                    SELECT column_index + 1 FROM grid_columns WHERE column_index < ?2
+                )
+            SELECT r.row_index, c.column_index, l.barcode
+                FROM grid_rows r CROSS JOIN grid_columns c
+                LEFT JOIN labware_positions p ON p.location_id = ?3
+                    AND p.row_index = r.row_index AND p.column_index = c.column_index
+                LEFT JOIN labwares l ON l.id = p.labware_id
+                ORDER BY r.row_index, c.column_index",
+        )
+        .bind(rows)
+        .bind(columns)
+        .bind(location.id)
+        .fetch_all(&mut *connection)
+        .await?
+        .into_iter()
+        .map(|(row, column, labware_barcode)| Well {
+            coordinate: coordinate(row, column),
+            row,
+            column,
+            labware_barcode,
+        })
+        .collect();
+        Ok(Layout {
+            location: location.name.clone(),
+            barcode: location.barcode.clone(),
+            rows,
+            columns,
+            wells,
+        })
+    }
+
+    /// Gives a location rows and columns, making it a coordinated location
+    ///
+    /// Returns a `ValidationError` if a labware would be left outside of the resized grid.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use layout::Layout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let layout = Layout::resize(&rack, 8, 12, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn resize(
+        location: &Location,
+        rows: u32,
+        columns: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Layout, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        let outside = sqlx::query_as::<_, (u32, u32)>(
+            "SELECT row_index, column_index FROM labware_positions
+                WHERE location_id = ? AND (row_index > ? OR column_index > ?)
+                ORDER BY row_index, column_index LIMIT 1",
+        )
+        .bind(location.id)
+        .bind(rows)
+        .bind(columns)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        if let Some((row, column)) = outside {
+            return Err(Box::new(ValidationError {
+                message: Message::new("layout-position-outside")
+                    .arg("location", &location.name)
+                    .arg("position", coordinate(row, column)),
+            }));
+        }
+
+        let layout = set_dimensions(location, rows, columns, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(layout)
+    }
+
+    /// Changes the rows and columns of a coordinated location, moving each labware (and each
+    /// reserved well) so that it keeps its well number, counted row by row.
+    ///
+    /// Going from 2 rows of 6 to 3 rows of 4, the labware in `A5` moves to `B1`. Nothing is changed
+    /// and a `ValidationError` is returned if a labware, or a well of a reservation which has not
+    /// expired, would be numbered past the last well of the new grid. Every labware moved is audited.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use layout::Layout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let layout = Layout::remap(&rack, 12, 8, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn remap(
+        location: &Location,
+        rows: u32,
+        columns: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Layout, Box<dyn Error + Send + Sync>> {
+        let Ok((_, old_columns)) = dimensions(location) else {
+            return Layout::resize(location, rows, columns, connection).await;
+        };
+        let remapped = |row: u32, column: u32| {
+            let index = (row - 1) * old_columns + column - 1;
+            (index < rows * columns).then(|| (index / columns + 1, index % columns + 1))
+        };
+
+        let mut transaction = connection.begin().await?;
+        let reserved = sqlx::query_as::<_, (u32, u32)>(
+            "SELECT p.row_index, p.column_index FROM reservation_positions p
+                JOIN reservations r ON r.id = p.reservation_id
+                WHERE r.location_id = ? AND r.expires_at > datetime('now')
+                ORDER BY p.row_index, p.column_index",
+        )
+        .bind(location.id)
+        .fetch_all(&mut *transaction)
+        .await?;
+        if let Some((row, column)) = reserved
+            .into_iter()
+            .find(|(row, column)| remapped(*row, *column).is_none())
+        {
+            return Err(Box::new(ValidationError {
+                message: Message::new("layout-remap-reserved")
+                    .arg("location", &location.name)
+                    .arg("position", coordinate(row, column))
+                    .arg("wells", rows * columns),
+            }));
+        }
+
+        let occupants = sqlx::query_as::<_, (u32, String, String, u32, u32)>(
+            "SELECT l.id, l.uuid, l.barcode, p.row_index, p.column_index FROM labware_positions p
+                JOIN labwares l ON l.id = p.labware_id
+                WHERE p.location_id = ?
+                ORDER BY p.row_index, p.column_index",
+        )
+        .bind(location.id)
+        .fetch_all(&mut *transaction)
+        .await?;
+        // Moved to negated rows first, so that no labware lands on a well which is yet to be vacated
+        for (id, uuid, barcode, row, column) in occupants {
+            let Some((new_row, new_column)) = remapped(row, column) else {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("layout-remap-outside")
+                        .arg("location", &location.name)
+                        .arg("position", coordinate(row, column))
+                        .arg("barcode", barcode)
+                        .arg("wells", rows * columns),
+                }));
+            };
+            if (new_row, new_column) == (row, column) {
+                continue;
+            }
+            sqlx::query(
+                "UPDATE labware_positions SET row_index = ?, column_index = ? WHERE labware_id = ?",
+            )
+            .bind(-i64::from(new_row))
+            .bind(new_column)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+            Audit::create(
+                "Labware",
+                id,
+                "position",
+                Some(location.id),
+                &serde_json::json!({
+                    "uuid": uuid,
+                    "barcode": barcode,
+                    "position": coordinate(new_row, new_column),
+                    "previous_position": coordinate(row, column),
+                }),
+                &mut transaction,
+            )
+            .await?;
+        }
+        sqlx::query(
+            "UPDATE labware_positions SET row_index = -row_index
+                WHERE location_id = ? AND row_index < 0",
+        )
+        .bind(location.id)
+        .execute(&mut *transaction)
+        .await?;
+
+        // Expired reservations no longer hold their wells, but are remapped all the same
+        sqlx::query(
+            "UPDATE reservation_positions
+                SET row_index = -(((row_index - 1) * ?1 + column_index - 1) / ?2 + 1),
+                    column_index = ((row_index - 1) * ?1 + column_index - 1) % ?2 + 1
+                WHERE reservation_id IN (SELECT id FROM reservations WHERE location_id = ?3)",
+        )
+        .bind(old_columns)
+        .bind(columns)
+        .bind(location.id)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(
+            "UPDATE reservation_positions SET row_index = -row_index
+                WHERE row_index < 0
+                    AND reservation_id IN (SELECT id FROM reservations WHERE location_id = ?)",
+        )
+        .bind(location.id)
+        .execute(&mut *transaction)
+        .await?;
+
+        let layout = set_dimensions(location, rows, columns, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(layout)
+    }
+
+    /// The id and the barcode of the labware in a well of a coordinated location, if there is one.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use layout::Layout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let holder = Layout::labware_at(&rack, 2, 3, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn labware_at(
+        location: &Location,
+        row: u32,
+        column: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<(u32, String)>, sqlx::Error> {
+        sqlx::query_as::<_, (u32, String)>(statements::LABWARE_AT_POSITION)
+            .bind(location.id)
+            .bind(row)
+            .bind(column)
+            .fetch_optional(&mut *connection)
+            .await
+    }
+
+    /// Puts a labware in a well of a coordinated location
+    ///
+    /// A labware from elsewhere is moved into the location first, and an unknown barcode is
+    /// registered as a new labware, as a scan would. If the location (or the current location of
+    /// the labware) is locked, the lock's token has to be given. Returns a `ValidationError` if the
+    /// well does not exist or holds another labware.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use layout::Layout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let layout = Layout::place(&rack, "B3", "lw-1".to_string(), None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn place(
+        location: &Location,
+        position: &str,
+        labware_barcode: String,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Layout, Box<dyn Error + Send + Sync>> {
+        let (rows, columns) = dimensions(location)?;
+        let (row, column) = parse_coordinate(position)
+            .filter(|(row, column)| *row <= rows && *column <= columns)
+            .ok_or_else(|| ValidationError {
+                message: Message::new("layout-invalid-position")
+                    .arg("location", &location.name)
+                    .arg("position", position),
+            })?;
+        let labware_barcode = BarcodeParser::new(&CONFIG).parse(&labware_barcode)?.barcode;
+        LocationFlag::ensure_not_quarantined(location, &mut *connection).await?;
+
+        let mut transaction = connection.begin().await?;
+        LocationLock::ensure_unlocked(location.id, lock_token, &mut transaction).await?;
+        Reservation::ensure_fillable(
+            location.id,
+            Some((row, column)),
+            lock_token,
+            &mut transaction,
+        )
+        .await?;
+        let labware =
+            match Labware::find_by_barcode(labware_barcode.clone(), &mut transaction).await {
+                Ok(mut labware) if labware.location_id != location.id => {
+                    labware.location_id = location.id;
+                    Labware::update(&labware, lock_token, &mut transaction).await?
+                }
+                Ok(labware) => labware,
+                Err(_) => Labware::create(labware_barcode, location.id, &mut transaction).await?,
+            };
+
+        let holder = Layout::labware_at(location, row, column, &mut transaction).await?;
+        if let Some((holder_id, holder_barcode)) = holder {
+            if holder_id != labware.id {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("layout-position-taken")
+                        .arg("location", &location.name)
+                        .arg("position", coordinate(row, column))
+                        .arg("barcode", holder_barcode),
+                }));
+            }
+        }
+
+        sqlx::query(
+            "INSERT INTO labware_positions (labware_id, location_id, row_index, column_index)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT (labware_id) DO UPDATE SET location_id = excluded.location_id,
+                    row_index = excluded.row_index, column_index = excluded.column_index",
+        )
+        .bind(labware.id)
+        .bind(location.id)
+        .bind(row)
+        .bind(column)
+        .execute(&mut *transaction)
+        .await?;
+        Audit::create(
+            "Labware",
+            labware.id,
+            "position",
+            Some(location.id),
+            &serde_json::json!({
+                "uuid": labware.uuid,
+                "barcode": labware.barcode,
+                "position": coordinate(row, column),
+            }),
+            &mut transaction,
+        )
+        .await?;
+        let layout = Layout::build(location, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(layout)
+    }
+
+    /// Moves every labware of a coordinated location to another one, all or nothing.
+    ///
+    /// Each labware keeps its coordinate, or with `compact` is put in the first free well of the
+    /// destination, row by row, in the order of the wells it was taken from. Returns a
+    /// `ValidationError` if a well is taken or outside of the destination, or if there are not enough
+    /// free wells, and nothing is moved. Every labware moved is audited.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use layout::Layout;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let transfer = Layout::transfer(&box1, &box2, false, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn transfer(
+        source: &Location,
+        destination: &Location,
+        compact: bool,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Transfer, Box<dyn Error + Send + Sync>> {
+        if source.id == destination.id {
+            return Err(Box::new(ValidationError {
+                message: Message::new("layout-transfer-same").arg("location", &source.name),
+            }));
+        }
+        dimensions(source)?;
+        let (rows, columns) = dimensions(destination)?;
+        LocationFlag::ensure_not_quarantined(destination, &mut *connection).await?;
+
+        let mut transaction = connection.begin().await?;
+        let occupants = sqlx::query_as::<_, (String, u32, u32)>(
+            "SELECT l.barcode, p.row_index, p.column_index FROM labware_positions p
+                JOIN labwares l ON l.id = p.labware_id
+                WHERE p.location_id = ?
+                ORDER BY p.row_index, p.column_index",
+        )
+        .bind(source.id)
+        .fetch_all(&mut *transaction)
+        .await?;
+        let targets: Vec<(u32, u32)> = if compact {
+            let free: Vec<(u32, u32)> = Layout::build(destination, &mut transaction)
+                .await?
+                .wells
+                .into_iter()
+                .filter(|well| well.labware_barcode.is_none())
+                .map(|well| (well.row, well.column))
+                .collect();
+            if free.len() < occupants.len() {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("layout-transfer-full")
+                        .arg("location", &destination.name)
+                        .arg("count", occupants.len())
+                        .arg("free", free.len()),
+                }));
+            }
+            free
+        } else {
+            occupants
+                .iter()
+                .map(|(_, row, column)| (*row, *column))
+                .collect()
+        };
+
+        let mut positions = BTreeMap::new();
+        for ((barcode, row, column), (to_row, to_column)) in occupants.into_iter().zip(targets) {
+            if to_row > rows || to_column > columns {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("layout-invalid-position")
+                        .arg("location", &destination.name)
+                        .arg("position", coordinate(to_row, to_column)),
+                }));
+            }
+            let holder =
+                Layout::labware_at(destination, to_row, to_column, &mut transaction).await?;
+            if let Some((_, holder_barcode)) = holder {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("layout-position-taken")
+                        .arg("location", &destination.name)
+                        .arg("position", coordinate(to_row, to_column))
+                        .arg("barcode", holder_barcode),
+                }));
+            }
+            Reservation::ensure_fillable(
+                destination.id,
+                Some((to_row, to_column)),
+                lock_token,
+                &mut transaction,
+            )
+            .await?;
+
+            let mut labware = Labware::find_by_barcode(barcode, &mut transaction).await?;
+            labware.location_id = destination.id;
+            let labware = Labware::update(&labware, lock_token, &mut transaction).await?;
+            sqlx::query(
+                "INSERT INTO labware_positions (labware_id, location_id, row_index, column_index)
+                    VALUES (?, ?, ?, ?)",
+            )
+            .bind(labware.id)
+            .bind(destination.id)
+            .bind(to_row)
+            .bind(to_column)
+            .execute(&mut *transaction)
+            .await?;
+            Audit::create(
+                "Labware",
+                labware.id,
+                "position",
+                Some(destination.id),
+                &serde_json::json!({
+                    "uuid": labware.uuid,
+                    "barcode": labware.barcode,
+                    "position": coordinate(to_row, to_column),
+                    "previous_location": source.name,
+                    "previous_position": coordinate(row, column),
+                }),
+                &mut transaction,
+            )
+            .await?;
+            positions.insert(
+                coordinate(row, column),
+                TransferredWell {
+                    labware_barcode: labware.barcode,
+                    position: coordinate(to_row, to_column),
+                },
+            );
+        }
+        transaction.commit().await?;
+        Ok(Transfer {
+            source: source.name.clone(),
+            destination: destination.name.clone(),
+            positions,
+        })
+    }
+
+    /// The layout as a grid for web frontends to render
+    pub fn to_grid(&self) -> Grid {
+        Grid {
+            location: self.location.clone(),
+            barcode: self.barcode.clone(),
+            rows: self.rows,
+            columns: self.columns,
+            cells: self
+                .wells
+                .chunks(self.columns as usize)
+                .map(|row| {
+                    row.iter()
+                        .map(|well| well.labware_barcode.clone())
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    /// Renders the layout as an SVG image
+    ///
+    /// Occupied wells are green and labelled with the barcode of their labware, free wells are
+    /// grey. Hovering over a well shows its coordinate and the full barcode.
+    pub fn to_svg(&self) -> String {
+        let width = MARGIN + self.columns * WELL_SIZE;
+        let height = MARGIN * 2 + self.rows * WELL_SIZE;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+                viewBox=\"0 0 {width} {height}\" font-family=\"sans-serif\">\n"
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{MARGIN}\" y=\"16\" font-size=\"14\" font-weight=\"bold\">{}</text>",
+            escape(&self.location)
+        );
+        for column in 1..=self.columns {
+            let _ = writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"middle\">{column}</text>",
+                MARGIN + (column - 1) * WELL_SIZE + WELL_SIZE / 2,
+                MARGIN * 2 - 6
+            );
+        }
+        for row in 1..=self.rows {
+            let _ = writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" font-size=\"12\" text-anchor=\"middle\">{}</text>",
+                MARGIN / 2,
+                MARGIN * 2 + (row - 1) * WELL_SIZE + WELL_SIZE / 2 + 4,
+                row_letter(row)
+            );
+        }
+        for well in &self.wells {
+            let x = MARGIN + (well.column - 1) * WELL_SIZE;
+            let y = MARGIN * 2 + (well.row - 1) * WELL_SIZE;
+            let (fill, tooltip) = match &well.labware_barcode {
+                Some(barcode) => (OCCUPIED_FILL, format!("{}: {}", well.coordinate, barcode)),
+                None => (FREE_FILL, well.coordinate.clone()),
+            };
+            let _ = write!(
+                svg,
+                "<g><title>{}</title><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"4\" \
+                    fill=\"{fill}\" stroke=\"#9e9e9e\"/>",
+                escape(&tooltip),
+                x + 2,
+                y + 2,
+                WELL_SIZE - 4,
+                WELL_SIZE - 4
+            );
+            if let Some(barcode) = &well.labware_barcode {
+                let _ = write!(
+                    svg,
+                    "<text x=\"{}\" y=\"{}\" font-size=\"8\" text-anchor=\"middle\">{}</text>",
+                    x + WELL_SIZE / 2,
+                    y + WELL_SIZE / 2 + 3,
+                    escape(&label(barcode))
+                );
+            }
+            svg.push_str("</g>\n");
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// The coordinate of a well e.g. `B3` for the third column of the second row.
+pub fn coordinate(row: u32, column: u32) -> String {
+    format!("{}{}", row_letter(row), column)
+}
+
+/// Parses a coordinate such as `B3` (or `b3`) into its row and column, both counted from 1.
+///
+/// Returns `None` if it is not a coordinate.
+pub fn parse_coordinate(coordinate: &str) -> Option<(u32, u32)> {
+    let mut chars = coordinate.trim().chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    if !letter.is_ascii_uppercase() {
+        return None;
+    }
+    let column = chars.as_str().parse::<u32>().ok().filter(|c| *c > 0)?;
+    Some((letter as u32 - 'A' as u32 + 1, column))
+}
+
+/// The letter of a row, counted from 1.
+fn row_letter(row: u32) -> char {
+    char::from_u32('A' as u32 + row - 1).unwrap_or('?')
+}
+
+/// Stores the rows and columns of a location, audited as a resize, and builds its new layout.
+async fn set_dimensions(
+    location: &Location,
+    rows: u32,
+    columns: u32,
+    connection: &mut SqliteConnection,
+) -> Result<Layout, Box<dyn Error + Send + Sync>> {
+    sqlx::query("UPDATE locations SET rows = ?, columns = ? WHERE id = ?")
+        .bind(rows)
+        .bind(columns)
+        .bind(location.id)
+        .execute(&mut *connection)
+        .await?;
+    let location = Location::find(location.id, &mut *connection).await?;
+    Audit::create(
+        "Location",
+        location.id,
+        "resize",
+        Some(location.id),
+        &location,
+        &mut *connection,
+    )
+    .await?;
+    Layout::build(&location, connection).await
+}
+
+/// The rows and columns of a location, or a `ValidationError` if it is not coordinated.
+pub(crate) fn dimensions(location: &Location) -> Result<(u32, u32), ValidationError> {
+    match (location.rows, location.columns) {
+        (Some(rows), Some(columns)) if rows > 0 && columns > 0 => Ok((rows, columns)),
+        _ => Err(ValidationError {
+            message: Message::new("layout-not-coordinated").arg("location", &location.name),
+        }),
+    }
+}
+
+/// The label drawn in a well, shortened to the end of the barcode, which tells labwares apart.
+fn label(barcode: &str) -> String {
+    let count = barcode.chars().count();
+    if count <= MAX_LABEL_LENGTH {
+        barcode.to_string()
+    } else {
+        let tail: String = barcode.chars().skip(count - MAX_LABEL_LENGTH + 1).collect();
+        format!("…{}", tail)
+    }
+}
+
+/// Escapes text for use in SVG.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::errors::ValidationError;
+    use crate::models::layout::*;
+    use crate::models::location_type::LocationType;
+
+    async fn create_rack(connection: &mut SqliteConnection) -> Location {
+        let location_type = LocationType::create("Rack".to_string(), connection)
+            .await
+            .unwrap();
+        let rack = Location::create("rack1".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        Layout::resize(&rack, 2, 3, connection).await.unwrap();
+        Location::find(rack.id, connection).await.unwrap()
+    }
+
+    #[test]
+    fn test_coordinates() {
+        assert_eq!(coordinate(2, 3), "B3");
+        assert_eq!(parse_coordinate("B3"), Some((2, 3)));
+        assert_eq!(parse_coordinate("h12"), Some((8, 12)));
+        assert_eq!(parse_coordinate("B0"), None);
+        assert_eq!(parse_coordinate("3B"), None);
+        assert_eq!(parse_coordinate(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_build_requires_dimensions() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let error = Layout::build(&freezer, &mut conn).await.unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_place() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_rack(&mut conn).await;
+
+        let layout = Layout::place(&rack, "B3", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(layout.wells.len(), 6);
+        assert_eq!(layout.wells[5].coordinate, "B3");
+        assert_eq!(layout.wells[5].labware_barcode.as_deref(), Some("lw-1"));
+        assert!(layout.wells[0].labware_barcode.is_none());
+
+        // Moving within the location frees the old well
+        let layout = Layout::place(&rack, "A1", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(layout.wells[0].labware_barcode.as_deref(), Some("lw-1"));
+        assert!(layout.wells[5].labware_barcode.is_none());
+
+        let error = Layout::place(&rack, "A1", "lw-2".to_string(), None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "A1 in rack1 is taken by lw-1");
+        let error = Layout::place(&rack, "C1", "lw-2".to_string(), None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "C1 is not a position in rack1");
+    }
+
+    #[tokio::test]
+    async fn test_to_grid() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_rack(&mut conn).await;
+        let layout = Layout::place(&rack, "B2", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+
+        let grid = layout.to_grid();
+        assert_eq!(grid.location, "rack1");
+        assert_eq!(
+            grid.cells,
+            vec![
+                vec![None, None, None],
+                vec![None, Some("lw-1".to_string()), None]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_moving_out_frees_the_well() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_rack(&mut conn).await;
+        Layout::place(&rack, "A2", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+
+        let location_type = LocationType::create("Bench".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let elsewhere = Location::create("bench".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        labware.location_id = elsewhere.id;
+        Labware::update(&labware, None, &mut conn).await.unwrap();
+
+        let layout = Layout::build(&rack, &mut conn).await.unwrap();
+        assert!(layout
+            .wells
+            .iter()
+            .all(|well| well.labware_barcode.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_resize_keeps_labwares_inside() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_rack(&mut conn).await;
+        Layout::place(&rack, "B2", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+
+        let error = Layout::resize(&rack, 1, 3, &mut conn).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot resize rack1 while B2 is occupied"
+        );
+        let layout = Layout::resize(&rack, 4, 4, &mut conn).await.unwrap();
+        assert_eq!(layout.wells.len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_remap_keeps_well_numbers() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_rack(&mut conn).await;
+        for (position, barcode) in [("A3", "lw-1"), ("B1", "lw-2"), ("B3", "lw-3")] {
+            Layout::place(&rack, position, barcode.to_string(), None, &mut conn)
+                .await
+                .unwrap();
+        }
+
+        let error = Layout::remap(&rack, 1, 5, &mut conn).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot remap rack1: lw-3 in B3 would be past the last of its 5 wells"
+        );
+        let layout = Layout::build(&rack, &mut conn).await.unwrap();
+        assert_eq!((layout.rows, layout.columns), (2, 3));
+
+        let layout = Layout::remap(&rack, 3, 2, &mut conn).await.unwrap();
+        let occupied: Vec<_> = layout
+            .wells
+            .iter()
+            .filter_map(|well| Some((well.coordinate.as_str(), well.labware_barcode.as_deref()?)))
+            .collect();
+        assert_eq!(
+            occupied,
+            vec![("B1", "lw-1"), ("B2", "lw-2"), ("C2", "lw-3")]
+        );
+        let audits = Audit::for_location(rack.id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            audits
+                .iter()
+                .filter(|audit| audit.action == "position")
+                .count(),
+            6
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transfer() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_rack(&mut conn).await;
+        let location_type = LocationType::create("Box".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let small = Location::create("box1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Layout::resize(&small, 2, 2, &mut conn).await.unwrap();
+        let small = Location::find(small.id, &mut conn).await.unwrap();
+        for (position, barcode) in [("A3", "lw-1"), ("B1", "lw-2")] {
+            Layout::place(&rack, position, barcode.to_string(), None, &mut conn)
+                .await
+                .unwrap();
+        }
+        Layout::place(&small, "A1", "lw-3".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+
+        // A3 is outside of the box, so nothing is moved
+        let error = Layout::transfer(&rack, &small, false, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "A3 is not a position in box1");
+        assert_eq!(
+            Labware::in_location(rack.id, &mut conn)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let transfer = Layout::transfer(&rack, &small, true, None, &mut conn)
+            .await
+            .unwrap();
+        let positions: Vec<_> = transfer
+            .positions
+            .iter()
+            .map(|(from, well)| {
+                (
+                    from.as_str(),
+                    well.labware_barcode.as_str(),
+                    well.position.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(positions, vec![("A3", "lw-1", "A2"), ("B1", "lw-2", "B1")]);
+        assert!(Labware::in_location(rack.id, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+        let layout = Layout::build(&small, &mut conn).await.unwrap();
+        let free: Vec<_> = layout
+            .wells
+            .iter()
+            .filter(|well| well.labware_barcode.is_none())
+            .map(|well| well.coordinate.as_str())
+            .collect();
+        assert_eq!(free, vec!["B2"]);
+
+        Layout::place(&rack, "A1", "lw-4".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+        let error = Layout::transfer(&small, &rack, false, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "A1 in rack1 is taken by lw-4");
+    }
+
+    #[tokio::test]
+    async fn test_to_svg() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_rack(&mut conn).await;
+        let layout = Layout::place(&rack, "A1", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+
+        let svg = layout.to_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.contains("<title>A1: lw-1</title>"));
+        assert!(svg.contains(OCCUPIED_FILL));
+        assert_eq!(svg.matches(FREE_FILL).count(), 5);
+        assert_eq!(label("lw-1234567890"), "…4567890");
+        assert_eq!(escape("<rack & \"1\">"), "&lt;rack &amp; &quot;1&quot;&gt;");
+    }
+}
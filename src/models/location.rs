@@ -1,50 +1,85 @@
-use crate::errors::NotFoundError;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use sqlx::SqliteConnection;
+use crate::barcode;
+use crate::barcode::check_digit::CheckDigitScheme;
+use crate::barcode::parser::BarcodeParser;
+use crate::barcode::signature::{self, SigningKey};
+use crate::cache::{location_key, NOT_FOUND_BARCODES};
+use crate::config::{Config, CONFIG};
+use crate::db::statements;
+use crate::errors::{FieldValidationError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::metrics::acquire;
+use crate::models::audit::Audit;
+use crate::models::event::Event;
+use crate::models::new_uuid;
+use crate::validation;
+use log::{error, info, warn};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection, SqlitePool};
 use std::error::Error;
-use std::fmt::{Debug, Display, Formatter};
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
 use PartialEq;
 
-/// The `UNKNOWN_LOCATION` constant is initialized only when it is first accessed.
-///  This can save resources if the constant is not used during the execution of the program.
-/// Lazy ensures that the initialization is thread-safe.
-///  If multiple threads try to access UNKNOWN_LOCATION at the same time, Lazy guarantees that the initialization happens only once.
-/// The `Box<Location>` involves heap allocation.
-///  Using Lazy ensures that this allocation happens only when necessary, avoiding unnecessary memory usage if the constant is never used.
-/// The initialization of `UNKNOWN_LOCATION` involves creating a `Location` struct with specific values.
-///  Lazy allows you to encapsulate this initialization logic in a closure, making the code cleaner and more maintainable.
-///
-/// `one_cell` is coming into the standard Rust library (it is already in the nightly build).
+/// How often the labware counts of locations are checked against the labwares table.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The name of the location labwares are put in when they are created without one.
+pub const UNKNOWN_LOCATION_NAME: &str = "UNKNOWN";
+
+/// The barcode of the unknown location, which identifies it in the database.
+pub const UNKNOWN_LOCATION_BARCODE: &str = "lw-unknown-999";
+
+/// The unknown location of the server's database, once `Location::cache_unknown` has read it.
 ///
-/// `static` keyword: https://doc.rust-lang.org/std/keyword.static.html
-pub(crate) static UNKNOWN_LOCATION: Lazy<Box<Location>> = Lazy::new(|| {
-    Box::new(
-        Location::new(
-            999,
-            "UNKNOWN".to_string(),
-            1,
-            Some("lw-unknown-999".to_string()),
-        )
-        .unwrap(),
-    )
-});
+/// The database is the source of truth, so that every instance of the server sharing it agrees on
+/// the unknown location; this is only a copy to save reading it for each labware. Nothing else
+/// which lives in this process may stand in for a row (see the statics in `cache`, which move to
+/// Redis when there are several instances).
+static UNKNOWN_LOCATION: OnceCell<Location> = OnceCell::new();
 
 /// Location of the Labware
-#[derive(Debug, PartialEq, sqlx::FromRow)]
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
 pub struct Location {
     /// ID of the location record
+    #[serde(skip_serializing)]
     pub id: u32,
-    /// Name of the location
+    /// The public identifier of the Location, used in URLs and event payloads
+    pub uuid: String,
+    /// Name of the location, following `Config::location_name_rules`
     pub name: String,
     /// The barcode of the location
     pub barcode: Option<String>,
     /// The id of the location_type
     location_type_id: u32,
+    /// The id of the location this location is inside of, if any
+    pub parent_id: Option<u32>,
+    /// The number of rows of a coordinated location such as a rack or a box, if it has positions
+    pub rows: Option<u32>,
+    /// The number of columns of a coordinated location, if it has positions
+    pub columns: Option<u32>,
+    /// The number of labwares directly in the location. Kept up to date as labwares move, so
+    /// nothing has to count the labwares table.
+    pub labwares_count: u32,
+    /// The instance the location was created on, if it was synced from another instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// The user or team responsible for the location. Notifications about the location are sent
+    /// to the members of the team, if it is one of `Config::team_emails`.
+    pub owner: Option<String>,
+    /// The email address notifications about the location are sent to
+    pub contact_email: Option<String>,
+    /// Anything people should know about the location, e.g. who to ask before moving things
+    pub notes: Option<String>,
+    /// The barcode the location was found by, if it is one the location had before its barcode
+    /// was regenerated. Lets a response tell that an old label was scanned.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_via_alias: Option<String>,
 }
 
 /// Implementation of the Location struct
-impl<'a> Location {
+impl Location {
     /// Create a new Location
     /// # Examples
     ///
@@ -67,18 +102,24 @@ impl<'a> Location {
         name: String,
         location_type_id: u32,
         barcode: Option<String>,
-    ) -> Result<Location, NameFormatError> {
-        if !Location::validate_name(name.clone()) {
-            return Err(NameFormatError {
-                message: "Invalid name format".to_string(),
-            });
-        }
+    ) -> Result<Location, FieldValidationError> {
         let location = Location {
             id,
-            name: name.clone(),
+            uuid: new_uuid(),
+            name,
             barcode,
             location_type_id,
+            parent_id: None,
+            rows: None,
+            columns: None,
+            labwares_count: 0,
+            origin: None,
+            owner: None,
+            contact_email: None,
+            notes: None,
+            resolved_via_alias: None,
         };
+        CONFIG.location_name_rules.validate(&location.name)?;
         Ok(location)
     }
 
@@ -90,21 +131,55 @@ impl<'a> Location {
     /// let location = Location::create("location1".to_string(), 1).await.unwrap();
     /// # }
     /// ```
-    pub(crate) async fn create(
+    pub async fn create(
         name: String,
         location_type_id: u32,
         connection: &mut SqliteConnection,
     ) -> Result<Location, sqlx::Error> {
-        let insert_query_result =
-            sqlx::query("INSERT INTO locations (name, location_type_id) VALUES (?, ?)")
-                .bind(name.clone())
-                .bind(location_type_id)
-                .execute(&mut *connection)
-                .await?;
+        Location::create_with_parent(name, location_type_id, None, connection).await
+    }
+
+    /// Create a new Location inside of another location
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// let freezer = Location::create("freezer1".to_string(), 1).await.unwrap();
+    /// let shelf = Location::create_with_parent("shelf1".to_string(), 1, Some(freezer.id)).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create_with_parent(
+        name: String,
+        location_type_id: u32,
+        parent_id: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, sqlx::Error> {
+        let uuid = new_uuid();
+        let insert_query_result = sqlx::query(
+            "INSERT INTO locations (uuid, name, location_type_id, parent_id) VALUES (?, ?, ?, ?)",
+        )
+        .bind(&uuid)
+        .bind(name.clone())
+        .bind(location_type_id)
+        .bind(parent_id)
+        .execute(&mut *connection)
+        .await?;
         let id = insert_query_result.last_insert_rowid();
 
-        let mut location = Location::new(id as u32, name.clone(), location_type_id, None).unwrap();
-        let barcode = location.create_barcode();
+        // Not validated, as names are checked before they get here and the unknown location or a
+        // location synced from another site may follow other rules
+        let mut location = Location {
+            id: id as u32,
+            uuid,
+            name: name.clone(),
+            location_type_id,
+            parent_id,
+            ..Default::default()
+        };
+        let barcode = location.create_barcode(
+            CONFIG.barcode_check_digit,
+            CONFIG.barcode_signing_keys.first(),
+        );
 
         // Catch errors (if any) and handle
         sqlx::query("UPDATE locations SET barcode = ? WHERE id = ?")
@@ -112,11 +187,110 @@ impl<'a> Location {
             .bind(id)
             .execute(&mut *connection)
             .await?;
+        NOT_FOUND_BARCODES
+            .remove(&location_key(
+                location.barcode.as_deref().unwrap_or_default(),
+            ))
+            .await;
+
+        Audit::create(
+            "Location",
+            location.id,
+            "create",
+            Some(location.id),
+            &location,
+            &mut *connection,
+        )
+        .await?;
+        Event::LocationCreated {
+            location_id: location.id,
+            name: location.name.clone(),
+            location_type_id,
+            parent_id,
+        }
+        .append(connection)
+        .await?;
 
         Ok(location)
     }
 
+    /// Moves the location inside of another location, or to the top level if `parent_id` is
+    /// `None`, taking everything inside of it along.
+    ///
+    /// Returns a `NotFoundError` if the parent does not exist, and a `ValidationError` if the
+    /// parent is the location itself or inside of it.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// let shelf = shelf.reparent(Some(freezer.id), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn reparent(
+        &self,
+        parent_id: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        if let Some(parent_id) = parent_id {
+            Location::find(parent_id, &mut *connection).await?;
+            let inside: bool = sqlx::query_scalar(
+                "WITH RECURSIVE ancestors(id) AS (
+                    SELECT ?1
+                    UNION SELECT locations.parent_id FROM locations
+                        JOIN ancestors ON locations.id = ancestors.id
+                        WHERE locations.parent_id IS NOT NULL
+                )
+                SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = ?2)",
+            )
+            .bind(parent_id)
+            .bind(self.id)
+            .fetch_one(&mut *connection)
+            .await?;
+            if inside {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("location-reparent-inside-itself")
+                        .arg("location", &self.name),
+                }));
+            }
+        }
+        let mut transaction = connection.begin().await?;
+        sqlx::query("UPDATE locations SET parent_id = ? WHERE id = ?")
+            .bind(parent_id)
+            .bind(self.id)
+            .execute(&mut *transaction)
+            .await?;
+        let location = Location::find(self.id, &mut transaction).await?;
+        Audit::create(
+            "Location",
+            location.id,
+            "update",
+            Some(location.id),
+            &location,
+            &mut transaction,
+        )
+        .await?;
+        Event::LocationReparented {
+            location_id: location.id,
+            parent_id,
+            previous_parent_id: self.parent_id,
+        }
+        .append(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(location)
+    }
+
     /// Find a location by barcode
+    ///
+    /// The barcode is run through the `BarcodeParser` pipeline before the database is queried, so
+    /// symbology and site prefixes are stripped and check digits are validated. If barcode signing
+    /// is enabled, barcodes whose signature does not verify are rejected. Either way a barcode which
+    /// does not validate is an `InvalidBarcodeError`, and one which validates but is not found a
+    /// `NotFoundError`. Database errors are returned as they are. Barcodes are matched regardless
+    /// of case.
+    /// Barcodes which were recently not found are answered from the negative lookup cache.
+    /// A barcode the location had before its barcode was regenerated still finds it, even if it no
+    /// longer validates, and is recorded in `resolved_via_alias`.
     /// # Examples
     /// ```
     /// # #[cfg(doctest)] {
@@ -125,56 +299,672 @@ impl<'a> Location {
     /// let location = Location::find_by_barode("lw-location1-1".to_string(), &mut connection).await.unwrap();
     /// # }
     /// ```
-    pub(crate) async fn find_by_barcode(
+    pub async fn find_by_barcode(
         barcode: String,
         connection: &mut SqliteConnection,
-    ) -> Result<Location, NotFoundError> {
-        match sqlx::query_as::<_, Location>("SELECT * FROM locations WHERE barcode = ?")
-            .bind(barcode)
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let parsed = match BarcodeParser::new(&CONFIG).parse(&barcode) {
+            Ok(parsed) => parsed,
+            // Labels printed before the check digit scheme changed may no longer validate
+            Err(e) => {
+                return match Location::find_by_alias(barcode.trim(), connection).await {
+                    Some(location) => Ok(location),
+                    None => Err(Box::new(e)),
+                }
+            }
+        };
+        if let Err(e) = signature::verify_configured(&CONFIG, &parsed.barcode) {
+            // Nor may labels printed before barcode signing was set up verify
+            if let Some(location) = Location::find_by_alias(&parsed.barcode, connection).await {
+                return Ok(location);
+            }
+            warn!("Rejected location barcode: {}", e);
+            return Err(Box::new(e));
+        }
+        let key = location_key(&parsed.barcode);
+        if NOT_FOUND_BARCODES.contains(&key).await {
+            return Err(Box::new(NotFoundError {
+                message: Message::new("location-not-found"),
+            }));
+        }
+        match sqlx::query_as::<_, Location>(statements::LOCATION_BY_BARCODE)
+            .bind(&parsed.barcode)
             .fetch_one(&mut *connection)
             .await
         {
             Ok(location) => Ok(location),
-            Err(_) => Err(NotFoundError {
-                message: "Location not found".to_string(),
-            }),
+            Err(sqlx::Error::RowNotFound) => {
+                if let Some(location) = Location::find_by_alias(&parsed.barcode, connection).await {
+                    return Ok(location);
+                }
+                NOT_FOUND_BARCODES.insert(&key).await;
+                Err(Box::new(NotFoundError {
+                    message: Message::new("location-not-found"),
+                }))
+            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// The location which had the barcode before its barcode was regenerated, if any, flagged as
+    /// resolved via the alias.
+    async fn find_by_alias(barcode: &str, connection: &mut SqliteConnection) -> Option<Location> {
+        let mut location = sqlx::query_as::<_, Location>(statements::LOCATION_BY_ALIAS)
+            .bind(barcode)
+            .fetch_optional(connection)
+            .await
+            .ok()
+            .flatten()?;
+        location.resolved_via_alias = Some(barcode.to_string());
+        Some(location)
+    }
+
+    /// Returns a `ValidationError` if a labware barcode is reserved for a location: the barcode a
+    /// location has or had before its barcode was regenerated, or the barcode generated for an
+    /// existing location, so that a labware can never be mistaken for one.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// Location::ensure_barcode_not_reserved("lw-freezer-1", &mut connection).await.unwrap_err();
+    /// # }
+    /// ```
+    pub async fn ensure_barcode_not_reserved(
+        barcode: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut location = sqlx::query_as::<_, Location>(statements::LOCATION_BY_BARCODE)
+            .bind(barcode)
+            .fetch_optional(&mut *connection)
+            .await?;
+        if location.is_none() {
+            location = Location::find_by_alias(barcode, &mut *connection).await;
+        }
+        if location.is_none() {
+            if let Some((unsigned, id)) = validation::generated_location_barcode(barcode) {
+                location = sqlx::query_as::<_, Location>(statements::LOCATION_BY_ID)
+                    .bind(id)
+                    .fetch_optional(&mut *connection)
+                    .await?
+                    .filter(|location| {
+                        barcode::generate(&location.name, id, None, None)
+                            .eq_ignore_ascii_case(unsigned)
+                    });
+            }
+        }
+        match location {
+            Some(location) => Err(Box::new(ValidationError {
+                message: Message::new("barcode-reserved")
+                    .arg("barcode", barcode)
+                    .arg("location", &location.name),
+            })),
+            None => Ok(()),
+        }
+    }
+
+    /// Find a location by id
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let location = Location::find(1, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, NotFoundError> {
+        sqlx::query_as::<_, Location>(statements::LOCATION_BY_ID)
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("location-not-found"),
+            })
+    }
+
+    /// The names of the locations the location is inside of, outermost first, ending with its own
+    /// name e.g. `["building1", "room1", "freezer1"]`
+    pub async fn breadcrumb(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, String>(
+            "WITH RECURSIVE ancestors(id, name, depth) AS (
+                SELECT id, name, 0 FROM locations WHERE id = ?
+                UNION ALL
+                SELECT locations.id, locations.name, ancestors.depth + 1 FROM locations
+                    JOIN ancestors ON locations.id = (
+                        SELECT parent_id FROM locations WHERE id = ancestors.id
+                    )
+            )
+            SELECT name FROM ancestors ORDER BY depth DESC",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// The location labwares are put in when they are created without one, from the cached copy if
+    /// there is one, or else from the database.
+    ///
+    /// The unknown location is created the first time it is needed, with a location type of the
+    /// same name, so databases which never need it do not have it.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// let location = Location::unknown(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn unknown(connection: &mut SqliteConnection) -> Result<Location, sqlx::Error> {
+        match UNKNOWN_LOCATION.get() {
+            Some(location) => Ok(location.clone()),
+            None => Location::find_or_create_unknown(connection).await,
+        }
+    }
+
+    /// Reads the unknown location of the server's database, creating it if needed, and keeps a copy
+    /// of it for `unknown` for as long as the process runs.
+    ///
+    /// Called once when the server starts. Not called by tests, each of which has a database of
+    /// its own.
+    pub async fn cache_unknown(
+        connection: &mut SqliteConnection,
+    ) -> Result<&'static Location, sqlx::Error> {
+        let location = Location::find_or_create_unknown(connection).await?;
+        Ok(UNKNOWN_LOCATION.get_or_init(|| location))
+    }
+
+    /// Reads the unknown location from the database, creating it if it does not exist yet.
+    ///
+    /// Each insert checks for the row it would create in the same statement, so instances of the
+    /// server racing to create the unknown location create it only once.
+    async fn find_or_create_unknown(
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, sqlx::Error> {
+        if let Some(location) = Location::find_unknown(connection).await? {
+            return Ok(location);
+        }
+        let mut tx = connection.begin().await?;
+        sqlx::query(
+            "INSERT INTO location_types (uuid, name) SELECT ?1, ?2
+                WHERE NOT EXISTS (SELECT 1 FROM location_types WHERE name = ?2)",
+        )
+        .bind(new_uuid())
+        .bind(UNKNOWN_LOCATION_NAME)
+        .execute(&mut *tx)
+        .await?;
+        let created = sqlx::query(
+            "INSERT INTO locations (uuid, name, barcode, location_type_id)
+                SELECT ?1, ?2, ?3, (SELECT MIN(id) FROM location_types WHERE name = ?2)
+                WHERE NOT EXISTS (SELECT 1 FROM locations WHERE barcode = ?3)",
+        )
+        .bind(new_uuid())
+        .bind(UNKNOWN_LOCATION_NAME)
+        .bind(UNKNOWN_LOCATION_BARCODE)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        let location = Location::find_unknown(&mut tx)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+        if created {
+            info!("Created the unknown location {}", location.id);
+            Audit::create(
+                "Location",
+                location.id,
+                "create",
+                Some(location.id),
+                &location,
+                &mut tx,
+            )
+            .await?;
+            Event::LocationCreated {
+                location_id: location.id,
+                name: location.name.clone(),
+                location_type_id: location.location_type_id,
+                parent_id: None,
+            }
+            .append(&mut tx)
+            .await?;
         }
+        tx.commit().await?;
+        Ok(location)
     }
 
-    /// Create a new unknown location
+    /// The unknown location, if the database has one.
+    async fn find_unknown(
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<Location>, sqlx::Error> {
+        sqlx::query_as::<_, Location>("SELECT * FROM locations WHERE barcode = ? ORDER BY id")
+            .bind(UNKNOWN_LOCATION_BARCODE)
+            .fetch_optional(&mut *connection)
+            .await
+    }
+
+    /// Adds to (or takes from) the number of labwares in a location
+    ///
+    /// Called in the same transaction as the labware is created or moved, so the count never
+    /// disagrees with the labwares table once the transaction is committed.
+    pub(crate) async fn count_labwares(
+        location_id: u32,
+        change: i32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE locations SET labwares_count = MAX(labwares_count + ?, 0) WHERE id = ?",
+        )
+        .bind(change)
+        .bind(location_id)
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Recounts the labwares of every location, correcting any count which has drifted, e.g.
+    /// after labwares were moved by hand in the database
+    ///
+    /// Returns the number of locations which were corrected.
     /// # Examples
     /// ```
     /// # #[cfg(doctest)] {
     /// use location::Location;
-    /// let location = Location::unknown();
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let corrected = Location::reconcile_labware_counts(&mut connection).await.unwrap();
     /// # }
     /// ```
+    pub async fn reconcile_labware_counts(
+        connection: &mut SqliteConnection,
+    ) -> Result<u32, sqlx::Error> {
+        let drifted = sqlx::query_as::<_, (u32, String, u32, u32)>(
+            "SELECT l.id, l.name, l.labwares_count, COUNT(w.id) FROM locations l
+                LEFT JOIN labwares w ON w.location_id = l.id AND w.status = 'active'
+                GROUP BY l.id HAVING l.labwares_count != COUNT(w.id)",
+        )
+        .fetch_all(&mut *connection)
+        .await?;
+        for (id, name, counted, actual) in &drifted {
+            warn!(
+                "Correcting the labware count of location {} from {} to {}",
+                name, counted, actual
+            );
+            sqlx::query("UPDATE locations SET labwares_count = ? WHERE id = ?")
+                .bind(actual)
+                .bind(id)
+                .execute(&mut *connection)
+                .await?;
+        }
+        Ok(drifted.len() as u32)
+    }
+
+    /// Regenerates the barcodes of all locations with the barcode format of `config`, e.g. after
+    /// a check digit scheme or a signing key was set up.
     ///
-    pub fn unknown() -> &'a Location {
-        UNKNOWN_LOCATION.as_ref()
+    /// The barcode each changed location had is kept as an alias, so labels printed with it keep
+    /// scanning until they are replaced. The unknown location keeps its barcode. With `dry_run`,
+    /// the changes are only returned.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let changes = Location::regenerate_barcodes(&CONFIG, true, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn regenerate_barcodes(
+        config: &Config,
+        dry_run: bool,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<BarcodeChange>, sqlx::Error> {
+        let locations = sqlx::query_as::<_, Location>(
+            "SELECT * FROM locations WHERE barcode IS NOT ? ORDER BY id",
+        )
+        .bind(UNKNOWN_LOCATION_BARCODE)
+        .fetch_all(&mut *connection)
+        .await?;
+
+        let mut changes = vec![];
+        let mut transaction = connection.begin().await?;
+        for mut location in locations {
+            let old_barcode = location.barcode.clone();
+            let new_barcode = location.create_barcode(
+                config.barcode_check_digit,
+                config.barcode_signing_keys.first(),
+            );
+            if old_barcode.as_deref() == Some(new_barcode.as_str()) {
+                continue;
+            }
+            if !dry_run {
+                Location::supersede_barcode(
+                    location.id,
+                    old_barcode.as_deref(),
+                    &new_barcode,
+                    &mut transaction,
+                )
+                .await?;
+                Audit::create(
+                    "Location",
+                    location.id,
+                    "update",
+                    Some(location.id),
+                    &location,
+                    &mut transaction,
+                )
+                .await?;
+            }
+            changes.push(BarcodeChange {
+                location_id: location.id,
+                name: location.name,
+                old_barcode,
+                new_barcode,
+            });
+        }
+        transaction.commit().await?;
+
+        if !dry_run {
+            for change in &changes {
+                NOT_FOUND_BARCODES
+                    .remove(&location_key(&change.new_barcode))
+                    .await;
+            }
+        }
+        Ok(changes)
     }
 
-    /// Creates a barcode
+    /// Renames the location, updating its audit trail and the event log.
+    ///
+    /// The barcode is kept unless `regenerate_barcode` is set, in which case it is generated from
+    /// the new name and the old barcode is kept as an alias, so labels printed with it keep
+    /// scanning until they are replaced.
+    ///
+    /// Returns a `FieldValidationError` if the new name is not valid, and a `ValidationError` for
+    /// the unknown location, which is found by its name and barcode.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// let shelf = shelf.rename("shelf 2".to_string(), false, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn rename(
+        &self,
+        name: String,
+        regenerate_barcode: bool,
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        if self.barcode.as_deref() == Some(UNKNOWN_LOCATION_BARCODE) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("location-rename-unknown"),
+            }));
+        }
+        let mut renamed = Location {
+            name,
+            resolved_via_alias: None,
+            ..self.clone()
+        };
+        CONFIG.location_name_rules.validate(&renamed.name)?;
+        if regenerate_barcode {
+            renamed.create_barcode(
+                CONFIG.barcode_check_digit,
+                CONFIG.barcode_signing_keys.first(),
+            );
+        }
+
+        let mut transaction = connection.begin().await?;
+        sqlx::query("UPDATE locations SET name = ? WHERE id = ?")
+            .bind(&renamed.name)
+            .bind(self.id)
+            .execute(&mut *transaction)
+            .await?;
+        if renamed.barcode != self.barcode {
+            Location::supersede_barcode(
+                self.id,
+                self.barcode.as_deref(),
+                renamed.barcode.as_deref().unwrap_or_default(),
+                &mut transaction,
+            )
+            .await?;
+        }
+        let location = Location::find(self.id, &mut transaction).await?;
+        Audit::create(
+            "Location",
+            location.id,
+            "update",
+            Some(location.id),
+            &location,
+            &mut transaction,
+        )
+        .await?;
+        Event::LocationRenamed {
+            location_id: location.id,
+            name: location.name.clone(),
+            previous_name: self.name.clone(),
+        }
+        .append(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+
+        if let Some(barcode) = &location.barcode {
+            NOT_FOUND_BARCODES.remove(&location_key(barcode)).await;
+        }
+        Ok(location)
+    }
+
+    /// Sets who is responsible for the location and how to reach them, updating its audit trail.
+    ///
+    /// Each of the owner, the contact email and the notes is cleared if it is `None`.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location::Location;
+    /// let freezer = freezer.update_ownership(Some("freezers".to_string()), Some("freezers@example.com".to_string()), None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn update_ownership(
+        &self,
+        owner: Option<String>,
+        contact_email: Option<String>,
+        notes: Option<String>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        sqlx::query("UPDATE locations SET owner = ?, contact_email = ?, notes = ? WHERE id = ?")
+            .bind(owner)
+            .bind(contact_email)
+            .bind(notes)
+            .bind(self.id)
+            .execute(&mut *transaction)
+            .await?;
+        let location = Location::find(self.id, &mut transaction).await?;
+        Audit::create(
+            "Location",
+            location.id,
+            "update",
+            Some(location.id),
+            &location,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(location)
+    }
+
+    /// Gives a location a new barcode, keeping the barcode it had as an alias.
+    async fn supersede_barcode(
+        location_id: u32,
+        old_barcode: Option<&str>,
+        new_barcode: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        if let Some(old_barcode) = old_barcode {
+            sqlx::query(
+                "INSERT OR IGNORE INTO location_barcode_aliases (location_id, barcode)
+                    VALUES (?, ?)",
+            )
+            .bind(location_id)
+            .bind(old_barcode)
+            .execute(&mut *connection)
+            .await?;
+        }
+        // A barcode the location had before is its barcode again rather than an alias
+        sqlx::query("DELETE FROM location_barcode_aliases WHERE barcode = ? COLLATE NOCASE")
+            .bind(new_barcode)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("UPDATE locations SET barcode = ? WHERE id = ?")
+            .bind(new_barcode)
+            .bind(location_id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates a barcode with `barcode::generate`
     /// Barcode format: `lw-{name trimmed and spaces replaced with "-"}-{id}`
-    fn create_barcode(&mut self) -> String {
-        let barcode = format!(
-            "lw-{}-{}",
-            self.name.trim().replace(" ", "-").to_lowercase(),
-            self.id
-        );
+    ///
+    /// If a signing key is given, the signature of the id is appended after a hyphen,
+    /// e.g. `lw-freezer-1-3f9a2c1b`.
+    /// If a check digit scheme is given, the check character is appended after a period,
+    /// e.g. `lw-freezer-1.e`.
+    fn create_barcode(
+        &mut self,
+        check_digit: Option<CheckDigitScheme>,
+        signing_key: Option<&SigningKey>,
+    ) -> String {
+        let barcode = barcode::generate(&self.name, self.id, check_digit, signing_key);
         self.barcode = Some(barcode.clone());
         barcode
     }
+}
+
+/// A location whose barcode is changed by `Location::regenerate_barcodes`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BarcodeChange {
+    pub location_id: u32,
+    pub name: String,
+    /// The barcode the location had, which is kept as an alias
+    pub old_barcode: Option<String>,
+    pub new_barcode: String,
+}
+
+impl Display for BarcodeChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.name,
+            self.old_barcode.as_deref().unwrap_or("(none)"),
+            self.new_barcode
+        )
+    }
+}
+
+/// Builds a `Location`, validating it on `build`
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::LocationBuilder;
+/// let location = LocationBuilder::new()
+///     .name("shelf 1")
+///     .location_type_id(1)
+///     .parent_id(freezer.id)
+///     .create(&mut connection)
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct LocationBuilder {
+    id: u32,
+    name: Option<String>,
+    location_type_id: Option<u32>,
+    barcode: Option<String>,
+    parent_id: Option<u32>,
+}
+
+impl LocationBuilder {
+    pub fn new() -> LocationBuilder {
+        Default::default()
+    }
 
-    /// Validate the name of the location for a certain format
-    /// Validations:
-    ///     1. Name must be between 1 and 60 characters
-    ///     2. Name must only contain alphanumeric characters, hyphens, spaces, and parentheses
-    fn validate_name(name: String) -> bool {
-        if !(1..=60).contains(&name.len()) {
-            return false;
+    /// The id of an existing location. Ignored by `create`.
+    pub fn id(mut self, id: u32) -> LocationBuilder {
+        self.id = id;
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> LocationBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn location_type_id(mut self, location_type_id: u32) -> LocationBuilder {
+        self.location_type_id = Some(location_type_id);
+        self
+    }
+
+    /// The barcode of an existing location. Ignored by `create`, which generates the barcode.
+    pub fn barcode(mut self, barcode: impl Into<String>) -> LocationBuilder {
+        self.barcode = Some(barcode.into());
+        self
+    }
+
+    pub fn parent_id(mut self, parent_id: u32) -> LocationBuilder {
+        self.parent_id = Some(parent_id);
+        self
+    }
+
+    /// Builds the location without saving it
+    ///
+    /// Returns a `FieldValidationError` if the name or location type is missing or the name is
+    /// not in a valid format.
+    pub fn build(self) -> Result<Location, FieldValidationError> {
+        let name = self.name.ok_or_else(|| {
+            FieldValidationError::field("name", Message::new("validation-required"))
+        })?;
+        let location_type_id = self.location_type_id.ok_or_else(|| {
+            FieldValidationError::field("location_type", Message::new("validation-required"))
+        })?;
+        let mut location = Location::new(self.id, name, location_type_id, self.barcode)?;
+        location.parent_id = self.parent_id;
+        Ok(location)
+    }
+
+    /// Validates and saves the location, generating its barcode
+    pub async fn create(
+        self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let location = self.build()?;
+        Ok(Location::create_with_parent(
+            location.name,
+            location.location_type_id,
+            location.parent_id,
+            connection,
+        )
+        .await?)
+    }
+}
+
+/// Reconciles the labware counts of locations every hour, forever.
+///
+/// Meant to be spawned once when the server starts. Failures are logged and retried.
+pub async fn reconcile_labware_counts(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let corrected = match acquire(&pool).await {
+            Ok(mut connection) => Location::reconcile_labware_counts(&mut connection).await,
+            Err(e) => Err(e),
+        };
+        match corrected {
+            Ok(0) => {}
+            Ok(corrected) => info!("Corrected the labware counts of {} locations", corrected),
+            Err(e) => error!("Could not reconcile labware counts: {}", e),
         }
-        Regex::new(r"\A[\w\-\s()]+\z").unwrap().is_match(&name)
     }
 }
 
@@ -182,36 +972,29 @@ impl Default for Location {
     fn default() -> Location {
         Location {
             id: 1,
+            uuid: new_uuid(),
             name: "Location1".to_string(),
             barcode: None,
             location_type_id: 1,
+            parent_id: None,
+            rows: None,
+            columns: None,
+            labwares_count: 0,
+            origin: None,
+            owner: None,
+            contact_email: None,
+            notes: None,
+            resolved_via_alias: None,
         }
     }
 }
 
-/// Error struct for containing name formatting errors
-struct NameFormatError {
-    /// Message contained within the exception
-    message: String,
-}
-
-impl Display for NameFormatError {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.message.to_string())
-    }
-}
-
-impl Debug for NameFormatError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message.to_string())
-    }
-}
-
-impl Error for NameFormatError {}
-
 #[cfg(test)]
 mod tests {
+    use crate::barcode::check_digit;
     use crate::db::init_db;
+    use crate::models::event::LoggedEvent;
+    use crate::models::labware::Labware;
     use crate::models::location::*;
     use crate::models::location_type::LocationType;
 
@@ -243,6 +1026,18 @@ mod tests {
         assert!(Location::new(1, "A location ~".to_string(), 1, None).is_err());
     }
 
+    #[test]
+    fn test_location_name_errors() {
+        let error = Location::new(1, "A/location".to_string(), 1, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Name must only contain letters, numbers, hyphens, spaces and parentheses"
+        );
+
+        let error = Location::new(1, "".to_string(), 1, None).unwrap_err();
+        assert_eq!(error.fields["name"].len(), 2);
+    }
+
     #[test]
     fn test_location_name_length() {
         assert!(Location::new(1, "".to_string(), 1, None).is_err());
@@ -254,27 +1049,199 @@ mod tests {
     #[test]
     fn test_barcode_sanitisation() {
         let mut location = Location::new(1, "location1".to_string(), 1, None).unwrap();
-        location.create_barcode();
+        location.create_barcode(None, None);
 
         assert_eq!("lw-location1-1", location.barcode.unwrap());
 
         location = Location::new(1, "location 1".to_string(), 1, None).unwrap();
-        location.create_barcode();
+        location.create_barcode(None, None);
 
         assert_eq!("lw-location-1-1", location.barcode.unwrap());
 
         location = Location::new(1, "Location1".to_string(), 1, None).unwrap();
-        location.create_barcode();
+        location.create_barcode(None, None);
 
         assert_eq!("lw-location1-1", location.barcode.unwrap());
     }
 
     #[test]
-    fn test_unknown_location() {
-        let location = Location::unknown();
-        assert_eq!(location.id, 999);
-        assert_eq!(location.name, "UNKNOWN");
-        assert_eq!(location.barcode.clone().unwrap(), "lw-unknown-999");
+    fn test_barcode_with_check_digit() {
+        let mut location = Location::new(1, "location1".to_string(), 1, None).unwrap();
+        location.create_barcode(Some(CheckDigitScheme::Mod43), None);
+
+        assert_eq!("lw-location1-1.u", location.barcode.unwrap());
+
+        location = Location::new(1, "location1".to_string(), 1, None).unwrap();
+        location.create_barcode(Some(CheckDigitScheme::Mod10), None);
+
+        assert_eq!("lw-location1-1.2", location.barcode.unwrap());
+    }
+
+    #[test]
+    fn test_location_builder() {
+        let location = LocationBuilder::new()
+            .id(2)
+            .name("shelf 1")
+            .location_type_id(1)
+            .barcode("lw-shelf-1-2")
+            .parent_id(1)
+            .build()
+            .unwrap();
+        assert_eq!(location.id, 2);
+        assert_eq!(location.name, "shelf 1");
+        assert_eq!(location.location_type_id, 1);
+        assert_eq!(location.barcode.unwrap(), "lw-shelf-1-2");
+        assert_eq!(location.parent_id, Some(1));
+
+        assert!(LocationBuilder::new().location_type_id(1).build().is_err());
+        assert!(LocationBuilder::new().name("shelf 1").build().is_err());
+        assert!(LocationBuilder::new()
+            .name("A/location")
+            .location_type_id(1)
+            .build()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_location_builder_create() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = LocationBuilder::new()
+            .name("freezer 1")
+            .location_type_id(location_type.id)
+            .create(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(location.barcode.unwrap(), "lw-freezer-1-1");
+
+        let error = LocationBuilder::new()
+            .name("A location +++")
+            .location_type_id(location_type.id)
+            .create(&mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<FieldValidationError>());
+    }
+
+    #[test]
+    fn test_barcode_with_signature() {
+        let key = SigningKey::new("secret");
+        let keys = vec![key.clone()];
+        let mut location = Location::new(1, "location1".to_string(), 1, None).unwrap();
+        location.create_barcode(None, Some(&key));
+        let barcode = location.barcode.unwrap();
+
+        assert!(barcode.starts_with("lw-location1-1-"));
+        assert!(signature::verify(&keys, &barcode).is_ok());
+
+        location = Location::new(1, "location1".to_string(), 1, None).unwrap();
+        location.create_barcode(Some(CheckDigitScheme::Mod43), Some(&key));
+        let barcode = location.barcode.unwrap();
+        let (signed, _) = check_digit::split(&barcode).unwrap();
+        assert!(signature::verify(&keys, signed).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = Location::unknown(&mut conn).await.unwrap();
+        assert_eq!(location.name, UNKNOWN_LOCATION_NAME);
+        assert_eq!(location.barcode.as_deref(), Some(UNKNOWN_LOCATION_BARCODE));
+        assert_eq!(
+            Location::find(location.id, &mut conn).await.unwrap(),
+            location
+        );
+
+        // It is only created once
+        assert_eq!(Location::unknown(&mut conn).await.unwrap(), location);
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM locations")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+        let events = LoggedEvent::all(None, Some(("Location", location.id)), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("renamed".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+
+        let renamed = location
+            .rename("renamed again".to_string(), false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(renamed.name, "renamed again");
+        assert_eq!(renamed.barcode, location.barcode);
+        let events = LoggedEvent::all(None, Some(("Location", location.id)), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            events[1].event,
+            Event::LocationRenamed {
+                location_id: location.id,
+                name: "renamed again".to_string(),
+                previous_name: "renamed".to_string(),
+            }
+        );
+
+        let error = renamed
+            .rename("renamed/again".to_string(), true, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<FieldValidationError>().is_some());
+
+        let unknown = Location::unknown(&mut conn).await.unwrap();
+        let error = unknown
+            .rename("somewhere".to_string(), false, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "The unknown location cannot be renamed");
+    }
+
+    #[tokio::test]
+    async fn test_update_ownership() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+
+        let owned = location
+            .update_ownership(
+                Some("freezers".to_string()),
+                Some("freezers@example.com".to_string()),
+                Some("Ask before defrosting".to_string()),
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        assert_eq!(owned.owner.as_deref(), Some("freezers"));
+        assert_eq!(owned.contact_email.as_deref(), Some("freezers@example.com"));
+        assert_eq!(Location::find(location.id, &mut conn).await.unwrap(), owned);
+        let audits = Audit::for_location(location.id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits[0].action, "update");
+
+        let disowned = owned
+            .update_ownership(None, None, None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(disowned.owner, None);
+        assert_eq!(disowned.notes, None);
     }
 
     #[tokio::test]
@@ -289,6 +1256,33 @@ mod tests {
         assert_eq!(location.name, "location1");
         assert_eq!(location.id, 1);
         assert_eq!(location_type.id, location.location_type_id);
+        assert_eq!(location.parent_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_create_location_with_parent() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let parent = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create_with_parent(
+            "shelf".to_string(),
+            location_type.id,
+            Some(parent.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(location.parent_id, Some(parent.id));
+
+        let found_location =
+            Location::find_by_barcode(location.barcode.clone().unwrap(), &mut conn)
+                .await
+                .unwrap();
+        assert_eq!(found_location.parent_id, Some(parent.id));
     }
 
     #[tokio::test]
@@ -306,6 +1300,120 @@ mod tests {
                 .unwrap();
 
         assert_eq!(location.barcode, found_location.barcode);
+        assert_eq!(location.uuid, found_location.uuid);
+
+        let found_location =
+            Location::find_by_barcode(location.barcode.clone().unwrap().to_uppercase(), &mut conn)
+                .await
+                .unwrap();
+
+        assert_eq!(location.uuid, found_location.uuid);
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_barcodes() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("regenerated".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let unknown = Location::unknown(&mut conn).await.unwrap();
+        let old_barcode = location.barcode.clone().unwrap();
+        let config = Config {
+            barcode_check_digit: Some(CheckDigitScheme::Mod43),
+            ..Default::default()
+        };
+        let new_barcode =
+            barcode::generate("regenerated", location.id, config.barcode_check_digit, None);
+
+        let changes = Location::regenerate_barcodes(&config, true, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            changes,
+            vec![BarcodeChange {
+                location_id: location.id,
+                name: "regenerated".to_string(),
+                old_barcode: Some(old_barcode.clone()),
+                new_barcode: new_barcode.clone(),
+            }]
+        );
+        assert_eq!(
+            Location::find(location.id, &mut conn)
+                .await
+                .unwrap()
+                .barcode,
+            Some(old_barcode.clone())
+        );
+
+        let changes = Location::regenerate_barcodes(&config, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].to_string(),
+            format!("regenerated: {} -> {}", old_barcode, new_barcode)
+        );
+        assert_eq!(
+            Location::find(unknown.id, &mut conn).await.unwrap().barcode,
+            unknown.barcode
+        );
+        for barcode in [&old_barcode, &new_barcode] {
+            let found = Location::find_by_barcode(barcode.to_uppercase(), &mut conn)
+                .await
+                .unwrap();
+            assert_eq!(found.id, location.id);
+            assert_eq!(found.barcode, Some(new_barcode.clone()));
+        }
+        let found = Location::find_by_barcode(old_barcode.clone(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(found.resolved_via_alias, Some(old_barcode.clone()));
+        let found = Location::find_by_barcode(new_barcode.clone(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(found.resolved_via_alias, None);
+
+        // The barcodes are in the configured format now
+        let changes = Location::regenerate_barcodes(&config, false, &mut conn)
+            .await
+            .unwrap();
+        assert!(changes.is_empty());
+
+        // Going back to the old format makes the alias the barcode again
+        let changes = Location::regenerate_barcodes(&Config::default(), false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(changes[0].new_barcode, old_barcode);
+        let aliases: Vec<String> =
+            sqlx::query_scalar("SELECT barcode FROM location_barcode_aliases")
+                .fetch_all(&mut conn)
+                .await
+                .unwrap();
+        assert_eq!(aliases, vec![new_barcode]);
+    }
+
+    #[tokio::test]
+    async fn test_location_uuid() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location1 = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let location2 = Location::create("location2".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        assert!(uuid::Uuid::parse_str(&location1.uuid).is_ok());
+        assert_ne!(location1.uuid, location2.uuid);
+
+        // The integer id stays internal
+        let json = serde_json::to_value(&location1).unwrap();
+        assert_eq!(json["uuid"], location1.uuid);
+        assert!(json.get("id").is_none());
     }
 
     #[tokio::test]
@@ -315,4 +1423,73 @@ mod tests {
             .await
             .expect_err("Location not found");
     }
+
+    #[tokio::test]
+    async fn test_find_by_barcode_with_symbology_prefix() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let found_location = Location::find_by_barcode(
+            format!("]C0{}\r\n", location.barcode.clone().unwrap()),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(location.barcode, found_location.barcode);
+    }
+
+    #[tokio::test]
+    async fn test_labwares_count() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer1 = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let freezer2 = Location::create("freezer2".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut labware = Labware::create("lw-1".to_string(), freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        labware.location_id = freezer2.id;
+        Labware::update(&labware, None, &mut conn).await.unwrap();
+
+        let count = |location: Location| location.labwares_count;
+        assert_eq!(
+            count(Location::find(freezer1.id, &mut conn).await.unwrap()),
+            1
+        );
+        assert_eq!(
+            count(Location::find(freezer2.id, &mut conn).await.unwrap()),
+            1
+        );
+        assert_eq!(
+            Location::reconcile_labware_counts(&mut conn).await.unwrap(),
+            0
+        );
+
+        sqlx::query("UPDATE locations SET labwares_count = 7 WHERE id = ?")
+            .bind(freezer1.id)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            Location::reconcile_labware_counts(&mut conn).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            count(Location::find(freezer1.id, &mut conn).await.unwrap()),
+            1
+        );
+    }
 }
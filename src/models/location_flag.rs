@@ -0,0 +1,348 @@
+use crate::errors::{ForbiddenError, LockedError, NotFoundError};
+use crate::i18n::Message;
+use crate::models::location::Location;
+use crate::models::new_uuid;
+use crate::notifications::{notify, Notification, Trigger};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// How serious a location flag is.
+///
+/// `info` and `warning` flags are only shown with the location. A `quarantine` flag also blocks
+/// scanning labware into the location until an admin clears it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum FlagSeverity {
+    Info,
+    Warning,
+    Quarantine,
+}
+
+impl Display for FlagSeverity {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            FlagSeverity::Info => "info",
+            FlagSeverity::Warning => "warning",
+            FlagSeverity::Quarantine => "quarantine",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A flag raised on a location after an incident, e.g. a temperature excursion or a door alarm.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct LocationFlag {
+    /// The unique identifier for the LocationFlag
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the LocationFlag, used in URLs and event payloads
+    pub uuid: String,
+    /// The ID of the flagged location
+    pub location_id: u32,
+    /// How serious the incident is
+    pub severity: FlagSeverity,
+    /// What happened, e.g. `temperature excursion 2024-05-01`
+    pub note: String,
+    /// Who raised the flag
+    pub raised_by: String,
+    /// When the flag was raised
+    pub created_at: DateTime<Utc>,
+    /// Who cleared the flag, once it has been
+    pub cleared_by: Option<String>,
+    /// When the flag was cleared, once it has been
+    pub cleared_at: Option<DateTime<Utc>>,
+}
+
+/// Implementation of the LocationFlag struct
+impl LocationFlag {
+    /// Raises a flag on a location, notifying the location's owner and contact
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location_flag::{FlagSeverity, LocationFlag};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let flag = LocationFlag::create(1, FlagSeverity::Warning, "door alarm".to_string(), "jane".to_string(), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        location_id: u32,
+        severity: FlagSeverity,
+        note: String,
+        raised_by: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<LocationFlag, Box<dyn Error + Send + Sync>> {
+        let insert_query_result = sqlx::query(
+            "INSERT INTO location_flags (uuid, location_id, severity, note, raised_by)
+                VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(location_id)
+        .bind(severity)
+        .bind(note)
+        .bind(raised_by)
+        .execute(&mut *connection)
+        .await?;
+        let flag =
+            LocationFlag::find(insert_query_result.last_insert_rowid() as u32, connection).await?;
+        let location = Location::find(location_id, connection).await?;
+        notify(flag.notification(&location));
+        Ok(flag)
+    }
+
+    /// The notification sent about the flag, to the teams configured for `location-flagged` and to
+    /// the owner and contact of the location
+    pub fn notification(&self, location: &Location) -> Notification {
+        Notification::new(Trigger::LocationFlagged)
+            .arg("location", &location.name)
+            .arg("severity", self.severity)
+            .arg("note", &self.note)
+            .arg("user", &self.raised_by)
+            .owned_by(location.owner.as_deref(), location.contact_email.as_deref())
+    }
+
+    /// Lists the flags of a location which have not been cleared, most serious first
+    pub async fn active(
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<LocationFlag>, sqlx::Error> {
+        sqlx::query_as::<_, LocationFlag>(
+            "SELECT * FROM location_flags WHERE location_id = ? AND cleared_at IS NULL
+                ORDER BY CASE severity WHEN 'quarantine' THEN 0 WHEN 'warning' THEN 1 ELSE 2 END, id",
+        )
+        .bind(location_id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Clears a flag of a location
+    ///
+    /// Quarantine flags can only be cleared by an admin, as the caller tells by `admin`; returns a
+    /// `ForbiddenError` otherwise. Returns a `NotFoundError` if the location has no such flag or it has already
+    /// been cleared.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location_flag::LocationFlag;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let flag = LocationFlag::clear(1, &flag.uuid, "jane".to_string(), true, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn clear(
+        location_id: u32,
+        uuid: &str,
+        cleared_by: String,
+        admin: bool,
+        connection: &mut SqliteConnection,
+    ) -> Result<LocationFlag, Box<dyn Error + Send + Sync>> {
+        let flag = LocationFlag::active(location_id, &mut *connection)
+            .await?
+            .into_iter()
+            .find(|flag| flag.uuid == uuid)
+            .ok_or_else(|| NotFoundError {
+                message: Message::new("flag-not-found"),
+            })?;
+        if flag.severity == FlagSeverity::Quarantine && !admin {
+            return Err(Box::new(ForbiddenError {
+                message: Message::new("flag-admin-required"),
+            }));
+        }
+
+        sqlx::query(
+            "UPDATE location_flags SET cleared_by = ?, cleared_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(cleared_by)
+        .bind(flag.id)
+        .execute(&mut *connection)
+        .await?;
+        Ok(LocationFlag::find(flag.id, connection).await?)
+    }
+
+    /// Returns a `LockedError` if the location is quarantined, so nothing can be put in it.
+    pub async fn ensure_not_quarantined(
+        location: &Location,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let flags = LocationFlag::active(location.id, &mut *connection).await?;
+        match flags
+            .iter()
+            .find(|flag| flag.severity == FlagSeverity::Quarantine)
+        {
+            Some(flag) => Err(Box::new(LockedError {
+                message: Message::new("flag-quarantined")
+                    .arg("location", &location.name)
+                    .arg("note", &flag.note),
+            })),
+            None => Ok(()),
+        }
+    }
+
+    /// Find a flag by id
+    async fn find(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<LocationFlag, NotFoundError> {
+        sqlx::query_as::<_, LocationFlag>("SELECT * FROM location_flags WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("flag-not-found"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::init_db;
+    use crate::models::location_flag::*;
+    use crate::models::location_type::LocationType;
+
+    async fn create_location(connection: &mut SqliteConnection) -> Location {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        Location::create("freezer1".to_string(), location_type.id, connection)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_and_clear() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn).await;
+
+        let info = LocationFlag::create(
+            location.id,
+            FlagSeverity::Info,
+            "defrosted".to_string(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let warning = LocationFlag::create(
+            location.id,
+            FlagSeverity::Warning,
+            "door alarm".to_string(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let active = LocationFlag::active(location.id, &mut conn).await.unwrap();
+        assert_eq!(active, vec![warning.clone(), info.clone()]);
+
+        let cleared = LocationFlag::clear(
+            location.id,
+            &warning.uuid,
+            "john".to_string(),
+            false,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(cleared.cleared_by, Some("john".to_string()));
+        assert!(cleared.cleared_at.is_some());
+        assert_eq!(
+            LocationFlag::active(location.id, &mut conn).await.unwrap(),
+            vec![info]
+        );
+
+        let error = LocationFlag::clear(
+            location.id,
+            &warning.uuid,
+            "john".to_string(),
+            false,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<NotFoundError>());
+    }
+
+    #[tokio::test]
+    async fn test_notification() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn)
+            .await
+            .update_ownership(
+                Some("freezers".to_string()),
+                Some("freezers@example.com".to_string()),
+                None,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        let flag = LocationFlag::create(
+            location.id,
+            FlagSeverity::Warning,
+            "door alarm".to_string(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let notification = flag.notification(&location);
+        assert_eq!(
+            notification.subject().to_string(),
+            "warning flag raised on freezer1"
+        );
+        assert_eq!(
+            notification.body().to_string(),
+            "jane raised a warning flag on freezer1: door alarm"
+        );
+        assert_eq!(
+            notification.recipients(&Config::default()),
+            vec!["freezers@example.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quarantine() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn).await;
+        LocationFlag::ensure_not_quarantined(&location, &mut conn)
+            .await
+            .unwrap();
+
+        let flag = LocationFlag::create(
+            location.id,
+            FlagSeverity::Quarantine,
+            "temperature excursion 2024-05-01".to_string(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let error = LocationFlag::ensure_not_quarantined(&location, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<LockedError>());
+        assert_eq!(
+            error.to_string(),
+            "Location freezer1 is quarantined: temperature excursion 2024-05-01"
+        );
+
+        let error = LocationFlag::clear(
+            location.id,
+            &flag.uuid,
+            "jane".to_string(),
+            false,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<ForbiddenError>());
+        let cleared =
+            LocationFlag::clear(location.id, &flag.uuid, "jane".to_string(), true, &mut conn)
+                .await
+                .unwrap();
+        assert!(cleared.cleared_at.is_some());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::errors::{LockedError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::new_uuid;
+use crate::timestamps;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// The longest a lock can be held for before it has to be renewed, in seconds.
+pub const MAX_LOCK_SECONDS: u32 = 3600;
+
+/// A short-lived advisory lock on a location.
+///
+/// While a location is locked (e.g. during a physical reorganization of a box), labware can only be
+/// moved into or out of it by whoever presents the lock's token. Locks expire automatically, so a
+/// forgotten lock never blocks a location for long.
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+pub struct LocationLock {
+    /// The unique identifier for the LocationLock
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the LocationLock, used in URLs and event payloads
+    pub uuid: String,
+    /// The ID of the locked location
+    pub location_id: u32,
+    /// Who holds the lock e.g. a user or a team
+    pub holder: String,
+    /// The secret which has to be presented to change the location while it is locked
+    pub token: String,
+    /// When the lock expires
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Implementation of the LocationLock struct
+impl LocationLock {
+    /// Locks a location for the given number of seconds
+    ///
+    /// If the holder already holds the lock it is renewed, keeping its token. Returns a
+    /// `LockedError` if somebody else holds an unexpired lock on the location.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location_lock::LocationLock;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let lock = LocationLock::acquire(1, "jane".to_string(), 300, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn acquire(
+        location_id: u32,
+        holder: String,
+        seconds: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<LocationLock, Box<dyn Error + Send + Sync>> {
+        if !(1..=MAX_LOCK_SECONDS).contains(&seconds) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("lock-duration").arg("max", MAX_LOCK_SECONDS),
+            }));
+        }
+        LocationLock::delete_expired(&mut *connection).await?;
+
+        if let Some(lock) = LocationLock::active(location_id, &mut *connection).await? {
+            if lock.holder != holder {
+                return Err(Box::new(LockedError {
+                    message: Message::new("lock-held")
+                        .arg("holder", &lock.holder)
+                        .arg("expires_at", timestamps::display(&lock.expires_at)),
+                }));
+            }
+            sqlx::query(
+                "UPDATE location_locks SET expires_at = datetime('now', '+' || ? || ' seconds') WHERE id = ?",
+            )
+            .bind(seconds)
+            .bind(lock.id)
+            .execute(&mut *connection)
+            .await?;
+        } else {
+            sqlx::query(
+                "INSERT INTO location_locks (uuid, location_id, holder, token, expires_at)
+                    VALUES (?, ?, ?, lower(hex(randomblob(16))), datetime('now', '+' || ? || ' seconds'))",
+            )
+            .bind(new_uuid())
+            .bind(location_id)
+            .bind(holder)
+            .bind(seconds)
+            .execute(&mut *connection)
+            .await?;
+        }
+
+        LocationLock::active(location_id, connection)
+            .await?
+            .ok_or_else(|| {
+                Box::new(NotFoundError {
+                    message: Message::new("lock-not-found"),
+                }) as Box<dyn Error + Send + Sync>
+            })
+    }
+
+    /// Releases the lock on a location. Only the holder of the token can release it.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location_lock::LocationLock;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// LocationLock::release(1, &lock.token, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn release(
+        location_id: u32,
+        token: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        LocationLock::ensure_unlocked(location_id, Some(token), &mut *connection).await?;
+        let delete_query_result = sqlx::query("DELETE FROM location_locks WHERE location_id = ?")
+            .bind(location_id)
+            .execute(&mut *connection)
+            .await?;
+        if delete_query_result.rows_affected() == 0 {
+            return Err(Box::new(NotFoundError {
+                message: Message::new("lock-not-locked"),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Returns the unexpired lock on a location, if any
+    pub async fn active(
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<LocationLock>, sqlx::Error> {
+        sqlx::query_as::<_, LocationLock>(
+            "SELECT * FROM location_locks WHERE location_id = ? AND expires_at > datetime('now')",
+        )
+        .bind(location_id)
+        .fetch_optional(&mut *connection)
+        .await
+    }
+
+    /// Checks that a location can be changed by whoever presents the given token.
+    ///
+    /// Returns a `LockedError` if the location has an unexpired lock whose token does not match.
+    /// This is called from every path which moves labware into or out of a location.
+    pub async fn ensure_unlocked(
+        location_id: u32,
+        token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match LocationLock::active(location_id, connection).await? {
+            Some(lock) if Some(lock.token.as_str()) != token => Err(Box::new(LockedError {
+                message: Message::new("lock-held")
+                    .arg("holder", &lock.holder)
+                    .arg("expires_at", timestamps::display(&lock.expires_at)),
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes locks which have expired
+    async fn delete_expired(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM location_locks WHERE expires_at <= datetime('now')")
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::location::Location;
+    use crate::models::location_lock::*;
+    use crate::models::location_type::LocationType;
+
+    async fn create_location(connection: &mut SqliteConnection) -> Location {
+        let location_type = LocationType::create("Box".to_string(), connection)
+            .await
+            .unwrap();
+        Location::create("box1".to_string(), location_type.id, connection)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_acquire_lock() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn).await;
+
+        let lock = LocationLock::acquire(location.id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(lock.location_id, location.id);
+        assert_eq!(lock.holder, "jane");
+        assert_eq!(lock.token.len(), 32);
+        let expires_in = lock.expires_at - Utc::now();
+        assert!(expires_in > chrono::Duration::seconds(290));
+        assert!(expires_in <= chrono::Duration::seconds(300));
+
+        // Renewing keeps the token
+        let renewed = LocationLock::acquire(location.id, "jane".to_string(), 600, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(renewed.token, lock.token);
+        assert!(renewed.expires_at > lock.expires_at);
+
+        let error = LocationLock::acquire(location.id, "john".to_string(), 300, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<LockedError>());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_lock_with_invalid_duration() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn).await;
+
+        for seconds in [0, MAX_LOCK_SECONDS + 1] {
+            let error = LocationLock::acquire(location.id, "jane".to_string(), seconds, &mut conn)
+                .await
+                .unwrap_err();
+            assert!(error.is::<ValidationError>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ensure_unlocked() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn).await;
+        assert!(LocationLock::ensure_unlocked(location.id, None, &mut conn)
+            .await
+            .is_ok());
+
+        let lock = LocationLock::acquire(location.id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+        let error = LocationLock::ensure_unlocked(location.id, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<LockedError>());
+        assert!(
+            LocationLock::ensure_unlocked(location.id, Some("wrong"), &mut conn)
+                .await
+                .is_err()
+        );
+        assert!(
+            LocationLock::ensure_unlocked(location.id, Some(&lock.token), &mut conn)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_lock() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn).await;
+        LocationLock::acquire(location.id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE location_locks SET expires_at = datetime('now', '-1 seconds')")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        assert!(LocationLock::ensure_unlocked(location.id, None, &mut conn)
+            .await
+            .is_ok());
+        let lock = LocationLock::acquire(location.id, "john".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(lock.holder, "john");
+    }
+
+    #[tokio::test]
+    async fn test_release_lock() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location = create_location(&mut conn).await;
+        let lock = LocationLock::acquire(location.id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+
+        let error = LocationLock::release(location.id, "wrong", &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<LockedError>());
+
+        LocationLock::release(location.id, &lock.token, &mut conn)
+            .await
+            .unwrap();
+        assert!(LocationLock::active(location.id, &mut conn)
+            .await
+            .unwrap()
+            .is_none());
+
+        let error = LocationLock::release(location.id, &lock.token, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<NotFoundError>());
+    }
+}
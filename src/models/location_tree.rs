@@ -0,0 +1,201 @@
+use crate::i18n::Message;
+use crate::models::location::Location;
+use crate::pdf::{Document, Weight};
+use crate::timestamps;
+use chrono::{DateTime, Utc};
+use fluent_templates::LanguageIdentifier;
+use serde::Serialize;
+use sqlx::SqliteConnection;
+
+/// The indent of each level of the tree in the PDF, in points.
+const INDENT: f32 = 16.0;
+
+/// A location within a tree, with how full it is.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct TreeNode {
+    /// The name of the location
+    pub name: String,
+    /// The barcode of the location
+    pub barcode: Option<String>,
+    /// How far below the root of the tree the location is; the root is 0
+    pub depth: u32,
+    /// The number of labwares directly in the location
+    pub labwares: u32,
+    /// The number of wells of a coordinated location, if it has rows and columns
+    pub capacity: Option<u32>,
+}
+
+/// A location and every location beneath it, in the order they are printed: each location is
+/// followed by the locations inside of it, sorted by name.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LocationTree {
+    /// The locations of the tree, starting with its root
+    pub nodes: Vec<TreeNode>,
+    /// When the tree was read
+    pub created_at: DateTime<Utc>,
+}
+
+/// Implementation of the LocationTree struct
+impl LocationTree {
+    /// Reads the tree beneath a location, with the occupancy of each location, in one query
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use location_tree::LocationTree;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let tree = LocationTree::build(&freezer, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn build(
+        location: &Location,
+        connection: &mut SqliteConnection,
+    ) -> Result<LocationTree, sqlx::Error> {
+        // The path of names (with the id to tell apart namesakes) sorts children under parents
+        let nodes = sqlx::query_as::<_, TreeNode>(
+            "WITH RECURSIVE subtree(id, depth, path) AS (
+                SELECT id, 0, name || char(31) || id FROM locations WHERE id = ?
+                UNION ALL
+                SELECT locations.id, subtree.depth + 1,
+                    subtree.path || char(30) || locations.name || char(31) || locations.id
+                    FROM locations JOIN subtree ON locations.parent_id = subtree.id
+            )
+            SELECT l.name, l.barcode, subtree.depth,
+                l.labwares_count AS labwares,
+                l.rows * l.columns AS capacity
+                FROM subtree JOIN locations l ON l.id = subtree.id
+                ORDER BY subtree.path",
+        )
+        .bind(location.id)
+        .fetch_all(&mut *connection)
+        .await?;
+        Ok(LocationTree {
+            nodes,
+            created_at: Utc::now(),
+        })
+    }
+
+    /// The number of labwares anywhere in the tree
+    pub fn labwares(&self) -> u32 {
+        self.nodes.iter().map(|node| node.labwares).sum()
+    }
+
+    /// Renders the tree as a printable PDF, with its text in the given locale
+    ///
+    /// Each location is listed with its barcode and how many labwares are in it, indented beneath
+    /// the location it is inside of.
+    pub fn to_pdf(&self, locale: &LanguageIdentifier) -> Vec<u8> {
+        let mut document = Document::new();
+        let Some(root) = self.nodes.first() else {
+            return document.finish();
+        };
+        document.line(
+            &Message::new("tree-title")
+                .arg("location", &root.name)
+                .localize(locale),
+            18.0,
+            0.0,
+            Weight::Bold,
+        );
+        document.line(
+            &Message::new("tree-summary")
+                .arg("locations", self.nodes.len())
+                .arg("labwares", self.labwares())
+                .arg("printed_at", timestamps::display(&self.created_at))
+                .localize(locale),
+            9.0,
+            0.0,
+            Weight::Regular,
+        );
+        document.gap(9.0);
+        for node in &self.nodes {
+            let occupancy = match node.capacity {
+                Some(capacity) => Message::new("tree-occupancy-wells")
+                    .arg("labwares", node.labwares)
+                    .arg("capacity", capacity),
+                None => Message::new("tree-occupancy").arg("labwares", node.labwares),
+            };
+            let text = format!(
+                "{}  {}  —  {}",
+                node.name,
+                node.barcode.as_deref().unwrap_or_default(),
+                occupancy.localize(locale)
+            );
+            let weight = if node.depth == 0 {
+                Weight::Bold
+            } else {
+                Weight::Regular
+            };
+            document.line(&text, 11.0, node.depth as f32 * INDENT, weight);
+        }
+        document.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::i18n::DEFAULT_LOCALE;
+    use crate::models::labware::Labware;
+    use crate::models::layout::Layout;
+    use crate::models::location_tree::*;
+    use crate::models::location_type::LocationType;
+
+    #[tokio::test]
+    async fn test_build() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut shelves = vec![];
+        for name in ["shelf2", "shelf1"] {
+            shelves.push(
+                Location::create_with_parent(
+                    name.to_string(),
+                    location_type.id,
+                    Some(freezer.id),
+                    &mut conn,
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        let rack = Location::create_with_parent(
+            "rack".to_string(),
+            location_type.id,
+            Some(shelves[0].id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Layout::resize(&rack, 2, 3, &mut conn).await.unwrap();
+        Labware::create("lw-1".to_string(), rack.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), shelves[1].id, &mut conn)
+            .await
+            .unwrap();
+
+        let tree = LocationTree::build(&freezer, &mut conn).await.unwrap();
+        let names: Vec<(&str, u32)> = tree
+            .nodes
+            .iter()
+            .map(|node| (node.name.as_str(), node.depth))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("freezer", 0), ("shelf1", 1), ("shelf2", 1), ("rack", 2)]
+        );
+        assert_eq!(tree.nodes[3].capacity, Some(6));
+        assert_eq!(tree.nodes[3].labwares, 1);
+        assert_eq!(tree.labwares(), 2);
+
+        let pdf = tree.to_pdf(&DEFAULT_LOCALE);
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains("(Map of freezer) Tj"));
+        assert!(pdf.contains("1 of 6 wells"));
+    }
+}
@@ -1,13 +1,22 @@
+use crate::config::CONFIG;
+use crate::errors::{FieldValidationError, ValidationError};
+use crate::i18n::Message;
+use crate::models::new_uuid;
+use serde::Serialize;
 use sqlx::SqliteConnection;
+use std::error::Error;
 use PartialEq;
 
 /// LocationType struct
 /// A LocationType is a type of location, e.g. Building, Room, etc.
-#[derive(Debug, PartialEq, sqlx::FromRow)]
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
 pub struct LocationType {
     /// The unique identifier for the LocationType
+    #[serde(skip_serializing)]
     pub id: u32,
-    /// The unique name of the LocationType
+    /// The public identifier of the LocationType, used in URLs and event payloads
+    pub uuid: String,
+    /// The unique name of the LocationType, following `Config::location_type_name_rules`
     name: String,
 }
 
@@ -23,7 +32,11 @@ impl LocationType {
     /// # }
     /// ```
     fn new(id: u32, name: String) -> LocationType {
-        LocationType { id, name }
+        LocationType {
+            id,
+            uuid: new_uuid(),
+            name,
+        }
     }
 
     /// Create a new LocationType
@@ -34,16 +47,94 @@ impl LocationType {
     /// let locationType = LocationType::create("Building".to_string()).await.unwrap();
     /// # }
     /// ```
-    pub(crate) async fn create(
+    pub async fn create(
         name: String,
         connection: &mut SqliteConnection,
     ) -> Result<LocationType, sqlx::Error> {
-        let insert_query_result = sqlx::query("INSERT INTO location_types (name) VALUES (?)")
-            .bind(name.clone())
-            .execute(&mut *connection)
-            .await?;
-        let id = insert_query_result.last_insert_rowid();
-        Ok(LocationType::new(id as u32, name))
+        let mut location_type = LocationType::new(0, name);
+        let insert_query_result =
+            sqlx::query("INSERT INTO location_types (uuid, name) VALUES (?, ?)")
+                .bind(&location_type.uuid)
+                .bind(&location_type.name)
+                .execute(&mut *connection)
+                .await?;
+        location_type.id = insert_query_result.last_insert_rowid() as u32;
+        Ok(location_type)
+    }
+
+    /// Lists every location type, ordered by name
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<LocationType>, sqlx::Error> {
+        sqlx::query_as::<_, LocationType>("SELECT * FROM location_types ORDER BY name")
+            .fetch_all(&mut *connection)
+            .await
+    }
+}
+
+/// Builds a `LocationType`, validating it on `build`
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::LocationTypeBuilder;
+/// let location_type = LocationTypeBuilder::new().name("Freezer").create(&mut connection).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct LocationTypeBuilder {
+    id: u32,
+    name: Option<String>,
+}
+
+impl LocationTypeBuilder {
+    pub fn new() -> LocationTypeBuilder {
+        Default::default()
+    }
+
+    /// The id of an existing location type. Ignored by `create`.
+    pub fn id(mut self, id: u32) -> LocationTypeBuilder {
+        self.id = id;
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> LocationTypeBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Builds the location type without saving it
+    ///
+    /// Returns a `FieldValidationError` if the name is missing or does not follow
+    /// `Config::location_type_name_rules`.
+    pub fn build(self) -> Result<LocationType, FieldValidationError> {
+        let name = self.name.ok_or_else(|| {
+            FieldValidationError::field("name", Message::new("validation-required"))
+        })?;
+        let location_type = LocationType::new(self.id, name.trim().to_string());
+        CONFIG
+            .location_type_name_rules
+            .validate(&location_type.name)?;
+        Ok(location_type)
+    }
+
+    /// Validates and saves the location type
+    ///
+    /// Returns a `ValidationError` if a location type already has the name.
+    pub async fn create(
+        self,
+        connection: &mut SqliteConnection,
+    ) -> Result<LocationType, Box<dyn Error + Send + Sync>> {
+        let location_type = self.build()?;
+        let taken: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM location_types WHERE name = ? COLLATE NOCASE)",
+        )
+        .bind(&location_type.name)
+        .fetch_one(&mut *connection)
+        .await?;
+        if taken {
+            return Err(Box::new(ValidationError {
+                message: Message::new("location-type-name-taken").arg("name", &location_type.name),
+            }));
+        }
+        Ok(LocationType::create(location_type.name, connection).await?)
     }
 }
 
@@ -51,6 +142,7 @@ impl Default for LocationType {
     fn default() -> LocationType {
         LocationType {
             id: 1,
+            uuid: new_uuid(),
             name: "Building".to_string(),
         }
     }
@@ -59,7 +151,7 @@ impl Default for LocationType {
 #[cfg(test)]
 mod tests {
     use crate::db::init_db;
-    use crate::models::location_type::LocationType;
+    use crate::models::location_type::{LocationType, LocationTypeBuilder};
 
     #[test]
     fn test_location_type_new() {
@@ -68,6 +160,24 @@ mod tests {
         assert_eq!(location_type.name, "Building");
     }
 
+    #[test]
+    fn test_location_type_builder() {
+        let location_type = LocationTypeBuilder::new()
+            .id(2)
+            .name(" Freezer ")
+            .build()
+            .unwrap();
+        assert_eq!(location_type.id, 2);
+        assert_eq!(location_type.name, "Freezer");
+
+        assert!(LocationTypeBuilder::new().build().is_err());
+        assert!(LocationTypeBuilder::new().name("  ").build().is_err());
+        assert!(LocationTypeBuilder::new()
+            .name("a".repeat(61))
+            .build()
+            .is_err());
+    }
+
     #[tokio::test]
     async fn test_create_location_type() {
         let mut conn = init_db("sqlite::memory:").await.unwrap();
@@ -77,4 +187,33 @@ mod tests {
         assert_eq!(location_type.id, 1);
         assert_eq!(location_type.name, "Freezer");
     }
+
+    #[tokio::test]
+    async fn test_all_and_name_taken() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        for name in ["Freezer", "Building"] {
+            LocationTypeBuilder::new()
+                .name(name)
+                .create(&mut conn)
+                .await
+                .unwrap();
+        }
+        let error = LocationTypeBuilder::new()
+            .name("freezer")
+            .create(&mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "A location type named freezer already exists"
+        );
+
+        let names: Vec<String> = LocationType::all(&mut conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|location_type| location_type.name)
+            .collect();
+        assert_eq!(names, vec!["Building", "Freezer"]);
+    }
 }
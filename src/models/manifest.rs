@@ -0,0 +1,701 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::config::CONFIG;
+use crate::errors::{domain_message, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::location_flag::LocationFlag;
+use crate::models::location_lock::LocationLock;
+use crate::models::new_uuid;
+use crate::models::reservation::Reservation;
+use crate::models::stocktake_report::csv_field;
+use crate::models::upload_mapping::UploadMapping;
+use chrono::{DateTime, Utc};
+use csv_core::{ReadRecordResult, Reader};
+use fluent_templates::LanguageIdentifier;
+use log::error;
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// The number of rows of a manifest imported in a single transaction. Progress is recorded after
+/// every batch.
+pub const BATCH_SIZE: usize = 1000;
+
+/// The most row errors shown with a manifest.
+pub const MAX_ERRORS_SHOWN: u32 = 100;
+
+/// The header of the error report of a manifest. It starts with the columns of a manifest, so the
+/// report can be fixed and uploaded again as it is.
+pub const ERRORS_CSV_HEADER: &str = "labware,location,line,error";
+
+/// The state of a manifest upload.
+///
+/// Manifests move `processing -> done`, or to `failed` (with an error) if the upload could not be
+/// read to the end. The rows imported before then stay imported.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ManifestState {
+    Processing,
+    Done,
+    Failed,
+}
+
+/// A CSV file of labwares and the locations they are in, uploaded to register many labwares at
+/// once, e.g. when a new freezer is filled or another system's inventory is migrated.
+///
+/// The file has a header row naming its `labware` and `location` columns, or the columns of the
+/// `UploadMapping` chosen for the upload (other columns are ignored). Each row is imported like a scan of the labware into the location. A row which
+/// cannot be imported is recorded as a `ManifestError` without holding up the rest.
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Manifest {
+    /// The unique identifier for the Manifest
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Manifest, used in URLs
+    pub uuid: String,
+    /// The current state of the upload
+    pub state: ManifestState,
+    /// The number of rows processed so far
+    pub rows_processed: u32,
+    /// The number of rows which were imported
+    pub rows_imported: u32,
+    /// The number of rows which could not be imported
+    pub rows_failed: u32,
+    /// Why the upload failed, if it did
+    pub error: Option<String>,
+    /// When the upload started
+    pub created_at: DateTime<Utc>,
+    /// When the last batch of rows was imported
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A row of a manifest which could not be imported.
+#[derive(Debug, PartialEq)]
+pub struct ManifestError {
+    /// The line of the file the row is on, counting the header as line 1 and leaving out blank
+    /// lines
+    pub line: u32,
+    /// The labware barcode of the row
+    pub barcode: String,
+    /// The location barcode of the row
+    pub location: String,
+    /// Why the row could not be imported, localized when it is shown
+    pub message: Message,
+}
+
+/// A `manifest_errors` row, whose message is stored as its key and its arguments as a JSON object.
+#[derive(sqlx::FromRow)]
+struct ManifestErrorRow {
+    line: u32,
+    barcode: String,
+    location: String,
+    message_key: String,
+    message_args: String,
+}
+
+impl From<ManifestErrorRow> for ManifestError {
+    fn from(row: ManifestErrorRow) -> ManifestError {
+        let args: BTreeMap<String, String> =
+            serde_json::from_str(&row.message_args).unwrap_or_default();
+        ManifestError {
+            line: row.line,
+            barcode: row.barcode,
+            location: row.location,
+            message: args
+                .into_iter()
+                .fold(Message::new(row.message_key), |message, (name, value)| {
+                    message.arg(name, value)
+                }),
+        }
+    }
+}
+
+/// A row read from a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestRow {
+    /// The line of the file the row is on, counting the header as line 1 and leaving out blank
+    /// lines
+    pub line: u32,
+    /// The barcode of the labware
+    pub labware: String,
+    /// The barcode of the location the labware is in
+    pub location: String,
+}
+
+/// Reads the rows of a manifest from its upload a chunk at a time, so the file never has to be
+/// held in memory. Chunks may end anywhere, even in the middle of a row.
+pub struct ManifestReader {
+    reader: Reader,
+    /// The fields of the row being read
+    fields: Vec<u8>,
+    /// Where each field of the row being read ends in `fields`
+    ends: Vec<usize>,
+    fields_len: usize,
+    ends_len: usize,
+    /// The number of rows read so far, including the header but not blank lines
+    lines: u32,
+    /// The positions of the `labware` and `location` columns, once the header has been read
+    columns: Option<(usize, Option<usize>)>,
+    /// Whether the header has to have a location column
+    locations: bool,
+    /// The header of the labware column, `labware` unless a mapping is used
+    labware_column: String,
+    /// The header of the location column, `location` unless a mapping is used
+    location_column: String,
+}
+
+impl Default for ManifestReader {
+    fn default() -> Self {
+        ManifestReader {
+            reader: Reader::new(),
+            fields: vec![0; 1024],
+            ends: vec![0; 16],
+            fields_len: 0,
+            ends_len: 0,
+            lines: 0,
+            columns: None,
+            locations: true,
+            labware_column: "labware".to_string(),
+            location_column: "location".to_string(),
+        }
+    }
+}
+
+impl ManifestReader {
+    /// A reader of manifests which only list labwares, e.g. the labwares expected in a receipt.
+    /// Their rows have an empty `location`.
+    pub fn without_locations() -> ManifestReader {
+        ManifestReader {
+            locations: false,
+            ..ManifestReader::default()
+        }
+    }
+
+    /// Reads the labware and location barcodes from the columns named by a mapping instead.
+    pub fn mapped(self, mapping: &UploadMapping) -> ManifestReader {
+        ManifestReader {
+            labware_column: mapping.labware_column.clone(),
+            location_column: mapping.location_column.clone(),
+            ..self
+        }
+    }
+
+    /// Reads the rows which end in the next chunk of the upload.
+    ///
+    /// Returns a `ValidationError` if the header is missing a column, or a row is not valid UTF-8.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use manifest::ManifestReader;
+    /// let mut reader = ManifestReader::default();
+    /// let rows = reader.read(b"labware,location\nlw-1,lw-freezer-1\n").unwrap();
+    /// # }
+    /// ```
+    pub fn read(&mut self, chunk: &[u8]) -> Result<Vec<ManifestRow>, ValidationError> {
+        // An empty chunk would tell the reader the upload is over
+        if chunk.is_empty() {
+            return Ok(vec![]);
+        }
+        self.read_records(chunk)
+    }
+
+    /// Reads the last row of the upload, which may not end with a newline.
+    ///
+    /// Returns a `ValidationError` if the upload was empty.
+    pub fn finish(&mut self) -> Result<Vec<ManifestRow>, ValidationError> {
+        let rows = self.read_records(&[])?;
+        if self.columns.is_none() {
+            return Err(ValidationError {
+                message: Message::new("manifest-empty"),
+            });
+        }
+        Ok(rows)
+    }
+
+    fn read_records(&mut self, mut input: &[u8]) -> Result<Vec<ManifestRow>, ValidationError> {
+        let mut rows = vec![];
+        loop {
+            let (result, read, written, ended) = self.reader.read_record(
+                input,
+                &mut self.fields[self.fields_len..],
+                &mut self.ends[self.ends_len..],
+            );
+            input = &input[read..];
+            self.fields_len += written;
+            self.ends_len += ended;
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return Ok(rows),
+                ReadRecordResult::OutputFull => self.fields.resize(self.fields.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    self.lines += 1;
+                    let record = self.record()?;
+                    self.fields_len = 0;
+                    self.ends_len = 0;
+                    match self.columns {
+                        None => self.columns = Some(self.header(&record)?),
+                        Some((labware, location)) => {
+                            if record.iter().any(|field| !field.is_empty()) {
+                                rows.push(ManifestRow {
+                                    line: self.lines,
+                                    labware: record.get(labware).cloned().unwrap_or_default(),
+                                    location: location
+                                        .and_then(|location| record.get(location).cloned())
+                                        .unwrap_or_default(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The trimmed fields of the row which was just read.
+    fn record(&self) -> Result<Vec<String>, ValidationError> {
+        let mut start = 0;
+        let mut record = vec![];
+        for &end in &self.ends[..self.ends_len] {
+            match std::str::from_utf8(&self.fields[start..end]) {
+                Ok(field) => record.push(field.trim().to_string()),
+                Err(_) => {
+                    return Err(ValidationError {
+                        message: Message::new("manifest-invalid-utf8").arg("line", self.lines),
+                    })
+                }
+            }
+            start = end;
+        }
+        Ok(record)
+    }
+
+    /// Finds the positions of the labware and location columns in the header.
+    fn header(&self, record: &[String]) -> Result<(usize, Option<usize>), ValidationError> {
+        let column = |name: &str| {
+            record
+                .iter()
+                .position(|field| field.eq_ignore_ascii_case(name))
+                .ok_or_else(|| ValidationError {
+                    message: Message::new("manifest-missing-column").arg("column", name),
+                })
+        };
+        let location = match self.locations {
+            true => Some(column(&self.location_column)?),
+            false => None,
+        };
+        Ok((column(&self.labware_column)?, location))
+    }
+}
+
+/// Implementation of the Manifest struct
+impl Manifest {
+    /// Starts the upload of a manifest
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use manifest::Manifest;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let manifest = Manifest::create(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(connection: &mut SqliteConnection) -> Result<Manifest, sqlx::Error> {
+        let insert_query_result = sqlx::query("INSERT INTO manifests (uuid) VALUES (?)")
+            .bind(new_uuid())
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query_as::<_, Manifest>("SELECT * FROM manifests WHERE id = ?")
+            .bind(insert_query_result.last_insert_rowid())
+            .fetch_one(&mut *connection)
+            .await
+    }
+
+    /// Find a manifest by its public identifier
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use manifest::Manifest;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let manifest = Manifest::find_by_uuid("5f0c6e0e-...", &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Manifest, NotFoundError> {
+        sqlx::query_as::<_, Manifest>("SELECT * FROM manifests WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("manifest-not-found"),
+            })
+    }
+
+    /// Lists manifests, newest first
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<Manifest>, sqlx::Error> {
+        sqlx::query_as::<_, Manifest>("SELECT * FROM manifests ORDER BY id DESC")
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// The first `MAX_ERRORS_SHOWN` rows of the manifest which could not be imported, in the
+    /// order they are in the file
+    pub async fn errors(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<ManifestError>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, ManifestErrorRow>(
+            "SELECT line, barcode, location, message_key, message_args FROM manifest_errors WHERE manifest_id = ? ORDER BY line LIMIT ?",
+        )
+        .bind(self.id)
+        .bind(MAX_ERRORS_SHOWN)
+        .fetch_all(&mut *connection)
+        .await?;
+        Ok(rows.into_iter().map(ManifestError::from).collect())
+    }
+
+    /// Exports every row of the manifest which could not be imported as CSV, in the order they are
+    /// in the file, with the `line` and the `error` in the given locale
+    ///
+    /// Only the failing rows have to be fixed and uploaded again: the extra columns are ignored.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use labwhere::i18n::DEFAULT_LOCALE;
+    /// use manifest::Manifest;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let csv = manifest.errors_csv(&DEFAULT_LOCALE, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn errors_csv(
+        &self,
+        locale: &LanguageIdentifier,
+        connection: &mut SqliteConnection,
+    ) -> Result<String, sqlx::Error> {
+        let rows = sqlx::query_as::<_, ManifestErrorRow>(
+            "SELECT line, barcode, location, message_key, message_args FROM manifest_errors WHERE manifest_id = ? ORDER BY line",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *connection)
+        .await?;
+        let mut csv = format!("{}\n", ERRORS_CSV_HEADER);
+        for error in rows.into_iter().map(ManifestError::from) {
+            let row = [
+                csv_field(&error.barcode),
+                csv_field(&error.location),
+                error.line.to_string(),
+                csv_field(&error.message.localize(locale)),
+            ];
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        Ok(csv)
+    }
+
+    /// Imports a batch of rows, each like a scan of its labware into its location, and records
+    /// the progress of the upload.
+    ///
+    /// The batch is imported in a single transaction, with each row in a transaction nested
+    /// inside it, so a row which cannot be imported is rolled back on its own and recorded as a
+    /// `ManifestError`. Rows are not imported into locked locations.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use manifest::{Manifest, ManifestReader};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let mut manifest = Manifest::create(&mut connection).await.unwrap();
+    /// let rows = ManifestReader::default().read(b"labware,location\nlw-1,lw-freezer-1\n").unwrap();
+    /// manifest.import(&rows, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn import(
+        &mut self,
+        rows: &[ManifestRow],
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        let mut transaction = connection.begin().await?;
+        let (mut imported, mut failed) = (0, 0);
+        for row in rows {
+            let mut row_transaction = transaction.begin().await?;
+            match Manifest::import_row(row, &mut row_transaction).await {
+                Ok(()) => {
+                    row_transaction.commit().await?;
+                    imported += 1;
+                }
+                Err(e) => {
+                    row_transaction.rollback().await?;
+                    failed += 1;
+                    let message = match domain_message(&*e) {
+                        Some(message) => message.clone(),
+                        None => {
+                            error!("Line {} of manifest {} failed: {}", row.line, self.uuid, e);
+                            Message::new("error-internal")
+                        }
+                    };
+                    let args: BTreeMap<&str, &str> = message
+                        .args
+                        .iter()
+                        .map(|(name, value)| (name.as_ref(), value.as_str()))
+                        .collect();
+                    sqlx::query(
+                        "INSERT INTO manifest_errors (manifest_id, line, barcode, location, message_key, message_args) VALUES (?, ?, ?, ?, ?, ?)",
+                    )
+                    .bind(self.id)
+                    .bind(row.line)
+                    .bind(&row.labware)
+                    .bind(&row.location)
+                    .bind(message.key.as_ref())
+                    .bind(serde_json::to_string(&args).unwrap_or_default())
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+            }
+        }
+        sqlx::query(
+            "UPDATE manifests SET rows_processed = rows_processed + ?, rows_imported = rows_imported + ?, rows_failed = rows_failed + ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(imported + failed)
+        .bind(imported)
+        .bind(failed)
+        .bind(self.id)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        self.rows_processed += imported + failed;
+        self.rows_imported += imported;
+        self.rows_failed += failed;
+        Ok(())
+    }
+
+    /// Puts the labware of a row into its location, registering the labware if it is new.
+    async fn import_row(
+        row: &ManifestRow,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let barcode = BarcodeParser::new(&CONFIG).parse(&row.labware)?.barcode;
+        let location = Location::find_by_barcode(row.location.clone(), &mut *connection).await?;
+        LocationFlag::ensure_not_quarantined(&location, &mut *connection).await?;
+        LocationLock::ensure_unlocked(location.id, None, &mut *connection).await?;
+        Reservation::ensure_fillable(location.id, None, None, &mut *connection).await?;
+        match Labware::find_by_barcode(barcode.clone(), &mut *connection).await {
+            Ok(mut labware) => {
+                labware.location_id = location.id;
+                Labware::update(&labware, None, connection).await?;
+            }
+            Err(_) => {
+                Labware::create(barcode, location.id, connection).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the upload as done
+    pub async fn complete(&mut self, connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        self.finish(ManifestState::Done, None, connection).await
+    }
+
+    /// Marks the upload as failed, recording why
+    pub async fn fail(
+        &mut self,
+        error: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        self.finish(ManifestState::Failed, Some(error), connection)
+            .await
+    }
+
+    async fn finish(
+        &mut self,
+        state: ManifestState,
+        error: Option<String>,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE manifests SET state = ?, error = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(state)
+        .bind(&error)
+        .bind(self.id)
+        .execute(&mut *connection)
+        .await?;
+        self.state = state;
+        self.error = error;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::i18n::DEFAULT_LOCALE;
+    use crate::models::location_lock::LocationLock;
+    use crate::models::location_type::LocationType;
+    use crate::models::manifest::*;
+    use fluent_templates::langid;
+
+    async fn create_locations(connection: &mut SqliteConnection) -> (Location, Location) {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let location1 = Location::create("freezer1".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        let location2 = Location::create("freezer2".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        (location1, location2)
+    }
+
+    #[test]
+    fn test_read_rows_across_chunks() {
+        let mut reader = ManifestReader::default();
+        let mut rows = reader
+            .read(b"\xef\xbb\xbfLocation,notes,Labware\nlw-freezer-1,\"a, b\",lw-")
+            .unwrap();
+        assert!(rows.is_empty());
+        rows.extend(
+            reader
+                .read(b"1\n\nlw-freezer-1,,  lw-2 \r\nlw-freezer-1")
+                .unwrap(),
+        );
+        rows.extend(reader.finish().unwrap());
+        assert_eq!(
+            rows,
+            vec![
+                ManifestRow {
+                    line: 2,
+                    labware: "lw-1".to_string(),
+                    location: "lw-freezer-1".to_string(),
+                },
+                ManifestRow {
+                    line: 3,
+                    labware: "lw-2".to_string(),
+                    location: "lw-freezer-1".to_string(),
+                },
+                ManifestRow {
+                    line: 4,
+                    labware: "".to_string(),
+                    location: "lw-freezer-1".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_invalid_manifests() {
+        let error = ManifestReader::default()
+            .read(b"labware,freezer\nlw-1,lw-freezer-1\n")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The manifest has no location column in its header"
+        );
+
+        let error = ManifestReader::default().finish().unwrap_err();
+        assert_eq!(error.to_string(), "The manifest is empty");
+
+        let error = ManifestReader::default()
+            .read(b"labware,location\nlw-\xff,lw-freezer-1\n")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line 2 of the manifest is not valid UTF-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location, location2) = create_locations(&mut conn).await;
+        let barcode = location.barcode.clone().unwrap();
+        Labware::create("lw-1".to_string(), location2.id, &mut conn)
+            .await
+            .unwrap();
+
+        let mut manifest = Manifest::create(&mut conn).await.unwrap();
+        assert_eq!(manifest.state, ManifestState::Processing);
+        let rows: Vec<ManifestRow> = ["lw-1", "lw-2", "lw-3"]
+            .iter()
+            .enumerate()
+            .map(|(i, labware)| ManifestRow {
+                line: i as u32 + 2,
+                labware: labware.to_string(),
+                location: if i == 1 {
+                    "lw-nowhere-9".to_string()
+                } else {
+                    barcode.clone()
+                },
+            })
+            .collect();
+        manifest.import(&rows, &mut conn).await.unwrap();
+        manifest.complete(&mut conn).await.unwrap();
+
+        let manifest = Manifest::find_by_uuid(&manifest.uuid, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(manifest.state, ManifestState::Done);
+        assert_eq!(
+            (
+                manifest.rows_processed,
+                manifest.rows_imported,
+                manifest.rows_failed
+            ),
+            (3, 2, 1)
+        );
+        assert_eq!(
+            manifest.errors(&mut conn).await.unwrap(),
+            vec![ManifestError {
+                line: 3,
+                barcode: "lw-2".to_string(),
+                location: "lw-nowhere-9".to_string(),
+                message: Message::new("location-not-found"),
+            }]
+        );
+        assert_eq!(
+            manifest
+                .errors_csv(&DEFAULT_LOCALE, &mut conn)
+                .await
+                .unwrap(),
+            "labware,location,line,error\nlw-2,lw-nowhere-9,3,Location not found\n"
+        );
+        assert_eq!(
+            manifest
+                .errors_csv(&langid!("es"), &mut conn)
+                .await
+                .unwrap(),
+            "labware,location,line,error\nlw-2,lw-nowhere-9,3,No se encontró la ubicación\n"
+        );
+        for labware in ["lw-1", "lw-3"] {
+            let labware = Labware::find_by_barcode(labware.to_string(), &mut conn)
+                .await
+                .unwrap();
+            assert_eq!(labware.location_id, location.id);
+        }
+        assert!(Labware::find_by_barcode("lw-2".to_string(), &mut conn)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_into_locked_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location, _) = create_locations(&mut conn).await;
+        LocationLock::acquire(location.id, "jd12".to_string(), 60, &mut conn)
+            .await
+            .unwrap();
+
+        let mut manifest = Manifest::create(&mut conn).await.unwrap();
+        let rows = ManifestReader::default()
+            .read(format!("labware,location\nlw-1,{}\n", location.barcode.unwrap()).as_bytes())
+            .unwrap();
+        manifest.import(&rows, &mut conn).await.unwrap();
+        assert_eq!(manifest.rows_failed, 1);
+        assert!(Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .is_err());
+    }
+}
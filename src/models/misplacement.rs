@@ -0,0 +1,288 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::labware::Labware;
+use crate::models::location::{Location, UNKNOWN_LOCATION_BARCODE};
+use crate::models::new_uuid;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// Selects misplacements along with the names of the locations involved.
+const SELECT_MISPLACEMENTS: &str = "SELECT m.*, r.name AS recorded_location,
+        f.name AS found_location
+    FROM misplacements m
+    JOIN locations r ON r.id = m.recorded_location_id
+    JOIN locations f ON f.id = m.found_location_id";
+
+/// A labware scanned into a location while the records had it in another one, without it having
+/// been checked out in between.
+///
+/// Such a scan is likely to correct a misplacement: the labware was put away in the wrong place at
+/// some point, or moved without being scanned. Misplacements are kept for QC to review.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Misplacement {
+    /// The unique identifier for the Misplacement
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Misplacement, used in URLs
+    pub uuid: String,
+    /// The ID of the scan which found the labware
+    #[serde(skip_serializing)]
+    pub scan_id: u32,
+    /// The ID of the labware
+    #[serde(skip_serializing)]
+    pub labware_id: u32,
+    /// The barcode the labware was scanned with
+    pub labware_barcode: String,
+    /// The ID of the location the records had the labware in
+    #[serde(skip_serializing)]
+    pub recorded_location_id: u32,
+    /// The name of the location the records had the labware in
+    pub recorded_location: String,
+    /// The ID of the location the labware was scanned into
+    #[serde(skip_serializing)]
+    pub found_location_id: u32,
+    /// The name of the location the labware was scanned into
+    pub found_location: String,
+    /// When the labware was scanned
+    pub created_at: DateTime<Utc>,
+    /// Who reviewed the misplacement, once someone has
+    pub reviewed_by: Option<String>,
+    /// When the misplacement was reviewed, once it has been
+    pub reviewed_at: Option<DateTime<Utc>>,
+}
+
+/// Implementation of the Misplacement struct
+impl Misplacement {
+    /// Whether scanning a labware into a location would correct a misplacement: the labware is
+    /// recorded in another location, which is not the unknown location, and is not checked out.
+    pub async fn suspected(
+        labware: &Labware,
+        location: &Location,
+        connection: &mut SqliteConnection,
+    ) -> Result<bool, sqlx::Error> {
+        if labware.location_id == location.id {
+            return Ok(false);
+        }
+        sqlx::query_scalar::<_, bool>(
+            "SELECT NOT EXISTS (SELECT 1 FROM checkouts
+                    WHERE labware_id = ?1 AND checked_in_at IS NULL)
+                AND NOT EXISTS (SELECT 1 FROM locations WHERE id = ?2 AND barcode = ?3)",
+        )
+        .bind(labware.id)
+        .bind(labware.location_id)
+        .bind(UNKNOWN_LOCATION_BARCODE)
+        .fetch_one(&mut *connection)
+        .await
+    }
+
+    /// Records that a scan found a labware outside of the location it was recorded in, auditing the
+    /// labware as misplaced. Called from the scan, once the labware has been moved.
+    pub async fn record(
+        scan_id: u32,
+        labware: &Labware,
+        recorded_location_id: u32,
+        found_location: &Location,
+        connection: &mut SqliteConnection,
+    ) -> Result<Misplacement, sqlx::Error> {
+        let insert_query_result = sqlx::query(
+            "INSERT INTO misplacements (uuid, scan_id, labware_id, labware_barcode,
+                recorded_location_id, found_location_id) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(scan_id)
+        .bind(labware.id)
+        .bind(&labware.barcode)
+        .bind(recorded_location_id)
+        .bind(found_location.id)
+        .execute(&mut *connection)
+        .await?;
+        let misplacement = Misplacement::find(
+            insert_query_result.last_insert_rowid() as u32,
+            &mut *connection,
+        )
+        .await?;
+        Audit::create(
+            "Labware",
+            labware.id,
+            "misplacement",
+            Some(found_location.id),
+            &misplacement,
+            &mut *connection,
+        )
+        .await?;
+        Ok(misplacement)
+    }
+
+    /// Lists misplacements for QC, newest first. With `reviewed`, only those which have (or have
+    /// not) been reviewed are listed.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use misplacement::Misplacement;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let to_review = Misplacement::report(Some(false), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn report(
+        reviewed: Option<bool>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Misplacement>, sqlx::Error> {
+        sqlx::query_as::<_, Misplacement>(&format!(
+            "{} WHERE ?1 IS NULL OR (m.reviewed_at IS NOT NULL) = ?1
+                ORDER BY m.created_at DESC, m.id DESC",
+            SELECT_MISPLACEMENTS
+        ))
+        .bind(reviewed)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Marks a misplacement as reviewed by QC
+    ///
+    /// Returns a `ValidationError` if it has already been reviewed.
+    pub async fn review(
+        &self,
+        reviewed_by: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<Misplacement, Box<dyn Error + Send + Sync>> {
+        if let Some(reviewer) = &self.reviewed_by {
+            return Err(Box::new(ValidationError {
+                message: Message::new("misplacement-reviewed").arg("user", reviewer),
+            }));
+        }
+        sqlx::query(
+            "UPDATE misplacements SET reviewed_by = ?, reviewed_at = CURRENT_TIMESTAMP
+                WHERE id = ?",
+        )
+        .bind(reviewed_by)
+        .bind(self.id)
+        .execute(&mut *connection)
+        .await?;
+        Ok(Misplacement::find(self.id, connection).await?)
+    }
+
+    /// Find a misplacement by its public identifier
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Misplacement, NotFoundError> {
+        sqlx::query_as::<_, Misplacement>(&format!("{} WHERE m.uuid = ?", SELECT_MISPLACEMENTS))
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("misplacement-not-found"),
+            })
+    }
+
+    /// Find a misplacement by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<Misplacement, sqlx::Error> {
+        sqlx::query_as::<_, Misplacement>(&format!("{} WHERE m.id = ?", SELECT_MISPLACEMENTS))
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::checkout::Checkout;
+    use crate::models::location_type::LocationType;
+    use crate::models::misplacement::*;
+    use crate::models::scan::Scan;
+
+    #[tokio::test]
+    async fn test_scan_records_misplacements() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer1 = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let freezer2 = Location::create("freezer2".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let unknown = Location::unknown(&mut conn).await.unwrap();
+        Labware::create("lw-1".to_string(), freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-3".to_string(), unknown.id, &mut conn)
+            .await
+            .unwrap();
+        Checkout::checkout(
+            "lw-2".to_string(),
+            "jane".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        // lw-2 is in transit and lw-3 was nowhere known, so only lw-1 was misplaced
+        let scan = Scan::create(
+            freezer2.barcode.clone().unwrap(),
+            vec!["lw-1".to_string(), "lw-2".to_string(), "lw-3".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(scan.misplacements.len(), 1);
+        let misplacement = &scan.misplacements[0];
+        assert_eq!(misplacement.labware_barcode, "lw-1");
+        assert_eq!(misplacement.recorded_location, "freezer1");
+        assert_eq!(misplacement.found_location, "freezer2");
+        let audits = Audit::for_location(freezer2.id, false, &mut conn)
+            .await
+            .unwrap();
+        assert!(audits.iter().any(|audit| audit.action == "misplacement"));
+
+        // Scanning it into the same location again is not another misplacement
+        let scan = Scan::create(
+            freezer2.barcode.clone().unwrap(),
+            vec!["lw-1".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert!(scan.misplacements.is_empty());
+
+        assert_eq!(
+            Misplacement::report(Some(false), &mut conn).await.unwrap(),
+            vec![misplacement.clone()]
+        );
+        let reviewed = misplacement
+            .review("qc".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(reviewed.reviewed_by.as_deref(), Some("qc"));
+        assert!(Misplacement::report(Some(false), &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Misplacement::report(None, &mut conn).await.unwrap(),
+            vec![reviewed.clone()]
+        );
+        let error = reviewed
+            .review("qc".to_string(), &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The misplacement was already reviewed by qc"
+        );
+    }
+}
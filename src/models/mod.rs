@@ -1,5 +1,76 @@
 // Module hierarchy of this module is as follows.
 // lib -> models -> (descendant e.g., labware)
+pub mod api_key;
+pub mod api_key_usage;
+pub mod audit;
+pub mod barcode_validation;
+pub mod capacity_alert;
+pub mod change;
+pub mod checkout;
+pub mod confirmation;
+pub mod dead_letter;
+pub mod device;
+pub mod event;
+pub mod event_schema;
 pub mod labware;
+pub mod labware_barcode;
+pub mod layout;
 pub mod location;
+pub mod location_flag;
+pub mod location_lock;
+pub mod location_tree;
 pub mod location_type;
+pub mod manifest;
+pub mod misplacement;
+pub mod occupancy;
+pub mod offline_scan;
+pub mod pending_labware;
+pub mod print_job;
+pub mod receipt;
+pub mod reservation;
+pub mod saved_search;
+pub mod scan;
+pub mod shipment;
+pub mod stocktake;
+pub mod stocktake_report;
+pub mod subscription;
+pub mod sync_conflict;
+pub mod sync_peer;
+pub mod transit_alert;
+pub mod upload_mapping;
+pub mod user;
+
+use sha2::{Digest, Sha256};
+use sqlx::SqliteConnection;
+
+/// Generates the public identifier of a new record.
+///
+/// Integer ids are internal to the database. Records are referred to by their UUID in API URLs
+/// and event payloads, so ids never leak record counts and records from several sites can be
+/// merged.
+pub(crate) fn new_uuid() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Generates a secret, e.g. an API key, of `bytes` random bytes as lowercase hex.
+///
+/// Only the digest of a secret (see `secret_digest`) is stored, so the secret itself can only be
+/// handed out when it is generated.
+pub(crate) async fn new_secret(
+    bytes: u32,
+    connection: &mut SqliteConnection,
+) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar("SELECT lower(hex(randomblob(?)))")
+        .bind(bytes)
+        .fetch_one(&mut *connection)
+        .await
+}
+
+/// The SHA-256 digest of a secret as lowercase hex, which is what is stored of it and what a
+/// secret presented by a client is looked up by.
+pub fn secret_digest(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,407 @@
+use crate::metrics::acquire;
+use chrono::NaiveDate;
+use log::{error, info};
+use serde::Serialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::time::Duration;
+
+/// The most days of trend that can be asked for.
+pub const MAX_TREND_DAYS: u32 = 365;
+
+/// How often the occupancy is snapshotted. The last snapshot of a day is the one kept for it.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The scope of the occupancy of a location type in snapshots.
+const LOCATION_TYPE_SCOPE: &str = "location_type";
+
+/// The scope of the occupancy of a top-level location in snapshots.
+const LOCATION_SCOPE: &str = "location";
+
+/// How many labwares there are against how many there is room for, in the locations of a type or
+/// in a top-level location (e.g. a building or a freezer) and everything beneath it.
+///
+/// Only coordinated locations have a capacity (their number of wells), so `occupied` counts the
+/// labwares in coordinated locations while `labwares` counts every labware.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Occupancy {
+    /// The ID of the location type or the top-level location
+    #[serde(skip_serializing)]
+    pub scope_id: u32,
+    /// The name of the location type or the top-level location
+    pub name: String,
+    /// The number of wells
+    pub capacity: u32,
+    /// The number of labwares in wells
+    pub occupied: u32,
+    /// The number of labwares
+    pub labwares: u32,
+    /// The percentage of the capacity which is occupied, or `None` if there is no capacity
+    #[sqlx(skip)]
+    pub percent: Option<u32>,
+    /// How full it was at the end of each of the last days, oldest first
+    #[sqlx(skip)]
+    pub trend: Vec<OccupancySnapshot>,
+}
+
+/// The occupancy at the end of a day.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct OccupancySnapshot {
+    /// The ID of the location type or the top-level location
+    #[serde(skip_serializing)]
+    pub scope_id: u32,
+    /// The day the snapshot was taken on (UTC)
+    pub day: NaiveDate,
+    /// The number of wells
+    pub capacity: u32,
+    /// The number of labwares in wells
+    pub occupied: u32,
+    /// The number of labwares
+    pub labwares: u32,
+}
+
+/// How full a single location was at the end of a day.
+///
+/// Unlike `OccupancySnapshot`, the labwares of the locations beneath it are not counted.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct LocationSnapshot {
+    /// The ID of the location
+    pub location_id: u32,
+    /// The day the snapshot was taken on (UTC)
+    pub day: NaiveDate,
+    /// The number of labwares
+    pub labwares: u32,
+    /// The number of wells, or 0 if the location is not coordinated
+    pub capacity: u32,
+}
+
+/// The occupancy of every location type and every top-level location.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OccupancyStats {
+    /// The occupancy per location type, by name
+    pub location_types: Vec<Occupancy>,
+    /// The occupancy per top-level location, by name
+    pub locations: Vec<Occupancy>,
+}
+
+/// Implementation of the Occupancy struct
+impl Occupancy {
+    /// Works out the current occupancy per location type
+    pub async fn per_location_type(
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Occupancy>, sqlx::Error> {
+        sqlx::query_as::<_, Occupancy>(
+            "SELECT t.id AS scope_id, t.name,
+                COALESCE(SUM(l.rows * l.columns), 0) AS capacity,
+                COALESCE(SUM(CASE WHEN l.rows * l.columns > 0 THEN l.labwares_count END), 0)
+                    AS occupied,
+                COALESCE(SUM(l.labwares_count), 0) AS labwares
+                FROM location_types t
+                LEFT JOIN locations l ON l.location_type_id = t.id
+                GROUP BY t.id ORDER BY t.name",
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .map(with_percent)
+    }
+
+    /// Works out the current occupancy per top-level location, including everything beneath it
+    pub async fn per_top_level_location(
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Occupancy>, sqlx::Error> {
+        sqlx::query_as::<_, Occupancy>(
+            "WITH RECURSIVE roots(id, root_id) AS (
+                SELECT id, id FROM locations WHERE parent_id IS NULL
+                UNION ALL
+                SELECT locations.id, roots.root_id FROM locations
+                    JOIN roots ON locations.parent_id = roots.id
+            )
+            SELECT root.id AS scope_id, root.name,
+                COALESCE(SUM(l.rows * l.columns), 0) AS capacity,
+                COALESCE(SUM(CASE WHEN l.rows * l.columns > 0 THEN l.labwares_count END), 0)
+                    AS occupied,
+                COALESCE(SUM(l.labwares_count), 0) AS labwares
+                FROM roots
+                JOIN locations root ON root.id = roots.root_id
+                JOIN locations l ON l.id = roots.id
+                GROUP BY root.id ORDER BY root.name",
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .map(with_percent)
+    }
+
+    /// Records the current occupancy as today's snapshot, replacing an earlier one from today
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use occupancy::Occupancy;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// Occupancy::snapshot(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn snapshot(connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        let location_types = Occupancy::per_location_type(&mut *connection).await?;
+        let locations = Occupancy::per_top_level_location(&mut *connection).await?;
+        for (scope, occupancies) in [
+            (LOCATION_TYPE_SCOPE, location_types),
+            (LOCATION_SCOPE, locations),
+        ] {
+            for occupancy in occupancies {
+                sqlx::query(
+                    "INSERT INTO occupancy_snapshots
+                        (day, scope, scope_id, name, capacity, occupied, labwares)
+                        VALUES (date('now'), ?, ?, ?, ?, ?, ?)
+                        ON CONFLICT (day, scope, scope_id) DO UPDATE SET name = excluded.name,
+                            capacity = excluded.capacity, occupied = excluded.occupied,
+                            labwares = excluded.labwares",
+                )
+                .bind(scope)
+                .bind(occupancy.scope_id)
+                .bind(&occupancy.name)
+                .bind(occupancy.capacity)
+                .bind(occupancy.occupied)
+                .bind(occupancy.labwares)
+                .execute(&mut *connection)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Implementation of the OccupancyStats struct
+impl OccupancyStats {
+    /// Works out the current occupancy, along with the snapshots of the last `days` days
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use occupancy::OccupancyStats;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let stats = OccupancyStats::build(30, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn build(
+        days: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<OccupancyStats, sqlx::Error> {
+        let mut location_types = Occupancy::per_location_type(&mut *connection).await?;
+        let mut locations = Occupancy::per_top_level_location(&mut *connection).await?;
+        for (scope, occupancies) in [
+            (LOCATION_TYPE_SCOPE, &mut location_types),
+            (LOCATION_SCOPE, &mut locations),
+        ] {
+            let snapshots = sqlx::query_as::<_, OccupancySnapshot>(
+                "SELECT scope_id, day, capacity, occupied, labwares FROM occupancy_snapshots
+                    WHERE scope = ? AND day > date('now', ?) ORDER BY day",
+            )
+            .bind(scope)
+            .bind(format!("-{} days", days))
+            .fetch_all(&mut *connection)
+            .await?;
+            for occupancy in occupancies.iter_mut() {
+                occupancy.trend = snapshots
+                    .iter()
+                    .filter(|snapshot| snapshot.scope_id == occupancy.scope_id)
+                    .cloned()
+                    .collect();
+            }
+        }
+        Ok(OccupancyStats {
+            location_types,
+            locations,
+        })
+    }
+}
+
+/// Implementation of the LocationSnapshot struct
+impl LocationSnapshot {
+    /// Records the current occupancy of every location as today's snapshot, replacing an earlier
+    /// one from today. Returns the number of locations snapshotted.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use occupancy::LocationSnapshot;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let recorded = LocationSnapshot::record(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn record(connection: &mut SqliteConnection) -> Result<u64, sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO snapshots (day, location_id, labwares, capacity)
+                SELECT date('now'), id, labwares_count, COALESCE(rows * columns, 0)
+                    FROM locations WHERE true
+                ON CONFLICT (location_id, day) DO UPDATE SET labwares = excluded.labwares,
+                    capacity = excluded.capacity",
+        )
+        .execute(&mut *connection)
+        .await
+        .map(|result| result.rows_affected())
+    }
+
+    /// Finds the snapshots of the last `days` days, oldest first, of a location or of every
+    /// location if none is given.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use occupancy::LocationSnapshot;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let trend = LocationSnapshot::trend(Some(1), 30, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn trend(
+        location_id: Option<u32>,
+        days: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<LocationSnapshot>, sqlx::Error> {
+        sqlx::query_as::<_, LocationSnapshot>(
+            "SELECT location_id, day, labwares, capacity FROM snapshots
+                WHERE (?1 IS NULL OR location_id = ?1) AND day > date('now', ?2)
+                ORDER BY day, location_id",
+        )
+        .bind(location_id)
+        .bind(format!("-{} days", days))
+        .fetch_all(&mut *connection)
+        .await
+    }
+}
+
+/// Works out how full each occupancy is.
+fn with_percent(mut occupancies: Vec<Occupancy>) -> Vec<Occupancy> {
+    for occupancy in occupancies.iter_mut() {
+        occupancy.percent =
+            (occupancy.capacity > 0).then(|| occupancy.occupied * 100 / occupancy.capacity);
+    }
+    occupancies
+}
+
+/// Snapshots the occupancy every hour, forever, overall and per location, so that `OccupancyStats`
+/// and `LocationSnapshot::trend` can show how it changes.
+///
+/// Meant to be spawned once when the server starts. Failures are logged and retried.
+pub async fn record_snapshots(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = match acquire(&pool).await {
+            Ok(mut connection) => match Occupancy::snapshot(&mut connection).await {
+                Ok(()) => LocationSnapshot::record(&mut connection).await.map(|_| ()),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => info!("Recorded occupancy snapshot"),
+            Err(e) => error!("Could not record occupancy snapshot: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::labware::Labware;
+    use crate::models::layout::Layout;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::occupancy::*;
+
+    async fn setup(connection: &mut SqliteConnection) {
+        let freezer_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let rack_type = LocationType::create("Rack".to_string(), connection)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), freezer_type.id, connection)
+            .await
+            .unwrap();
+        let rack = Location::create_with_parent(
+            "rack1".to_string(),
+            rack_type.id,
+            Some(freezer.id),
+            connection,
+        )
+        .await
+        .unwrap();
+        Layout::resize(&rack, 2, 2, connection).await.unwrap();
+        Labware::create("lw-1".to_string(), rack.id, connection)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), freezer.id, connection)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_occupancy() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        setup(&mut conn).await;
+
+        let location_types = Occupancy::per_location_type(&mut conn).await.unwrap();
+        assert_eq!(location_types.len(), 2);
+        assert_eq!(location_types[0].name, "Freezer");
+        assert_eq!(location_types[0].capacity, 0);
+        assert_eq!(location_types[0].labwares, 1);
+        assert_eq!(location_types[0].percent, None);
+        assert_eq!(location_types[1].name, "Rack");
+        assert_eq!(location_types[1].capacity, 4);
+        assert_eq!(location_types[1].occupied, 1);
+        assert_eq!(location_types[1].percent, Some(25));
+
+        let locations = Occupancy::per_top_level_location(&mut conn).await.unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].name, "freezer1");
+        assert_eq!(locations[0].capacity, 4);
+        assert_eq!(locations[0].occupied, 1);
+        assert_eq!(locations[0].labwares, 2);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_trend() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        setup(&mut conn).await;
+        Occupancy::snapshot(&mut conn).await.unwrap();
+        // A later snapshot on the same day replaces the earlier one
+        Occupancy::snapshot(&mut conn).await.unwrap();
+
+        let stats = OccupancyStats::build(30, &mut conn).await.unwrap();
+        assert_eq!(stats.locations[0].trend.len(), 1);
+        assert_eq!(stats.locations[0].trend[0].labwares, 2);
+        assert_eq!(stats.location_types[1].trend[0].capacity, 4);
+    }
+
+    #[tokio::test]
+    async fn test_location_snapshots() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        setup(&mut conn).await;
+        assert_eq!(LocationSnapshot::record(&mut conn).await.unwrap(), 2);
+        Labware::create("lw-3".to_string(), 2, &mut conn)
+            .await
+            .unwrap();
+        // A later snapshot on the same day replaces the earlier one
+        LocationSnapshot::record(&mut conn).await.unwrap();
+
+        let trend = LocationSnapshot::trend(Some(2), 30, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(trend.len(), 1);
+        assert_eq!(trend[0].labwares, 2);
+        assert_eq!(trend[0].capacity, 4);
+        assert_eq!(
+            LocationSnapshot::trend(None, 30, &mut conn)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        sqlx::query("UPDATE snapshots SET day = date('now', '-40 days')")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        assert!(LocationSnapshot::trend(Some(2), 30, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,402 @@
+use crate::config::{Config, CONFIG};
+use crate::db::health::Backoff;
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::labware::Labware;
+use crate::models::scan::Scan;
+use crate::timestamps::{self, STORAGE_FORMAT};
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// A scan a client recorded while it was offline (e.g. a PWA which worked it out with
+/// `labwhere_core::scan_plan`), submitted once the client is back online.
+///
+/// The scan keeps the UUID the client gave it, so a scan submitted more than once is applied once,
+/// and it is recorded as having happened when it was scanned: its audits and events are dated then
+/// too, like the changes synced from other instances. A time too far from the server's clock is
+/// clamped or rejected, according to the skew policy of the configuration (see
+/// `timestamps::normalize`); a clamped time is audited.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OfflineScan {
+    /// The UUID the client gave the scan
+    pub uuid: String,
+    /// When the scan was recorded on the client
+    pub scanned_at: DateTime<Utc>,
+    /// The barcode of the location the labwares were scanned into
+    pub location_barcode: String,
+    /// The barcodes of the labwares
+    pub labware_barcodes: Vec<String>,
+}
+
+/// A labware an offline scan left where it is, as it changed after the scan was recorded, e.g.
+/// because it was scanned elsewhere in the meantime.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OfflineConflict {
+    /// The barcode of the labware, as scanned
+    pub barcode: String,
+    /// The name of the location the labware is in now
+    pub location: String,
+    /// When the labware last changed
+    pub changed_at: DateTime<Utc>,
+}
+
+/// What became of an offline scan.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OfflineOutcome {
+    /// The scan was applied to the labwares which had not changed since it was recorded
+    Applied(Scan, Vec<OfflineConflict>),
+    /// A scan with the same UUID was applied before, which is returned as it was recorded
+    Duplicate(Scan),
+    /// Every labware had changed since the scan was recorded, so nothing was scanned
+    Conflicted(Vec<OfflineConflict>),
+}
+
+/// Implementation of the OfflineScan struct
+impl OfflineScan {
+    /// Applies scans recorded offline in the order they were recorded (rather than submitted),
+    /// returning the outcome of each in the order they were submitted.
+    ///
+    /// Like `Scan::create_batch`, the batch is committed once, with each scan in a transaction
+    /// nested inside it, so a scan which fails is rolled back on its own. Labwares which changed
+    /// after a scan was recorded are reported as conflicts and left where they are, while the
+    /// other labwares of the scan are scanned. A scan whose labwares all conflict records nothing,
+    /// so it is looked at again if it is submitted again.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use offline_scan::OfflineScan;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let scans = vec![OfflineScan { uuid: "9b2f6c1e-6f2a-4d43-9a3e-0c6d5b1f0e21".to_string(), scanned_at: Utc::now(), location_barcode: "lw-freezer-1".to_string(), labware_barcodes: vec!["lw-1".to_string()] }];
+    /// let outcomes = OfflineScan::apply_batch(scans, None, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn apply_batch(
+        scans: Vec<OfflineScan>,
+        lock_token: Option<&str>,
+        device_id: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Result<OfflineOutcome, Box<dyn Error + Send + Sync>>>, sqlx::Error> {
+        let mut order: Vec<usize> = (0..scans.len()).collect();
+        order.sort_by_key(|&i| scans[i].scanned_at);
+        let mut results: Vec<_> = scans.iter().map(|_| None).collect();
+        let mut transaction = connection.begin().await?;
+        for i in order {
+            let mut backoff = Backoff::new(&CONFIG);
+            let result = loop {
+                let mut scan_transaction = transaction.begin().await?;
+                let result = scans[i]
+                    .apply(lock_token, device_id, &CONFIG, &mut scan_transaction)
+                    .await;
+                match result {
+                    Ok(_) => scan_transaction.commit().await?,
+                    Err(_) => scan_transaction.rollback().await?,
+                }
+                match result {
+                    Err(e) if backoff.retry(&*e).await => continue,
+                    result => break result,
+                }
+            };
+            results[i] = Some(result);
+        }
+        transaction.commit().await?;
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Applies the scan within a transaction the caller commits.
+    async fn apply(
+        &self,
+        lock_token: Option<&str>,
+        device_id: Option<u32>,
+        config: &Config,
+        connection: &mut SqliteConnection,
+    ) -> Result<OfflineOutcome, Box<dyn Error + Send + Sync>> {
+        let uuid = uuid::Uuid::parse_str(self.uuid.trim())
+            .map_err(|_| ValidationError {
+                message: Message::new("scan-offline-invalid-uuid").arg("uuid", &self.uuid),
+            })?
+            .to_string();
+        if let Some(scan) = sqlx::query_as::<_, Scan>("SELECT * FROM scans WHERE uuid = ?")
+            .bind(&uuid)
+            .fetch_optional(&mut *connection)
+            .await?
+        {
+            return Ok(OfflineOutcome::Duplicate(scan));
+        }
+        let normalized = timestamps::normalize(self.scanned_at, config)?;
+        // A client whose clock is ahead within the skew allowed still cannot date its scans after
+        // the ones made since
+        let scanned_at = normalized.timestamp.min(Utc::now()).trunc_subsecs(0);
+        let stored_at = scanned_at.format(STORAGE_FORMAT).to_string();
+
+        let mut conflicts = vec![];
+        let mut conflicted = vec![];
+        let mut labware_barcodes = vec![];
+        for barcode in &self.labware_barcodes {
+            // Barcodes which are not found are left to the scan to register or reject
+            match Labware::find_by_barcode(barcode.clone(), &mut *connection).await {
+                Ok(labware) => match OfflineScan::changed_at(&labware, &stored_at, connection)
+                    .await?
+                {
+                    Some(_) if conflicted.contains(&labware.id) => {}
+                    Some(changed_at) => {
+                        conflicted.push(labware.id);
+                        conflicts.push(OfflineConflict {
+                            barcode: barcode.clone(),
+                            location: sqlx::query_scalar("SELECT name FROM locations WHERE id = ?")
+                                .bind(labware.location_id)
+                                .fetch_one(&mut *connection)
+                                .await?,
+                            changed_at,
+                        })
+                    }
+                    None => labware_barcodes.push(barcode.clone()),
+                },
+                Err(_) => labware_barcodes.push(barcode.clone()),
+            }
+        }
+        if labware_barcodes.is_empty() && !conflicts.is_empty() {
+            return Ok(OfflineOutcome::Conflicted(conflicts));
+        }
+
+        let last_audit_id: u32 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM audits")
+            .fetch_one(&mut *connection)
+            .await?;
+        let last_event_id: u32 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM events")
+            .fetch_one(&mut *connection)
+            .await?;
+        let mut scan = Scan::record(
+            self.location_barcode.clone(),
+            labware_barcodes,
+            lock_token,
+            device_id,
+            config,
+            &mut *connection,
+        )
+        .await?;
+        sqlx::query("UPDATE scans SET uuid = ?, created_at = ? WHERE id = ?")
+            .bind(&uuid)
+            .bind(&stored_at)
+            .bind(scan.id)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("UPDATE audits SET created_at = ? WHERE id > ?")
+            .bind(&stored_at)
+            .bind(last_audit_id)
+            .execute(&mut *connection)
+            .await?;
+        Audit::reseal_after(last_audit_id, &mut *connection).await?;
+        sqlx::query("UPDATE events SET created_at = ? WHERE id > ?")
+            .bind(&stored_at)
+            .bind(last_event_id)
+            .execute(&mut *connection)
+            .await?;
+        if let Some(warning) = normalized.warning {
+            Audit::create(
+                "Scan",
+                scan.id,
+                "clamp-timestamp",
+                Some(scan.location_id),
+                &serde_json::json!({
+                    "uuid": uuid,
+                    "scanned_at": self.scanned_at,
+                    "recorded_at": scanned_at,
+                }),
+                &mut *connection,
+            )
+            .await?;
+            scan.warnings.push(warning);
+        }
+        scan.uuid = uuid;
+        scan.created_at = scanned_at;
+        Ok(OfflineOutcome::Applied(scan, conflicts))
+    }
+
+    /// When the labware last changed, if it changed after a time in the storage format.
+    async fn changed_at(
+        labware: &Labware,
+        after: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT MAX(created_at) FROM audits
+                WHERE auditable_type = 'Labware' AND auditable_id = ? AND created_at > ?",
+        )
+        .bind(labware.id)
+        .bind(after)
+        .fetch_one(&mut *connection)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::offline_scan::*;
+    use chrono::Duration;
+
+    const UUID_1: &str = "9b2f6c1e-6f2a-4d43-9a3e-0c6d5b1f0e21";
+    const UUID_2: &str = "3c1d2e4f-5a6b-4c7d-8e9f-a0b1c2d3e4f5";
+
+    async fn create_locations(connection: &mut SqliteConnection) -> (Location, Location) {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let freezer1 = Location::create("freezer1".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        let freezer2 = Location::create("freezer2".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        (freezer1, freezer2)
+    }
+
+    fn offline_scan(
+        uuid: &str,
+        minutes_ago: i64,
+        location: &Location,
+        labwares: &[&str],
+    ) -> OfflineScan {
+        OfflineScan {
+            uuid: uuid.to_string(),
+            scanned_at: Utc::now() - Duration::minutes(minutes_ago),
+            location_barcode: location.barcode.clone().unwrap(),
+            labware_barcodes: labwares.iter().map(|b| b.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_batch_once() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer1, _) = create_locations(&mut conn).await;
+        let scans = vec![offline_scan(UUID_1, 60, &freezer1, &["lw-1", "lw-2"])];
+
+        let outcomes = OfflineScan::apply_batch(scans.clone(), None, None, &mut conn)
+            .await
+            .unwrap();
+        let OfflineOutcome::Applied(scan, conflicts) = outcomes[0].as_ref().unwrap() else {
+            panic!("the scan was not applied");
+        };
+        assert_eq!(scan.uuid, UUID_1);
+        assert_eq!(scan.message, "2 labwares scanned into freezer1");
+        assert!(conflicts.is_empty());
+        assert!(scan.created_at < Utc::now() - Duration::minutes(59));
+        let audited_at: DateTime<Utc> = sqlx::query_scalar(
+            "SELECT MAX(created_at) FROM audits WHERE auditable_type = 'Labware'",
+        )
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+        assert_eq!(audited_at, scan.created_at);
+
+        let outcomes = OfflineScan::apply_batch(scans, None, None, &mut conn)
+            .await
+            .unwrap();
+        let OfflineOutcome::Duplicate(duplicate) = outcomes[0].as_ref().unwrap() else {
+            panic!("the scan was applied twice");
+        };
+        assert_eq!(duplicate.id, scan.id);
+        let count: u32 = sqlx::query_scalar("SELECT COUNT(*) FROM scans")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_apply_batch_in_order_scanned() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer1, freezer2) = create_locations(&mut conn).await;
+        let scans = vec![
+            offline_scan(UUID_2, 30, &freezer2, &["lw-1"]),
+            offline_scan(UUID_1, 60, &freezer1, &["lw-1"]),
+            offline_scan("not-a-uuid", 10, &freezer1, &["lw-1"]),
+        ];
+
+        let outcomes = OfflineScan::apply_batch(scans, None, None, &mut conn)
+            .await
+            .unwrap();
+        assert!(matches!(
+            outcomes[0].as_ref().unwrap(),
+            OfflineOutcome::Applied(scan, conflicts) if scan.location_id == freezer2.id && conflicts.is_empty()
+        ));
+        assert!(matches!(
+            outcomes[1].as_ref().unwrap(),
+            OfflineOutcome::Applied(scan, _) if scan.location_id == freezer1.id
+        ));
+        assert!(outcomes[2]
+            .as_ref()
+            .unwrap_err()
+            .downcast_ref::<ValidationError>()
+            .is_some());
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, freezer2.id);
+    }
+
+    #[tokio::test]
+    async fn test_apply_batch_clamps_skewed_time() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer1, _) = create_locations(&mut conn).await;
+        let scans = vec![offline_scan(UUID_1, 60 * 24 * 30, &freezer1, &["lw-1"])];
+
+        let outcomes = OfflineScan::apply_batch(scans, None, None, &mut conn)
+            .await
+            .unwrap();
+        let OfflineOutcome::Applied(scan, _) = outcomes[0].as_ref().unwrap() else {
+            panic!("the scan was not applied");
+        };
+        let max_behind = Duration::seconds(CONFIG.timestamp_max_behind_seconds as i64);
+        assert!(scan.created_at >= Utc::now() - max_behind - Duration::minutes(1));
+        assert_eq!(scan.warnings[0].key, "timestamp-clamped");
+        let action: String =
+            sqlx::query_scalar("SELECT action FROM audits WHERE auditable_type = 'Scan'")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+        assert_eq!(action, "clamp-timestamp");
+    }
+
+    #[tokio::test]
+    async fn test_apply_batch_with_conflicts() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer1, freezer2) = create_locations(&mut conn).await;
+        Scan::create(
+            freezer2.barcode.clone().unwrap(),
+            vec!["lw-1".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let scans = vec![
+            offline_scan(UUID_1, 60, &freezer1, &["lw-1", "lw-2"]),
+            offline_scan(UUID_2, 60, &freezer1, &["lw-1"]),
+        ];
+
+        let outcomes = OfflineScan::apply_batch(scans, None, None, &mut conn)
+            .await
+            .unwrap();
+        let OfflineOutcome::Applied(scan, conflicts) = outcomes[0].as_ref().unwrap() else {
+            panic!("the scan was not applied");
+        };
+        assert_eq!(scan.message, "1 labwares scanned into freezer1");
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].barcode, "lw-1");
+        assert_eq!(conflicts[0].location, "freezer2");
+        assert!(matches!(
+            outcomes[1].as_ref().unwrap(),
+            OfflineOutcome::Conflicted(conflicts) if conflicts.len() == 1
+        ));
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, freezer2.id);
+    }
+}
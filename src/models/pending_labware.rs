@@ -0,0 +1,477 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::labware::Labware;
+use crate::models::new_uuid;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// The pending labwares with the barcode of the location they were scanned into.
+const SELECT_PENDING_LABWARES: &str = "SELECT p.*, l.barcode AS location_barcode
+    FROM pending_labwares p JOIN locations l ON l.id = p.location_id";
+
+/// The most known barcodes listed as similar to a pending labware's.
+const MAX_SIMILAR_BARCODES: usize = 5;
+
+/// What happens when a labware barcode which is not known is scanned into a location.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RegistrationPolicy {
+    /// The labware is registered in the location it was scanned into
+    #[default]
+    AutoCreate,
+    /// The whole scan is refused
+    Reject,
+    /// The labware is not registered but queued as a `PendingLabware` for an admin to approve or
+    /// reject. The rest of the scan goes ahead.
+    Quarantine,
+}
+
+impl RegistrationPolicy {
+    /// Parses a registration policy from its name e.g. `quarantine`.
+    pub fn from_name(name: &str) -> Option<RegistrationPolicy> {
+        match name.trim().to_lowercase().as_str() {
+            "auto-create" => Some(RegistrationPolicy::AutoCreate),
+            "reject" => Some(RegistrationPolicy::Reject),
+            "quarantine" => Some(RegistrationPolicy::Quarantine),
+            _ => None,
+        }
+    }
+}
+
+/// Where a pending labware stands.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum PendingStatus {
+    /// Waiting for an admin to review it
+    Pending,
+    /// The labware was created in the location it was scanned into
+    Approved,
+    /// The barcode was not created as a labware, e.g. because it was mistyped
+    Rejected,
+}
+
+impl PendingStatus {
+    /// Parses a pending labware status from its name e.g. `pending`.
+    pub fn from_name(name: &str) -> Option<PendingStatus> {
+        match name.trim().to_lowercase().as_str() {
+            "pending" => Some(PendingStatus::Pending),
+            "approved" => Some(PendingStatus::Approved),
+            "rejected" => Some(PendingStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+/// A labware barcode which was not known when it was scanned into a location under the
+/// quarantine policy, held back from the inventory until an admin approves creating the labware
+/// there or rejects the barcode, so mistyped barcodes do not become labwares.
+///
+/// A barcode is only pending once: scanning it again before it is reviewed moves it to the new
+/// location.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct PendingLabware {
+    /// The unique identifier for the PendingLabware
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the PendingLabware, used in URLs
+    pub uuid: String,
+    /// The barcode which was scanned
+    pub barcode: String,
+    /// The ID of the location the labware was scanned into
+    #[serde(skip_serializing)]
+    pub location_id: u32,
+    /// The barcode of the location the labware was scanned into
+    pub location_barcode: Option<String>,
+    /// Where the pending labware stands
+    pub status: PendingStatus,
+    /// Who approved or rejected the labware
+    pub reviewed_by: Option<String>,
+    /// When the labware was scanned
+    pub created_at: DateTime<Utc>,
+    /// When the labware was approved or rejected
+    pub reviewed_at: Option<DateTime<Utc>>,
+    /// Known labware barcodes one typo away from the barcode, which it was likely meant to be.
+    /// Only filled in by `list`.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub similar_barcodes: Vec<String>,
+}
+
+/// Implementation of the PendingLabware struct
+impl PendingLabware {
+    /// Quarantines a labware scanned into a location, or moves it to the location if it is
+    /// already pending
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use pending_labware::PendingLabware;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let pending = PendingLabware::queue("lw-1", freezer.id, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn queue(
+        barcode: &str,
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<PendingLabware, sqlx::Error> {
+        let moved = sqlx::query(
+            "UPDATE pending_labwares SET location_id = ?
+                WHERE barcode = ? COLLATE NOCASE AND status = 'pending'",
+        )
+        .bind(location_id)
+        .bind(barcode)
+        .execute(&mut *connection)
+        .await?;
+        if moved.rows_affected() == 0 {
+            sqlx::query(
+                "INSERT INTO pending_labwares (uuid, barcode, location_id) VALUES (?, ?, ?)",
+            )
+            .bind(new_uuid())
+            .bind(barcode)
+            .bind(location_id)
+            .execute(&mut *connection)
+            .await?;
+        }
+        sqlx::query_as::<_, PendingLabware>(&format!(
+            "{} WHERE p.barcode = ? COLLATE NOCASE AND p.status = 'pending'",
+            SELECT_PENDING_LABWARES
+        ))
+        .bind(barcode)
+        .fetch_one(&mut *connection)
+        .await
+    }
+
+    /// Lists the pending labwares, or only those with the given status, newest first. Those still
+    /// pending come with the known barcodes similar to theirs.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use pending_labware::{PendingLabware, PendingStatus};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let pending = PendingLabware::list(Some(PendingStatus::Pending), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn list(
+        status: Option<PendingStatus>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<PendingLabware>, sqlx::Error> {
+        let mut pending_labwares = sqlx::query_as::<_, PendingLabware>(&format!(
+            "{} WHERE ?1 IS NULL OR p.status = ?1 ORDER BY p.id DESC",
+            SELECT_PENDING_LABWARES
+        ))
+        .bind(status)
+        .fetch_all(&mut *connection)
+        .await?;
+        for pending in &mut pending_labwares {
+            if pending.status == PendingStatus::Pending {
+                pending.similar_barcodes =
+                    PendingLabware::similar_barcodes(&pending.barcode, &mut *connection).await?;
+            }
+        }
+        Ok(pending_labwares)
+    }
+
+    /// The known labware barcodes one typo (a character added, left out, changed or two swapped)
+    /// away from the barcode.
+    ///
+    /// Such a typo leaves either the first or the second half of a barcode as it was, so only the
+    /// barcodes sharing one of them are compared.
+    async fn similar_barcodes(
+        barcode: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let chars: Vec<char> = barcode.to_lowercase().chars().collect();
+        if chars.len() < 4 {
+            return Ok(vec![]);
+        }
+        let half = chars.len() / 2;
+        let prefix: String = chars[..half].iter().collect();
+        let suffix: String = chars[half + 1..].iter().collect();
+        let candidates = sqlx::query_scalar::<_, String>(
+            "SELECT barcode FROM labware_barcodes
+                WHERE length(barcode) BETWEEN ? AND ?
+                AND (barcode LIKE ? ESCAPE '\\' OR barcode LIKE ? ESCAPE '\\')
+                ORDER BY barcode",
+        )
+        .bind(chars.len() as u32 - 1)
+        .bind(chars.len() as u32 + 1)
+        .bind(format!("{}%", escape_like(&prefix)))
+        .bind(format!("%{}", escape_like(&suffix)))
+        .fetch_all(&mut *connection)
+        .await?;
+        Ok(candidates
+            .into_iter()
+            .filter(|candidate| {
+                let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+                edit_distance(&chars, &candidate) == 1
+            })
+            .take(MAX_SIMILAR_BARCODES)
+            .collect())
+    }
+
+    /// Reviews a pending labware by approving it (`PendingStatus::Approved`), which creates the
+    /// labware in the location it was scanned into, or by rejecting it (`PendingStatus::Rejected`)
+    ///
+    /// A `ValidationError` is returned if the labware is no longer pending or a labware with its
+    /// barcode has been created since it was quarantined.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use pending_labware::{PendingLabware, PendingStatus};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let pending = PendingLabware::review(&uuid, PendingStatus::Approved, "jane".to_string(), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn review(
+        uuid: &str,
+        status: PendingStatus,
+        reviewed_by: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<PendingLabware, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        let pending = sqlx::query_as::<_, PendingLabware>(&format!(
+            "{} WHERE p.uuid = ?",
+            SELECT_PENDING_LABWARES
+        ))
+        .bind(uuid)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or_else(|| NotFoundError {
+            message: Message::new("pending-labware-not-found"),
+        })?;
+        if pending.status != PendingStatus::Pending {
+            return Err(Box::new(ValidationError {
+                message: Message::new("pending-labware-reviewed"),
+            }));
+        }
+        if status == PendingStatus::Approved {
+            if Labware::find_by_barcode(pending.barcode.clone(), &mut transaction)
+                .await
+                .is_ok()
+            {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("pending-labware-exists")
+                        .arg("barcode", &pending.barcode),
+                }));
+            }
+            Labware::create(
+                pending.barcode.clone(),
+                pending.location_id,
+                &mut transaction,
+            )
+            .await?;
+        }
+        sqlx::query(
+            "UPDATE pending_labwares
+                SET status = ?, reviewed_by = ?, reviewed_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(status)
+        .bind(reviewed_by)
+        .bind(pending.id)
+        .execute(&mut *transaction)
+        .await?;
+        let pending = sqlx::query_as::<_, PendingLabware>(&format!(
+            "{} WHERE p.id = ?",
+            SELECT_PENDING_LABWARES
+        ))
+        .bind(pending.id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(pending)
+    }
+}
+
+/// Escapes the wildcards of a `LIKE` pattern, which barcodes may contain (e.g. `lw_1`).
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// The number of characters which have to be added, left out, changed or swapped with their
+/// neighbour to turn one string into the other.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::pending_labware::*;
+
+    async fn create_locations(connection: &mut SqliteConnection) -> (Location, Location) {
+        let location_type = LocationType::create("Freezer".to_string(), &mut *connection)
+            .await
+            .unwrap();
+        let freezer1 = Location::create("freezer1".to_string(), location_type.id, &mut *connection)
+            .await
+            .unwrap();
+        let freezer2 = Location::create("freezer2".to_string(), location_type.id, &mut *connection)
+            .await
+            .unwrap();
+        (freezer1, freezer2)
+    }
+
+    #[test]
+    fn test_registration_policy_from_name() {
+        assert_eq!(
+            RegistrationPolicy::from_name(" Quarantine"),
+            Some(RegistrationPolicy::Quarantine)
+        );
+        assert_eq!(
+            RegistrationPolicy::from_name("auto-create"),
+            Some(RegistrationPolicy::AutoCreate)
+        );
+        assert_eq!(RegistrationPolicy::from_name("ignore"), None);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        let distance = |a: &str, b: &str| {
+            edit_distance(
+                &a.chars().collect::<Vec<_>>(),
+                &b.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(distance("lw-123", "lw-123"), 0);
+        assert_eq!(distance("lw-123", "lw-124"), 1);
+        assert_eq!(distance("lw-123", "lw-1234"), 1);
+        assert_eq!(distance("lw-123", "lw-13"), 1);
+        assert_eq!(distance("lw-123", "lw-132"), 1);
+        assert_eq!(distance("lw-123", "lw-321"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer1, freezer2) = create_locations(&mut conn).await;
+        let pending = PendingLabware::queue("lw-1", freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(pending.status, PendingStatus::Pending);
+        assert_eq!(pending.location_barcode, freezer1.barcode);
+
+        let moved = PendingLabware::queue("LW-1", freezer2.id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(moved.uuid, pending.uuid);
+        assert_eq!(moved.location_id, freezer2.id);
+        let pending = PendingLabware::list(Some(PendingStatus::Pending), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_similar_barcodes() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer1, _) = create_locations(&mut conn).await;
+        for barcode in ["lw_1042", "lw_1024", "lw_1043", "lw_2042", "lw_10424"] {
+            Labware::create(barcode.to_string(), freezer1.id, &mut conn)
+                .await
+                .unwrap();
+        }
+        PendingLabware::queue("LW_1042", freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        PendingLabware::queue("lw_9999", freezer1.id, &mut conn)
+            .await
+            .unwrap();
+
+        let pending = PendingLabware::list(None, &mut conn).await.unwrap();
+        assert_eq!(pending[0].barcode, "lw_9999");
+        assert!(pending[0].similar_barcodes.is_empty());
+        assert_eq!(pending[1].barcode, "LW_1042");
+        assert_eq!(
+            pending[1].similar_barcodes,
+            vec!["lw_1024", "lw_10424", "lw_1043", "lw_2042"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_review() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer1, _) = create_locations(&mut conn).await;
+        let approved = PendingLabware::queue("lw-1", freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        let rejected = PendingLabware::queue("lw-2", freezer1.id, &mut conn)
+            .await
+            .unwrap();
+
+        let approved = PendingLabware::review(
+            &approved.uuid,
+            PendingStatus::Approved,
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(approved.status, PendingStatus::Approved);
+        assert_eq!(approved.reviewed_by.as_deref(), Some("jane"));
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, freezer1.id);
+
+        PendingLabware::review(
+            &rejected.uuid,
+            PendingStatus::Rejected,
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert!(Labware::find_by_barcode("lw-2".to_string(), &mut conn)
+            .await
+            .is_err());
+
+        let err = PendingLabware::review(
+            &rejected.uuid,
+            PendingStatus::Approved,
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "The labware has already been reviewed");
+
+        let queued = PendingLabware::queue("lw-1", freezer1.id, &mut conn)
+            .await
+            .unwrap();
+        let err = PendingLabware::review(
+            &queued.uuid,
+            PendingStatus::Approved,
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Labware lw-1 already exists");
+    }
+}
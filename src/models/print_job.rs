@@ -0,0 +1,529 @@
+use crate::config::CONFIG;
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::labels::{Label, LabelTemplate};
+use crate::models::location::Location;
+use crate::models::new_uuid;
+use crate::notifications::{notify, Notification, Trigger};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// The state of a print job.
+///
+/// Jobs move `queued -> printing -> done`, or to `failed` (with an error) from either of the
+/// first two states. A failed job can be retried, which puts it back in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum PrintJobState {
+    Queued,
+    Printing,
+    Done,
+    Failed,
+}
+
+impl PrintJobState {
+    /// Parses a state from its name e.g. `failed`.
+    pub fn from_name(name: &str) -> Option<PrintJobState> {
+        match name {
+            "queued" => Some(PrintJobState::Queued),
+            "printing" => Some(PrintJobState::Printing),
+            "done" => Some(PrintJobState::Done),
+            "failed" => Some(PrintJobState::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// A request to print the labels of one or more locations on a printer.
+#[derive(Debug, PartialEq, Serialize, sqlx::FromRow)]
+pub struct PrintJob {
+    /// The unique identifier for the PrintJob
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the PrintJob, used in URLs and event payloads
+    pub uuid: String,
+    /// The name of the printer the labels are sent to
+    pub printer: String,
+    /// The template the labels are printed with
+    pub template: LabelTemplate,
+    /// The current state of the job
+    pub state: PrintJobState,
+    /// Why the job failed, if it did
+    pub error: Option<String>,
+    /// The number of times the job has been sent to the printer
+    pub attempts: u32,
+    /// When the job was created
+    pub created_at: DateTime<Utc>,
+    /// When the job last changed state
+    pub updated_at: DateTime<Utc>,
+    /// The barcodes of the locations whose labels are printed
+    #[sqlx(skip)]
+    pub location_barcodes: Vec<String>,
+}
+
+/// Implementation of the PrintJob struct
+impl PrintJob {
+    /// Queues a print job for the labels of the given locations
+    ///
+    /// The labels are printed with the given template, or the template configured for the
+    /// printer if none is given.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use print_job::PrintJob;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let print_job = PrintJob::create("printer-1".to_string(), vec!["lw-freezer-1".to_string()], None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        printer: String,
+        location_barcodes: Vec<String>,
+        template: Option<LabelTemplate>,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, Box<dyn Error + Send + Sync>> {
+        if location_barcodes.is_empty() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("print-job-no-locations"),
+            }));
+        }
+        let mut locations = vec![];
+        for barcode in location_barcodes {
+            locations.push(Location::find_by_barcode(barcode, &mut *connection).await?);
+        }
+
+        let template = template.unwrap_or_else(|| LabelTemplate::for_printer(&CONFIG, &printer));
+
+        let mut transaction = connection.begin().await?;
+        let insert_query_result =
+            sqlx::query("INSERT INTO print_jobs (uuid, printer, template) VALUES (?, ?, ?)")
+                .bind(new_uuid())
+                .bind(printer)
+                .bind(template)
+                .execute(&mut *transaction)
+                .await?;
+        let id = insert_query_result.last_insert_rowid();
+        for location in locations {
+            sqlx::query(
+                "INSERT INTO print_job_locations (print_job_id, location_id) VALUES (?, ?)",
+            )
+            .bind(id)
+            .bind(location.id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(PrintJob::find(id as u32, connection).await?)
+    }
+
+    /// Find a print job by id
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use print_job::PrintJob;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let print_job = PrintJob::find(1, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, NotFoundError> {
+        let print_job = sqlx::query_as::<_, PrintJob>("SELECT * FROM print_jobs WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await;
+        match print_job {
+            Ok(print_job) => print_job.with_location_barcodes(connection).await,
+            Err(_) => Err(NotFoundError {
+                message: Message::new("print-job-not-found"),
+            }),
+        }
+    }
+
+    /// Find a print job by its public identifier
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use print_job::PrintJob;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let print_job = PrintJob::find_by_uuid("5f0c6e0e-...", &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, NotFoundError> {
+        let print_job = sqlx::query_as::<_, PrintJob>("SELECT * FROM print_jobs WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await;
+        match print_job {
+            Ok(print_job) => print_job.with_location_barcodes(connection).await,
+            Err(_) => Err(NotFoundError {
+                message: Message::new("print-job-not-found"),
+            }),
+        }
+    }
+
+    /// Lists print jobs, newest first, optionally only those in the given state
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use print_job::PrintJob;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let failed = PrintJob::all(Some(PrintJobState::Failed), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn all(
+        state: Option<PrintJobState>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<PrintJob>, Box<dyn Error + Send + Sync>> {
+        let print_jobs = sqlx::query_as::<_, PrintJob>(
+            "SELECT * FROM print_jobs WHERE ?1 IS NULL OR state = ?1 ORDER BY id DESC",
+        )
+        .bind(state)
+        .fetch_all(&mut *connection)
+        .await?;
+
+        let mut result = vec![];
+        for print_job in print_jobs {
+            result.push(print_job.with_location_barcodes(&mut *connection).await?);
+        }
+        Ok(result)
+    }
+
+    /// Renders the labels of the locations of the job with its template
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use print_job::PrintJob;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let labels = PrintJob::find(1, &mut connection).await.unwrap().labels(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn labels(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Label>, Box<dyn Error + Send + Sync>> {
+        let mut labels = vec![];
+        for barcode in &self.location_barcodes {
+            let location = Location::find_by_barcode(barcode.clone(), &mut *connection).await?;
+            labels.push(Label::render(
+                &CONFIG,
+                barcode,
+                &location.name,
+                self.template,
+            )?);
+        }
+        Ok(labels)
+    }
+
+    /// Marks a queued job as being sent to the printer
+    pub async fn start(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, Box<dyn Error + Send + Sync>> {
+        PrintJob::transition(
+            id,
+            &[PrintJobState::Queued],
+            PrintJobState::Printing,
+            None,
+            connection,
+        )
+        .await
+    }
+
+    /// Marks a printing job as done
+    pub async fn complete(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, Box<dyn Error + Send + Sync>> {
+        PrintJob::transition(
+            id,
+            &[PrintJobState::Printing],
+            PrintJobState::Done,
+            None,
+            connection,
+        )
+        .await
+    }
+
+    /// Marks a queued or printing job as failed, recording why, and notifies the teams
+    /// configured for failed print jobs
+    pub async fn fail(
+        id: u32,
+        error: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, Box<dyn Error + Send + Sync>> {
+        let print_job = PrintJob::transition(
+            id,
+            &[PrintJobState::Queued, PrintJobState::Printing],
+            PrintJobState::Failed,
+            Some(error.clone()),
+            connection,
+        )
+        .await?;
+        notify(
+            Notification::new(Trigger::PrintJobFailed)
+                .arg("uuid", &print_job.uuid)
+                .arg("printer", &print_job.printer)
+                .arg("attempts", print_job.attempts)
+                .arg("error", error),
+        );
+        Ok(print_job)
+    }
+
+    /// Puts a failed job back in the queue, clearing its error
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use print_job::PrintJob;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let print_job = PrintJob::retry(1, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn retry(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, Box<dyn Error + Send + Sync>> {
+        PrintJob::transition(
+            id,
+            &[PrintJobState::Failed],
+            PrintJobState::Queued,
+            None,
+            connection,
+        )
+        .await
+    }
+
+    /// Moves a job from one of the `from` states to the `to` state.
+    /// Returns a `ValidationError` if the job is not in one of the `from` states.
+    async fn transition(
+        id: u32,
+        from: &[PrintJobState],
+        to: PrintJobState,
+        error: Option<String>,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, Box<dyn Error + Send + Sync>> {
+        let print_job = PrintJob::find(id, &mut *connection).await?;
+        if !from.contains(&print_job.state) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("print-job-invalid-transition")
+                    .arg("id", id)
+                    .arg("from", format!("{:?}", print_job.state))
+                    .arg("to", format!("{:?}", to)),
+            }));
+        }
+        let attempts = if to == PrintJobState::Printing {
+            print_job.attempts + 1
+        } else {
+            print_job.attempts
+        };
+        sqlx::query(
+            "UPDATE print_jobs SET state = ?, error = ?, attempts = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(to)
+        .bind(error)
+        .bind(attempts)
+        .bind(id)
+        .execute(&mut *connection)
+        .await?;
+        Ok(PrintJob::find(id, connection).await?)
+    }
+
+    /// Loads the barcodes of the locations of the job
+    async fn with_location_barcodes(
+        mut self,
+        connection: &mut SqliteConnection,
+    ) -> Result<PrintJob, NotFoundError> {
+        self.location_barcodes = sqlx::query_scalar::<_, String>(
+            "SELECT locations.barcode FROM print_job_locations
+                JOIN locations ON locations.id = print_job_locations.location_id
+                WHERE print_job_locations.print_job_id = ?",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *connection)
+        .await
+        .map_err(|_| NotFoundError {
+            message: Message::new("print-job-locations-not-found"),
+        })?;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::labels::datamatrix::CapSize;
+    use crate::models::location_type::LocationType;
+    use crate::models::print_job::*;
+
+    async fn create_print_job(connection: &mut SqliteConnection) -> PrintJob {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        PrintJob::create(
+            "printer-1".to_string(),
+            vec![location.barcode.unwrap()],
+            None,
+            connection,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn test_state_from_name() {
+        assert_eq!(
+            PrintJobState::from_name("failed"),
+            Some(PrintJobState::Failed)
+        );
+        assert_eq!(PrintJobState::from_name("lost"), None);
+    }
+
+    #[tokio::test]
+    async fn test_create_print_job() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let print_job = create_print_job(&mut conn).await;
+        assert_eq!(print_job.printer, "printer-1");
+        assert_eq!(print_job.state, PrintJobState::Queued);
+        assert_eq!(print_job.attempts, 0);
+        assert_eq!(print_job.template, LabelTemplate::Barcode);
+        assert_eq!(print_job.location_barcodes, vec!["lw-location1-1"]);
+    }
+
+    #[tokio::test]
+    async fn test_print_job_labels() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let print_job = create_print_job(&mut conn).await;
+        let labels = print_job.labels(&mut conn).await.unwrap();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].name, "location1");
+        assert_eq!(labels[0].content, "lw-location1-1");
+
+        let print_job = PrintJob::create(
+            "printer-1".to_string(),
+            vec!["lw-location1-1".to_string()],
+            Some(LabelTemplate::Qr),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(print_job.template, LabelTemplate::Qr);
+        let labels = print_job.labels(&mut conn).await.unwrap();
+        assert!(labels[0].svg.is_some());
+
+        let print_job = PrintJob::create(
+            "printer-1".to_string(),
+            vec!["lw-location1-1".to_string()],
+            Some(LabelTemplate::DataMatrix(CapSize::Micro)),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let print_job = PrintJob::find_by_uuid(&print_job.uuid, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            print_job.template,
+            LabelTemplate::DataMatrix(CapSize::Micro)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_by_uuid() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let print_job = create_print_job(&mut conn).await;
+        let found = PrintJob::find_by_uuid(&print_job.uuid, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(found.id, print_job.id);
+        assert_eq!(found.location_barcodes, vec!["lw-location1-1"]);
+
+        PrintJob::find_by_uuid("1", &mut conn)
+            .await
+            .expect_err("Print job not found");
+    }
+
+    #[tokio::test]
+    async fn test_create_print_job_with_unknown_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let error = PrintJob::create(
+            "printer-1".to_string(),
+            vec!["lw-location1-1".to_string()],
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<NotFoundError>());
+
+        let error = PrintJob::create("printer-1".to_string(), vec![], None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_print_job_lifecycle() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let print_job = create_print_job(&mut conn).await;
+
+        let print_job = PrintJob::start(print_job.id, &mut conn).await.unwrap();
+        assert_eq!(print_job.state, PrintJobState::Printing);
+        assert_eq!(print_job.attempts, 1);
+
+        let print_job = PrintJob::fail(print_job.id, "Out of labels".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(print_job.state, PrintJobState::Failed);
+        assert_eq!(print_job.error, Some("Out of labels".to_string()));
+
+        let print_job = PrintJob::retry(print_job.id, &mut conn).await.unwrap();
+        assert_eq!(print_job.state, PrintJobState::Queued);
+        assert_eq!(print_job.error, None);
+
+        PrintJob::start(print_job.id, &mut conn).await.unwrap();
+        let print_job = PrintJob::complete(print_job.id, &mut conn).await.unwrap();
+        assert_eq!(print_job.state, PrintJobState::Done);
+        assert_eq!(print_job.attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_requires_failed_job() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let print_job = create_print_job(&mut conn).await;
+
+        let error = PrintJob::retry(print_job.id, &mut conn).await.unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_all_print_jobs() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let print_job = create_print_job(&mut conn).await;
+        PrintJob::fail(print_job.id, "Printer offline".to_string(), &mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(PrintJob::all(None, &mut conn).await.unwrap().len(), 1);
+        assert_eq!(
+            PrintJob::all(Some(PrintJobState::Failed), &mut conn)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(PrintJob::all(Some(PrintJobState::Done), &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,386 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::config::Config;
+use crate::errors::{InvalidBarcodeError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::new_uuid;
+use crate::models::scan::Scan;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// The state of a receipt. Labwares can only be received while it is `open`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ReceiptState {
+    Open,
+    Completed,
+}
+
+/// A delivery of labwares into the building (goods in).
+///
+/// A receipt is registered with the labwares expected in the delivery. As the delivery is
+/// unpacked, the labwares are received against it: they are scanned into the receiving location
+/// (see `Config::receiving_location`) like any other scan, and compared with what was expected to
+/// list the shortages (expected but not received) and the extras (received but not expected).
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Receipt {
+    /// The unique identifier for the Receipt
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Receipt, used in URLs
+    pub uuid: String,
+    /// What identifies the delivery e.g. the supplier's delivery note
+    pub reference: String,
+    /// Who registered the delivery
+    pub received_by: String,
+    /// Whether labwares are still being received
+    pub state: ReceiptState,
+    /// When the receipt was registered
+    pub created_at: DateTime<Utc>,
+    /// When the receipt was completed, once it has been
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A labware expected in, or received against, a receipt.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct ReceiptItem {
+    /// The barcode of the labware
+    pub barcode: String,
+    /// Whether the labware was listed when the receipt was registered
+    pub expected: bool,
+    /// When the labware was received, if it has been
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+/// How what was received differs from what was expected.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ReceiptDiscrepancies {
+    /// The barcodes of the labwares which were expected but have not been received
+    pub shortages: Vec<String>,
+    /// The barcodes of the labwares which were received but were not expected
+    pub extras: Vec<String>,
+}
+
+/// Implementation of the Receipt struct
+impl Receipt {
+    /// Registers a delivery and the labwares expected in it
+    ///
+    /// Barcodes are run through the `BarcodeParser` pipeline, and listing a labware twice expects
+    /// it once. Returns a `ValidationError` if no labware is expected, and an `InvalidBarcodeError`
+    /// if a barcode is not valid.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use receipt::Receipt;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let receipt = Receipt::create(&CONFIG, "DN-1234".to_string(), "jane".to_string(), &["lw-1".to_string()], &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        config: &Config,
+        reference: String,
+        received_by: String,
+        barcodes: &[String],
+        connection: &mut SqliteConnection,
+    ) -> Result<Receipt, Box<dyn Error + Send + Sync>> {
+        if barcodes.is_empty() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("receipt-empty"),
+            }));
+        }
+        let parser = BarcodeParser::new(config);
+        let barcodes = barcodes
+            .iter()
+            .map(|barcode| parser.parse(barcode).map(|parsed| parsed.barcode))
+            .collect::<Result<Vec<String>, InvalidBarcodeError>>()?;
+
+        let mut transaction = connection.begin().await?;
+        let insert_query_result =
+            sqlx::query("INSERT INTO receipts (uuid, reference, received_by) VALUES (?, ?, ?)")
+                .bind(new_uuid())
+                .bind(reference)
+                .bind(received_by)
+                .execute(&mut *transaction)
+                .await?;
+        let receipt = Receipt::find(
+            insert_query_result.last_insert_rowid() as u32,
+            &mut transaction,
+        )
+        .await?;
+        for barcode in barcodes {
+            sqlx::query("INSERT OR IGNORE INTO receipt_items (receipt_id, barcode) VALUES (?, ?)")
+                .bind(receipt.id)
+                .bind(barcode)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        Audit::create(
+            "Receipt",
+            receipt.id,
+            "create",
+            None,
+            &receipt,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(receipt)
+    }
+
+    /// Receives labwares against the receipt, scanning them into the receiving location
+    ///
+    /// The labwares are scanned in a single transaction (see `Scan::create`), so either all of
+    /// them are received or none are. Labwares which were not expected are received as extras.
+    /// Returns a `ValidationError` if the receipt is completed or no receiving location is
+    /// configured, and a `NotFoundError` if the receiving location does not exist.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use receipt::Receipt;
+    /// let scan = receipt.receive(&CONFIG, vec!["lw-1".to_string()], None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn receive(
+        &self,
+        config: &Config,
+        barcodes: Vec<String>,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+        self.ensure_open()?;
+        let Some(location_barcode) = config.receiving_location.clone() else {
+            return Err(Box::new(ValidationError {
+                message: Message::new("receipt-no-receiving-location"),
+            }));
+        };
+        let parser = BarcodeParser::new(config);
+        let parsed = barcodes
+            .iter()
+            .map(|barcode| parser.parse(barcode).map(|parsed| parsed.barcode))
+            .collect::<Result<Vec<String>, InvalidBarcodeError>>()?;
+
+        let mut transaction = connection.begin().await?;
+        let scan = Scan::create(
+            location_barcode,
+            barcodes,
+            lock_token,
+            None,
+            &mut transaction,
+        )
+        .await?;
+        for barcode in parsed {
+            sqlx::query(
+                "INSERT INTO receipt_items (receipt_id, barcode, expected, received_at)
+                    VALUES (?, ?, 0, CURRENT_TIMESTAMP)
+                    ON CONFLICT (receipt_id, barcode)
+                    DO UPDATE SET received_at = COALESCE(received_at, CURRENT_TIMESTAMP)",
+            )
+            .bind(self.id)
+            .bind(barcode)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(scan)
+    }
+
+    /// Completes the receipt, so no more labwares can be received against it. Labwares which were
+    /// not received stay listed as shortages.
+    pub async fn complete(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Receipt, Box<dyn Error + Send + Sync>> {
+        self.ensure_open()?;
+        let mut transaction = connection.begin().await?;
+        sqlx::query(
+            "UPDATE receipts SET state = 'completed', completed_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(self.id)
+        .execute(&mut *transaction)
+        .await?;
+        let receipt = Receipt::find(self.id, &mut transaction).await?;
+        Audit::create(
+            "Receipt",
+            receipt.id,
+            "complete",
+            None,
+            &receipt,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(receipt)
+    }
+
+    /// The labwares expected in, or received against, the receipt, in the order they were listed
+    /// or received
+    pub async fn items(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<ReceiptItem>, sqlx::Error> {
+        sqlx::query_as::<_, ReceiptItem>(
+            "SELECT barcode, expected, received_at FROM receipt_items WHERE receipt_id = ?
+                ORDER BY id",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// The shortages and extras of the receipt so far
+    pub async fn discrepancies(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<ReceiptDiscrepancies, sqlx::Error> {
+        let mut discrepancies = ReceiptDiscrepancies::default();
+        for item in self.items(connection).await? {
+            match (item.expected, item.received_at) {
+                (true, None) => discrepancies.shortages.push(item.barcode),
+                (false, Some(_)) => discrepancies.extras.push(item.barcode),
+                _ => {}
+            }
+        }
+        Ok(discrepancies)
+    }
+
+    /// Find a receipt by its public identifier
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use receipt::Receipt;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let receipt = Receipt::find_by_uuid("5f0c6e0e-...", &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Receipt, NotFoundError> {
+        sqlx::query_as::<_, Receipt>("SELECT * FROM receipts WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("receipt-not-found"),
+            })
+    }
+
+    /// Lists receipts, newest first
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<Receipt>, sqlx::Error> {
+        sqlx::query_as::<_, Receipt>("SELECT * FROM receipts ORDER BY id DESC")
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// Returns a `ValidationError` unless labwares can still be received against the receipt
+    fn ensure_open(&self) -> Result<(), ValidationError> {
+        match self.state {
+            ReceiptState::Open => Ok(()),
+            ReceiptState::Completed => Err(ValidationError {
+                message: Message::new("receipt-completed"),
+            }),
+        }
+    }
+
+    /// Find a receipt by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<Receipt, sqlx::Error> {
+        sqlx::query_as::<_, Receipt>("SELECT * FROM receipts WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::init_db;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::receipt::*;
+
+    #[tokio::test]
+    async fn test_receive() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Bench".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let goods_in = Location::create("goods in".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let config = Config {
+            receiving_location: goods_in.barcode.clone(),
+            ..Config::default()
+        };
+
+        let receipt = Receipt::create(
+            &config,
+            "DN-1234".to_string(),
+            "jane".to_string(),
+            &["lw-1".to_string(), "lw-2".to_string(), "LW-1".to_string()],
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(receipt.state, ReceiptState::Open);
+        assert_eq!(receipt.items(&mut conn).await.unwrap().len(), 2);
+
+        receipt
+            .receive(
+                &config,
+                vec!["LW-1".to_string(), "lw-3".to_string()],
+                None,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        let labware = Labware::find_by_barcode("lw-3".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, goods_in.id);
+        assert_eq!(
+            receipt.discrepancies(&mut conn).await.unwrap(),
+            ReceiptDiscrepancies {
+                shortages: vec!["lw-2".to_string()],
+                extras: vec!["lw-3".to_string()],
+            }
+        );
+
+        let receipt = receipt.complete(&mut conn).await.unwrap();
+        assert_eq!(receipt.state, ReceiptState::Completed);
+        let error = receipt
+            .receive(&config, vec!["lw-2".to_string()], None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "The receipt is already completed");
+    }
+
+    #[tokio::test]
+    async fn test_receive_without_receiving_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let config = Config::default();
+        let receipt = Receipt::create(
+            &config,
+            "DN-1234".to_string(),
+            "jane".to_string(),
+            &["lw-1".to_string()],
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let error = receipt
+            .receive(&config, vec!["lw-1".to_string()], None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<ValidationError>().is_some());
+        assert_eq!(
+            receipt.discrepancies(&mut conn).await.unwrap().shortages,
+            vec!["lw-1"]
+        );
+    }
+}
@@ -0,0 +1,459 @@
+use crate::errors::{LockedError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::layout::{coordinate, dimensions};
+use crate::models::location::Location;
+use crate::models::new_uuid;
+use crate::timestamps;
+use crate::timestamps::STORAGE_FORMAT;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::BTreeSet;
+use std::error::Error;
+
+/// Empty storage space booked by a team ahead of an experiment.
+///
+/// A reservation holds either a number of empty wells of a coordinated location, or a whole
+/// location (e.g. an empty box). Until it expires, the reserved space can only be filled by
+/// whoever presents the reservation's token, the same way as a lock's token (see `LocationLock`).
+/// Reservations which are not used by their deadline simply expire, freeing the space.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Reservation {
+    /// The unique identifier for the Reservation
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Reservation, used in URLs
+    pub uuid: String,
+    /// The ID of the location the space is reserved in
+    pub location_id: u32,
+    /// The team the space is reserved for
+    pub team: String,
+    /// What the space is reserved for e.g. the name of the experiment
+    pub purpose: Option<String>,
+    /// Whether the whole location is reserved, rather than some of its wells
+    pub whole_location: bool,
+    /// The secret which has to be presented to fill the reserved space. Only shown when the
+    /// reservation is made.
+    #[serde(skip_serializing)]
+    pub token: String,
+    /// When the reservation expires if the space is not used by then
+    pub expires_at: DateTime<Utc>,
+    /// When the reservation was made
+    pub created_at: DateTime<Utc>,
+}
+
+/// Implementation of the Reservation struct
+impl Reservation {
+    /// Reserves empty space in a location until a deadline
+    ///
+    /// With a number of wells, the first free wells of the coordinated location (in row order)
+    /// are reserved; without one, the whole location is, which has to be empty. Returns a
+    /// `ValidationError` if the deadline has passed or there is not enough free space.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use reservation::Reservation;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let reservation = Reservation::create(&rack, "genomics".to_string(), None, Some(8), next_week, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        location: &Location,
+        team: String,
+        purpose: Option<String>,
+        wells: Option<u32>,
+        expires_at: DateTime<Utc>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Reservation, Box<dyn Error + Send + Sync>> {
+        if expires_at <= Utc::now() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("reservation-expired"),
+            }));
+        }
+        let mut transaction = connection.begin().await?;
+        let active = Reservation::active(location.id, &mut transaction).await?;
+        if let Some(whole) = active.iter().find(|reservation| reservation.whole_location) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("reservation-location-reserved")
+                    .arg("location", &location.name)
+                    .arg("team", &whole.team),
+            }));
+        }
+        let positions = match wells {
+            Some(wells) => {
+                let free = Reservation::free_wells(location, &mut transaction).await?;
+                if wells == 0 || (free.len() as u32) < wells {
+                    return Err(Box::new(ValidationError {
+                        message: Message::new("reservation-not-enough-space")
+                            .arg("location", &location.name)
+                            .arg("wells", wells)
+                            .arg("free", free.len()),
+                    }));
+                }
+                free.into_iter().take(wells as usize).collect()
+            }
+            None => {
+                if location.labwares_count > 0 || !active.is_empty() {
+                    return Err(Box::new(ValidationError {
+                        message: Message::new("reservation-location-not-empty")
+                            .arg("location", &location.name),
+                    }));
+                }
+                vec![]
+            }
+        };
+
+        let insert_query_result = sqlx::query(
+            "INSERT INTO reservations (uuid, location_id, team, purpose, whole_location, token, expires_at)
+                VALUES (?, ?, ?, ?, ?, lower(hex(randomblob(16))), ?)",
+        )
+        .bind(new_uuid())
+        .bind(location.id)
+        .bind(team)
+        .bind(purpose)
+        .bind(wells.is_none())
+        .bind(expires_at.format(STORAGE_FORMAT).to_string())
+        .execute(&mut *transaction)
+        .await?;
+        let reservation = Reservation::find(
+            insert_query_result.last_insert_rowid() as u32,
+            &mut transaction,
+        )
+        .await?;
+        for (row, column) in positions {
+            sqlx::query(
+                "INSERT INTO reservation_positions (reservation_id, row_index, column_index)
+                    VALUES (?, ?, ?)",
+            )
+            .bind(reservation.id)
+            .bind(row)
+            .bind(column)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        Audit::create(
+            "Reservation",
+            reservation.id,
+            "reserve",
+            Some(location.id),
+            &reservation,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(reservation)
+    }
+
+    /// Releases the space before the reservation expires, e.g. once the experiment is cancelled
+    ///
+    /// Returns a `LockedError` unless the reservation's token is given.
+    pub async fn release(
+        &self,
+        token: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.token != token {
+            return Err(Box::new(self.held()));
+        }
+        let mut transaction = connection.begin().await?;
+        sqlx::query("UPDATE reservations SET expires_at = datetime('now') WHERE id = ?")
+            .bind(self.id)
+            .execute(&mut *transaction)
+            .await?;
+        Audit::create(
+            "Reservation",
+            self.id,
+            "release",
+            Some(self.location_id),
+            self,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// The coordinates (e.g. `B3`) of the wells reserved, in row order. Empty if the whole location
+    /// is reserved.
+    pub async fn wells(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let positions = sqlx::query_as::<_, (u32, u32)>(
+            "SELECT row_index, column_index FROM reservation_positions WHERE reservation_id = ?
+                ORDER BY row_index, column_index",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *connection)
+        .await?;
+        Ok(positions
+            .into_iter()
+            .map(|(row, column)| coordinate(row, column))
+            .collect())
+    }
+
+    /// Lists the reservations of a location which have not expired, soonest to expire first
+    pub async fn active(
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Reservation>, sqlx::Error> {
+        sqlx::query_as::<_, Reservation>(
+            "SELECT * FROM reservations WHERE location_id = ? AND expires_at > datetime('now')
+                ORDER BY expires_at, id",
+        )
+        .bind(location_id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Checks that a location, or a well of it, can be filled by whoever presents the given token.
+    ///
+    /// Returns a `LockedError` if the whole location, or the well, is reserved by a reservation
+    /// which has not expired and whose token does not match. This is called from every path which
+    /// puts labware into a location.
+    pub async fn ensure_fillable(
+        location_id: u32,
+        position: Option<(u32, u32)>,
+        token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let reservation = sqlx::query_as::<_, Reservation>(
+            "SELECT * FROM reservations r WHERE location_id = ?1 AND expires_at > datetime('now')
+                AND (whole_location OR EXISTS (SELECT 1 FROM reservation_positions p
+                    WHERE p.reservation_id = r.id AND p.row_index = ?2 AND p.column_index = ?3))
+                AND token IS NOT ?4
+                ORDER BY id LIMIT 1",
+        )
+        .bind(location_id)
+        .bind(position.map(|(row, _)| row))
+        .bind(position.map(|(_, column)| column))
+        .bind(token)
+        .fetch_optional(&mut *connection)
+        .await?;
+        match reservation {
+            Some(reservation) => Err(Box::new(reservation.held())),
+            None => Ok(()),
+        }
+    }
+
+    /// Find a reservation by its public identifier
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Reservation, NotFoundError> {
+        sqlx::query_as::<_, Reservation>("SELECT * FROM reservations WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("reservation-not-found"),
+            })
+    }
+
+    /// The wells of a coordinated location which neither hold a labware nor are reserved, in row
+    /// order
+    async fn free_wells(
+        location: &Location,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<(u32, u32)>, Box<dyn Error + Send + Sync>> {
+        let (rows, columns) = dimensions(location)?;
+        let taken: BTreeSet<(u32, u32)> = sqlx::query_as::<_, (u32, u32)>(
+            "SELECT row_index, column_index FROM labware_positions WHERE location_id = ?1
+            UNION
+            SELECT p.row_index, p.column_index FROM reservation_positions p
+                JOIN reservations r ON r.id = p.reservation_id
+                WHERE r.location_id = ?1 AND r.expires_at > datetime('now')",
+        )
+        .bind(location.id)
+        .fetch_all(&mut *connection)
+        .await?
+        .into_iter()
+        .collect();
+        Ok((1..=rows)
+            .flat_map(|row| (1..=columns).map(move |column| (row, column)))
+            .filter(|position| !taken.contains(position))
+            .collect())
+    }
+
+    /// Find a reservation by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<Reservation, sqlx::Error> {
+        sqlx::query_as::<_, Reservation>("SELECT * FROM reservations WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+    }
+
+    /// The error returned when the reserved space is filled without the reservation's token
+    fn held(&self) -> LockedError {
+        LockedError {
+            message: Message::new("reservation-held")
+                .arg("team", &self.team)
+                .arg("expires_at", timestamps::display(&self.expires_at)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::labware::Labware;
+    use crate::models::layout::Layout;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::reservation::*;
+    use crate::models::scan::Scan;
+    use chrono::Duration;
+
+    async fn create_box(name: &str, connection: &mut SqliteConnection) -> Location {
+        let location_type = LocationType::create("Box".to_string(), connection)
+            .await
+            .unwrap_or_else(|_| panic!("Location type"));
+        let location = Location::create(name.to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        Layout::resize(&location, 2, 2, connection).await.unwrap();
+        Location::find(location.id, connection).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reserve_wells() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let rack = create_box("rack", &mut conn).await;
+        Layout::place(&rack, "A1", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+        let next_week = Utc::now() + Duration::days(7);
+
+        let reservation = Reservation::create(
+            &rack,
+            "genomics".to_string(),
+            Some("sequencing run".to_string()),
+            Some(2),
+            next_week,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            reservation.wells(&mut conn).await.unwrap(),
+            vec!["A2", "B1"]
+        );
+
+        // Only one well is left
+        let error = Reservation::create(
+            &rack,
+            "proteomics".to_string(),
+            None,
+            Some(2),
+            next_week,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Only 1 of the 2 wells asked for are free in rack"
+        );
+
+        // Other teams cannot fill the reserved wells, the team with the token can
+        let error = Layout::place(&rack, "A2", "lw-2".to_string(), None, &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<LockedError>().is_some());
+        Layout::place(&rack, "B2", "lw-2".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+        Layout::place(
+            &rack,
+            "A2",
+            "lw-3".to_string(),
+            Some(&reservation.token),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        // Once released, the space is free again
+        reservation
+            .release(&reservation.token, &mut conn)
+            .await
+            .unwrap();
+        assert!(Reservation::active(rack.id, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+        Layout::place(&rack, "B1", "lw-4".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reserve_whole_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let empty_box = create_box("box", &mut conn).await;
+        let next_week = Utc::now() + Duration::days(7);
+
+        let error = Reservation::create(
+            &empty_box,
+            "genomics".to_string(),
+            None,
+            None,
+            Utc::now() - Duration::days(1),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.downcast_ref::<ValidationError>().is_some());
+
+        let reservation = Reservation::create(
+            &empty_box,
+            "genomics".to_string(),
+            None,
+            None,
+            next_week,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert!(reservation.wells(&mut conn).await.unwrap().is_empty());
+
+        let barcode = empty_box.barcode.clone().unwrap();
+        let error = Scan::create(
+            barcode.clone(),
+            vec!["lw-1".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Space is reserved for genomics until {}",
+                timestamps::display(&reservation.expires_at)
+            )
+        );
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn).await;
+        assert!(labware.is_err());
+        Scan::create(
+            barcode,
+            vec!["lw-1".to_string()],
+            Some(&reservation.token),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        // A reservation which was not used by its deadline frees the space
+        sqlx::query("UPDATE reservations SET expires_at = datetime('now', '-1 minute')")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        Reservation::ensure_fillable(empty_box.id, None, None, &mut conn)
+            .await
+            .unwrap();
+    }
+}
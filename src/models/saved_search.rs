@@ -0,0 +1,220 @@
+use crate::config::Config;
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::labware::LabwareStatus;
+use crate::models::new_uuid;
+use crate::search::{search, SearchResults, SearchSort};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// A search and its filters, saved under a name so that it can be run again, e.g. a weekly report
+/// of the plates which were shipped out.
+///
+/// Saved searches are run by their UUID, so a link to one can be shared with anyone.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct SavedSearch {
+    /// The unique identifier for the SavedSearch
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the SavedSearch, used in URLs
+    pub uuid: String,
+    /// Who saved the search
+    pub owner: String,
+    /// The name of the search, unique among the searches of its owner
+    pub name: String,
+    /// What is searched for, as in `GET /search?q=`
+    pub query: String,
+    /// The status of the labwares found, or `None` for labwares whatever their status
+    pub status: Option<LabwareStatus>,
+    /// The most locations, and the most labwares, returned
+    #[sqlx(rename = "result_limit")]
+    pub limit: u32,
+    /// The order the results are returned in
+    pub sort: SearchSort,
+    /// When the search was saved
+    pub created_at: DateTime<Utc>,
+}
+
+/// Implementation of the SavedSearch struct
+impl SavedSearch {
+    /// Saves a search
+    ///
+    /// Returns a `ValidationError` if the owner already has a search with the same name.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use saved_search::SavedSearch;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let search = SavedSearch::create("jane".to_string(), "Shipped plates".to_string(), "plate".to_string(), Some(LabwareStatus::ShippedOut), 20, SearchSort::Name, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        owner: String,
+        name: String,
+        query: String,
+        status: Option<LabwareStatus>,
+        limit: u32,
+        sort: SearchSort,
+        connection: &mut SqliteConnection,
+    ) -> Result<SavedSearch, Box<dyn Error + Send + Sync>> {
+        let taken = sqlx::query_scalar::<_, u32>(
+            "SELECT COUNT(*) FROM saved_searches WHERE owner = ? AND name = ?",
+        )
+        .bind(&owner)
+        .bind(&name)
+        .fetch_one(&mut *connection)
+        .await?;
+        if taken > 0 {
+            return Err(Box::new(ValidationError {
+                message: Message::new("saved-search-name-taken").arg("name", &name),
+            }));
+        }
+        let uuid = new_uuid();
+        sqlx::query(
+            "INSERT INTO saved_searches (uuid, owner, name, query, status, result_limit, sort)
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&uuid)
+        .bind(owner)
+        .bind(name)
+        .bind(query)
+        .bind(status)
+        .bind(limit)
+        .bind(sort)
+        .execute(&mut *connection)
+        .await?;
+        Ok(SavedSearch::find_by_uuid(&uuid, connection).await?)
+    }
+
+    /// Lists the searches saved by the owner, or by anyone if it is `None`, ordered by owner and
+    /// name
+    pub async fn all(
+        owner: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<SavedSearch>, sqlx::Error> {
+        sqlx::query_as::<_, SavedSearch>(
+            "SELECT * FROM saved_searches WHERE ?1 IS NULL OR owner = ?1 ORDER BY owner, name",
+        )
+        .bind(owner)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Find a saved search by its uuid
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<SavedSearch, NotFoundError> {
+        sqlx::query_as::<_, SavedSearch>("SELECT * FROM saved_searches WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("saved-search-not-found"),
+            })
+    }
+
+    /// Runs the search with its filters, returning the results in its order.
+    pub async fn run(
+        &self,
+        config: &Config,
+        connection: &mut SqliteConnection,
+    ) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+        let results = search(config, &self.query, self.limit, self.status, connection).await?;
+        Ok(results.sorted(self.sort))
+    }
+
+    /// Deletes the saved search.
+    pub async fn delete(&self, connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM saved_searches WHERE id = ?")
+            .bind(self.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::init_db;
+    use crate::models::labware::{Labware, LabwareStatus};
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::saved_search::*;
+
+    #[tokio::test]
+    async fn test_create_and_run() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("Freezer 1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        for barcode in ["plate-1", "plate-2", "plate-3"] {
+            Labware::create(barcode.to_string(), freezer.id, &mut conn)
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE labwares SET status = 'shipped-out' WHERE barcode != 'plate-2'")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let saved = SavedSearch::create(
+            "jane".to_string(),
+            "Shipped plates".to_string(),
+            "plate".to_string(),
+            Some(LabwareStatus::ShippedOut),
+            20,
+            SearchSort::NameDesc,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let error = SavedSearch::create(
+            "jane".to_string(),
+            "Shipped plates".to_string(),
+            "tube".to_string(),
+            None,
+            20,
+            SearchSort::Relevance,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "You already have a saved search named Shipped plates"
+        );
+        // Another owner can use the same name
+        SavedSearch::create(
+            "john".to_string(),
+            "Shipped plates".to_string(),
+            "plate".to_string(),
+            None,
+            1,
+            SearchSort::Relevance,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let results = saved.run(&Config::default(), &mut conn).await.unwrap();
+        let barcodes: Vec<&str> = results.labwares.iter().map(|l| &*l.barcode).collect();
+        assert_eq!(barcodes, vec!["plate-3", "plate-1"]);
+
+        assert_eq!(
+            SavedSearch::all(Some("jane"), &mut conn).await.unwrap(),
+            vec![saved.clone()]
+        );
+        assert_eq!(SavedSearch::all(None, &mut conn).await.unwrap().len(), 2);
+        saved.delete(&mut conn).await.unwrap();
+        assert!(SavedSearch::find_by_uuid(&saved.uuid, &mut conn)
+            .await
+            .is_err());
+    }
+}
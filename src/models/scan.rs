@@ -0,0 +1,673 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::config::{Config, CONFIG};
+use crate::db::health::Backoff;
+use crate::errors::{InvalidBarcodeError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::capacity_alert::CapacityAlert;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::location_flag::LocationFlag;
+use crate::models::location_lock::LocationLock;
+use crate::models::misplacement::Misplacement;
+use crate::models::new_uuid;
+use crate::models::pending_labware::{PendingLabware, RegistrationPolicy};
+use crate::models::reservation::Reservation;
+use chrono::{DateTime, Utc};
+use labwhere_core::scan_plan::{self, ScanPlanError};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// A scan records labwares being put into a location.
+///
+/// Labwares which are already known are moved into the location. What happens to unknown barcodes
+/// depends on the registration policy of the location's type or the barcode's site (see
+/// `Config::registration_policy`): by default they are registered as new labwares in the location.
+/// A known labware which was recorded in another location without being checked out is recorded
+/// as a `Misplacement` the scan corrected.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Scan {
+    /// The unique identifier for the Scan
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Scan, used in URLs and event payloads
+    pub uuid: String,
+    /// The ID of the location the labwares were scanned into
+    pub location_id: u32,
+    /// A human readable summary e.g. `3 labwares scanned into freezer1`
+    pub message: String,
+    /// The ID of the device the scan came from, if it identified itself
+    #[serde(skip_serializing)]
+    pub device_id: Option<u32>,
+    /// When the scan happened
+    pub created_at: DateTime<Utc>,
+    /// The summary as a translatable message. Only set for scans which have just been created.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub summary: Option<Message>,
+    /// The labwares the scan found outside of the location they were recorded in. Only set for
+    /// scans which have just been created.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub misplacements: Vec<Misplacement>,
+    /// The location the labwares were scanned into. Only set for scans which have just been
+    /// created.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<ScannedLocation>,
+    /// Where each labware was and now is, in the order they were scanned. Only set for scans which
+    /// have just been created.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labwares: Vec<ScannedLabware>,
+    /// What the scan went ahead despite, e.g. a location nearly full or a lock the scan held the
+    /// token of. Only set for scans which have just been created.
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub warnings: Vec<Message>,
+}
+
+/// The location a scan put labwares into.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScannedLocation {
+    /// The name of the location
+    pub name: String,
+    /// The barcode of the location
+    pub barcode: Option<String>,
+    /// The names of the locations it is inside of, outermost first, ending with its own
+    pub breadcrumb: Vec<String>,
+}
+
+/// A labware a scan put into a location.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScannedLabware {
+    /// The barcode of the labware
+    pub barcode: String,
+    /// The name of the location the labware was in, or none if the scan registered it
+    pub previous_location: Option<String>,
+    /// The name of the location the labware is in now
+    pub location: String,
+    /// Whether the labware was queued for approval rather than registered
+    pub queued: bool,
+}
+
+/// One scan of a batch: labwares to put into a location.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScanOperation {
+    /// The barcode of the location to scan the labwares into
+    pub location_barcode: String,
+    /// The barcodes of the labwares
+    pub labware_barcodes: Vec<String>,
+}
+
+/// Implementation of the Scan struct
+impl Scan {
+    /// Scans labwares into a location
+    ///
+    /// All labwares are scanned in a single transaction, so either every labware ends up in the
+    /// location or none of them do, and the whole transaction is tried again if the database is
+    /// locked by another connection (see `Backoff`). If the location (or the current location of a
+    /// labware) is locked, the lock's token has to be given. The device the scan came from, if
+    /// known, is recorded with it.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use scan::Scan;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let scan = Scan::create("lw-freezer-1".to_string(), vec!["lw-1".to_string()], None, None, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        location_barcode: String,
+        labware_barcodes: Vec<String>,
+        lock_token: Option<&str>,
+        device_id: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+        let mut backoff = Backoff::new(&CONFIG);
+        loop {
+            let mut transaction = connection.begin().await?;
+            let result = match Scan::record(
+                location_barcode.clone(),
+                labware_barcodes.clone(),
+                lock_token,
+                device_id,
+                &CONFIG,
+                &mut transaction,
+            )
+            .await
+            {
+                Ok(scan) => transaction.commit().await.map(|_| scan).map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            match result {
+                Err(e) if backoff.retry(&*e).await => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Performs a batch of scans, e.g. of a robot working through a rack of tubes, returning the
+    /// result of each in order.
+    ///
+    /// The batch is committed once, in a single transaction, with each scan in a transaction
+    /// nested inside it, so a scan which fails is rolled back on its own (and tried again if the
+    /// database was locked) and the others go ahead.
+    /// Returns an error only if the batch as a whole could not be committed.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use scan::{Scan, ScanOperation};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let operations = vec![ScanOperation { location_barcode: "lw-freezer-1".to_string(), labware_barcodes: vec!["lw-1".to_string()] }];
+    /// let results = Scan::create_batch(operations, None, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create_batch(
+        operations: Vec<ScanOperation>,
+        lock_token: Option<&str>,
+        device_id: Option<u32>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Result<Scan, Box<dyn Error + Send + Sync>>>, sqlx::Error> {
+        let mut transaction = connection.begin().await?;
+        let mut results = Vec::with_capacity(operations.len());
+        for operation in operations {
+            let mut backoff = Backoff::new(&CONFIG);
+            let result = loop {
+                let mut scan_transaction = transaction.begin().await?;
+                let result = Scan::record(
+                    operation.location_barcode.clone(),
+                    operation.labware_barcodes.clone(),
+                    lock_token,
+                    device_id,
+                    &CONFIG,
+                    &mut scan_transaction,
+                )
+                .await;
+                match result {
+                    Ok(_) => scan_transaction.commit().await?,
+                    Err(_) => scan_transaction.rollback().await?,
+                }
+                match result {
+                    Err(e) if backoff.retry(&*e).await => continue,
+                    result => break result,
+                }
+            };
+            results.push(result);
+        }
+        transaction.commit().await?;
+        Ok(results)
+    }
+
+    /// Scans labwares into a location, within a transaction the caller commits, registering
+    /// unknown labwares according to the registration policies of the configuration.
+    pub(crate) async fn record(
+        location_barcode: String,
+        labware_barcodes: Vec<String>,
+        lock_token: Option<&str>,
+        device_id: Option<u32>,
+        config: &Config,
+        connection: &mut SqliteConnection,
+    ) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+        let parser = BarcodeParser::new(config);
+        let barcodes = match scan_plan::labware_barcodes(parser.core(), labware_barcodes) {
+            Ok(barcodes) => barcodes,
+            Err(ScanPlanError::InvalidBarcode(problem)) => {
+                return Err(Box::new(InvalidBarcodeError::from(problem)))
+            }
+            Err(ScanPlanError::NoLabwares) => {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("scan-no-labwares"),
+                }))
+            }
+        };
+
+        let location = Location::find_by_barcode(location_barcode, &mut *connection).await?;
+        LocationFlag::ensure_not_quarantined(&location, &mut *connection).await?;
+        LocationLock::ensure_unlocked(location.id, lock_token, &mut *connection).await?;
+        Reservation::ensure_fillable(location.id, None, lock_token, &mut *connection).await?;
+        let mut location_type = None;
+        let mut queued = 0;
+        let mut misplaced: Vec<(Labware, u32)> = vec![];
+        let mut labwares = vec![];
+        let mut warnings = vec![];
+        let mut checked = vec![];
+        for parsed in &barcodes {
+            let barcode = &parsed.barcode;
+            let mut scanned = ScannedLabware {
+                barcode: barcode.clone(),
+                previous_location: None,
+                location: location.name.clone(),
+                queued: false,
+            };
+            match Labware::find_by_barcode(barcode.clone(), &mut *connection).await {
+                Ok(mut labware) => {
+                    let suspected =
+                        Misplacement::suspected(&labware, &location, &mut *connection).await?;
+                    let recorded_location_id = labware.location_id;
+                    scanned.previous_location = Some(
+                        sqlx::query_scalar::<_, String>("SELECT name FROM locations WHERE id = ?")
+                            .bind(recorded_location_id)
+                            .fetch_one(&mut *connection)
+                            .await?,
+                    );
+                    if !checked.contains(&recorded_location_id) {
+                        checked.push(recorded_location_id);
+                    }
+                    labware.location_id = location.id;
+                    Labware::update(&labware, lock_token, &mut *connection).await?;
+                    if suspected {
+                        misplaced.push((labware, recorded_location_id));
+                    }
+                }
+                Err(e) if e.downcast_ref::<NotFoundError>().is_some() => {
+                    if location_type.is_none() {
+                        location_type = Some(
+                            sqlx::query_scalar::<_, String>(
+                                "SELECT t.name FROM location_types t
+                                    JOIN locations l ON l.location_type_id = t.id WHERE l.id = ?",
+                            )
+                            .bind(location.id)
+                            .fetch_one(&mut *connection)
+                            .await?,
+                        );
+                    }
+                    let policy = config.registration_policy(
+                        location_type.as_deref().unwrap_or_default(),
+                        parsed.site_prefix.as_deref(),
+                    );
+                    match policy {
+                        RegistrationPolicy::AutoCreate => {
+                            Labware::create(barcode.clone(), location.id, &mut *connection).await?;
+                        }
+                        RegistrationPolicy::Reject => {
+                            return Err(Box::new(ValidationError {
+                                message: Message::new("scan-labware-not-registered")
+                                    .arg("barcode", barcode)
+                                    .arg("location", &location.name),
+                            }));
+                        }
+                        RegistrationPolicy::Quarantine => {
+                            PendingLabware::queue(barcode, location.id, &mut *connection).await?;
+                            scanned.queued = true;
+                            queued += 1;
+                        }
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+            labwares.push(scanned);
+        }
+
+        // The locks checked above only let the scan through when it held their tokens
+        if !checked.contains(&location.id) {
+            checked.insert(0, location.id);
+        }
+        for location_id in checked {
+            if let Some(lock) = LocationLock::active(location_id, &mut *connection).await? {
+                let name =
+                    sqlx::query_scalar::<_, String>("SELECT name FROM locations WHERE id = ?")
+                        .bind(location_id)
+                        .fetch_one(&mut *connection)
+                        .await?;
+                warnings.push(
+                    Message::new("scan-warning-lock-overridden")
+                        .arg("location", name)
+                        .arg("holder", lock.holder),
+                );
+            }
+        }
+        for alert in CapacityAlert::over_threshold(location.id, config, &mut *connection).await? {
+            warnings.push(
+                Message::new("scan-warning-near-capacity")
+                    .arg("location", alert.location)
+                    .arg("percent", alert.percent)
+                    .arg("threshold", alert.threshold),
+            );
+        }
+
+        let summary = match queued {
+            0 => Message::new("scan-created"),
+            _ => Message::new("scan-created-queued").arg("queued", queued),
+        }
+        .arg("count", barcodes.len() - queued)
+        .arg("location", &location.name);
+        let insert_query_result = sqlx::query(
+            "INSERT INTO scans (uuid, location_id, message, device_id) VALUES (?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(location.id)
+        .bind(summary.to_string())
+        .bind(device_id)
+        .execute(&mut *connection)
+        .await?;
+        let mut scan = sqlx::query_as::<_, Scan>("SELECT * FROM scans WHERE id = ?")
+            .bind(insert_query_result.last_insert_rowid())
+            .fetch_one(&mut *connection)
+            .await?;
+        for (labware, recorded_location_id) in &misplaced {
+            let misplacement = Misplacement::record(
+                scan.id,
+                labware,
+                *recorded_location_id,
+                &location,
+                &mut *connection,
+            )
+            .await?;
+            scan.misplacements.push(misplacement);
+        }
+
+        scan.summary = Some(summary);
+        scan.location = Some(ScannedLocation {
+            breadcrumb: location.breadcrumb(&mut *connection).await?,
+            name: location.name,
+            barcode: location.barcode,
+        });
+        scan.labwares = labwares;
+        scan.warnings = warnings;
+        Ok(scan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::errors::{LockedError, NotFoundError};
+    use crate::models::layout::Layout;
+    use crate::models::location_type::LocationType;
+    use crate::models::scan::*;
+    use std::collections::HashMap;
+
+    async fn create_locations(connection: &mut SqliteConnection) -> (Location, Location) {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let location1 = Location::create("freezer1".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        let location2 = Location::create("freezer2".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        (location1, location2)
+    }
+
+    #[tokio::test]
+    async fn test_scan_new_labwares() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location, _) = create_locations(&mut conn).await;
+
+        let scan = Scan::create(
+            location.barcode.clone().unwrap(),
+            vec![
+                "lw-1".to_string(),
+                "lw-2\r\n".to_string(),
+                "lw-1".to_string(),
+            ],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(scan.location_id, location.id);
+        assert_eq!(scan.message, "2 labwares scanned into freezer1");
+
+        let labware = Labware::find_by_barcode("lw-2".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.barcode, "lw-2");
+        assert_eq!(labware.location_id, location.id);
+    }
+
+    #[tokio::test]
+    async fn test_scan_moves_existing_labwares() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location1, location2) = create_locations(&mut conn).await;
+        Labware::create("lw-1".to_string(), location1.id, &mut conn)
+            .await
+            .unwrap();
+
+        Scan::create(
+            location2.barcode.clone().unwrap(),
+            vec!["lw-1".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, location2.id);
+    }
+
+    #[tokio::test]
+    async fn test_scan_resolves_locations_and_warnings() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (freezer, _) = create_locations(&mut conn).await;
+        let location_type = LocationType::create("Rack".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let rack = Location::create_with_parent(
+            "rack1".to_string(),
+            location_type.id,
+            Some(freezer.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Layout::resize(&rack, 1, 2, &mut conn).await.unwrap();
+        Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        let lock = LocationLock::acquire(rack.id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+        let config = Config {
+            capacity_thresholds: HashMap::from([("Rack".to_string(), 50)]),
+            ..Default::default()
+        };
+
+        let scan = Scan::record(
+            rack.barcode.clone().unwrap(),
+            vec!["lw-1".to_string(), "lw-2".to_string()],
+            Some(&lock.token),
+            None,
+            &config,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let location = scan.location.unwrap();
+        assert_eq!(location.name, "rack1");
+        assert_eq!(location.breadcrumb, vec!["freezer1", "rack1"]);
+        assert_eq!(
+            scan.labwares[0].previous_location.as_deref(),
+            Some("freezer1")
+        );
+        assert_eq!(scan.labwares[0].location, "rack1");
+        assert_eq!(scan.labwares[1].previous_location, None);
+        let warnings: Vec<String> = scan.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "rack1 is locked by jane",
+                "rack1 is 100% full (threshold 50%)"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_registration_policies() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location1, location2) = create_locations(&mut conn).await;
+        Labware::create("lw-1".to_string(), location1.id, &mut conn)
+            .await
+            .unwrap();
+        let config = Config {
+            labware_registration: RegistrationPolicy::Reject,
+            location_type_registration: HashMap::from([(
+                "Freezer".to_string(),
+                RegistrationPolicy::Quarantine,
+            )]),
+            ..Default::default()
+        };
+
+        let scan = Scan::record(
+            location2.barcode.clone().unwrap(),
+            vec!["lw-1".to_string(), "lw-2".to_string()],
+            None,
+            None,
+            &config,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            scan.message,
+            "1 labwares scanned into freezer2, 1 queued for review"
+        );
+        assert!(Labware::find_by_barcode("lw-2".to_string(), &mut conn)
+            .await
+            .is_err());
+        let pending = PendingLabware::list(None, &mut conn).await.unwrap();
+        assert_eq!(pending[0].barcode, "lw-2");
+        assert_eq!(pending[0].location_id, location2.id);
+
+        let config = Config {
+            labware_registration: RegistrationPolicy::Reject,
+            ..Default::default()
+        };
+        let err = Scan::record(
+            location1.barcode.clone().unwrap(),
+            vec!["lw-1".to_string(), "lw-3".to_string()],
+            None,
+            None,
+            &config,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Labware lw-3 is not registered and cannot be scanned into freezer1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_validation() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location, _) = create_locations(&mut conn).await;
+
+        let error = Scan::create(
+            location.barcode.clone().unwrap(),
+            vec![],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<ValidationError>());
+
+        let error = Scan::create(
+            "lw-fridge-9".to_string(),
+            vec!["lw-1".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<NotFoundError>());
+    }
+
+    #[tokio::test]
+    async fn test_scan_into_locked_location() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location1, location2) = create_locations(&mut conn).await;
+        Labware::create("lw-1".to_string(), location1.id, &mut conn)
+            .await
+            .unwrap();
+        let lock = LocationLock::acquire(location1.id, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+
+        // Scanning into the locked location
+        let error = Scan::create(
+            location1.barcode.clone().unwrap(),
+            vec!["lw-2".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<LockedError>());
+
+        // Scanning labware out of the locked location rolls back the whole scan
+        let error = Scan::create(
+            location2.barcode.clone().unwrap(),
+            vec!["lw-3".to_string(), "lw-1".to_string()],
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<LockedError>());
+        assert!(Labware::find_by_barcode("lw-3".to_string(), &mut conn)
+            .await
+            .is_err());
+
+        Scan::create(
+            location1.barcode.clone().unwrap(),
+            vec!["lw-2".to_string()],
+            Some(&lock.token),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_batch() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (location1, location2) = create_locations(&mut conn).await;
+        let operation = |location: &Location, barcodes: &[&str]| ScanOperation {
+            location_barcode: location.barcode.clone().unwrap(),
+            labware_barcodes: barcodes.iter().map(|b| b.to_string()).collect(),
+        };
+        let operations = vec![
+            operation(&location1, &["lw-1", "lw-2"]),
+            ScanOperation {
+                location_barcode: "lw-fridge-9".to_string(),
+                labware_barcodes: vec!["lw-3".to_string()],
+            },
+            operation(&location2, &["lw-2"]),
+        ];
+        let results = Scan::create_batch(operations, None, None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().location_id, location1.id);
+        assert!(results[1].as_ref().unwrap_err().is::<NotFoundError>());
+        assert_eq!(results[2].as_ref().unwrap().location_id, location2.id);
+
+        // The failed scan is rolled back on its own
+        assert!(Labware::find_by_barcode("lw-3".to_string(), &mut conn)
+            .await
+            .is_err());
+        let labware = Labware::find_by_barcode("lw-2".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, location2.id);
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, location1.id);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::new_uuid;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// Labwares sent out of the building together, e.g. to a collaborator.
+///
+/// Shipping labwares marks them as `shipped-out` (see `Labware::change_status`), so they are no
+/// longer counted in their location, lose their position and checkout, and are left out of
+/// searches. They keep the location they were shipped from, which the shipment records too, so
+/// they can be put back there should they come back.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Shipment {
+    /// The unique identifier for the Shipment
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Shipment, used in URLs
+    pub uuid: String,
+    /// Where the labwares were sent
+    pub destination: String,
+    /// The reference of the courier taking the labwares, if there is one
+    pub courier_reference: Option<String>,
+    /// The day the labwares were sent
+    pub shipped_on: NaiveDate,
+    /// Who sent the labwares
+    pub shipped_by: String,
+    /// When the shipment was recorded
+    pub created_at: DateTime<Utc>,
+}
+
+/// A labware sent in a shipment.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct ShippedLabware {
+    /// The barcode the labware was shipped with
+    pub barcode: String,
+    /// The ID of the location the labware was shipped from
+    pub location_id: u32,
+}
+
+/// Implementation of the Shipment struct
+impl Shipment {
+    /// Records a shipment of labwares and marks them as shipped out, in a transaction.
+    ///
+    /// Every labware is audited as shipped out and the shipment as created. Returns a
+    /// `ValidationError` if there are no labwares or one of them is not in storage, and a
+    /// `LockedError` if the location of one of them is locked and the lock's token is not given.
+    /// Nothing is shipped unless all of the labwares are.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use shipment::Shipment;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let shipment = Shipment::create(
+    ///     "Wellcome Sanger Institute".to_string(),
+    ///     Some("DHL-123".to_string()),
+    ///     today,
+    ///     "jane".to_string(),
+    ///     &["lw-1".to_string()],
+    ///     None,
+    ///     &mut connection,
+    /// )
+    /// .await
+    /// .unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        destination: String,
+        courier_reference: Option<String>,
+        shipped_on: NaiveDate,
+        shipped_by: String,
+        barcodes: &[String],
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Shipment, Box<dyn Error + Send + Sync>> {
+        if barcodes.is_empty() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("shipment-empty"),
+            }));
+        }
+        let mut labwares: Vec<Labware> = vec![];
+        for barcode in barcodes {
+            let labware = Labware::find_by_barcode(barcode.clone(), &mut *connection)
+                .await
+                .map_err(|_| ValidationError {
+                    message: Message::new("shipment-labware-not-found").arg("barcode", barcode),
+                })?;
+            if !labwares.iter().any(|shipped| shipped.id == labware.id) {
+                labwares.push(labware);
+            }
+        }
+
+        let mut transaction = connection.begin().await?;
+        let insert_query_result = sqlx::query(
+            "INSERT INTO shipments (uuid, destination, courier_reference, shipped_on, shipped_by)
+                VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(&destination)
+        .bind(&courier_reference)
+        .bind(shipped_on)
+        .bind(&shipped_by)
+        .execute(&mut *transaction)
+        .await?;
+        let shipment = Shipment::find(
+            insert_query_result.last_insert_rowid() as u32,
+            &mut transaction,
+        )
+        .await?;
+        let reason = format!("Shipped to {}", shipment.destination);
+        for labware in &labwares {
+            sqlx::query(
+                "INSERT INTO shipment_labwares (shipment_id, labware_id, barcode, location_id)
+                    VALUES (?, ?, ?, ?)",
+            )
+            .bind(shipment.id)
+            .bind(labware.id)
+            .bind(&labware.barcode)
+            .bind(labware.location_id)
+            .execute(&mut *transaction)
+            .await?;
+            labware
+                .change_status(
+                    LabwareStatus::ShippedOut,
+                    Some(&reason),
+                    lock_token,
+                    &mut transaction,
+                )
+                .await?;
+        }
+        Audit::create(
+            "Shipment",
+            shipment.id,
+            "create",
+            None,
+            &shipment,
+            &mut transaction,
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(shipment)
+    }
+
+    /// Find a shipment by its ID
+    pub async fn find(id: u32, connection: &mut SqliteConnection) -> Result<Shipment, sqlx::Error> {
+        sqlx::query_as::<_, Shipment>("SELECT * FROM shipments WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+    }
+
+    /// Find a shipment by its public identifier
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use shipment::Shipment;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let shipment = Shipment::find_by_uuid("5f0c6e0e-...", &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Shipment, NotFoundError> {
+        sqlx::query_as::<_, Shipment>("SELECT * FROM shipments WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("shipment-not-found"),
+            })
+    }
+
+    /// Lists shipments, newest first
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<Shipment>, sqlx::Error> {
+        sqlx::query_as::<_, Shipment>("SELECT * FROM shipments ORDER BY shipped_on DESC, id DESC")
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// The labwares sent in the shipment, in the order they were given
+    pub async fn labwares(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<ShippedLabware>, sqlx::Error> {
+        sqlx::query_as::<_, ShippedLabware>(
+            "SELECT barcode, location_id FROM shipment_labwares WHERE shipment_id = ? ORDER BY id",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::audit::Audit;
+    use crate::models::labware::{Labware, LabwareStatus};
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::shipment::*;
+
+    #[tokio::test]
+    async fn test_create() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        let shipped_on = NaiveDate::from_ymd_opt(2026, 3, 3).unwrap();
+
+        let shipment = Shipment::create(
+            "Hinxton".to_string(),
+            Some("DHL-123".to_string()),
+            shipped_on,
+            "jane".to_string(),
+            &["lw-1".to_string(), "LW-1".to_string()],
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(shipment.destination, "Hinxton");
+        assert_eq!(
+            Shipment::find_by_uuid(&shipment.uuid, &mut conn)
+                .await
+                .unwrap(),
+            shipment
+        );
+        let labwares = shipment.labwares(&mut conn).await.unwrap();
+        assert_eq!(
+            labwares,
+            vec![ShippedLabware {
+                barcode: "lw-1".to_string(),
+                location_id: freezer.id
+            }]
+        );
+        let labware = Labware::find_with_history("lw-1", &mut conn).await.unwrap();
+        assert_eq!(labware.status, LabwareStatus::ShippedOut);
+        assert_eq!(labware.status_reason.as_deref(), Some("Shipped to Hinxton"));
+        let freezer = Location::find(freezer.id, &mut conn).await.unwrap();
+        assert_eq!(freezer.labwares_count, 1);
+        let audits = Audit::for_location(freezer.id, false, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(audits[0].action, "ship_out");
+
+        // Nothing is shipped unless every labware can be
+        let error = Shipment::create(
+            "Hinxton".to_string(),
+            None,
+            shipped_on,
+            "jane".to_string(),
+            &["lw-2".to_string(), "lw-1".to_string()],
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Labware lw-1 is not in storage, so it cannot be shipped"
+        );
+        Labware::find_by_barcode("lw-2".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(Shipment::all(&mut conn).await.unwrap(), vec![shipment]);
+    }
+}
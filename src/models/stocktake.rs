@@ -0,0 +1,594 @@
+use crate::barcode::parser::BarcodeParser;
+use crate::config::CONFIG;
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::new_uuid;
+use crate::notifications::{notify, Notification, Trigger};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// Selects the IDs of a location (bound as `?1`) and every location beneath it, at any depth.
+const SUBTREE: &str = "WITH RECURSIVE subtree(id) AS (
+        SELECT ?1
+        UNION
+        SELECT locations.id FROM locations JOIN subtree ON locations.parent_id = subtree.id
+    )";
+
+/// The state of a stocktake. Labwares can only be scanned while it is `open`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum StocktakeState {
+    Open,
+    Completed,
+}
+
+/// How the physical contents of a location differ from its recorded contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum DiscrepancyKind {
+    /// Recorded in the audited locations but not scanned
+    Missing,
+    /// Scanned but recorded outside the audited locations, or not known at all
+    Unexpected,
+    /// Scanned in an audited location other than the one it is recorded in
+    Misplaced,
+}
+
+/// A labware which was not where it was recorded to be.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Discrepancy {
+    /// What is wrong
+    pub kind: DiscrepancyKind,
+    /// The barcode of the labware
+    pub labware_barcode: String,
+    /// The ID of the location the labware is recorded in, if it is known
+    pub expected_location_id: Option<u32>,
+    /// The ID of the location the labware was scanned in, if it was found
+    pub found_location_id: Option<u32>,
+    /// Whether the labware was moved to where it was found when the stocktake was completed
+    pub corrected: bool,
+}
+
+/// An inventory audit of a location and every location beneath it.
+///
+/// While a stocktake is open, everything physically present is scanned into it location by
+/// location. Scanning does not move anything; instead the scans are compared with the recorded
+/// contents of the locations to list missing, unexpected and misplaced labwares. Completing the
+/// stocktake records the discrepancies and can optionally correct the recorded locations of the
+/// labwares which were found.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Stocktake {
+    /// The unique identifier for the Stocktake
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Stocktake, used in URLs and event payloads
+    pub uuid: String,
+    /// The ID of the location at the top of the audited subtree
+    pub location_id: u32,
+    /// Who started the stocktake
+    pub started_by: String,
+    /// Whether labwares are still being scanned
+    pub state: StocktakeState,
+    /// Whether the recorded locations were corrected when the stocktake was completed
+    pub corrected: bool,
+    /// When the stocktake was started
+    pub created_at: DateTime<Utc>,
+    /// When the stocktake was completed, once it has been
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Implementation of the Stocktake struct
+impl Stocktake {
+    /// Starts a stocktake of a location and every location beneath it
+    ///
+    /// Returns a `ValidationError` if a stocktake of the location is already open.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use stocktake::Stocktake;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let stocktake = Stocktake::open("lw-freezer-1".to_string(), "jane".to_string(), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn open(
+        location_barcode: String,
+        started_by: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<Stocktake, Box<dyn Error + Send + Sync>> {
+        let location = Location::find_by_barcode(location_barcode, &mut *connection).await?;
+        let in_progress = sqlx::query_scalar::<_, u32>(
+            "SELECT COUNT(*) FROM stocktakes WHERE location_id = ? AND state = 'open'",
+        )
+        .bind(location.id)
+        .fetch_one(&mut *connection)
+        .await?;
+        if in_progress > 0 {
+            return Err(Box::new(ValidationError {
+                message: Message::new("stocktake-in-progress").arg("location", &location.name),
+            }));
+        }
+
+        let insert_query_result =
+            sqlx::query("INSERT INTO stocktakes (uuid, location_id, started_by) VALUES (?, ?, ?)")
+                .bind(new_uuid())
+                .bind(location.id)
+                .bind(started_by)
+                .execute(&mut *connection)
+                .await?;
+        Ok(Stocktake::find(insert_query_result.last_insert_rowid() as u32, connection).await?)
+    }
+
+    /// Find a stocktake by its public identifier
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Stocktake, NotFoundError> {
+        sqlx::query_as::<_, Stocktake>("SELECT * FROM stocktakes WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("stocktake-not-found"),
+            })
+    }
+
+    /// Records labwares as physically present in one of the audited locations
+    ///
+    /// A labware scanned again replaces its earlier sighting, so a labware moved during the
+    /// stocktake counts where it was last seen. Returns a `ValidationError` if the stocktake is
+    /// completed or the location is outside the audited subtree.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use stocktake::Stocktake;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// stocktake.scan("lw-shelf-2".to_string(), vec!["lw-1".to_string()], &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn scan(
+        &self,
+        location_barcode: String,
+        labware_barcodes: Vec<String>,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.ensure_open()?;
+        let location = Location::find_by_barcode(location_barcode, &mut *connection).await?;
+        if !self.subtree(&mut *connection).await?.contains(&location.id) {
+            return Err(Box::new(ValidationError {
+                message: Message::new("stocktake-outside").arg("location", &location.name),
+            }));
+        }
+
+        let parser = BarcodeParser::new(&CONFIG);
+        let mut transaction = connection.begin().await?;
+        for barcode in labware_barcodes {
+            let barcode = parser.parse(&barcode)?.barcode;
+            let (barcode, labware_id) =
+                match Labware::find_by_barcode(barcode.clone(), &mut transaction).await {
+                    Ok(labware) => (labware.barcode, Some(labware.id)),
+                    Err(_) => (barcode, None),
+                };
+            sqlx::query(
+                "INSERT INTO stocktake_items (stocktake_id, barcode, labware_id, location_id)
+                    VALUES (?, ?, ?, ?)
+                    ON CONFLICT (stocktake_id, barcode) DO UPDATE SET
+                        location_id = excluded.location_id, scanned_at = CURRENT_TIMESTAMP",
+            )
+            .bind(self.id)
+            .bind(barcode)
+            .bind(labware_id)
+            .bind(location.id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Lists the discrepancies between the scanned and the recorded contents, grouped by kind
+    ///
+    /// While the stocktake is open they are worked out from the current records; once it is
+    /// completed, the discrepancies recorded on completion are returned.
+    pub async fn discrepancies(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Discrepancy>, sqlx::Error> {
+        match self.state {
+            StocktakeState::Open => self.compare(connection).await,
+            StocktakeState::Completed => {
+                sqlx::query_as::<_, Discrepancy>(
+                    "SELECT * FROM stocktake_discrepancies WHERE stocktake_id = ? ORDER BY id",
+                )
+                .bind(self.id)
+                .fetch_all(&mut *connection)
+                .await
+            }
+        }
+    }
+
+    /// Completes the stocktake, recording its discrepancies
+    ///
+    /// If `apply_corrections` is set, unexpected and misplaced labwares are moved to where they
+    /// were found (unknown labwares are registered there), so the lock token has to be given for
+    /// any locked location involved. Missing labwares are left where they are recorded. The teams
+    /// configured for completed stocktakes are notified with a link to the report.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use stocktake::Stocktake;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let stocktake = stocktake.complete(true, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn complete(
+        &self,
+        apply_corrections: bool,
+        lock_token: Option<&str>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Stocktake, Box<dyn Error + Send + Sync>> {
+        self.ensure_open()?;
+        let discrepancies = self.compare(&mut *connection).await?;
+        let discrepancy_count = discrepancies.len();
+
+        let mut transaction = connection.begin().await?;
+        for mut discrepancy in discrepancies {
+            if let (true, Some(found_location_id)) =
+                (apply_corrections, discrepancy.found_location_id)
+            {
+                match Labware::find_by_barcode(
+                    discrepancy.labware_barcode.clone(),
+                    &mut transaction,
+                )
+                .await
+                {
+                    Ok(mut labware) => {
+                        labware.location_id = found_location_id;
+                        Labware::update(&labware, lock_token, &mut transaction).await?;
+                    }
+                    Err(_) => {
+                        Labware::create(
+                            discrepancy.labware_barcode.clone(),
+                            found_location_id,
+                            &mut transaction,
+                        )
+                        .await?;
+                    }
+                }
+                discrepancy.corrected = true;
+            }
+            sqlx::query(
+                "INSERT INTO stocktake_discrepancies
+                    (stocktake_id, kind, labware_barcode, expected_location_id, found_location_id, corrected)
+                    VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(self.id)
+            .bind(discrepancy.kind)
+            .bind(discrepancy.labware_barcode)
+            .bind(discrepancy.expected_location_id)
+            .bind(discrepancy.found_location_id)
+            .bind(discrepancy.corrected)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        sqlx::query(
+            "UPDATE stocktakes SET state = 'completed', corrected = ?, completed_at = CURRENT_TIMESTAMP
+                WHERE id = ?",
+        )
+        .bind(apply_corrections)
+        .bind(self.id)
+        .execute(&mut *transaction)
+        .await?;
+        let stocktake = Stocktake::find(self.id, &mut transaction).await?;
+        let location_name =
+            sqlx::query_scalar::<_, String>("SELECT name FROM locations WHERE id = ?")
+                .bind(self.location_id)
+                .fetch_one(&mut *transaction)
+                .await?;
+        transaction.commit().await?;
+
+        notify(
+            Notification::new(Trigger::StocktakeCompleted)
+                .arg("location", location_name)
+                .arg("user", &stocktake.started_by)
+                .arg("discrepancies", discrepancy_count)
+                .arg(
+                    "report",
+                    format!(
+                        "{}/stocktakes/{}/report.csv",
+                        CONFIG.base_url.trim_end_matches('/'),
+                        stocktake.uuid
+                    ),
+                ),
+        );
+        Ok(stocktake)
+    }
+
+    /// Compares the scanned labwares with the recorded contents of the audited locations
+    async fn compare(
+        &self,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Discrepancy>, sqlx::Error> {
+        let subtree = self.subtree(&mut *connection).await?;
+        let mut discrepancies: Vec<Discrepancy> =
+            sqlx::query_as::<_, (String, Option<u32>)>(&format!(
+                "{} SELECT barcode, location_id FROM labwares
+                    WHERE location_id IN (SELECT id FROM subtree) AND status = 'active'
+                    AND id NOT IN (SELECT labware_id FROM stocktake_items
+                        WHERE stocktake_id = ?2 AND labware_id IS NOT NULL)",
+                SUBTREE
+            ))
+            .bind(self.location_id)
+            .bind(self.id)
+            .fetch_all(&mut *connection)
+            .await?
+            .into_iter()
+            .map(|(barcode, location_id)| Discrepancy {
+                kind: DiscrepancyKind::Missing,
+                labware_barcode: barcode,
+                expected_location_id: location_id,
+                found_location_id: None,
+                corrected: false,
+            })
+            .collect();
+
+        let items = sqlx::query_as::<_, (String, Option<u32>, u32)>(
+            "SELECT stocktake_items.barcode, labwares.location_id, stocktake_items.location_id
+                FROM stocktake_items LEFT JOIN labwares ON labwares.id = stocktake_items.labware_id
+                WHERE stocktake_items.stocktake_id = ?",
+        )
+        .bind(self.id)
+        .fetch_all(&mut *connection)
+        .await?;
+        for (barcode, expected_location_id, found_location_id) in items {
+            let kind = match expected_location_id {
+                Some(id) if id == found_location_id => continue,
+                Some(id) if subtree.contains(&id) => DiscrepancyKind::Misplaced,
+                _ => DiscrepancyKind::Unexpected,
+            };
+            discrepancies.push(Discrepancy {
+                kind,
+                labware_barcode: barcode,
+                expected_location_id,
+                found_location_id: Some(found_location_id),
+                corrected: false,
+            });
+        }
+
+        discrepancies
+            .sort_by(|a, b| (a.kind, &a.labware_barcode).cmp(&(b.kind, &b.labware_barcode)));
+        Ok(discrepancies)
+    }
+
+    /// The IDs of the audited locations
+    async fn subtree(&self, connection: &mut SqliteConnection) -> Result<Vec<u32>, sqlx::Error> {
+        sqlx::query_scalar::<_, u32>(&format!("{} SELECT id FROM subtree", SUBTREE))
+            .bind(self.location_id)
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// Returns a `ValidationError` if the stocktake is completed
+    fn ensure_open(&self) -> Result<(), ValidationError> {
+        match self.state {
+            StocktakeState::Open => Ok(()),
+            StocktakeState::Completed => Err(ValidationError {
+                message: Message::new("stocktake-completed"),
+            }),
+        }
+    }
+
+    /// Find a stocktake by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<Stocktake, NotFoundError> {
+        sqlx::query_as::<_, Stocktake>("SELECT * FROM stocktakes WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("stocktake-not-found"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::location_type::LocationType;
+    use crate::models::stocktake::*;
+
+    /// A freezer with two shelves, and a fridge outside the freezer.
+    async fn create_locations(connection: &mut SqliteConnection) -> Vec<Location> {
+        let location_type = LocationType::create("Freezer".to_string(), connection)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        let mut locations = vec![];
+        for name in ["shelf1", "shelf2"] {
+            locations.push(
+                Location::create_with_parent(
+                    name.to_string(),
+                    location_type.id,
+                    Some(freezer.id),
+                    connection,
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        let fridge = Location::create("fridge".to_string(), location_type.id, connection)
+            .await
+            .unwrap();
+        locations.insert(0, freezer);
+        locations.push(fridge);
+        locations
+    }
+
+    #[tokio::test]
+    async fn test_discrepancies() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let locations = create_locations(&mut conn).await;
+        let (freezer, shelf1, shelf2, fridge) =
+            (&locations[0], &locations[1], &locations[2], &locations[3]);
+        for (barcode, location) in [
+            ("lw-st-1", shelf1),
+            ("lw-st-2", shelf1),
+            ("lw-st-3", shelf2),
+            ("lw-st-4", fridge),
+        ] {
+            Labware::create(barcode.to_string(), location.id, &mut conn)
+                .await
+                .unwrap();
+        }
+
+        let stocktake = Stocktake::open(
+            freezer.barcode.clone().unwrap(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(stocktake.state, StocktakeState::Open);
+        stocktake
+            .scan(
+                shelf1.barcode.clone().unwrap(),
+                vec![
+                    "lw-st-1".to_string(),
+                    "lw-st-3".to_string(),
+                    "lw-st-4".to_string(),
+                    "lw-st-new".to_string(),
+                ],
+                &mut conn,
+            )
+            .await
+            .unwrap();
+
+        let discrepancies = stocktake.discrepancies(&mut conn).await.unwrap();
+        let summary: Vec<(DiscrepancyKind, &str)> = discrepancies
+            .iter()
+            .map(|d| (d.kind, d.labware_barcode.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (DiscrepancyKind::Missing, "lw-st-2"),
+                (DiscrepancyKind::Unexpected, "lw-st-4"),
+                (DiscrepancyKind::Unexpected, "lw-st-new"),
+                (DiscrepancyKind::Misplaced, "lw-st-3"),
+            ]
+        );
+        assert_eq!(discrepancies[3].expected_location_id, Some(shelf2.id));
+        assert_eq!(discrepancies[3].found_location_id, Some(shelf1.id));
+
+        // Scanning a labware again counts it where it was last seen
+        stocktake
+            .scan(
+                shelf2.barcode.clone().unwrap(),
+                vec!["lw-st-3".to_string()],
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        assert_eq!(stocktake.discrepancies(&mut conn).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_scan_outside_subtree() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let locations = create_locations(&mut conn).await;
+        let stocktake = Stocktake::open(
+            locations[1].barcode.clone().unwrap(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let error = stocktake
+            .scan(
+                locations[2].barcode.clone().unwrap(),
+                vec!["lw-1".to_string()],
+                &mut conn,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Location shelf2 is not part of the stocktake"
+        );
+
+        let error = Stocktake::open(
+            locations[1].barcode.clone().unwrap(),
+            "john".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_complete_with_corrections() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let locations = create_locations(&mut conn).await;
+        let (freezer, shelf1, shelf2) = (&locations[0], &locations[1], &locations[2]);
+        Labware::create("lw-st-5".to_string(), shelf2.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-st-6".to_string(), shelf2.id, &mut conn)
+            .await
+            .unwrap();
+        let stocktake = Stocktake::open(
+            freezer.barcode.clone().unwrap(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        stocktake
+            .scan(
+                shelf1.barcode.clone().unwrap(),
+                vec!["lw-st-5".to_string(), "lw-st-new-2".to_string()],
+                &mut conn,
+            )
+            .await
+            .unwrap();
+
+        let completed = stocktake.complete(true, None, &mut conn).await.unwrap();
+        assert_eq!(completed.state, StocktakeState::Completed);
+        assert!(completed.corrected);
+        assert!(completed.completed_at.is_some());
+
+        let discrepancies = completed.discrepancies(&mut conn).await.unwrap();
+        let corrected: Vec<(&str, bool)> = discrepancies
+            .iter()
+            .map(|d| (d.labware_barcode.as_str(), d.corrected))
+            .collect();
+        assert_eq!(
+            corrected,
+            vec![("lw-st-6", false), ("lw-st-new-2", true), ("lw-st-5", true)]
+        );
+        for barcode in ["lw-st-5", "lw-st-new-2"] {
+            let labware = Labware::find_by_barcode(barcode.to_string(), &mut conn)
+                .await
+                .unwrap();
+            assert_eq!(labware.location_id, shelf1.id);
+        }
+
+        let error = completed
+            .scan(
+                shelf1.barcode.clone().unwrap(),
+                vec!["lw-st-5".to_string()],
+                &mut conn,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "The stocktake is already completed");
+    }
+}
@@ -0,0 +1,351 @@
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use crate::models::stocktake::{Discrepancy, DiscrepancyKind, Stocktake, StocktakeState};
+use crate::timestamps;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// The header of the CSV export of a report.
+pub const CSV_HEADER: &str =
+    "stocktake,completed_at,location,location_barcode,kind,labware_barcode,expected_location,found_location,resolution";
+
+/// What was done about a discrepancy when its stocktake was completed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// The labware was moved to where it was found
+    Corrected,
+    /// The records were left as they were, e.g. because the labware is missing
+    Unresolved,
+}
+
+/// A discrepancy with its locations referred to by name.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportLine {
+    /// What is wrong
+    pub kind: DiscrepancyKind,
+    /// The barcode of the labware
+    pub labware_barcode: String,
+    /// The name of the location the labware is recorded in, if it is known
+    pub expected_location: Option<String>,
+    /// The name of the location the labware was scanned in, if it was found
+    pub found_location: Option<String>,
+    /// What was done about it
+    pub resolution: Resolution,
+}
+
+/// The discrepancies observed at one location: where a labware was found, or where a missing
+/// labware should have been.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LocationDiscrepancies {
+    /// The name of the location
+    pub location: String,
+    /// The barcode of the location
+    pub location_barcode: Option<String>,
+    /// The number of missing labwares
+    pub missing: usize,
+    /// The number of unexpected labwares
+    pub unexpected: usize,
+    /// The number of misplaced labwares
+    pub misplaced: usize,
+    /// The number of discrepancies which were not corrected
+    pub unresolved: usize,
+    /// The discrepancies
+    pub discrepancies: Vec<ReportLine>,
+}
+
+/// A summary of the discrepancies found by a completed stocktake, per location, suitable for
+/// attaching to quality records.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StocktakeReport {
+    /// The public identifier of the stocktake
+    pub stocktake: String,
+    /// The name of the location at the top of the audited subtree
+    pub location: String,
+    /// Who did the stocktake
+    pub started_by: String,
+    /// When the stocktake was started
+    pub created_at: DateTime<Utc>,
+    /// When the stocktake was completed
+    pub completed_at: DateTime<Utc>,
+    /// Whether the recorded locations were corrected
+    pub corrected: bool,
+    /// The discrepancies grouped by location, ordered by location name
+    pub locations: Vec<LocationDiscrepancies>,
+}
+
+impl StocktakeReport {
+    /// Builds the report of a completed stocktake
+    ///
+    /// Returns a `ValidationError` if the stocktake is still open.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use stocktake_report::StocktakeReport;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let report = StocktakeReport::build(&stocktake, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn build(
+        stocktake: &Stocktake,
+        connection: &mut SqliteConnection,
+    ) -> Result<StocktakeReport, Box<dyn Error + Send + Sync>> {
+        let completed_at = match (stocktake.state, stocktake.completed_at) {
+            (StocktakeState::Completed, Some(completed_at)) => completed_at,
+            _ => {
+                return Err(Box::new(ValidationError {
+                    message: Message::new("stocktake-not-completed"),
+                }))
+            }
+        };
+
+        let mut locations: HashMap<u32, (String, Option<String>)> = HashMap::new();
+        let (root, _) = location(stocktake.location_id, &mut locations, connection).await?;
+        let mut groups: Vec<LocationDiscrepancies> = vec![];
+        let mut group_ids: Vec<u32> = vec![];
+        for discrepancy in stocktake.discrepancies(&mut *connection).await? {
+            let Some(group_id) = discrepancy
+                .found_location_id
+                .or(discrepancy.expected_location_id)
+            else {
+                continue;
+            };
+            let line = line(&discrepancy, &mut locations, connection).await?;
+            let index = match group_ids.iter().position(|id| *id == group_id) {
+                Some(index) => index,
+                None => {
+                    let (name, barcode) = location(group_id, &mut locations, connection).await?;
+                    groups.push(LocationDiscrepancies {
+                        location: name,
+                        location_barcode: barcode,
+                        missing: 0,
+                        unexpected: 0,
+                        misplaced: 0,
+                        unresolved: 0,
+                        discrepancies: vec![],
+                    });
+                    group_ids.push(group_id);
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[index];
+            match line.kind {
+                DiscrepancyKind::Missing => group.missing += 1,
+                DiscrepancyKind::Unexpected => group.unexpected += 1,
+                DiscrepancyKind::Misplaced => group.misplaced += 1,
+            }
+            if line.resolution == Resolution::Unresolved {
+                group.unresolved += 1;
+            }
+            group.discrepancies.push(line);
+        }
+        groups.sort_by(|a, b| a.location.cmp(&b.location));
+
+        Ok(StocktakeReport {
+            stocktake: stocktake.uuid.clone(),
+            location: root,
+            started_by: stocktake.started_by.clone(),
+            created_at: stocktake.created_at,
+            completed_at,
+            corrected: stocktake.corrected,
+            locations: groups,
+        })
+    }
+
+    /// Exports the report as CSV, one row per discrepancy, with timestamps in the display timezone.
+    pub fn to_csv(&self) -> String {
+        let completed_at = timestamps::display(&self.completed_at);
+        let mut csv = format!("{}\n", CSV_HEADER);
+        for group in &self.locations {
+            for line in &group.discrepancies {
+                let row = [
+                    self.stocktake.as_str(),
+                    completed_at.as_str(),
+                    group.location.as_str(),
+                    group.location_barcode.as_deref().unwrap_or_default(),
+                    kind_name(line.kind),
+                    line.labware_barcode.as_str(),
+                    line.expected_location.as_deref().unwrap_or_default(),
+                    line.found_location.as_deref().unwrap_or_default(),
+                    match line.resolution {
+                        Resolution::Corrected => "corrected",
+                        Resolution::Unresolved => "unresolved",
+                    },
+                ];
+                let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+                csv.push_str(&row.join(","));
+                csv.push('\n');
+            }
+        }
+        csv
+    }
+}
+
+/// Names the locations of a discrepancy.
+async fn line(
+    discrepancy: &Discrepancy,
+    locations: &mut HashMap<u32, (String, Option<String>)>,
+    connection: &mut SqliteConnection,
+) -> Result<ReportLine, sqlx::Error> {
+    let mut names = [None, None];
+    for (name, id) in names.iter_mut().zip([
+        discrepancy.expected_location_id,
+        discrepancy.found_location_id,
+    ]) {
+        if let Some(id) = id {
+            *name = Some(location(id, locations, connection).await?.0);
+        }
+    }
+    let [expected_location, found_location] = names;
+    Ok(ReportLine {
+        kind: discrepancy.kind,
+        labware_barcode: discrepancy.labware_barcode.clone(),
+        expected_location,
+        found_location,
+        resolution: if discrepancy.corrected {
+            Resolution::Corrected
+        } else {
+            Resolution::Unresolved
+        },
+    })
+}
+
+/// Looks up the name and barcode of a location, remembering them for the rest of the report.
+async fn location(
+    id: u32,
+    locations: &mut HashMap<u32, (String, Option<String>)>,
+    connection: &mut SqliteConnection,
+) -> Result<(String, Option<String>), sqlx::Error> {
+    if let Some(location) = locations.get(&id) {
+        return Ok(location.clone());
+    }
+    let location = sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT name, barcode FROM locations WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_one(&mut *connection)
+    .await?;
+    locations.insert(id, location.clone());
+    Ok(location)
+}
+
+/// The name of a discrepancy kind, as sent in JSON.
+fn kind_name(kind: DiscrepancyKind) -> &'static str {
+    match kind {
+        DiscrepancyKind::Missing => "missing",
+        DiscrepancyKind::Unexpected => "unexpected",
+        DiscrepancyKind::Misplaced => "misplaced",
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break.
+/// # Examples
+/// ```
+/// use labwhere::models::stocktake_report::csv_field;
+/// assert_eq!(csv_field("freezer"), "freezer");
+/// assert_eq!(csv_field("box 1, shelf \"A\""), "\"box 1, shelf \"\"A\"\"\"");
+/// ```
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::stocktake_report::*;
+
+    #[tokio::test]
+    async fn test_report() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let mut shelves = vec![];
+        for name in ["shelf1", "shelf2"] {
+            shelves.push(
+                Location::create_with_parent(
+                    name.to_string(),
+                    location_type.id,
+                    Some(freezer.id),
+                    &mut conn,
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        Labware::create("lw-rep-1".to_string(), shelves[0].id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-rep-2".to_string(), shelves[1].id, &mut conn)
+            .await
+            .unwrap();
+        let stocktake = Stocktake::open(
+            freezer.barcode.clone().unwrap(),
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let error = StocktakeReport::build(&stocktake, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "The stocktake is not completed yet");
+
+        stocktake
+            .scan(
+                shelves[0].barcode.clone().unwrap(),
+                vec!["lw-rep-2".to_string(), "lw-rep-new".to_string()],
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        let stocktake = stocktake.complete(false, None, &mut conn).await.unwrap();
+        let report = StocktakeReport::build(&stocktake, &mut conn).await.unwrap();
+        assert_eq!(report.location, "freezer");
+        assert_eq!(report.locations.len(), 1);
+        let shelf1 = &report.locations[0];
+        assert_eq!(shelf1.location, "shelf1");
+        assert_eq!(
+            (
+                shelf1.missing,
+                shelf1.unexpected,
+                shelf1.misplaced,
+                shelf1.unresolved
+            ),
+            (1, 1, 1, 3)
+        );
+        assert_eq!(
+            shelf1.discrepancies[2],
+            ReportLine {
+                kind: DiscrepancyKind::Misplaced,
+                labware_barcode: "lw-rep-2".to_string(),
+                expected_location: Some("shelf2".to_string()),
+                found_location: Some("shelf1".to_string()),
+                resolution: Resolution::Unresolved,
+            }
+        );
+
+        let csv = report.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[3].ends_with(&format!(
+            ",shelf1,{},misplaced,lw-rep-2,shelf2,shelf1,unresolved",
+            shelves[0].barcode.as_ref().unwrap()
+        )));
+    }
+}
@@ -0,0 +1,495 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::dead_letter::DeadLetter;
+use crate::models::new_uuid;
+use crate::notifications::email::MAILER;
+use crate::notifications::webhook::Webhook;
+use crate::notifications::{Notification, Trigger};
+use chrono::{DateTime, Utc};
+use lettre::message::Mailbox;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// How many times delivering a notification is attempted before giving up on it.
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+
+/// The most notifications delivered in one go.
+const DELIVERY_BATCH: u32 = 100;
+
+/// The location and its ancestors, i.e. every location whose subtree contains it.
+const ANCESTORS: &str = "WITH RECURSIVE ancestors(id) AS (
+        SELECT ?1
+        UNION
+        SELECT locations.parent_id FROM locations JOIN ancestors ON locations.id = ancestors.id
+            WHERE locations.parent_id IS NOT NULL
+    )";
+
+/// Where the notifications of a subscription are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum SubscriptionChannel {
+    /// The target is an email address
+    Email,
+    /// The target is a chat webhook, e.g. `slack:https://hooks.slack.com/services/...`
+    Webhook,
+}
+
+/// A subscription to the changes within a location and everything beneath it, e.g. "tell me
+/// whenever something moves in or out of Freezer 7".
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct Subscription {
+    /// The unique identifier for the Subscription
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the Subscription, used in URLs and event payloads
+    pub uuid: String,
+    /// The ID of the location at the top of the subscribed subtree
+    pub location_id: u32,
+    /// Who subscribed
+    pub subscriber: String,
+    /// How the subscriber is notified
+    pub channel: SubscriptionChannel,
+    /// The email address or webhook notifications are sent to
+    pub target: String,
+    /// When the subscription was made
+    pub created_at: DateTime<Utc>,
+}
+
+/// A change queued for delivery to a subscriber.
+#[derive(Debug, sqlx::FromRow)]
+struct SubscriptionEvent {
+    id: u32,
+    subscription_id: u32,
+    audit_id: u32,
+    attempts: u32,
+}
+
+/// Implementation of the Subscription struct
+impl Subscription {
+    /// Subscribes to the changes within a location
+    ///
+    /// Returns a `ValidationError` if the target is not an email address or a webhook, as the
+    /// channel requires.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use subscription::{Subscription, SubscriptionChannel};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let subscription = Subscription::create(1, "jane".to_string(), SubscriptionChannel::Email, "jane@example.com".to_string(), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        location_id: u32,
+        subscriber: String,
+        channel: SubscriptionChannel,
+        target: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<Subscription, Box<dyn Error + Send + Sync>> {
+        let target = target.trim().to_string();
+        let valid = match channel {
+            SubscriptionChannel::Email => target.parse::<Mailbox>().is_ok(),
+            SubscriptionChannel::Webhook => Webhook::from_name(&target).is_some(),
+        };
+        if !valid {
+            return Err(Box::new(ValidationError {
+                message: Message::new("subscription-invalid-target")
+                    .arg("target", &target)
+                    .arg("channel", channel_name(channel)),
+            }));
+        }
+
+        let insert_query_result = sqlx::query(
+            "INSERT INTO subscriptions (uuid, location_id, subscriber, channel, target)
+                VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(location_id)
+        .bind(subscriber)
+        .bind(channel)
+        .bind(target)
+        .execute(&mut *connection)
+        .await?;
+        Ok(Subscription::find(insert_query_result.last_insert_rowid() as u32, connection).await?)
+    }
+
+    /// Lists the subscriptions to a location, oldest first
+    pub async fn for_location(
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<Subscription>, sqlx::Error> {
+        sqlx::query_as::<_, Subscription>(
+            "SELECT * FROM subscriptions WHERE location_id = ? ORDER BY id",
+        )
+        .bind(location_id)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Unsubscribes, dropping any notifications which have not been delivered yet
+    ///
+    /// Returns a `NotFoundError` if the location has no such subscription.
+    pub async fn delete(
+        location_id: u32,
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let subscription = Subscription::for_location(location_id, &mut *connection)
+            .await?
+            .into_iter()
+            .find(|subscription| subscription.uuid == uuid)
+            .ok_or_else(|| NotFoundError {
+                message: Message::new("subscription-not-found"),
+            })?;
+
+        sqlx::query(
+            "DELETE FROM dead_letters WHERE subscription_event_id IN
+                (SELECT id FROM subscription_events WHERE subscription_id = ?)",
+        )
+        .bind(subscription.id)
+        .execute(&mut *connection)
+        .await?;
+        sqlx::query("DELETE FROM subscription_events WHERE subscription_id = ?")
+            .bind(subscription.id)
+            .execute(&mut *connection)
+            .await?;
+        sqlx::query("DELETE FROM subscriptions WHERE id = ?")
+            .bind(subscription.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Queues an audit for delivery to every subscription to the location or one of its
+    /// ancestors. An audit is only queued once per subscription, so a move within a subscribed
+    /// subtree is not notified twice.
+    ///
+    /// This runs within the transaction of the audit, so nothing is delivered about changes which
+    /// are rolled back.
+    pub async fn fan_out(
+        audit_id: u32,
+        location_id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(&format!(
+            "{}
+            INSERT OR IGNORE INTO subscription_events (subscription_id, audit_id)
+                SELECT subscriptions.id, ?2 FROM subscriptions
+                    WHERE subscriptions.location_id IN (SELECT id FROM ancestors)",
+            ANCESTORS
+        ))
+        .bind(location_id)
+        .bind(audit_id)
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+
+    /// Delivers the queued notifications, oldest first, and returns how many were delivered.
+    ///
+    /// A notification which cannot be delivered is retried next time, up to
+    /// `MAX_DELIVERY_ATTEMPTS` times, then becomes a `DeadLetter`. A notification the target
+    /// refuses outright, e.g. a webhook responding with 404, becomes a `DeadLetter` at once.
+    pub async fn deliver_pending(connection: &mut SqliteConnection) -> Result<usize, sqlx::Error> {
+        DeadLetter::sweep(&mut *connection).await?;
+        let events = sqlx::query_as::<_, SubscriptionEvent>(
+            "SELECT id, subscription_id, audit_id, attempts FROM subscription_events
+                WHERE delivered_at IS NULL AND attempts < ?
+                AND id NOT IN (SELECT subscription_event_id FROM dead_letters
+                    WHERE redriven_at IS NULL)
+                ORDER BY id LIMIT ?",
+        )
+        .bind(MAX_DELIVERY_ATTEMPTS)
+        .bind(DELIVERY_BATCH)
+        .fetch_all(&mut *connection)
+        .await?;
+
+        let mut delivered = 0;
+        for event in events {
+            let subscription =
+                sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ?")
+                    .bind(event.subscription_id)
+                    .fetch_one(&mut *connection)
+                    .await?;
+            let audit = sqlx::query_as::<_, Audit>("SELECT * FROM audits WHERE id = ?")
+                .bind(event.audit_id)
+                .fetch_one(&mut *connection)
+                .await?;
+            let notification = notification(&subscription, &audit, &mut *connection).await?;
+
+            match subscription.send(&notification).await {
+                Ok(()) => {
+                    sqlx::query(
+                        "UPDATE subscription_events
+                            SET attempts = attempts + 1, last_error = NULL, delivered_at = CURRENT_TIMESTAMP
+                            WHERE id = ?",
+                    )
+                    .bind(event.id)
+                    .execute(&mut *connection)
+                    .await?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not deliver notification of audit {} to subscription {} (attempt {}): {}",
+                        audit.uuid,
+                        subscription.uuid,
+                        event.attempts + 1,
+                        e
+                    );
+                    sqlx::query(
+                        "UPDATE subscription_events SET attempts = attempts + 1, last_error = ?
+                            WHERE id = ?",
+                    )
+                    .bind(e.to_string())
+                    .bind(event.id)
+                    .execute(&mut *connection)
+                    .await?;
+                    if is_permanent(&*e) || event.attempts + 1 >= MAX_DELIVERY_ATTEMPTS {
+                        DeadLetter::record(
+                            event.id,
+                            &e.to_string(),
+                            event.attempts + 1,
+                            &mut *connection,
+                        )
+                        .await?;
+                    }
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Sends a notification to the target of the subscription.
+    async fn send(&self, notification: &Notification) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.channel {
+            SubscriptionChannel::Email => match MAILER.as_ref() {
+                Some(mailer) => {
+                    mailer
+                        .send_to(std::slice::from_ref(&self.target), notification)
+                        .await
+                }
+                None => Err("no SMTP server is configured".into()),
+            },
+            SubscriptionChannel::Webhook => match Webhook::from_name(&self.target) {
+                Some(webhook) => webhook.send(notification).await,
+                None => Err(format!("{} is not a webhook", self.target).into()),
+            },
+        }
+    }
+
+    /// Find a subscription by id
+    async fn find(
+        id: u32,
+        connection: &mut SqliteConnection,
+    ) -> Result<Subscription, NotFoundError> {
+        sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("subscription-not-found"),
+            })
+    }
+}
+
+/// Describes an audit to a subscriber.
+async fn notification(
+    subscription: &Subscription,
+    audit: &Audit,
+    connection: &mut SqliteConnection,
+) -> Result<Notification, sqlx::Error> {
+    let record: serde_json::Value = serde_json::from_str(&audit.record_data).unwrap_or_default();
+    let record = record["barcode"]
+        .as_str()
+        .or(record["name"].as_str())
+        .map(String::from)
+        .unwrap_or_else(|| audit.auditable_id.to_string());
+    let location = match audit.location_id {
+        Some(location_id) => location_name(location_id, &mut *connection).await?,
+        None => String::new(),
+    };
+    let subscribed = location_name(subscription.location_id, connection).await?;
+    Ok(Notification::new(Trigger::LocationChanged)
+        .arg("type", &audit.auditable_type)
+        .arg("record", record)
+        .arg("action", &audit.action)
+        .arg("location", location)
+        .arg("subscribed", subscribed))
+}
+
+async fn location_name(id: u32, connection: &mut SqliteConnection) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar::<_, String>("SELECT name FROM locations WHERE id = ?")
+        .bind(id)
+        .fetch_one(&mut *connection)
+        .await
+}
+
+/// Whether a delivery failed in a way retrying cannot fix, i.e. the target responded with a client
+/// error other than a timeout or rate limit.
+fn is_permanent(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| {
+            status.is_client_error()
+                && status != reqwest::StatusCode::REQUEST_TIMEOUT
+                && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
+/// The name of a channel, as sent in JSON.
+fn channel_name(channel: SubscriptionChannel) -> &'static str {
+    match channel {
+        SubscriptionChannel::Email => "email",
+        SubscriptionChannel::Webhook => "webhook",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::subscription::*;
+
+    async fn pending(subscription: &Subscription, connection: &mut SqliteConnection) -> Vec<u32> {
+        sqlx::query_scalar::<_, u32>(
+            "SELECT audit_id FROM subscription_events WHERE subscription_id = ? ORDER BY id",
+        )
+        .bind(subscription.id)
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer7".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create_with_parent(
+            "shelf1".to_string(),
+            location_type.id,
+            Some(freezer.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let bench = Location::create("bench".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let subscription = Subscription::create(
+            freezer.id,
+            "jane".to_string(),
+            SubscriptionChannel::Webhook,
+            "slack:https://hooks.slack.com/services/1".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        // Into the subtree, within it, and out of it
+        let mut labware = Labware::create("lw-sub-1".to_string(), shelf.id, &mut conn)
+            .await
+            .unwrap();
+        labware.location_id = freezer.id;
+        Labware::update(&labware, None, &mut conn).await.unwrap();
+        labware.location_id = bench.id;
+        Labware::update(&labware, None, &mut conn).await.unwrap();
+        // Entirely outside of it
+        Labware::create("lw-sub-2".to_string(), bench.id, &mut conn)
+            .await
+            .unwrap();
+
+        let audits = pending(&subscription, &mut conn).await;
+        assert_eq!(audits.len(), 3);
+        let audit = sqlx::query_as::<_, Audit>("SELECT * FROM audits WHERE id = ?")
+            .bind(audits[2])
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        let notification = notification(&subscription, &audit, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            notification.body().to_string(),
+            "Labware lw-sub-1 (update) is now in bench. You are subscribed to changes in freezer7."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_with_invalid_target() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer7".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let error = Subscription::create(
+            freezer.id,
+            "jane".to_string(),
+            SubscriptionChannel::Email,
+            "not an address".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "not an address is not a valid email target"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_failed_delivery() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer7".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let subscription = Subscription::create(
+            freezer.id,
+            "jane".to_string(),
+            SubscriptionChannel::Email,
+            "jane@example.com".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Labware::create("lw-sub-3".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+
+        // No SMTP server is configured in tests, so delivery fails and is retried later
+        assert_eq!(Subscription::deliver_pending(&mut conn).await.unwrap(), 0);
+        let (attempts, last_error) = sqlx::query_as::<_, (u32, Option<String>)>(
+            "SELECT attempts, last_error FROM subscription_events",
+        )
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+        assert_eq!(attempts, 1);
+        assert!(last_error.is_some());
+
+        Subscription::delete(freezer.id, &subscription.uuid, &mut conn)
+            .await
+            .unwrap();
+        assert!(pending(&subscription, &mut conn).await.is_empty());
+        let error = Subscription::delete(freezer.id, &subscription.uuid, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Subscription not found");
+    }
+}
@@ -0,0 +1,316 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::change::Change;
+use crate::models::new_uuid;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// Who resolved the conflicts which the last writer won.
+const LAST_WRITER_WINS: &str = "last-writer-wins";
+
+/// Where a sync conflict stands.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ConflictStatus {
+    /// Waiting for an admin to review it
+    Pending,
+    /// The change from the other instance won
+    Applied,
+    /// The local change won and the change from the other instance was dropped
+    Discarded,
+}
+
+impl ConflictStatus {
+    /// Parses a conflict status from its name e.g. `pending`.
+    pub fn from_name(name: &str) -> Option<ConflictStatus> {
+        match name.trim().to_lowercase().as_str() {
+            "pending" => Some(ConflictStatus::Pending),
+            "applied" => Some(ConflictStatus::Applied),
+            "discarded" => Some(ConflictStatus::Discarded),
+            _ => None,
+        }
+    }
+}
+
+/// A change synced from another instance which conflicted with a local change to the same
+/// labware, e.g. both instances moved it while they were apart.
+///
+/// Every conflict is logged. Those resolved by the last-writer-wins policy are logged resolved;
+/// under the manual-review policy they are queued until an admin applies or discards the change.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct SyncConflict {
+    /// The unique identifier for the SyncConflict
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the SyncConflict, used in URLs
+    pub uuid: String,
+    /// The UUID of the conflicting change
+    pub change_uuid: String,
+    /// The conflicting change
+    #[serde(rename = "change", serialize_with = "serialize_change_data")]
+    pub change_data: String,
+    /// The UUID of the location the labware was in here when the conflict was found
+    pub location_uuid: Option<String>,
+    /// Where the conflict stands
+    pub status: ConflictStatus,
+    /// Who resolved the conflict, `last-writer-wins` if it was resolved by the policy
+    pub resolved_by: Option<String>,
+    /// When the conflict was found
+    pub created_at: DateTime<Utc>,
+    /// When the conflict was resolved
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// Serializes the stored change as JSON rather than as a string.
+fn serialize_change_data<S: Serializer>(
+    change_data: &str,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match serde_json::from_str::<serde_json::Value>(change_data) {
+        Ok(value) => value.serialize(serializer),
+        Err(_) => serializer.serialize_str(change_data),
+    }
+}
+
+/// Implementation of the SyncConflict struct
+impl SyncConflict {
+    /// Logs a conflict, resolved by the last-writer-wins policy unless it is pending
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use sync_conflict::{ConflictStatus, SyncConflict};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let conflict = SyncConflict::log(&change, Some(freezer.uuid), ConflictStatus::Pending, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn log(
+        change: &Change,
+        location_uuid: Option<String>,
+        status: ConflictStatus,
+        connection: &mut SqliteConnection,
+    ) -> Result<SyncConflict, sqlx::Error> {
+        let change_data =
+            serde_json::to_string(change).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+        let resolved = status != ConflictStatus::Pending;
+        let insert_query_result = sqlx::query(
+            "INSERT INTO sync_conflicts
+                (uuid, change_uuid, change_data, location_uuid, status, resolved_by, resolved_at)
+                VALUES (?, ?, ?, ?, ?, ?, CASE WHEN ? THEN CURRENT_TIMESTAMP END)",
+        )
+        .bind(new_uuid())
+        .bind(&change.uuid)
+        .bind(change_data)
+        .bind(location_uuid)
+        .bind(status)
+        .bind(resolved.then_some(LAST_WRITER_WINS))
+        .bind(resolved)
+        .execute(&mut *connection)
+        .await?;
+        sqlx::query_as::<_, SyncConflict>("SELECT * FROM sync_conflicts WHERE id = ?")
+            .bind(insert_query_result.last_insert_rowid())
+            .fetch_one(&mut *connection)
+            .await
+    }
+
+    /// Lists the conflicts, or only those with the given status, newest first
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use sync_conflict::{ConflictStatus, SyncConflict};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let pending = SyncConflict::list(Some(ConflictStatus::Pending), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn list(
+        status: Option<ConflictStatus>,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<SyncConflict>, sqlx::Error> {
+        sqlx::query_as::<_, SyncConflict>(
+            "SELECT * FROM sync_conflicts WHERE ?1 IS NULL OR status = ?1 ORDER BY id DESC",
+        )
+        .bind(status)
+        .fetch_all(&mut *connection)
+        .await
+    }
+
+    /// Resolves a pending conflict by applying the change (`ConflictStatus::Applied`), which
+    /// overrides the local change, or by discarding it (`ConflictStatus::Discarded`)
+    ///
+    /// A `ValidationError` is returned if the conflict is not pending or the change cannot be
+    /// applied any more, e.g. because its location is gone.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use sync_conflict::{ConflictStatus, SyncConflict};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let conflict = SyncConflict::resolve(&uuid, ConflictStatus::Applied, "jane".to_string(), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn resolve(
+        uuid: &str,
+        status: ConflictStatus,
+        resolved_by: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<SyncConflict, Box<dyn Error + Send + Sync>> {
+        let mut transaction = connection.begin().await?;
+        let conflict =
+            sqlx::query_as::<_, SyncConflict>("SELECT * FROM sync_conflicts WHERE uuid = ?")
+                .bind(uuid)
+                .fetch_optional(&mut *transaction)
+                .await?
+                .ok_or_else(|| NotFoundError {
+                    message: Message::new("sync-conflict-not-found"),
+                })?;
+        if conflict.status != ConflictStatus::Pending {
+            return Err(Box::new(ValidationError {
+                message: Message::new("sync-conflict-resolved"),
+            }));
+        }
+        if status == ConflictStatus::Applied {
+            let change: Change = serde_json::from_str(&conflict.change_data)?;
+            change.apply_resolved(&mut transaction).await?;
+        }
+        sqlx::query(
+            "UPDATE sync_conflicts SET status = ?, resolved_by = ?, resolved_at = CURRENT_TIMESTAMP
+                WHERE id = ?",
+        )
+        .bind(status)
+        .bind(resolved_by)
+        .bind(conflict.id)
+        .execute(&mut *transaction)
+        .await?;
+        let conflict =
+            sqlx::query_as::<_, SyncConflict>("SELECT * FROM sync_conflicts WHERE id = ?")
+                .bind(conflict.id)
+                .fetch_one(&mut *transaction)
+                .await?;
+        transaction.commit().await?;
+        Ok(conflict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::change::{Change, ConflictPolicy};
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::sync_conflict::*;
+
+    /// Creates a labware in a freezer and a move of it made on a satellite which still had it on a
+    /// shelf, while it was moved to the freezer here.
+    async fn conflicting_move(connection: &mut SqliteConnection) -> (Location, Change) {
+        let location_type = LocationType::create("Freezer".to_string(), &mut *connection)
+            .await
+            .unwrap();
+        let shelf = Location::create("shelf1".to_string(), location_type.id, &mut *connection)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut *connection)
+            .await
+            .unwrap();
+        let bench = Location::create("bench1".to_string(), location_type.id, &mut *connection)
+            .await
+            .unwrap();
+        let mut labware = Labware::create("lw-1".to_string(), shelf.id, &mut *connection)
+            .await
+            .unwrap();
+        labware.location_id = freezer.id;
+        Labware::update(&labware, None, &mut *connection)
+            .await
+            .unwrap();
+        let change = Change {
+            uuid: "move-1".to_string(),
+            origin: "satellite".to_string(),
+            record_type: "Labware".to_string(),
+            action: "update".to_string(),
+            record_uuid: labware.uuid.clone(),
+            barcode: Some("lw-1".to_string()),
+            location_uuid: Some(bench.uuid.clone()),
+            previous_location_uuid: Some(shelf.uuid.clone()),
+            name: None,
+            location_type: None,
+            created_at: Utc::now() + chrono::Duration::hours(1),
+        };
+        (bench, change)
+    }
+
+    async fn location_of(connection: &mut SqliteConnection) -> u32 {
+        Labware::find_by_barcode("lw-1".to_string(), connection)
+            .await
+            .unwrap()
+            .location_id
+    }
+
+    #[tokio::test]
+    async fn test_last_writer_wins() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (bench, change) = conflicting_move(&mut conn).await;
+        let report = Change::apply(
+            std::slice::from_ref(&change),
+            ConflictPolicy::LastWriterWins,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.applied, 1);
+        assert_eq!(location_of(&mut conn).await, bench.id);
+
+        let conflicts = SyncConflict::list(None, &mut conn).await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].change_uuid, "move-1");
+        assert_eq!(conflicts[0].status, ConflictStatus::Applied);
+        assert_eq!(conflicts[0].resolved_by.as_deref(), Some(LAST_WRITER_WINS));
+    }
+
+    #[tokio::test]
+    async fn test_manual_review() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let (bench, change) = conflicting_move(&mut conn).await;
+        let freezer_id = location_of(&mut conn).await;
+        for queued in [1, 0] {
+            let report = Change::apply(
+                std::slice::from_ref(&change),
+                ConflictPolicy::ManualReview,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+            assert_eq!(report.queued, queued);
+        }
+        assert_eq!(location_of(&mut conn).await, freezer_id);
+
+        let pending = SyncConflict::list(Some(ConflictStatus::Pending), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+        let json = serde_json::to_value(&pending[0]).unwrap();
+        assert_eq!(json["change"]["uuid"], "move-1");
+
+        let resolved = SyncConflict::resolve(
+            &pending[0].uuid,
+            ConflictStatus::Applied,
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(resolved.status, ConflictStatus::Applied);
+        assert_eq!(resolved.resolved_by.as_deref(), Some("jane"));
+        assert_eq!(location_of(&mut conn).await, bench.id);
+
+        let err = SyncConflict::resolve(
+            &pending[0].uuid,
+            ConflictStatus::Discarded,
+            "jane".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "The conflict has already been resolved");
+    }
+}
@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+
+/// Another instance this instance syncs its changes with, and how far the sync has got.
+///
+/// The cursors are ids of audits: `pushed_audit_id` is the last local audit sent to the peer and
+/// `pulled_audit_id` the last audit of the peer which was read from it. They only move forward
+/// once a batch has been sent or applied, so a sync which is interrupted picks up where it left off.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct SyncPeer {
+    /// The URL of the peer
+    pub name: String,
+    /// The last local audit sent to the peer
+    pub pushed_audit_id: u32,
+    /// The last audit of the peer applied here
+    pub pulled_audit_id: u32,
+    /// When the last sync with the peer completed
+    pub synced_at: Option<DateTime<Utc>>,
+}
+
+/// Implementation of the SyncPeer struct
+impl SyncPeer {
+    /// Finds a peer, or a peer nothing has been synced with yet
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use sync_peer::SyncPeer;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let peer = SyncPeer::find("https://labwhere.example.com", &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn find(
+        name: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<SyncPeer, sqlx::Error> {
+        let peer = sqlx::query_as::<_, SyncPeer>("SELECT * FROM sync_peers WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&mut *connection)
+            .await?;
+        Ok(peer.unwrap_or_else(|| SyncPeer {
+            name: name.to_string(),
+            ..Default::default()
+        }))
+    }
+
+    /// Saves the cursors of the peer
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use sync_peer::SyncPeer;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let mut peer = SyncPeer::find("https://labwhere.example.com", &mut connection).await.unwrap();
+    /// peer.pulled_audit_id = 10;
+    /// peer.save(&mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn save(&self, connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO sync_peers (name, pushed_audit_id, pulled_audit_id, synced_at)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (name) DO UPDATE
+                SET pushed_audit_id = ?2, pulled_audit_id = ?3, synced_at = ?4",
+        )
+        .bind(&self.name)
+        .bind(self.pushed_audit_id)
+        .bind(self.pulled_audit_id)
+        .bind(self.synced_at)
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::sync_peer::*;
+
+    #[tokio::test]
+    async fn test_find_and_save() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let mut peer = SyncPeer::find("https://hub.example.com", &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(peer.pushed_audit_id, 0);
+        assert_eq!(peer.pulled_audit_id, 0);
+
+        peer.pushed_audit_id = 3;
+        peer.pulled_audit_id = 7;
+        peer.synced_at = Some(Utc::now());
+        peer.save(&mut conn).await.unwrap();
+        peer.pulled_audit_id = 9;
+        peer.save(&mut conn).await.unwrap();
+
+        let saved = SyncPeer::find("https://hub.example.com", &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(saved.pushed_audit_id, 3);
+        assert_eq!(saved.pulled_audit_id, 9);
+        assert!(saved.synced_at.is_some());
+    }
+}
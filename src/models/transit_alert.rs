@@ -0,0 +1,168 @@
+use crate::config::Config;
+use crate::notifications::{Notification, Trigger};
+use crate::timestamps::STORAGE_FORMAT;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+
+/// A labware which has been in transit (checked out) for longer than `transit_alert_minutes`.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct TransitAlert {
+    /// The ID of the checkout
+    #[serde(skip_serializing)]
+    pub checkout_id: u32,
+    /// The primary barcode of the labware
+    pub labware_barcode: String,
+    /// Who has the labware
+    pub holder: String,
+    /// The name of the location the labware was checked out of
+    pub location: String,
+    /// The owner of the location the labware was checked out of, who is notified of the alert
+    pub owner: Option<String>,
+    /// The contact email of the location the labware was checked out of, which is notified
+    pub contact_email: Option<String>,
+    /// When the labware was checked out
+    pub checked_out_at: DateTime<Utc>,
+}
+
+/// Implementation of the TransitAlert struct
+impl TransitAlert {
+    /// Returns an alert for each labware which has been in transit for longer than the threshold
+    /// since the last check
+    ///
+    /// Each checkout raises one alert at most, however long it stays open.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use transit_alert::TransitAlert;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let alerts = TransitAlert::check(&CONFIG, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn check(
+        config: &Config,
+        connection: &mut SqliteConnection,
+    ) -> Result<Vec<TransitAlert>, sqlx::Error> {
+        if config.transit_alert_minutes == 0 {
+            return Ok(vec![]);
+        }
+        sqlx::query(
+            "DELETE FROM transit_alerts WHERE checkout_id IN
+                (SELECT id FROM checkouts WHERE checked_in_at IS NOT NULL)",
+        )
+        .execute(&mut *connection)
+        .await?;
+        let since = Utc::now() - Duration::minutes(config.transit_alert_minutes.into());
+        let alerts = sqlx::query_as::<_, TransitAlert>(
+            "SELECT c.id AS checkout_id, lw.barcode AS labware_barcode, c.holder,
+                l.name AS location, l.owner, l.contact_email, c.checked_out_at
+                FROM checkouts c
+                JOIN labwares lw ON lw.id = c.labware_id
+                JOIN locations l ON l.id = c.from_location_id
+                WHERE c.checked_in_at IS NULL AND c.checked_out_at <= ?
+                    AND NOT EXISTS (SELECT 1 FROM transit_alerts WHERE checkout_id = c.id)
+                ORDER BY c.checked_out_at, c.id",
+        )
+        .bind(since.format(STORAGE_FORMAT).to_string())
+        .fetch_all(&mut *connection)
+        .await?;
+        for alert in &alerts {
+            sqlx::query("INSERT INTO transit_alerts (checkout_id, minutes) VALUES (?, ?)")
+                .bind(alert.checkout_id)
+                .bind(alert.minutes())
+                .execute(&mut *connection)
+                .await?;
+        }
+        Ok(alerts)
+    }
+
+    /// How long the labware has been in transit, in minutes
+    pub fn minutes(&self) -> i64 {
+        (Utc::now() - self.checked_out_at).num_minutes()
+    }
+
+    /// The notification sent about the alert, to the teams configured for `transit-threshold` and
+    /// to the owner and contact of the location the labware was checked out of
+    pub fn notification(&self) -> Notification {
+        Notification::new(Trigger::TransitThreshold)
+            .arg("barcode", &self.labware_barcode)
+            .arg("holder", &self.holder)
+            .arg("location", &self.location)
+            .arg("minutes", self.minutes())
+            .owned_by(self.owner.as_deref(), self.contact_email.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::checkout::Checkout;
+    use crate::models::labware::Labware;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::transit_alert::*;
+
+    #[tokio::test]
+    async fn test_check() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        for barcode in ["lw-1", "lw-2"] {
+            Labware::create(barcode.to_string(), freezer.id, &mut conn)
+                .await
+                .unwrap();
+            Checkout::checkout(
+                barcode.to_string(),
+                "jane".to_string(),
+                None,
+                None,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        let config = Config {
+            transit_alert_minutes: 60,
+            ..Default::default()
+        };
+        assert!(TransitAlert::check(&config, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+
+        sqlx::query(
+            "UPDATE checkouts SET checked_out_at = datetime('now', '-90 minutes')
+                WHERE labware_id = (SELECT id FROM labwares WHERE barcode = 'lw-1')",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        let alerts = TransitAlert::check(&config, &mut conn).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].labware_barcode, "lw-1");
+        assert_eq!(alerts[0].minutes(), 90);
+        assert_eq!(
+            alerts[0].notification().subject().to_string(),
+            "lw-1 has been in transit for 90 minutes"
+        );
+        // Still in transit, but it was already alerted
+        assert!(TransitAlert::check(&config, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let off = Config::default();
+        sqlx::query("UPDATE checkouts SET checked_out_at = datetime('now', '-1 day')")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        assert!(TransitAlert::check(&off, &mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
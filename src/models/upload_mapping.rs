@@ -0,0 +1,152 @@
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// Which columns of a facility's export hold the labware and location barcodes, saved so that the
+/// file can be uploaded as a manifest as it is.
+///
+/// Facilities export manifests with their own headers, e.g. `Tube Barcode` and `Rack`, in their
+/// own order. A mapping is chosen by name when uploading; without one, the columns have to be
+/// named `labware` and `location`.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct UploadMapping {
+    /// The unique identifier for the UploadMapping
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The unique name of the mapping, chosen when uploading e.g. `sanger-freezers`
+    pub name: String,
+    /// The header of the column holding the labware barcodes
+    pub labware_column: String,
+    /// The header of the column holding the location barcodes
+    pub location_column: String,
+    /// When the mapping was saved
+    pub created_at: DateTime<Utc>,
+}
+
+/// Implementation of the UploadMapping struct
+impl UploadMapping {
+    /// Saves a mapping
+    ///
+    /// Returns a `ValidationError` if another mapping has the same name.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use upload_mapping::UploadMapping;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let mapping = UploadMapping::create("sanger-freezers".to_string(), "Tube Barcode".to_string(), "Rack".to_string(), &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        name: String,
+        labware_column: String,
+        location_column: String,
+        connection: &mut SqliteConnection,
+    ) -> Result<UploadMapping, Box<dyn Error + Send + Sync>> {
+        let taken =
+            sqlx::query_scalar::<_, u32>("SELECT COUNT(*) FROM upload_mappings WHERE name = ?")
+                .bind(&name)
+                .fetch_one(&mut *connection)
+                .await?;
+        if taken > 0 {
+            return Err(Box::new(ValidationError {
+                message: Message::new("upload-mapping-name-taken").arg("name", &name),
+            }));
+        }
+        sqlx::query(
+            "INSERT INTO upload_mappings (name, labware_column, location_column) VALUES (?, ?, ?)",
+        )
+        .bind(&name)
+        .bind(labware_column)
+        .bind(location_column)
+        .execute(&mut *connection)
+        .await?;
+        Ok(UploadMapping::find_by_name(&name, connection).await?)
+    }
+
+    /// Lists every mapping, ordered by name
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<UploadMapping>, sqlx::Error> {
+        sqlx::query_as::<_, UploadMapping>("SELECT * FROM upload_mappings ORDER BY name")
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// Find a mapping by its name
+    pub async fn find_by_name(
+        name: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<UploadMapping, NotFoundError> {
+        sqlx::query_as::<_, UploadMapping>("SELECT * FROM upload_mappings WHERE name = ?")
+            .bind(name)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("upload-mapping-not-found").arg("name", name),
+            })
+    }
+
+    /// Deletes the mapping. Manifests uploaded with it are not affected.
+    pub async fn delete(&self, connection: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM upload_mappings WHERE id = ?")
+            .bind(self.id)
+            .execute(&mut *connection)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::manifest::ManifestReader;
+    use crate::models::upload_mapping::*;
+
+    #[tokio::test]
+    async fn test_create_and_read_with_mapping() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let mapping = UploadMapping::create(
+            "sanger-freezers".to_string(),
+            "Tube Barcode".to_string(),
+            "Rack".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let error = UploadMapping::create(
+            "sanger-freezers".to_string(),
+            "Tube".to_string(),
+            "Rack".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "An upload mapping named sanger-freezers already exists"
+        );
+
+        let mut reader = ManifestReader::default().mapped(&mapping);
+        let rows = reader
+            .read(b"Rack,Volume,tube barcode\nlw-rack-1,10,lw-1\n")
+            .unwrap();
+        assert_eq!(rows[0].labware, "lw-1");
+        assert_eq!(rows[0].location, "lw-rack-1");
+        let mut reader = ManifestReader::default().mapped(&mapping);
+        let error = reader.read(b"labware,Rack\nlw-1,lw-rack-1\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The manifest has no Tube Barcode column in its header"
+        );
+
+        assert_eq!(
+            UploadMapping::all(&mut conn).await.unwrap(),
+            vec![mapping.clone()]
+        );
+        mapping.delete(&mut conn).await.unwrap();
+        assert!(UploadMapping::find_by_name("sanger-freezers", &mut conn)
+            .await
+            .is_err());
+    }
+}
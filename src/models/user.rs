@@ -0,0 +1,739 @@
+use crate::errors::{ForbiddenError, NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::audit::Audit;
+use crate::models::{new_secret, new_uuid, secret_digest};
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection};
+use std::error::Error;
+
+/// What a user is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Role {
+    /// Manages users, devices and the configuration of the storage
+    Admin,
+    /// Moves labware around the lab
+    Technician,
+    /// Looks things up
+    Viewer,
+}
+
+impl Role {
+    /// Parses a role from its name e.g. `technician`.
+    pub fn from_name(name: &str) -> Option<Role> {
+        match name.trim().to_lowercase().as_str() {
+            "admin" => Some(Role::Admin),
+            "technician" => Some(Role::Technician),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+}
+
+/// The credentials of a user which an admin can reset, e.g. when a swipe card is lost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Credential {
+    /// The code of the swipe card the user scans with
+    SwipeCode,
+    /// The API key the user's scripts send
+    ApiKey,
+}
+
+impl Credential {
+    /// The action the reset is audited as.
+    fn action(&self) -> &'static str {
+        match self {
+            Credential::SwipeCode => "reset_swipe_code",
+            Credential::ApiKey => "reset_api_key",
+        }
+    }
+}
+
+/// An admin acting as a user, e.g. to reproduce a problem with their scans (see `User::act_as`).
+///
+/// What the admin does as the user is recorded with both of them, so it is never mistaken for
+/// something the user did themselves.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Impersonation {
+    /// The login of the admin
+    pub real_user: String,
+    /// The login of the user the admin acts as
+    pub effective_user: String,
+}
+
+impl Impersonation {
+    /// Does something as the user, e.g. a scan, stamping the audits and events it records with
+    /// both the admin and the user
+    ///
+    /// It is done within a transaction, so the audits and events of other requests cannot slip in
+    /// between and be stamped too. Without an impersonation it is just done.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use user::Impersonation;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let scan = Impersonation::perform(Some(&impersonation), &mut connection, |connection| {
+    ///     Box::pin(Scan::create("lw-freezer-1".to_string(), vec!["lw-1".to_string()], None, None, connection))
+    /// }).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn perform<T>(
+        impersonation: Option<&Impersonation>,
+        connection: &mut SqliteConnection,
+        action: impl for<'c> FnOnce(
+            &'c mut SqliteConnection,
+        ) -> BoxFuture<'c, Result<T, Box<dyn Error + Send + Sync>>>,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        let Some(impersonation) = impersonation else {
+            return action(connection).await;
+        };
+        let mut transaction = connection.begin().await?;
+        let last_audit_id: u32 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM audits")
+            .fetch_one(&mut *transaction)
+            .await?;
+        let last_event_id: u32 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM events")
+            .fetch_one(&mut *transaction)
+            .await?;
+        let result = action(&mut transaction).await?;
+        for (table, last_id) in [("audits", last_audit_id), ("events", last_event_id)] {
+            sqlx::query(&format!(
+                "UPDATE {} SET real_user = ?, effective_user = ? WHERE id > ?",
+                table
+            ))
+            .bind(&impersonation.real_user)
+            .bind(&impersonation.effective_user)
+            .bind(last_id)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        // The audits were completed after they were sealed
+        Audit::reseal_after(last_audit_id, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(result)
+    }
+}
+
+/// The random bytes of a generated swipe code.
+const SWIPE_CODE_BYTES: u32 = 5;
+
+/// The random bytes of the API key of a user.
+const API_KEY_BYTES: u32 = 16;
+
+/// A person who uses the system, identified by the swipe card they scan with or by their API key.
+///
+/// Users are never deleted, so their history stays attributable; a user who leaves is deactivated
+/// instead, after which scans with their swipe card and requests with their API key are refused.
+/// Every change to a user is audited. Only the digests of their swipe code and API key are
+/// stored.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct User {
+    /// The unique identifier for the User
+    #[serde(skip_serializing)]
+    pub id: u32,
+    /// The public identifier of the User, used in URLs
+    pub uuid: String,
+    /// The unique login of the User e.g. `jd12`
+    pub login: String,
+    /// What the User is allowed to do
+    pub role: Role,
+    /// The team the User belongs to, if any
+    pub team: Option<String>,
+    /// The SHA-256 digest of the code of the User's swipe card
+    #[serde(skip_serializing)]
+    pub swipe_code_digest: String,
+    /// The SHA-256 digest of the secret the User's scripts send in the `X-Api-Key` header
+    #[serde(skip_serializing)]
+    pub api_key_digest: String,
+    /// The code of the User's swipe card, which is only known (and shown) when it is set
+    #[serde(skip_serializing)]
+    #[sqlx(skip)]
+    pub swipe_code: Option<String>,
+    /// The User's API key, which is only known (and shown) when it is set
+    #[serde(skip_serializing)]
+    #[sqlx(skip)]
+    pub api_key: Option<String>,
+    /// Whether the User may use the system
+    pub active: bool,
+    /// When the User last scanned
+    pub last_active_at: Option<DateTime<Utc>>,
+    /// When the User was created
+    pub created_at: DateTime<Utc>,
+}
+
+/// Implementation of the User struct
+impl User {
+    /// Creates a user with a new API key, and with a generated swipe code unless the code of their
+    /// card is given
+    ///
+    /// Returns a `ValidationError` if another user has the same login or swipe code.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use user::{Role, User};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let user = User::create("jd12".to_string(), Role::Technician, Some("Cell lines".to_string()), None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn create(
+        login: String,
+        role: Role,
+        team: Option<String>,
+        swipe_code: Option<String>,
+        connection: &mut SqliteConnection,
+    ) -> Result<User, Box<dyn Error + Send + Sync>> {
+        let taken = sqlx::query_scalar::<_, u32>("SELECT COUNT(*) FROM users WHERE login = ?")
+            .bind(&login)
+            .fetch_one(&mut *connection)
+            .await?;
+        if taken > 0 {
+            return Err(Box::new(ValidationError {
+                message: Message::new("user-login-taken").arg("login", &login),
+            }));
+        }
+        let swipe_code = match swipe_code {
+            Some(swipe_code) => {
+                ensure_swipe_code_free(&swipe_code, &mut *connection).await?;
+                swipe_code
+            }
+            None => new_swipe_code(&mut *connection).await?,
+        };
+        let api_key = new_secret(API_KEY_BYTES, &mut *connection).await?;
+
+        let insert_query_result = sqlx::query(
+            "INSERT INTO users (uuid, login, role, team, swipe_code_digest, api_key_digest)
+                VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(new_uuid())
+        .bind(login)
+        .bind(role)
+        .bind(team)
+        .bind(secret_digest(&swipe_code))
+        .bind(secret_digest(&api_key))
+        .execute(&mut *connection)
+        .await?;
+        let mut user =
+            User::find(insert_query_result.last_insert_rowid() as u32, connection).await?;
+        Audit::create("User", user.id, "create", None, &user, &mut *connection).await?;
+        user.swipe_code = Some(swipe_code);
+        user.api_key = Some(api_key);
+        Ok(user)
+    }
+
+    /// Lists every user, ordered by login
+    pub async fn all(connection: &mut SqliteConnection) -> Result<Vec<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>("SELECT * FROM users ORDER BY login")
+            .fetch_all(&mut *connection)
+            .await
+    }
+
+    /// Find a user by their public identifier
+    pub async fn find_by_uuid(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<User, NotFoundError> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("user-not-found"),
+            })
+    }
+
+    /// Find a user by their login
+    pub async fn find_by_login(
+        login: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<User, NotFoundError> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE login = ?")
+            .bind(login.trim())
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("user-not-found"),
+            })
+    }
+
+    /// Changes the role and team of a user
+    pub async fn update(
+        uuid: &str,
+        role: Role,
+        team: Option<String>,
+        connection: &mut SqliteConnection,
+    ) -> Result<User, Box<dyn Error + Send + Sync>> {
+        let user = User::find_by_uuid(uuid, &mut *connection).await?;
+        sqlx::query("UPDATE users SET role = ?, team = ? WHERE id = ?")
+            .bind(role)
+            .bind(team)
+            .bind(user.id)
+            .execute(&mut *connection)
+            .await?;
+        User::changed(user.id, "update", connection).await
+    }
+
+    /// Deactivates a user, after which scans with their swipe card are refused
+    pub async fn deactivate(
+        uuid: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<User, Box<dyn Error + Send + Sync>> {
+        let user = User::find_by_uuid(uuid, &mut *connection).await?;
+        sqlx::query("UPDATE users SET active = 0 WHERE id = ?")
+            .bind(user.id)
+            .execute(&mut *connection)
+            .await?;
+        User::changed(user.id, "deactivate", connection).await
+    }
+
+    /// Replaces a swipe code or API key of a user, e.g. when a card is lost or a key leaked
+    ///
+    /// A swipe code is generated unless the code of the new card is given. Returns a
+    /// `ValidationError` if another user has the swipe code.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use user::{Credential, User};
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let user = User::reset(&user.uuid, Credential::ApiKey, None, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn reset(
+        uuid: &str,
+        credential: Credential,
+        swipe_code: Option<String>,
+        connection: &mut SqliteConnection,
+    ) -> Result<User, Box<dyn Error + Send + Sync>> {
+        let user = User::find_by_uuid(uuid, &mut *connection).await?;
+        let (column, secret) = match credential {
+            Credential::SwipeCode => match swipe_code {
+                Some(swipe_code) => {
+                    ensure_swipe_code_free(&swipe_code, &mut *connection).await?;
+                    ("swipe_code_digest", swipe_code)
+                }
+                None => ("swipe_code_digest", new_swipe_code(&mut *connection).await?),
+            },
+            Credential::ApiKey => (
+                "api_key_digest",
+                new_secret(API_KEY_BYTES, &mut *connection).await?,
+            ),
+        };
+        sqlx::query(&format!("UPDATE users SET {} = ? WHERE id = ?", column))
+            .bind(secret_digest(&secret))
+            .bind(user.id)
+            .execute(&mut *connection)
+            .await?;
+        let mut user = User::changed(user.id, credential.action(), connection).await?;
+        match credential {
+            Credential::SwipeCode => user.swipe_code = Some(secret),
+            Credential::ApiKey => user.api_key = Some(secret),
+        }
+        Ok(user)
+    }
+
+    /// Records that the user with a swipe code scanned
+    ///
+    /// Returns a `ForbiddenError` if the user is deactivated. Codes which do not belong to a user
+    /// are let through, as not every scan station has users set up.
+    pub async fn record_activity(
+        swipe_code: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE swipe_code_digest = ?")
+            .bind(secret_digest(swipe_code.trim()))
+            .fetch_optional(&mut *connection)
+            .await?;
+        match user {
+            Some(user) if !user.active => Err(Box::new(ForbiddenError {
+                message: Message::new("user-deactivated").arg("login", &user.login),
+            })),
+            Some(user) => {
+                sqlx::query("UPDATE users SET last_active_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(user.id)
+                    .execute(&mut *connection)
+                    .await?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Lets an admin act as a user, e.g. to reproduce a problem with their scans, and audits it
+    /// with both the real user (the admin) and the effective user
+    ///
+    /// The admin is identified by the code of their own swipe card, and the user by login;
+    /// `request` describes what the admin does as them e.g. `scan`. Returns a `ForbiddenError`
+    /// if the swipe code is not that of an active admin, or if the user is deactivated, once the
+    /// attempt is audited.
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use user::User;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let impersonation = User::act_as("jd12", "support-card", "scan", &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn act_as(
+        login: &str,
+        real_swipe_code: &str,
+        request: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Impersonation, Box<dyn Error + Send + Sync>> {
+        let user = User::find_by_login(login, &mut *connection).await?;
+        let real_user =
+            sqlx::query_as::<_, User>("SELECT * FROM users WHERE swipe_code_digest = ?")
+                .bind(secret_digest(real_swipe_code.trim()))
+                .fetch_optional(&mut *connection)
+                .await?;
+        Audit::create(
+            "User",
+            user.id,
+            "act_as",
+            None,
+            &serde_json::json!({
+                "real_user": real_user.as_ref().map(|real_user| &real_user.login),
+                "effective_user": user.login,
+                "request": request,
+            }),
+            &mut *connection,
+        )
+        .await?;
+        let real_user = match real_user {
+            Some(real_user) if real_user.active && real_user.role == Role::Admin => real_user,
+            _ => {
+                return Err(Box::new(ForbiddenError {
+                    message: Message::new("user-act-as-admin-required"),
+                }))
+            }
+        };
+        if !user.active {
+            return Err(Box::new(ForbiddenError {
+                message: Message::new("user-deactivated").arg("login", &user.login),
+            }));
+        }
+        Ok(Impersonation {
+            real_user: real_user.login,
+            effective_user: user.login,
+        })
+    }
+
+    /// Finds the active user whose API key is sent in the `X-Api-Key` header, or `None` if there
+    /// is none
+    /// # Examples
+    /// ```
+    /// # #[cfg(doctest)] {
+    /// use user::User;
+    /// let mut connection = init_db("sqlite::memory:").await.unwrap();
+    /// let user = User::authenticate(&api_key, &mut connection).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn authenticate(
+        api_key: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE api_key_digest = ? AND active = 1")
+            .bind(secret_digest(api_key))
+            .fetch_optional(&mut *connection)
+            .await
+    }
+
+    /// Audits a change to a user, returning the user as changed
+    async fn changed(
+        id: u32,
+        action: &str,
+        connection: &mut SqliteConnection,
+    ) -> Result<User, Box<dyn Error + Send + Sync>> {
+        let user = User::find(id, &mut *connection).await?;
+        Audit::create("User", user.id, action, None, &user, connection).await?;
+        Ok(user)
+    }
+
+    /// Find a user by id
+    async fn find(id: u32, connection: &mut SqliteConnection) -> Result<User, NotFoundError> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_one(&mut *connection)
+            .await
+            .map_err(|_| NotFoundError {
+                message: Message::new("user-not-found"),
+            })
+    }
+}
+
+/// Returns a `ValidationError` if a user already has the swipe code.
+async fn ensure_swipe_code_free(
+    swipe_code: &str,
+    connection: &mut SqliteConnection,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let taken =
+        sqlx::query_scalar::<_, u32>("SELECT COUNT(*) FROM users WHERE swipe_code_digest = ?")
+            .bind(secret_digest(swipe_code))
+            .fetch_one(&mut *connection)
+            .await?;
+    if taken > 0 {
+        return Err(Box::new(ValidationError {
+            message: Message::new("user-swipe-code-taken"),
+        }));
+    }
+    Ok(())
+}
+
+/// Generates the code of a swipe card for a user who has no card of their own yet, e.g.
+/// `3F9A01C2D4`.
+async fn new_swipe_code(connection: &mut SqliteConnection) -> Result<String, sqlx::Error> {
+    Ok(new_secret(SWIPE_CODE_BYTES, connection)
+        .await?
+        .to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::models::event::LoggedEvent;
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::models::scan::Scan;
+    use crate::models::user::*;
+
+    async fn actions(user: &User, connection: &mut SqliteConnection) -> Vec<String> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT action FROM audits WHERE auditable_type = 'User' AND auditable_id = ?
+                ORDER BY id",
+        )
+        .bind(user.id)
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let user = User::create(
+            "jd12".to_string(),
+            Role::Technician,
+            Some("Cell lines".to_string()),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let swipe_code = user.swipe_code.clone().unwrap();
+        let api_key = user.api_key.clone().unwrap();
+        assert_eq!(swipe_code.len(), 10);
+        assert_eq!(api_key.len(), 32);
+        assert_eq!(user.swipe_code_digest, secret_digest(&swipe_code));
+        assert_eq!(user.api_key_digest, secret_digest(&api_key));
+        assert!(user.active);
+        let json = serde_json::to_value(&user).unwrap();
+        assert_eq!(json["role"], "technician");
+        assert!(json.get("api_key").is_none());
+        assert!(json.get("api_key_digest").is_none());
+        let found = User::authenticate(&api_key, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.login, "jd12");
+        assert_eq!(found.api_key, None);
+        assert!(User::authenticate(&user.api_key_digest, &mut conn)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(actions(&user, &mut conn).await, ["create"]);
+
+        let error = User::create("jd12".to_string(), Role::Viewer, None, None, &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "A user with login jd12 already exists");
+        let error = User::create(
+            "ab3".to_string(),
+            Role::Viewer,
+            None,
+            Some(swipe_code),
+            &mut conn,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.is::<ValidationError>());
+    }
+
+    #[tokio::test]
+    async fn test_reset_and_deactivate() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let user = User::create(
+            "jd12".to_string(),
+            Role::Technician,
+            None,
+            Some("card-1".to_string()),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        User::record_activity("card-1", &mut conn).await.unwrap();
+        User::record_activity("someone-else", &mut conn)
+            .await
+            .unwrap();
+
+        let reset = User::reset(
+            &user.uuid,
+            Credential::SwipeCode,
+            Some("card-2".to_string()),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(reset.swipe_code.as_deref(), Some("card-2"));
+        assert_eq!(reset.swipe_code_digest, secret_digest("card-2"));
+        assert!(reset.last_active_at.is_some());
+        let reset = User::reset(&user.uuid, Credential::ApiKey, None, &mut conn)
+            .await
+            .unwrap();
+        assert_ne!(reset.api_key, user.api_key);
+        assert!(
+            User::authenticate(user.api_key.as_deref().unwrap(), &mut conn)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            User::authenticate(reset.api_key.as_deref().unwrap(), &mut conn)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let deactivated = User::deactivate(&user.uuid, &mut conn).await.unwrap();
+        assert!(!deactivated.active);
+        let error = User::record_activity("card-2", &mut conn)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "User jd12 has been deactivated");
+        assert_eq!(
+            actions(&user, &mut conn).await,
+            ["create", "reset_swipe_code", "reset_api_key", "deactivate"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_act_as() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let user = User::create(
+            "jd12".to_string(),
+            Role::Technician,
+            None,
+            Some("jd12-card".to_string()),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        User::create(
+            "support1".to_string(),
+            Role::Admin,
+            None,
+            Some("support-card".to_string()),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let impersonation = User::act_as("jd12", "support-card", "scan", &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(impersonation.real_user, "support1");
+        assert_eq!(impersonation.effective_user, "jd12");
+        let record_data = sqlx::query_scalar::<_, String>(
+            "SELECT record_data FROM audits WHERE action = 'act_as' AND auditable_id = ?",
+        )
+        .bind(user.id)
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+        let record: serde_json::Value = serde_json::from_str(&record_data).unwrap();
+        assert_eq!(record["real_user"], "support1");
+        assert_eq!(record["effective_user"], "jd12");
+        assert_eq!(record["request"], "scan");
+
+        let error = User::act_as("nobody", "support-card", "scan", &mut conn)
+            .await
+            .unwrap_err();
+        assert!(error.is::<NotFoundError>());
+        // Only an active admin, identified by their own swipe card, can act as a user
+        for swipe_code in ["unknown-card", "jd12-card"] {
+            let error = User::act_as("jd12", swipe_code, "scan", &mut conn)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Only an active admin can act as another user, with their own swipe card"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_impersonation_stamps_what_it_records() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let freezer_barcode = freezer.barcode.unwrap();
+        let impersonation = Impersonation {
+            real_user: "support1".to_string(),
+            effective_user: "jd12".to_string(),
+        };
+
+        let barcode = freezer_barcode.clone();
+        let scan = Impersonation::perform(Some(&impersonation), &mut conn, |connection| {
+            Box::pin(Scan::create(
+                barcode,
+                vec!["lw-1".to_string()],
+                None,
+                None,
+                connection,
+            ))
+        })
+        .await
+        .unwrap();
+        assert_eq!(scan.labwares.len(), 1);
+        Impersonation::perform(None, &mut conn, |connection| {
+            Box::pin(Scan::create(
+                freezer_barcode,
+                vec!["lw-2".to_string()],
+                None,
+                None,
+                connection,
+            ))
+        })
+        .await
+        .unwrap();
+
+        let audits = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+            "SELECT json_extract(record_data, '$.barcode'), real_user, effective_user
+                FROM audits WHERE auditable_type = 'Labware' ORDER BY id",
+        )
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+        assert_eq!(
+            audits,
+            [
+                (
+                    "lw-1".to_string(),
+                    Some("support1".to_string()),
+                    Some("jd12".to_string())
+                ),
+                ("lw-2".to_string(), None, None),
+            ]
+        );
+        let events = LoggedEvent::all(None, None, &mut conn).await.unwrap();
+        let created: Vec<(Option<&str>, Option<&str>)> = events
+            .iter()
+            .filter(|event| event.event.name() == "LabwareCreated")
+            .map(|event| (event.real_user.as_deref(), event.effective_user.as_deref()))
+            .collect();
+        assert_eq!(created, [(Some("support1"), Some("jd12")), (None, None)]);
+    }
+}
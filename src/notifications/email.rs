@@ -0,0 +1,134 @@
+use crate::config::{Config, CONFIG};
+use crate::notifications::Notification;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message as Email, Tokio1Executor};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use std::error::Error;
+
+/// The mailer built from the configuration, or `None` if no SMTP server is configured.
+pub static MAILER: Lazy<Option<EmailNotifier>> = Lazy::new(|| match EmailNotifier::new(&CONFIG) {
+    Ok(mailer) => mailer,
+    Err(e) => {
+        warn!("Email notifications are disabled: {}", e);
+        None
+    }
+});
+
+/// Sends notifications as plain text emails through an SMTP server.
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl EmailNotifier {
+    /// Connects to the configured SMTP server. Returns `None` if no server is configured, and an
+    /// error if the server URL or the sender address is invalid.
+    pub fn new(config: &Config) -> Result<Option<EmailNotifier>, Box<dyn Error + Send + Sync>> {
+        let Some(url) = &config.smtp_url else {
+            return Ok(None);
+        };
+        Ok(Some(EmailNotifier {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(url)?.build(),
+            from: config.email_from.parse()?,
+        }))
+    }
+
+    /// Emails a notification to the teams configured for its trigger. Does nothing if no team is.
+    pub async fn send(
+        &self,
+        notification: &Notification,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.send_to(&notification.recipients(&CONFIG), notification)
+            .await
+    }
+
+    /// Emails a notification to the given addresses. Does nothing if there are none.
+    pub async fn send_to(
+        &self,
+        recipients: &[String],
+        notification: &Notification,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if recipients.is_empty() {
+            return Ok(());
+        }
+        let email = build(&self.from, recipients, notification)?;
+        self.transport.send(email).await?;
+        info!(
+            "Emailed {} notification to {} recipients",
+            notification.trigger,
+            recipients.len()
+        );
+        Ok(())
+    }
+}
+
+/// Builds the email of a notification.
+pub fn build(
+    from: &Mailbox,
+    recipients: &[String],
+    notification: &Notification,
+) -> Result<Email, Box<dyn Error + Send + Sync>> {
+    let mut builder = Email::builder()
+        .from(from.clone())
+        .subject(notification.subject().to_string())
+        .header(ContentType::TEXT_PLAIN);
+    for recipient in recipients {
+        builder = builder.to(recipient.parse()?);
+    }
+    Ok(builder.body(notification.body().to_string())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::notifications::email::*;
+    use crate::notifications::Trigger;
+
+    #[test]
+    fn test_build() {
+        let notification = Notification::new(Trigger::PrintJobFailed)
+            .arg("uuid", "0d6f")
+            .arg("printer", "printer-1")
+            .arg("attempts", 2)
+            .arg("error", "Out of labels");
+        let email = build(
+            &"LabWhere <labwhere@example.com>".parse().unwrap(),
+            &[
+                "ops@example.com".to_string(),
+                "jane@example.com".to_string(),
+            ],
+            &notification,
+        )
+        .unwrap();
+        let formatted = String::from_utf8(email.formatted()).unwrap();
+        assert!(formatted.contains("From: LabWhere <labwhere@example.com>"));
+        assert!(formatted.contains("To: ops@example.com, jane@example.com"));
+        assert!(formatted.contains("Subject: Print job on printer-1 failed"));
+        assert!(formatted
+            .contains("Print job 0d6f on printer-1 failed after 2 attempts: Out of labels"));
+    }
+
+    #[test]
+    fn test_build_with_invalid_recipient() {
+        let notification = Notification::new(Trigger::RetentionReportReady);
+        assert!(build(
+            &"labwhere@example.com".parse().unwrap(),
+            &["not an address".to_string()],
+            &notification,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_new_without_smtp_server() {
+        assert!(EmailNotifier::new(&Config::default()).unwrap().is_none());
+        let config = Config {
+            smtp_url: Some("smtp://localhost:2525".to_string()),
+            email_from: "not an address".to_string(),
+            ..Default::default()
+        };
+        assert!(EmailNotifier::new(&config).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+//! Notifications sent to people when something needs their attention.
+//!
+//! Events such as a failed print job raise a `Notification` for a `Trigger`. Its subject and body
+//! are rendered from the `notification-{trigger}-subject` and `notification-{trigger}-body`
+//! messages in the catalogs. It is emailed to the teams configured for the trigger and posted to
+//! the chat webhooks configured for it, e.g. capacity alerts to a `#lab-ops` Slack channel.
+//! Sending happens in the background, so a slow or unreachable server never holds up or fails
+//! whatever raised the notification.
+//!
+//! People can also subscribe to the changes within a location. Those notifications are queued
+//! with the audits they are about and delivered by `deliver_subscriptions`, straight to the
+//! address or webhook of each subscription.
+//!
+//! Capacity alerts are raised by `watch_capacity` when a location becomes fuller than the
+//! threshold configured for it or its location type, and transit alerts by `watch_transit` when a
+//! labware has been checked out for longer than `transit_alert_minutes`.
+//!
+//! Notifications about a location, such as capacity alerts and flags, are also emailed to the
+//! location's owner (if it is one of the configured teams) and its contact email.
+pub mod email;
+pub mod webhook;
+
+use crate::config::{Config, CONFIG};
+use crate::i18n::Message;
+use crate::metrics::acquire;
+use crate::models::capacity_alert::CapacityAlert;
+use crate::models::subscription::Subscription;
+use crate::models::transit_alert::TransitAlert;
+use log::{error, info};
+use sqlx::SqlitePool;
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// How often queued subscription notifications are delivered.
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the occupancy of locations is compared against their capacity thresholds.
+const CAPACITY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often open checkouts are compared against the transit threshold.
+const TRANSIT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The events people can be notified about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    /// A location is fuller than its threshold
+    CapacityThreshold,
+    /// A stocktake was completed and its report can be downloaded
+    StocktakeCompleted,
+    /// A print job failed
+    PrintJobFailed,
+    /// A retention report is ready
+    RetentionReportReady,
+    /// Something changed in a location someone is subscribed to
+    LocationChanged,
+    /// A flag was raised on a location
+    LocationFlagged,
+    /// A labware has been in transit for longer than the threshold
+    TransitThreshold,
+}
+
+impl Trigger {
+    /// Parses a trigger from its name e.g. `print-job-failed`.
+    pub fn from_name(name: &str) -> Option<Trigger> {
+        match name.trim() {
+            "capacity-threshold" => Some(Trigger::CapacityThreshold),
+            "stocktake-completed" => Some(Trigger::StocktakeCompleted),
+            "print-job-failed" => Some(Trigger::PrintJobFailed),
+            "retention-report-ready" => Some(Trigger::RetentionReportReady),
+            "location-changed" => Some(Trigger::LocationChanged),
+            "location-flagged" => Some(Trigger::LocationFlagged),
+            "transit-threshold" => Some(Trigger::TransitThreshold),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            Trigger::CapacityThreshold => "capacity-threshold",
+            Trigger::StocktakeCompleted => "stocktake-completed",
+            Trigger::PrintJobFailed => "print-job-failed",
+            Trigger::RetentionReportReady => "retention-report-ready",
+            Trigger::LocationChanged => "location-changed",
+            Trigger::LocationFlagged => "location-flagged",
+            Trigger::TransitThreshold => "transit-threshold",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A notification about an event: its trigger and the arguments its templates are rendered with.
+/// # Examples
+/// ```
+/// use labwhere::notifications::{Notification, Trigger};
+/// let notification = Notification::new(Trigger::PrintJobFailed)
+///     .arg("uuid", "0d6f")
+///     .arg("printer", "printer-1")
+///     .arg("attempts", 2)
+///     .arg("error", "Out of labels");
+/// assert_eq!(notification.subject().to_string(), "Print job on printer-1 failed");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// What happened
+    pub trigger: Trigger,
+    /// The named arguments of the subject and body templates
+    pub args: Vec<(Cow<'static, str>, String)>,
+    /// The teams notified on top of those configured for the trigger, e.g. a location's owner
+    pub teams: Vec<String>,
+    /// The email addresses notified on top of the teams, e.g. a location's contact email
+    pub addresses: Vec<String>,
+}
+
+impl Notification {
+    pub fn new(trigger: Trigger) -> Notification {
+        Notification {
+            trigger,
+            args: vec![],
+            teams: vec![],
+            addresses: vec![],
+        }
+    }
+
+    /// Adds a named argument to the templates
+    pub fn arg(mut self, name: impl Into<Cow<'static, str>>, value: impl ToString) -> Notification {
+        self.args.push((name.into(), value.to_string()));
+        self
+    }
+
+    /// Also notifies the owner and the contact email of the location the notification is about
+    pub fn owned_by(mut self, owner: Option<&str>, contact_email: Option<&str>) -> Notification {
+        self.teams.extend(owner.map(String::from));
+        self.addresses.extend(contact_email.map(String::from));
+        self
+    }
+
+    /// The subject line, e.g. the subject of an email
+    pub fn subject(&self) -> Message {
+        self.template("subject")
+    }
+
+    /// The full text of the notification
+    pub fn body(&self) -> Message {
+        self.template("body")
+    }
+
+    /// The email addresses of the members of the teams configured for the trigger and of the
+    /// notification's own teams, followed by its own addresses, without repeats.
+    pub fn recipients(&self, config: &Config) -> Vec<String> {
+        let mut recipients: Vec<String> = vec![];
+        let teams = config
+            .notification_teams
+            .get(&self.trigger)
+            .into_iter()
+            .flatten()
+            .chain(&self.teams);
+        let addresses = teams
+            .flat_map(|team| config.team_emails.get(team).into_iter().flatten())
+            .chain(&self.addresses);
+        for address in addresses {
+            if !recipients.contains(address) {
+                recipients.push(address.clone());
+            }
+        }
+        recipients
+    }
+
+    fn template(&self, part: &str) -> Message {
+        Message {
+            key: format!("notification-{}-{}", self.trigger, part).into(),
+            args: self.args.clone(),
+        }
+    }
+}
+
+/// Sends a notification through every configured channel, in the background.
+///
+/// Does nothing if no channel is configured. Failures to send are logged.
+pub fn notify(notification: Notification) {
+    for webhook in CONFIG
+        .notification_webhooks
+        .get(&notification.trigger)
+        .into_iter()
+        .flatten()
+    {
+        let (webhook, notification) = (webhook.clone(), notification.clone());
+        tokio::spawn(async move {
+            if let Err(e) = webhook.send(&notification).await {
+                error!(
+                    "Could not post {} notification to {:?} webhook: {}",
+                    notification.trigger, webhook.format, e
+                );
+            }
+        });
+    }
+    if let Some(mailer) = email::MAILER.as_ref() {
+        tokio::spawn(async move {
+            if let Err(e) = mailer.send(&notification).await {
+                error!(
+                    "Could not email {} notification: {}",
+                    notification.trigger, e
+                );
+            }
+        });
+    }
+}
+
+/// Delivers the notifications of location subscriptions every few seconds, forever.
+///
+/// Meant to be spawned once when the server starts. Failures are logged and retried.
+pub async fn deliver_subscriptions(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(DELIVERY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut connection = match acquire(&pool).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("Could not deliver subscription notifications: {}", e);
+                continue;
+            }
+        };
+        match Subscription::deliver_pending(&mut connection).await {
+            Ok(0) => {}
+            Ok(delivered) => info!("Delivered {} subscription notifications", delivered),
+            Err(e) => error!("Could not deliver subscription notifications: {}", e),
+        }
+    }
+}
+
+/// Raises a notification for every location which crosses its capacity threshold, checking every
+/// minute, forever.
+///
+/// Meant to be spawned once when the server starts. Failures are logged and retried.
+pub async fn watch_capacity(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(CAPACITY_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let alerts = match acquire(&pool).await {
+            Ok(mut connection) => CapacityAlert::check(&CONFIG, &mut connection).await,
+            Err(e) => Err(e),
+        };
+        match alerts {
+            Ok(alerts) => {
+                for alert in alerts {
+                    info!(
+                        "{} is {}% full, over its {}% threshold",
+                        alert.location, alert.percent, alert.threshold
+                    );
+                    notify(alert.notification());
+                }
+            }
+            Err(e) => error!("Could not check capacity thresholds: {}", e),
+        }
+    }
+}
+
+/// Raises a notification for every labware which has been in transit for longer than the
+/// threshold, checking every minute, forever.
+///
+/// Meant to be spawned once when the server starts. Failures are logged and retried.
+pub async fn watch_transit(pool: SqlitePool) {
+    let mut interval = tokio::time::interval(TRANSIT_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let alerts = match acquire(&pool).await {
+            Ok(mut connection) => TransitAlert::check(&CONFIG, &mut connection).await,
+            Err(e) => Err(e),
+        };
+        match alerts {
+            Ok(alerts) => {
+                for alert in alerts {
+                    info!(
+                        "{} has been in transit with {} for {} minutes",
+                        alert.labware_barcode,
+                        alert.holder,
+                        alert.minutes()
+                    );
+                    notify(alert.notification());
+                }
+            }
+            Err(e) => error!("Could not check labwares in transit: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::notifications::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_trigger_names_round_trip() {
+        for trigger in [
+            Trigger::CapacityThreshold,
+            Trigger::StocktakeCompleted,
+            Trigger::PrintJobFailed,
+            Trigger::RetentionReportReady,
+            Trigger::LocationChanged,
+            Trigger::LocationFlagged,
+            Trigger::TransitThreshold,
+        ] {
+            assert_eq!(Trigger::from_name(&trigger.to_string()), Some(trigger));
+        }
+        assert_eq!(Trigger::from_name("fire-alarm"), None);
+    }
+
+    #[test]
+    fn test_templates() {
+        let notification = Notification::new(Trigger::StocktakeCompleted)
+            .arg("location", "freezer")
+            .arg("user", "jane")
+            .arg("discrepancies", 3)
+            .arg("report", "http://localhost:3000/stocktakes/1/report.csv");
+        assert_eq!(
+            notification.subject().to_string(),
+            "Stocktake of freezer completed"
+        );
+        assert_eq!(
+            notification.body().to_string(),
+            "The stocktake of freezer started by jane was completed with 3 discrepancies. \
+             The report can be downloaded from http://localhost:3000/stocktakes/1/report.csv"
+        );
+    }
+
+    #[test]
+    fn test_recipients() {
+        let config = Config {
+            team_emails: HashMap::from([
+                (
+                    "lab-ops".to_string(),
+                    vec![
+                        "ops@example.com".to_string(),
+                        "jane@example.com".to_string(),
+                    ],
+                ),
+                ("freezers".to_string(), vec!["jane@example.com".to_string()]),
+            ]),
+            notification_teams: HashMap::from([(
+                Trigger::PrintJobFailed,
+                vec!["lab-ops".to_string(), "freezers".to_string()],
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            Notification::new(Trigger::PrintJobFailed).recipients(&config),
+            vec!["ops@example.com", "jane@example.com"]
+        );
+        assert!(Notification::new(Trigger::StocktakeCompleted)
+            .recipients(&config)
+            .is_empty());
+        assert_eq!(
+            Notification::new(Trigger::PrintJobFailed)
+                .owned_by(Some("freezers"), Some("ops@example.com"))
+                .recipients(&config),
+            vec!["ops@example.com", "jane@example.com"]
+        );
+        assert_eq!(
+            Notification::new(Trigger::CapacityThreshold)
+                .owned_by(Some("jane"), Some("bob@example.com"))
+                .recipients(&config),
+            vec!["bob@example.com"]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::notifications::Notification;
+use log::info;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::time::Duration;
+
+/// The HTTP client webhooks are posted with.
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+});
+
+/// The chat service a webhook posts to, which decides the shape of the payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WebhookFormat {
+    /// A Slack incoming webhook
+    Slack,
+    /// A Microsoft Teams incoming webhook
+    Teams,
+}
+
+/// A chat channel notifications are posted to.
+///
+/// Webhooks are configured as `{format}:{url}` e.g. `slack:https://hooks.slack.com/services/...`
+/// or `teams:https://example.webhook.office.com/...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    /// The chat service
+    pub format: WebhookFormat,
+    /// The URL of the webhook
+    pub url: String,
+}
+
+impl Webhook {
+    /// Parses a webhook from its configuration e.g. `slack:https://hooks.slack.com/services/...`.
+    /// # Examples
+    /// ```
+    /// use labwhere::notifications::webhook::{Webhook, WebhookFormat};
+    /// let webhook = Webhook::from_name("teams:https://example.webhook.office.com/1").unwrap();
+    /// assert_eq!(webhook.format, WebhookFormat::Teams);
+    /// assert_eq!(webhook.url, "https://example.webhook.office.com/1");
+    /// assert_eq!(Webhook::from_name("https://hooks.slack.com/services/1"), None);
+    /// ```
+    pub fn from_name(name: &str) -> Option<Webhook> {
+        let (format, url) = name.trim().split_once(':')?;
+        let format = match format {
+            "slack" => WebhookFormat::Slack,
+            "teams" => WebhookFormat::Teams,
+            _ => return None,
+        };
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return None;
+        }
+        Some(Webhook {
+            format,
+            url: url.to_string(),
+        })
+    }
+
+    /// The JSON posted to the webhook for a notification.
+    pub fn payload(&self, notification: &Notification) -> Value {
+        let subject = notification.subject().to_string();
+        let body = notification.body().to_string();
+        match self.format {
+            WebhookFormat::Slack => json!({ "text": format!("*{}*\n{}", subject, body) }),
+            WebhookFormat::Teams => json!({
+                "@type": "MessageCard",
+                "@context": "https://schema.org/extensions",
+                "summary": subject,
+                "title": subject,
+                "text": body,
+            }),
+        }
+    }
+
+    /// Posts a notification to the webhook.
+    pub async fn send(
+        &self,
+        notification: &Notification,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        CLIENT
+            .post(&self.url)
+            .json(&self.payload(notification))
+            .send()
+            .await?
+            .error_for_status()?;
+        info!(
+            "Posted {} notification to {:?} webhook",
+            notification.trigger, self.format
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notifications::webhook::*;
+    use crate::notifications::Trigger;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn notification() -> Notification {
+        Notification::new(Trigger::PrintJobFailed)
+            .arg("uuid", "0d6f")
+            .arg("printer", "printer-1")
+            .arg("attempts", 2)
+            .arg("error", "Out of labels")
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            Webhook::from_name(" slack:https://hooks.slack.com/services/1 "),
+            Some(Webhook {
+                format: WebhookFormat::Slack,
+                url: "https://hooks.slack.com/services/1".to_string()
+            })
+        );
+        assert_eq!(Webhook::from_name("irc:https://example.com"), None);
+        assert_eq!(Webhook::from_name("slack:#lab-ops"), None);
+    }
+
+    #[test]
+    fn test_payload() {
+        let slack = Webhook::from_name("slack:https://hooks.slack.com/services/1").unwrap();
+        assert_eq!(
+            slack.payload(&notification())["text"],
+            "*Print job on printer-1 failed*\nPrint job 0d6f on printer-1 failed after 2 attempts: Out of labels"
+        );
+
+        let teams = Webhook::from_name("teams:https://example.webhook.office.com/1").unwrap();
+        let payload = teams.payload(&notification());
+        assert_eq!(payload["@type"], "MessageCard");
+        assert_eq!(payload["title"], "Print job on printer-1 failed");
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("slack:http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut chunk = [0; 1024];
+            while !String::from_utf8_lossy(&request).contains("Out of labels") {
+                let read = socket.read(&mut chunk).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&chunk[..read]);
+            }
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        Webhook::from_name(&url)
+            .unwrap()
+            .send(&notification())
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook"));
+        assert!(request.contains("Print job on printer-1 failed"));
+    }
+}
@@ -0,0 +1,234 @@
+//! Writing of printable PDF documents.
+//!
+//! Reports meant to be printed and pinned up in the lab (e.g. the map of a freezer on its door)
+//! are plain lines of text, so rather than pulling in a PDF library this writes them directly:
+//! A4 pages in the standard Helvetica fonts, which every PDF viewer has built in. Text is encoded
+//! as WinAnsi, which covers the accented letters of the supported locales; other characters are
+//! printed as `?`.
+use std::fmt::Write;
+
+/// The width of an A4 page, in points.
+pub const PAGE_WIDTH: f32 = 595.0;
+
+/// The height of an A4 page, in points.
+pub const PAGE_HEIGHT: f32 = 842.0;
+
+/// The margin around the text of a page, in points.
+const MARGIN: f32 = 50.0;
+
+/// The space between lines, as a multiple of the font size.
+const LINE_SPACING: f32 = 1.4;
+
+/// The weight of a line of text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weight {
+    Regular,
+    Bold,
+}
+
+/// A PDF document being written, line by line.
+///
+/// A new page is started whenever a line does not fit on the current one.
+/// # Examples
+/// ```
+/// use labwhere::pdf::{Document, Weight};
+/// let mut document = Document::new();
+/// document.line("Freezer 1", 16.0, 0.0, Weight::Bold);
+/// document.line("Shelf 1", 11.0, 12.0, Weight::Regular);
+/// let bytes = document.finish();
+/// assert!(bytes.starts_with(b"%PDF-1.4"));
+/// ```
+#[derive(Debug)]
+pub struct Document {
+    /// The content streams of the finished pages
+    pages: Vec<Vec<u8>>,
+    /// The content stream of the page being written
+    current: Vec<u8>,
+    /// Where the next line goes, in points from the bottom of the page
+    cursor: f32,
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document::new()
+    }
+}
+
+impl Document {
+    /// Starts a document with an empty first page.
+    pub fn new() -> Document {
+        Document {
+            pages: vec![],
+            current: vec![],
+            cursor: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    /// Writes a line of text in the given font size, indented from the left margin by the given
+    /// number of points.
+    pub fn line(&mut self, text: &str, size: f32, indent: f32, weight: Weight) {
+        let height = size * LINE_SPACING;
+        if self.cursor - height < MARGIN && !self.current.is_empty() {
+            self.pages.push(std::mem::take(&mut self.current));
+            self.cursor = PAGE_HEIGHT - MARGIN;
+        }
+        self.cursor -= height;
+        let font = match weight {
+            Weight::Regular => "F1",
+            Weight::Bold => "F2",
+        };
+        self.current.extend_from_slice(
+            format!(
+                "BT /{} {} Tf {} {} Td (",
+                font,
+                size,
+                MARGIN + indent,
+                self.cursor
+            )
+            .as_bytes(),
+        );
+        self.current.extend(encode(text));
+        self.current.extend_from_slice(b") Tj ET\n");
+    }
+
+    /// Leaves a blank line of the given font size.
+    pub fn gap(&mut self, size: f32) {
+        self.cursor -= size * LINE_SPACING;
+    }
+
+    /// The number of pages written so far, including the current one.
+    pub fn page_count(&self) -> usize {
+        self.pages.len() + 1
+    }
+
+    /// Finishes the document, returning the bytes of the PDF file.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.pages.push(self.current);
+
+        // Objects 1 to 4 are the catalog, the page tree and the two fonts; each page is followed
+        // by its content stream.
+        let page_ids: Vec<usize> = (0..self.pages.len()).map(|i| 5 + i * 2).collect();
+        let kids = page_ids
+            .iter()
+            .map(|id| format!("{} 0 R", id))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mut objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids,
+                page_ids.len()
+            )
+            .into_bytes(),
+            font("Helvetica"),
+            font("Helvetica-Bold"),
+        ];
+        for (page_id, content) in page_ids.iter().zip(self.pages) {
+            objects.push(
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                        /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                    PAGE_WIDTH,
+                    PAGE_HEIGHT,
+                    page_id + 1
+                )
+                .into_bytes(),
+            );
+            let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+            stream.extend(content);
+            stream.extend_from_slice(b"\nendstream");
+            objects.push(stream);
+        }
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            pdf.extend_from_slice(object);
+            pdf.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = pdf.len();
+        let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            trailer,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        );
+        pdf.extend_from_slice(trailer.as_bytes());
+        pdf
+    }
+}
+
+/// The dictionary of a standard font.
+fn font(name: &str) -> Vec<u8> {
+    format!(
+        "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+        name
+    )
+    .into_bytes()
+}
+
+/// Encodes text as the bytes of a PDF string, escaping the characters which delimit it.
+fn encode(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                bytes.push(b'\\');
+                bytes.push(c as u8);
+            }
+            // WinAnsi agrees with Latin-1 for printable characters apart from 0x80 to 0x9F
+            ' '..='~' | '\u{a0}'..='\u{ff}' => bytes.push(c as u32 as u8),
+            '—' => bytes.push(0x97),
+            '…' => bytes.push(0x85),
+            _ => bytes.push(b'?'),
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pdf::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("box (1)"), b"box \\(1\\)");
+        assert_eq!(encode("ubicación"), b"ubicaci\xf3n");
+        assert_eq!(encode("☃"), b"?");
+    }
+
+    #[test]
+    fn test_page_breaks() {
+        let mut document = Document::new();
+        for i in 0..100 {
+            document.line(&format!("Line {}", i), 12.0, 0.0, Weight::Regular);
+        }
+        assert_eq!(document.page_count(), 3);
+
+        let pdf = String::from_utf8(document.finish()).unwrap();
+        assert!(pdf.contains("/Count 3"));
+        assert!(pdf.contains("(Line 99) Tj"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        // The cross-reference table points at each object
+        let xref: usize = pdf
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(pdf[xref..].starts_with("xref\n0 11\n"));
+        let first_object = pdf[xref..].lines().nth(3).unwrap();
+        let offset: usize = first_object[..10].parse().unwrap();
+        assert!(pdf[offset..].starts_with("1 0 obj"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Searching locations and labwares by name or barcode, for `GET /search`.
+//!
+//! Searches query the database, which is fine until there are millions of labwares. Very large
+//! deployments can set `LABWHERE_SEARCH_URL` to an OpenSearch (or Elasticsearch) cluster instead:
+//! `index_events` then follows the event log and indexes every location and labware an event
+//! happens to, and searches query the cluster. Should the cluster fail to answer, searches fall
+//! back to the database, so a search never fails because of it.
+pub mod opensearch;
+
+use crate::config::Config;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::location::Location;
+use crate::search::opensearch::OpenSearch;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// The most locations, and the most labwares, a search returns.
+pub const MAX_RESULTS: u32 = 100;
+
+/// The locations and labwares which match a search.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SearchResults {
+    /// The locations whose name or barcode match, best match first
+    pub locations: Vec<Location>,
+    /// The labwares whose barcode (or one of its aliases) match, best match first
+    pub labwares: Vec<Labware>,
+}
+
+/// The order the results of a search are returned in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "TEXT", rename_all = "kebab-case")]
+pub enum SearchSort {
+    /// Best match first
+    #[default]
+    Relevance,
+    /// Locations by name and labwares by barcode, A to Z
+    Name,
+    /// Locations by name and labwares by barcode, Z to A
+    NameDesc,
+}
+
+impl SearchSort {
+    /// Parses an order from its name e.g. `name-desc`.
+    pub fn from_name(name: &str) -> Option<SearchSort> {
+        match name {
+            "relevance" => Some(SearchSort::Relevance),
+            "name" => Some(SearchSort::Name),
+            "name-desc" => Some(SearchSort::NameDesc),
+            _ => None,
+        }
+    }
+}
+
+impl SearchResults {
+    /// Puts the results in the given order, regardless of case.
+    pub fn sorted(mut self, sort: SearchSort) -> SearchResults {
+        match sort {
+            SearchSort::Relevance => {}
+            SearchSort::Name | SearchSort::NameDesc => {
+                self.locations.sort_by_key(|l| l.name.to_lowercase());
+                self.labwares.sort_by_key(|l| l.barcode.to_lowercase());
+                if sort == SearchSort::NameDesc {
+                    self.locations.reverse();
+                    self.labwares.reverse();
+                }
+            }
+        }
+        self
+    }
+}
+
+/// Searches the locations and labwares whose name or barcode match the query, returning up to
+/// `limit` of each. Only labwares with the given status are returned, or labwares with any status
+/// if it is `None`.
+///
+/// Queries the search cluster if one is configured, and the database otherwise or if the cluster
+/// cannot be queried.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// let mut connection = init_db("sqlite::memory:").await.unwrap();
+/// let results = search(&CONFIG, "freezer", 20, Some(LabwareStatus::Active), &mut connection).await.unwrap();
+/// # }
+/// ```
+pub async fn search(
+    config: &Config,
+    query: &str,
+    limit: u32,
+    status: Option<LabwareStatus>,
+    connection: &mut SqliteConnection,
+) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+    let limit = limit.clamp(1, MAX_RESULTS);
+    if let Some(cluster) = OpenSearch::from_config(config) {
+        match cluster.search(query, limit, status, &mut *connection).await {
+            Ok(results) => return Ok(results),
+            Err(e) => warn!(
+                "Could not search {}, searching the database instead: {}",
+                cluster.url, e
+            ),
+        }
+    }
+    Ok(search_database(query, limit, status, connection).await?)
+}
+
+/// Searches the database for the locations and labwares whose name or barcode contain the query,
+/// regardless of case, and the labwares with the given status (any if `None`).
+pub async fn search_database(
+    query: &str,
+    limit: u32,
+    status: Option<LabwareStatus>,
+    connection: &mut SqliteConnection,
+) -> Result<SearchResults, sqlx::Error> {
+    let pattern = format!(
+        "%{}%",
+        query
+            .trim()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+    let locations = sqlx::query_as::<_, Location>(
+        "SELECT * FROM locations WHERE name LIKE ?1 ESCAPE '\\' OR barcode LIKE ?1 ESCAPE '\\'
+            ORDER BY name, id LIMIT ?2",
+    )
+    .bind(&pattern)
+    .bind(limit)
+    .fetch_all(&mut *connection)
+    .await?;
+    let labwares = sqlx::query_as::<_, Labware>(
+        "SELECT * FROM labwares WHERE (?3 IS NULL OR status = ?3)
+            AND (barcode LIKE ?1 ESCAPE '\\'
+                OR id IN (SELECT labware_id FROM labware_barcodes WHERE barcode LIKE ?1 ESCAPE '\\'))
+            ORDER BY barcode LIMIT ?2",
+    )
+    .bind(&pattern)
+    .bind(limit)
+    .bind(status)
+    .fetch_all(&mut *connection)
+    .await?;
+    Ok(SearchResults {
+        locations,
+        labwares,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::init_db;
+    use crate::models::labware::{Labware, LabwareStatus};
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::search::*;
+
+    #[tokio::test]
+    async fn test_search_database() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("Freezer 1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Location::create("fridge".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("PLATE_1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("plate-2".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap()
+            .change_status(
+                LabwareStatus::ShippedOut,
+                Some("to Hinxton"),
+                None,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+
+        let results = search(&Config::default(), "freezer", 20, None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(results.locations.len(), 1);
+        assert_eq!(results.locations[0].name, "Freezer 1");
+        assert!(results.labwares.is_empty());
+
+        let results = search_database("plate", 1, None, &mut conn).await.unwrap();
+        assert_eq!(results.labwares.len(), 1);
+        assert_eq!(results.labwares[0].barcode, "PLATE_1");
+
+        // Only labwares with the status asked for are found
+        let active = Some(LabwareStatus::Active);
+        let results = search_database("plate", 20, active, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(results.labwares.len(), 1);
+        let shipped_out = Some(LabwareStatus::ShippedOut);
+        let results = search_database("plate", 20, shipped_out, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(results.labwares[0].barcode, "plate-2");
+
+        // Wildcards in the query are matched literally
+        let results = search_database("e_", 20, None, &mut conn).await.unwrap();
+        assert_eq!(results.labwares.len(), 1);
+        assert!(results.locations.is_empty());
+
+        let results = search_database("plate", 20, None, &mut conn)
+            .await
+            .unwrap()
+            .sorted(SearchSort::NameDesc);
+        assert_eq!(results.labwares[0].barcode, "PLATE_1");
+        assert_eq!(results.labwares[1].barcode, "plate-2");
+    }
+}
@@ -0,0 +1,410 @@
+use crate::config::{Config, CONFIG};
+use crate::metrics::acquire;
+use crate::models::event::LoggedEvent;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::location::Location;
+use crate::search::SearchResults;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+/// The most events indexed in one request to the cluster.
+pub const BATCH_SIZE: u32 = 500;
+
+/// How often the event log is checked for events to index.
+const INDEX_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The HTTP client the cluster is queried with.
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+});
+
+/// An OpenSearch (or Elasticsearch) cluster holding an index of locations and an index of
+/// labwares.
+///
+/// Each document is the id, barcode and name (or location) of a record, along with the id of the
+/// last event which was indexed for it, so the indexing carries on from where it left off after a
+/// restart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenSearch {
+    /// The URL of the cluster
+    pub url: String,
+    /// The prefix of the names of the indices
+    pub index_prefix: String,
+}
+
+impl OpenSearch {
+    /// The cluster configured with `LABWHERE_SEARCH_URL`, if any.
+    pub fn from_config(config: &Config) -> Option<OpenSearch> {
+        config.search_url.as_ref().map(|url| OpenSearch {
+            url: url.clone(),
+            index_prefix: config.search_index_prefix.clone(),
+        })
+    }
+
+    /// The name of the index of a type of record, e.g. `labwhere-labwares` for `Labware`.
+    pub fn index(&self, record_type: &str) -> String {
+        match record_type {
+            "Location" => format!("{}-locations", self.index_prefix),
+            _ => format!("{}-labwares", self.index_prefix),
+        }
+    }
+
+    /// The URL of a search of both indices.
+    fn search_url(&self) -> String {
+        format!(
+            "{}/{},{}/_search?ignore_unavailable=true",
+            self.url,
+            self.index("Location"),
+            self.index("Labware")
+        )
+    }
+
+    /// Searches the locations and labwares whose name or barcode start with the words of the
+    /// query, then reads them from the database, so they are returned as they are now. Labwares
+    /// whose status is not the given one (if any) are left out.
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+        status: Option<LabwareStatus>,
+        connection: &mut SqliteConnection,
+    ) -> Result<SearchResults, Box<dyn Error + Send + Sync>> {
+        let response: Value = CLIENT
+            .post(self.search_url())
+            .json(&json!({
+                "size": limit * 2,
+                "query": {
+                    "multi_match": {
+                        "query": query,
+                        "type": "bool_prefix",
+                        "fields": ["barcode", "name"],
+                    }
+                },
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let mut results = SearchResults::default();
+        for hit in response["hits"]["hits"].as_array().into_iter().flatten() {
+            let Some(id) = hit["_id"].as_str().and_then(|id| id.parse::<u32>().ok()) else {
+                continue;
+            };
+            if hit["_index"] == self.index("Location") {
+                if results.locations.len() < limit as usize {
+                    if let Ok(location) = Location::find(id, &mut *connection).await {
+                        results.locations.push(location);
+                    }
+                }
+            } else if results.labwares.len() < limit as usize {
+                if let Some(labware) = find_labware(id, &mut *connection).await? {
+                    if status.is_none_or(|status| status == labware.status) {
+                        results.labwares.push(labware);
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// The id of the last event which was indexed, or 0 if nothing has been indexed yet.
+    pub async fn last_indexed_event(&self) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let response: Value = CLIENT
+            .post(self.search_url())
+            .json(&json!({
+                "size": 0,
+                "aggs": { "last_event": { "max": { "field": "event_id" } } },
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response["aggregations"]["last_event"]["value"]
+            .as_f64()
+            .map_or(0, |id| id as u32))
+    }
+
+    /// Indexes the records the events happened to as they are now, removing those which no longer
+    /// exist from the index. Returns the id of the last event indexed.
+    pub async fn index_events(
+        &self,
+        events: &[LoggedEvent],
+        connection: &mut SqliteConnection,
+    ) -> Result<Option<u32>, Box<dyn Error + Send + Sync>> {
+        let Some(last) = events.last() else {
+            return Ok(None);
+        };
+        // A record is indexed once however many events happened to it
+        let mut records = BTreeMap::new();
+        for logged in events {
+            records.insert(logged.event.record(), logged.id);
+        }
+        let mut lines = vec![];
+        for ((record_type, id), event_id) in records {
+            let document = match record_type {
+                "Location" => match Location::find(id, &mut *connection).await {
+                    Ok(location) => Some(json!({
+                        "id": location.id,
+                        "barcode": location.barcode,
+                        "name": location.name,
+                        "parent_id": location.parent_id,
+                        "event_id": event_id,
+                    })),
+                    Err(_) => None,
+                },
+                _ => find_labware(id, &mut *connection).await?.map(|labware| {
+                    json!({
+                        "id": id,
+                        "barcode": labware.barcode,
+                        "location_id": labware.location_id,
+                        "status": labware.status,
+                        "event_id": event_id,
+                    })
+                }),
+            };
+            lines.extend(bulk_lines(&self.index(record_type), id, document));
+        }
+        let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let response: Value = CLIENT
+            .post(format!("{}/_bulk", self.url))
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if response["errors"] == true {
+            return Err(format!("The cluster rejected part of the batch: {}", response).into());
+        }
+        Ok(Some(last.id))
+    }
+}
+
+/// The lines of a bulk request which index a document, or delete it if there is none.
+fn bulk_lines(index: &str, id: u32, document: Option<Value>) -> Vec<Value> {
+    match document {
+        Some(document) => vec![
+            json!({ "index": { "_index": index, "_id": id.to_string() } }),
+            document,
+        ],
+        None => vec![json!({ "delete": { "_index": index, "_id": id.to_string() } })],
+    }
+}
+
+/// Reads a labware by its id, if it still exists.
+async fn find_labware(
+    id: u32,
+    connection: &mut SqliteConnection,
+) -> Result<Option<Labware>, sqlx::Error> {
+    sqlx::query_as::<_, Labware>("SELECT * FROM labwares WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *connection)
+        .await
+}
+
+/// Indexes the events of the event log into the configured search cluster as they are logged,
+/// forever.
+///
+/// Meant to be spawned once when the server starts, if `LABWHERE_SEARCH_URL` is set. While the
+/// cluster cannot be reached, failures are logged and the indexing is retried from where it left
+/// off.
+pub async fn index_events(pool: SqlitePool) {
+    let Some(cluster) = OpenSearch::from_config(&CONFIG) else {
+        return;
+    };
+    let mut interval = tokio::time::interval(INDEX_INTERVAL);
+    let mut cursor = None;
+    loop {
+        interval.tick().await;
+        let last_indexed = match cursor {
+            Some(last_indexed) => last_indexed,
+            None => match cluster.last_indexed_event().await {
+                Ok(last_indexed) => last_indexed,
+                Err(e) => {
+                    warn!("Could not reach {}: {}", cluster.url, e);
+                    continue;
+                }
+            },
+        };
+        let indexed = match acquire(&pool).await {
+            Ok(mut connection) => {
+                match LoggedEvent::after(last_indexed, BATCH_SIZE, &mut connection).await {
+                    Ok(events) => cluster.index_events(&events, &mut connection).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e.into()),
+        };
+        match indexed {
+            Ok(Some(last)) => {
+                info!("Indexed events up to {} into {}", last, cluster.url);
+                cursor = Some(last);
+            }
+            Ok(None) => cursor = Some(last_indexed),
+            Err(e) => {
+                warn!("Could not index events into {}: {}", cluster.url, e);
+                cursor = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::init_db;
+    use crate::models::event::LoggedEvent;
+    use crate::models::labware::{Labware, LabwareStatus};
+    use crate::models::location::Location;
+    use crate::models::location_type::LocationType;
+    use crate::search::opensearch::*;
+    use crate::search::search;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers one request with the body, and returns the request.
+    async fn serve(listener: TcpListener, body: Value) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = vec![];
+        let mut chunk = [0; 4096];
+        loop {
+            let read = socket.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, content)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length: ")?
+                            .parse()
+                            .ok()
+                    })
+                    .unwrap_or(0);
+                if read == 0 || content.len() >= length {
+                    break;
+                }
+            }
+        }
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request).to_string()
+    }
+
+    async fn cluster() -> (TcpListener, Config) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            search_url: Some(format!("http://{}", listener.local_addr().unwrap())),
+            search_index_prefix: "labwhere".to_string(),
+            ..Default::default()
+        };
+        (listener, config)
+    }
+
+    #[test]
+    fn test_bulk_lines() {
+        assert_eq!(
+            bulk_lines("labwhere-labwares", 7, Some(json!({ "id": 7 }))),
+            vec![
+                json!({ "index": { "_index": "labwhere-labwares", "_id": "7" } }),
+                json!({ "id": 7 }),
+            ]
+        );
+        assert_eq!(
+            bulk_lines("labwhere-labwares", 7, None),
+            vec![json!({ "delete": { "_index": "labwhere-labwares", "_id": "7" } })]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_events() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let labware = Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        labware
+            .change_status(LabwareStatus::Destroyed, None, None, &mut conn)
+            .await
+            .unwrap();
+
+        let (listener, config) = cluster().await;
+        let server = tokio::spawn(serve(listener, json!({ "errors": false, "items": [] })));
+        let events = LoggedEvent::after(0, BATCH_SIZE, &mut conn).await.unwrap();
+        let cluster = OpenSearch::from_config(&config).unwrap();
+        assert_eq!(
+            cluster.index_events(&events, &mut conn).await.unwrap(),
+            Some(4)
+        );
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /_bulk"));
+        assert!(request.contains(r#"{"index":{"_id":"1","_index":"labwhere-locations"}}"#));
+        assert!(request.contains(r#""status":"destroyed""#));
+        assert!(request.contains(r#""barcode":"lw-2""#));
+
+        assert_eq!(cluster.index_events(&[], &mut conn).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+
+        let (listener, config) = cluster().await;
+        let server = tokio::spawn(serve(
+            listener,
+            json!({ "hits": { "hits": [
+                { "_index": "labwhere-labwares", "_id": "1" },
+                { "_index": "labwhere-locations", "_id": "1" },
+                { "_index": "labwhere-labwares", "_id": "9" },
+            ] } }),
+        ));
+        let results = search(&config, "lw", 20, None, &mut conn).await.unwrap();
+        assert_eq!(results.locations[0].name, "freezer");
+        assert_eq!(results.labwares.len(), 1);
+        assert_eq!(results.labwares[0].barcode, "lw-1");
+        let request = server.await.unwrap();
+        assert!(request.starts_with(
+            "POST /labwhere-locations,labwhere-labwares/_search?ignore_unavailable=true"
+        ));
+        assert!(request.contains(r#""query":"lw""#));
+
+        // Without the cluster, the database is searched instead
+        let results = search(&config, "freezer", 20, None, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(results.locations[0].name, "freezer");
+    }
+}
@@ -0,0 +1,270 @@
+use crate::services::{
+    json, map_error, method_not_allowed, parquet, read_json, status_only, ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::cache::RECORDED_REQUESTS;
+use labwhere::config::CONFIG;
+use labwhere::db::analytics::Dataset;
+use labwhere::db::query::ReadOnlyQuery;
+use labwhere::errors::NotFoundError;
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::metrics::slo::LATENCY_SLOS;
+use labwhere::models::api_key::ApiKey;
+use labwhere::models::api_key_usage::ApiKeyUsage;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::time::Duration;
+use validator::Validate;
+
+/// The payload for running a query.
+#[derive(Debug, Deserialize, Validate)]
+struct AdminQuery {
+    /// A single `SELECT` statement
+    #[validate(length(min = 1, message = "validation-blank"))]
+    sql: String,
+}
+
+/// Shows (`GET`) how much an API key has been used, so admins can see which integration is
+/// hammering the service.
+///
+/// `GET /admin/api_keys/{uuid}/usage` responds with the name of the key as `api_key`, its daily
+/// `quota` (`null` if it is unlimited) and its `usage` per day over the last 30 days, newest
+/// first. Requires one of the configured admin tokens in the `X-Admin-Token` header, otherwise
+/// the response is 403.
+pub async fn api_key_usage(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/api_keys/{}/usage endpoint",
+        uuid
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let api_key = match ApiKey::find_by_uuid(uuid, &mut connection).await {
+        Ok(api_key) => api_key,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match ApiKeyUsage::history(&api_key.name, &mut connection).await {
+        Ok(usage) => Ok(json(
+            StatusCode::OK,
+            &serde_json::json!({
+                "api_key": api_key.name,
+                "quota": CONFIG.api_key_quotas.get(&api_key.name),
+                "usage": usage,
+            }),
+        )),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Downloads (`GET`) a dataset for the data warehouse as a Parquet file.
+///
+/// `GET /admin/analytics/{dataset}.parquet` responds with every row of `scans`, `audits`,
+/// `occupancy_snapshots` or `snapshots`. Requires one of the configured admin tokens in the `X-Admin-Token`
+/// header, otherwise the response is 403.
+pub async fn analytics(pool: SqlitePool, filename: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/analytics/{} endpoint",
+        filename
+    );
+    let name = filename.strip_suffix(".parquet").unwrap_or(filename);
+    let Some(dataset) = Dataset::find(name) else {
+        return Ok(map_error(&NotFoundError {
+            message: Message::new("analytics-dataset-not-found").arg("dataset", name),
+        }));
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let mut body = vec![];
+    match dataset.write(&mut body, &mut connection).await {
+        Ok(_) => Ok(parquet(body, &dataset.filename())),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Runs (`POST`) a read-only query, for investigations without shell access to the server.
+///
+/// `POST /admin/query` with `{"sql": "SELECT ..."}` responds with the `columns` and `rows` of the
+/// query, at most `LABWHERE_ADMIN_QUERY_MAX_ROWS` of them (`truncated` is true if there were more).
+/// The connection is switched to `PRAGMA query_only` while the query runs, so it cannot write.
+/// Anything but a single `SELECT` statement is rejected with a 422, as is a query which runs for
+/// longer than `LABWHERE_ADMIN_QUERY_SECONDS`. Requires one of the configured admin tokens in the
+/// `X-Admin-Token` header, otherwise the response is 403.
+pub async fn query(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /admin/query endpoint");
+    let payload = match read_json::<AdminQuery>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let query = match ReadOnlyQuery::parse(&payload.sql) {
+        Ok(query) => query,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    // The query runs on its own task, so the time limit is lifted from the connection even if
+    // the client goes away first.
+    let result = tokio::spawn(async move {
+        let mut connection = acquire(&pool).await?;
+        query
+            .run(
+                CONFIG.admin_query_max_rows,
+                Duration::from_secs(CONFIG.admin_query_seconds),
+                &mut connection,
+            )
+            .await
+    })
+    .await;
+    match result {
+        Ok(Ok(result)) => Ok(json(StatusCode::OK, &result)),
+        Ok(Err(e)) => Ok(map_error(&*e)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Shows (`GET`) or forgets (`DELETE`) the latest requests this process handled, with their
+/// responses, to diagnose clients such as scanner integrations which send malformed payloads.
+///
+/// `GET /admin/debug/requests` responds with the recorded exchanges, newest first: the `method`,
+/// `path`, `request_headers` and `request_body` of each request, and the `status` and
+/// `response_body` of its response. Headers carrying credentials are never recorded, nor are the
+/// bodies of requests managing API keys or users or registering a device. The `user_code`,
+/// `swipe_code`, `api_key` and `key` fields of other JSON bodies are masked, and bodies are cut
+/// short after 64 KB. Responds with 404 unless `LABWHERE_DEBUG_RECORDER_CAPACITY` is set.
+/// `DELETE /admin/debug/requests` responds with 204. Requires one of the configured admin tokens
+/// in the `X-Admin-Token` header, otherwise the response is 403.
+pub async fn debug_requests(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+) -> ServiceResponse {
+    info!("Processing request for /admin/debug/requests endpoint");
+    if !RECORDED_REQUESTS.enabled() {
+        return Ok(map_error(&NotFoundError {
+            message: Message::new("admin-debug-recorder-disabled"),
+        }));
+    }
+    match *req.method() {
+        Method::GET => Ok(json(StatusCode::OK, &RECORDED_REQUESTS.recent())),
+        Method::DELETE => {
+            RECORDED_REQUESTS.clear();
+            Ok(status_only(StatusCode::NO_CONTENT))
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::DELETE])),
+    }
+}
+
+/// Shows (`GET`) how the latency objectives of routes are doing, so operations can alert on the
+/// degradation that matters rather than on raw latencies.
+///
+/// `GET /admin/slos` responds with the `window_seconds` the objectives are tracked over
+/// (`LABWHERE_SLO_WINDOW_SECONDS`) and, for each objective configured with
+/// `LABWHERE_LATENCY_SLOS`, its `route` and `objective`, the `requests` made within the window and
+/// how many were `slow`, the `compliance` (percentage in time), the `burn_rate` of its error budget
+/// and whether it is `met`. The burn rates are also exposed at `GET /metrics`. Requires one of the
+/// configured admin tokens in the `X-Admin-Token` header, otherwise the response is 403.
+pub async fn slos() -> ServiceResponse {
+    info!("Processing request for /admin/slos endpoint");
+    Ok(json(
+        StatusCode::OK,
+        &serde_json::json!({
+            "window_seconds": CONFIG.slo_window_seconds,
+            "slos": LATENCY_SLOS.statuses(),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::models::api_key::ApiKey;
+    use labwhere::models::api_key_usage::ApiKeyUsage;
+
+    #[tokio::test]
+    async fn test_api_key_usage() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let api_key = ApiKey::create("lims".to_string(), None, None, &mut conn)
+            .await
+            .unwrap();
+        ApiKeyUsage::record("lims", None, &mut conn).await.unwrap();
+        drop(conn);
+
+        let res = super::api_key_usage(pool.clone(), &api_key.uuid)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["api_key"], "lims");
+        assert_eq!(body["usage"][0]["requests"], 1);
+
+        let res = super::api_key_usage(pool, "lims").await.unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_usage_requires_admin() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/admin/api_keys/k-1/usage", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "Only an admin can do this"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_analytics_requires_admin() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/admin/analytics/scans.parquet", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_debug_requests_requires_admin() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/admin/debug/requests", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_slos_requires_admin() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/admin/slos", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_query_requires_admin() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(
+            request(
+                "POST",
+                "/admin/query",
+                br#"{"sql": "SELECT * FROM labwares"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::services::{
+    current_locale, error_response, json, map_error, method_not_allowed, query_params, read_json,
+    ServiceResponse,
+};
+use chrono::{DateTime, Duration, Utc};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::api_key::{ApiKey, DEFAULT_GRACE_HOURS};
+use labwhere::models::user::User;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The longest grace window of a rotated key, in hours.
+const MAX_GRACE_HOURS: i64 = 30 * 24;
+
+/// The payload for creating an API key.
+#[derive(Debug, Deserialize, Validate)]
+struct NewApiKey {
+    /// The unique name of the key, usually the integration it is for
+    #[validate(length(min = 1, message = "validation-blank"))]
+    name: String,
+    /// The login of the user the key belongs to
+    user: Option<String>,
+    /// When the key stops working, e.g. `2027-01-01T00:00:00Z`
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// The payload for changing when an API key expires.
+#[derive(Debug, Deserialize, Validate)]
+struct ApiKeyExpiry {
+    /// When the key stops working, or `null` for never
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Lists (`GET`) or creates (`POST`) the API keys managed through the API.
+///
+/// - `GET /admin/api_keys` responds with every key, ordered by name, including when it was
+///   `last_used_at`. Keys set with `LABWHERE_API_KEYS` are not listed.
+/// - `POST /admin/api_keys` with `{"name": "lims", "user": "jd12", "expires_at": "2027-01-01T00:00:00Z"}`
+///   creates a key and responds with 201 and the key, including the `key` to send in the
+///   `X-Api-Key` header. It is not shown again.
+///
+/// Requires one of the configured admin tokens in the `X-Admin-Token` header, otherwise the
+/// response is 403.
+pub async fn api_keys(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /admin/api_keys endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => match ApiKey::all(&mut connection).await {
+            Ok(api_keys) => Ok(json(StatusCode::OK, &api_keys)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::POST => {
+            let payload = match read_json::<NewApiKey>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let user_id = match payload.user {
+                Some(login) => match User::find_by_login(&login, &mut connection).await {
+                    Ok(user) => Some(user.id),
+                    Err(e) => return Ok(map_error(&e)),
+                },
+                None => None,
+            };
+            match ApiKey::create(payload.name, user_id, payload.expires_at, &mut connection).await {
+                Ok(api_key) => {
+                    let mut body = serde_json::to_value(&api_key).unwrap_or_default();
+                    body["key"] = api_key.key.into();
+                    Ok(json(StatusCode::CREATED, &body))
+                }
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Shows (`GET`) an API key or changes (`PUT`) when it expires.
+///
+/// - `GET /admin/api_keys/{uuid}` responds with the key.
+/// - `PUT /admin/api_keys/{uuid}` with `{"expires_at": "2027-01-01T00:00:00Z"}` (or `null` for
+///   never) responds with the key. A revoked key cannot be changed.
+///
+/// Requires one of the configured admin tokens in the `X-Admin-Token` header, otherwise the
+/// response is 403.
+pub async fn api_key(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /admin/api_keys/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => match ApiKey::find_by_uuid(uuid, &mut connection).await {
+            Ok(api_key) => Ok(json(StatusCode::OK, &api_key)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::PUT => {
+            let payload = match read_json::<ApiKeyExpiry>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            match ApiKey::set_expiry(uuid, payload.expires_at, &mut connection).await {
+                Ok(api_key) => Ok(json(StatusCode::OK, &api_key)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::PUT])),
+    }
+}
+
+/// Rotates (`POST`) an API key, e.g. when it may have leaked.
+///
+/// `POST /admin/api_keys/{uuid}/rotate` generates a new `key` and responds with the API key
+/// including it; it is not shown again. The old key keeps working for `?grace_hours=` (24 by
+/// default, at most 720) so the integration can be switched over. Requires one of the configured
+/// admin tokens in the `X-Admin-Token` header, otherwise the response is 403.
+pub async fn rotate(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/api_keys/{}/rotate endpoint",
+        uuid
+    );
+    let grace_hours = match query_params(&req).get("grace_hours") {
+        Some(hours) => match hours.parse::<i64>() {
+            Ok(hours) if (0..=MAX_GRACE_HOURS).contains(&hours) => hours,
+            _ => {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    Message::new("auth-api-key-invalid-grace")
+                        .arg("max", MAX_GRACE_HOURS)
+                        .localize(&current_locale()),
+                ))
+            }
+        },
+        None => DEFAULT_GRACE_HOURS,
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match ApiKey::rotate(uuid, Duration::hours(grace_hours), &mut connection).await {
+        Ok(api_key) => {
+            let mut body = serde_json::to_value(&api_key).unwrap_or_default();
+            body["key"] = api_key.key.into();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Revokes (`POST`) an API key for good.
+///
+/// `POST /admin/api_keys/{uuid}/revoke` responds with the key. Requests with it, or with its
+/// previous key, are refused from then on. Requires one of the configured admin tokens in the
+/// `X-Admin-Token` header, otherwise the response is 403.
+pub async fn revoke(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/api_keys/{}/revoke endpoint",
+        uuid
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match ApiKey::revoke(uuid, &mut connection).await {
+        Ok(api_key) => Ok(json(StatusCode::OK, &api_key)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request};
+    use labwhere::db::init_pool;
+
+    #[tokio::test]
+    async fn test_api_keys_require_admin() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/admin/api_keys", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+
+        let res = handle(
+            request("POST", "/admin/api_keys", br#"{"name": "lims"}"#),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+}
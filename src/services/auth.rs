@@ -0,0 +1,414 @@
+use crate::services::locations::ADMIN_TOKEN_HEADER;
+use crate::services::scan::ACT_AS_USER_HEADER;
+use crate::services::{current_locale, error_response, map_error};
+use chrono::{Duration, Utc};
+use http_body_util::combinators::BoxBody;
+use hyper::body::Bytes;
+use hyper::{Method, Request, Response, StatusCode};
+use labwhere::cache::REDIS;
+use labwhere::config::{AuthMode, Config};
+use labwhere::errors::ForbiddenError;
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::api_key::ApiKey;
+use labwhere::models::api_key_usage::ApiKeyUsage;
+use labwhere::models::user::User;
+use log::{error, warn};
+use sqlx::SqlitePool;
+
+/// The header carrying the API key of an integration.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// How long the count of the requests an API key made on a day is kept in Redis, long enough to
+/// outlast the day in any time zone.
+const QUOTA_COUNT_TTL: std::time::Duration = std::time::Duration::from_secs(2 * 24 * 60 * 60);
+
+/// What a request does, which decides whether it needs an API key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// Looks something up, e.g. where a labware is
+    Read,
+    /// Changes something, or reads something private
+    Write,
+}
+
+/// Classifies a request by its method and the segments of its path.
+///
+/// `GET` and `HEAD` requests are reads, except for the routes listed here which expose private
+/// details, and so are validations which change nothing. Everything else is a write.
+pub fn access(method: &Method, segments: &[&str]) -> Access {
+    match (method, segments) {
+        // Locations reveal their owners and contact emails, and their audits who did what
+        (_, ["locations", _]) | (_, ["locations", _, "audits"]) => Access::Write,
+        // Subscriptions reveal the email addresses and webhooks of the subscribers
+        (_, ["locations", _, "subscriptions", ..]) => Access::Write,
+        // Devices reveal the scan stations and what they have been scanning
+        (_, ["devices", ..]) => Access::Write,
+        // Checkouts and labwares in transit reveal who took what, and where to
+        (_, ["checkouts", ..]) | (_, ["labwares", "in_transit"]) => Access::Write,
+        // Saved searches reveal their owners; only their results are meant to be shared
+        (_, ["searches"]) | (_, ["searches", _]) => Access::Write,
+        // Shipments reveal who shipped them
+        (_, ["shipments", ..]) => Access::Write,
+        // The replication feed is every change made to the inventory, and by whom
+        (_, ["sync", ..]) => Access::Write,
+        (&Method::GET | &Method::HEAD, _) | (&Method::POST, ["validate", ..]) => Access::Read,
+        _ => Access::Write,
+    }
+}
+
+/// Identifies the integration a request comes from by the API key in its `X-Api-Key` header.
+///
+/// Returns the name of the key if it is one of the configured keys, or else a key managed at
+/// `/admin/api_keys` which has not expired or been revoked (recording that it was used), or else
+/// the login of the active user the key belongs to (see `/admin/users`).
+pub async fn api_key_name<B>(
+    config: &Config,
+    req: &Request<B>,
+    pool: &SqlitePool,
+) -> Option<String> {
+    let key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())?;
+    if let Some(name) = config.api_key_name(Some(key)) {
+        return Some(name.to_string());
+    }
+    let name = match acquire(pool).await {
+        Ok(mut connection) => match ApiKey::authenticate(key, &mut connection).await {
+            Ok(Some(api_key)) => Ok(Some(api_key.name)),
+            Ok(None) => User::authenticate(key, &mut connection)
+                .await
+                .map(|user| user.map(|user| user.login)),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    match name {
+        Ok(name) => name,
+        Err(e) => {
+            error!("Could not look up an API key: {}", e);
+            None
+        }
+    }
+}
+
+/// Applies the auth policy of the configured `AuthMode` to a request, given the name of its API
+/// key (see `api_key_name`).
+///
+/// Returns `None` if the request may go ahead, or else the 401 response to send instead. In
+/// `public-read-only` mode reads are open to anyone, and writes need a valid API key in the
+/// `X-Api-Key` header.
+pub fn authorize<B>(
+    config: &Config,
+    req: &Request<B>,
+    segments: &[&str],
+    api_key_name: Option<&str>,
+) -> Option<Response<BoxBody<Bytes, hyper::Error>>> {
+    if config.auth_mode == AuthMode::Open || access(req.method(), segments) == Access::Read {
+        return None;
+    }
+    let key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let message = match (key, api_key_name) {
+        (_, Some(_)) => return None,
+        (None, _) => Message::new("auth-api-key-required"),
+        (Some(_), None) => {
+            warn!(
+                "Rejected {} {} with an unknown API key",
+                req.method(),
+                req.uri().path()
+            );
+            Message::new("auth-api-key-invalid")
+        }
+    };
+    Some(error_response(
+        StatusCode::UNAUTHORIZED,
+        message.localize(&current_locale()),
+    ))
+}
+
+/// Whether only admins may make a request, by its method, the segments of its path and its
+/// headers: the `/admin` endpoints, the management of devices, pushing synced changes and
+/// resolving their conflicts, and scans as another user (with the `X-Act-As-User` header).
+pub fn admin_only<B>(req: &Request<B>, segments: &[&str]) -> bool {
+    req.headers().contains_key(ACT_AS_USER_HEADER)
+        || matches!(
+            (req.method(), segments),
+            (_, ["admin", ..])
+                | (&Method::POST, ["devices"])
+                | (&Method::POST, ["devices", _, "enable" | "disable"])
+                | (&Method::PUT, ["devices", _, "config"])
+                | (&Method::POST, ["sync", "changes"])
+                | (&Method::POST, ["sync", "conflicts", _, "resolve"])
+        )
+}
+
+/// Whether the request carries one of the configured admin tokens in its `X-Admin-Token` header.
+pub fn is_admin<B>(config: &Config, req: &Request<B>) -> bool {
+    let admin_token = req
+        .headers()
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok());
+    config.is_admin(admin_token)
+}
+
+/// The 403 response to a request which only admins may make.
+pub fn forbidden() -> Response<BoxBody<Bytes, hyper::Error>> {
+    map_error(&ForbiddenError {
+        message: Message::new("auth-admin-required"),
+    })
+}
+
+/// Counts a request made with an API key against the daily quota of the key, given its name (see
+/// `api_key_name`).
+///
+/// Returns `None` if the request may go ahead, or else the 429 response to send instead, with a
+/// `Retry-After` header counting down to midnight (UTC) when the quota resets. Requests without a
+/// valid API key are not counted. If the usage cannot be recorded, the request goes ahead.
+pub async fn meter(
+    config: &Config,
+    api_key_name: Option<&str>,
+    pool: &SqlitePool,
+) -> Option<Response<BoxBody<Bytes, hyper::Error>>> {
+    let name = api_key_name?;
+    let quota = config.api_key_quotas.get(name).copied();
+    let allowed = match acquire(pool).await {
+        Ok(mut connection) => match count_in_redis(name, quota).await {
+            // The quota was checked in Redis, so the database only records the usage: a quota of
+            // zero records the request as rejected.
+            Some(allowed) => ApiKeyUsage::record(name, (!allowed).then_some(0), &mut connection)
+                .await
+                .map(|_| allowed),
+            None => ApiKeyUsage::record(name, quota, &mut connection).await,
+        },
+        Err(e) => Err(e),
+    };
+    match allowed {
+        Ok(true) => None,
+        Ok(false) => {
+            warn!("Rejected request from {}: daily quota used up", name);
+            let mut response = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                Message::new("auth-quota-exceeded")
+                    .arg("name", name)
+                    .arg("quota", quota.unwrap_or_default())
+                    .localize(&current_locale()),
+            );
+            response
+                .headers_mut()
+                .insert(hyper::header::RETRY_AFTER, seconds_until_midnight().into());
+            Some(response)
+        }
+        Err(e) => {
+            error!("Could not record usage of API key {}: {}", name, e);
+            None
+        }
+    }
+}
+
+/// Counts a request made with an API key against its daily quota in Redis, which every replica
+/// shares, returning whether the request is within the quota.
+///
+/// Returns `None` if Redis is not configured, the key has no quota or the request could not be
+/// counted, in which case the quota is checked in the database.
+async fn count_in_redis(name: &str, quota: Option<u32>) -> Option<bool> {
+    let (redis, quota) = (REDIS.as_ref()?, quota?);
+    let counter = format!("quota:{}:{}", name, Utc::now().date_naive());
+    match redis.increment(&counter, QUOTA_COUNT_TTL).await {
+        Ok(count) => Some(count <= u64::from(quota)),
+        Err(e) => {
+            error!(
+                "Could not count the usage of API key {} in Redis: {}",
+                name, e
+            );
+            None
+        }
+    }
+}
+
+/// The number of seconds until the next midnight (UTC), when daily quotas reset.
+fn seconds_until_midnight() -> u32 {
+    let now = Utc::now();
+    let midnight = (now.date_naive() + Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap_or_default()
+        .and_utc();
+    (midnight - now).num_seconds().max(1) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::auth::*;
+    use crate::services::{mock_request as request, response_json, MockBody};
+    use labwhere::models::user::Role;
+    use std::collections::HashMap;
+
+    fn config() -> Config {
+        Config {
+            auth_mode: AuthMode::PublicReadOnly,
+            api_keys: HashMap::from([("lims".to_string(), "key1".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_access() {
+        assert_eq!(access(&Method::GET, &["labwares", "lw-1"]), Access::Read);
+        assert_eq!(access(&Method::POST, &["scan"]), Access::Write);
+        assert_eq!(
+            access(&Method::POST, &["validate", "barcodes"]),
+            Access::Read
+        );
+        assert_eq!(
+            access(&Method::DELETE, &["locations", "lw-freezer-1", "lock"]),
+            Access::Write
+        );
+        assert_eq!(
+            access(
+                &Method::GET,
+                &["locations", "lw-freezer-1", "subscriptions"]
+            ),
+            Access::Write
+        );
+        for segments in [
+            &["devices"][..],
+            &["devices", "d-1"],
+            &["checkouts"],
+            &["labwares", "in_transit"],
+            &["searches"],
+            &["searches", "s-1"],
+            &["locations", "lw-freezer-1"],
+            &["locations", "lw-freezer-1", "audits"],
+            &["shipments"],
+            &["shipments", "s-1"],
+            &["sync", "changes"],
+            &["sync", "conflicts"],
+        ] {
+            assert_eq!(
+                access(&Method::GET, segments),
+                Access::Write,
+                "{:?}",
+                segments
+            );
+        }
+        assert_eq!(
+            access(&Method::GET, &["searches", "s-1", "results"]),
+            Access::Read
+        );
+        assert_eq!(
+            access(&Method::GET, &["locations", "lw-freezer-1", "contents"]),
+            Access::Read
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let config = config();
+        let get = request("GET", "/labwares/lw-1", b"");
+        assert!(authorize(&config, &get, &["labwares", "lw-1"], None).is_none());
+
+        let post = request("POST", "/scan", b"");
+        let response = authorize(&config, &post, &["scan"], None).unwrap();
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response_json(response).await["errors"][0],
+            "An API key is required in the X-Api-Key header to make changes"
+        );
+
+        let post = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("X-Api-Key", "guess")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let response = authorize(&config, &post, &["scan"], None).unwrap();
+        assert_eq!(
+            response_json(response).await["errors"][0],
+            "The API key is not valid"
+        );
+
+        let post = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("X-Api-Key", "key1")
+            .body(MockBody::new(b""))
+            .unwrap();
+        assert!(authorize(&config, &post, &["scan"], Some("lims")).is_none());
+        assert!(authorize(&Config::default(), &post, &["scan"], None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_meter() {
+        let pool = labwhere::db::init_pool("sqlite::memory:").await.unwrap();
+        let config = Config {
+            api_key_quotas: HashMap::from([("lims".to_string(), 1)]),
+            ..config()
+        };
+        assert!(meter(&config, Some("lims"), &pool).await.is_none());
+        let response = meter(&config, Some("lims"), &pool).await.unwrap();
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().contains_key("retry-after"));
+        assert_eq!(
+            response_json(response).await["errors"][0],
+            "lims has used up its daily quota of 1 requests"
+        );
+
+        // Requests without a key are not metered
+        assert!(meter(&config, None, &pool).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_name() {
+        let pool = labwhere::db::init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let api_key = ApiKey::create("robot".to_string(), None, None, &mut conn)
+            .await
+            .unwrap();
+        let user = User::create("jd12".to_string(), Role::Technician, None, None, &mut conn)
+            .await
+            .unwrap();
+        let user_key = user.api_key.clone().unwrap();
+        drop(conn);
+        let with_key = |key: &str| {
+            hyper::Request::builder()
+                .method("POST")
+                .uri("/scan")
+                .header("X-Api-Key", key)
+                .body(MockBody::new(b""))
+                .unwrap()
+        };
+
+        let config = config();
+        assert_eq!(
+            api_key_name(&config, &with_key("key1"), &pool)
+                .await
+                .as_deref(),
+            Some("lims")
+        );
+        assert_eq!(
+            api_key_name(&config, &with_key(api_key.key.as_deref().unwrap()), &pool)
+                .await
+                .as_deref(),
+            Some("robot")
+        );
+        assert_eq!(
+            api_key_name(&config, &with_key(&user_key), &pool)
+                .await
+                .as_deref(),
+            Some("jd12")
+        );
+        let mut conn = pool.acquire().await.unwrap();
+        User::deactivate(&user.uuid, &mut conn).await.unwrap();
+        drop(conn);
+        assert_eq!(
+            api_key_name(&config, &with_key(&user_key), &pool).await,
+            None
+        );
+        assert_eq!(api_key_name(&config, &with_key("guess"), &pool).await, None);
+        let without_key = request("POST", "/scan", b"");
+        assert_eq!(api_key_name(&config, &without_key, &pool).await, None);
+    }
+}
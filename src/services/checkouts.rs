@@ -0,0 +1,183 @@
+use crate::services::{current_locale, json, map_error, query_params, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::config::CONFIG;
+use labwhere::metrics::acquire;
+use labwhere::models::checkout::Checkout;
+use log::info;
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+/// Lists (`GET`) the labwares which are checked out, soonest due first.
+///
+/// `GET /checkouts` responds with every open checkout; `?overdue=true` only lists those which
+/// should have been returned by now.
+pub async fn checkouts(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /checkouts endpoint");
+    let overdue = query_params(&req)
+        .get("overdue")
+        .is_some_and(|overdue| overdue == "true");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match Checkout::all_open(overdue, &mut connection).await {
+        Ok(checkouts) => {
+            let body: Vec<Value> = checkouts.iter().map(checkout_json).collect();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Lists (`GET`) the labwares which are in transit, the longest in transit first.
+///
+/// `GET /labwares/in_transit` responds with the open checkouts, each with who has the labware, how
+/// many `minutes_in_transit` it has been with them and whether that is `over_threshold`, i.e. longer
+/// than `transit_alert_minutes`, which raises an alert.
+pub async fn in_transit(pool: SqlitePool) -> ServiceResponse {
+    info!("Processing request for /labwares/in_transit endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match Checkout::in_transit(&mut connection).await {
+        Ok(checkouts) => {
+            let threshold = i64::from(CONFIG.transit_alert_minutes);
+            let body: Vec<Value> = checkouts
+                .iter()
+                .map(|checkout| {
+                    let minutes = checkout.minutes_in_transit();
+                    let mut body = checkout_json(checkout);
+                    body["minutes_in_transit"] = minutes.into();
+                    body["over_threshold"] = (threshold > 0 && minutes >= threshold).into();
+                    body
+                })
+                .collect();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Serializes a checkout along with where its labware is while it is open, e.g.
+/// `"location": "In transit with jane"`, in the locale of the request.
+pub(crate) fn checkout_json(checkout: &Checkout) -> Value {
+    let mut body = serde_json::to_value(checkout).unwrap_or_default();
+    if let Some(whereabouts) = checkout.whereabouts() {
+        body["location"] = whereabouts.localize(&current_locale()).into();
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use chrono::{Duration, Utc};
+    use labwhere::db::init_pool;
+    use labwhere::models::checkout::Checkout;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+
+    #[tokio::test]
+    async fn test_list_checkouts() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        for barcode in ["lw-1", "lw-2"] {
+            Labware::create(barcode.to_string(), location.id, &mut conn)
+                .await
+                .unwrap();
+        }
+        Checkout::checkout(
+            "lw-1".to_string(),
+            "jane".to_string(),
+            Some(Utc::now() - Duration::days(1)),
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Checkout::checkout(
+            "lw-2".to_string(),
+            "john".to_string(),
+            None,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        drop(conn);
+
+        let res = handle(request("GET", "/checkouts", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(body[0]["labware_barcode"], "lw-1");
+        assert_eq!(body[0]["location"], "In transit with jane");
+
+        let res = handle(request("GET", "/checkouts?overdue=true", b""), pool)
+            .await
+            .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["holder"], "jane");
+    }
+
+    #[tokio::test]
+    async fn test_in_transit() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        for (barcode, holder) in [("lw-1", "jane"), ("lw-2", "john")] {
+            Labware::create(barcode.to_string(), location.id, &mut conn)
+                .await
+                .unwrap();
+            Checkout::checkout(
+                barcode.to_string(),
+                holder.to_string(),
+                None,
+                None,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        sqlx::query(
+            "UPDATE checkouts SET checked_out_at = datetime('now', '-1 day') WHERE holder = 'john'",
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        drop(conn);
+
+        let res = handle(request("GET", "/labwares/in_transit", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["labware_barcode"], "lw-2");
+        assert_eq!(body[0]["location"], "In transit with john");
+        assert_eq!(body[0]["minutes_in_transit"], 24 * 60);
+        assert_eq!(body[0]["over_threshold"], true);
+        assert_eq!(body[1]["holder"], "jane");
+        assert_eq!(body[1]["minutes_in_transit"], 0);
+        assert_eq!(body[1]["over_threshold"], false);
+    }
+}
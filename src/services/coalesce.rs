@@ -0,0 +1,136 @@
+use hyper::body::Bytes;
+use hyper::StatusCode;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// A response which can be handed to several identical requests.
+pub(crate) type SharedResponse = (StatusCode, Bytes);
+
+/// A request submitted within the window, and its (possibly still pending) response.
+struct Submission {
+    submitted_at: Instant,
+    response: Arc<OnceCell<SharedResponse>>,
+}
+
+/// Coalesces identical requests submitted within a short window of each other.
+///
+/// The first request for a key does the work; any identical request arriving while it is in flight,
+/// or shortly after it finished, waits for and returns the same response instead of repeating the
+/// work. This is used to absorb e.g. double trigger pulls on scanners.
+pub(crate) struct Coalescer {
+    window: Duration,
+    submissions: Mutex<HashMap<u64, Submission>>,
+}
+
+impl Coalescer {
+    /// Create a new Coalescer. A window of zero disables coalescing.
+    pub(crate) fn new(window: Duration) -> Coalescer {
+        Coalescer {
+            window,
+            submissions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Hashes the parts of a request which make it identical to another request.
+    pub(crate) fn key<T: Hash>(parts: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        parts.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Runs `work` for the key, unless an identical request was submitted within the window, in
+    /// which case the response of that request is returned.
+    pub(crate) async fn run<F, Fut>(&self, key: u64, work: F) -> SharedResponse
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = SharedResponse>,
+    {
+        if self.window.is_zero() {
+            return work().await;
+        }
+        let response = {
+            let mut submissions = self.submissions.lock().unwrap();
+            submissions.retain(|_, submission| submission.submitted_at.elapsed() < self.window);
+            submissions
+                .entry(key)
+                .or_insert_with(|| Submission {
+                    submitted_at: Instant::now(),
+                    response: Arc::new(OnceCell::new()),
+                })
+                .response
+                .clone()
+        };
+        response.get_or_init(work).await.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::coalesce::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn work(calls: &AtomicUsize) -> SharedResponse {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        (StatusCode::OK, Bytes::from("done"))
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_identical_requests_run_once() {
+        let coalescer = Coalescer::new(Duration::from_secs(1));
+        let calls = AtomicUsize::new(0);
+        let key = Coalescer::key(&("jane", "lw-freezer-1", vec!["lw-1"]));
+
+        let (first, second) = tokio::join!(
+            coalescer.run(key, || work(&calls)),
+            coalescer.run(key, || work(&calls))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first, second);
+
+        // Identical requests shortly after are coalesced too
+        coalescer.run(key, || work(&calls)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_different_requests_are_not_coalesced() {
+        let coalescer = Coalescer::new(Duration::from_secs(1));
+        let calls = AtomicUsize::new(0);
+
+        coalescer
+            .run(Coalescer::key(&("jane", "lw-1")), || work(&calls))
+            .await;
+        coalescer
+            .run(Coalescer::key(&("john", "lw-1")), || work(&calls))
+            .await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_requests_after_the_window_are_not_coalesced() {
+        let coalescer = Coalescer::new(Duration::from_millis(30));
+        let calls = AtomicUsize::new(0);
+        let key = Coalescer::key(&"lw-1");
+
+        coalescer.run(key, || work(&calls)).await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        coalescer.run(key, || work(&calls)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let coalescer = Coalescer::new(Duration::ZERO);
+        let calls = AtomicUsize::new(0);
+        let key = Coalescer::key(&"lw-1");
+
+        coalescer.run(key, || work(&calls)).await;
+        coalescer.run(key, || work(&calls)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,43 @@
+use crate::services::{json, map_error};
+use http_body_util::combinators::BoxBody;
+use hyper::body::Bytes;
+use hyper::{Request, Response, StatusCode};
+use labwhere::models::confirmation::Confirmation;
+use serde::Serialize;
+use sqlx::SqliteConnection;
+
+/// The header carrying a confirmation token, replayed to perform a destructive operation.
+pub const CONFIRMATION_TOKEN_HEADER: &str = "x-confirmation-token";
+
+/// The confirmation token sent with a request, if any.
+pub(crate) fn confirmation_token<B>(req: &Request<B>) -> Option<String> {
+    req.headers()
+        .get(CONFIRMATION_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// Asks for a destructive operation to be confirmed instead of performing it.
+///
+/// Responds with 202, the summary of what the operation would do and a `confirmation_token`
+/// which has to be sent back in the `X-Confirmation-Token` header (before `expires_at`) with the
+/// same request to perform it. The token only confirms the action on the given subject, so if
+/// what the request applies to changes in the meantime the operation has to be confirmed again.
+pub(crate) async fn ask<T: Serialize, S: Serialize>(
+    action: &str,
+    subject: &T,
+    summary: &S,
+    connection: &mut SqliteConnection,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    match Confirmation::issue(action, subject, connection).await {
+        Ok(confirmation) => json(
+            StatusCode::ACCEPTED,
+            &serde_json::json!({
+                "summary": summary,
+                "confirmation_token": confirmation.token,
+                "expires_at": confirmation.expires_at,
+            }),
+        ),
+        Err(e) => map_error(&e),
+    }
+}
@@ -0,0 +1,148 @@
+use crate::services::{json, map_error, read_json, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::dead_letter::DeadLetter;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for re-driving a dead letter.
+#[derive(Debug, Deserialize, Validate)]
+struct RedriveDeadLetter {
+    /// Who re-drives the dead letter
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+}
+
+/// Lists (`GET`) the notifications to subscribers which could not be delivered and are no longer
+/// retried.
+///
+/// `GET /admin/dead_letters` responds with the dead letters which have not been re-driven, newest
+/// first, each with the `subscription_uuid`, `channel` and `target` it was for, the `audit_uuid`
+/// it was about, the `error` of the last attempt and the number of `attempts`. Requires one of the
+/// configured admin tokens in the `X-Admin-Token` header, otherwise the response is 403.
+pub async fn dead_letters(pool: SqlitePool) -> ServiceResponse {
+    info!("Processing request for /admin/dead_letters endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match DeadLetter::pending(&mut connection).await {
+        Ok(dead_letters) => Ok(json(StatusCode::OK, &dead_letters)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Re-drives (`POST`) a dead letter, queueing its notification for delivery again.
+///
+/// `POST /admin/dead_letters/{uuid}/redrive` with `{"user": "jane"}` gives the notification a
+/// fresh set of attempts, e.g. once the target of the subscription is fixed. Responds with the
+/// re-driven dead letter, with 404 if there is no such dead letter, or with 422 if it was already
+/// re-driven. Requires one of the configured admin tokens in the `X-Admin-Token` header, otherwise
+/// the response is 403.
+pub async fn redrive(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/dead_letters/{}/redrive endpoint",
+        uuid
+    );
+    let payload = match read_json::<RedriveDeadLetter>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match DeadLetter::redrive(uuid, payload.user, &mut connection).await {
+        Ok(dead_letter) => Ok(json(StatusCode::OK, &dead_letter)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::models::subscription::{
+        Subscription, SubscriptionChannel, MAX_DELIVERY_ATTEMPTS,
+    };
+
+    #[tokio::test]
+    async fn test_redrive_dead_letters() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Subscription::create(
+            location.id,
+            "jane".to_string(),
+            SubscriptionChannel::Email,
+            "jane@example.com".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Labware::create("lw-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        // No SMTP server is configured in tests, so every attempt fails
+        for _ in 0..MAX_DELIVERY_ATTEMPTS {
+            Subscription::deliver_pending(&mut conn).await.unwrap();
+        }
+        drop(conn);
+
+        let res = super::dead_letters(pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["target"], "jane@example.com");
+        assert_eq!(body[0]["attempts"], MAX_DELIVERY_ATTEMPTS);
+        let uuid = body[0]["uuid"].as_str().unwrap().to_string();
+
+        let res = super::redrive(
+            request("POST", "/", br#"{"user": "jane"}"#),
+            pool.clone(),
+            &uuid,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await["redriven_by"], "jane");
+
+        let res = super::redrive(
+            request("POST", "/", br#"{"user": "jane"}"#),
+            pool.clone(),
+            &uuid,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = super::redrive(
+            request("POST", "/", br#"{"user": "jane"}"#),
+            pool.clone(),
+            "x",
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 404);
+
+        let path = format!("/admin/dead_letters/{}/redrive", uuid);
+        let res = handle(request("POST", &path, br#"{"user": "jane"}"#), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+}
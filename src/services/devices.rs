@@ -0,0 +1,367 @@
+use crate::services::scan::device_key;
+use crate::services::{
+    current_locale, error_response, json, map_error, method_not_allowed, read_json, ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::device::Device;
+use labwhere::models::location::Location;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for registering a device.
+#[derive(Debug, Deserialize, Validate)]
+struct NewDevice {
+    /// The unique name of the device
+    #[validate(length(min = 1, message = "validation-blank"))]
+    name: String,
+    /// The barcode of the location the device is pinned to
+    location_barcode: Option<String>,
+}
+
+/// The payload for configuring a device.
+#[derive(Debug, Deserialize, Validate)]
+struct DeviceConfig {
+    /// The barcode of the location to pin the device to, or `null` to unpin it
+    #[validate(length(min = 1, message = "validation-blank"))]
+    location_barcode: Option<String>,
+}
+
+/// Lists (`GET`) or registers (`POST`) the scan stations.
+///
+/// - `GET /devices` responds with every device, ordered by name.
+/// - `POST /devices` with `{"name": "goods-in-1", "location_barcode": "lw-bench-1"}` registers a
+///   device pinned to the location and responds with 201 and the device, including the `api_key` it has to send in the `X-Device-Key` header. The
+///   key is not shown again. Requires one of the configured admin tokens in the `X-Admin-Token`
+///   header, otherwise the response is 403.
+pub async fn devices(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /devices endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => match Device::all(&mut connection).await {
+            Ok(devices) => Ok(json(StatusCode::OK, &devices)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::POST => {
+            let payload = match read_json::<NewDevice>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let location_id = match payload.location_barcode {
+                Some(barcode) => match Location::find_by_barcode(barcode, &mut connection).await {
+                    Ok(location) => Some(location.id),
+                    Err(e) => return Ok(map_error(&*e)),
+                },
+                None => None,
+            };
+            match Device::create(payload.name, location_id, &mut connection).await {
+                Ok(device) => {
+                    let mut body = serde_json::to_value(&device).unwrap_or_default();
+                    body["api_key"] = device.api_key.into();
+                    Ok(json(StatusCode::CREATED, &body))
+                }
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Shows (`GET`) a device along with its latest scans, to find out what a station has been doing.
+///
+/// `GET /devices/{uuid}` responds with the device and its `recent_scans`, newest first.
+pub async fn device(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /devices/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let device = match Device::find_by_uuid(uuid, &mut connection).await {
+        Ok(device) => device,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match device.recent_scans(&mut connection).await {
+        Ok(scans) => {
+            let mut body = serde_json::to_value(&device).unwrap_or_default();
+            body["recent_scans"] = serde_json::to_value(&scans).unwrap_or_default();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Enables or disables (`POST`) a device remotely.
+///
+/// `POST /devices/{uuid}/enable` and `POST /devices/{uuid}/disable` respond with the device. Scans
+/// from a disabled device are refused with 403. Requires one of the configured admin tokens in the
+/// `X-Admin-Token` header, otherwise the response is 403.
+pub async fn set_enabled(pool: SqlitePool, uuid: &str, enabled: bool) -> ServiceResponse {
+    info!(
+        "Processing request for /devices/{}/{} endpoint",
+        uuid,
+        if enabled { "enable" } else { "disable" }
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match Device::set_enabled(uuid, enabled, &mut connection).await {
+        Ok(device) => Ok(json(StatusCode::OK, &device)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Shows (`GET`) or changes (`PUT`) the configuration of a device.
+///
+/// - `GET /devices/{uuid}/config` responds with `{"location_barcode": "lw-bench-1", "enabled": true}`.
+/// - `PUT /devices/{uuid}/config` with `{"location_barcode": "lw-bench-1"}` pins the device to the
+///   location, so scans from it which omit the location barcode go there; `null` unpins it.
+///   Responds with the new configuration. Requires one of the configured admin tokens in the
+///   `X-Admin-Token` header, otherwise the response is 403.
+pub async fn config(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /devices/{}/config endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let device = match *req.method() {
+        Method::GET => match Device::find_by_uuid(uuid, &mut connection).await {
+            Ok(device) => device,
+            Err(e) => return Ok(map_error(&e)),
+        },
+        Method::PUT => {
+            let payload = match read_json::<DeviceConfig>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let location_id = match payload.location_barcode {
+                Some(barcode) => match Location::find_by_barcode(barcode, &mut connection).await {
+                    Ok(location) => Some(location.id),
+                    Err(e) => return Ok(map_error(&*e)),
+                },
+                None => None,
+            };
+            match Device::pin(uuid, location_id, &mut connection).await {
+                Ok(device) => device,
+                Err(e) => return Ok(map_error(&*e)),
+            }
+        }
+        _ => return Ok(method_not_allowed(&[Method::GET, Method::PUT])),
+    };
+    match device.pinned_location(&mut connection).await {
+        Ok(location) => Ok(json(
+            StatusCode::OK,
+            &serde_json::json!({
+                "location_barcode": location.and_then(|location| location.barcode),
+                "enabled": device.enabled,
+            }),
+        )),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Records (`POST`) that a device is alive.
+///
+/// `POST /devices/heartbeat` with the `X-Device-Key` header responds with the device. Responds with
+/// 403 if the device is disabled, so the station can tell its operator.
+pub async fn heartbeat(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /devices/heartbeat endpoint");
+    let Some(key) = device_key(&req) else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            Message::new("device-key-required").localize(&current_locale()),
+        ));
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match Device::authenticate(&key, &mut connection).await {
+        Ok(device) => Ok(json(StatusCode::OK, &device)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json, MockBody};
+    use labwhere::db::init_pool;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::models::secret_digest;
+    use sqlx::SqlitePool;
+
+    /// Registers a device directly, as no admin token is configured in tests.
+    async fn setup() -> (SqlitePool, String, String) {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Bench".to_string(), &mut conn)
+            .await
+            .unwrap();
+        Location::create("bench".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO devices (uuid, name, api_key_digest) VALUES ('d-1', 'goods-in-1', ?)",
+        )
+        .bind(secret_digest("key-1"))
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        (pool, "d-1".to_string(), "key-1".to_string())
+    }
+
+    fn with_device_key(
+        method: &str,
+        uri: &str,
+        key: &str,
+        body: &'static [u8],
+    ) -> hyper::Request<MockBody> {
+        hyper::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("X-Device-Key", key)
+            .body(MockBody::new(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_and_scans() {
+        let (pool, uuid, key) = setup().await;
+
+        let res = handle(request("POST", "/devices/heartbeat", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+        let res = handle(
+            with_device_key("POST", "/devices/heartbeat", &key, b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert!(response_json(res).await["last_seen_at"].is_string());
+
+        let res = handle(
+            with_device_key(
+                "POST",
+                "/scan",
+                &key,
+                br#"{"location_barcode": "lw-bench-1", "labware_barcodes": ["lw-dev-1"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+
+        let path: &'static str = Box::leak(format!("/devices/{}", uuid).into_boxed_str());
+        let res = handle(request("GET", path, b""), pool.clone())
+            .await
+            .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body["name"], "goods-in-1");
+        assert!(body.get("api_key").is_none());
+        assert_eq!(
+            body["recent_scans"][0]["message"],
+            "1 labwares scanned into bench"
+        );
+
+        let res = handle(
+            with_device_key(
+                "POST",
+                "/scan",
+                "guess",
+                br#"{"location_barcode": "lw-bench-1", "labware_barcodes": ["lw-dev-2"]}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_scan_into_pinned_location() {
+        let (pool, uuid, key) = setup().await;
+
+        let res = handle(
+            with_device_key(
+                "POST",
+                "/scan",
+                &key,
+                br#"{"labware_barcodes": ["lw-pin-1"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        assert_eq!(
+            response_json(res).await["fields"]["location_barcode"][0],
+            "is required"
+        );
+
+        sqlx::query("UPDATE devices SET location_id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let path: &'static str = Box::leak(format!("/devices/{}/config", uuid).into_boxed_str());
+        let res = handle(request("GET", path, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            response_json(res).await,
+            serde_json::json!({"location_barcode": "lw-bench-1", "enabled": true})
+        );
+
+        let scan = br#"{"labware_barcodes": ["lw-pin-2"]}"#;
+        let res = handle(with_device_key("POST", "/scan", &key, scan), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            response_json(res).await["message"],
+            "1 labwares scanned into bench"
+        );
+
+        let res = handle(request("PUT", path, br#"{"location_barcode": null}"#), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_register_and_disable_require_admin() {
+        let (pool, uuid, _) = setup().await;
+
+        let res = handle(
+            request("POST", "/devices", br#"{"name": "goods-in-2"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 403);
+
+        let path: &'static str = Box::leak(format!("/devices/{}/disable", uuid).into_boxed_str());
+        let res = handle(request("POST", path, b""), pool).await.unwrap();
+        assert_eq!(res.status(), 403);
+    }
+}
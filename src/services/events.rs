@@ -0,0 +1,78 @@
+use crate::services::{full, map_error, ServiceResponse};
+use hyper::{header, Response};
+use labwhere::config::CONFIG;
+use labwhere::errors::NotFoundError;
+use labwhere::i18n::Message;
+use labwhere::models::event_schema;
+use log::info;
+
+/// Shows (`GET`) the JSON Schema of a version of the payload of an event type, for consumers of
+/// the event log to validate the events they receive against.
+///
+/// `GET /events/schema/LabwareMoved/1` responds with the schema as `application/schema+json`, its
+/// `$id` being its URL on this server. Responds with 404 if there is no such type or version.
+pub async fn schema(event_type: &str, version: &str) -> ServiceResponse {
+    info!("Processing request for /events/schema endpoint");
+    let schema = version
+        .trim_start_matches('v')
+        .parse()
+        .ok()
+        .and_then(|version| event_schema::find(event_type, version));
+    let Some(schema) = schema else {
+        return Ok(map_error(&NotFoundError {
+            message: Message::new("event-schema-not-found")
+                .arg("type", event_type)
+                .arg("version", version),
+        }));
+    };
+    // The schemas are stored with their path as `$id`
+    let id = format!("/events/schema/{}/{}", schema.event_type, schema.version);
+    let body = schema.schema.replacen(
+        &format!("\"{}\"", id),
+        &format!("\"{}{}\"", CONFIG.base_url, id),
+        1,
+    );
+    let mut response = Response::new(full(body));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/schema+json"),
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request, response_json};
+    use labwhere::db::init_pool;
+
+    #[tokio::test]
+    async fn test_schema() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let res = handle(
+            mock_request("GET", "/events/schema/LabwareMoved/1", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "application/schema+json");
+        let body = response_json(res).await;
+        assert_eq!(body["title"], "LabwareMoved");
+        assert!(body["$id"]
+            .as_str()
+            .unwrap()
+            .ends_with("/events/schema/LabwareMoved/1"));
+        assert_eq!(body["required"][3], "previous_location_id");
+
+        for path in [
+            "/events/schema/LabwareMoved/2",
+            "/events/schema/LabwareLost/1",
+            "/events/schema/LabwareMoved/latest",
+        ] {
+            let res = handle(mock_request("GET", path, b""), pool.clone())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 404);
+        }
+    }
+}
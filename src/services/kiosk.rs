@@ -0,0 +1,87 @@
+use crate::services::{current_locale, html, ServiceResponse};
+use labwhere::i18n::Message;
+use log::info;
+
+/// The page served to scan stations, with `{{key}}` placeholders for the catalog messages.
+const TEMPLATE: &str = include_str!("kiosk.html");
+
+/// The catalog messages shown on the page.
+const LABELS: [&str; 6] = [
+    "kiosk-title",
+    "kiosk-location",
+    "kiosk-location-hint",
+    "kiosk-labware",
+    "kiosk-recent",
+    "kiosk-offline",
+];
+
+/// Serves (`GET`) the scan page for touchscreen stations which only run a browser.
+///
+/// `GET /kiosk` responds with an HTML page with a big input box that scans each labware barcode
+/// into the location entered above it as soon as Enter is pressed, which is what keyboard wedge
+/// scanners send after a barcode. Each scan flashes green or red with the result and is added to
+/// a list of recent scans. Open `/kiosk?device_key=...` once on a registered station to send its
+/// key with every scan; a pinned station can leave the location empty. `/kiosk?labware=...` fills
+/// in a labware barcode to register, to scan once the location is entered.
+pub async fn kiosk() -> ServiceResponse {
+    info!("Processing request for /kiosk endpoint");
+    Ok(html(render()))
+}
+
+/// Fills the labels of the page in the locale of the request.
+fn render() -> String {
+    let locale = current_locale();
+    LABELS.iter().fold(
+        TEMPLATE.replace("{{lang}}", &locale.to_string()),
+        |page, key| {
+            page.replace(
+                &format!("{{{{{}}}}}", key),
+                &escape(&Message::new(*key).localize(&locale)),
+            )
+        },
+    )
+}
+
+/// Escapes text for use in HTML content and attributes.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::kiosk::*;
+    use crate::services::{handle, MockBody};
+    use http_body_util::BodyExt;
+    use labwhere::db::init_pool;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape(r#"<b>"Tom" & 'Jerry'</b>"#),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kiosk() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let req = hyper::Request::builder()
+            .method("GET")
+            .uri("/kiosk")
+            .header("Accept-Language", "es")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let res = handle(req, pool).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "text/html; charset=utf-8");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains(r#"<html lang="es">"#));
+        assert!(page.contains("<title>Estación de escaneo</title>"));
+        assert!(!page.contains("{{"));
+    }
+}
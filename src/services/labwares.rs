@@ -0,0 +1,874 @@
+use crate::services::checkouts::checkout_json;
+use crate::services::confirmations::{ask, confirmation_token};
+use crate::services::scan::lock_token;
+use crate::services::{
+    error_response, json, json_stream, map_error, method_not_allowed, read_json, status_only,
+    time_param, ServiceResponse,
+};
+use chrono::{DateTime, Utc};
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, Response, StatusCode};
+use labwhere::errors::NotFoundError;
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::checkout::Checkout;
+use labwhere::models::confirmation::Confirmation;
+use labwhere::models::event::State;
+use labwhere::models::labware::{Labware, LabwareStatus};
+use labwhere::models::labware_barcode::LabwareBarcode;
+use labwhere::models::location::{Location, UNKNOWN_LOCATION_NAME};
+use labwhere::storage::{SqliteStorage, Storage};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use std::error::Error;
+use validator::Validate;
+
+/// The action confirmation tokens are issued for when exhausting labwares.
+const EXHAUST: &str = "exhaust";
+
+/// The payload for adding a barcode to a labware.
+#[derive(Debug, Deserialize, Validate)]
+struct NewLabwareBarcode {
+    /// The barcode to add
+    #[validate(length(min = 1, message = "validation-blank"))]
+    barcode: String,
+    /// Whether the barcode should become the primary barcode of the labware
+    #[serde(default)]
+    primary: bool,
+}
+
+/// The payload for checking a labware out.
+#[derive(Debug, Deserialize, Validate)]
+struct NewCheckout {
+    /// Who takes the labware
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+    /// When the labware should be returned, if it has to be e.g. `2024-05-01T17:00:00Z`
+    due_at: Option<DateTime<Utc>>,
+}
+
+/// The payload for checking a labware in.
+#[derive(Debug, Deserialize, Validate)]
+struct NewCheckin {
+    /// The barcode of the location the labware is put in
+    #[validate(length(min = 1, message = "validation-blank"))]
+    location_barcode: String,
+}
+
+/// The payload for changing the status of a labware.
+#[derive(Debug, Deserialize, Validate)]
+struct NewLabwareStatus {
+    /// The status the labware is in now e.g. `shipped-out`
+    status: LabwareStatus,
+    /// Why the status changed
+    #[validate(length(min = 1, message = "validation-blank"))]
+    reason: String,
+}
+
+/// The payload for exhausting labwares.
+#[derive(Debug, Deserialize, Validate)]
+struct ExhaustLabwares {
+    /// The barcodes of the labwares
+    #[validate(length(min = 1, message = "validation-empty"))]
+    barcodes: Vec<String>,
+}
+
+/// What exhausting labwares would do, or did.
+#[derive(Debug, Serialize)]
+struct ExhaustSummary {
+    /// The number of labwares
+    count: usize,
+    /// The labwares, with the names of the locations they are taken out of
+    labwares: Vec<ExhaustedLabware>,
+    /// The barcodes which were not found, and are left alone
+    not_found: Vec<String>,
+}
+
+/// A labware in an `ExhaustSummary`.
+#[derive(Debug, Serialize)]
+struct ExhaustedLabware {
+    barcode: String,
+    location: String,
+}
+
+/// Lists (`GET`) every labware, oldest first.
+///
+/// `GET /labwares` streams the labwares as they are read from the database, so the response starts
+/// straight away and the server's memory stays flat however big the inventory is.
+pub async fn labwares(pool: SqlitePool) -> ServiceResponse {
+    info!("Processing request for /labwares endpoint");
+    match acquire(&pool).await {
+        Ok(connection) => Ok(json_stream(connection, Labware::all)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Shows (`GET`) where a labware is.
+///
+/// `GET /labwares/{barcode}` responds with the labware and its `location`. The labware can be
+/// referred to by any of its barcodes.
+pub async fn labware(pool: SqlitePool, barcode: &str) -> ServiceResponse {
+    info!("Processing request for /labwares/{} endpoint", barcode);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match labware_body(&mut SqliteStorage::new(&mut connection), barcode).await {
+        Ok(body) => Ok(json(StatusCode::OK, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// The body of the response showing a labware, with the `location` it is in.
+async fn labware_body(
+    storage: &mut impl Storage,
+    barcode: &str,
+) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    let labware = storage.find_labware_by_barcode(barcode).await?;
+    let location = match storage.find_location(labware.location_id).await {
+        Ok(location) => Some(location),
+        Err(_) => storage.unknown_location().await.ok(),
+    };
+    let mut body = serde_json::to_value(&labware).unwrap_or_default();
+    body["location"] = serde_json::to_value(location).unwrap_or_default();
+    Ok(body)
+}
+
+/// Shows (`GET`) where a labware was at a point in time, from the event log.
+///
+/// `GET /labwares/{barcode}/location?at=2026-03-03` responds with the `barcode`, the time asked
+/// about (`at`) and the `location` the labware was in then, or `null` if it was not registered yet
+/// or had been exhausted by then. `at` is an RFC 3339 timestamp, or a date for the end of that day
+/// (UTC), and defaults to now. Labwares which have since been exhausted can be asked about too.
+pub async fn location_at(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /labwares/{}/location endpoint",
+        barcode
+    );
+    let at = match time_param(&req, "at") {
+        Ok(at) => at,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match State::labware_location(barcode, at, &mut connection).await {
+        Ok(location) => Ok(json(
+            StatusCode::OK,
+            &serde_json::json!({ "barcode": barcode, "at": at, "location": location }),
+        )),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Lists (`GET`) or adds (`POST`) the barcodes of a labware.
+///
+/// The labware can be referred to by any of its barcodes.
+/// - `GET /labwares/{barcode}/barcodes` responds with the barcodes of the labware, primary first.
+/// - `POST /labwares/{barcode}/barcodes` with `{"barcode": "2D-1234", "primary": false}` adds a
+///   barcode and responds with 201.
+pub async fn barcodes(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /labwares/{}/barcodes endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let labware = match Labware::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(labware) => labware,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match *req.method() {
+        Method::GET => match LabwareBarcode::for_labware(labware.id, &mut connection).await {
+            Ok(barcodes) => Ok(json(StatusCode::OK, &barcodes)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::POST => {
+            let payload = match read_json::<NewLabwareBarcode>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            match LabwareBarcode::create(
+                labware.id,
+                payload.barcode,
+                payload.primary,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(labware_barcode) => Ok(json(StatusCode::CREATED, &labware_barcode)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Removes (`DELETE`) an alias from a labware.
+///
+/// `DELETE /labwares/{barcode}/barcodes/{alias}` responds with 204. The primary barcode cannot be
+/// removed.
+pub async fn barcode(pool: SqlitePool, barcode: &str, alias: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /labwares/{}/barcodes/{} endpoint",
+        barcode, alias
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let labware = match Labware::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(labware) => labware,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match LabwareBarcode::delete(labware.id, alias.to_string(), &mut connection).await {
+        Ok(()) => Ok(status_only(StatusCode::NO_CONTENT)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Checks (`POST`) a labware out to a user.
+///
+/// `POST /labwares/{barcode}/checkout` with `{"user": "jane", "due_at": "2024-05-01T17:00:00Z"}`
+/// responds with 201 and the checkout, whose location is `In transit with jane` until the labware
+/// is checked in. If the labware's location is locked, the lock's token has to be sent in the
+/// `X-Lock-Token` header.
+pub async fn checkout(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /labwares/{}/checkout endpoint",
+        barcode
+    );
+    let lock_token = lock_token(&req);
+    let payload = match read_json::<NewCheckout>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match Checkout::checkout(
+        barcode.to_string(),
+        payload.user,
+        payload.due_at,
+        lock_token.as_deref(),
+        &mut connection,
+    )
+    .await
+    {
+        Ok(checkout) => Ok(json(StatusCode::CREATED, &checkout_json(&checkout))),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Checks (`POST`) a checked out labware in at a location.
+///
+/// `POST /labwares/{barcode}/checkin` with `{"location_barcode": "lw-freezer-1"}` responds with the
+/// closed checkout, or 422 if the labware is not checked out.
+pub async fn checkin(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /labwares/{}/checkin endpoint",
+        barcode
+    );
+    let lock_token = lock_token(&req);
+    let payload = match read_json::<NewCheckin>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match Checkout::checkin(
+        barcode.to_string(),
+        payload.location_barcode,
+        lock_token.as_deref(),
+        &mut connection,
+    )
+    .await
+    {
+        Ok(checkout) => Ok(json(StatusCode::OK, &checkout_json(&checkout))),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Shows (`GET`) or changes (`PUT`) the status of a labware.
+///
+/// The labware can be referred to by its primary barcode whatever its status, so labwares which
+/// are no longer active can be looked up too.
+/// - `GET /labwares/{barcode}/status` responds with the labware, including its `status` and the
+///   `status_reason`.
+/// - `PUT /labwares/{barcode}/status` with `{"status": "shipped-out", "reason": "Sent to Hinxton"}`
+///   responds with the labware, or 422 if it cannot go from its status to the new one (a labware
+///   which was shipped out can be made `active` again, exhausted and destroyed labwares cannot).
+///   If the labware's location is locked, the lock's token has to be sent in the `X-Lock-Token`
+///   header.
+pub async fn status(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /labwares/{}/status endpoint",
+        barcode
+    );
+    match *req.method() {
+        Method::GET => {
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match Labware::find_with_history(barcode, &mut connection).await {
+                Ok(labware) => Ok(json(StatusCode::OK, &labware)),
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        Method::PUT => {
+            let lock_token = lock_token(&req);
+            let payload = match read_json::<NewLabwareStatus>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            let labware = match Labware::find_with_history(barcode, &mut connection).await {
+                Ok(labware) => labware,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match labware
+                .change_status(
+                    payload.status,
+                    Some(payload.reason.trim()),
+                    lock_token.as_deref(),
+                    &mut connection,
+                )
+                .await
+            {
+                Ok(labware) => Ok(json(StatusCode::OK, &labware)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::PUT])),
+    }
+}
+
+/// Exhausts (`POST`) labwares which are used up, once confirmed.
+///
+/// `POST /labwares/exhaust` with `{"barcodes": ["lw-1", "lw-2"]}` responds with 202, a summary of
+/// the labwares which would be exhausted and a confirmation token. Sending the same request with
+/// the token in the `X-Confirmation-Token` header exhausts them and responds with the summary.
+/// Barcodes which are not found are listed in the summary and left alone. If the location of a
+/// labware is locked, the lock's token has to be sent in the `X-Lock-Token` header.
+pub async fn exhaust(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /labwares/exhaust endpoint");
+    let lock_token = lock_token(&req);
+    let confirmation_token = confirmation_token(&req);
+    let payload = match read_json::<ExhaustLabwares>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let mut labwares: Vec<Labware> = vec![];
+    let mut not_found = vec![];
+    for barcode in payload.barcodes {
+        match Labware::find_by_barcode(barcode.clone(), &mut connection).await {
+            Ok(labware) if labwares.iter().any(|l| l.id == labware.id) => {}
+            Ok(labware) => labwares.push(labware),
+            Err(_) => not_found.push(barcode),
+        }
+    }
+    if labwares.is_empty() {
+        return Ok(map_error(&NotFoundError {
+            message: Message::new("labware-none-found"),
+        }));
+    }
+    Ok(exhaust_confirmed(
+        labwares,
+        not_found,
+        confirmation_token,
+        lock_token,
+        &mut connection,
+    )
+    .await)
+}
+
+/// Asks for exhausting labwares to be confirmed, or exhausts them if a confirmation token is given.
+///
+/// The token confirms exhausting exactly these labwares, so it is refused if the labwares have
+/// changed since it was issued (e.g. another labware was put in the location being emptied).
+pub(crate) async fn exhaust_confirmed(
+    labwares: Vec<Labware>,
+    not_found: Vec<String>,
+    confirmation_token: Option<String>,
+    lock_token: Option<String>,
+    connection: &mut SqliteConnection,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut subject: Vec<u32> = labwares.iter().map(|labware| labware.id).collect();
+    subject.sort_unstable();
+    let summary = exhaust_summary(&labwares, not_found, &mut *connection).await;
+    let Some(confirmation_token) = confirmation_token else {
+        return ask(EXHAUST, &subject, &summary, connection).await;
+    };
+    let exhausted: Result<(), Box<dyn Error + Send + Sync>> = async {
+        let mut transaction = connection.begin().await?;
+        Confirmation::redeem(&confirmation_token, EXHAUST, &subject, &mut transaction).await?;
+        Labware::exhaust(&labwares, lock_token.as_deref(), &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+    .await;
+    match exhausted {
+        Ok(()) => json(StatusCode::OK, &summary),
+        Err(e) => map_error(&*e),
+    }
+}
+
+/// Summarises exhausting labwares, naming the locations they are taken out of.
+async fn exhaust_summary(
+    labwares: &[Labware],
+    not_found: Vec<String>,
+    connection: &mut SqliteConnection,
+) -> ExhaustSummary {
+    let mut names: HashMap<u32, String> = HashMap::new();
+    let mut exhausted = vec![];
+    for labware in labwares {
+        let location = match names.get(&labware.location_id) {
+            Some(name) => name.clone(),
+            None => {
+                let name = match Location::find(labware.location_id, &mut *connection).await {
+                    Ok(location) => location.name,
+                    Err(_) => UNKNOWN_LOCATION_NAME.to_string(),
+                };
+                names.insert(labware.location_id, name.clone());
+                name
+            }
+        };
+        exhausted.push(ExhaustedLabware {
+            barcode: labware.barcode.clone(),
+            location,
+        });
+    }
+    ExhaustSummary {
+        count: exhausted.len(),
+        labwares: exhausted,
+        not_found,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::labwares::labware_body;
+    use crate::services::{handle, mock_request as request, response_json, MockBody};
+    use http_body_util::BodyExt;
+    use labwhere::db::init_pool;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::storage::{InMemoryStorage, LabwareStore, LocationStore};
+    use sqlx::SqlitePool;
+
+    async fn setup() -> SqlitePool {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_labware() {
+        let pool = setup().await;
+
+        let res = handle(request("GET", "/labwares/lw-1", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["barcode"], "lw-1");
+        assert_eq!(body["location"]["name"], "location1");
+
+        let res = handle(request("GET", "/labwares/lw-404", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_labware_body() {
+        let mut storage = InMemoryStorage::new();
+        let freezer = storage.create_location("freezer1", 1, None).await.unwrap();
+        storage.create_labware("lw-1", freezer.id).await.unwrap();
+
+        let body = labware_body(&mut storage, "lw-1").await.unwrap();
+        assert_eq!(body["barcode"], "lw-1");
+        assert_eq!(body["location"]["name"], "freezer1");
+        assert!(labware_body(&mut storage, "lw-404").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_location_at() {
+        let pool = setup().await;
+
+        let res = handle(request("GET", "/labwares/lw-1/location", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["barcode"], "lw-1");
+        assert_eq!(body["location"]["name"], "location1");
+
+        let res = handle(
+            request("GET", "/labwares/lw-1/location?at=2020-03-03", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["at"], "2020-03-03T23:59:59Z");
+        assert_eq!(body["location"], serde_json::Value::Null);
+
+        let res = handle(
+            request("GET", "/labwares/lw-1/location?at=march", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = handle(request("GET", "/labwares/lw-404/location", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_labwares() {
+        let pool = setup().await;
+        sqlx::query(
+            "WITH RECURSIVE n(i) AS (SELECT 2 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+             INSERT INTO labwares (uuid, barcode, location_id) SELECT 'uuid-' || i, 'lw-' || i, 1 FROM n",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let res = handle(request("GET", "/labwares", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        let mut body = res.into_body();
+        let mut chunks = vec![];
+        while let Some(frame) = body.frame().await {
+            chunks.push(frame.unwrap().into_data().unwrap());
+        }
+        // The labwares are sent in several chunks rather than all at once
+        assert!(chunks.len() > 1);
+        let labwares: serde_json::Value = serde_json::from_slice(&chunks.concat()).unwrap();
+        assert_eq!(labwares.as_array().unwrap().len(), 2000);
+        assert_eq!(labwares[0]["barcode"], "lw-1");
+        assert_eq!(labwares[1999]["barcode"], "lw-2000");
+
+        let res = handle(request("POST", "/labwares", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 405);
+        assert_eq!(res.headers()["allow"], "GET");
+    }
+
+    #[tokio::test]
+    async fn test_add_and_list_barcodes() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("POST", "/labwares/lw-1/barcodes", br#"{"barcode": "2D-1"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+
+        let res = handle(request("GET", "/labwares/2D-1/barcodes", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["barcode"], "lw-1");
+        assert_eq!(body[0]["is_primary"], true);
+        assert_eq!(body[1]["barcode"], "2D-1");
+        assert_eq!(body[1]["is_primary"], false);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_and_checkin() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("POST", "/labwares/lw-1/checkout", br#"{"user": "jane"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let body = response_json(res).await;
+        assert_eq!(body["holder"], "jane");
+        assert_eq!(body["location"], "In transit with jane");
+
+        let res = handle(
+            request("POST", "/labwares/lw-1/checkout", br#"{"user": "john"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request(
+                "POST",
+                "/labwares/lw-1/checkin",
+                br#"{"location_barcode": "lw-location1-1"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert!(body["checked_in_at"].is_string());
+        assert_eq!(body.get("location"), None);
+
+        let res = handle(
+            request(
+                "POST",
+                "/labwares/lw-1/checkin",
+                br#"{"location_barcode": "lw-location1-1"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_with_invalid_payload() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("POST", "/labwares/lw-1/checkout", br#"{"user": ""}"#),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let body = response_json(res).await;
+        assert_eq!(body["errors"][0], "User can't be blank");
+    }
+
+    #[tokio::test]
+    async fn test_add_duplicate_barcode() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("POST", "/labwares/lw-1/barcodes", br#"{"barcode": "lw-1"}"#),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_add_barcode_with_invalid_payload() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("POST", "/labwares/lw-1/barcodes", br#"{"code": "2D-1"}"#),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_barcodes_for_unknown_labware() {
+        let pool = setup().await;
+
+        let res = handle(request("GET", "/labwares/lw-2/barcodes", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_remove_barcode() {
+        let pool = setup().await;
+        handle(
+            request("POST", "/labwares/lw-1/barcodes", br#"{"barcode": "2D-1"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+
+        let res = handle(
+            request("DELETE", "/labwares/lw-1/barcodes/2D-1", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 204);
+
+        let res = handle(request("DELETE", "/labwares/lw-1/barcodes/lw-1", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_exhaust() {
+        let pool = setup().await;
+        let body: &[u8] = br#"{"barcodes": ["lw-1", "LW-1", "lw-404"]}"#;
+
+        let res = handle(request("POST", "/labwares/exhaust", body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 202);
+        let body_json = response_json(res).await;
+        assert_eq!(body_json["summary"]["count"], 1);
+        assert_eq!(body_json["summary"]["labwares"][0]["barcode"], "lw-1");
+        assert_eq!(body_json["summary"]["labwares"][0]["location"], "location1");
+        assert_eq!(body_json["summary"]["not_found"][0], "lw-404");
+        let token = body_json["confirmation_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // Nothing is exhausted until the token is replayed
+        let res = handle(request("GET", "/labwares/lw-1", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+
+        let confirmed = |token: &str| {
+            hyper::Request::builder()
+                .method("POST")
+                .uri("/labwares/exhaust")
+                .header("X-Confirmation-Token", token)
+                .body(MockBody::new(
+                    br#"{"barcodes": ["lw-1", "LW-1", "lw-404"]}"#,
+                ))
+                .unwrap()
+        };
+        let res = handle(confirmed(&token), pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await["count"], 1);
+
+        let res = handle(request("GET", "/labwares/lw-1", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+
+        let res = handle(request("POST", "/labwares/exhaust", body), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let pool = setup().await;
+        let shipped_out: &[u8] = br#"{"status": "shipped-out", "reason": "Sent to Hinxton"}"#;
+
+        let res = handle(
+            request("PUT", "/labwares/lw-1/status", shipped_out),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body_json = response_json(res).await;
+        assert_eq!(body_json["status"], "shipped-out");
+        assert_eq!(body_json["status_reason"], "Sent to Hinxton");
+
+        // Only active labwares are found by their barcode, but the history can still be looked up
+        let res = handle(request("GET", "/labwares/lw-1", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+        let res = handle(request("GET", "/labwares/lw-1/status", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await["status"], "shipped-out");
+
+        let back: &[u8] = br#"{"status": "active", "reason": "Came back"}"#;
+        let res = handle(request("PUT", "/labwares/lw-1/status", back), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let res = handle(request("GET", "/labwares/lw-1", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+
+        let destroyed: &[u8] = br#"{"status": "destroyed", "reason": "Dropped"}"#;
+        handle(
+            request("PUT", "/labwares/lw-1/status", destroyed),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let res = handle(request("PUT", "/labwares/lw-1/status", back), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "Labware lw-1 cannot go from destroyed to active"
+        );
+
+        let no_reason: &[u8] = br#"{"status": "exhausted", "reason": ""}"#;
+        let res = handle(request("PUT", "/labwares/lw-1/status", no_reason), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+}
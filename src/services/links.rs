@@ -0,0 +1,165 @@
+use crate::services::kiosk::escape;
+use crate::services::{current_locale, html, map_error, status_only, ServiceResponse};
+use hyper::{header, StatusCode};
+use labwhere::i18n::Message;
+use labwhere::labels::PATH_SEGMENT;
+use labwhere::metrics::acquire;
+use labwhere::models::labware::Labware;
+use labwhere::models::location::Location;
+use log::info;
+use percent_encoding::utf8_percent_encode;
+use sqlx::SqlitePool;
+
+/// The page of a barcode which is not known, with `{{key}}` placeholders.
+const NOT_FOUND_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="{{lang}}">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+</head>
+<body>
+<main>
+  <h1>{{title}}</h1>
+  <p>{{message}}</p>
+  <p><a href="/kiosk?labware={{barcode}}">{{register}}</a></p>
+</main>
+</body>
+</html>
+"#;
+
+/// Resolves (`GET`) a short link, which QR code labels encode whatever the barcode is of.
+///
+/// `GET /b/{barcode}` redirects to `/locations/{barcode}` if a location has the barcode (or had it,
+/// as an alias), and otherwise to `/labwares/{barcode}` if a labware has it. The barcode may be in
+/// any form a scanner sends, e.g. with a site prefix or a check digit, and the redirect is to the
+/// stored barcode, percent-encoded. If nothing has the barcode, the response is a 404 HTML page offering to
+/// register it as a labware at the scan station (`/kiosk?labware={barcode}`).
+pub async fn resolve(pool: SqlitePool, barcode: &str) -> ServiceResponse {
+    info!("Processing request for /b/{} endpoint", barcode);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let target = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => Some(format!(
+            "/locations/{}",
+            utf8_percent_encode(location.barcode.as_deref().unwrap_or(barcode), PATH_SEGMENT)
+        )),
+        Err(_) => Labware::find_by_barcode(barcode.to_string(), &mut connection)
+            .await
+            .ok()
+            .map(|labware| {
+                format!(
+                    "/labwares/{}",
+                    utf8_percent_encode(&labware.barcode, PATH_SEGMENT)
+                )
+            }),
+    };
+    let Some(target) = target else {
+        let mut response = html(not_found_page(barcode));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    };
+    let mut response = status_only(StatusCode::FOUND);
+    match header::HeaderValue::from_str(&target) {
+        Ok(location) => {
+            response.headers_mut().insert(header::LOCATION, location);
+            Ok(response)
+        }
+        Err(_) => Ok(status_only(StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Fills the page of an unknown barcode in the locale of the request.
+fn not_found_page(barcode: &str) -> String {
+    let locale = current_locale();
+    NOT_FOUND_TEMPLATE
+        .replace("{{lang}}", &locale.to_string())
+        .replace(
+            "{{title}}",
+            &escape(&Message::new("link-not-found-title").localize(&locale)),
+        )
+        .replace(
+            "{{message}}",
+            &escape(
+                &Message::new("link-not-found")
+                    .arg("barcode", barcode)
+                    .localize(&locale),
+            ),
+        )
+        .replace(
+            "{{register}}",
+            &escape(&Message::new("link-register").localize(&locale)),
+        )
+        .replace(
+            "{{barcode}}",
+            &escape(&form_urlencoded::byte_serialize(barcode.as_bytes()).collect::<String>()),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request};
+    use http_body_util::BodyExt;
+    use labwhere::db::init_pool;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-link-1".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("plate 7/b".to_string(), location.id, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        let barcode = location.barcode.unwrap();
+
+        let res = handle(
+            request("GET", &format!("/b/{}", barcode), b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers()["location"], format!("/locations/{}", barcode));
+
+        let res = handle(request("GET", "/b/LW-LINK-1", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers()["location"], "/labwares/lw-link-1");
+
+        let res = handle(request("GET", "/b/plate%207%2Fb", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 302);
+        assert_eq!(res.headers()["location"], "/labwares/plate%207%2Fb");
+
+        let res = handle(request("GET", "/b/lw-unknown-9", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.headers()["content-type"], "text/html; charset=utf-8");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("No location or labware has the barcode lw-unknown-9"));
+        assert!(page.contains(r#"href="/kiosk?labware=lw-unknown-9""#));
+
+        let res = handle(request("POST", "/b/lw-link-1", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 405);
+    }
+}
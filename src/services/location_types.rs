@@ -0,0 +1,108 @@
+use crate::services::{json, map_error, method_not_allowed, read_json, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::location_type::{LocationType, LocationTypeBuilder};
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for creating a location type.
+#[derive(Debug, Deserialize, Validate)]
+struct NewLocationType {
+    /// The unique name of the location type, following `Config::location_type_name_rules`
+    name: String,
+}
+
+/// Lists (`GET`) or creates (`POST`) the types locations can be of.
+///
+/// - `GET /location_types` responds with every location type, ordered by name.
+/// - `POST /location_types` with `{"name": "Freezer"}` responds with 201 and the location type,
+///   or 422 if the name does not follow the naming rules or another location type has it.
+pub async fn location_types(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /location_types endpoint");
+    match *req.method() {
+        Method::GET => {
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match LocationType::all(&mut connection).await {
+                Ok(location_types) => Ok(json(StatusCode::OK, &location_types)),
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        Method::POST => {
+            let payload = match read_json::<NewLocationType>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match LocationTypeBuilder::new()
+                .name(payload.name)
+                .create(&mut connection)
+                .await
+            {
+                Ok(location_type) => Ok(json(StatusCode::CREATED, &location_type)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+
+    #[tokio::test]
+    async fn test_location_types() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(
+            request("POST", "/location_types", br#"{"name": "Freezer"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let body = response_json(res).await;
+        assert_eq!(body["name"], "Freezer");
+        assert!(body["uuid"].is_string());
+        assert!(body.get("id").is_none());
+
+        let res = handle(
+            request("POST", "/location_types", br#"{"name": "FREEZER"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let res = handle(
+            request("POST", "/location_types", br#"{"name": " "}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(request("GET", "/location_types", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await[0]["name"], "Freezer");
+
+        let res = handle(request("DELETE", "/location_types", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 405);
+    }
+}
@@ -0,0 +1,1682 @@
+use crate::services::confirmations::confirmation_token;
+use crate::services::labwares::exhaust_confirmed;
+use crate::services::middleware::IsAdmin;
+use crate::services::scan::{lock_token, LOCK_TOKEN_HEADER};
+use crate::services::stats::trend_days;
+use crate::services::{
+    current_locale, error_response, json, map_error, method_not_allowed, pdf, query_params,
+    read_json, status_only, svg, time_param, ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::errors::ValidationError;
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::audit::Audit;
+use labwhere::models::event::State;
+use labwhere::models::labware::Labware;
+use labwhere::models::layout::{Layout, MAX_COLUMNS, MAX_ROWS};
+use labwhere::models::location::Location;
+use labwhere::models::location_flag::{FlagSeverity, LocationFlag};
+use labwhere::models::location_lock::{LocationLock, MAX_LOCK_SECONDS};
+use labwhere::models::location_tree::LocationTree;
+use labwhere::models::occupancy::LocationSnapshot;
+use labwhere::models::subscription::{Subscription, SubscriptionChannel};
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The header carrying an admin token, required to clear a quarantine.
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// The default duration of a location lock, in seconds.
+const DEFAULT_LOCK_SECONDS: u32 = 300;
+
+/// The payload for locking a location.
+#[derive(Debug, Deserialize, Validate)]
+struct NewLocationLock {
+    /// Who is locking the location
+    #[validate(length(min = 1, message = "validation-blank"))]
+    holder: String,
+    /// How long the lock lasts for, in seconds
+    #[validate(range(min = 1, max = MAX_LOCK_SECONDS, message = "validation-range"))]
+    seconds: Option<u32>,
+}
+
+/// The payload for moving a location.
+#[derive(Debug, Deserialize, Validate)]
+struct LocationMove {
+    /// The barcode of the location to move it inside of, or `None` for the top level
+    parent: Option<String>,
+}
+
+/// The payload for renaming a location.
+#[derive(Debug, Deserialize, Validate)]
+struct LocationRename {
+    /// The new name of the location
+    name: String,
+    /// Whether the barcode is generated from the new name, rather than kept
+    #[serde(default)]
+    regenerate_barcode: bool,
+}
+
+/// The payload for setting who is responsible for a location.
+#[derive(Debug, Deserialize, Validate)]
+struct LocationOwnership {
+    /// The user or team responsible for the location
+    owner: Option<String>,
+    /// The email address notifications about the location are sent to
+    #[validate(email(message = "validation-email"))]
+    contact_email: Option<String>,
+    /// Anything people should know about the location
+    notes: Option<String>,
+}
+
+/// The payload for flagging a location.
+#[derive(Debug, Deserialize, Validate)]
+struct NewLocationFlag {
+    /// What happened
+    #[validate(length(min = 1, message = "validation-blank"))]
+    note: String,
+    /// How serious it is
+    severity: FlagSeverity,
+    /// Who is raising the flag
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+}
+
+/// The payload for clearing a location flag.
+#[derive(Debug, Deserialize, Validate)]
+struct ClearLocationFlag {
+    /// Who is clearing the flag
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+}
+
+/// The payload for subscribing to the changes within a location.
+#[derive(Debug, Deserialize, Validate)]
+struct NewSubscription {
+    /// Who is subscribing
+    #[validate(length(min = 1, message = "validation-blank"))]
+    subscriber: String,
+    /// How they are notified
+    channel: SubscriptionChannel,
+    /// The email address or webhook they are notified at
+    #[validate(length(min = 1, message = "validation-blank"))]
+    target: String,
+}
+
+/// The payload for giving a location rows and columns.
+#[derive(Debug, Deserialize, Validate)]
+struct LayoutSize {
+    /// The number of rows, lettered from `A`
+    #[validate(range(min = 1, max = MAX_ROWS, message = "validation-range"))]
+    rows: u32,
+    /// The number of columns, numbered from 1
+    #[validate(range(min = 1, max = MAX_COLUMNS, message = "validation-range"))]
+    columns: u32,
+    /// Whether to move the labwares so that they keep their well numbers rather than coordinates
+    #[serde(default)]
+    remap: bool,
+}
+
+/// The payload for putting a labware in a well of a coordinated location.
+#[derive(Debug, Deserialize, Validate)]
+struct LayoutPosition {
+    /// The barcode of the labware
+    #[validate(length(min = 1, message = "validation-blank"))]
+    labware_barcode: String,
+}
+
+/// Shows (`GET`) a location along with its active flags.
+///
+/// `GET /locations/{barcode}` responds with the location, including its `owner`, `contact_email`
+/// and `notes`, and a `flags` list of the flags which have not been cleared, most serious first.
+pub async fn location(pool: SqlitePool, barcode: &str) -> ServiceResponse {
+    info!("Processing request for /locations/{} endpoint", barcode);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match LocationFlag::active(location.id, &mut connection).await {
+        Ok(flags) => {
+            let mut body = serde_json::to_value(&location).unwrap_or_default();
+            body["flags"] = serde_json::to_value(&flags).unwrap_or_default();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Moves (`POST`) a location inside of another location, taking everything inside of it along.
+///
+/// `POST /locations/{barcode}/move` with `{"parent": "lw-freezer-1"}` responds with the moved
+/// location, or with 422 if the parent is the location itself or inside of it. `{"parent": null}`
+/// moves the location to the top level.
+pub async fn move_location(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/move endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    let payload = match read_json::<LocationMove>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let parent = match payload.parent {
+        Some(parent) => match Location::find_by_barcode(parent, &mut connection).await {
+            Ok(parent) => Some(parent.id),
+            Err(e) => return Ok(map_error(&*e)),
+        },
+        None => None,
+    };
+    match location.reparent(parent, &mut connection).await {
+        Ok(location) => Ok(json(StatusCode::OK, &location)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Renames (`PUT`) a location.
+///
+/// `PUT /locations/{barcode}/name` with `{"name": "shelf 2"}` responds with the renamed location,
+/// which keeps its barcode. With `"regenerate_barcode": true` the location gets a barcode made from
+/// its new name instead, and the old barcode keeps finding it so printed labels keep scanning.
+pub async fn rename(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/name endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    let payload = match read_json::<LocationRename>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    match location
+        .rename(payload.name, payload.regenerate_barcode, &mut connection)
+        .await
+    {
+        Ok(location) => Ok(json(StatusCode::OK, &location)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Sets (`PUT`) who is responsible for a location and how to reach them.
+///
+/// `PUT /locations/{barcode}/owner` with `{"owner": "freezers", "contact_email":
+/// "freezers@example.com", "notes": "Ask before defrosting"}` responds with the location. Anything
+/// left out is cleared. Capacity alerts and flags of the location are sent to the contact email and,
+/// if the owner is one of the configured teams, to its members.
+pub async fn owner(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/owner endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    let payload = match read_json::<LocationOwnership>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    match location
+        .update_ownership(
+            payload.owner,
+            payload.contact_email,
+            payload.notes,
+            &mut connection,
+        )
+        .await
+    {
+        Ok(location) => Ok(json(StatusCode::OK, &location)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Lists (`GET`) or raises (`POST`) the flags of a location.
+///
+/// - `GET /locations/{barcode}/flags` responds with the active flags, most serious first.
+/// - `POST /locations/{barcode}/flags` with
+///   `{"note": "door alarm", "severity": "warning", "user": "jane"}` responds with 201 and the
+///   flag. Severities are `info`, `warning` and `quarantine`; nothing can be scanned into a
+///   quarantined location until the flag is cleared.
+pub async fn flags(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/flags endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match *req.method() {
+        Method::GET => match LocationFlag::active(location.id, &mut connection).await {
+            Ok(flags) => Ok(json(StatusCode::OK, &flags)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::POST => {
+            let payload = match read_json::<NewLocationFlag>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            match LocationFlag::create(
+                location.id,
+                payload.severity,
+                payload.note,
+                payload.user,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(flag) => Ok(json(StatusCode::CREATED, &flag)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Clears (`POST`) a flag of a location.
+///
+/// `POST /locations/{barcode}/flags/{uuid}/clear` with `{"user": "jane"}` responds with the
+/// cleared flag. Clearing a quarantine requires one of the configured admin tokens in the
+/// `X-Admin-Token` header (see `IsAdmin`), otherwise the response is 403.
+pub async fn clear_flag(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+    uuid: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/flags/{}/clear endpoint",
+        barcode, uuid
+    );
+    let admin = req
+        .extensions()
+        .get::<IsAdmin>()
+        .is_some_and(|admin| admin.0);
+    let payload = match read_json::<ClearLocationFlag>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match LocationFlag::clear(location.id, uuid, payload.user, admin, &mut connection).await {
+        Ok(flag) => Ok(json(StatusCode::OK, &flag)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Lists (`GET`) or makes (`POST`) the subscriptions to the changes within a location.
+///
+/// - `GET /locations/{barcode}/subscriptions` responds with the subscriptions, oldest first.
+/// - `POST /locations/{barcode}/subscriptions` with
+///   `{"subscriber": "jane", "channel": "email", "target": "jane@example.com"}` responds with 201
+///   and the subscription. From then on every change to the location, the locations beneath it
+///   and the labwares in them, including labwares moved out, is sent to the target. Channels are
+///   `email` and `webhook`, whose target is e.g. `slack:https://hooks.slack.com/services/...`.
+pub async fn subscriptions(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/subscriptions endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match *req.method() {
+        Method::GET => match Subscription::for_location(location.id, &mut connection).await {
+            Ok(subscriptions) => Ok(json(StatusCode::OK, &subscriptions)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::POST => {
+            let payload = match read_json::<NewSubscription>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            match Subscription::create(
+                location.id,
+                payload.subscriber,
+                payload.channel,
+                payload.target,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(subscription) => Ok(json(StatusCode::CREATED, &subscription)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Unsubscribes (`DELETE`) from the changes within a location.
+///
+/// `DELETE /locations/{barcode}/subscriptions/{uuid}` responds with 204. Notifications which have
+/// not been delivered yet are dropped.
+pub async fn subscription(pool: SqlitePool, barcode: &str, uuid: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/subscriptions/{} endpoint",
+        barcode, uuid
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match Subscription::delete(location.id, uuid, &mut connection).await {
+        Ok(()) => Ok(status_only(StatusCode::NO_CONTENT)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Lists (`GET`) the audits of a location, newest first.
+///
+/// `GET /locations/{barcode}/audits?include_descendants=true` also includes the audits of every
+/// location beneath it and of the labwares in them, so all activity in e.g. a freezer can be
+/// reviewed in one request.
+pub async fn audits(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/audits endpoint",
+        barcode
+    );
+    let include_descendants = query_params(&req)
+        .get("include_descendants")
+        .is_some_and(|v| v == "true" || v == "1");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match Audit::for_location(location.id, include_descendants, &mut connection).await {
+        Ok(audits) => Ok(json(StatusCode::OK, &audits)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Shows (`GET`) what was in a location at a point in time, from the event log, e.g. to
+/// investigate a sample mix-up.
+///
+/// `GET /locations/{barcode}/contents?as_of=2024-03-03T12:00Z` responds with the `location`, the
+/// time asked about (`as_of`), the barcodes of the `labwares` directly in the location then and the
+/// `locations` which were inside of it. `as_of` is an RFC 3339 timestamp (seconds may be left
+/// out), or a date for the end of that day (UTC), and defaults to now.
+pub async fn contents(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/contents endpoint",
+        barcode
+    );
+    let as_of = match time_param(&req, "as_of") {
+        Ok(as_of) => as_of,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match State::contents(&location, as_of, &mut connection).await {
+        Ok(contents) => {
+            let mut body = serde_json::to_value(&contents).unwrap_or_default();
+            body["location"] = serde_json::to_value(&location).unwrap_or_default();
+            body["as_of"] = serde_json::to_value(as_of).unwrap_or_default();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Shows (`GET`) how full a location has been, to plan its storage.
+///
+/// `GET /locations/{barcode}/occupancy?days=30` responds with the `location`, including how many
+/// labwares are directly in it now, and its `trend`: a snapshot for each of the last `days` days,
+/// oldest first, with the number of `labwares` and the `capacity` (number of wells, 0 if the
+/// location is not coordinated).
+pub async fn occupancy(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/occupancy endpoint",
+        barcode
+    );
+    let days = match trend_days(&req) {
+        Ok(days) => days,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match LocationSnapshot::trend(Some(location.id), days, &mut connection).await {
+        Ok(trend) => Ok(json(
+            StatusCode::OK,
+            &serde_json::json!({ "location": location, "trend": trend }),
+        )),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Lists (`GET`) or exhausts (`DELETE`) the labwares in a location.
+///
+/// - `GET /locations/{barcode}/labwares` responds with the labwares in the location, by barcode.
+/// - `DELETE /locations/{barcode}/labwares` empties the location once confirmed: it responds with
+///   202, a summary of the labwares which would be exhausted and a confirmation token. Sending the
+///   request again with the token in the `X-Confirmation-Token` header exhausts them and responds
+///   with the summary. If the location is locked, the lock's token has to be sent in the
+///   `X-Lock-Token` header.
+pub async fn labwares(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/labwares endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    let labwares = match Labware::in_location(location.id, &mut connection).await {
+        Ok(labwares) => labwares,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => Ok(json(StatusCode::OK, &labwares)),
+        Method::DELETE => {
+            if labwares.is_empty() {
+                return Ok(map_error(&ValidationError {
+                    message: Message::new("location-empty").arg("location", &location.name),
+                }));
+            }
+            Ok(exhaust_confirmed(
+                labwares,
+                vec![],
+                confirmation_token(&req),
+                lock_token(&req),
+                &mut connection,
+            )
+            .await)
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::DELETE])),
+    }
+}
+
+/// Shows (`GET`) the grid of a coordinated location, or gives (`PUT`) a location rows and columns,
+/// making it a coordinated location such as a rack.
+///
+/// - `GET /locations/{barcode}/layout` responds with the `rows`, the `columns` and the `cells`: one
+///   list per row with the barcode of the labware in each column, or `null` for a free well.
+/// - `PUT /locations/{barcode}/layout` with `{"rows": 8, "columns": 12}` responds with the layout:
+///   every well, row by row, with the barcode of the labware in it. A location cannot be made
+///   smaller than the wells which hold labware. With `"remap": true`, the labwares are moved so that
+///   they keep their well numbers, counted row by row, instead: responds with 422 if one would be
+///   numbered past the last well.
+pub async fn layout(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/layout endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match *req.method() {
+        Method::GET => match Layout::build(&location, &mut connection).await {
+            Ok(layout) => Ok(json(StatusCode::OK, &layout.to_grid())),
+            Err(e) => Ok(map_error(&*e)),
+        },
+        Method::PUT => {
+            let payload = match read_json::<LayoutSize>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let layout = if payload.remap {
+                Layout::remap(&location, payload.rows, payload.columns, &mut connection).await
+            } else {
+                Layout::resize(&location, payload.rows, payload.columns, &mut connection).await
+            };
+            match layout {
+                Ok(layout) => Ok(json(StatusCode::OK, &layout)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::PUT])),
+    }
+}
+
+/// Puts (`PUT`) a labware in a well of a coordinated location.
+///
+/// `PUT /locations/{barcode}/layout/{position}` e.g. `/layout/B3` with
+/// `{"labware_barcode": "lw-1"}` responds with the layout. A labware from elsewhere is moved into
+/// the location, and a labware already in the location moves to the well. If a location is
+/// locked, its token has to be sent in the `X-Lock-Token` header.
+pub async fn position(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+    position: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/layout/{} endpoint",
+        barcode, position
+    );
+    let lock_token = req
+        .headers()
+        .get(LOCK_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let payload = match read_json::<LayoutPosition>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match Layout::place(
+        &location,
+        position,
+        payload.labware_barcode,
+        lock_token.as_deref(),
+        &mut connection,
+    )
+    .await
+    {
+        Ok(layout) => Ok(json(StatusCode::OK, &layout)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Transfers (`POST`) every labware of a coordinated location to another one.
+///
+/// `POST /locations/{barcode}/transfer?to={barcode}` moves the labwares to the same wells of the
+/// destination, or with `&compact=true` to its first free wells, row by row. Responds with the
+/// `positions`: the well each labware was put in, by the well it was taken from. Responds with 422,
+/// and moves nothing, if a labware cannot be put in its well.
+pub async fn transfer(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/transfer endpoint",
+        barcode
+    );
+    let params = query_params(&req);
+    let Some(destination) = params.get("to") else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            Message::new("layout-transfer-destination-required").localize(&current_locale()),
+        ));
+    };
+    let compact = params
+        .get("compact")
+        .is_some_and(|compact| compact == "true");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let source = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    let destination = match Location::find_by_barcode(destination.clone(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match Layout::transfer(
+        &source,
+        &destination,
+        compact,
+        lock_token(&req).as_deref(),
+        &mut connection,
+    )
+    .await
+    {
+        Ok(transfer) => Ok(json(StatusCode::OK, &transfer)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Renders (`GET`) a coordinated location as an SVG image.
+///
+/// `GET /locations/{barcode}/layout.svg` draws the location as a grid, with occupied wells in
+/// green labelled with their labware and free wells in grey. Hovering over a well shows its
+/// coordinate and the full barcode of its labware.
+pub async fn layout_svg(pool: SqlitePool, barcode: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/layout.svg endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match Layout::build(&location, &mut connection).await {
+        Ok(layout) => Ok(svg(layout.to_svg())),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Prints (`GET`) a location and every location beneath it as a PDF, e.g. to post a map of a
+/// freezer on its door.
+///
+/// `GET /locations/{barcode}/tree.pdf` lists each location indented beneath its parent, with its
+/// barcode and how many labwares are in it (out of how many wells, for coordinated locations).
+pub async fn tree_pdf(pool: SqlitePool, barcode: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/tree.pdf endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match LocationTree::build(&location, &mut connection).await {
+        Ok(tree) => Ok(pdf(
+            tree.to_pdf(&current_locale()),
+            &format!("{}.pdf", barcode),
+        )),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Locks (`POST`) or unlocks (`DELETE`) a location.
+///
+/// - `POST /locations/{barcode}/lock` with `{"holder": "jane", "seconds": 300}` responds with 201
+///   and the lock, including the token that has to be sent in the `X-Lock-Token` header to scan
+///   into or out of the location while it is locked. Posting again as the same holder renews the lock.
+/// - `DELETE /locations/{barcode}/lock` with the `X-Lock-Token` header releases the lock and
+///   responds with 204.
+pub async fn lock(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/lock endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match *req.method() {
+        Method::POST => {
+            let payload = match read_json::<NewLocationLock>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            match LocationLock::acquire(
+                location.id,
+                payload.holder,
+                payload.seconds.unwrap_or(DEFAULT_LOCK_SECONDS),
+                &mut connection,
+            )
+            .await
+            {
+                Ok(lock) => Ok(json(StatusCode::CREATED, &lock)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        Method::DELETE => {
+            let Some(token) = req
+                .headers()
+                .get(LOCK_TOKEN_HEADER)
+                .and_then(|v| v.to_str().ok())
+            else {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    Message::new("lock-token-required").localize(&current_locale()),
+                ));
+            };
+            match LocationLock::release(location.id, token, &mut connection).await {
+                Ok(()) => Ok(status_only(StatusCode::NO_CONTENT)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::POST, Method::DELETE])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json, MockBody};
+    use http_body_util::BodyExt;
+    use labwhere::db::init_pool;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::models::occupancy::LocationSnapshot;
+    use sqlx::SqlitePool;
+
+    async fn setup() -> SqlitePool {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create_with_parent(
+            "shelf".to_string(),
+            location_type.id,
+            Some(freezer.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        Labware::create("lw-1".to_string(), shelf.id, &mut conn)
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_move_location() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("POST", "/locations/lw-shelf-2/move", br#"{"parent": null}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            response_json(res).await["parent_id"],
+            serde_json::Value::Null
+        );
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-freezer-1/move",
+                br#"{"parent": "lw-freezer-1"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "Location freezer cannot be put inside of itself"
+        );
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/move",
+                br#"{"parent": "lw-nowhere-9"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/name",
+                br#"{"name": "shelf a"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["name"], "shelf a");
+        assert_eq!(body["barcode"], "lw-shelf-2");
+
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/name",
+                br#"{"name": "shelf b", "regenerate_barcode": true}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["name"], "shelf b");
+        assert_eq!(body["barcode"], "lw-shelf-b-2");
+
+        let res = handle(request("GET", "/locations/lw-shelf-2", b""), pool.clone())
+            .await
+            .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body["name"], "shelf b");
+        assert_eq!(body["resolved_via_alias"], "lw-shelf-2");
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-b-2/audits", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body[0]["action"], "update");
+        assert_eq!(body[0]["record_data"]["name"], "shelf b");
+
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-b-2/name",
+                br#"{"name": "shelf/c"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-b-2/name",
+                br#"{"name": "shelf c"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 405);
+    }
+
+    #[tokio::test]
+    async fn test_contents() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("GET", "/locations/lw-freezer-1/contents", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["location"]["name"], "freezer");
+        assert_eq!(body["labwares"], serde_json::json!([]));
+        assert_eq!(body["locations"][0]["name"], "shelf");
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/contents", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response_json(res).await["labwares"],
+            serde_json::json!(["lw-1"])
+        );
+
+        let res = handle(
+            request(
+                "GET",
+                "/locations/lw-shelf-2/contents?as_of=2020-03-03T12:00Z",
+                b"",
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["as_of"], "2020-03-03T12:00:00Z");
+        assert_eq!(body["labwares"], serde_json::json!([]));
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/contents?as_of=noon", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = handle(
+            request("GET", "/locations/lw-nowhere-9/contents", b""),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_occupancy() {
+        let pool = setup().await;
+        let mut conn = pool.acquire().await.unwrap();
+        LocationSnapshot::record(&mut conn).await.unwrap();
+        drop(conn);
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/occupancy", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["location"]["name"], "shelf");
+        assert_eq!(body["trend"].as_array().unwrap().len(), 1);
+        assert_eq!(body["trend"][0]["labwares"], 1);
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/occupancy?days=x", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = handle(
+            request("GET", "/locations/lw-nowhere-9/occupancy", b""),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_audits() {
+        let pool = setup().await;
+
+        let res = handle(request("GET", "/locations/lw-freezer-1/audits", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["auditable_type"], "Location");
+        assert_eq!(body[0]["record_data"]["name"], "freezer");
+    }
+
+    #[tokio::test]
+    async fn test_audits_including_descendants() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "GET",
+                "/locations/lw-freezer-1/audits?include_descendants=true",
+                b"",
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        assert_eq!(body[0]["auditable_type"], "Labware");
+        assert_eq!(body[0]["record_data"]["barcode"], "lw-1");
+    }
+
+    #[tokio::test]
+    async fn test_audits_for_unknown_location() {
+        let pool = setup().await;
+
+        let res = handle(request("GET", "/locations/lw-fridge-9/audits", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_lock_and_unlock() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/lock",
+                br#"{"holder": "jane", "seconds": 60}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let token = response_json(res).await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/lock",
+                br#"{"holder": "john"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 423);
+
+        let res = handle(
+            request("DELETE", "/locations/lw-shelf-2/lock", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let req = hyper::Request::builder()
+            .method("DELETE")
+            .uri("/locations/lw-shelf-2/lock")
+            .header("X-Lock-Token", token)
+            .body(MockBody::new(b""))
+            .unwrap();
+        let res = handle(req, pool).await.unwrap();
+        assert_eq!(res.status(), 204);
+    }
+
+    #[tokio::test]
+    async fn test_location_info() {
+        let pool = setup().await;
+
+        let res = handle(request("GET", "/locations/lw-shelf-2", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["name"], "shelf");
+        assert_eq!(body["flags"], serde_json::json!([]));
+
+        let res = handle(request("GET", "/locations/lw-fridge-9", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+
+        // A misread barcode is rejected rather than not found
+        let res = handle(request("GET", "/locations/%5DC", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_owner() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/owner",
+                br#"{"owner": "freezers", "contact_email": "not an email"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/owner",
+                br#"{"owner": "freezers", "contact_email": "freezers@example.com",
+                    "notes": "Ask before defrosting"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+
+        let res = handle(request("GET", "/locations/lw-shelf-2", b""), pool)
+            .await
+            .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body["owner"], "freezers");
+        assert_eq!(body["contact_email"], "freezers@example.com");
+        assert_eq!(body["notes"], "Ask before defrosting");
+    }
+
+    #[tokio::test]
+    async fn test_location_info_via_alias() {
+        let pool = setup().await;
+        sqlx::query(
+            "INSERT INTO location_barcode_aliases (location_id, barcode) VALUES (2, 'lw-old-shelf-2')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let res = handle(
+            request("GET", "/locations/lw-old-shelf-2", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["barcode"], "lw-shelf-2");
+        assert_eq!(body["resolved_via_alias"], "lw-old-shelf-2");
+
+        let res = handle(request("GET", "/locations/lw-shelf-2", b""), pool)
+            .await
+            .unwrap();
+        let body = response_json(res).await;
+        assert!(body.get("resolved_via_alias").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flag_and_clear() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/flags",
+                br#"{"note": "door alarm", "severity": "warning", "user": "jane"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let uuid = response_json(res).await["uuid"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let res = handle(request("GET", "/locations/lw-shelf-2", b""), pool.clone())
+            .await
+            .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body["flags"][0]["note"], "door alarm");
+        assert_eq!(body["flags"][0]["severity"], "warning");
+
+        let path: &'static str =
+            Box::leak(format!("/locations/lw-shelf-2/flags/{}/clear", uuid).into_boxed_str());
+        let res = handle(request("POST", path, br#"{"user": "john"}"#), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await["cleared_by"], "john");
+
+        let res = handle(request("GET", "/locations/lw-shelf-2/flags", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await, serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_flag() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/flags",
+                br#"{"note": "", "severity": "warning", "user": "jane"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/flags",
+                br#"{"note": "door alarm", "severity": "apocalyptic", "user": "jane"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_blocks_scans() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/flags",
+                br#"{"note": "temperature excursion", "severity": "quarantine", "user": "jane"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let uuid = response_json(res).await["uuid"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let res = handle(
+            request(
+                "POST",
+                "/scan",
+                br#"{"location_barcode": "lw-shelf-2", "labware_barcodes": ["lw-quarantine-1"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 423);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "Location shelf is quarantined: temperature excursion"
+        );
+
+        let path: &'static str =
+            Box::leak(format!("/locations/lw-shelf-2/flags/{}/clear", uuid).into_boxed_str());
+        let res = handle(request("POST", path, br#"{"user": "jane"}"#), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_and_unsubscribe() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-freezer-1/subscriptions",
+                br#"{"subscriber": "jane", "channel": "webhook", "target": "jane@example.com"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-freezer-1/subscriptions",
+                br#"{"subscriber": "jane", "channel": "email", "target": "jane@example.com"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let uuid = response_json(res).await["uuid"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let res = handle(
+            request("GET", "/locations/lw-freezer-1/subscriptions", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body[0]["channel"], "email");
+        assert_eq!(body[0]["target"], "jane@example.com");
+
+        let path: &'static str =
+            Box::leak(format!("/locations/lw-freezer-1/subscriptions/{}", uuid).into_boxed_str());
+        let res = handle(request("DELETE", path, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 204);
+        let res = handle(request("DELETE", path, b""), pool).await.unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_layout() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/layout.svg", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/layout",
+                br#"{"rows": 27, "columns": 12}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/layout",
+                br#"{"rows": 2, "columns": 2}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await["wells"][3]["coordinate"], "B2");
+
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/layout/B1",
+                br#"{"labware_barcode": "lw-1"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            response_json(res).await["wells"][2]["labware_barcode"],
+            "lw-1"
+        );
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/layout", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            response_json(res).await["cells"],
+            serde_json::json!([[null, null], ["lw-1", null]])
+        );
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/layout.svg", b""),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "image/svg+xml");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let image = String::from_utf8(body.to_vec()).unwrap();
+        assert!(image.contains("<title>B1: lw-1</title>"));
+    }
+
+    #[tokio::test]
+    async fn test_transfer() {
+        let pool = setup().await;
+        for barcode in ["lw-freezer-1", "lw-shelf-2"] {
+            let res = handle(
+                request(
+                    "PUT",
+                    &format!("/locations/{}/layout", barcode),
+                    br#"{"rows": 2, "columns": 2}"#,
+                ),
+                pool.clone(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(res.status(), 200);
+        }
+        let res = handle(
+            request(
+                "PUT",
+                "/locations/lw-shelf-2/layout/B1",
+                br#"{"labware_barcode": "lw-1"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+
+        let res = handle(
+            request("POST", "/locations/lw-shelf-2/transfer", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = handle(
+            request(
+                "POST",
+                "/locations/lw-shelf-2/transfer?to=lw-freezer-1&compact=true",
+                b"",
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let transfer = response_json(res).await;
+        assert_eq!(transfer["destination"], "freezer");
+        assert_eq!(
+            transfer["positions"],
+            serde_json::json!({"B1": {"labware_barcode": "lw-1", "position": "A1"}})
+        );
+
+        let res = handle(
+            request("POST", "/locations/lw-shelf-2/transfer?to=lw-shelf-2", b""),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_tree_pdf() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("GET", "/locations/lw-freezer-1/tree.pdf", b""),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "application/pdf");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.starts_with(b"%PDF-1.4"));
+        let document = String::from_utf8_lossy(&body);
+        assert!(document.contains("(shelf  lw-shelf-2"));
+    }
+
+    #[tokio::test]
+    async fn test_exhaust_labwares() {
+        let pool = setup().await;
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/labwares", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await[0]["barcode"], "lw-1");
+
+        let res = handle(
+            request("DELETE", "/locations/lw-shelf-2/labwares", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 202);
+        let body = response_json(res).await;
+        assert_eq!(body["summary"]["count"], 1);
+        let token = body["confirmation_token"].as_str().unwrap().to_string();
+
+        let confirmed = |token: &str| {
+            hyper::Request::builder()
+                .method("DELETE")
+                .uri("/locations/lw-shelf-2/labwares")
+                .header("X-Confirmation-Token", token)
+                .body(MockBody::new(b""))
+                .unwrap()
+        };
+        // Another labware was put in the location since, so the token no longer confirms emptying it
+        let mut conn = pool.acquire().await.unwrap();
+        let shelf = Location::find_by_barcode("lw-shelf-2".to_string(), &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-2".to_string(), shelf.id, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        let res = handle(confirmed(&token), pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request("DELETE", "/locations/lw-shelf-2/labwares", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body["summary"]["count"], 2);
+        let token = body["confirmation_token"].as_str().unwrap().to_string();
+        let res = handle(confirmed(&token), pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 200);
+
+        let res = handle(
+            request("GET", "/locations/lw-shelf-2/labwares", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response_json(res).await, serde_json::json!([]));
+
+        let res = handle(
+            request("DELETE", "/locations/lw-shelf-2/labwares", b""),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+}
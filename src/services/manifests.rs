@@ -0,0 +1,288 @@
+use crate::services::{
+    csv, current_locale, json, map_error, method_not_allowed, query_params, ServiceResponse,
+};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::manifest::{Manifest, ManifestReader, ManifestRow, BATCH_SIZE};
+use labwhere::models::upload_mapping::UploadMapping;
+use log::{error, info};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::error::Error;
+
+/// Lists (`GET`) or uploads (`POST`) manifests.
+///
+/// - `GET /manifests` responds with all manifests, newest first.
+/// - `POST /manifests` with a CSV file whose header names a `labware` and a `location` column puts
+///   the labware of each row into its location, registering labwares which are new. The file is
+///   read and imported `BATCH_SIZE` rows at a time as it arrives, so `GET /manifests/{uuid}` shows
+///   the progress of a large upload. Responds with 201 and the manifest once the whole file has
+///   been imported, or with 422 if the file is not a valid manifest. With `?mapping={name}`, the
+///   labware and location columns are those of the saved upload mapping instead.
+pub async fn manifests(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /manifests endpoint");
+    match *req.method() {
+        Method::GET => {
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match Manifest::all(&mut connection).await {
+                Ok(manifests) => Ok(json(StatusCode::OK, &manifests)),
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        Method::POST => {
+            let mut reader = ManifestReader::default();
+            if let Some(name) = query_params(&req).get("mapping") {
+                let mut connection = match acquire(&pool).await {
+                    Ok(connection) => connection,
+                    Err(e) => return Ok(map_error(&e)),
+                };
+                match UploadMapping::find_by_name(name, &mut connection).await {
+                    Ok(mapping) => reader = reader.mapped(&mapping),
+                    Err(e) => return Ok(map_error(&e)),
+                }
+            }
+            // The upload is imported on its own task, so the manifest is marked as failed rather
+            // than left processing if the client goes away part way through.
+            let upload = tokio::spawn(async move {
+                let mut connection = acquire(&pool).await?;
+                let mut manifest = Manifest::create(&mut connection).await?;
+                match upload(req.into_body(), reader, &mut manifest, &mut connection).await {
+                    Ok(()) => {
+                        manifest.complete(&mut connection).await?;
+                        Ok((manifest, None))
+                    }
+                    Err(e) => {
+                        error!("Manifest {} failed: {}", manifest.uuid, e);
+                        manifest.fail(e.to_string(), &mut connection).await?;
+                        Ok((manifest, Some(e)))
+                    }
+                }
+            });
+            let result: Result<_, Box<dyn Error + Send + Sync>> = match upload.await {
+                Ok(result) => result.map_err(|e: sqlx::Error| e.into()),
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok((manifest, None)) => Ok(json(StatusCode::CREATED, &manifest)),
+                Ok((_, Some(e))) | Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Reads the rows of an upload as they arrive and imports them in batches.
+async fn upload(
+    body: impl Body<Data = Bytes, Error = hyper::Error> + Send + 'static,
+    mut reader: ManifestReader,
+    manifest: &mut Manifest,
+    connection: &mut SqliteConnection,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut body = Box::pin(body);
+    let mut rows: Vec<ManifestRow> = vec![];
+    while let Some(frame) = body.frame().await {
+        if let Ok(chunk) = frame?.into_data() {
+            rows.extend(reader.read(&chunk)?);
+        }
+        while rows.len() >= BATCH_SIZE {
+            let batch: Vec<ManifestRow> = rows.drain(..BATCH_SIZE).collect();
+            manifest.import(&batch, &mut *connection).await?;
+        }
+    }
+    rows.extend(reader.finish()?);
+    for batch in rows.chunks(BATCH_SIZE) {
+        manifest.import(batch, &mut *connection).await?;
+    }
+    Ok(())
+}
+
+/// Shows (`GET`) a manifest, including how many of its rows have been processed so far and the
+/// first `MAX_ERRORS_SHOWN` rows which could not be imported.
+///
+/// `GET /manifests/{uuid}`. Each error has its `line`, `barcode`, `location` and `message` in the
+/// locale of the request. Every row which could not be imported can be downloaded from
+/// `GET /uploads/{uuid}/errors.csv`.
+pub async fn manifest(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /manifests/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let manifest = match Manifest::find_by_uuid(uuid, &mut connection).await {
+        Ok(manifest) => manifest,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match manifest.errors(&mut connection).await {
+        Ok(errors) => {
+            let mut body = serde_json::to_value(&manifest).unwrap_or_default();
+            let locale = current_locale();
+            body["errors"] = errors
+                .iter()
+                .map(|error| {
+                    serde_json::json!({
+                        "line": error.line,
+                        "barcode": error.barcode,
+                        "location": error.location,
+                        "message": error.message.localize(&locale),
+                    })
+                })
+                .collect();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Downloads (`GET`) the rows of a manifest which could not be imported as a CSV attachment.
+///
+/// `GET /uploads/{uuid}/errors.csv` (or `GET /manifests/{uuid}/errors.csv`) responds with a
+/// `labware,location,line,error` file, with the errors in the locale of the request, which can be
+/// fixed and uploaded again to `POST /manifests` as it is.
+pub async fn errors_csv(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /uploads/{}/errors.csv endpoint",
+        uuid
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let manifest = match Manifest::find_by_uuid(uuid, &mut connection).await {
+        Ok(manifest) => manifest,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match manifest
+        .errors_csv(&current_locale(), &mut connection)
+        .await
+    {
+        Ok(body) => Ok(csv(body, &format!("manifest-{}-errors.csv", manifest.uuid))),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json, MockBody};
+    use http_body_util::BodyExt;
+    use labwhere::db::init_pool;
+    use labwhere::factories::LocationFactory;
+    use sqlx::SqlitePool;
+
+    async fn setup() -> SqlitePool {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        LocationFactory::new()
+            .with_name("location1")
+            .with_type("Freezer")
+            .create(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_upload_and_show_manifest() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/manifests",
+                b"labware,location\nlw-1,lw-location1-1\nlw-2,lw-nowhere-9\n",
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let body = response_json(res).await;
+        assert_eq!(body["state"], "done");
+        assert_eq!(body["rows_imported"], 1);
+        assert_eq!(body["rows_failed"], 1);
+        let uuid = body["uuid"].as_str().unwrap().to_string();
+
+        let res = handle(
+            request("GET", &format!("/manifests/{}", uuid), b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["rows_processed"], 2);
+        assert_eq!(body["errors"][0]["line"], 3);
+        assert_eq!(body["errors"][0]["barcode"], "lw-2");
+        assert_eq!(body["errors"][0]["message"], "Location not found");
+
+        let res = handle(
+            request("GET", &format!("/uploads/{}/errors.csv", uuid), b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "text/csv; charset=utf-8");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            "labware,location,line,error\nlw-2,lw-nowhere-9,3,Location not found\n"
+        );
+
+        let req = hyper::Request::builder()
+            .method("GET")
+            .uri(format!("/manifests/{}/errors.csv", uuid))
+            .header("Accept-Language", "es")
+            .body(MockBody::new(b""))
+            .unwrap();
+        let res = handle(req, pool.clone()).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            "labware,location,line,error\nlw-2,lw-nowhere-9,3,No se encontró la ubicación\n"
+        );
+
+        let res = handle(request("GET", "/labwares/lw-1", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+
+        let res = handle(request("GET", "/manifests/1", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_upload_invalid_manifest() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/manifests",
+                b"barcode,location\nlw-1,lw-location1-1\n",
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "The manifest has no labware column in its header"
+        );
+
+        let res = handle(request("GET", "/manifests", b""), pool)
+            .await
+            .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body[0]["state"], "failed");
+        assert_eq!(body[0]["rows_processed"], 0);
+    }
+}
@@ -0,0 +1,48 @@
+use crate::services::{map_error, prometheus, ServiceResponse};
+use labwhere::metrics::Metrics;
+use log::info;
+use sqlx::SqlitePool;
+
+/// Shows (`GET`) how saturated the server is, in the Prometheus text format.
+///
+/// `GET /metrics` responds with the open HTTP connections, the size of the database pool, the
+/// connections in use and the tasks waiting for one, and the jobs waiting in each background job
+/// queue (print jobs, subscription notifications and manifests), and the burn rate of each latency
+/// objective (see `GET /admin/slos`).
+pub async fn metrics(pool: SqlitePool) -> ServiceResponse {
+    info!("Processing request for /metrics endpoint");
+    match Metrics::gather(&pool).await {
+        Ok(metrics) => Ok(prometheus(metrics.render())),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request};
+    use http_body_util::BodyExt;
+    use labwhere::db::init_pool;
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/metrics", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.headers()["content-type"],
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("\nlabwhere_db_pool_max_connections 1\n"));
+        assert!(text.contains("labwhere_job_queue_depth{queue=\"print_jobs\"} 0\n"));
+
+        let res = handle(request("POST", "/metrics", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 405);
+    }
+}
@@ -0,0 +1,1297 @@
+use crate::services::auth::API_KEY_HEADER;
+use crate::services::confirmations::CONFIRMATION_TOKEN_HEADER;
+use crate::services::locations::ADMIN_TOKEN_HEADER;
+use crate::services::scan::{DEVICE_KEY_HEADER, LOCK_TOKEN_HEADER};
+use crate::services::{auth, current_locale, error_response, full, segments, unavailable, LOCALE};
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY,
+};
+use hyper::{Method, Request, Response, StatusCode};
+use labwhere::cache::idempotency::IdempotencyStore;
+use labwhere::cache::recorder::{RecordedBody, RecordedExchange, RequestRecorder};
+use labwhere::config::Config;
+use labwhere::db::health::CircuitBreaker;
+use labwhere::i18n::{negotiate, Message};
+use labwhere::metrics::slo::SloTracker;
+use log::{error, warn};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+/// The future every middleware service resolves to.
+type ResponseFuture = Pin<
+    Box<dyn Future<Output = Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>> + Send>,
+>;
+
+/// Takes the inner service which was driven to readiness by `poll_ready`, leaving a clone in its
+/// place, so the request can be handed to it from a `'static` future.
+fn take<S: Clone>(inner: &mut S) -> S {
+    let clone = inner.clone();
+    std::mem::replace(inner, clone)
+}
+
+/// The bound every inner service of the middleware meets.
+pub trait Inner<B>:
+    Service<
+        Request<B>,
+        Response = Response<BoxBody<Bytes, hyper::Error>>,
+        Error = hyper::Error,
+        Future: Send,
+    > + Clone
+    + Send
+    + 'static
+{
+}
+
+impl<S, B> Inner<B> for S where
+    S: Service<
+            Request<B>,
+            Response = Response<BoxBody<Bytes, hyper::Error>>,
+            Error = hyper::Error,
+            Future: Send,
+        > + Clone
+        + Send
+        + 'static
+{
+}
+
+/// Picks the locale to respond in from the `Accept-Language` header, which every layer inside
+/// this one (and every endpoint) localizes its messages to.
+#[derive(Debug, Clone, Default)]
+pub struct LocaleLayer {}
+
+impl<S> Layer<S> for LocaleLayer {
+    type Service = Locale<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Locale { inner }
+    }
+}
+
+/// The service of `LocaleLayer`.
+#[derive(Debug, Clone)]
+pub struct Locale<S> {
+    inner: S,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for Locale<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let locale = negotiate(
+            req.headers()
+                .get(hyper::header::ACCEPT_LANGUAGE)
+                .and_then(|v| v.to_str().ok()),
+        );
+        Box::pin(LOCALE.scope(locale, async move { inner.call(req).await }))
+    }
+}
+
+/// Answers every request with a 503 while the circuit breaker of the database is open, i.e. the
+/// database could not be reached several times in a row, telling clients when to try again in
+/// the `Retry-After` header.
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerLayer {
+    breaker: &'static CircuitBreaker,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(breaker: &'static CircuitBreaker) -> Self {
+        Self { breaker }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = Circuit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Circuit {
+            inner,
+            breaker: self.breaker,
+        }
+    }
+}
+
+/// The service of `CircuitBreakerLayer`.
+#[derive(Debug, Clone)]
+pub struct Circuit<S> {
+    inner: S,
+    breaker: &'static CircuitBreaker,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for Circuit<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let retry_after = self.breaker.retry_after();
+        Box::pin(async move {
+            match retry_after {
+                Some(retry_after) => Ok(unavailable(
+                    Message::new("database-unavailable"),
+                    retry_after,
+                )),
+                None => inner.call(req).await,
+            }
+        })
+    }
+}
+
+/// Gives up on a request which takes longer than `duration`, responding with a 503 instead.
+/// Requests for which `exempt` is true, given their method and the segments of their path, may
+/// take as long as they need.
+#[derive(Debug, Clone)]
+pub struct TimeoutLayer {
+    duration: Duration,
+    exempt: fn(&Method, &[&str]) -> bool,
+}
+
+impl TimeoutLayer {
+    pub fn new(duration: Duration, exempt: fn(&Method, &[&str]) -> bool) -> Self {
+        Self { duration, exempt }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Timeout {
+            inner,
+            duration: self.duration,
+            exempt: self.exempt,
+        }
+    }
+}
+
+/// The service of `TimeoutLayer`.
+#[derive(Debug, Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    duration: Duration,
+    exempt: fn(&Method, &[&str]) -> bool,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for Timeout<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let duration = self.duration;
+        if (self.exempt)(req.method(), &segments(req.uri().path())) {
+            return Box::pin(async move { inner.call(req).await });
+        }
+        Box::pin(async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            match tokio::time::timeout(duration, inner.call(req)).await {
+                Ok(response) => response,
+                Err(_) => {
+                    warn!("Gave up on {} {} after {:?}", method, path, duration);
+                    Ok(error_response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        Message::new("request-timeout")
+                            .arg("seconds", duration.as_secs_f64())
+                            .localize(&current_locale()),
+                    ))
+                }
+            }
+        })
+    }
+}
+
+/// Times every request, from when it reaches the layer until its response is ready, against the
+/// latency objectives of its route (see `SloTracker`). Goes just inside the tracing, so the
+/// time the other layers take counts too.
+#[derive(Clone)]
+pub struct SloLayer {
+    tracker: &'static SloTracker,
+}
+
+impl SloLayer {
+    pub fn new(tracker: &'static SloTracker) -> Self {
+        Self { tracker }
+    }
+}
+
+impl<S> Layer<S> for SloLayer {
+    type Service = Slo<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Slo {
+            inner,
+            tracker: self.tracker,
+        }
+    }
+}
+
+/// The service of `SloLayer`.
+#[derive(Clone)]
+pub struct Slo<S> {
+    inner: S,
+    tracker: &'static SloTracker,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for Slo<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let tracker = self.tracker;
+        if tracker.is_empty() {
+            return Box::pin(async move { inner.call(req).await });
+        }
+        let method = req.method().to_string();
+        let path = req.uri().path().to_string();
+        Box::pin(async move {
+            let started = Instant::now();
+            let response = inner.call(req).await;
+            tracker.observe(&method, &segments(&path), started.elapsed());
+            response
+        })
+    }
+}
+
+/// The name of the API key a request was made with, if any, which `AuthLayer` adds to the
+/// extensions of the request for the layers inside it.
+#[derive(Debug, Clone)]
+pub struct ApiKeyName(pub Option<String>);
+
+/// Whether a request carries one of the configured admin tokens, which `AdminLayer` adds to the
+/// extensions of the request for the endpoints whose rules depend on the data, e.g. only admins
+/// clear a quarantine.
+#[derive(Debug, Clone, Copy)]
+pub struct IsAdmin(pub bool);
+
+/// Applies the auth policy of the configured `AuthMode` to every request (see `auth::authorize`),
+/// answering the requests it does not allow with a 401.
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    config: &'static Config,
+    pool: SqlitePool,
+}
+
+impl AuthLayer {
+    pub fn new(config: &'static Config, pool: SqlitePool) -> Self {
+        Self { config, pool }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            config: self.config,
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+/// The service of `AuthLayer`.
+#[derive(Debug, Clone)]
+pub struct Auth<S> {
+    inner: S,
+    config: &'static Config,
+    pool: SqlitePool,
+}
+
+impl<S: Inner<B>, B: Send + Sync + 'static> Service<Request<B>> for Auth<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let (config, pool) = (self.config, self.pool.clone());
+        Box::pin(async move {
+            let name = auth::api_key_name(config, &req, &pool).await;
+            let path = req.uri().path().to_string();
+            if let Some(response) = auth::authorize(config, &req, &segments(&path), name.as_deref())
+            {
+                return Ok(response);
+            }
+            req.extensions_mut().insert(ApiKeyName(name));
+            inner.call(req).await
+        })
+    }
+}
+
+/// Answers every request which only admins may make (see `auth::admin_only`) and which does not
+/// carry one of the configured admin tokens with a 403, so no endpoint checks the token itself.
+#[derive(Debug, Clone)]
+pub struct AdminLayer {
+    config: &'static Config,
+}
+
+impl AdminLayer {
+    pub fn new(config: &'static Config) -> Self {
+        Self { config }
+    }
+}
+
+impl<S> Layer<S> for AdminLayer {
+    type Service = Admin<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Admin {
+            inner,
+            config: self.config,
+        }
+    }
+}
+
+/// The service of `AdminLayer`.
+#[derive(Debug, Clone)]
+pub struct Admin<S> {
+    inner: S,
+    config: &'static Config,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for Admin<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let admin = auth::is_admin(self.config, &req);
+        let response =
+            (!admin && auth::admin_only(&req, &segments(req.uri().path()))).then(auth::forbidden);
+        req.extensions_mut().insert(IsAdmin(admin));
+        Box::pin(async move {
+            match response {
+                Some(response) => Ok(response),
+                None => inner.call(req).await,
+            }
+        })
+    }
+}
+
+/// Counts every request made with an API key against the daily quota of the key (see
+/// `auth::meter`), answering the requests over the quota with a 429. Goes inside `AuthLayer`,
+/// which identifies the key.
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    config: &'static Config,
+    pool: SqlitePool,
+}
+
+impl RateLimitLayer {
+    pub fn new(config: &'static Config, pool: SqlitePool) -> Self {
+        Self { config, pool }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            config: self.config,
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+/// The service of `RateLimitLayer`.
+#[derive(Debug, Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    config: &'static Config,
+    pool: SqlitePool,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for RateLimit<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let (config, pool) = (self.config, self.pool.clone());
+        let name = req
+            .extensions()
+            .get::<ApiKeyName>()
+            .and_then(|name| name.0.clone());
+        Box::pin(async move {
+            match auth::meter(config, name.as_deref(), &pool).await {
+                Some(response) => Ok(response),
+                None => inner.call(req).await,
+            }
+        })
+    }
+}
+
+/// The header carrying the key which makes a request that changes something safe to retry.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// The header marking a response as the replay of the response to an earlier request with the same
+/// idempotency key.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Answers the retries of a request which changes something (`POST`, `PUT`, `PATCH` or `DELETE`)
+/// with the response to the first request, if they carry the same `Idempotency-Key` header, so
+/// that e.g. a robot retrying a move which timed out does not move the labware twice.
+///
+/// Keys are scoped to the API key, the method and the path of the request. Server errors are not
+/// stored, so those requests can be retried. Goes inside `AuthLayer`, which identifies the key.
+#[derive(Clone)]
+pub struct IdempotencyLayer {
+    store: &'static IdempotencyStore,
+}
+
+impl IdempotencyLayer {
+    pub fn new(store: &'static IdempotencyStore) -> Self {
+        Self { store }
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = Idempotency<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Idempotency {
+            inner,
+            store: self.store,
+        }
+    }
+}
+
+/// The service of `IdempotencyLayer`.
+#[derive(Clone)]
+pub struct Idempotency<S> {
+    inner: S,
+    store: &'static IdempotencyStore,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for Idempotency<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let store = self.store;
+        let key = idempotency_key(&req);
+        Box::pin(async move {
+            let Some(key) = key else {
+                return inner.call(req).await;
+            };
+            if let Some(response) = store.get(&key).await.and_then(|stored| replay(&stored)) {
+                return Ok(response);
+            }
+            let response = inner.call(req).await?;
+            if response.status().is_server_error() {
+                return Ok(response);
+            }
+            let (parts, body) = response.into_parts();
+            let body = body.collect().await?.to_bytes();
+            let content_type = parts.headers.get(CONTENT_TYPE);
+            store
+                .put(&key, stored(parts.status, content_type, &body))
+                .await;
+            Ok(Response::from_parts(parts, full(body)))
+        })
+    }
+}
+
+/// The key a request is stored under in the idempotency store, e.g.
+/// `lims:POST /labwares:4b1c6a0e`, if it changes something and carries an idempotency key.
+fn idempotency_key<B>(req: &Request<B>) -> Option<String> {
+    if !matches!(
+        *req.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    ) {
+        return None;
+    }
+    let key = req.headers().get(IDEMPOTENCY_KEY_HEADER)?.to_str().ok()?;
+    let name = req
+        .extensions()
+        .get::<ApiKeyName>()
+        .and_then(|name| name.0.clone())
+        .unwrap_or_default();
+    Some(format!(
+        "{}:{} {}:{}",
+        name,
+        req.method(),
+        req.uri().path(),
+        key.trim()
+    ))
+}
+
+/// A response as it is stored in the idempotency store: its status and content type on a line
+/// each, then its body.
+fn stored(status: StatusCode, content_type: Option<&HeaderValue>, body: &[u8]) -> Vec<u8> {
+    let content_type = content_type.map(HeaderValue::as_bytes).unwrap_or_default();
+    [status.as_str().as_bytes(), b"\n", content_type, b"\n", body].concat()
+}
+
+/// Rebuilds a response from the idempotency store, marked as replayed.
+fn replay(stored: &[u8]) -> Option<Response<BoxBody<Bytes, hyper::Error>>> {
+    let mut parts = stored.splitn(3, |byte| *byte == b'\n');
+    let status = StatusCode::from_bytes(parts.next()?).ok()?;
+    let content_type = parts.next()?;
+    let body = parts.next()?;
+    let mut response = Response::new(full(body.to_vec()));
+    *response.status_mut() = status;
+    if let Ok(content_type) = HeaderValue::from_bytes(content_type) {
+        if !content_type.is_empty() {
+            response.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+    }
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    Some(response)
+}
+
+/// The headers which carry credentials, which are never recorded.
+const CREDENTIAL_HEADERS: [&str; 7] = [
+    "authorization",
+    "cookie",
+    API_KEY_HEADER,
+    ADMIN_TOKEN_HEADER,
+    LOCK_TOKEN_HEADER,
+    DEVICE_KEY_HEADER,
+    CONFIRMATION_TOKEN_HEADER,
+];
+
+/// The fields of JSON bodies which carry credentials, whose values are masked when recorded.
+const CREDENTIAL_FIELDS: [&str; 4] = ["user_code", "swipe_code", "api_key", "key"];
+
+/// What the value of a credential field is recorded as.
+const MASKED_CREDENTIAL: &str = "[FILTERED]";
+
+/// Records every request and the response to it in a `RequestRecorder`, if the recorder is
+/// enabled, without the headers which carry credentials and with the credential fields of JSON
+/// bodies masked. Requests to `/admin/debug`, which shows the recorded exchanges, are not
+/// recorded. Neither are the bodies of requests which manage API keys or users or register a
+/// device, nor those of their responses, as they are full of credentials.
+///
+/// The bodies of requests for which `streamed` is true, given their method and the segments of
+/// their path, are handed to the endpoint as they arrive instead of being recorded, as are
+/// responses whose size is not known up front (e.g. streamed JSON arrays).
+#[derive(Clone)]
+pub struct RecorderLayer {
+    recorder: &'static RequestRecorder,
+    streamed: fn(&Method, &[&str]) -> bool,
+}
+
+impl RecorderLayer {
+    pub fn new(recorder: &'static RequestRecorder, streamed: fn(&Method, &[&str]) -> bool) -> Self {
+        Self { recorder, streamed }
+    }
+}
+
+impl<S> Layer<S> for RecorderLayer {
+    type Service = Recorder<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Recorder {
+            inner,
+            recorder: self.recorder,
+            streamed: self.streamed,
+        }
+    }
+}
+
+/// The service of `RecorderLayer`.
+#[derive(Clone)]
+pub struct Recorder<S> {
+    inner: S,
+    recorder: &'static RequestRecorder,
+    streamed: fn(&Method, &[&str]) -> bool,
+}
+
+impl<S, B> Service<Request<B>> for Recorder<S>
+where
+    S: Inner<BoxBody<Bytes, hyper::Error>>,
+    B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static,
+{
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let recorder = self.recorder;
+        let path_segments = segments(req.uri().path());
+        let recorded = recorder.enabled() && !matches!(path_segments[..], ["admin", "debug", ..]);
+        let streamed = (self.streamed)(req.method(), &path_segments);
+        let credentials = credential_bodies(req.method(), &path_segments);
+        if !recorded {
+            return Box::pin(async move { inner.call(req.map(BodyExt::boxed)).await });
+        }
+        Box::pin(async move {
+            let started = Instant::now();
+            let received_at = Utc::now();
+            let method = req.method().to_string();
+            let path = req.uri().path_and_query().map_or_else(
+                || req.uri().path().to_string(),
+                |path_and_query| path_and_query.to_string(),
+            );
+            let request_headers = recorded_headers(req.headers());
+            let (req, request_body) = if streamed || credentials {
+                (req.map(BodyExt::boxed), None)
+            } else {
+                let (parts, body) = req.into_parts();
+                let body = body.collect().await?.to_bytes();
+                let recorded = recorded_body(&body);
+                (Request::from_parts(parts, full(body)), Some(recorded))
+            };
+
+            let (parts, body) = inner.call(req).await?.into_parts();
+            let (body, response_body) = match body.size_hint().exact() {
+                Some(_) if !credentials => {
+                    let body = body.collect().await?.to_bytes();
+                    let recorded = recorded_body(&body);
+                    (full(body), Some(recorded))
+                }
+                _ => (body, None),
+            };
+            recorder.record(RecordedExchange {
+                received_at,
+                method,
+                path,
+                request_headers,
+                request_body,
+                status: parts.status.as_u16(),
+                response_body,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+            Ok(Response::from_parts(parts, body))
+        })
+    }
+}
+
+/// Whether the bodies of a request and its response are mostly credentials, so neither is
+/// recorded: those managing API keys and users, whose keys and swipe codes they show, and the
+/// registration of a device, which responds with its key.
+fn credential_bodies(method: &Method, segments: &[&str]) -> bool {
+    matches!(
+        (method, segments),
+        (_, ["admin", "api_keys", ..]) | (_, ["admin", "users", ..]) | (&Method::POST, ["devices"])
+    )
+}
+
+/// A body as it is recorded, with the values of its `CREDENTIAL_FIELDS` masked if it is JSON.
+/// Bodies without credentials are recorded as they were sent.
+fn recorded_body(body: &[u8]) -> RecordedBody {
+    if let Ok(mut json) = serde_json::from_slice::<Value>(body) {
+        if mask_credentials(&mut json) {
+            return RecordedBody::new(json.to_string().as_bytes());
+        }
+    }
+    RecordedBody::new(body)
+}
+
+/// Masks the values of the `CREDENTIAL_FIELDS` of a JSON value, at any depth. Returns whether
+/// there were any.
+fn mask_credentials(json: &mut Value) -> bool {
+    match json {
+        Value::Object(fields) => {
+            let mut masked = false;
+            for (name, value) in fields.iter_mut() {
+                if CREDENTIAL_FIELDS.contains(&name.as_str()) {
+                    *value = MASKED_CREDENTIAL.into();
+                    masked = true;
+                } else {
+                    masked |= mask_credentials(value);
+                }
+            }
+            masked
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .fold(false, |masked, value| mask_credentials(value) | masked),
+        _ => false,
+    }
+}
+
+/// The headers of a request as they are recorded: by name, with the values of repeated headers
+/// joined with commas, and without those which carry credentials.
+fn recorded_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut recorded: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        if CREDENTIAL_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        let value = String::from_utf8_lossy(value.as_bytes());
+        recorded
+            .entry(name.to_string())
+            .and_modify(|values| {
+                values.push_str(", ");
+                values.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+    recorded
+}
+
+/// Compresses responses with gzip for clients which accept it, if the body is at least
+/// `min_size` bytes of text or JSON. Files which are compressed already, such as PDFs and Parquet
+/// files, are sent as they are.
+#[derive(Debug, Clone)]
+pub struct CompressionLayer {
+    min_size: u64,
+}
+
+impl CompressionLayer {
+    pub fn new(min_size: u64) -> Self {
+        Self { min_size }
+    }
+}
+
+impl<S> Layer<S> for CompressionLayer {
+    type Service = Compress<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Compress {
+            inner,
+            min_size: self.min_size,
+        }
+    }
+}
+
+/// The service of `CompressionLayer`.
+#[derive(Debug, Clone)]
+pub struct Compress<S> {
+    inner: S,
+    min_size: u64,
+}
+
+impl<S: Inner<B>, B: Send + 'static> Service<Request<B>> for Compress<S> {
+    type Response = Response<BoxBody<Bytes, hyper::Error>>;
+    type Error = hyper::Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let mut inner = take(&mut self.inner);
+        let min_size = self.min_size;
+        let gzip = accepts_gzip(
+            req.headers()
+                .get(ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok()),
+        );
+        Box::pin(async move {
+            let response = inner.call(req).await?;
+            if !gzip || !compressible(&response, min_size) {
+                return Ok(response);
+            }
+            let (mut parts, body) = response.into_parts();
+            let body = body.collect().await?.to_bytes();
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            match encoder.write_all(&body).and_then(|_| encoder.finish()) {
+                Ok(compressed) => {
+                    parts
+                        .headers
+                        .insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+                    parts.headers.remove(CONTENT_LENGTH);
+                    parts
+                        .headers
+                        .append(VARY, HeaderValue::from_static("accept-encoding"));
+                    Ok(Response::from_parts(parts, full(compressed)))
+                }
+                Err(e) => {
+                    error!("Could not compress a response: {}", e);
+                    Ok(Response::from_parts(parts, full(body)))
+                }
+            }
+        })
+    }
+}
+
+/// Whether an `Accept-Encoding` header allows gzip, i.e. lists `gzip` or `*` without `q=0`.
+fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    accept_encoding.is_some_and(|header| {
+        header.split(',').any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+    })
+}
+
+/// Whether a response is worth compressing: an uncompressed body of text or JSON, whose size is
+/// known up front and at least `min_size` bytes.
+fn compressible(response: &Response<BoxBody<Bytes, hyper::Error>>, min_size: u64) -> bool {
+    let content_type = response
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let textual = content_type.starts_with("text/")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("image/svg+xml");
+    textual
+        && !response.headers().contains_key(CONTENT_ENCODING)
+        && response
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|size| size >= min_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::middleware::*;
+    use crate::services::{json, mock_request as request, response_json, MockBody};
+    use flate2::read::GzDecoder;
+    use labwhere::metrics::slo::LatencySlo;
+    use std::io::Read;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+
+    async fn echo(
+        req: Request<MockBody>,
+    ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+        Ok(json(
+            StatusCode::OK,
+            &serde_json::json!({ "path": req.uri().path().repeat(200) }),
+        ))
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip(Some("gzip")));
+        assert!(accepts_gzip(Some("deflate, GZIP;q=0.5")));
+        assert!(accepts_gzip(Some("*")));
+        assert!(!accepts_gzip(Some("gzip;q=0")));
+        assert!(!accepts_gzip(Some("br")));
+        assert!(!accepts_gzip(None));
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let service = ServiceBuilder::new()
+            .layer(CompressionLayer::new(1024))
+            .service(service_fn(echo));
+
+        let mut req = request("GET", "/labwares", b"");
+        req.headers_mut()
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"));
+        let res = service.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(res.headers()[VARY], "accept-encoding");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let mut decompressed = String::new();
+        GzDecoder::new(&body[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert!(decompressed.starts_with(r#"{"path":"/labwares/labwares"#));
+
+        // Without Accept-Encoding the response is sent as it is
+        let res = service
+            .clone()
+            .oneshot(request("GET", "/labwares", b""))
+            .await
+            .unwrap();
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert!(response_json(res).await["path"].is_string());
+
+        // Neither are small responses
+        let mut req = request("GET", "/", b"");
+        req.headers_mut()
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        let res = service.oneshot(req).await.unwrap();
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let service = ServiceBuilder::new()
+            .layer(TimeoutLayer::new(
+                Duration::from_millis(10),
+                |method, segments| method == Method::POST && segments == ["scan"],
+            ))
+            .service(service_fn(|req: Request<MockBody>| async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                echo(req).await
+            }));
+
+        let res = service
+            .clone()
+            .oneshot(request("GET", "/labwares/lw-1", b""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "The request took longer than 0.01 seconds"
+        );
+
+        let res = service
+            .oneshot(request("POST", "/scan", b""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_slo() {
+        let slo = LatencySlo::parse("GET /labwares/*=p50<50ms").unwrap();
+        let tracker = Box::leak(Box::new(SloTracker::new(
+            vec![slo],
+            Duration::from_secs(3600),
+        )));
+        let service = ServiceBuilder::new()
+            .layer(SloLayer::new(tracker))
+            .service(service_fn(|req: Request<MockBody>| async move {
+                if req.uri().path() == "/labwares/slow" {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                echo(req).await
+            }));
+
+        for path in [
+            "/labwares/lw-1",
+            "/labwares/slow",
+            "/labwares/slow",
+            "/scan",
+        ] {
+            service
+                .clone()
+                .oneshot(request("GET", path, b""))
+                .await
+                .unwrap();
+        }
+        let status = &tracker.statuses()[0];
+        assert_eq!((status.requests, status.slow), (3, 2));
+        assert!(!status.met);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let breaker = Box::leak(Box::new(CircuitBreaker::new(1, Duration::from_secs(30))));
+        let service = ServiceBuilder::new()
+            .layer(CircuitBreakerLayer::new(breaker))
+            .service(service_fn(echo));
+
+        let res = service
+            .clone()
+            .oneshot(request("GET", "/labwares/lw-1", b""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+
+        breaker.record_failure(&sqlx::Error::PoolTimedOut);
+        let res = service
+            .clone()
+            .oneshot(request("GET", "/labwares/lw-1", b""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.headers()["retry-after"], "30");
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "The database is unavailable, please try again in 30 seconds"
+        );
+
+        breaker.record_success();
+        let res = service
+            .oneshot(request("GET", "/labwares/lw-1", b""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency() {
+        let store = Box::leak(Box::new(IdempotencyStore::new(Duration::from_secs(60))));
+        let calls = Box::leak(Box::new(std::sync::atomic::AtomicUsize::new(0)));
+        let service = ServiceBuilder::new()
+            .layer(IdempotencyLayer::new(store))
+            .service(service_fn(|req: Request<MockBody>| {
+                let call = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async move {
+                    let status = match req.uri().path() {
+                        "/fail" => StatusCode::SERVICE_UNAVAILABLE,
+                        _ => StatusCode::CREATED,
+                    };
+                    Ok::<_, hyper::Error>(json(status, &serde_json::json!({ "call": call })))
+                }
+            }));
+        let with_key = |method, path, key| {
+            let mut req = request(method, path, b"");
+            req.headers_mut()
+                .insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_static(key));
+            req
+        };
+
+        let res = service
+            .clone()
+            .oneshot(with_key("POST", "/labwares", "k1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(response_json(res).await["call"], 0);
+
+        // A retry is answered with the first response
+        let res = service
+            .clone()
+            .oneshot(with_key("POST", "/labwares", "k1"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers()[IDEMPOTENT_REPLAYED_HEADER], "true");
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(response_json(res).await["call"], 0);
+
+        // Another key, another path, a read or a server error are handled as usual
+        for (req, call) in [
+            (with_key("POST", "/labwares", "k2"), 1),
+            (with_key("POST", "/scan", "k1"), 2),
+            (with_key("GET", "/labwares", "k1"), 3),
+            (with_key("POST", "/fail", "k1"), 4),
+            (with_key("POST", "/fail", "k1"), 5),
+        ] {
+            let res = service.clone().oneshot(req).await.unwrap();
+            assert!(!res.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER));
+            assert_eq!(response_json(res).await["call"], call);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recorder() {
+        let recorder = Box::leak(Box::new(RequestRecorder::new(10)));
+        let service = ServiceBuilder::new()
+            .layer(RecorderLayer::new(recorder, |method, segments| {
+                method == Method::POST && segments == ["manifests"]
+            }))
+            .service(service_fn(
+                |req: Request<BoxBody<Bytes, hyper::Error>>| async move {
+                    let body = req.into_body().collect().await?.to_bytes();
+                    Ok::<_, hyper::Error>(Response::new(full(body)))
+                },
+            ));
+        let payload = br#"{"location_barcode": "lw-freezer1-1"}"#;
+        let mut req = request("POST", "/scan?user_code=jd12", payload);
+        req.headers_mut()
+            .insert(LOCK_TOKEN_HEADER, HeaderValue::from_static("secret"));
+        req.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        // The endpoint still reads the body
+        let res = service.clone().oneshot(req).await.unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], payload);
+        for (method, path) in [("POST", "/manifests"), ("GET", "/admin/debug/requests")] {
+            service
+                .clone()
+                .oneshot(request(method, path, b"labware,location\n"))
+                .await
+                .unwrap();
+        }
+
+        let recorded = recorder.recent();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].path, "/manifests");
+        assert_eq!(recorded[0].request_body, None);
+        assert_eq!(recorded[1].path, "/scan?user_code=jd12");
+        assert_eq!(recorded[1].status, 200);
+        assert_eq!(
+            recorded[1].request_headers["content-type"],
+            "application/json"
+        );
+        assert!(!recorded[1].request_headers.contains_key(LOCK_TOKEN_HEADER));
+        let request_body = recorded[1].request_body.as_ref().unwrap();
+        assert_eq!(request_body.content.as_bytes(), payload);
+        assert_eq!(recorded[1].response_body.as_ref(), Some(request_body));
+
+        let payload = br#"{"user_code": "jd12", "device": {"key": "dk-1", "name": "bench"}}"#;
+        service
+            .clone()
+            .oneshot(request("POST", "/scan", payload))
+            .await
+            .unwrap();
+        let recorded = &recorder.recent()[0];
+        let request_body: serde_json::Value =
+            serde_json::from_str(&recorded.request_body.as_ref().unwrap().content).unwrap();
+        assert_eq!(
+            request_body,
+            serde_json::json!({
+                "user_code": "[FILTERED]",
+                "device": {"key": "[FILTERED]", "name": "bench"},
+            })
+        );
+        assert_eq!(recorded.response_body, recorded.request_body);
+    }
+
+    #[tokio::test]
+    async fn test_recorder_leaves_out_credentials() {
+        let pool = labwhere::db::init_pool("sqlite::memory:").await.unwrap();
+        let recorder = Box::leak(Box::new(RequestRecorder::new(10)));
+        let service = ServiceBuilder::new()
+            .layer(RecorderLayer::new(recorder, |_, _| false))
+            .service(service_fn(move |req| {
+                crate::services::router::route(req, pool.clone())
+            }));
+
+        let res = service
+            .clone()
+            .oneshot(request("POST", "/admin/api_keys", br#"{"name": "lims"}"#))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        let created = response_json(res).await;
+        let res = service
+            .clone()
+            .oneshot(request(
+                "POST",
+                &format!(
+                    "/admin/api_keys/{}/rotate",
+                    created["uuid"].as_str().unwrap()
+                ),
+                b"",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let rotated = response_json(res).await;
+
+        let recorded = recorder.recent();
+        assert_eq!(recorded.len(), 2);
+        assert!(recorded
+            .iter()
+            .all(|exchange| exchange.request_body.is_none() && exchange.response_body.is_none()));
+        let recorded = serde_json::to_string(&recorded).unwrap();
+        for key in [&created["key"], &rotated["key"]] {
+            assert!(!recorded.contains(key.as_str().unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin() {
+        let config = Box::leak(Box::new(Config::default()));
+        let service = ServiceBuilder::new()
+            .layer(AdminLayer::new(config))
+            .service(service_fn(echo));
+
+        let res = service
+            .clone()
+            .oneshot(request("GET", "/admin/users", b""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+
+        for (method, path) in [
+            ("POST", "/devices"),
+            ("PUT", "/devices/d-1/config"),
+            ("POST", "/devices/d-1/disable"),
+            ("POST", "/sync/changes"),
+            ("POST", "/sync/conflicts/c-1/resolve"),
+        ] {
+            let res = service
+                .clone()
+                .oneshot(request(method, path, b""))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 403, "{} {}", method, path);
+        }
+        let req = Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("X-Act-As-User", "jd12")
+            .body(MockBody::new(b""))
+            .unwrap();
+        assert_eq!(service.clone().oneshot(req).await.unwrap().status(), 403);
+
+        for (method, path) in [
+            ("GET", "/labwares/admin"),
+            ("GET", "/devices"),
+            ("POST", "/devices/heartbeat"),
+            ("GET", "/sync/changes"),
+        ] {
+            let res = service
+                .clone()
+                .oneshot(request(method, path, b""))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 200, "{} {}", method, path);
+        }
+
+        let config = Box::leak(Box::new(Config {
+            admin_tokens: vec!["secret".to_string()],
+            ..Config::default()
+        }));
+        let service = ServiceBuilder::new()
+            .layer(AdminLayer::new(config))
+            .service(service_fn(echo));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/devices")
+            .header("X-Admin-Token", "secret")
+            .body(MockBody::new(b""))
+            .unwrap();
+        assert_eq!(service.oneshot(req).await.unwrap().status(), 200);
+    }
+}
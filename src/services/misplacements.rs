@@ -0,0 +1,154 @@
+use crate::services::{json, map_error, query_params, read_json, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::misplacement::Misplacement;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for reviewing a misplacement.
+#[derive(Debug, Deserialize, Validate)]
+struct MisplacementReview {
+    /// Who reviewed the misplacement
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+}
+
+/// Lists (`GET`) the misplacements scans have corrected, for QC to review.
+///
+/// `GET /misplacements` responds with every labware scanned into a location while it was recorded
+/// in another one and not checked out, the latest first, with the `recorded_location` and the
+/// `found_location`. `?reviewed=false` only lists those which have not been reviewed yet, and
+/// `?reviewed=true` those which have.
+pub async fn misplacements(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /misplacements endpoint");
+    let reviewed = query_params(&req)
+        .get("reviewed")
+        .map(|reviewed| reviewed == "true");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match Misplacement::report(reviewed, &mut connection).await {
+        Ok(misplacements) => Ok(json(StatusCode::OK, &misplacements)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Marks (`POST`) a misplacement as reviewed.
+///
+/// `POST /misplacements/{uuid}/review` with `{"user": "jane"}` responds with the reviewed
+/// misplacement, or with 422 if it was already reviewed.
+pub async fn review(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /misplacements/{}/review endpoint",
+        uuid
+    );
+    let payload = match read_json::<MisplacementReview>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let misplacement = match Misplacement::find_by_uuid(uuid, &mut connection).await {
+        Ok(misplacement) => misplacement,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match misplacement.review(payload.user, &mut connection).await {
+        Ok(misplacement) => Ok(json(StatusCode::OK, &misplacement)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::factories::{LabwareFactory, LocationFactory};
+
+    #[tokio::test]
+    async fn test_misplacements() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let freezer = LocationFactory::new()
+            .with_name("freezer")
+            .create(&pool)
+            .await
+            .unwrap();
+        let fridge = LocationFactory::new()
+            .with_name("fridge")
+            .create(&pool)
+            .await
+            .unwrap();
+        LabwareFactory::new()
+            .with_barcode("lw-1")
+            .with_location(&freezer)
+            .create(&pool)
+            .await
+            .unwrap();
+        let body = format!(
+            r#"{{"location_barcode": "{}", "labware_barcodes": ["lw-1"]}}"#,
+            fridge.barcode.unwrap()
+        );
+        let body: &'static [u8] = Box::leak(body.into_bytes().into_boxed_slice());
+
+        let res = handle(request("POST", "/scan", body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let scan = response_json(res).await;
+        assert_eq!(scan["misplacements"][0]["recorded_location"], "freezer");
+        assert_eq!(scan["misplacements"][0]["found_location"], "fridge");
+
+        let res = handle(
+            request("GET", "/misplacements?reviewed=false", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let misplacements = response_json(res).await;
+        assert_eq!(misplacements[0]["labware_barcode"], "lw-1");
+        let uuid = misplacements[0]["uuid"].as_str().unwrap();
+
+        let path = format!("/misplacements/{}/review", uuid);
+        let res = handle(request("POST", &path, br#"{"user": "qc"}"#), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await["reviewed_by"], "qc");
+        let res = handle(request("POST", &path, br#"{"user": "qc"}"#), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request("GET", "/misplacements?reviewed=false", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response_json(res).await, serde_json::json!([]));
+
+        let res = handle(
+            request(
+                "POST",
+                "/misplacements/unknown/review",
+                br#"{"user": "qc"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+}
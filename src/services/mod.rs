@@ -1,8 +1,159 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use fluent_templates::LanguageIdentifier;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use http_body_util::channel::Channel;
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Empty};
-use hyper::body::Bytes;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Body, Bytes};
+use hyper::{header, Method, Request, Response, StatusCode};
+use labwhere::cache::{IDEMPOTENT_RESPONSES, RECORDED_REQUESTS};
+use labwhere::config::CONFIG;
+use labwhere::db::health::{self, DB_CIRCUIT};
+use labwhere::errors::{
+    FieldValidationError, ForbiddenError, InvalidBarcodeError, LockedError, NotFoundError,
+    ValidationError,
+};
+use labwhere::i18n::{Message, DEFAULT_LOCALE};
+use labwhere::metrics::slo::LATENCY_SLOS;
+use log::{error, warn};
+use middleware::{
+    AdminLayer, AuthLayer, CircuitBreakerLayer, CompressionLayer, IdempotencyLayer, LocaleLayer,
+    RateLimitLayer, RecorderLayer, SloLayer, TimeoutLayer,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::{Sqlite, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use std::error::Error;
+use std::mem;
+use std::time::Duration;
+use tower::util::BoxCloneService;
+use tower::{service_fn, ServiceBuilder, ServiceExt};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::Level;
+use validator::Validate;
 
+pub mod admin;
+pub mod api_keys;
+pub mod auth;
+pub mod checkouts;
+pub mod coalesce;
+pub mod confirmations;
+pub mod dead_letters;
+pub mod devices;
+pub mod events;
+pub mod kiosk;
+pub mod labwares;
+pub mod links;
+pub mod location_types;
+pub mod locations;
+pub mod manifests;
+pub mod metrics;
+pub mod middleware;
+pub mod misplacements;
+pub mod pending_labwares;
+pub mod print_jobs;
+pub mod receipts;
+pub mod reservations;
+pub mod router;
+pub mod saved_searches;
 pub mod scan;
+pub mod scan_payload;
+pub mod search;
+pub mod shipments;
+pub mod stats;
+pub mod stocktakes;
+pub mod sync;
+pub mod upload_mappings;
+pub mod users;
+pub mod validate;
+
+/// The size of the chunks a streamed JSON array is sent in, in bytes.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Responses with bodies smaller than this many bytes are not worth compressing.
+const COMPRESSION_MIN_SIZE: u64 = 1024;
+
+/// The result every service function resolves to.
+pub(crate) type ServiceResponse = Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>;
+
+tokio::task_local! {
+    /// The locale picked from the `Accept-Language` header of the request being handled.
+    static LOCALE: LanguageIdentifier;
+}
+
+/// Returns the locale of the request being handled, or the default locale outside of a request
+/// (e.g. when a service function is called directly in tests).
+pub(crate) fn current_locale() -> LanguageIdentifier {
+    LOCALE
+        .try_with(LanguageIdentifier::clone)
+        .unwrap_or_else(|_| DEFAULT_LOCALE.clone())
+}
+
+/// Builds the stack of middleware every request passes through on its way to the endpoint.
+///
+/// From the outside in, requests are traced and timed against the latency objectives of their
+/// route, their responses compressed, they are recorded with their responses if
+/// `LABWHERE_DEBUG_RECORDER_CAPACITY` is set, the locale to respond in is picked from the
+/// `Accept-Language` header, requests are refused while the database is down, slow requests are
+/// given up on, the auth policy is applied (admin-only
+/// requests needing an admin token), API keys are metered and retries of requests with an
+/// idempotency key are answered with the first response, before the request is routed to the
+/// service function of the matching endpoint.
+pub fn service<B>(
+    pool: SqlitePool,
+) -> BoxCloneService<Request<B>, Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>
+where
+    B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static,
+{
+    let timeout = Duration::from_secs(CONFIG.request_timeout_seconds);
+    ServiceBuilder::new()
+        .map_response(|response: Response<_>| response.map(BodyExt::boxed))
+        .layer(
+            TraceLayer::new_for_http().on_response(
+                DefaultOnResponse::new()
+                    .level(Level::INFO)
+                    .latency_unit(LatencyUnit::Millis),
+            ),
+        )
+        .layer(SloLayer::new(&LATENCY_SLOS))
+        .layer(CompressionLayer::new(COMPRESSION_MIN_SIZE))
+        .layer(RecorderLayer::new(&RECORDED_REQUESTS, long_running))
+        .layer(LocaleLayer::default())
+        .layer(CircuitBreakerLayer::new(&DB_CIRCUIT))
+        .option_layer((!timeout.is_zero()).then(|| TimeoutLayer::new(timeout, long_running)))
+        .layer(AuthLayer::new(&CONFIG, pool.clone()))
+        .layer(AdminLayer::new(&CONFIG))
+        .layer(RateLimitLayer::new(&CONFIG, pool.clone()))
+        .layer(IdempotencyLayer::new(&IDEMPOTENT_RESPONSES))
+        .service(service_fn(move |req| router::route(req, pool.clone())))
+        .boxed_clone()
+}
+
+/// The global service handler.
+///
+/// Passes the request through the middleware (see `service`) to the service function of the
+/// matching endpoint.
+pub async fn handle(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    service(pool).oneshot(req).await
+}
+
+/// Whether a request is exempt from `LABWHERE_REQUEST_TIMEOUT_SECONDS`, as it streams an upload
+/// which may take minutes. The bodies of these requests are not recorded either.
+fn long_running(method: &Method, segments: &[&str]) -> bool {
+    matches!((method, segments), (&Method::POST, ["manifests"]))
+}
+
+/// Splits a path into its segments, e.g. `["labwares", "lw-1"]` for `/labwares/lw-1`.
+pub(crate) fn segments(path: &str) -> Vec<&str> {
+    path.trim_matches('/').split('/').collect()
+}
 
 /// An empty function visible only to the crate scope that returns a
 /// boxed empty response. This can be used for 404 error responses.
@@ -11,3 +162,396 @@ pub(crate) fn empty() -> BoxBody<Bytes, hyper::Error> {
         .map_err(|never| match never {})
         .boxed()
 }
+
+/// Returns a boxed body containing the given chunk.
+pub(crate) fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// Returns a 200 response with a JSON array of the rows `rows` streams from the connection.
+///
+/// The rows are serialized and sent in chunks of about `STREAM_CHUNK_SIZE` bytes as they are read,
+/// so memory stays flat however many rows there are. If reading the rows fails part way through,
+/// the error is logged and the array is cut short, leaving the body invalid JSON.
+pub(crate) fn json_stream<T, F>(
+    mut connection: PoolConnection<Sqlite>,
+    rows: F,
+) -> Response<BoxBody<Bytes, hyper::Error>>
+where
+    T: Serialize + Send,
+    F: for<'c> FnOnce(&'c mut SqliteConnection) -> BoxStream<'c, Result<T, sqlx::Error>>
+        + Send
+        + 'static,
+{
+    let (mut sender, body) = Channel::<Bytes, hyper::Error>::new(1);
+    tokio::spawn(async move {
+        let mut rows = rows(&mut connection);
+        let mut chunk = b"[".to_vec();
+        let mut empty = true;
+        while let Some(row) = rows.next().await {
+            if !empty {
+                chunk.push(b',');
+            }
+            empty = false;
+            if let Err(e) = row
+                .map_err(Box::<dyn Error + Send + Sync>::from)
+                .and_then(|row| Ok(serde_json::to_writer(&mut chunk, &row)?))
+            {
+                error!("Could not stream rows: {}", e);
+                return;
+            }
+            // Sending only fails once the client has gone away
+            if chunk.len() >= STREAM_CHUNK_SIZE
+                && sender
+                    .send_data(mem::take(&mut chunk).into())
+                    .await
+                    .is_err()
+            {
+                return;
+            }
+        }
+        chunk.push(b']');
+        let _ = sender.send_data(chunk.into()).await;
+    });
+    let mut response = Response::new(body.boxed());
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+/// Returns a response with the given status and the value serialized as JSON.
+pub(crate) fn json<T: Serialize>(
+    status: StatusCode,
+    value: &T,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    match serde_json::to_vec(value) {
+        Ok(body) => {
+            let mut response = Response::new(full(body));
+            *response.status_mut() = status;
+            response.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        Err(e) => {
+            error!("Could not serialize response: {:?}", e);
+            status_only(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Returns a 200 response with an HTML page.
+pub(crate) fn html(body: String) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(full(body));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    response
+}
+
+/// Returns a 200 response with an SVG image.
+pub(crate) fn svg(body: String) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(full(body));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("image/svg+xml"),
+    );
+    response
+}
+
+/// Returns a 200 response with a PDF document, shown in the browser with the given file name.
+pub(crate) fn pdf(body: Vec<u8>, filename: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(full(body));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/pdf"),
+    );
+    if let Ok(disposition) =
+        hyper::header::HeaderValue::from_str(&format!("inline; filename=\"{}\"", filename))
+    {
+        response
+            .headers_mut()
+            .insert(hyper::header::CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+/// Returns a 200 response with a Parquet body, downloaded as an attachment with the given file
+/// name.
+pub(crate) fn parquet(body: Vec<u8>, filename: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(full(body));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/vnd.apache.parquet"),
+    );
+    if let Ok(disposition) =
+        hyper::header::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+    {
+        response
+            .headers_mut()
+            .insert(hyper::header::CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+/// Returns a 200 response with a CSV body, downloaded as an attachment with the given file name.
+pub(crate) fn csv(body: String, filename: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(full(body));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/csv; charset=utf-8"),
+    );
+    if let Ok(disposition) =
+        hyper::header::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+    {
+        response
+            .headers_mut()
+            .insert(hyper::header::CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+/// Returns a 200 response with metrics in the Prometheus text format.
+pub(crate) fn prometheus(body: String) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(full(body));
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+    );
+    response
+}
+
+/// Returns an empty response with the given status.
+pub(crate) fn status_only(status: StatusCode) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = Response::new(empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Returns an empty 405 response to a request whose method the endpoint does not support, listing
+/// the methods it does support in the `Allow` header.
+pub(crate) fn method_not_allowed(allowed: &[Method]) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut response = status_only(StatusCode::METHOD_NOT_ALLOWED);
+    let allow = allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Ok(allow) = header::HeaderValue::from_str(&allow) {
+        response.headers_mut().insert(header::ALLOW, allow);
+    }
+    response
+}
+
+/// Returns a JSON error response of the form `{"errors": ["message"]}`.
+pub(crate) fn error_response(
+    status: StatusCode,
+    message: String,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    json(status, &serde_json::json!({ "errors": [message] }))
+}
+
+/// Returns a 503 response with the message, telling the client in the `Retry-After` header to try
+/// again after `retry_after`, rounded up to a whole number of seconds.
+pub(crate) fn unavailable(
+    message: Message,
+    retry_after: Duration,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let mut response = error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        message.arg("seconds", seconds).localize(&current_locale()),
+    );
+    response
+        .headers_mut()
+        .insert(hyper::header::RETRY_AFTER, seconds.into());
+    response
+}
+
+/// Maps an error returned by the library crate to a JSON error response, with the error messages
+/// in the locale of the request.
+///
+/// - `NotFoundError` responds with 404
+/// - `ValidationError` and `InvalidBarcodeError` respond with 422
+/// - `FieldValidationError` responds with 422, with the messages of each invalid field under
+///   `fields`
+/// - `ForbiddenError` responds with 403
+/// - `LockedError` responds with 423
+/// - A database which is locked or cannot be reached responds with 503 and a `Retry-After` header
+///   (see `db::health`)
+/// - Anything else (e.g. a database error) responds with 500, without exposing the cause
+pub(crate) fn map_error(
+    err: &(dyn Error + Send + Sync + 'static),
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let locale = current_locale();
+    if let Some(err) = err.downcast_ref::<FieldValidationError>() {
+        json(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &serde_json::json!({
+                "errors": err.messages(&locale),
+                "fields": err.localize_fields(&locale),
+            }),
+        )
+    } else if let Some(err) = err.downcast_ref::<NotFoundError>() {
+        error_response(StatusCode::NOT_FOUND, err.message.localize(&locale))
+    } else if let Some(err) = err.downcast_ref::<ValidationError>() {
+        error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            err.message.localize(&locale),
+        )
+    } else if let Some(err) = err.downcast_ref::<InvalidBarcodeError>() {
+        error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            err.message.localize(&locale),
+        )
+    } else if let Some(err) = err.downcast_ref::<ForbiddenError>() {
+        error_response(StatusCode::FORBIDDEN, err.message.localize(&locale))
+    } else if let Some(err) = err.downcast_ref::<LockedError>() {
+        error_response(StatusCode::LOCKED, err.message.localize(&locale))
+    } else if let Some(err) = err
+        .downcast_ref::<sqlx::Error>()
+        .filter(|err| health::is_transient(err))
+    {
+        warn!("Gave up on a locked database: {}", err);
+        unavailable(Message::new("database-busy"), Duration::from_secs(1))
+    } else if let Some(err) = err
+        .downcast_ref::<sqlx::Error>()
+        .filter(|err| health::is_unavailable(err))
+    {
+        error!("Database unavailable: {}", err);
+        // Failures to acquire a connection are recorded by `acquire`
+        if !matches!(err, sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed) {
+            DB_CIRCUIT.record_failure(err);
+        }
+        unavailable(
+            Message::new("database-unavailable"),
+            DB_CIRCUIT.retry_after().unwrap_or(Duration::from_secs(1)),
+        )
+    } else {
+        error!("Internal error: {:?}", err);
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Message::new("error-internal").localize(&locale),
+        )
+    }
+}
+
+/// Returns the decoded query string parameters of a request.
+pub(crate) fn query_params<B>(req: &Request<B>) -> HashMap<String, String> {
+    req.uri()
+        .query()
+        .map(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads a point in time from a query string parameter, e.g. `?at=2024-03-03T12:00Z`.
+///
+/// The time is an RFC 3339 timestamp (seconds may be left out), or a date for the end of that day
+/// (UTC), and defaults to now if the parameter is missing. Otherwise the error is the message to
+/// respond with 400 with.
+pub(crate) fn time_param<B>(req: &Request<B>, name: &str) -> Result<DateTime<Utc>, String> {
+    let Some(value) = query_params(req).remove(name) else {
+        return Ok(Utc::now());
+    };
+    let time = DateTime::parse_from_rfc3339(&value)
+        .or_else(|_| DateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M%#z"))
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(23, 59, 59)
+                .map(|time| time.and_utc())
+        });
+    time.ok_or_else(|| {
+        Message::new("time-invalid")
+            .arg("time", value)
+            .localize(&current_locale())
+    })
+}
+
+/// Reads the request body, deserializes it from JSON and validates it.
+///
+/// Responds with 400 if the body cannot be read or is not valid JSON for the expected type, and
+/// with 422 if it fails validation.
+pub(crate) async fn read_json<T: DeserializeOwned + Validate>(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error>>,
+) -> Result<T, Response<BoxBody<Bytes, hyper::Error>>> {
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))?
+        .to_bytes();
+    let payload: T = serde_json::from_slice(&body)
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))?;
+    payload
+        .validate()
+        .map_err(|e| map_error(&FieldValidationError::from(e)))?;
+    Ok(payload)
+}
+
+/// `MockBody` is a utility body written **only** for tests.
+#[cfg(test)]
+pub(crate) struct MockBody {
+    data: &'static [u8],
+}
+
+#[cfg(test)]
+impl MockBody {
+    pub(crate) fn new(data: &'static [u8]) -> Self {
+        Self { data }
+    }
+}
+
+#[cfg(test)]
+impl hyper::body::Body for MockBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<hyper::Result<http_body::Frame<Bytes>>>> {
+        if self.data.is_empty() {
+            std::task::Poll::Ready(None)
+        } else {
+            let data = self.data;
+            self.data = &[];
+            std::task::Poll::Ready(Some(Ok(http_body::Frame::data(Bytes::from(data)))))
+        }
+    }
+}
+
+/// Builds a request with a `MockBody` **only** for tests.
+#[cfg(test)]
+pub(crate) fn mock_request(
+    method: &str,
+    uri: &str,
+    body: &'static [u8],
+) -> hyper::Request<MockBody> {
+    hyper::Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(MockBody::new(body))
+        .unwrap()
+}
+
+/// Reads a response body into JSON **only** for tests.
+#[cfg(test)]
+pub(crate) async fn response_json(
+    response: Response<BoxBody<Bytes, hyper::Error>>,
+) -> serde_json::Value {
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
@@ -0,0 +1,159 @@
+use crate::services::{json, map_error, query_params, read_json, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::errors::ValidationError;
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::pending_labware::{PendingLabware, PendingStatus};
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for approving or rejecting a pending labware.
+#[derive(Debug, Deserialize, Validate)]
+struct ReviewPendingLabware {
+    /// Who reviews the labware
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+}
+
+/// Lists (`GET`) the unknown labware barcodes quarantined by scans under the `quarantine`
+/// registration policy.
+///
+/// `GET /admin/pending_labwares?status=pending` responds with the pending labwares, newest first,
+/// each with the scanned `barcode`, the `location_barcode` it was scanned into and its `status`
+/// (`pending`, `approved` or `rejected`). Those still pending list the `similar_barcodes` of known
+/// labwares one typo away, if any. Leaving out `status` lists every pending labware. Requires one
+/// of the configured admin tokens in the `X-Admin-Token` header, otherwise the response is 403.
+pub async fn pending_labwares(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /admin/pending_labwares endpoint");
+    let status = match query_params(&req).get("status") {
+        Some(status) => match PendingStatus::from_name(status) {
+            Some(status) => Some(status),
+            None => {
+                return Ok(map_error(&ValidationError {
+                    message: Message::new("pending-labware-unknown-status").arg("status", status),
+                }))
+            }
+        },
+        None => None,
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match PendingLabware::list(status, &mut connection).await {
+        Ok(pending_labwares) => Ok(json(StatusCode::OK, &pending_labwares)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Approves or rejects (`POST`) a pending labware.
+///
+/// `POST /admin/pending_labwares/{uuid}/approve` with `{"user": "jane"}` creates the labware in the
+/// location it was scanned into, and `POST /admin/pending_labwares/{uuid}/reject` drops the
+/// barcode, e.g. as a typo. Responds with the reviewed labware, or with 422 if it was already
+/// reviewed. Requires one of the configured admin tokens in the `X-Admin-Token` header, otherwise
+/// the response is 403.
+pub async fn review(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+    status: PendingStatus,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/pending_labwares/{}/{} endpoint",
+        uuid,
+        match status {
+            PendingStatus::Rejected => "reject",
+            _ => "approve",
+        }
+    );
+    let payload = match read_json::<ReviewPendingLabware>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match PendingLabware::review(uuid, status, payload.user, &mut connection).await {
+        Ok(pending) => Ok(json(StatusCode::OK, &pending)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::models::pending_labware::{PendingLabware, PendingStatus};
+
+    #[tokio::test]
+    async fn test_review_pending_labwares() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let location = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let pending = PendingLabware::queue("lw-1", location.id, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let res = super::pending_labwares(
+            request("GET", "/admin/pending_labwares?status=pending", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["barcode"], "lw-1");
+        assert_eq!(body[0]["location_barcode"], location.barcode.unwrap());
+
+        let res = super::pending_labwares(
+            request("GET", "/admin/pending_labwares?status=lost", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = super::review(
+            request("POST", "/", br#"{"user": "jane"}"#),
+            pool.clone(),
+            &pending.uuid,
+            PendingStatus::Approved,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await["status"], "approved");
+
+        let res = super::review(
+            request("POST", "/", br#"{"user": "jane"}"#),
+            pool.clone(),
+            &pending.uuid,
+            PendingStatus::Rejected,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let path = format!("/admin/pending_labwares/{}/reject", pending.uuid);
+        let res = handle(request("POST", &path, br#"{"user": "jane"}"#), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+}
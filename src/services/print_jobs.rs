@@ -0,0 +1,449 @@
+use crate::services::{
+    current_locale, error_response, json, map_error, method_not_allowed, query_params, read_json,
+    ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::i18n::Message;
+use labwhere::labels::LabelTemplate;
+use labwhere::metrics::acquire;
+use labwhere::models::print_job::{PrintJob, PrintJobState};
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for queueing a print job.
+#[derive(Debug, Deserialize, Validate)]
+struct NewPrintJob {
+    /// The name of the printer
+    #[validate(length(min = 1, message = "validation-blank"))]
+    printer: String,
+    /// The barcodes of the locations whose labels should be printed
+    #[validate(length(min = 1, message = "validation-empty"))]
+    locations: Vec<String>,
+    /// The label template e.g. `barcode`, `qr` or `datamatrix-13mm`. Defaults to the template configured for the printer.
+    template: Option<LabelTemplate>,
+}
+
+/// The payload for reporting the state of a print job.
+#[derive(Debug, Deserialize, Validate)]
+struct PrintJobReport {
+    /// The state the job is in now: `printing`, `done`, `failed`, or `queued` to retry a failed job
+    state: String,
+    /// Why the job failed, for the `failed` state
+    #[validate(length(min = 1, message = "validation-blank"))]
+    error: Option<String>,
+}
+
+/// Lists (`GET`) or queues (`POST`) print jobs.
+///
+/// - `GET /print_jobs` responds with all print jobs, newest first. `?state=failed` only lists jobs in
+///   the given state.
+/// - `POST /print_jobs` with `{"printer": "printer-1", "locations": ["lw-freezer-1"]}` queues a job
+///   and responds with 201. `"template": "qr"` prints QR code labels instead of the template
+///   configured for the printer.
+pub async fn print_jobs(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /print_jobs endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => {
+            let state = match query_params(&req).get("state") {
+                Some(name) => match PrintJobState::from_name(name) {
+                    Some(state) => Some(state),
+                    None => {
+                        return Ok(error_response(
+                            StatusCode::BAD_REQUEST,
+                            Message::new("print-job-unknown-state")
+                                .arg("state", name)
+                                .localize(&current_locale()),
+                        ))
+                    }
+                },
+                None => None,
+            };
+            match PrintJob::all(state, &mut connection).await {
+                Ok(print_jobs) => Ok(json(StatusCode::OK, &print_jobs)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        Method::POST => {
+            let payload = match read_json::<NewPrintJob>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            match PrintJob::create(
+                payload.printer,
+                payload.locations,
+                payload.template,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(print_job) => Ok(json(StatusCode::CREATED, &print_job)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Shows (`GET`) a print job, including its state and the error if it failed.
+///
+/// `GET /print_jobs/{uuid}`
+pub async fn print_job(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /print_jobs/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match PrintJob::find_by_uuid(uuid, &mut connection).await {
+        Ok(print_job) => Ok(json(StatusCode::OK, &print_job)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Renders (`GET`) the labels of a print job with its template, for the printer to print.
+///
+/// `GET /print_jobs/{uuid}/labels` responds with a label per location. QR code labels include the
+/// QR code as an SVG image.
+pub async fn labels(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /print_jobs/{}/labels endpoint",
+        uuid
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let print_job = match PrintJob::find_by_uuid(uuid, &mut connection).await {
+        Ok(print_job) => print_job,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match print_job.labels(&mut connection).await {
+        Ok(labels) => Ok(json(StatusCode::OK, &labels)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Puts (`POST`) a failed print job back in the queue, without having to select its locations again.
+///
+/// `POST /print_jobs/{uuid}/retry` responds with the queued job, or 422 if the job has not failed.
+pub async fn retry(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /print_jobs/{}/retry endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let print_job = match PrintJob::find_by_uuid(uuid, &mut connection).await {
+        Ok(print_job) => print_job,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match PrintJob::retry(print_job.id, &mut connection).await {
+        Ok(print_job) => Ok(json(StatusCode::OK, &print_job)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Reports (`POST`) the state of a print job, as the printer agent sends it to the printer.
+///
+/// `POST /print_jobs/{uuid}/state` with `{"state": "printing"}` when the job is picked up from the
+/// queue, then `{"state": "done"}`, or `{"state": "failed", "error": "Printer offline"}` which
+/// notifies the teams configured for failed print jobs. Responds with the job, or 422 if the job
+/// cannot move to the state from the one it is in (e.g. `done` for a queued job) or a failure has
+/// no `error`.
+pub async fn state(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /print_jobs/{}/state endpoint", uuid);
+    let payload = match read_json::<PrintJobReport>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let Some(state) = PrintJobState::from_name(&payload.state) else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            Message::new("print-job-unknown-state")
+                .arg("state", &payload.state)
+                .localize(&current_locale()),
+        ));
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let print_job = match PrintJob::find_by_uuid(uuid, &mut connection).await {
+        Ok(print_job) => print_job,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let result = match (state, payload.error) {
+        (PrintJobState::Queued, _) => PrintJob::retry(print_job.id, &mut connection).await,
+        (PrintJobState::Printing, _) => PrintJob::start(print_job.id, &mut connection).await,
+        (PrintJobState::Done, _) => PrintJob::complete(print_job.id, &mut connection).await,
+        (PrintJobState::Failed, Some(error)) => {
+            PrintJob::fail(print_job.id, error, &mut connection).await
+        }
+        (PrintJobState::Failed, None) => {
+            return Ok(error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Message::new("print-job-error-required").localize(&current_locale()),
+            ))
+        }
+    };
+    match result {
+        Ok(print_job) => Ok(json(StatusCode::OK, &print_job)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::models::print_job::PrintJob;
+    use sqlx::SqlitePool;
+
+    async fn setup() -> SqlitePool {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        Location::create("location1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_create_and_show_print_job() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/print_jobs",
+                br#"{"printer": "printer-1", "locations": ["lw-location1-1"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let uuid = response_json(res).await["uuid"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let res = handle(
+            request("GET", &format!("/print_jobs/{}", uuid), b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["state"], "queued");
+        assert_eq!(body["location_barcodes"][0], "lw-location1-1");
+
+        let res = handle(request("GET", "/print_jobs/1", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_print_job_labels() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/print_jobs",
+                br#"{"printer": "printer-1", "locations": ["lw-location1-1"], "template": "qr"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let body = response_json(res).await;
+        assert_eq!(body["template"], "qr");
+        let uri = format!("/print_jobs/{}/labels", body["uuid"].as_str().unwrap());
+
+        let res = handle(request("GET", &uri, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["barcode"], "lw-location1-1");
+        assert_eq!(body[0]["name"], "location1");
+        assert!(body[0]["content"]
+            .as_str()
+            .unwrap()
+            .ends_with("/b/lw-location1-1"));
+        assert!(body[0]["svg"].as_str().unwrap().contains("<svg"));
+
+        let res = handle(
+            request(
+                "POST",
+                "/print_jobs",
+                br#"{"printer": "printer-1", "locations": ["lw-location1-1"], "template": "hologram"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_list_print_jobs_by_state() {
+        let pool = setup().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let print_job = PrintJob::create(
+            "printer-1".to_string(),
+            vec!["lw-location1-1".to_string()],
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        PrintJob::fail(print_job.id, "Printer offline".to_string(), &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let res = handle(
+            request("GET", "/print_jobs?state=failed", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body[0]["error"], "Printer offline");
+
+        let res = handle(request("GET", "/print_jobs?state=done", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await.as_array().unwrap().len(), 0);
+
+        let res = handle(request("GET", "/print_jobs?state=lost", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_retry_print_job() {
+        let pool = setup().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let print_job = PrintJob::create(
+            "printer-1".to_string(),
+            vec!["lw-location1-1".to_string()],
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        drop(conn);
+        let uri = format!("/print_jobs/{}/retry", print_job.uuid);
+
+        let res = handle(request("POST", &uri, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let mut conn = pool.acquire().await.unwrap();
+        PrintJob::fail(print_job.id, "Printer offline".to_string(), &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let res = handle(request("POST", &uri, b""), pool).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["state"], "queued");
+        assert_eq!(body["error"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_report_print_job_state() {
+        let pool = setup().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let print_job = PrintJob::create(
+            "printer-1".to_string(),
+            vec!["lw-location1-1".to_string()],
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        drop(conn);
+        let uri = format!("/print_jobs/{}/state", print_job.uuid);
+
+        let res = handle(request("POST", &uri, br#"{"state": "done"}"#), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request("POST", &uri, br#"{"state": "printing"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["state"], "printing");
+        assert_eq!(body["attempts"], 1);
+
+        let res = handle(
+            request("POST", &uri, br#"{"state": "failed"}"#),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let res = handle(
+            request(
+                "POST",
+                &uri,
+                br#"{"state": "failed", "error": "Printer offline"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response_json(res).await["error"], "Printer offline");
+
+        for (state, payload) in [
+            ("queued", br#"{"state": "queued"}"#.as_slice()),
+            ("printing", br#"{"state": "printing"}"#),
+            ("done", br#"{"state": "done"}"#),
+        ] {
+            let res = handle(request("POST", &uri, payload), pool.clone())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 200);
+            assert_eq!(response_json(res).await["state"], state);
+        }
+
+        let res = handle(request("POST", &uri, br#"{"state": "lost"}"#), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+        let res = handle(request("GET", &uri, b""), pool).await.unwrap();
+        assert_eq!(res.status(), 405);
+    }
+}
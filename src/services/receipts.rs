@@ -0,0 +1,334 @@
+use crate::services::scan::lock_token;
+use crate::services::{
+    error_response, json, map_error, method_not_allowed, query_params, read_json, ServiceResponse,
+};
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Method, Request, Response, StatusCode};
+use labwhere::config::CONFIG;
+use labwhere::errors::{FieldValidationError, ValidationError};
+use labwhere::metrics::acquire;
+use labwhere::models::manifest::ManifestReader;
+use labwhere::models::receipt::Receipt;
+use log::info;
+use serde::Deserialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::error::Error;
+use validator::Validate;
+
+/// The payload for registering a receipt.
+#[derive(Debug, Deserialize, Validate)]
+struct NewReceipt {
+    /// What identifies the delivery e.g. the supplier's delivery note
+    #[validate(length(min = 1, message = "validation-blank"))]
+    reference: String,
+    /// Who registers the delivery
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+    /// The barcodes of the labwares expected in the delivery
+    #[validate(length(min = 1, message = "validation-blank"))]
+    labware_barcodes: Vec<String>,
+}
+
+/// The payload for receiving labwares against a receipt.
+#[derive(Debug, Deserialize, Validate)]
+struct NewReceiptScan {
+    /// The barcodes of the labwares unpacked
+    #[validate(length(min = 1, message = "validation-blank"))]
+    labware_barcodes: Vec<String>,
+}
+
+/// Lists (`GET`) or registers (`POST`) receipts of incoming deliveries.
+///
+/// - `GET /receipts` responds with all receipts, newest first.
+/// - `POST /receipts` with `{"reference": "DN-1234", "user": "jane", "labware_barcodes": ["lw-1"]}`
+///   registers a delivery and the labwares expected in it, and responds with 201 and the receipt.
+///   The expected labwares can also be sent as a CSV file (`Content-Type: text/csv`) with a
+///   `labware` column, with the reference and the user in the query, e.g.
+///   `POST /receipts?reference=DN-1234&user=jane`.
+pub async fn receipts(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /receipts endpoint");
+    match *req.method() {
+        Method::GET => {
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match Receipt::all(&mut connection).await {
+                Ok(receipts) => Ok(json(StatusCode::OK, &receipts)),
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        Method::POST => {
+            let payload = match read_receipt(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            let receipt = match Receipt::create(
+                &CONFIG,
+                payload.reference,
+                payload.user,
+                &payload.labware_barcodes,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(receipt) => receipt,
+                Err(e) => return Ok(map_error(&*e)),
+            };
+            match receipt_json(&receipt, &mut connection).await {
+                Ok(body) => Ok(json(StatusCode::CREATED, &body)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Reads a new receipt from a JSON payload, or from a CSV file of the expected labwares.
+async fn read_receipt(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error>>,
+) -> Result<NewReceipt, Response<BoxBody<Bytes, hyper::Error>>> {
+    let is_csv = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/csv"));
+    if !is_csv {
+        return read_json::<NewReceipt>(req).await;
+    }
+    let params = query_params(&req);
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))?
+        .to_bytes();
+    let mut reader = ManifestReader::without_locations();
+    let rows = reader
+        .read(&body)
+        .and_then(|mut rows| {
+            rows.extend(reader.finish()?);
+            Ok::<_, ValidationError>(rows)
+        })
+        .map_err(|e| map_error(&e))?;
+    let payload = NewReceipt {
+        reference: params.get("reference").cloned().unwrap_or_default(),
+        user: params.get("user").cloned().unwrap_or_default(),
+        labware_barcodes: rows.into_iter().map(|row| row.labware).collect(),
+    };
+    payload
+        .validate()
+        .map_err(|e| map_error(&FieldValidationError::from(e)))?;
+    Ok(payload)
+}
+
+/// Shows (`GET`) a receipt along with its labwares and discrepancies.
+///
+/// `GET /receipts/{uuid}` responds with the receipt, its `items` (the labwares expected and
+/// received) and the `shortages` and `extras` so far.
+pub async fn receipt(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /receipts/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let receipt = match Receipt::find_by_uuid(uuid, &mut connection).await {
+        Ok(receipt) => receipt,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match receipt_json(&receipt, &mut connection).await {
+        Ok(body) => Ok(json(StatusCode::OK, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Receives (`POST`) the labwares unpacked from a delivery.
+///
+/// `POST /receipts/{uuid}/scans` with `{"labware_barcodes": ["lw-1"]}` scans the labwares into the
+/// receiving location (`LABWHERE_RECEIVING_LOCATION`) and responds with the receipt, its items and
+/// its discrepancies. If the receiving location is locked, the lock's token has to be sent in the
+/// `X-Lock-Token` header.
+pub async fn scans(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /receipts/{}/scans endpoint", uuid);
+    let lock_token = lock_token(&req);
+    let payload = match read_json::<NewReceiptScan>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let receipt = match Receipt::find_by_uuid(uuid, &mut connection).await {
+        Ok(receipt) => receipt,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    if let Err(e) = receipt
+        .receive(
+            &CONFIG,
+            payload.labware_barcodes,
+            lock_token.as_deref(),
+            &mut connection,
+        )
+        .await
+    {
+        return Ok(map_error(&*e));
+    }
+    match receipt_json(&receipt, &mut connection).await {
+        Ok(body) => Ok(json(StatusCode::OK, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Completes (`POST`) a receipt once the delivery is unpacked.
+///
+/// `POST /receipts/{uuid}/complete` responds with the completed receipt, its items and its
+/// discrepancies. No more labwares can be received against it.
+pub async fn complete(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /receipts/{}/complete endpoint",
+        uuid
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let receipt = match Receipt::find_by_uuid(uuid, &mut connection).await {
+        Ok(receipt) => receipt,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let receipt = match receipt.complete(&mut connection).await {
+        Ok(receipt) => receipt,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match receipt_json(&receipt, &mut connection).await {
+        Ok(body) => Ok(json(StatusCode::OK, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// The receipt with its items, shortages and extras.
+async fn receipt_json(
+    receipt: &Receipt,
+    connection: &mut SqliteConnection,
+) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    let mut body = serde_json::to_value(receipt)?;
+    body["items"] = serde_json::to_value(receipt.items(&mut *connection).await?)?;
+    let discrepancies = receipt.discrepancies(connection).await?;
+    body["shortages"] = serde_json::to_value(discrepancies.shortages)?;
+    body["extras"] = serde_json::to_value(discrepancies.extras)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json, MockBody};
+    use labwhere::db::init_pool;
+
+    #[tokio::test]
+    async fn test_receipts() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let body: &[u8] =
+            br#"{"reference": "DN-1234", "user": "jane", "labware_barcodes": ["lw-1", "lw-2"]}"#;
+
+        let res = handle(request("POST", "/receipts", body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        let receipt = response_json(res).await;
+        assert_eq!(receipt["reference"], "DN-1234");
+        assert_eq!(receipt["state"], "open");
+        assert_eq!(receipt["shortages"], serde_json::json!(["lw-1", "lw-2"]));
+        let uuid = receipt["uuid"].as_str().unwrap();
+
+        // Nothing can be received until a receiving location is configured
+        let res = handle(
+            request(
+                "POST",
+                &format!("/receipts/{}/scans", uuid),
+                br#"{"labware_barcodes": ["lw-1"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request("POST", &format!("/receipts/{}/complete", uuid), b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await["state"], "completed");
+
+        let res = handle(request("GET", "/receipts", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await[0]["uuid"], uuid);
+
+        let res = handle(request("GET", "/receipts/unknown", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_receipt_from_csv() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let csv = |uri: &str, body: &'static [u8]| {
+            hyper::Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("Content-Type", "text/csv")
+                .body(MockBody::new(body))
+                .unwrap()
+        };
+
+        let res = handle(
+            csv(
+                "/receipts?reference=DN-1234&user=jane",
+                b"labware,supplier\nlw-1,acme\nlw-2,acme\n",
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let receipt = response_json(res).await;
+        assert_eq!(receipt["items"][1]["barcode"], "lw-2");
+        assert_eq!(receipt["items"][1]["expected"], true);
+
+        let res = handle(
+            csv("/receipts?reference=DN-1234&user=jane", b"barcode\nlw-1\n"),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "The manifest has no labware column in its header"
+        );
+
+        let res = handle(csv("/receipts?user=jane", b"labware\nlw-1\n"), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::services::scan::LOCK_TOKEN_HEADER;
+use crate::services::{
+    current_locale, error_response, json, map_error, method_not_allowed, read_json, status_only,
+    ServiceResponse,
+};
+use chrono::{DateTime, Utc};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::layout::{MAX_COLUMNS, MAX_ROWS};
+use labwhere::models::location::Location;
+use labwhere::models::reservation::Reservation;
+use log::info;
+use serde::Deserialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use validator::Validate;
+
+/// The most wells which can be reserved at once, those of the largest layout.
+const MAX_RESERVED_WELLS: u32 = MAX_ROWS * MAX_COLUMNS;
+
+/// The payload for reserving space in a location.
+#[derive(Debug, Deserialize, Validate)]
+struct NewReservation {
+    /// The team the space is reserved for
+    #[validate(length(min = 1, message = "validation-blank"))]
+    team: String,
+    /// What the space is reserved for e.g. the name of the experiment
+    purpose: Option<String>,
+    /// The number of empty wells to reserve, the whole location if it is not given
+    #[validate(range(min = 1, max = MAX_RESERVED_WELLS, message = "validation-range"))]
+    wells: Option<u32>,
+    /// When the reservation expires if the space is not used by then
+    expires_at: DateTime<Utc>,
+}
+
+/// Lists (`GET`) the reservations of a location or reserves (`POST`) space in it.
+///
+/// - `GET /locations/{barcode}/reservations` responds with the reservations which have not expired,
+///   soonest to expire first, each with the `wells` it holds.
+/// - `POST /locations/{barcode}/reservations` with `{"team": "genomics", "purpose": "run 42",
+///   "wells": 8, "expires_at": "2026-03-03T17:00:00Z"}` reserves the first 8 free wells of the
+///   location (or the whole, empty, location without `wells`) and responds with 201 and the
+///   reservation, including the token that has to be sent in the `X-Lock-Token` header to fill the
+///   reserved space. Responds with 422 if there is not enough free space.
+pub async fn reservations(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    barcode: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /locations/{}/reservations endpoint",
+        barcode
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let location = match Location::find_by_barcode(barcode.to_string(), &mut connection).await {
+        Ok(location) => location,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match *req.method() {
+        Method::GET => {
+            let reservations = match Reservation::active(location.id, &mut connection).await {
+                Ok(reservations) => reservations,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            let mut body = vec![];
+            for reservation in &reservations {
+                match reservation_json(reservation, &mut connection).await {
+                    Ok(reservation) => body.push(reservation),
+                    Err(e) => return Ok(map_error(&e)),
+                }
+            }
+            Ok(json(StatusCode::OK, &body))
+        }
+        Method::POST => {
+            let payload = match read_json::<NewReservation>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let reservation = match Reservation::create(
+                &location,
+                payload.team,
+                payload.purpose,
+                payload.wells,
+                payload.expires_at,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(reservation) => reservation,
+                Err(e) => return Ok(map_error(&*e)),
+            };
+            match reservation_json(&reservation, &mut connection).await {
+                Ok(mut body) => {
+                    body["token"] = serde_json::Value::from(reservation.token);
+                    Ok(json(StatusCode::CREATED, &body))
+                }
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Releases (`DELETE`) a reservation before it expires.
+///
+/// `DELETE /reservations/{uuid}` with the reservation's token in the `X-Lock-Token` header frees the
+/// reserved space and responds with 204.
+pub async fn reservation(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /reservations/{} endpoint", uuid);
+    let Some(token) = req
+        .headers()
+        .get(LOCK_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            Message::new("reservation-token-required").localize(&current_locale()),
+        ));
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let reservation = match Reservation::find_by_uuid(uuid, &mut connection).await {
+        Ok(reservation) => reservation,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match reservation.release(token, &mut connection).await {
+        Ok(()) => Ok(status_only(StatusCode::NO_CONTENT)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// A reservation along with the coordinates of the wells it holds.
+async fn reservation_json(
+    reservation: &Reservation,
+    connection: &mut SqliteConnection,
+) -> Result<serde_json::Value, sqlx::Error> {
+    let wells = reservation.wells(connection).await?;
+    let mut body = serde_json::to_value(reservation).unwrap_or_default();
+    body["wells"] = serde_json::to_value(wells).unwrap_or_default();
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json, MockBody};
+    use chrono::{Duration, Utc};
+    use labwhere::db::init_pool;
+    use labwhere::factories::LocationFactory;
+
+    #[tokio::test]
+    async fn test_reservations() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let rack = LocationFactory::new()
+            .with_name("rack")
+            .create(&pool)
+            .await
+            .unwrap();
+        let barcode = rack.barcode.unwrap();
+        let res = handle(
+            request(
+                "PUT",
+                &format!("/locations/{}/layout", barcode),
+                br#"{"rows": 2, "columns": 2}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let path = format!("/locations/{}/reservations", barcode);
+        let expires_at = (Utc::now() + Duration::days(7)).to_rfc3339();
+        let body = format!(
+            r#"{{"team": "genomics", "purpose": "run 42", "wells": 3, "expires_at": "{}"}}"#,
+            expires_at
+        );
+        let body: &'static [u8] = Box::leak(body.into_bytes().into_boxed_slice());
+
+        let res = handle(request("POST", &path, body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        let reservation = response_json(res).await;
+        assert_eq!(reservation["team"], "genomics");
+        assert_eq!(reservation["wells"], serde_json::json!(["A1", "A2", "B1"]));
+        let token = reservation["token"].as_str().unwrap().to_string();
+        let uuid = reservation["uuid"].as_str().unwrap().to_string();
+
+        // Only one well is left
+        let res = handle(request("POST", &path, body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(request("GET", &path, b""), pool.clone())
+            .await
+            .unwrap();
+        let reservations = response_json(res).await;
+        assert_eq!(reservations[0]["uuid"], uuid.as_str());
+        assert!(reservations[0].get("token").is_none());
+
+        let res = handle(
+            request(
+                "PUT",
+                &format!("/locations/{}/layout/A1", barcode),
+                br#"{"labware_barcode": "lw-1"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 423);
+
+        let res = handle(
+            request("DELETE", &format!("/reservations/{}", uuid), b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let req = hyper::Request::builder()
+            .method("DELETE")
+            .uri(format!("/reservations/{}", uuid))
+            .header("X-Lock-Token", token)
+            .body(MockBody::new(b""))
+            .unwrap();
+        let res = handle(req, pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 204);
+
+        let res = handle(request("GET", &path, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await, serde_json::json!([]));
+
+        let res = handle(request("DELETE", "/reservations/unknown", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+}
@@ -0,0 +1,266 @@
+//! Routes each request to the handler of its endpoint by its path and method.
+//!
+//! The route table (see `endpoint`) lists the path of every endpoint with the methods it supports
+//! and its handler. A path which matches no endpoint responds with 404, and a method the endpoint
+//! does not support responds with 405 before its handler runs, so handlers do not check the
+//! method themselves (those of endpoints with several methods only match on it to pick what to
+//! do).
+
+use crate::services::{
+    admin, api_keys, checkouts, dead_letters, devices, events, kiosk, labwares, links,
+    location_types, locations, manifests, method_not_allowed, metrics, misplacements,
+    pending_labwares, print_jobs, receipts, reservations, saved_searches, scan, search, segments,
+    shipments, stats, status_only, stocktakes, sync, upload_mappings, users, validate,
+    ServiceResponse,
+};
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::models::pending_labware::PendingStatus;
+use percent_encoding::percent_decode_str;
+use sqlx::SqlitePool;
+
+const GET: &[Method] = &[Method::GET];
+const POST: &[Method] = &[Method::POST];
+const PUT: &[Method] = &[Method::PUT];
+const DELETE: &[Method] = &[Method::DELETE];
+const GET_POST: &[Method] = &[Method::GET, Method::POST];
+const GET_PUT: &[Method] = &[Method::GET, Method::PUT];
+const GET_DELETE: &[Method] = &[Method::GET, Method::DELETE];
+const POST_DELETE: &[Method] = &[Method::POST, Method::DELETE];
+
+/// An endpoint a path matched: the methods it supports, and the response of its handler to the
+/// request, which is only run if the request has one of those methods.
+type Endpoint<'a> = (&'static [Method], BoxFuture<'a, ServiceResponse>);
+
+/// Delegates the request to the handler of the endpoint matching its path and method. Path
+/// segments such as barcodes are percent-decoded and passed to the handlers as arguments.
+///
+/// A path which matches no endpoint responds with 404, and a method the endpoint does not support
+/// with 405 and the methods it does (see `method_not_allowed`).
+pub(crate) async fn route(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    let method = req.method().clone();
+    let decoded: Vec<String> = segments(req.uri().path())
+        .into_iter()
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+    let segments: Vec<&str> = decoded.iter().map(String::as_str).collect();
+    let Some((methods, response)) = endpoint(req, pool, &segments) else {
+        return Ok(status_only(StatusCode::NOT_FOUND));
+    };
+    if !methods.contains(&method) {
+        return Ok(method_not_allowed(methods));
+    }
+    response.await
+}
+
+/// The route table: the endpoint matching the segments of a path, if any.
+fn endpoint<'a>(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    segments: &'a [&'a str],
+) -> Option<Endpoint<'a>> {
+    let endpoint: Endpoint<'a> = match segments {
+        ["admin", "analytics", filename] => (GET, admin::analytics(pool, filename).boxed()),
+        ["admin", "api_keys"] => (GET_POST, api_keys::api_keys(req, pool).boxed()),
+        ["admin", "api_keys", uuid] => (GET_PUT, api_keys::api_key(req, pool, uuid).boxed()),
+        ["admin", "api_keys", uuid, "rotate"] => (POST, api_keys::rotate(req, pool, uuid).boxed()),
+        ["admin", "api_keys", uuid, "revoke"] => (POST, api_keys::revoke(pool, uuid).boxed()),
+        ["admin", "api_keys", uuid, "usage"] => (GET, admin::api_key_usage(pool, uuid).boxed()),
+        ["admin", "dead_letters"] => (GET, dead_letters::dead_letters(pool).boxed()),
+        ["admin", "dead_letters", uuid, "redrive"] => {
+            (POST, dead_letters::redrive(req, pool, uuid).boxed())
+        }
+        ["admin", "debug", "requests"] => (GET_DELETE, admin::debug_requests(req).boxed()),
+        ["admin", "pending_labwares"] => {
+            (GET, pending_labwares::pending_labwares(req, pool).boxed())
+        }
+        ["admin", "pending_labwares", uuid, "approve"] => (
+            POST,
+            pending_labwares::review(req, pool, uuid, PendingStatus::Approved).boxed(),
+        ),
+        ["admin", "pending_labwares", uuid, "reject"] => (
+            POST,
+            pending_labwares::review(req, pool, uuid, PendingStatus::Rejected).boxed(),
+        ),
+        ["admin", "query"] => (POST, admin::query(req, pool).boxed()),
+        ["admin", "slos"] => (GET, admin::slos().boxed()),
+        ["admin", "users"] => (GET_POST, users::users(req, pool).boxed()),
+        ["admin", "users", uuid] => (GET_PUT, users::user(req, pool, uuid).boxed()),
+        ["admin", "users", uuid, "deactivate"] => (POST, users::deactivate(pool, uuid).boxed()),
+        ["admin", "users", uuid, "reset"] => (POST, users::reset(req, pool, uuid).boxed()),
+        ["checkouts"] => (GET, checkouts::checkouts(req, pool).boxed()),
+        ["devices"] => (GET_POST, devices::devices(req, pool).boxed()),
+        ["devices", "heartbeat"] => (POST, devices::heartbeat(req, pool).boxed()),
+        ["devices", uuid] => (GET, devices::device(pool, uuid).boxed()),
+        ["devices", uuid, "config"] => (GET_PUT, devices::config(req, pool, uuid).boxed()),
+        ["devices", uuid, "enable"] => (POST, devices::set_enabled(pool, uuid, true).boxed()),
+        ["devices", uuid, "disable"] => (POST, devices::set_enabled(pool, uuid, false).boxed()),
+        ["events", "schema", event_type, version] => {
+            (GET, events::schema(event_type, version).boxed())
+        }
+        ["b", barcode] => (GET, links::resolve(pool, barcode).boxed()),
+        ["kiosk"] => (GET, kiosk::kiosk().boxed()),
+        ["labwares"] => (GET, labwares::labwares(pool).boxed()),
+        ["labwares", "exhaust"] => (POST, labwares::exhaust(req, pool).boxed()),
+        ["labwares", "in_transit"] => (GET, checkouts::in_transit(pool).boxed()),
+        ["labwares", barcode] => (GET, labwares::labware(pool, barcode).boxed()),
+        ["labwares", barcode, "checkout"] => (POST, labwares::checkout(req, pool, barcode).boxed()),
+        ["labwares", barcode, "checkin"] => (POST, labwares::checkin(req, pool, barcode).boxed()),
+        ["labwares", barcode, "status"] => (GET_PUT, labwares::status(req, pool, barcode).boxed()),
+        ["labwares", barcode, "location"] => {
+            (GET, labwares::location_at(req, pool, barcode).boxed())
+        }
+        ["labwares", barcode, "barcodes"] => {
+            (GET_POST, labwares::barcodes(req, pool, barcode).boxed())
+        }
+        ["labwares", barcode, "barcodes", alias] => {
+            (DELETE, labwares::barcode(pool, barcode, alias).boxed())
+        }
+        ["location_types"] => (GET_POST, location_types::location_types(req, pool).boxed()),
+        ["locations", barcode] => (GET, locations::location(pool, barcode).boxed()),
+        ["locations", barcode, "audits"] => (GET, locations::audits(req, pool, barcode).boxed()),
+        ["locations", barcode, "contents"] => {
+            (GET, locations::contents(req, pool, barcode).boxed())
+        }
+        ["locations", barcode, "occupancy"] => {
+            (GET, locations::occupancy(req, pool, barcode).boxed())
+        }
+        ["locations", barcode, "labwares"] => {
+            (GET_DELETE, locations::labwares(req, pool, barcode).boxed())
+        }
+        ["locations", barcode, "layout"] => {
+            (GET_PUT, locations::layout(req, pool, barcode).boxed())
+        }
+        ["locations", barcode, "layout.svg"] => (GET, locations::layout_svg(pool, barcode).boxed()),
+        ["locations", barcode, "layout", position] => (
+            PUT,
+            locations::position(req, pool, barcode, position).boxed(),
+        ),
+        ["locations", barcode, "transfer"] => {
+            (POST, locations::transfer(req, pool, barcode).boxed())
+        }
+        ["locations", barcode, "tree.pdf"] => (GET, locations::tree_pdf(pool, barcode).boxed()),
+        ["locations", barcode, "move"] => {
+            (POST, locations::move_location(req, pool, barcode).boxed())
+        }
+        ["locations", barcode, "name"] => (PUT, locations::rename(req, pool, barcode).boxed()),
+        ["locations", barcode, "owner"] => (PUT, locations::owner(req, pool, barcode).boxed()),
+        ["locations", barcode, "lock"] => {
+            (POST_DELETE, locations::lock(req, pool, barcode).boxed())
+        }
+        ["locations", barcode, "reservations"] => (
+            GET_POST,
+            reservations::reservations(req, pool, barcode).boxed(),
+        ),
+        ["locations", barcode, "flags"] => (GET_POST, locations::flags(req, pool, barcode).boxed()),
+        ["locations", barcode, "flags", uuid, "clear"] => (
+            POST,
+            locations::clear_flag(req, pool, barcode, uuid).boxed(),
+        ),
+        ["locations", barcode, "subscriptions"] => (
+            GET_POST,
+            locations::subscriptions(req, pool, barcode).boxed(),
+        ),
+        ["locations", barcode, "subscriptions", uuid] => {
+            (DELETE, locations::subscription(pool, barcode, uuid).boxed())
+        }
+        ["manifests"] => (GET_POST, manifests::manifests(req, pool).boxed()),
+        ["manifests", uuid] => (GET, manifests::manifest(pool, uuid).boxed()),
+        ["manifests", uuid, "errors.csv"] => (GET, manifests::errors_csv(pool, uuid).boxed()),
+        ["upload_mappings"] => (
+            GET_POST,
+            upload_mappings::upload_mappings(req, pool).boxed(),
+        ),
+        ["upload_mappings", name] => (
+            GET_DELETE,
+            upload_mappings::upload_mapping(req, pool, name).boxed(),
+        ),
+        ["uploads", uuid, "errors.csv"] => (GET, manifests::errors_csv(pool, uuid).boxed()),
+        ["metrics"] => (GET, metrics::metrics(pool).boxed()),
+        ["misplacements"] => (GET, misplacements::misplacements(req, pool).boxed()),
+        ["misplacements", uuid, "review"] => (POST, misplacements::review(req, pool, uuid).boxed()),
+        ["print_jobs"] => (GET_POST, print_jobs::print_jobs(req, pool).boxed()),
+        ["print_jobs", uuid] => (GET, print_jobs::print_job(pool, uuid).boxed()),
+        ["print_jobs", uuid, "labels"] => (GET, print_jobs::labels(pool, uuid).boxed()),
+        ["print_jobs", uuid, "retry"] => (POST, print_jobs::retry(pool, uuid).boxed()),
+        ["print_jobs", uuid, "state"] => (POST, print_jobs::state(req, pool, uuid).boxed()),
+        ["scan", "image"] => (POST, scan::scan_image(req, pool).boxed()),
+        ["scan"] => (POST, scan::scan(req, pool).boxed()),
+        ["scans", "batch"] => (POST, scan::batch(req, pool).boxed()),
+        ["scans", "offline_batch"] => (POST, scan::offline_batch(req, pool).boxed()),
+        ["receipts"] => (GET_POST, receipts::receipts(req, pool).boxed()),
+        ["receipts", uuid] => (GET, receipts::receipt(pool, uuid).boxed()),
+        ["receipts", uuid, "scans"] => (POST, receipts::scans(req, pool, uuid).boxed()),
+        ["receipts", uuid, "complete"] => (POST, receipts::complete(pool, uuid).boxed()),
+        ["reservations", uuid] => (DELETE, reservations::reservation(req, pool, uuid).boxed()),
+        ["search"] => (GET, search::search(req, pool).boxed()),
+        ["searches"] => (GET_POST, saved_searches::saved_searches(req, pool).boxed()),
+        ["searches", uuid] => (
+            GET_DELETE,
+            saved_searches::saved_search(req, pool, uuid).boxed(),
+        ),
+        ["searches", uuid, "results"] => (GET, saved_searches::results(pool, uuid).boxed()),
+        ["validate", "barcodes"] => (POST, validate::barcodes(req, pool).boxed()),
+        ["stats", "occupancy"] => (GET, stats::occupancy(req, pool).boxed()),
+        ["stats", "occupancy", "locations"] => (GET, stats::location_occupancy(req, pool).boxed()),
+        ["shipments"] => (GET_POST, shipments::shipments(req, pool).boxed()),
+        ["shipments", uuid] => (GET, shipments::shipment(pool, uuid).boxed()),
+        ["stocktakes"] => (POST, stocktakes::stocktakes(req, pool).boxed()),
+        ["stocktakes", uuid] => (GET, stocktakes::stocktake(pool, uuid).boxed()),
+        ["stocktakes", uuid, "scans"] => (POST, stocktakes::scans(req, pool, uuid).boxed()),
+        ["stocktakes", uuid, "complete"] => (POST, stocktakes::complete(req, pool, uuid).boxed()),
+        ["stocktakes", uuid, "report"] => (GET, stocktakes::report(pool, uuid, false).boxed()),
+        ["stocktakes", uuid, "report.csv"] => (GET, stocktakes::report(pool, uuid, true).boxed()),
+        ["sync", "changes"] => (GET_POST, sync::changes(req, pool).boxed()),
+        ["sync", "conflicts"] => (GET, sync::conflicts(req, pool).boxed()),
+        ["sync", "conflicts", uuid, "resolve"] => {
+            (POST, sync::resolve_conflict(req, pool, uuid).boxed())
+        }
+        _ => return None,
+    };
+    Some(endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request};
+    use labwhere::db::init_pool;
+
+    #[tokio::test]
+    async fn test_route() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/nowhere", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+
+        let res = handle(request("DELETE", "/print_jobs", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 405);
+        assert_eq!(res.headers()["allow"], "GET, POST");
+
+        // The method is checked before the location is looked up
+        let res = handle(
+            request("PUT", "/locations/lw-unknown-1/flags", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 405);
+
+        // Nor is the database needed to tell
+        pool.close().await;
+        let res = handle(request("PATCH", "/print_jobs/1", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 405);
+        assert_eq!(res.headers()["allow"], "GET");
+    }
+}
@@ -0,0 +1,228 @@
+use crate::services::search::{status_filter, DEFAULT_LIMIT};
+use crate::services::{
+    error_response, json, map_error, method_not_allowed, query_params, read_json, status_only,
+    ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::config::CONFIG;
+use labwhere::metrics::acquire;
+use labwhere::models::saved_search::SavedSearch;
+use labwhere::search::{SearchSort, MAX_RESULTS};
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for saving a search.
+#[derive(Debug, Deserialize, Validate)]
+struct NewSavedSearch {
+    /// Who is saving the search
+    #[validate(length(min = 1, message = "validation-blank"))]
+    owner: String,
+    /// The name of the search
+    #[validate(length(min = 1, message = "validation-blank"))]
+    name: String,
+    /// What is searched for
+    #[validate(length(min = 1, message = "validation-blank"))]
+    q: String,
+    /// The status of the labwares found, `active` if not given or `all`
+    status: Option<String>,
+    /// The most locations, and the most labwares, returned
+    #[validate(range(min = 1, max = MAX_RESULTS, message = "validation-range"))]
+    limit: Option<u32>,
+    /// The order the results are returned in
+    #[serde(default)]
+    sort: SearchSort,
+}
+
+/// Lists (`GET`) or saves (`POST`) searches, so that they can be run again.
+///
+/// - `GET /searches?owner=jane` responds with the searches saved by `jane`, ordered by name, or
+///   with every saved search if no owner is asked for.
+/// - `POST /searches` with `{"owner": "jane", "name": "Shipped plates", "q": "plate",
+///   "status": "shipped-out", "limit": 50, "sort": "name"}` responds with 201 and the saved
+///   search. `status`, `limit` and `sort` are as for `GET /search`, and default the same way.
+pub async fn saved_searches(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /searches endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => {
+            let params = query_params(&req);
+            match SavedSearch::all(params.get("owner").map(String::as_str), &mut connection).await {
+                Ok(searches) => Ok(json(StatusCode::OK, &searches)),
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        Method::POST => {
+            let payload = match read_json::<NewSavedSearch>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let status = match status_filter(payload.status.as_deref()) {
+                Ok(status) => status,
+                Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, message)),
+            };
+            match SavedSearch::create(
+                payload.owner,
+                payload.name,
+                payload.q,
+                status,
+                payload.limit.unwrap_or(DEFAULT_LIMIT),
+                payload.sort,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(search) => Ok(json(StatusCode::CREATED, &search)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Shows (`GET`) or deletes (`DELETE`) a saved search.
+///
+/// - `GET /searches/{uuid}` responds with the saved search.
+/// - `DELETE /searches/{uuid}` responds with 204.
+pub async fn saved_search(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /searches/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let search = match SavedSearch::find_by_uuid(uuid, &mut connection).await {
+        Ok(search) => search,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => Ok(json(StatusCode::OK, &search)),
+        Method::DELETE => match search.delete(&mut connection).await {
+            Ok(()) => Ok(status_only(StatusCode::NO_CONTENT)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        _ => Ok(method_not_allowed(&[Method::GET, Method::DELETE])),
+    }
+}
+
+/// Runs (`GET`) a saved search.
+///
+/// `GET /searches/{uuid}/results` responds with the `locations` and `labwares` found by the saved
+/// search with its filters and in its order, just as `GET /search` does. The link can be shared
+/// with anyone to see the latest results.
+pub async fn results(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /searches/{}/results endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let search = match SavedSearch::find_by_uuid(uuid, &mut connection).await {
+        Ok(search) => search,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match search.run(&CONFIG, &mut connection).await {
+        Ok(results) => Ok(json(StatusCode::OK, &results)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::factories::{LabwareFactory, LocationFactory};
+
+    #[tokio::test]
+    async fn test_saved_searches() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let freezer = LocationFactory::new()
+            .with_name("freezer")
+            .create(&pool)
+            .await
+            .unwrap();
+        for barcode in ["plate-1", "plate-2"] {
+            LabwareFactory::new()
+                .with_barcode(barcode)
+                .with_location(&freezer)
+                .create(&pool)
+                .await
+                .unwrap();
+        }
+
+        let res = handle(
+            request(
+                "POST",
+                "/searches",
+                br#"{"owner": "jane", "name": "Plates", "q": "plate", "sort": "name-desc"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let body = response_json(res).await;
+        assert_eq!(body["status"], "active");
+        assert_eq!(body["limit"], 20);
+        let uuid = body["uuid"].as_str().unwrap().to_string();
+
+        let res = handle(
+            request(
+                "POST",
+                "/searches",
+                br#"{"owner": "jane", "name": "Plates", "q": "plate"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let res = handle(
+            request(
+                "POST",
+                "/searches",
+                br#"{"owner": "jane", "name": "Lost", "q": "plate", "status": "lost"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = handle(request("GET", "/searches?owner=jane", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await[0]["name"], "Plates");
+        let res = handle(request("GET", "/searches?owner=john", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await, serde_json::json!([]));
+
+        let path = format!("/searches/{}/results", uuid);
+        let res = handle(request("GET", &path, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["labwares"][0]["barcode"], "plate-2");
+        assert_eq!(body["labwares"][1]["barcode"], "plate-1");
+
+        let path = format!("/searches/{}", uuid);
+        let res = handle(request("DELETE", &path, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 204);
+        let res = handle(request("GET", &path, b""), pool).await.unwrap();
+        assert_eq!(res.status(), 404);
+    }
+}
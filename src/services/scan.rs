@@ -1,75 +1,1061 @@
-use crate::services::empty;
+use crate::services::coalesce::{Coalescer, SharedResponse};
+use crate::services::scan_payload::{read_scan, NewScan};
+use crate::services::{
+    current_locale, error_response, full, json, map_error, query_params, read_json, ServiceResponse,
+};
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
 use hyper::body::{Body, Bytes};
-use hyper::{Method, Request, Response, Result, StatusCode};
-use log::{error, info};
-use std::pin::Pin;
-use std::task::{Context, Poll};
+use hyper::{Request, Response, StatusCode};
+use labwhere::barcode::reader;
+use labwhere::config::CONFIG;
+use labwhere::errors::{FieldValidationError, ForbiddenError, ValidationError};
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::device::Device;
+use labwhere::models::location::Location;
+use labwhere::models::offline_scan::{OfflineConflict, OfflineOutcome, OfflineScan};
+use labwhere::models::scan::{Scan, ScanOperation};
+use labwhere::models::user::{Impersonation, User};
+use labwhere::storage::{ScanStore, SqliteStorage};
+use log::info;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::error::Error;
+use std::time::Duration;
+use validator::Validate;
+
+/// The header carrying the token of a location lock, required to scan into or out of a locked location.
+pub const LOCK_TOKEN_HEADER: &str = "x-lock-token";
+
+/// The header carrying the API key of the scan station a scan comes from.
+pub const DEVICE_KEY_HEADER: &str = "x-device-key";
+
+/// The header naming the login of the user an admin scans as, e.g. to reproduce a problem with
+/// their scans without knowing their swipe code.
+pub const ACT_AS_USER_HEADER: &str = "x-act-as-user";
+
+/// The largest image accepted by `POST /scan/image`, in bytes.
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Scans submitted recently, so that identical submissions (e.g. double trigger pulls) within the
+/// configured window return the result of the first one instead of scanning twice.
+///
+/// Unlike the caches in `cache`, this is never shared between instances of the server: the window
+/// is a fraction of a second, and it only dedupes repeats from the same scanner, which reuse its
+/// connection to the same instance.
+static RECENT_SCANS: Lazy<Coalescer> =
+    Lazy::new(|| Coalescer::new(Duration::from_millis(CONFIG.scan_coalesce_millis)));
+
+/// The payload for performing a batch of scans.
+#[derive(Debug, Deserialize, Validate)]
+struct NewScanBatch {
+    /// The swipe card or barcode of the user scanning
+    user_code: Option<String>,
+    /// The scans, performed in order
+    #[validate(length(min = 1, message = "validation-empty"))]
+    operations: Vec<ScanOperation>,
+}
+
+/// The payload for submitting scans recorded offline.
+#[derive(Debug, Deserialize, Validate)]
+struct NewOfflineScanBatch {
+    /// The swipe card or barcode of the user scanning
+    user_code: Option<String>,
+    /// The scans, in any order
+    #[validate(length(min = 1, message = "validation-empty"))]
+    scans: Vec<OfflineScan>,
+}
+
+impl NewScan {
+    /// The key identical scans are coalesced on: the user (and the user an admin acts as), the
+    /// location, the set of labware barcodes (ignoring order and repeats), the lock token, the
+    /// device and the locale the response is in.
+    fn coalesce_key(&self, lock_token: Option<&str>, device_key: Option<&str>) -> u64 {
+        let mut labware_barcodes: Vec<&str> =
+            self.labware_barcodes.iter().map(|b| b.trim()).collect();
+        labware_barcodes.sort_unstable();
+        labware_barcodes.dedup();
+        Coalescer::key(&(
+            self.user_code.as_deref(),
+            self.act_as.as_deref(),
+            self.location_barcode.as_deref().map(str::trim),
+            labware_barcodes,
+            lock_token,
+            device_key,
+            current_locale().to_string(),
+        ))
+    }
+}
 
 /// Receives location barcode and labware, scans them into LabWhere.
 /// - The incoming request implements `Send` trait as it is safe to be sent to another thread.
 /// - The incoming request implements `Sync` trait as it is safe to be used among multiple threads.
+///
 /// This function is a service function, and is to be passed as a closure to a hyper `service_fn`
 /// call.
+///
+/// `POST /scan` with `{"location_barcode": "lw-freezer-1", "labware_barcodes": ["lw-1"]}` responds
+/// with the scan: its `message`, the `location` with its `breadcrumb`, the `previous_location` and
+/// `location` of each of its `labwares`, the `warnings` it went ahead despite (a location at its
+/// capacity threshold, or a lock whose token was sent) and the `misplacements` it corrected, i.e.
+/// the labwares which were recorded in another location although nobody had checked them out. If a
+/// location involved is locked, the lock's token has to be sent in the `X-Lock-Token` header.
+/// Registered scan stations send their key in the `X-Device-Key` header, so the scan is recorded
+/// against the station; a disabled station is refused with 403. A station which is pinned to a
+/// location may leave `location_barcode` out to scan into that location. The `user_code` of a
+/// user's swipe card marks them active; a deactivated user is refused with 403. An admin (with the
+/// `X-Admin-Token` header) can scan as a user by naming their login in the `X-Act-As-User` header
+/// and giving the code of their own swipe card as `user_code`, otherwise the response is 403. The
+/// audits and events of the scan record both the admin (`real_user`) and the user
+/// (`effective_user`).
+///
+/// The payload may also be form data with new line separated barcodes, as posted to the Rails
+/// LabWhere (see `scan_payload::read_scan`); any other content type responds with 415.
+/// Responds with 400 if the body cannot be parsed, with 422 naming the fields if a field is
+/// missing, of the wrong type or unknown, and with 404 if the location is not found.
+///
+/// An identical scan submitted again within `LABWHERE_SCAN_COALESCE_MILLIS` (or while the first is
+/// still in progress) responds with the result of the first scan, without scanning again.
 pub async fn scan(
     req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
-) -> std::result::Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    pool: SqlitePool,
+) -> ServiceResponse {
     info!("Processing request for /scan endpoint");
-    match (req.method(), req.uri().path()) {
-        // Use https://github.com/hyperium/hyper/blob/master/examples/web_api.rs for processing the request
-        (&Method::POST, "/scan") => Ok(Response::new(req.into_body().boxed())),
-        _ => {
-            let mut not_found = Response::new(empty());
-            *not_found.status_mut() = StatusCode::NOT_FOUND;
-            error!("Responding with not found");
-            Ok(not_found)
+    let lock_token = lock_token(&req);
+    let device_key = device_key(&req);
+    let act_as = act_as(&req);
+    let mut payload = match read_scan(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    payload.act_as = act_as;
+    Ok(coalesced(payload, lock_token, device_key, pool).await)
+}
+
+/// Performs a batch of scans in one request, for robots which would otherwise make a request for
+/// each tube.
+///
+/// `POST /scans/batch` with `{"operations": [{"location_barcode": "lw-rack-1", "labware_barcodes":
+/// ["lw-1"]}, ...]}` performs up to `LABWHERE_SCAN_BATCH_MAX` scans in order and responds with an
+/// array of their results, each being what `POST /scan` would respond with plus its `status`: a
+/// scan which fails (e.g. with 404 for an unknown location) does not stop the others. The whole
+/// batch is committed at once, with each scan rolled back on its own if it fails.
+///
+/// `user_code` and the `X-Lock-Token`, `X-Device-Key` and `X-Act-As-User` headers are handled as
+/// for `POST /scan`, and apply to every scan of the batch.
+pub async fn batch(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /scans/batch endpoint");
+    let lock_token = lock_token(&req);
+    let device_key = device_key(&req);
+    let act_as = act_as(&req);
+    let payload = match read_json::<NewScanBatch>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    if payload.operations.len() > CONFIG.scan_batch_max {
+        return Ok(map_error(&ValidationError {
+            message: Message::new("scan-batch-too-large").arg("max", CONFIG.scan_batch_max),
+        }));
+    }
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let (device, impersonation) = match scanning_device(
+        payload.user_code.as_deref(),
+        act_as.as_deref(),
+        device_key.as_deref(),
+        &mut connection,
+    )
+    .await
+    {
+        Ok(scanning) => scanning,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    let device_id = device.map(|device| device.id);
+    let results =
+        match Impersonation::perform(impersonation.as_ref(), &mut connection, |connection| {
+            Box::pin(async move {
+                Ok(Scan::create_batch(
+                    payload.operations,
+                    lock_token.as_deref(),
+                    device_id,
+                    connection,
+                )
+                .await?)
+            })
+        })
+        .await
+        {
+            Ok(results) => results,
+            Err(e) => return Ok(map_error(&*e)),
+        };
+    let mut bodies = Vec::with_capacity(results.len());
+    for result in results {
+        let body = match result {
+            Ok(scan) => {
+                let mut body = scan_body(&scan);
+                body["status"] = StatusCode::OK.as_u16().into();
+                body
+            }
+            Err(e) => error_body(&*e).await,
+        };
+        bodies.push(body);
+    }
+    Ok(json(StatusCode::OK, &bodies))
+}
+
+/// Submits the scans a client recorded while it was offline, e.g. a PWA which queued them.
+///
+/// `POST /scans/offline_batch` with `{"scans": [{"uuid": "9b2f6c1e-...", "scanned_at":
+/// "2026-10-16T09:30:00Z", "location_barcode": "lw-rack-1", "labware_barcodes": ["lw-1"]}, ...]}`
+/// applies up to `LABWHERE_SCAN_BATCH_MAX` scans in the order they were scanned, and responds with
+/// an array of their results in the order they were sent. Each result has the `uuid` of its scan,
+/// its `status` and its `outcome`:
+/// - `applied`: the scan was applied, and the result is what `POST /scan` would respond with
+/// - `duplicate`: a scan with the UUID was applied before, and the result is that scan
+/// - `conflicted` (with status 409): nothing was scanned, as every labware changed after the scan
+///   was recorded
+///
+/// Labwares which changed after their scan was recorded (e.g. were scanned elsewhere in the
+/// meantime) are left where they are and listed in the `conflicts` of the result, with the
+/// `location` they are in now, when they `changed_at` and a `message`. A scan which fails (e.g.
+/// with 404 for an unknown location) does not stop the others, and can be sent again. A
+/// `scanned_at` too far from the server's clock is clamped, with a warning, or fails with 422,
+/// according to `LABWHERE_TIMESTAMP_SKEW_POLICY`.
+///
+/// `user_code` and the `X-Lock-Token`, `X-Device-Key` and `X-Act-As-User` headers are handled as
+/// for `POST /scan`, and apply to every scan of the batch.
+pub async fn offline_batch(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /scans/offline_batch endpoint");
+    let lock_token = lock_token(&req);
+    let device_key = device_key(&req);
+    let act_as = act_as(&req);
+    let payload = match read_json::<NewOfflineScanBatch>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    if payload.scans.len() > CONFIG.scan_batch_max {
+        return Ok(map_error(&ValidationError {
+            message: Message::new("scan-batch-too-large").arg("max", CONFIG.scan_batch_max),
+        }));
+    }
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let (device, impersonation) = match scanning_device(
+        payload.user_code.as_deref(),
+        act_as.as_deref(),
+        device_key.as_deref(),
+        &mut connection,
+    )
+    .await
+    {
+        Ok(scanning) => scanning,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    let uuids: Vec<String> = payload.scans.iter().map(|scan| scan.uuid.clone()).collect();
+    let device_id = device.map(|device| device.id);
+    let outcomes =
+        match Impersonation::perform(impersonation.as_ref(), &mut connection, |connection| {
+            Box::pin(async move {
+                Ok(OfflineScan::apply_batch(
+                    payload.scans,
+                    lock_token.as_deref(),
+                    device_id,
+                    connection,
+                )
+                .await?)
+            })
+        })
+        .await
+        {
+            Ok(outcomes) => outcomes,
+            Err(e) => return Ok(map_error(&*e)),
+        };
+    let mut bodies = Vec::with_capacity(outcomes.len());
+    for (uuid, outcome) in uuids.into_iter().zip(outcomes) {
+        let (mut body, status, outcome, conflicts) = match outcome {
+            Ok(OfflineOutcome::Applied(scan, conflicts)) => {
+                (scan_body(&scan), StatusCode::OK, "applied", conflicts)
+            }
+            Ok(OfflineOutcome::Duplicate(scan)) => {
+                (scan_body(&scan), StatusCode::OK, "duplicate", vec![])
+            }
+            Ok(OfflineOutcome::Conflicted(conflicts)) => (
+                serde_json::json!({ "uuid": uuid }),
+                StatusCode::CONFLICT,
+                "conflicted",
+                conflicts,
+            ),
+            Err(e) => {
+                let mut body = error_body(&*e).await;
+                body["uuid"] = uuid.into();
+                bodies.push(body);
+                continue;
+            }
+        };
+        body["status"] = status.as_u16().into();
+        body["outcome"] = outcome.into();
+        body["conflicts"] = conflicts_body(&conflicts);
+        bodies.push(body);
+    }
+    Ok(json(StatusCode::OK, &bodies))
+}
+
+/// Scans the labwares in a photo (e.g. taken with a phone camera of a box of tubes) into a
+/// location, for when no hardware scanner is available.
+///
+/// `POST /scan/image` with a PNG or JPEG as the body decodes every visible 1D and 2D barcode and
+/// scans them like `POST /scan`. The location is given with `?location_barcode=lw-freezer-1`, or
+/// else taken from the first decoded barcode which is a location, or else the location the device
+/// is pinned to; every other barcode is scanned as a labware. `?user_code=` and the
+/// `X-Lock-Token`, `X-Device-Key` and `X-Act-As-User` headers are handled as for `POST /scan`.
+///
+/// Responds with 413 if the image is larger than 20 MB, and with 422 if it cannot be read or no
+/// location is found.
+pub async fn scan_image(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /scan/image endpoint");
+    let lock_token = lock_token(&req);
+    let device_key = device_key(&req);
+    let act_as = act_as(&req);
+    let params = query_params(&req);
+    let image = match Limited::new(req.into_body(), MAX_IMAGE_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(e) if e.is::<LengthLimitError>() => {
+            return Ok(error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Message::new("scan-image-too-large")
+                    .arg("max", MAX_IMAGE_BYTES / 1024 / 1024)
+                    .localize(&current_locale()),
+            ))
         }
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+
+    // Decoding is CPU-bound, so it is kept off the async workers.
+    let barcodes = match tokio::task::spawn_blocking(move || reader::decode(&image)).await {
+        Ok(Ok(barcodes)) => barcodes,
+        Ok(Err(e)) => return Ok(map_error(&e)),
+        Err(e) => return Ok(map_error(&e)),
+    };
+    info!("Decoded {} barcodes from image", barcodes.len());
+
+    let (location_barcode, labware_barcodes) = match params.get("location_barcode") {
+        Some(location_barcode) => (Some(location_barcode.clone()), barcodes),
+        None => {
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            let mut location_barcode = None;
+            for barcode in &barcodes {
+                if Location::find_by_barcode(barcode.clone(), &mut connection)
+                    .await
+                    .is_ok()
+                {
+                    location_barcode = Some(barcode.clone());
+                    break;
+                }
+            }
+            match location_barcode {
+                Some(location_barcode) => {
+                    let labware_barcodes = barcodes
+                        .into_iter()
+                        .filter(|barcode| !barcode.eq_ignore_ascii_case(&location_barcode))
+                        .collect();
+                    (Some(location_barcode), labware_barcodes)
+                }
+                // A pinned device scans into its location
+                None if device_key.is_some() => (None, barcodes),
+                None => {
+                    return Ok(map_error(&ValidationError {
+                        message: Message::new("scan-image-no-location"),
+                    }))
+                }
+            }
+        }
+    };
+
+    let payload = NewScan {
+        user_code: params.get("user_code").cloned(),
+        location_barcode,
+        labware_barcodes,
+        act_as,
+    };
+    Ok(coalesced(payload, lock_token, device_key, pool).await)
+}
+
+/// The lock token sent with a request, if any.
+pub(crate) fn lock_token<B>(req: &Request<B>) -> Option<String> {
+    req.headers()
+        .get(LOCK_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// The key of the scan station a request comes from, if any.
+pub(crate) fn device_key<B>(req: &Request<B>) -> Option<String> {
+    req.headers()
+        .get(DEVICE_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// The login of the user an admin scans as, if any. Only admins may send the header (see
+/// `auth::admin_only`).
+fn act_as<B>(req: &Request<B>) -> Option<String> {
+    req.headers()
+        .get(ACT_AS_USER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// Performs a scan unless an identical scan was submitted recently, and responds with its result.
+async fn coalesced(
+    payload: NewScan,
+    lock_token: Option<String>,
+    device_key: Option<String>,
+    pool: SqlitePool,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let key = payload.coalesce_key(lock_token.as_deref(), device_key.as_deref());
+    let (status, body) = RECENT_SCANS
+        .run(key, || perform(payload, lock_token, device_key, pool))
+        .await;
+    let mut response = Response::new(full(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+/// Performs a scan, returning a response which can be shared with coalesced requests.
+async fn perform(
+    payload: NewScan,
+    lock_token: Option<String>,
+    device_key: Option<String>,
+    pool: SqlitePool,
+) -> SharedResponse {
+    let response = match acquire(&pool).await {
+        Ok(mut connection) => {
+            match perform_on(payload, lock_token, device_key, &mut connection).await {
+                Ok(scan) => json(StatusCode::OK, &scan_body(&scan)),
+                Err(e) => map_error(&*e),
+            }
+        }
+        Err(e) => map_error(&e),
+    };
+    let status = response.status();
+    match response.into_body().collect().await {
+        Ok(body) => (status, body.to_bytes()),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Bytes::new()),
     }
 }
 
-/// `MockBody` is a utility body written **only** for tests.
-struct MockBody {
-    data: &'static [u8],
+/// Performs a scan on a connection, as the user an admin acts as, if any.
+async fn perform_on(
+    payload: NewScan,
+    lock_token: Option<String>,
+    device_key: Option<String>,
+    connection: &mut SqliteConnection,
+) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+    let (device, impersonation) = scanning_device(
+        payload.user_code.as_deref(),
+        payload.act_as.as_deref(),
+        device_key.as_deref(),
+        &mut *connection,
+    )
+    .await?;
+    let location_barcode =
+        location_barcode(payload.location_barcode, device.as_ref(), &mut *connection).await?;
+    let device_id = device.map(|device| device.id);
+    Impersonation::perform(impersonation.as_ref(), connection, |connection| {
+        Box::pin(async move {
+            SqliteStorage::new(connection)
+                .scan(
+                    &location_barcode,
+                    payload.labware_barcodes,
+                    lock_token.as_deref(),
+                    device_id,
+                )
+                .await
+        })
+    })
+    .await
 }
 
-impl MockBody {
-    fn new(data: &'static [u8]) -> Self {
-        Self { data }
+/// The body of the response to a scan, with its `message` and `warnings` in the current locale.
+fn scan_body(scan: &Scan) -> serde_json::Value {
+    let locale = current_locale();
+    let mut body = serde_json::to_value(scan).unwrap_or_default();
+    if let Some(summary) = &scan.summary {
+        body["message"] = summary.localize(&locale).into();
     }
+    let warnings: Vec<String> = scan
+        .warnings
+        .iter()
+        .map(|warning| warning.localize(&locale))
+        .collect();
+    body["warnings"] = warnings.into();
+    body
 }
 
-impl Body for MockBody {
-    type Data = Bytes;
-    type Error = hyper::Error;
+/// The body of the error a scan of a batch failed with, with its `status`.
+async fn error_body(e: &(dyn Error + Send + Sync + 'static)) -> serde_json::Value {
+    let response = map_error(e);
+    let status = response.status();
+    let mut body = match response.into_body().collect().await {
+        Ok(body) => serde_json::from_slice(&body.to_bytes()).unwrap_or_default(),
+        Err(_) => serde_json::Value::default(),
+    };
+    body["status"] = status.as_u16().into();
+    body
+}
 
-    fn poll_frame(
-        mut self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
-    ) -> Poll<Option<Result<http_body::Frame<Bytes>>>> {
-        if self.data.is_empty() {
-            Poll::Ready(None)
-        } else {
-            let data = self.data;
-            self.data = &[];
-            Poll::Ready(Some(Ok(http_body::Frame::data(Bytes::from(data)))))
+/// The conflicts of an offline scan, each with its `message` in the current locale.
+fn conflicts_body(conflicts: &[OfflineConflict]) -> serde_json::Value {
+    let locale = current_locale();
+    conflicts
+        .iter()
+        .map(|conflict| {
+            let mut body = serde_json::to_value(conflict).unwrap_or_default();
+            body["message"] = Message::new("scan-offline-conflict")
+                .arg("barcode", &conflict.barcode)
+                .arg("location", &conflict.location)
+                .localize(&locale)
+                .into();
+            body
+        })
+        .collect()
+}
+
+/// Authenticates the device a scan comes from, if it identified itself, and records the activity
+/// of the user scanning.
+///
+/// An admin acting as a user has to give the `user_code` of their own swipe card, so the scan is
+/// audited with them as the real user; it does not count as the user's activity.
+async fn scanning_device(
+    user_code: Option<&str>,
+    act_as: Option<&str>,
+    device_key: Option<&str>,
+    connection: &mut SqliteConnection,
+) -> Result<(Option<Device>, Option<Impersonation>), Box<dyn Error + Send + Sync>> {
+    let device = match device_key {
+        Some(key) => Some(Device::authenticate(key, &mut *connection).await?),
+        None => None,
+    };
+    let impersonation = match (act_as, user_code) {
+        (Some(login), Some(user_code)) => {
+            Some(User::act_as(login, user_code, "scan", connection).await?)
+        }
+        (Some(_), None) => {
+            return Err(Box::new(ForbiddenError {
+                message: Message::new("user-act-as-admin-required"),
+            }))
+        }
+        (None, Some(user_code)) => {
+            User::record_activity(user_code, connection).await?;
+            None
         }
+        (None, None) => None,
+    };
+    Ok((device, impersonation))
+}
+
+/// The barcode of the location to scan into: the one given, or else the one the device is
+/// pinned to. Returns a `FieldValidationError` if there is neither.
+async fn location_barcode(
+    location_barcode: Option<String>,
+    device: Option<&Device>,
+    connection: &mut SqliteConnection,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    if let Some(location_barcode) = location_barcode {
+        return Ok(location_barcode);
+    }
+    let pinned = match device {
+        Some(device) => device.pinned_location(connection).await?,
+        None => None,
+    };
+    match pinned.and_then(|location| location.barcode) {
+        Some(location_barcode) => Ok(location_barcode),
+        None => Err(Box::new(FieldValidationError::field(
+            "location_barcode",
+            Message::new("validation-required"),
+        ))),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::services::scan::MockBody;
+    use crate::services::{handle, mock_request, response_json, MockBody};
+    use image::{GrayImage, ImageFormat, Luma};
+    use labwhere::db::init_pool;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_lock::LocationLock;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::models::user::{Role, User};
+    use qrcode::{Color, QrCode};
+    use sqlx::SqlitePool;
+    use std::io::Cursor;
+
+    async fn setup() -> SqlitePool {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        pool
+    }
 
     #[tokio::test]
     async fn test_scan() {
-        let body: MockBody = MockBody::new(b"anything");
+        let pool = setup().await;
+        let body: MockBody = MockBody::new(
+            br#"{"location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-1", "lw-2"]}"#,
+        );
         let req = hyper::Request::builder()
             .method("POST")
             .uri("/scan")
             .body(body)
             .unwrap();
-        let res = super::scan(req).await.unwrap();
+        let res = super::scan(req, pool).await.unwrap();
         assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["message"], "2 labwares scanned into freezer1");
+        assert_eq!(
+            body["location"]["breadcrumb"],
+            serde_json::json!(["freezer1"])
+        );
+        assert_eq!(body["labwares"][1]["barcode"], "lw-2");
+        assert_eq!(body["labwares"][1]["location"], "freezer1");
+        assert_eq!(body["warnings"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_scan_into_unknown_location() {
+        let pool = setup().await;
+        let req = mock_request(
+            "POST",
+            "/scan",
+            br#"{"location_barcode": "lw-fridge-9", "labware_barcodes": ["lw-1"]}"#,
+        );
+        let res = super::scan(req, pool).await.unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_scan_batch() {
+        let pool = setup().await;
+        let req = mock_request(
+            "POST",
+            "/scans/batch",
+            br#"{"operations": [
+                {"location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-1", "lw-2"]},
+                {"location_barcode": "lw-fridge-9", "labware_barcodes": ["lw-3"]},
+                {"location_barcode": "lw-freezer1-1", "labware_barcodes": []}
+            ]}"#,
+        );
+        let res = handle(req, pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["status"], 200);
+        assert_eq!(body[0]["message"], "2 labwares scanned into freezer1");
+        assert_eq!(body[1]["status"], 404);
+        assert_eq!(body[1]["errors"][0], "Location not found");
+        assert_eq!(body[2]["status"], 422);
+
+        let req = mock_request("POST", "/scans/batch", br#"{"operations": []}"#);
+        let res = handle(req, pool).await.unwrap();
+        assert_eq!(res.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_scan_offline_batch() {
+        let pool = setup().await;
+        let res = super::scan(
+            mock_request(
+                "POST",
+                "/scan",
+                br#"{"location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-5"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let scanned_at = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+        let payload = format!(
+            r#"{{"scans": [
+                {{"uuid": "9b2f6c1e-6f2a-4d43-9a3e-0c6d5b1f0e21", "scanned_at": "{scanned_at}",
+                  "location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-5", "lw-2"]}},
+                {{"uuid": "3c1d2e4f-5a6b-4c7d-8e9f-a0b1c2d3e4f5", "scanned_at": "{scanned_at}",
+                  "location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-5"]}},
+                {{"uuid": "4d2e3f5a-6b7c-4d8e-9fa0-b1c2d3e4f5a6", "scanned_at": "{scanned_at}",
+                  "location_barcode": "lw-fridge-9", "labware_barcodes": ["lw-3"]}}
+            ]}}"#
+        );
+        let payload: &'static [u8] = Box::leak(payload.into_bytes().into_boxed_slice());
+
+        let req = mock_request("POST", "/scans/offline_batch", payload);
+        let res = handle(req, pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["outcome"], "applied");
+        assert_eq!(body[0]["uuid"], "9b2f6c1e-6f2a-4d43-9a3e-0c6d5b1f0e21");
+        assert_eq!(body[0]["message"], "1 labwares scanned into freezer1");
+        assert_eq!(body[0]["conflicts"][0]["barcode"], "lw-5");
+        assert_eq!(
+            body[0]["conflicts"][0]["message"],
+            "lw-5 changed after the scan was recorded and is now in freezer1"
+        );
+        assert_eq!(body[1]["status"], 409);
+        assert_eq!(body[1]["outcome"], "conflicted");
+        assert_eq!(body[2]["status"], 404);
+        assert_eq!(body[2]["uuid"], "4d2e3f5a-6b7c-4d8e-9fa0-b1c2d3e4f5a6");
+
+        let req = mock_request("POST", "/scans/offline_batch", payload);
+        let body = response_json(handle(req, pool).await.unwrap()).await;
+        assert_eq!(body[0]["status"], 200);
+        assert_eq!(body[0]["outcome"], "duplicate");
+        assert_eq!(body[0]["message"], "1 labwares scanned into freezer1");
+    }
+
+    #[tokio::test]
+    async fn test_scan_into_locked_location() {
+        let pool = setup().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let lock = LocationLock::acquire(1, "jane".to_string(), 300, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        let payload = br#"{"location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-1"]}"#;
+
+        let res = super::scan(mock_request("POST", "/scan", payload), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 423);
+
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("X-Lock-Token", lock.token)
+            .body(MockBody::new(payload))
+            .unwrap();
+        let res = super::scan(req, pool).await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_scans_are_coalesced() {
+        let pool = setup().await;
+        let payload = br#"{"user_code": "jane", "location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-dup-1", "lw-dup-2"]}"#;
+        let reordered = br#"{"user_code": "jane", "location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-dup-2", "lw-dup-1"]}"#;
+
+        let (first, second) = tokio::join!(
+            super::scan(mock_request("POST", "/scan", payload), pool.clone()),
+            super::scan(mock_request("POST", "/scan", reordered), pool.clone())
+        );
+        let first = response_json(first.unwrap()).await;
+        let second = response_json(second.unwrap()).await;
+        assert_eq!(first["uuid"], second["uuid"]);
+
+        let mut conn = pool.acquire().await.unwrap();
+        let scans = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM scans")
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(scans, 1);
+    }
+
+    #[tokio::test]
+    async fn test_scans_by_different_users_are_not_coalesced() {
+        let pool = setup().await;
+        let jane = br#"{"user_code": "jane", "location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-user-1"]}"#;
+        let john = br#"{"user_code": "john", "location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-user-1"]}"#;
+
+        let first = super::scan(mock_request("POST", "/scan", jane), pool.clone())
+            .await
+            .unwrap();
+        let second = super::scan(mock_request("POST", "/scan", john), pool)
+            .await
+            .unwrap();
+        assert_ne!(
+            response_json(first).await["uuid"],
+            response_json(second).await["uuid"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_by_deactivated_user() {
+        let pool = setup().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let user = User::create(
+            "jd12".to_string(),
+            Role::Technician,
+            None,
+            Some("card-7".to_string()),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        User::deactivate(&user.uuid, &mut conn).await.unwrap();
+        drop(conn);
+
+        let payload = br#"{"user_code": "card-7", "location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-1"]}"#;
+        let res = super::scan(mock_request("POST", "/scan", payload), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "User jd12 has been deactivated"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_act_as_user_requires_admin() {
+        let pool = setup().await;
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("X-Act-As-User", "jd12")
+            .body(MockBody::new(
+                br#"{"location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-1"]}"#,
+            ))
+            .unwrap();
+        let res = handle(req, pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 403);
+
+        // Even past the admin check, the admin has to identify themselves with their swipe card
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("X-Act-As-User", "jd12")
+            .body(MockBody::new(
+                br#"{"location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-1"]}"#,
+            ))
+            .unwrap();
+        let res = super::scan(req, pool).await.unwrap();
+        assert_eq!(res.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_scan_invalid_payload() {
+        let pool = setup().await;
+        let res = super::scan(
+            mock_request(
+                "POST",
+                "/scan",
+                br#"{"location_barcode": "", "labware_barcodes": []}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let body = response_json(res).await;
+        assert_eq!(body["fields"]["location_barcode"][0], "can't be blank");
+        assert_eq!(body["fields"]["labware_barcodes"][0], "can't be empty");
+        assert_eq!(body["errors"][0], "Labware barcodes can't be empty");
+    }
+
+    #[tokio::test]
+    async fn test_scan_without_labware_barcodes() {
+        let pool = setup().await;
+        let res = super::scan(
+            mock_request(
+                "POST",
+                "/scan",
+                br#"{"location_barcode": "lw-freezer1-1", "labwares": ["lw-1"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let body = response_json(res).await;
+        assert_eq!(body["fields"]["labware_barcodes"][0], "is required");
+        assert_eq!(body["fields"]["labwares"][0], "is not allowed");
+
+        let res = super::scan(mock_request("POST", "/scan", b"{\"labware"), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_scan_form() {
+        let pool = setup().await;
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(MockBody::new(
+                b"scan%5Blocation_barcode%5D=lw-freezer1-1&scan%5Blabware_barcodes%5D=lw-1%0Alw-2",
+            ))
+            .unwrap();
+        let res = super::scan(req, pool.clone()).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["message"], "2 labwares scanned into freezer1");
+
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("content-type", "text/xml")
+            .body(MockBody::new(b"<scan/>"))
+            .unwrap();
+        let res = super::scan(req, pool).await.unwrap();
+        assert_eq!(res.status(), 415);
+    }
+
+    #[tokio::test]
+    async fn test_scan_in_requested_language() {
+        let pool = setup().await;
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("Accept-Language", "es-ES, en;q=0.5")
+            .body(MockBody::new(
+                br#"{"location_barcode": "lw-freezer1-1", "labware_barcodes": ["lw-es-1"]}"#,
+            ))
+            .unwrap();
+        let res = crate::services::handle(req, pool.clone()).await.unwrap();
+        let body = response_json(res).await;
+        assert_eq!(body["message"], "1 labwares escaneados en freezer1");
+
+        let req = hyper::Request::builder()
+            .method("POST")
+            .uri("/scan")
+            .header("Accept-Language", "es")
+            .body(MockBody::new(
+                br#"{"location_barcode": "lw-fridge-9", "labware_barcodes": ["lw-es-1"]}"#,
+            ))
+            .unwrap();
+        let res = crate::services::handle(req, pool).await.unwrap();
+        assert_eq!(res.status(), 404);
+        let body = response_json(res).await;
+        assert_eq!(body["errors"][0], "No se encontró la ubicación");
+    }
+
+    /// A PNG photo of QR codes of the values, side by side.
+    fn photo(values: &[&str]) -> &'static [u8] {
+        const SCALE: u32 = 6;
+        let codes: Vec<QrCode> = values.iter().map(|v| QrCode::new(v).unwrap()).collect();
+        let cell = codes
+            .iter()
+            .map(|c| c.width() as u32 + 8)
+            .max()
+            .unwrap_or(8)
+            * SCALE;
+        let mut picture =
+            GrayImage::from_pixel(cell * codes.len().max(1) as u32, cell, Luma([255]));
+        for (i, code) in codes.iter().enumerate() {
+            for (index, color) in code.to_colors().iter().enumerate() {
+                if *color == Color::Dark {
+                    let x = i as u32 * cell + (4 + (index % code.width()) as u32) * SCALE;
+                    let y = (4 + (index / code.width()) as u32) * SCALE;
+                    for (dx, dy) in (0..SCALE).flat_map(|dx| (0..SCALE).map(move |dy| (dx, dy))) {
+                        picture.put_pixel(x + dx, y + dy, Luma([0]));
+                    }
+                }
+            }
+        }
+        let mut png = Cursor::new(vec![]);
+        picture.write_to(&mut png, ImageFormat::Png).unwrap();
+        Box::leak(png.into_inner().into_boxed_slice())
+    }
+
+    #[tokio::test]
+    async fn test_scan_image() {
+        let pool = setup().await;
+        let image = photo(&["lw-image-1", "lw-freezer1-1", "lw-image-2"]);
+        let res = handle(mock_request("POST", "/scan/image", image), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["message"], "2 labwares scanned into freezer1");
+    }
+
+    #[tokio::test]
+    async fn test_scan_image_into_given_location() {
+        let pool = setup().await;
+        let image = photo(&["lw-image-3"]);
+        let res = handle(
+            mock_request("POST", "/scan/image?location_barcode=lw-freezer1-1", image),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["message"], "1 labwares scanned into freezer1");
+
+        let mut conn = pool.acquire().await.unwrap();
+        let labware = labwhere::models::labware::Labware::find_by_barcode(
+            "lw-image-3".to_string(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(labware.location_id, 1);
+    }
+
+    #[tokio::test]
+    async fn test_scan_image_without_location() {
+        let pool = setup().await;
+        let res = handle(
+            mock_request("POST", "/scan/image", photo(&["lw-image-4"])),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+        let body = response_json(res).await;
+        assert_eq!(
+            body["errors"][0],
+            "No location barcode was found in the image"
+        );
+
+        let res = handle(mock_request("POST", "/scan/image", b"not an image"), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_scan_not_found() {
+        let pool = setup().await;
+        let res = handle(mock_request("GET", "/scan", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 405);
+        assert_eq!(res.headers()["allow"], "POST");
+
+        let res = handle(mock_request("POST", "/scan/nowhere", b"{}"), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
     }
 }
@@ -0,0 +1,379 @@
+use crate::services::{current_locale, error_response, map_error};
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Request, Response, StatusCode};
+use labwhere::errors::{FieldValidationError, ValidationError};
+use labwhere::i18n::Message;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use validator::Validate;
+
+/// The form fields added by Rails' form helpers to the legacy scan form, which are not part of the
+/// scan.
+const IGNORED_FORM_FIELDS: [&str; 4] = ["utf8", "authenticity_token", "commit", "_method"];
+
+/// The payload for scanning labwares into a location.
+#[derive(Debug, Default, Validate)]
+pub(crate) struct NewScan {
+    /// The swipe card or barcode of the user scanning
+    pub(crate) user_code: Option<String>,
+    /// The barcode of the location to scan the labwares into. May be left out by a device which is
+    /// pinned to a location.
+    #[validate(length(min = 1, message = "validation-blank"))]
+    pub(crate) location_barcode: Option<String>,
+    /// The barcodes of the labwares
+    #[validate(length(min = 1, message = "validation-empty"))]
+    pub(crate) labware_barcodes: Vec<String>,
+    /// The login of the user an admin scans as, from the `X-Act-As-User` header
+    pub(crate) act_as: Option<String>,
+}
+
+/// Why a scan payload could not be read.
+#[derive(Debug)]
+pub(crate) enum PayloadError {
+    /// The body is not JSON (or form data) at all. Responds with 400.
+    Malformed(String),
+    /// The body is well formed but not a scan, e.g. a field is missing or has the wrong type.
+    /// Responds like `map_error`, with 422 naming the invalid fields.
+    Invalid(Box<dyn Error + Send + Sync>),
+}
+
+impl PayloadError {
+    /// The response to a request with this payload.
+    pub(crate) fn response(&self) -> Response<BoxBody<Bytes, hyper::Error>> {
+        match self {
+            PayloadError::Malformed(reason) => {
+                error_response(StatusCode::BAD_REQUEST, reason.clone())
+            }
+            PayloadError::Invalid(e) => map_error(&**e),
+        }
+    }
+}
+
+/// The formats a scan payload can be sent in.
+#[derive(Debug, PartialEq)]
+enum Format {
+    /// `application/json`, or any body without a content type
+    Json,
+    /// `application/x-www-form-urlencoded`, as posted by the legacy scan form and scanner
+    /// integrations written for it
+    Form,
+}
+
+impl Format {
+    /// The format of a body with the given content type, or `None` if it is not supported.
+    fn of(content_type: Option<&str>) -> Option<Format> {
+        let Some(content_type) = content_type else {
+            return Some(Format::Json);
+        };
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match media_type.as_str() {
+            "application/x-www-form-urlencoded" => Some(Format::Form),
+            "" | "application/json" => Some(Format::Json),
+            media_type if media_type.ends_with("+json") => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the payload of `POST /scan`, in the format given by its content type: JSON, or form data
+/// (e.g. `scan[location_barcode]=lw-freezer-1&scan[labware_barcodes]=lw-1%0Alw-2`) as posted by
+/// the legacy scan form. Any other content type responds with 415.
+///
+/// Unlike `read_json`, every field is checked: a missing `labware_barcodes`, a field of the wrong
+/// type or a field which is not part of a scan responds with 422, with the messages of each
+/// offending field under `fields`.
+pub(crate) async fn read_scan(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error>>,
+) -> Result<NewScan, Response<BoxBody<Bytes, hyper::Error>>> {
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|v| v.to_str().unwrap_or_default());
+    let Some(format) = Format::of(content_type) else {
+        return Err(error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Message::new("scan-unsupported-content-type").localize(&current_locale()),
+        ));
+    };
+    let body = req
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))?
+        .to_bytes();
+    let payload = match format {
+        Format::Json => from_json(&body),
+        Format::Form => from_form(&body),
+    };
+    payload.map_err(|e| e.response())
+}
+
+/// Parses a scan from a JSON object. As with the Rails API, the fields may be wrapped in a `scan`
+/// object, and `labware_barcodes` may be a single string of barcodes separated by new lines.
+pub(crate) fn from_json(body: &[u8]) -> Result<NewScan, PayloadError> {
+    let value: Value =
+        serde_json::from_slice(body).map_err(|e| PayloadError::Malformed(e.to_string()))?;
+    let object = match value {
+        Value::Object(mut object) if object.len() == 1 && object.contains_key("scan") => {
+            object.remove("scan")
+        }
+        value => Some(value),
+    };
+    let Some(Value::Object(object)) = object else {
+        return Err(PayloadError::Invalid(Box::new(ValidationError {
+            message: Message::new("scan-payload-not-object"),
+        })));
+    };
+    let mut payload = NewScan::default();
+    let mut errors = FieldErrors::default();
+    let mut has_labware_barcodes = false;
+    for (field, value) in object {
+        match field.as_str() {
+            "user_code" => payload.user_code = errors.check(&field, optional_string(value)),
+            "location_barcode" => {
+                payload.location_barcode = errors.check(&field, optional_string(value))
+            }
+            "labware_barcodes" if !value.is_null() => {
+                has_labware_barcodes = true;
+                payload.labware_barcodes = errors.check(&field, strings(value));
+            }
+            "labware_barcodes" => {}
+            _ => errors.add(&field, Message::new("validation-unknown-field")),
+        }
+    }
+    if !has_labware_barcodes {
+        errors.add("labware_barcodes", Message::new("validation-required"));
+    }
+    errors.validate(payload)
+}
+
+/// Parses a scan from form data. Fields may be wrapped as `scan[labware_barcodes]` and lists may be
+/// suffixed with `[]`. Each `labware_barcodes` value may hold several barcodes separated by
+/// commas, spaces or new lines, as typed into the textarea of the legacy form.
+pub(crate) fn from_form(body: &[u8]) -> Result<NewScan, PayloadError> {
+    let mut payload = NewScan::default();
+    let mut errors = FieldErrors::default();
+    let mut has_labware_barcodes = false;
+    let mut seen = Vec::new();
+    for (key, value) in form_urlencoded::parse(body) {
+        let field = key
+            .strip_prefix("scan[")
+            .and_then(|key| key.strip_suffix(']'))
+            .unwrap_or(&key);
+        let field = field.strip_suffix("[]").unwrap_or(field);
+        match field {
+            "user_code" | "location_barcode" if seen.contains(&field.to_string()) => {
+                errors.add(field, Message::new("validation-repeated"))
+            }
+            "user_code" => payload.user_code = Some(value.to_string()).filter(|v| !v.is_empty()),
+            "location_barcode" => payload.location_barcode = Some(value.trim().to_string()),
+            "labware_barcodes" => {
+                has_labware_barcodes = true;
+                payload.labware_barcodes.extend(split_barcodes(&value));
+            }
+            field if IGNORED_FORM_FIELDS.contains(&field) => {}
+            field => errors.add(field, Message::new("validation-unknown-field")),
+        }
+        seen.push(field.to_string());
+    }
+    if !has_labware_barcodes {
+        errors.add("labware_barcodes", Message::new("validation-required"));
+    }
+    errors.validate(payload)
+}
+
+/// A field which must be a string, if given.
+fn optional_string(value: Value) -> Result<Option<String>, Message> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(value) => Ok(Some(value)),
+        _ => Err(Message::new("validation-expected-string")),
+    }
+}
+
+/// The barcodes in a string of barcodes separated by new lines, commas or spaces.
+fn split_barcodes(barcodes: &str) -> Vec<String> {
+    barcodes
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|barcode| !barcode.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// A field which must be a list of strings, or a string of barcodes separated by new lines.
+fn strings(value: Value) -> Result<Vec<String>, Message> {
+    let values = match value {
+        Value::Array(values) => values,
+        Value::String(barcodes) => return Ok(split_barcodes(&barcodes)),
+        _ => return Err(Message::new("validation-expected-strings")),
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(value) => Ok(value),
+            _ => Err(Message::new("validation-expected-strings")),
+        })
+        .collect()
+}
+
+/// The messages of each invalid field found while parsing a payload.
+#[derive(Default)]
+struct FieldErrors {
+    fields: BTreeMap<String, Vec<Message>>,
+}
+
+impl FieldErrors {
+    /// Adds a message for a field.
+    fn add(&mut self, field: &str, message: Message) {
+        self.fields
+            .entry(field.to_string())
+            .or_default()
+            .push(message);
+    }
+
+    /// The value of a field, or its default after adding the message if it is invalid.
+    fn check<T: Default>(&mut self, field: &str, value: Result<T, Message>) -> T {
+        value.unwrap_or_else(|message| {
+            self.add(field, message);
+            T::default()
+        })
+    }
+
+    /// Validates the parsed payload, reporting each field once: a field which could not be parsed
+    /// is not validated further.
+    fn validate(mut self, payload: NewScan) -> Result<NewScan, PayloadError> {
+        if let Err(e) = payload.validate() {
+            for (field, messages) in FieldValidationError::from(e).fields {
+                self.fields.entry(field).or_insert(messages);
+            }
+        }
+        if self.fields.is_empty() {
+            Ok(payload)
+        } else {
+            Err(PayloadError::Invalid(Box::new(FieldValidationError {
+                fields: self.fields,
+            })))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::scan_payload::*;
+
+    /// The messages of each invalid field of a payload.
+    fn invalid_fields(result: Result<NewScan, PayloadError>) -> BTreeMap<String, Vec<Message>> {
+        match result {
+            Err(PayloadError::Invalid(e)) => e
+                .downcast::<FieldValidationError>()
+                .map(|e| e.fields)
+                .expect("a field validation error"),
+            other => panic!("expected invalid fields, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_json() {
+        let payload = from_json(
+            br#"{"user_code": null, "location_barcode": "lw-freezer-1", "labware_barcodes": ["lw-1"]}"#,
+        )
+        .unwrap();
+        assert_eq!(payload.user_code, None);
+        assert_eq!(payload.location_barcode.as_deref(), Some("lw-freezer-1"));
+        assert_eq!(payload.labware_barcodes, vec!["lw-1"]);
+    }
+
+    #[test]
+    fn test_from_rails_json() {
+        let payload = from_json(
+            br#"{"scan": {"location_barcode": "lw-freezer-1", "labware_barcodes": "lw-1\nlw-2\n"}}"#,
+        )
+        .unwrap();
+        assert_eq!(payload.location_barcode.as_deref(), Some("lw-freezer-1"));
+        assert_eq!(payload.labware_barcodes, vec!["lw-1", "lw-2"]);
+
+        let fields = invalid_fields(from_json(br#"{"scan": {}, "user_code": "1234"}"#));
+        assert_eq!(fields["scan"], vec!["is not allowed"]);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Format::of(None), Some(Format::Json));
+        assert_eq!(
+            Format::of(Some("application/json; charset=utf-8")),
+            Some(Format::Json)
+        );
+        assert_eq!(
+            Format::of(Some("Application/X-WWW-Form-Urlencoded; charset=UTF-8")),
+            Some(Format::Form)
+        );
+        assert_eq!(Format::of(Some("text/xml")), None);
+    }
+
+    #[test]
+    fn test_from_json_with_invalid_fields() {
+        let fields = invalid_fields(from_json(
+            br#"{"location_barcode": 12, "labware": ["lw-1"]}"#,
+        ));
+        assert_eq!(fields["labware_barcodes"], vec!["is required"]);
+        assert_eq!(fields["location_barcode"], vec!["must be a string"]);
+        assert_eq!(fields["labware"], vec!["is not allowed"]);
+
+        let fields = invalid_fields(from_json(br#"{"labware_barcodes": ["lw-1", 2]}"#));
+        assert_eq!(
+            fields["labware_barcodes"],
+            vec!["must be a list of strings"]
+        );
+
+        let fields = invalid_fields(from_json(br#"{"labware_barcodes": []}"#));
+        assert_eq!(fields["labware_barcodes"], vec!["can't be empty"]);
+    }
+
+    #[test]
+    fn test_from_malformed_json() {
+        assert!(matches!(
+            from_json(b"{\"labware_barcodes\": ["),
+            Err(PayloadError::Malformed(_))
+        ));
+        let Err(PayloadError::Invalid(e)) = from_json(br#"["lw-1"]"#) else {
+            panic!("expected a payload which is not an object to be invalid");
+        };
+        assert_eq!(e.to_string(), "A scan must be a JSON object");
+    }
+
+    #[test]
+    fn test_from_form() {
+        let payload = from_form(
+            b"utf8=%E2%9C%93&scan%5Buser_code%5D=&scan%5Blocation_barcode%5D=lw-freezer-1\
+              &scan%5Blabware_barcodes%5D=lw-1%0D%0Alw-2,+lw-3&commit=Go",
+        )
+        .unwrap();
+        assert_eq!(payload.user_code, None);
+        assert_eq!(payload.location_barcode.as_deref(), Some("lw-freezer-1"));
+        assert_eq!(payload.labware_barcodes, vec!["lw-1", "lw-2", "lw-3"]);
+
+        let payload =
+            from_form(b"labware_barcodes[]=lw-1&labware_barcodes[]=lw-2&user_code=1234").unwrap();
+        assert_eq!(payload.user_code.as_deref(), Some("1234"));
+        assert_eq!(payload.labware_barcodes, vec!["lw-1", "lw-2"]);
+    }
+
+    #[test]
+    fn test_from_form_with_invalid_fields() {
+        let fields = invalid_fields(from_form(
+            b"location_barcode=lw-freezer-1&location_barcode=lw-freezer-2&rack=1",
+        ));
+        assert_eq!(fields["labware_barcodes"], vec!["is required"]);
+        assert_eq!(fields["location_barcode"], vec!["was given more than once"]);
+        assert_eq!(fields["rack"], vec!["is not allowed"]);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::services::{
+    current_locale, error_response, json, map_error, query_params, ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::config::CONFIG;
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::labware::LabwareStatus;
+use labwhere::search::{SearchSort, MAX_RESULTS};
+use log::info;
+use sqlx::SqlitePool;
+
+/// The number of locations, and of labwares, returned when no limit is asked for.
+pub(crate) const DEFAULT_LIMIT: u32 = 20;
+
+/// Searches (`GET`) the locations and labwares by name or barcode.
+///
+/// `GET /search?q=freezer&limit=20` responds with up to `limit` (at most `MAX_RESULTS`)
+/// `locations` whose name or barcode match the query and up to as many `labwares` whose barcode
+/// (or one of its aliases) match it. The search cluster is queried if one is configured, and the
+/// database otherwise. Only active labwares are returned unless another `status` is asked for,
+/// e.g. `?status=shipped-out`, or `?status=all` for labwares whatever their status. The results
+/// are the best matches first unless `?sort=name` (A to Z) or `?sort=name-desc` is asked for.
+pub async fn search(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /search endpoint");
+    let params = query_params(&req);
+    let Some(query) = params.get("q").filter(|query| !query.trim().is_empty()) else {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            Message::new("search-missing-query").localize(&current_locale()),
+        ));
+    };
+    let limit = match params.get("limit") {
+        Some(limit) => match limit.parse::<u32>() {
+            Ok(limit) if (1..=MAX_RESULTS).contains(&limit) => limit,
+            _ => {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    Message::new("search-invalid-limit")
+                        .arg("max", MAX_RESULTS)
+                        .localize(&current_locale()),
+                ))
+            }
+        },
+        None => DEFAULT_LIMIT,
+    };
+    let status = match status_filter(params.get("status").map(String::as_str)) {
+        Ok(status) => status,
+        Err(message) => return Ok(error_response(StatusCode::BAD_REQUEST, message)),
+    };
+    let sort = match params.get("sort") {
+        Some(name) => match SearchSort::from_name(name) {
+            Some(sort) => sort,
+            None => {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    Message::new("search-invalid-sort").localize(&current_locale()),
+                ))
+            }
+        },
+        None => SearchSort::Relevance,
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match labwhere::search::search(&CONFIG, query, limit, status, &mut connection).await {
+        Ok(results) => Ok(json(StatusCode::OK, &results.sorted(sort))),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Reads the status of the labwares a search is for, as in `GET /search?status=`: active labwares
+/// if none is given, and labwares whatever their status (`None`) for `all`.
+///
+/// Otherwise the error is the message to respond with 400 with.
+pub(crate) fn status_filter(name: Option<&str>) -> Result<Option<LabwareStatus>, String> {
+    match name {
+        None => Ok(Some(LabwareStatus::Active)),
+        Some("all") => Ok(None),
+        Some(name) => LabwareStatus::from_name(name)
+            .map(Some)
+            .ok_or_else(|| Message::new("search-invalid-status").localize(&current_locale())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::factories::{LabwareFactory, LocationFactory};
+
+    #[tokio::test]
+    async fn test_search() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let freezer = LocationFactory::new()
+            .with_name("freezer")
+            .create(&pool)
+            .await
+            .unwrap();
+        LabwareFactory::new()
+            .with_barcode("plate-1")
+            .with_location(&freezer)
+            .create(&pool)
+            .await
+            .unwrap();
+
+        let res = handle(request("GET", "/search?q=FREEZER", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["locations"][0]["name"], "freezer");
+        assert_eq!(body["labwares"], serde_json::json!([]));
+
+        let res = handle(request("GET", "/search?q=plate&limit=5", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            response_json(res).await["labwares"][0]["barcode"],
+            "plate-1"
+        );
+
+        // Labwares which are no longer active are only found when asked for
+        sqlx::query("UPDATE labwares SET status = 'destroyed'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let res = handle(request("GET", "/search?q=plate", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await["labwares"], serde_json::json!([]));
+        for status in ["all", "destroyed"] {
+            let path = format!("/search?q=plate&status={}", status);
+            let res = handle(request("GET", &path, b""), pool.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                response_json(res).await["labwares"][0]["status"],
+                "destroyed"
+            );
+        }
+        let res = handle(
+            request("GET", "/search?q=plate&status=all&sort=name-desc", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let res = handle(
+            request("GET", "/search?q=plate&sort=newest", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+        let res = handle(
+            request("GET", "/search?q=plate&status=lost", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+
+        let res = handle(request("GET", "/search?q=%20", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "Search for something with ?q=, e.g. ?q=freezer"
+        );
+
+        let res = handle(request("GET", "/search?q=plate&limit=101", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::services::scan::lock_token;
+use crate::services::{json, map_error, method_not_allowed, read_json, ServiceResponse};
+use chrono::{NaiveDate, Utc};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::shipment::Shipment;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for shipping labwares.
+#[derive(Debug, Deserialize, Validate)]
+struct NewShipment {
+    /// Where the labwares are sent
+    #[validate(length(min = 1, message = "validation-blank"))]
+    destination: String,
+    /// The reference of the courier taking the labwares, if there is one
+    courier_reference: Option<String>,
+    /// The day the labwares are sent e.g. `2026-03-03`, today if it is not given
+    shipped_on: Option<NaiveDate>,
+    /// Who sends the labwares
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+    /// The barcodes of the labwares sent
+    #[validate(length(min = 1, message = "validation-blank"))]
+    labware_barcodes: Vec<String>,
+}
+
+/// Lists (`GET`) shipments or ships (`POST`) labwares.
+///
+/// - `GET /shipments` responds with all shipments, the latest first.
+/// - `POST /shipments` with `{"destination": "Hinxton", "courier_reference": "DHL-123",
+///   "shipped_on": "2026-03-03", "user": "jane", "labware_barcodes": ["lw-1"]}` marks the labwares
+///   as shipped out and responds with 201 and the shipment, or 422 if one of the labwares is not in
+///   storage. If the location of a labware is locked, the lock's token has to be sent in the
+///   `X-Lock-Token` header.
+pub async fn shipments(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /shipments endpoint");
+    match *req.method() {
+        Method::GET => {
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match Shipment::all(&mut connection).await {
+                Ok(shipments) => Ok(json(StatusCode::OK, &shipments)),
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        Method::POST => {
+            let lock_token = lock_token(&req);
+            let payload = match read_json::<NewShipment>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match Shipment::create(
+                payload.destination,
+                payload.courier_reference,
+                payload
+                    .shipped_on
+                    .unwrap_or_else(|| Utc::now().date_naive()),
+                payload.user,
+                &payload.labware_barcodes,
+                lock_token.as_deref(),
+                &mut connection,
+            )
+            .await
+            {
+                Ok(shipment) => Ok(json(StatusCode::CREATED, &shipment)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Shows (`GET`) a shipment along with the labwares sent in it.
+///
+/// `GET /shipments/{uuid}` responds with the shipment and a `labwares` list of the barcodes of the
+/// labwares and the locations they were shipped from.
+pub async fn shipment(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /shipments/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let shipment = match Shipment::find_by_uuid(uuid, &mut connection).await {
+        Ok(shipment) => shipment,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match shipment.labwares(&mut connection).await {
+        Ok(labwares) => {
+            let mut body = serde_json::to_value(&shipment).unwrap_or_default();
+            body["labwares"] = serde_json::to_value(labwares).unwrap_or_default();
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::factories::{LabwareFactory, LocationFactory};
+
+    #[tokio::test]
+    async fn test_shipments() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let freezer = LocationFactory::new()
+            .with_name("freezer")
+            .create(&pool)
+            .await
+            .unwrap();
+        LabwareFactory::new()
+            .with_barcode("lw-1")
+            .with_location(&freezer)
+            .create(&pool)
+            .await
+            .unwrap();
+        let body: &[u8] = br#"{"destination": "Hinxton", "courier_reference": "DHL-123",
+            "shipped_on": "2026-03-03", "user": "jane", "labware_barcodes": ["lw-1"]}"#;
+
+        let res = handle(request("POST", "/shipments", body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 201);
+        let shipment = response_json(res).await;
+        assert_eq!(shipment["destination"], "Hinxton");
+        assert_eq!(shipment["shipped_on"], "2026-03-03");
+        let uuid = shipment["uuid"].as_str().unwrap();
+
+        let res = handle(
+            request("GET", &format!("/shipments/{}", uuid), b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let body_json = response_json(res).await;
+        assert_eq!(body_json["courier_reference"], "DHL-123");
+        assert_eq!(body_json["labwares"][0]["barcode"], "lw-1");
+        assert_eq!(body_json["labwares"][0]["location_id"], freezer.id);
+
+        let res = handle(request("GET", "/labwares/lw-1/status", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await["status"], "shipped-out");
+
+        // Labwares which were shipped out cannot be shipped again
+        let res = handle(request("POST", "/shipments", body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(request("GET", "/shipments", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await[0]["uuid"], uuid);
+
+        let res = handle(request("GET", "/shipments/unknown", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+}
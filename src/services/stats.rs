@@ -0,0 +1,160 @@
+use crate::services::{
+    current_locale, error_response, json, map_error, query_params, ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::occupancy::{LocationSnapshot, OccupancyStats, MAX_TREND_DAYS};
+use log::info;
+use sqlx::SqlitePool;
+
+/// The number of days of trend shown when none are asked for.
+const DEFAULT_TREND_DAYS: u32 = 30;
+
+/// Shows (`GET`) how full the storage is, to plan for more.
+///
+/// `GET /stats/occupancy?days=30` responds with the `capacity` (number of wells of coordinated
+/// locations), the `occupied` wells, the `percent` occupied and the number of `labwares`, per
+/// location type (`location_types`) and per top-level location such as a building or a freezer
+/// (`locations`). Each comes with its `trend`: a snapshot for each of the last `days` days, oldest
+/// first.
+pub async fn occupancy(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /stats/occupancy endpoint");
+    let days = match trend_days(&req) {
+        Ok(days) => days,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match OccupancyStats::build(days, &mut connection).await {
+        Ok(stats) => Ok(json(StatusCode::OK, &stats)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Shows (`GET`) the daily occupancy snapshots of every location, for long-term storage planning.
+///
+/// `GET /stats/occupancy/locations?days=30` responds with a snapshot per location for each of the
+/// last `days` days, oldest first: the `location_id`, the `day`, the number of `labwares` directly
+/// in the location and its `capacity` (number of wells, 0 if it is not coordinated).
+pub async fn location_occupancy(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /stats/occupancy/locations endpoint");
+    let days = match trend_days(&req) {
+        Ok(days) => days,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, e)),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match LocationSnapshot::trend(None, days, &mut connection).await {
+        Ok(snapshots) => Ok(json(StatusCode::OK, &snapshots)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Reads the number of days of trend asked for with `?days=`, up to `MAX_TREND_DAYS`.
+///
+/// Defaults to `DEFAULT_TREND_DAYS`. Otherwise the error is the message to respond with 400 with.
+pub(crate) fn trend_days<B>(req: &Request<B>) -> Result<u32, String> {
+    match query_params(req).get("days") {
+        Some(days) => match days.parse::<u32>() {
+            Ok(days) if (1..=MAX_TREND_DAYS).contains(&days) => Ok(days),
+            _ => Err(Message::new("stats-invalid-days")
+                .arg("max", MAX_TREND_DAYS)
+                .localize(&current_locale())),
+        },
+        None => Ok(DEFAULT_TREND_DAYS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::layout::Layout;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use labwhere::models::occupancy::{LocationSnapshot, Occupancy};
+
+    #[tokio::test]
+    async fn test_occupancy() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Rack".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let rack = Location::create("rack1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Layout::resize(&rack, 2, 5, &mut conn).await.unwrap();
+        let rack = Location::find(rack.id, &mut conn).await.unwrap();
+        Layout::place(&rack, "A1", "lw-1".to_string(), None, &mut conn)
+            .await
+            .unwrap();
+        Occupancy::snapshot(&mut conn).await.unwrap();
+        drop(conn);
+
+        let res = handle(request("GET", "/stats/occupancy", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["location_types"][0]["name"], "Rack");
+        assert_eq!(body["location_types"][0]["percent"], 10);
+        assert_eq!(body["locations"][0]["capacity"], 10);
+        assert_eq!(body["locations"][0]["trend"][0]["occupied"], 1);
+
+        let res = handle(request("GET", "/stats/occupancy?days=0", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_location_occupancy() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Rack".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let rack = Location::create("rack1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), rack.id, &mut conn)
+            .await
+            .unwrap();
+        LocationSnapshot::record(&mut conn).await.unwrap();
+        drop(conn);
+
+        let res = handle(
+            request("GET", "/stats/occupancy/locations?days=7", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body[0]["location_id"], rack.id);
+        assert_eq!(body[0]["labwares"], 1);
+        assert_eq!(body[0]["capacity"], 0);
+
+        let res = handle(
+            request("GET", "/stats/occupancy/locations?days=366", b""),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 400);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::services::scan::lock_token;
+use crate::services::{csv, json, map_error, read_json, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::stocktake::Stocktake;
+use labwhere::models::stocktake_report::StocktakeReport;
+use log::info;
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{SqliteConnection, SqlitePool};
+use std::error::Error;
+use validator::Validate;
+
+/// The payload for starting a stocktake.
+#[derive(Debug, Deserialize, Validate)]
+struct NewStocktake {
+    /// The barcode of the location at the top of the audited subtree
+    #[validate(length(min = 1, message = "validation-blank"))]
+    location_barcode: String,
+    /// Who is doing the stocktake
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+}
+
+/// The payload for scanning labwares during a stocktake.
+#[derive(Debug, Deserialize, Validate)]
+struct NewStocktakeScan {
+    /// The barcode of the location the labwares are in
+    #[validate(length(min = 1, message = "validation-blank"))]
+    location_barcode: String,
+    /// The barcodes of the labwares found in the location
+    #[validate(length(min = 1, message = "validation-blank"))]
+    labware_barcodes: Vec<String>,
+}
+
+/// The payload for completing a stocktake.
+#[derive(Debug, Deserialize, Validate)]
+struct CompleteStocktake {
+    /// Whether to move the labwares which were found to where they were found
+    #[serde(default)]
+    apply_corrections: bool,
+}
+
+/// Starts (`POST`) a stocktake.
+///
+/// `POST /stocktakes` with `{"location_barcode": "lw-freezer-1", "user": "jane"}` responds with 201
+/// and the stocktake of the location and every location beneath it.
+pub async fn stocktakes(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /stocktakes endpoint");
+    let payload = match read_json::<NewStocktake>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let stocktake =
+        match Stocktake::open(payload.location_barcode, payload.user, &mut connection).await {
+            Ok(stocktake) => stocktake,
+            Err(e) => return Ok(map_error(&*e)),
+        };
+    match stocktake_json(&stocktake, &mut connection).await {
+        Ok(body) => Ok(json(StatusCode::CREATED, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Shows (`GET`) a stocktake along with its discrepancies.
+///
+/// `GET /stocktakes/{uuid}` responds with the stocktake and a `discrepancies` list of the
+/// `missing`, `unexpected` and `misplaced` labwares found so far.
+pub async fn stocktake(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!("Processing request for /stocktakes/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let stocktake = match Stocktake::find_by_uuid(uuid, &mut connection).await {
+        Ok(stocktake) => stocktake,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match stocktake_json(&stocktake, &mut connection).await {
+        Ok(body) => Ok(json(StatusCode::OK, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Records (`POST`) the labwares physically present in a location during a stocktake.
+///
+/// `POST /stocktakes/{uuid}/scans` with
+/// `{"location_barcode": "lw-shelf-2", "labware_barcodes": ["lw-1"]}` responds with the stocktake
+/// and its updated discrepancies. Nothing is moved until the stocktake is completed.
+pub async fn scans(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /stocktakes/{}/scans endpoint", uuid);
+    let payload = match read_json::<NewStocktakeScan>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let stocktake = match Stocktake::find_by_uuid(uuid, &mut connection).await {
+        Ok(stocktake) => stocktake,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    if let Err(e) = stocktake
+        .scan(
+            payload.location_barcode,
+            payload.labware_barcodes,
+            &mut connection,
+        )
+        .await
+    {
+        return Ok(map_error(&*e));
+    }
+    match stocktake_json(&stocktake, &mut connection).await {
+        Ok(body) => Ok(json(StatusCode::OK, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Completes (`POST`) a stocktake.
+///
+/// `POST /stocktakes/{uuid}/complete` with `{"apply_corrections": true}` records the
+/// discrepancies and moves the unexpected and misplaced labwares to where they were found; without
+/// `apply_corrections` nothing is moved. If a location involved is locked, the lock's token has to
+/// be sent in the `X-Lock-Token` header.
+pub async fn complete(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /stocktakes/{}/complete endpoint",
+        uuid
+    );
+    let lock_token = lock_token(&req);
+    let payload = match read_json::<CompleteStocktake>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let stocktake = match Stocktake::find_by_uuid(uuid, &mut connection).await {
+        Ok(stocktake) => stocktake,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let stocktake = match stocktake
+        .complete(
+            payload.apply_corrections,
+            lock_token.as_deref(),
+            &mut connection,
+        )
+        .await
+    {
+        Ok(stocktake) => stocktake,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+    match stocktake_json(&stocktake, &mut connection).await {
+        Ok(body) => Ok(json(StatusCode::OK, &body)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Exports (`GET`) the discrepancy report of a completed stocktake.
+///
+/// `GET /stocktakes/{uuid}/report` responds with the discrepancies grouped per location, with
+/// counts of each kind and whether each was corrected. `GET /stocktakes/{uuid}/report.csv`
+/// responds with the same discrepancies as a CSV attachment, one row each. Both respond with 422
+/// while the stocktake is open.
+pub async fn report(pool: SqlitePool, uuid: &str, as_csv: bool) -> ServiceResponse {
+    info!(
+        "Processing request for /stocktakes/{}/report{} endpoint",
+        uuid,
+        if as_csv { ".csv" } else { "" }
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let stocktake = match Stocktake::find_by_uuid(uuid, &mut connection).await {
+        Ok(stocktake) => stocktake,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match StocktakeReport::build(&stocktake, &mut connection).await {
+        Ok(report) if as_csv => Ok(csv(
+            report.to_csv(),
+            &format!("stocktake-{}.csv", stocktake.uuid),
+        )),
+        Ok(report) => Ok(json(StatusCode::OK, &report)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Serializes a stocktake along with its discrepancies.
+async fn stocktake_json(
+    stocktake: &Stocktake,
+    connection: &mut SqliteConnection,
+) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    let mut body = serde_json::to_value(stocktake)?;
+    body["discrepancies"] = serde_json::to_value(stocktake.discrepancies(connection).await?)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use http_body_util::BodyExt;
+    use labwhere::db::init_pool;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+    use sqlx::SqlitePool;
+
+    /// A freezer with a shelf holding two labwares.
+    async fn setup() -> SqlitePool {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::create_with_parent(
+            "shelf".to_string(),
+            location_type.id,
+            Some(freezer.id),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        for barcode in ["lw-stock-1", "lw-stock-2"] {
+            Labware::create(barcode.to_string(), shelf.id, &mut conn)
+                .await
+                .unwrap();
+        }
+        pool
+    }
+
+    fn path(uuid: &str, action: &str) -> &'static str {
+        Box::leak(format!("/stocktakes/{}/{}", uuid, action).into_boxed_str())
+    }
+
+    #[tokio::test]
+    async fn test_stocktake() {
+        let pool = setup().await;
+
+        let res = handle(
+            request(
+                "POST",
+                "/stocktakes",
+                br#"{"location_barcode": "lw-freezer-1", "user": "jane"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        let body = response_json(res).await;
+        assert_eq!(body["state"], "open");
+        assert_eq!(body["discrepancies"].as_array().unwrap().len(), 2);
+        let uuid = body["uuid"].as_str().unwrap().to_string();
+
+        let res = handle(
+            request(
+                "POST",
+                path(&uuid, "scans"),
+                br#"{"location_barcode": "lw-shelf-2", "labware_barcodes": ["lw-stock-1", "lw-stock-3"]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["discrepancies"][0]["kind"], "missing");
+        assert_eq!(body["discrepancies"][0]["labware_barcode"], "lw-stock-2");
+        assert_eq!(body["discrepancies"][1]["kind"], "unexpected");
+        assert_eq!(body["discrepancies"][1]["labware_barcode"], "lw-stock-3");
+
+        let res = handle(
+            request(
+                "POST",
+                path(&uuid, "complete"),
+                br#"{"apply_corrections": true}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["state"], "completed");
+        assert_eq!(body["discrepancies"][0]["corrected"], false);
+        assert_eq!(body["discrepancies"][1]["corrected"], true);
+
+        let stocktake = Box::leak(format!("/stocktakes/{}", uuid).into_boxed_str());
+        let res = handle(request("GET", stocktake, b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await["corrected"], true);
+
+        let res = handle(
+            request("POST", path(&uuid, "complete"), b"{}"),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let mut conn = pool.acquire().await.unwrap();
+        let labware = Labware::find_by_barcode("lw-stock-3".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let shelf = Location::find_by_barcode("lw-shelf-2".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.location_id, shelf.id);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_stocktake() {
+        let pool = setup().await;
+
+        let res = handle(request("GET", "/stocktakes/not-a-uuid", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_report() {
+        let pool = setup().await;
+        let res = handle(
+            request(
+                "POST",
+                "/stocktakes",
+                br#"{"location_barcode": "lw-freezer-1", "user": "jane"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let uuid = response_json(res).await["uuid"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let res = handle(request("GET", path(&uuid, "report.csv"), b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 422);
+
+        handle(
+            request("POST", path(&uuid, "complete"), b"{}"),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+
+        let res = handle(request("GET", path(&uuid, "report"), b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = response_json(res).await;
+        assert_eq!(body["locations"][0]["location"], "shelf");
+        assert_eq!(body["locations"][0]["missing"], 2);
+        assert_eq!(
+            body["locations"][0]["discrepancies"][0]["resolution"],
+            "unresolved"
+        );
+
+        let res = handle(request("GET", path(&uuid, "report.csv"), b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-type"], "text/csv; charset=utf-8");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(body.lines().count(), 3);
+        assert!(body.contains(",missing,lw-stock-2,shelf,,unresolved"));
+    }
+}
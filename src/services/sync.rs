@@ -0,0 +1,357 @@
+use crate::services::{
+    current_locale, error_response, json, map_error, method_not_allowed, query_params, read_json,
+    ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::config::CONFIG;
+use labwhere::errors::{FieldValidationError, ValidationError};
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::change::{Change, Origins};
+use labwhere::models::sync_conflict::{ConflictStatus, SyncConflict};
+use labwhere::sync::BATCH_SIZE;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The most changes which can be read in one request.
+const MAX_LIMIT: u32 = 1000;
+
+/// The payload for applying the changes made on another instance.
+#[derive(Debug, Deserialize, Validate)]
+struct PushedChanges {
+    /// The changes, oldest first
+    changes: Vec<Change>,
+}
+
+/// The payload for resolving a sync conflict.
+#[derive(Debug, Deserialize, Validate)]
+struct ResolveSyncConflict {
+    /// `apply` to apply the change from the other instance, `discard` to keep the local change
+    resolution: String,
+    /// Who resolves the conflict
+    #[validate(length(min = 1, message = "validation-blank"))]
+    user: String,
+}
+
+/// Reads (`GET`) the changes made on this instance and the instances it syncs with, or applies
+/// (`POST`) the changes made on another instance.
+///
+/// `GET /sync/changes?since=0&peer=satellite-1&limit=500` responds with the `changes` recorded
+/// after the audit `since`, oldest first, and the `cursor` to send as `since` for the next page.
+/// Changes made on the `peer` itself are left out.
+///
+/// `POST /sync/changes` with `{"changes": [...]}` and an admin token in the `X-Admin-Token` header
+/// (see `auth::admin_only`) applies the changes and responds with how many were `applied`,
+/// `skipped` (because they were applied before or lost a conflict) and `queued` for review as
+/// conflicts, and the changes which were `rejected`, each with its `uuid` and `error`.
+pub async fn changes(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /sync/changes endpoint");
+    match *req.method() {
+        Method::GET => {
+            let params = query_params(&req);
+            let Ok(since) = params
+                .get("since")
+                .map_or(Ok(0), |since| since.parse::<u32>())
+            else {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    Message::new("sync-invalid-since").localize(&current_locale()),
+                ));
+            };
+            let limit = match params.get("limit").map(|limit| limit.parse::<u32>()) {
+                None => BATCH_SIZE,
+                Some(Ok(limit)) if (1..=MAX_LIMIT).contains(&limit) => limit,
+                Some(_) => {
+                    return Ok(error_response(
+                        StatusCode::BAD_REQUEST,
+                        Message::new("sync-invalid-limit")
+                            .arg("max", MAX_LIMIT)
+                            .localize(&current_locale()),
+                    ))
+                }
+            };
+            let peer = params.get("peer").map_or("", String::as_str);
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match Change::feed(
+                since,
+                limit,
+                &CONFIG.instance_name,
+                Origins::Except(peer),
+                &mut connection,
+            )
+            .await
+            {
+                Ok(feed) => Ok(json(StatusCode::OK, &feed)),
+                Err(e) => Ok(map_error(&e)),
+            }
+        }
+        Method::POST => {
+            let payload = match read_json::<PushedChanges>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let mut connection = match acquire(&pool).await {
+                Ok(connection) => connection,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match Change::apply(
+                &payload.changes,
+                CONFIG.sync_conflict_policy,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(report) => {
+                    let locale = current_locale();
+                    Ok(json(
+                        StatusCode::OK,
+                        &serde_json::json!({
+                            "applied": report.applied,
+                            "skipped": report.skipped,
+                            "queued": report.queued,
+                            "rejected": report.rejected.iter().map(|rejection| serde_json::json!({
+                                "uuid": rejection.uuid,
+                                "error": rejection.message.localize(&locale),
+                            })).collect::<Vec<_>>(),
+                        }),
+                    ))
+                }
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Lists (`GET`) the conflicts between synced changes and local changes.
+///
+/// `GET /sync/conflicts?status=pending` responds with the conflicts, newest first, each with the
+/// conflicting `change`, the `location_uuid` the labware was in here and its `status` (`pending`,
+/// `applied` or `discarded`). Leaving out `status` lists every conflict.
+pub async fn conflicts(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /sync/conflicts endpoint");
+    let status = match query_params(&req).get("status") {
+        Some(status) => match ConflictStatus::from_name(status) {
+            Some(status) => Some(status),
+            None => {
+                return Ok(map_error(&ValidationError {
+                    message: Message::new("sync-conflict-unknown-status").arg("status", status),
+                }))
+            }
+        },
+        None => None,
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match SyncConflict::list(status, &mut connection).await {
+        Ok(conflicts) => Ok(json(StatusCode::OK, &conflicts)),
+        Err(e) => Ok(map_error(&e)),
+    }
+}
+
+/// Resolves (`POST`) a sync conflict queued for review.
+///
+/// `POST /sync/conflicts/{uuid}/resolve` with `{"resolution": "apply", "user": "jane"}` applies
+/// the change from the other instance, overriding the local change, and `"discard"` keeps the local
+/// change. Responds with the resolved conflict. Requires one of the configured admin tokens in the
+/// `X-Admin-Token` header, otherwise the response is 403.
+pub async fn resolve_conflict(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /sync/conflicts/{}/resolve endpoint",
+        uuid
+    );
+    let payload = match read_json::<ResolveSyncConflict>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let status = match payload.resolution.as_str() {
+        "apply" => ConflictStatus::Applied,
+        "discard" => ConflictStatus::Discarded,
+        _ => {
+            return Ok(map_error(&FieldValidationError::field(
+                "resolution",
+                Message::new("sync-conflict-invalid-resolution"),
+            )))
+        }
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match SyncConflict::resolve(uuid, status, payload.user, &mut connection).await {
+        Ok(conflict) => Ok(json(StatusCode::OK, &conflict)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::sync;
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::models::labware::Labware;
+    use labwhere::models::location::Location;
+    use labwhere::models::location_type::LocationType;
+
+    #[tokio::test]
+    async fn test_changes_round_trip() {
+        let hub = init_pool("sqlite::memory:").await.unwrap();
+        let mut conn = hub.acquire().await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        let freezer = Location::create("freezer1".to_string(), location_type.id, &mut conn)
+            .await
+            .unwrap();
+        Labware::create("lw-1".to_string(), freezer.id, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let res = handle(request("GET", "/sync/changes?since=0", b""), hub.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let feed = response_json(res).await;
+        let changes = feed["changes"].as_array().unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0]["record_type"], "Location");
+        assert_eq!(changes[0]["location_type"], "Freezer");
+        assert_eq!(changes[1]["record_type"], "Labware");
+        assert_eq!(changes[1]["barcode"], "lw-1");
+        assert_eq!(changes[1]["location_uuid"], freezer.uuid.as_str());
+        assert!(feed["cursor"].as_u64().unwrap() > 0);
+
+        // A satellite applies the changes of the hub, once
+        let satellite = init_pool("sqlite::memory:").await.unwrap();
+        let body: &'static [u8] = Box::leak(
+            serde_json::json!({ "changes": changes })
+                .to_string()
+                .into_bytes()
+                .into_boxed_slice(),
+        );
+        // Only admins may push changes (see `auth::admin_only`), which the AdminLayer checks
+        let res = handle(request("POST", "/sync/changes", body), satellite.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+        for (applied, skipped) in [(2, 0), (0, 2)] {
+            let res = sync::changes(request("POST", "/sync/changes", body), satellite.clone())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 200);
+            let report = response_json(res).await;
+            assert_eq!(report["applied"], applied);
+            assert_eq!(report["skipped"], skipped);
+            assert_eq!(report["rejected"].as_array().unwrap().len(), 0);
+        }
+        let mut conn = satellite.acquire().await.unwrap();
+        let labware = Labware::find_by_barcode("lw-1".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(labware.uuid, changes[1]["record_uuid"]);
+        let location = Location::find(labware.location_id, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(location.uuid, freezer.uuid);
+        assert_eq!(location.barcode, freezer.barcode);
+        drop(conn);
+
+        // Changes are not sent back to where they were made
+        let res = handle(
+            request("GET", "/sync/changes?since=0&peer=labwhere", b""),
+            satellite.clone(),
+        )
+        .await
+        .unwrap();
+        let feed = response_json(res).await;
+        assert_eq!(feed["changes"].as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_changes_rejected() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let res = sync::changes(
+            request(
+                "POST",
+                "/sync/changes",
+                br#"{"changes": [{"uuid": "a", "origin": "hub", "record_type": "Labware",
+                    "action": "update", "record_uuid": "b", "barcode": "lw-1",
+                    "location_uuid": "c", "name": null, "location_type": null,
+                    "created_at": "2026-10-01T09:00:00Z"}]}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        let report = response_json(res).await;
+        assert_eq!(report["applied"], 0);
+        assert_eq!(report["rejected"][0]["uuid"], "a");
+        assert_eq!(
+            report["rejected"][0]["error"],
+            "Location c is not known on this instance"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changes_invalid_params() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        for uri in ["/sync/changes?since=x", "/sync/changes?limit=0"] {
+            let res = handle(request("GET", uri, b""), pool.clone())
+                .await
+                .unwrap();
+            assert_eq!(res.status(), 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conflicts() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let res = handle(
+            request("GET", "/sync/conflicts?status=pending", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(response_json(res).await, serde_json::json!([]));
+
+        let res = handle(
+            request("GET", "/sync/conflicts?status=lost", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+
+        let res = handle(
+            request(
+                "POST",
+                "/sync/conflicts/0d6f/resolve",
+                br#"{"resolution": "apply", "user": "jane"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 403);
+    }
+}
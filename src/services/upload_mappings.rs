@@ -0,0 +1,158 @@
+use crate::services::{
+    json, map_error, method_not_allowed, read_json, status_only, ServiceResponse,
+};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::upload_mapping::UploadMapping;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for saving an upload mapping.
+#[derive(Debug, Deserialize, Validate)]
+struct NewUploadMapping {
+    /// The unique name of the mapping
+    #[validate(length(min = 1, message = "validation-blank"))]
+    name: String,
+    /// The header of the column holding the labware barcodes
+    #[validate(length(min = 1, message = "validation-blank"))]
+    labware_column: String,
+    /// The header of the column holding the location barcodes
+    #[validate(length(min = 1, message = "validation-blank"))]
+    location_column: String,
+}
+
+/// Lists (`GET`) or saves (`POST`) the column mappings manifests can be uploaded with.
+///
+/// - `GET /upload_mappings` responds with every mapping, ordered by name.
+/// - `POST /upload_mappings` with `{"name": "sanger-freezers", "labware_column": "Tube Barcode",
+///   "location_column": "Rack"}` responds with 201 and the mapping, which manifests can then be
+///   uploaded with as `POST /manifests?mapping=sanger-freezers`.
+pub async fn upload_mappings(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /upload_mappings endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => match UploadMapping::all(&mut connection).await {
+            Ok(mappings) => Ok(json(StatusCode::OK, &mappings)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::POST => {
+            let payload = match read_json::<NewUploadMapping>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            match UploadMapping::create(
+                payload.name,
+                payload.labware_column,
+                payload.location_column,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(mapping) => Ok(json(StatusCode::CREATED, &mapping)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Shows (`GET`) or deletes (`DELETE`) an upload mapping.
+///
+/// - `GET /upload_mappings/{name}` responds with the mapping.
+/// - `DELETE /upload_mappings/{name}` responds with 204. Manifests already uploaded with it are
+///   not affected.
+pub async fn upload_mapping(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    name: &str,
+) -> ServiceResponse {
+    info!("Processing request for /upload_mappings/{} endpoint", name);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let mapping = match UploadMapping::find_by_name(name, &mut connection).await {
+        Ok(mapping) => mapping,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => Ok(json(StatusCode::OK, &mapping)),
+        Method::DELETE => match mapping.delete(&mut connection).await {
+            Ok(()) => Ok(status_only(StatusCode::NO_CONTENT)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        _ => Ok(method_not_allowed(&[Method::GET, Method::DELETE])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::factories::LocationFactory;
+
+    #[tokio::test]
+    async fn test_upload_with_mapping() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        LocationFactory::new()
+            .with_name("location1")
+            .create(&pool)
+            .await
+            .unwrap();
+
+        let res = handle(
+            request(
+                "POST",
+                "/upload_mappings",
+                br#"{"name": "sanger", "labware_column": "Tube Barcode", "location_column": "Rack"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(response_json(res).await["labware_column"], "Tube Barcode");
+
+        let res = handle(
+            request(
+                "POST",
+                "/manifests?mapping=sanger",
+                b"Rack,Tube Barcode\nlw-location1-1,lw-1\n",
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 201);
+        assert_eq!(response_json(res).await["rows_imported"], 1);
+
+        let res = handle(
+            request("POST", "/manifests?mapping=unknown", b"labware,location\n"),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 404);
+
+        let res = handle(
+            request("DELETE", "/upload_mappings/sanger", b""),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 204);
+        let res = handle(request("GET", "/upload_mappings", b""), pool)
+            .await
+            .unwrap();
+        assert_eq!(response_json(res).await, serde_json::json!([]));
+    }
+}
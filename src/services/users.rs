@@ -0,0 +1,246 @@
+use crate::services::{json, map_error, method_not_allowed, read_json, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Method, Request, StatusCode};
+use labwhere::errors::FieldValidationError;
+use labwhere::i18n::Message;
+use labwhere::metrics::acquire;
+use labwhere::models::user::{Credential, Role, User};
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for creating a user.
+#[derive(Debug, Deserialize, Validate)]
+struct NewUser {
+    /// The unique login of the user
+    #[validate(length(min = 1, message = "validation-blank"))]
+    login: String,
+    /// `admin`, `technician` or `viewer`
+    role: String,
+    /// The team the user belongs to
+    team: Option<String>,
+    /// The code of the user's swipe card, generated if left out
+    #[validate(length(min = 1, message = "validation-blank"))]
+    swipe_code: Option<String>,
+}
+
+/// The payload for changing a user.
+#[derive(Debug, Deserialize, Validate)]
+struct UserChange {
+    /// `admin`, `technician` or `viewer`
+    role: String,
+    /// The team the user belongs to, or `null` for none
+    team: Option<String>,
+}
+
+/// The payload for resetting a credential of a user.
+#[derive(Debug, Deserialize, Validate)]
+struct CredentialReset {
+    /// `swipe_code` or `api_key`
+    credential: String,
+    /// The code of the user's new swipe card, generated if left out
+    #[validate(length(min = 1, message = "validation-blank"))]
+    swipe_code: Option<String>,
+}
+
+/// Lists (`GET`) or creates (`POST`) the users.
+///
+/// - `GET /admin/users` responds with every user, ordered by login, including when they were
+///   `last_active_at`.
+/// - `POST /admin/users` with `{"login": "jd12", "role": "technician", "team": "Cell lines"}`
+///   creates a user and responds with 201 and the user, including their `swipe_code` (generated
+///   unless given) and the `api_key` their scripts send in the `X-Api-Key` header. Neither is
+///   stored, so neither is shown again.
+///
+/// Requires one of the configured admin tokens in the `X-Admin-Token` header, otherwise the
+/// response is 403.
+pub async fn users(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /admin/users endpoint");
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => match User::all(&mut connection).await {
+            Ok(users) => Ok(json(StatusCode::OK, &users)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::POST => {
+            let payload = match read_json::<NewUser>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let role = match role(&payload.role) {
+                Ok(role) => role,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match User::create(
+                payload.login,
+                role,
+                payload.team,
+                payload.swipe_code,
+                &mut connection,
+            )
+            .await
+            {
+                Ok(user) => {
+                    let mut body = serde_json::to_value(&user).unwrap_or_default();
+                    body["swipe_code"] = user.swipe_code.into();
+                    body["api_key"] = user.api_key.into();
+                    Ok(json(StatusCode::CREATED, &body))
+                }
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::POST])),
+    }
+}
+
+/// Shows (`GET`) or changes (`PUT`) a user.
+///
+/// - `GET /admin/users/{uuid}` responds with the user.
+/// - `PUT /admin/users/{uuid}` with `{"role": "admin", "team": null}` changes the role and team of
+///   the user and responds with the user.
+///
+/// Requires one of the configured admin tokens in the `X-Admin-Token` header, otherwise the
+/// response is 403.
+pub async fn user(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!("Processing request for /admin/users/{} endpoint", uuid);
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match *req.method() {
+        Method::GET => match User::find_by_uuid(uuid, &mut connection).await {
+            Ok(user) => Ok(json(StatusCode::OK, &user)),
+            Err(e) => Ok(map_error(&e)),
+        },
+        Method::PUT => {
+            let payload = match read_json::<UserChange>(req).await {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            let role = match role(&payload.role) {
+                Ok(role) => role,
+                Err(e) => return Ok(map_error(&e)),
+            };
+            match User::update(uuid, role, payload.team, &mut connection).await {
+                Ok(user) => Ok(json(StatusCode::OK, &user)),
+                Err(e) => Ok(map_error(&*e)),
+            }
+        }
+        _ => Ok(method_not_allowed(&[Method::GET, Method::PUT])),
+    }
+}
+
+/// Deactivates (`POST`) a user who should no longer use the system.
+///
+/// `POST /admin/users/{uuid}/deactivate` responds with the user. Scans with their swipe card are
+/// refused with 403 from then on. Requires one of the configured admin tokens in the
+/// `X-Admin-Token` header, otherwise the response is 403.
+pub async fn deactivate(pool: SqlitePool, uuid: &str) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/users/{}/deactivate endpoint",
+        uuid
+    );
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match User::deactivate(uuid, &mut connection).await {
+        Ok(user) => Ok(json(StatusCode::OK, &user)),
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Resets (`POST`) the swipe code or API key of a user, e.g. when a card is lost.
+///
+/// `POST /admin/users/{uuid}/reset` with `{"credential": "swipe_code"}` (optionally with the
+/// `swipe_code` of the new card) or `{"credential": "api_key"}` responds with the user and the new
+/// credential, which is not shown again. Requires one of the configured admin tokens in the
+/// `X-Admin-Token` header, otherwise the response is 403.
+pub async fn reset(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+    uuid: &str,
+) -> ServiceResponse {
+    info!(
+        "Processing request for /admin/users/{}/reset endpoint",
+        uuid
+    );
+    let payload = match read_json::<CredentialReset>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let credential = match payload.credential.as_str() {
+        "swipe_code" => Credential::SwipeCode,
+        "api_key" => Credential::ApiKey,
+        _ => {
+            return Ok(map_error(&FieldValidationError::field(
+                "credential",
+                Message::new("user-unknown-credential"),
+            )))
+        }
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    match User::reset(uuid, credential, payload.swipe_code, &mut connection).await {
+        Ok(user) => {
+            let mut body = serde_json::to_value(&user).unwrap_or_default();
+            match credential {
+                Credential::SwipeCode => body["swipe_code"] = user.swipe_code.into(),
+                Credential::ApiKey => body["api_key"] = user.api_key.into(),
+            }
+            Ok(json(StatusCode::OK, &body))
+        }
+        Err(e) => Ok(map_error(&*e)),
+    }
+}
+
+/// Parses the role of a payload.
+fn role(name: &str) -> Result<Role, FieldValidationError> {
+    Role::from_name(name)
+        .ok_or_else(|| FieldValidationError::field("role", Message::new("user-unknown-role")))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+
+    #[tokio::test]
+    async fn test_users_require_admin() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+
+        let res = handle(request("GET", "/admin/users", b""), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 403);
+
+        let res = handle(
+            request(
+                "POST",
+                "/admin/users",
+                br#"{"login": "jd12", "role": "technician"}"#,
+            ),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 403);
+        assert_eq!(
+            response_json(res).await["errors"][0],
+            "Only an admin can do this"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use crate::services::scan::lock_token;
+use crate::services::{current_locale, json, map_error, read_json, ServiceResponse};
+use hyper::body::{Body, Bytes};
+use hyper::{Request, StatusCode};
+use labwhere::metrics::acquire;
+use labwhere::models::barcode_validation::BarcodeValidation;
+use log::info;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use validator::Validate;
+
+/// The payload for checking barcodes.
+#[derive(Debug, Deserialize, Validate)]
+struct BarcodeList {
+    /// The barcodes to check
+    #[validate(length(min = 1, message = "validation-required"))]
+    barcodes: Vec<String>,
+    /// The barcode of the location the barcodes are about to be scanned into, if any
+    location_barcode: Option<String>,
+}
+
+/// Checks (`POST`) barcodes before they are scanned, without changing anything.
+///
+/// `POST /validate/barcodes` with `{"barcodes": ["lw-1", "lw-2"], "location_barcode":
+/// "lw-freezer-1"}` responds with the `kind` of each barcode (`labware`, `location` or `unknown`),
+/// and the `status` and `location` of labwares. With a `location_barcode`, each barcode also has
+/// the `errors` a scan of it into the location would fail with, and `location_errors` lists those
+/// of the location itself, e.g. that it is locked (send the lock's token in the `X-Lock-Token`
+/// header, as for the scan). `valid` is true if the scan would succeed.
+///
+/// No API key is needed, as nothing is changed.
+pub async fn barcodes(
+    req: Request<impl Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static>,
+    pool: SqlitePool,
+) -> ServiceResponse {
+    info!("Processing request for /validate/barcodes endpoint");
+    let lock_token = lock_token(&req);
+    let payload = match read_json::<BarcodeList>(req).await {
+        Ok(payload) => payload,
+        Err(response) => return Ok(response),
+    };
+    let mut connection = match acquire(&pool).await {
+        Ok(connection) => connection,
+        Err(e) => return Ok(map_error(&e)),
+    };
+    let validation = match BarcodeValidation::check(
+        payload.barcodes,
+        payload.location_barcode,
+        lock_token.as_deref(),
+        &mut connection,
+    )
+    .await
+    {
+        Ok(validation) => validation,
+        Err(e) => return Ok(map_error(&*e)),
+    };
+
+    let locale = current_locale();
+    let mut body = serde_json::to_value(&validation).unwrap_or_default();
+    body["valid"] = validation.valid().into();
+    let location_errors: Vec<String> = validation
+        .location_problems
+        .iter()
+        .map(|problem| problem.localize(&locale))
+        .collect();
+    body["location_errors"] = location_errors.into();
+    for (check, json) in validation
+        .barcodes
+        .iter()
+        .zip(body["barcodes"].as_array_mut().into_iter().flatten())
+    {
+        let errors: Vec<String> = check
+            .problems
+            .iter()
+            .map(|problem| problem.localize(&locale))
+            .collect();
+        json["valid"] = errors.is_empty().into();
+        json["errors"] = errors.into();
+    }
+    Ok(json(StatusCode::OK, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::{handle, mock_request as request, response_json};
+    use labwhere::db::init_pool;
+    use labwhere::factories::{LabwareFactory, LocationFactory};
+
+    #[tokio::test]
+    async fn test_validate_barcodes() {
+        let pool = init_pool("sqlite::memory:").await.unwrap();
+        let freezer = LocationFactory::new()
+            .with_name("freezer")
+            .create(&pool)
+            .await
+            .unwrap();
+        LabwareFactory::new()
+            .with_barcode("lw-1")
+            .with_location(&freezer)
+            .create(&pool)
+            .await
+            .unwrap();
+        let body = format!(
+            r#"{{"barcodes": ["lw-1", "lw-2", "{0}"], "location_barcode": "{0}"}}"#,
+            freezer.barcode.unwrap()
+        );
+        let body: &'static [u8] = Box::leak(body.into_bytes().into_boxed_slice());
+
+        let res = handle(request("POST", "/validate/barcodes", body), pool.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let validation = response_json(res).await;
+        assert_eq!(validation["valid"], false);
+        assert_eq!(validation["location"], "freezer");
+        assert_eq!(validation["location_errors"], serde_json::json!([]));
+        let barcodes = &validation["barcodes"];
+        assert_eq!(barcodes[0]["kind"], "labware");
+        assert_eq!(barcodes[0]["status"], "active");
+        assert_eq!(barcodes[0]["location"], "freezer");
+        assert_eq!(barcodes[0]["valid"], true);
+        assert_eq!(barcodes[1]["kind"], "unknown");
+        assert_eq!(barcodes[1]["valid"], true);
+        assert_eq!(barcodes[2]["kind"], "location");
+        assert_eq!(barcodes[2]["valid"], false);
+
+        let res = handle(
+            request(
+                "POST",
+                "/validate/barcodes",
+                br#"{"barcodes": ["lw-1"], "location_barcode": "lw-nowhere-9"}"#,
+            ),
+            pool.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 404);
+
+        let res = handle(
+            request("POST", "/validate/barcodes", br#"{"barcodes": []}"#),
+            pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.status(), 422);
+    }
+}
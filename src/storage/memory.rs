@@ -0,0 +1,393 @@
+use crate::barcode;
+use crate::barcode::parser::BarcodeParser;
+use crate::config::CONFIG;
+use crate::errors::{NotFoundError, ValidationError};
+use crate::i18n::Message;
+use crate::models::labware::{Labware, LabwareStatus};
+use crate::models::location::{Location, UNKNOWN_LOCATION_BARCODE, UNKNOWN_LOCATION_NAME};
+use crate::models::new_uuid;
+use crate::models::scan::{Scan, ScannedLabware, ScannedLocation};
+use crate::storage::{LabwareStore, LocationStore, ScanStore};
+use crate::{LabwareBuilder, LocationBuilder};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+
+/// Rows kept by id, with the ids of those which have a barcode by their barcode.
+#[derive(Debug)]
+struct Table<T> {
+    rows: HashMap<u32, T>,
+    barcodes: HashMap<String, u32>,
+    /// The id given to the latest row, as ids are never reused
+    last_id: u32,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Table<T> {
+        Table {
+            rows: HashMap::new(),
+            barcodes: HashMap::new(),
+            last_id: 0,
+        }
+    }
+}
+
+impl<T> Table<T> {
+    fn next_id(&mut self) -> u32 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn find(&self, id: u32) -> Option<&T> {
+        self.rows.get(&id)
+    }
+
+    fn find_by_barcode(&self, barcode: &str) -> Option<&T> {
+        self.barcodes.get(barcode).and_then(|id| self.rows.get(id))
+    }
+
+    fn insert(&mut self, id: u32, barcode: Option<&str>, row: T) {
+        if let Some(barcode) = barcode {
+            self.barcodes.insert(barcode.to_string(), id);
+        }
+        self.rows.insert(id, row);
+    }
+}
+
+impl Table<Location> {
+    fn find_by_scanned_barcode(
+        &self,
+        barcode: &str,
+    ) -> Result<&Location, Box<dyn Error + Send + Sync>> {
+        let parsed = BarcodeParser::new(&CONFIG).parse(barcode)?;
+        Ok(self.find_by_barcode(&parsed.barcode).ok_or(NotFoundError {
+            message: Message::new("location-not-found"),
+        })?)
+    }
+
+    /// The names of the locations a location is inside of, outermost first, ending with its own
+    fn breadcrumb(&self, location: &Location) -> Vec<String> {
+        let mut names = vec![location.name.clone()];
+        let mut parent_id = location.parent_id;
+        while let Some(parent) = parent_id.and_then(|id| self.find(id)) {
+            names.insert(0, parent.name.clone());
+            parent_id = parent.parent_id;
+        }
+        names
+    }
+}
+
+/// Storage kept in memory, for unit tests and tools embedding the library which have no database.
+///
+/// Locations, labwares and scans are kept in `HashMap`s behind `RwLock`s, so a storage can be
+/// cloned cheaply and its clones shared between tasks and threads: they all see the same data.
+/// Nothing is kept once the last clone is dropped.
+///
+/// Only what the traits promise is implemented: scans move known labwares and register unknown
+/// ones, all of them or none, but nothing else is checked or recorded, i.e. there are no locks,
+/// quarantines, reservations, registration policies, misplacements, audits or events. Tests of
+/// those need `SqliteStorage`.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::storage::{InMemoryStorage, LocationStore, ScanStore};
+/// let mut storage = InMemoryStorage::new();
+/// let freezer = storage.create_location("freezer1", 1, None).await.unwrap();
+/// let scan = storage.scan(&freezer.barcode.unwrap(), vec!["lw-1".to_string()], None, None).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStorage {
+    // Locks are always taken in this order, so that scans cannot deadlock with each other
+    locations: Arc<RwLock<Table<Location>>>,
+    labwares: Arc<RwLock<Table<Labware>>>,
+    scans: Arc<RwLock<Table<Scan>>>,
+}
+
+impl InMemoryStorage {
+    /// Create a new InMemoryStorage, which is empty
+    pub fn new() -> InMemoryStorage {
+        Default::default()
+    }
+
+    /// The scans which were made, oldest first
+    pub fn scans(&self) -> Vec<Scan> {
+        let mut scans: Vec<Scan> = self.scans.read().unwrap().rows.values().cloned().collect();
+        scans.sort_by_key(|scan| scan.id);
+        scans
+    }
+}
+
+impl LocationStore for InMemoryStorage {
+    async fn find_location(&mut self, id: u32) -> Result<Location, NotFoundError> {
+        self.locations
+            .read()
+            .unwrap()
+            .find(id)
+            .cloned()
+            .ok_or(NotFoundError {
+                message: Message::new("location-not-found"),
+            })
+    }
+
+    async fn find_location_by_barcode(
+        &mut self,
+        barcode: &str,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        self.locations
+            .read()
+            .unwrap()
+            .find_by_scanned_barcode(barcode)
+            .cloned()
+    }
+
+    async fn unknown_location(&mut self) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let mut locations = self.locations.write().unwrap();
+        if let Some(location) = locations.find_by_barcode(UNKNOWN_LOCATION_BARCODE) {
+            return Ok(location.clone());
+        }
+        // Not validated, like the unknown location of the database
+        let mut location = Location::default();
+        location.id = locations.next_id();
+        location.uuid = new_uuid();
+        location.name = UNKNOWN_LOCATION_NAME.to_string();
+        location.barcode = Some(UNKNOWN_LOCATION_BARCODE.to_string());
+        locations.insert(
+            location.id,
+            Some(UNKNOWN_LOCATION_BARCODE),
+            location.clone(),
+        );
+        Ok(location)
+    }
+
+    async fn create_location(
+        &mut self,
+        name: &str,
+        location_type_id: u32,
+        parent_id: Option<u32>,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        let mut locations = self.locations.write().unwrap();
+        let mut builder = LocationBuilder::new()
+            .name(name)
+            .location_type_id(location_type_id);
+        if let Some(parent_id) = parent_id {
+            locations.find(parent_id).ok_or(NotFoundError {
+                message: Message::new("location-not-found"),
+            })?;
+            builder = builder.parent_id(parent_id);
+        }
+        let mut location = builder.build()?;
+        location.id = locations.next_id();
+        location.uuid = new_uuid();
+        let barcode = barcode::generate(
+            name,
+            location.id,
+            CONFIG.barcode_check_digit,
+            CONFIG.barcode_signing_keys.first(),
+        );
+        location.barcode = Some(barcode.clone());
+        locations.insert(location.id, Some(&barcode), location.clone());
+        Ok(location)
+    }
+}
+
+impl LabwareStore for InMemoryStorage {
+    async fn find_labware_by_barcode(
+        &mut self,
+        barcode: &str,
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let parsed = BarcodeParser::new(&CONFIG).parse(barcode)?;
+        Ok(self
+            .labwares
+            .read()
+            .unwrap()
+            .find_by_barcode(&parsed.barcode)
+            .filter(|labware| labware.status == LabwareStatus::Active)
+            .cloned()
+            .ok_or(NotFoundError {
+                message: Message::new("labware-not-found"),
+            })?)
+    }
+
+    async fn create_labware(
+        &mut self,
+        barcode: &str,
+        location_id: u32,
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        let locations = self.locations.read().unwrap();
+        let mut labwares = self.labwares.write().unwrap();
+        locations.find(location_id).ok_or(NotFoundError {
+            message: Message::new("location-not-found"),
+        })?;
+        let mut labware = LabwareBuilder::new()
+            .barcode(barcode)
+            .location_id(location_id)
+            .build()?;
+        if labwares.find_by_barcode(&labware.barcode).is_some() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("barcode-in-use").arg("barcode", &labware.barcode),
+            }));
+        }
+        labware.id = labwares.next_id();
+        labwares.insert(labware.id, Some(&labware.barcode), labware.clone());
+        Ok(labware)
+    }
+
+    async fn labwares_in_location(
+        &mut self,
+        location_id: u32,
+    ) -> Result<Vec<Labware>, Box<dyn Error + Send + Sync>> {
+        let mut labwares: Vec<Labware> = self
+            .labwares
+            .read()
+            .unwrap()
+            .rows
+            .values()
+            .filter(|labware| {
+                labware.location_id == location_id && labware.status == LabwareStatus::Active
+            })
+            .cloned()
+            .collect();
+        labwares.sort_by(|a, b| a.barcode.cmp(&b.barcode).then(a.id.cmp(&b.id)));
+        Ok(labwares)
+    }
+}
+
+impl ScanStore for InMemoryStorage {
+    async fn scan(
+        &mut self,
+        location_barcode: &str,
+        labware_barcodes: Vec<String>,
+        _lock_token: Option<&str>,
+        device_id: Option<u32>,
+    ) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+        let parser = BarcodeParser::new(&CONFIG);
+        let mut barcodes: Vec<String> = vec![];
+        for barcode in labware_barcodes {
+            let parsed = parser.parse(&barcode)?;
+            if !barcodes.contains(&parsed.barcode) {
+                barcodes.push(parsed.barcode);
+            }
+        }
+        if barcodes.is_empty() {
+            return Err(Box::new(ValidationError {
+                message: Message::new("scan-no-labwares"),
+            }));
+        }
+
+        let locations = self.locations.read().unwrap();
+        let mut labwares = self.labwares.write().unwrap();
+        let location = locations.find_by_scanned_barcode(location_barcode)?;
+        // Checked before anything is moved, so that either every labware is scanned or none is
+        if let Some(labware) = barcodes
+            .iter()
+            .filter_map(|barcode| labwares.find_by_barcode(barcode))
+            .find(|labware| labware.status != LabwareStatus::Active)
+        {
+            return Err(Box::new(ValidationError {
+                message: Message::new("barcode-in-use").arg("barcode", &labware.barcode),
+            }));
+        }
+
+        let mut scanned = Vec::with_capacity(barcodes.len());
+        for barcode in barcodes {
+            let previous_location = match labwares.barcodes.get(&barcode).copied() {
+                Some(id) => labwares.rows.get_mut(&id).and_then(|labware| {
+                    let previous = std::mem::replace(&mut labware.location_id, location.id);
+                    locations
+                        .find(previous)
+                        .map(|previous| previous.name.clone())
+                }),
+                None => {
+                    let mut labware = LabwareBuilder::new()
+                        .barcode(&barcode)
+                        .location_id(location.id)
+                        .build()?;
+                    labware.id = labwares.next_id();
+                    labwares.insert(labware.id, Some(&barcode), labware);
+                    None
+                }
+            };
+            scanned.push(ScannedLabware {
+                barcode,
+                previous_location,
+                location: location.name.clone(),
+                queued: false,
+            });
+        }
+
+        let summary = Message::new("scan-created")
+            .arg("count", scanned.len())
+            .arg("location", &location.name);
+        let mut scans = self.scans.write().unwrap();
+        let mut scan = Scan {
+            id: scans.next_id(),
+            uuid: new_uuid(),
+            location_id: location.id,
+            message: summary.to_string(),
+            device_id,
+            created_at: Utc::now(),
+            summary: None,
+            misplacements: vec![],
+            location: None,
+            labwares: vec![],
+            warnings: vec![],
+        };
+        // Only what the database would keep is stored, as scans read back have no more
+        scans.insert(scan.id, None, scan.clone());
+        scan.summary = Some(summary);
+        scan.location = Some(ScannedLocation {
+            breadcrumb: locations.breadcrumb(location),
+            name: location.name.clone(),
+            barcode: location.barcode.clone(),
+        });
+        scan.labwares = scanned;
+        Ok(scan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::memory::*;
+
+    #[tokio::test]
+    async fn test_clones_share_storage() {
+        let mut storage = InMemoryStorage::new();
+        let freezer = storage.create_location("freezer1", 1, None).await.unwrap();
+        let barcode = freezer.barcode.unwrap();
+
+        let tasks: Vec<_> = (0..10)
+            .map(|i| {
+                let mut storage = storage.clone();
+                let barcode = barcode.clone();
+                tokio::spawn(async move {
+                    storage
+                        .scan(&barcode, vec![format!("lw-{}", i % 5)], None, None)
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(storage.scans().len(), 10);
+        assert_eq!(
+            storage
+                .labwares_in_location(freezer.id)
+                .await
+                .unwrap()
+                .len(),
+            5
+        );
+
+        assert!(storage.create_labware("lw-1", freezer.id).await.is_err());
+        assert!(storage
+            .create_location("shelf1", 1, Some(99))
+            .await
+            .is_err());
+        let unknown = storage.unknown_location().await.unwrap();
+        assert_eq!(storage.unknown_location().await.unwrap(), unknown);
+    }
+}
@@ -0,0 +1,184 @@
+// Module hierarchy of this module is as follows.
+// lib -> storage -> (descendant e.g., sqlite)
+//
+// Where locations, labwares and scans are kept. Services which only need to look up and move
+// labwares go through these traits rather than a `SqliteConnection`, so they can be given the
+// in-memory store in tests, or another backend later on.
+use crate::errors::NotFoundError;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::scan::Scan;
+use std::error::Error;
+use std::future::Future;
+
+pub mod memory;
+pub mod sqlite;
+
+pub use memory::InMemoryStorage;
+pub use sqlite::SqliteStorage;
+
+/// Keeps locations
+pub trait LocationStore {
+    /// Finds a location by id
+    fn find_location(
+        &mut self,
+        id: u32,
+    ) -> impl Future<Output = Result<Location, NotFoundError>> + Send;
+
+    /// Finds a location by its barcode, which is an `InvalidBarcodeError` if it does not validate
+    fn find_location_by_barcode(
+        &mut self,
+        barcode: &str,
+    ) -> impl Future<Output = Result<Location, Box<dyn Error + Send + Sync>>> + Send;
+
+    /// The location labwares are put in when they are created without one
+    fn unknown_location(
+        &mut self,
+    ) -> impl Future<Output = Result<Location, Box<dyn Error + Send + Sync>>> + Send;
+
+    /// Creates a location, inside of another location if a parent is given, generating its
+    /// barcode
+    fn create_location(
+        &mut self,
+        name: &str,
+        location_type_id: u32,
+        parent_id: Option<u32>,
+    ) -> impl Future<Output = Result<Location, Box<dyn Error + Send + Sync>>> + Send;
+}
+
+/// Keeps labwares
+pub trait LabwareStore {
+    /// Finds an active labware by its barcode, which is an `InvalidBarcodeError` if it does not
+    /// parse
+    fn find_labware_by_barcode(
+        &mut self,
+        barcode: &str,
+    ) -> impl Future<Output = Result<Labware, Box<dyn Error + Send + Sync>>> + Send;
+
+    /// Registers a labware in a location
+    fn create_labware(
+        &mut self,
+        barcode: &str,
+        location_id: u32,
+    ) -> impl Future<Output = Result<Labware, Box<dyn Error + Send + Sync>>> + Send;
+
+    /// Lists the active labwares in a location, by barcode
+    fn labwares_in_location(
+        &mut self,
+        location_id: u32,
+    ) -> impl Future<Output = Result<Vec<Labware>, Box<dyn Error + Send + Sync>>> + Send;
+}
+
+/// Keeps scans
+pub trait ScanStore {
+    /// Scans labwares into a location, registering those which are not known yet. If the location
+    /// is locked, the lock's token has to be given.
+    fn scan(
+        &mut self,
+        location_barcode: &str,
+        labware_barcodes: Vec<String>,
+        lock_token: Option<&str>,
+        device_id: Option<u32>,
+    ) -> impl Future<Output = Result<Scan, Box<dyn Error + Send + Sync>>> + Send;
+}
+
+/// Everything the model layer keeps, as one bound for services e.g. `storage: &mut impl Storage`.
+pub trait Storage: LocationStore + LabwareStore + ScanStore + Send {}
+
+impl<T: LocationStore + LabwareStore + ScanStore + Send> Storage for T {}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::init_db;
+    use crate::errors::{InvalidBarcodeError, ValidationError};
+    use crate::models::location_type::LocationType;
+    use crate::storage::*;
+
+    /// Runs the same steps against a store, which every backend has to agree on
+    async fn exercise(storage: &mut impl Storage, location_type_id: u32) {
+        let freezer = storage
+            .create_location("freezer1", location_type_id, None)
+            .await
+            .unwrap();
+        let shelf = storage
+            .create_location("shelf1", location_type_id, Some(freezer.id))
+            .await
+            .unwrap();
+        assert_eq!(shelf.parent_id, Some(freezer.id));
+        let barcode = shelf.barcode.clone().unwrap();
+        assert_eq!(
+            storage.find_location_by_barcode(&barcode).await.unwrap(),
+            shelf
+        );
+        assert_eq!(storage.find_location(freezer.id).await.unwrap(), freezer);
+        assert!(storage.find_location(999).await.is_err());
+
+        storage.create_labware("lw-1", freezer.id).await.unwrap();
+        let scan = storage
+            .scan(
+                &barcode,
+                vec!["lw-1".to_string(), "lw-2".to_string()],
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(scan.location_id, shelf.id);
+        assert_eq!(scan.message, "2 labwares scanned into shelf1");
+        assert_eq!(
+            scan.labwares[0].previous_location.as_deref(),
+            Some("freezer1")
+        );
+        assert_eq!(scan.labwares[1].previous_location, None);
+        assert_eq!(
+            scan.location.unwrap().breadcrumb,
+            vec!["freezer1", "shelf1"]
+        );
+
+        let labware = storage.find_labware_by_barcode("lw-2").await.unwrap();
+        assert_eq!(labware.location_id, shelf.id);
+        let barcodes: Vec<_> = storage
+            .labwares_in_location(shelf.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|labware| labware.barcode)
+            .collect();
+        assert_eq!(barcodes, vec!["lw-1", "lw-2"]);
+        assert!(storage
+            .labwares_in_location(freezer.id)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(storage.find_labware_by_barcode("lw-3").await.is_err());
+        let error = storage.find_labware_by_barcode("]C").await.unwrap_err();
+        assert!(error.downcast_ref::<InvalidBarcodeError>().is_some());
+        let error = storage.find_location_by_barcode("]C").await.unwrap_err();
+        assert!(error.downcast_ref::<InvalidBarcodeError>().is_some());
+
+        let error = storage
+            .scan(&barcode, vec![], None, None)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<ValidationError>().is_some());
+        let error = storage
+            .scan("lw-nowhere-1", vec!["lw-1".to_string()], None, None)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<NotFoundError>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_storage() {
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let location_type = LocationType::create("Freezer".to_string(), &mut conn)
+            .await
+            .unwrap();
+        exercise(&mut SqliteStorage::new(&mut conn), location_type.id).await;
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_storage() {
+        exercise(&mut InMemoryStorage::new(), 1).await;
+    }
+}
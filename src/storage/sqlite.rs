@@ -0,0 +1,105 @@
+use crate::errors::NotFoundError;
+use crate::models::labware::Labware;
+use crate::models::location::Location;
+use crate::models::scan::Scan;
+use crate::storage::{LabwareStore, LocationStore, ScanStore};
+use sqlx::SqliteConnection;
+use std::error::Error;
+
+/// The storage of the server: the models, kept in SQLite through a connection.
+///
+/// Every method goes through the model of the same name, so the rules of the models (locks,
+/// reservations, registration policies, audits and events) all apply.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::storage::{LabwareStore, SqliteStorage};
+/// let mut connection = acquire(&pool).await.unwrap();
+/// let labware = SqliteStorage::new(&mut connection).find_labware_by_barcode("lw-1").await;
+/// # }
+/// ```
+pub struct SqliteStorage<'c> {
+    connection: &'c mut SqliteConnection,
+}
+
+impl<'c> SqliteStorage<'c> {
+    /// Create a new SqliteStorage on a connection, or a transaction the caller commits
+    pub fn new(connection: &'c mut SqliteConnection) -> SqliteStorage<'c> {
+        SqliteStorage { connection }
+    }
+}
+
+impl LocationStore for SqliteStorage<'_> {
+    async fn find_location(&mut self, id: u32) -> Result<Location, NotFoundError> {
+        Location::find(id, self.connection).await
+    }
+
+    async fn find_location_by_barcode(
+        &mut self,
+        barcode: &str,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        Location::find_by_barcode(barcode.to_string(), self.connection).await
+    }
+
+    async fn unknown_location(&mut self) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        Ok(Location::unknown(self.connection).await?)
+    }
+
+    async fn create_location(
+        &mut self,
+        name: &str,
+        location_type_id: u32,
+        parent_id: Option<u32>,
+    ) -> Result<Location, Box<dyn Error + Send + Sync>> {
+        Ok(Location::create_with_parent(
+            name.to_string(),
+            location_type_id,
+            parent_id,
+            self.connection,
+        )
+        .await?)
+    }
+}
+
+impl LabwareStore for SqliteStorage<'_> {
+    async fn find_labware_by_barcode(
+        &mut self,
+        barcode: &str,
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        Labware::find_by_barcode(barcode.to_string(), self.connection).await
+    }
+
+    async fn create_labware(
+        &mut self,
+        barcode: &str,
+        location_id: u32,
+    ) -> Result<Labware, Box<dyn Error + Send + Sync>> {
+        Labware::create(barcode.to_string(), location_id, self.connection).await
+    }
+
+    async fn labwares_in_location(
+        &mut self,
+        location_id: u32,
+    ) -> Result<Vec<Labware>, Box<dyn Error + Send + Sync>> {
+        Ok(Labware::in_location(location_id, self.connection).await?)
+    }
+}
+
+impl ScanStore for SqliteStorage<'_> {
+    async fn scan(
+        &mut self,
+        location_barcode: &str,
+        labware_barcodes: Vec<String>,
+        lock_token: Option<&str>,
+        device_id: Option<u32>,
+    ) -> Result<Scan, Box<dyn Error + Send + Sync>> {
+        Scan::create(
+            location_barcode.to_string(),
+            labware_barcodes,
+            lock_token,
+            device_id,
+            self.connection,
+        )
+        .await
+    }
+}
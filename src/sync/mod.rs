@@ -0,0 +1,235 @@
+//! Syncing satellite instances with a hub, so that a lab which loses its connection keeps working
+//! and reconciles with the hub when the connection returns.
+//!
+//! Every instance serves a feed of its changes at `GET /sync/changes` and applies the changes of
+//! other instances sent to `POST /sync/changes` with an admin token. A satellite (an instance with
+//! `LABWHERE_SYNC_HUB_URL` set) runs `sync_with_hub`, which pushes the changes made on it to the
+//! hub and pulls the changes made anywhere else from it. How far it got in each direction is kept
+//! in `sync_peers`, so nothing is sent twice and nothing is lost while it is offline.
+//!
+//! Changes carry the name of the instance they were made on (`LABWHERE_INSTANCE_NAME`), so they
+//! are never sent back to it. When a labware was changed on both sides while they were apart, the
+//! conflict is resolved by `LABWHERE_SYNC_CONFLICT_POLICY`: the latest change wins, or the change
+//! is queued for an admin to review at `GET /sync/conflicts`. Either way the conflict is logged.
+use crate::config::{Config, CONFIG};
+use crate::metrics::acquire;
+use crate::models::change::{Change, ChangeFeed, Origins, SyncReport};
+use crate::models::sync_peer::SyncPeer;
+use chrono::Utc;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::error::Error;
+use std::time::Duration;
+
+/// The most changes sent or read in one request.
+pub const BATCH_SIZE: u32 = 500;
+
+/// The HTTP client changes are synced with.
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap_or_default()
+});
+
+/// What a sync with the hub did.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Synced {
+    /// How many local changes were sent to the hub
+    pub pushed: u32,
+    /// What came of applying the changes read from the hub
+    pub pulled: SyncReport,
+}
+
+/// Pushes the changes made on this instance to the hub, then pulls and applies the changes made
+/// anywhere else from it.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// let mut connection = init_db("sqlite::memory:").await.unwrap();
+/// let synced = sync(&CONFIG, "https://labwhere.example.com", &mut connection).await.unwrap();
+/// # }
+/// ```
+pub async fn sync(
+    config: &Config,
+    hub_url: &str,
+    connection: &mut SqliteConnection,
+) -> Result<Synced, Box<dyn Error + Send + Sync>> {
+    let mut peer = SyncPeer::find(hub_url, &mut *connection).await?;
+    let mut synced = Synced::default();
+    let url = format!("{}/sync/changes", hub_url);
+
+    loop {
+        let feed = Change::feed(
+            peer.pushed_audit_id,
+            BATCH_SIZE,
+            &config.instance_name,
+            Origins::Local,
+            &mut *connection,
+        )
+        .await?;
+        if !feed.changes.is_empty() {
+            let push = match &config.sync_admin_token {
+                Some(token) => CLIENT.post(&url).header("X-Admin-Token", token),
+                None => CLIENT.post(&url),
+            };
+            let report: Value = request(push, config)
+                .json(&json!({ "changes": feed.changes }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            for rejection in report["rejected"].as_array().into_iter().flatten() {
+                warn!(
+                    "The hub could not apply change {}: {}",
+                    rejection["uuid"], rejection["error"]
+                );
+            }
+            synced.pushed += feed.changes.len() as u32;
+        }
+        peer.pushed_audit_id = feed.cursor;
+        peer.save(&mut *connection).await?;
+        if (feed.changes.len() as u32) < BATCH_SIZE {
+            break;
+        }
+    }
+
+    loop {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("since", &peer.pulled_audit_id.to_string())
+            .append_pair("peer", &config.instance_name)
+            .append_pair("limit", &BATCH_SIZE.to_string())
+            .finish();
+        let feed: ChangeFeed = request(CLIENT.get(format!("{}?{}", url, query)), config)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let report =
+            Change::apply(&feed.changes, config.sync_conflict_policy, &mut *connection).await?;
+        for rejection in &report.rejected {
+            warn!(
+                "Could not apply change {} from the hub: {}",
+                rejection.uuid, rejection.message
+            );
+        }
+        synced.pulled.applied += report.applied;
+        synced.pulled.skipped += report.skipped;
+        synced.pulled.queued += report.queued;
+        synced.pulled.rejected.extend(report.rejected);
+        peer.pulled_audit_id = feed.cursor;
+        peer.save(&mut *connection).await?;
+        if (feed.changes.len() as u32) < BATCH_SIZE {
+            break;
+        }
+    }
+
+    peer.synced_at = Some(Utc::now());
+    peer.save(connection).await?;
+    Ok(synced)
+}
+
+/// Adds the API key the hub requires, if any, to a request.
+fn request(builder: reqwest::RequestBuilder, config: &Config) -> reqwest::RequestBuilder {
+    match &config.sync_api_key {
+        Some(key) => builder.header("X-Api-Key", key),
+        None => builder,
+    }
+}
+
+/// Syncs with the hub every `LABWHERE_SYNC_SECONDS`, forever.
+///
+/// Meant to be spawned once when the server of a satellite starts. While the hub cannot be
+/// reached, failures are logged and the sync is retried.
+pub async fn sync_with_hub(pool: SqlitePool) {
+    let Some(hub_url) = CONFIG.sync_hub_url.clone() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.sync_seconds.max(1)));
+    loop {
+        interval.tick().await;
+        let synced = match acquire(&pool).await {
+            Ok(mut connection) => sync(&CONFIG, &hub_url, &mut connection).await,
+            Err(e) => Err(e.into()),
+        };
+        match synced {
+            Ok(synced)
+                if synced.pushed == 0 && synced.pulled.applied == 0 && synced.pulled.queued == 0 => {}
+            Ok(synced) => info!(
+                "Synced with {}: pushed {} changes, applied {} changes, queued {} conflicts for review",
+                hub_url, synced.pushed, synced.pulled.applied, synced.pulled.queued
+            ),
+            Err(e) => warn!("Could not sync with {}: {}", hub_url, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::db::init_db;
+    use crate::models::location::Location;
+    use crate::models::sync_peer::SyncPeer;
+    use crate::sync::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_sync_pulls_changes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let hub_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut chunk = [0; 4096];
+            let read = socket.read(&mut chunk).await.unwrap();
+            let body = json!({
+                "changes": [{
+                    "uuid": "4b1c6a0e-0000-4000-8000-000000000001",
+                    "origin": "hub",
+                    "record_type": "Location",
+                    "action": "create",
+                    "record_uuid": "4b1c6a0e-0000-4000-8000-000000000002",
+                    "barcode": "lw-freezer-7",
+                    "location_uuid": null,
+                    "name": "freezer",
+                    "location_type": "Freezer",
+                    "created_at": "2026-10-01T09:00:00Z",
+                }],
+                "cursor": 42,
+            })
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&chunk[..read]).to_string()
+        });
+
+        let mut conn = init_db("sqlite::memory:").await.unwrap();
+        let config = Config {
+            instance_name: "satellite".to_string(),
+            ..Default::default()
+        };
+        let synced = sync(&config, &hub_url, &mut conn).await.unwrap();
+        assert_eq!(synced.pushed, 0);
+        assert_eq!(synced.pulled.applied, 1);
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("GET /sync/changes?since=0&peer=satellite&limit=500"));
+
+        let location = Location::find_by_barcode("lw-freezer-7".to_string(), &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(location.uuid, "4b1c6a0e-0000-4000-8000-000000000002");
+        assert_eq!(location.origin.as_deref(), Some("hub"));
+        let peer = SyncPeer::find(&hub_url, &mut conn).await.unwrap();
+        assert_eq!(peer.pulled_audit_id, 42);
+        assert!(peer.synced_at.is_some());
+    }
+}
@@ -0,0 +1,184 @@
+//! Rendering of timestamps for people.
+//!
+//! Timestamps are stored as UTC and sent in JSON as RFC 3339. Anything read by people in the lab
+//! (HTML views, CSV exports and messages) shows them in the configured display timezone instead,
+//! so that they match the lab's wall clock.
+//!
+//! Timestamps sent by clients (e.g. when an offline scan was scanned) come from clocks which may be
+//! wrong, so they are checked against the server's clock with `normalize`.
+use crate::config::{Config, CONFIG};
+use crate::errors::ValidationError;
+use crate::i18n::Message;
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+
+/// How timestamps are stored in the database (as by `CURRENT_TIMESTAMP`), so that timestamps given
+/// in queries compare with the stored ones.
+pub const STORAGE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The format of displayed timestamps e.g. `2024-06-01 14:30:00 BST`.
+pub const DISPLAY_FORMAT: &str = "%Y-%m-%d %H:%M:%S %Z";
+
+/// Formats a timestamp in the configured display timezone.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::timestamps;
+/// let shown = timestamps::display(&audit.created_at);
+/// # }
+/// ```
+pub fn display(timestamp: &DateTime<Utc>) -> String {
+    display_in(timestamp, CONFIG.display_timezone)
+}
+
+/// Formats a timestamp in the given timezone.
+pub fn display_in(timestamp: &DateTime<Utc>, timezone: Tz) -> String {
+    timestamp
+        .with_timezone(&timezone)
+        .format(DISPLAY_FORMAT)
+        .to_string()
+}
+
+/// What happens to a timestamp sent by a client which is further from the server's clock than the
+/// configured skew allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SkewPolicy {
+    /// The timestamp is moved to the nearest time allowed, with a warning
+    #[default]
+    Clamp,
+    /// The timestamp is refused with a `ValidationError`
+    Reject,
+}
+
+impl SkewPolicy {
+    /// Parses a skew policy from its name e.g. `reject`.
+    pub fn from_name(name: &str) -> Option<SkewPolicy> {
+        match name.trim().to_lowercase().as_str() {
+            "clamp" => Some(SkewPolicy::Clamp),
+            "reject" => Some(SkewPolicy::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// A timestamp sent by a client, within the skew the configuration allows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Normalized {
+    /// The timestamp, clamped if it was too far from the server's clock
+    pub timestamp: DateTime<Utc>,
+    /// Why the timestamp was clamped, if it was
+    pub warning: Option<Message>,
+}
+
+/// Checks a timestamp sent by a client against the server's clock, allowing it to be up to
+/// `timestamp_max_ahead_seconds` ahead and `timestamp_max_behind_seconds` behind. A timestamp
+/// beyond either is clamped or rejected according to `timestamp_skew_policy`.
+/// # Examples
+/// ```
+/// # #[cfg(doctest)] {
+/// use labwhere::timestamps;
+/// let scanned_at = timestamps::normalize(offline_scan.scanned_at, &CONFIG)?.timestamp;
+/// # }
+/// ```
+pub fn normalize(timestamp: DateTime<Utc>, config: &Config) -> Result<Normalized, ValidationError> {
+    normalize_at(timestamp, Utc::now(), config)
+}
+
+/// Checks a timestamp sent by a client against the given time on the server's clock.
+pub fn normalize_at(
+    timestamp: DateTime<Utc>,
+    now: DateTime<Utc>,
+    config: &Config,
+) -> Result<Normalized, ValidationError> {
+    let max_ahead = config.timestamp_max_ahead_seconds;
+    let max_behind = config.timestamp_max_behind_seconds;
+    let (bound, key, seconds) = if timestamp > now + Duration::seconds(max_ahead as i64) {
+        (
+            now + Duration::seconds(max_ahead as i64),
+            "timestamp-too-far-ahead",
+            max_ahead,
+        )
+    } else if timestamp < now - Duration::seconds(max_behind as i64) {
+        (
+            now - Duration::seconds(max_behind as i64),
+            "timestamp-too-far-behind",
+            max_behind,
+        )
+    } else {
+        return Ok(Normalized {
+            timestamp,
+            warning: None,
+        });
+    };
+    match config.timestamp_skew_policy {
+        SkewPolicy::Reject => Err(ValidationError {
+            message: Message::new(key)
+                .arg("timestamp", display_in(&timestamp, config.display_timezone))
+                .arg("seconds", seconds),
+        }),
+        SkewPolicy::Clamp => Ok(Normalized {
+            timestamp: bound,
+            warning: Some(
+                Message::new("timestamp-clamped")
+                    .arg("timestamp", display_in(&timestamp, config.display_timezone))
+                    .arg("clamped", display_in(&bound, config.display_timezone)),
+            ),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::timestamps::*;
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::Tz;
+
+    #[test]
+    fn test_display_in() {
+        let summer = Utc.with_ymd_and_hms(2024, 6, 1, 13, 30, 0).unwrap();
+        let winter = Utc.with_ymd_and_hms(2024, 12, 1, 13, 30, 0).unwrap();
+
+        assert_eq!(display_in(&summer, Tz::UTC), "2024-06-01 13:30:00 UTC");
+        assert_eq!(
+            display_in(&summer, Tz::Europe__London),
+            "2024-06-01 14:30:00 BST"
+        );
+        assert_eq!(
+            display_in(&winter, Tz::Europe__London),
+            "2024-12-01 13:30:00 GMT"
+        );
+    }
+
+    #[test]
+    fn test_normalize_at() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let mut config = Config::from_env();
+        config.timestamp_max_ahead_seconds = 300;
+        config.timestamp_max_behind_seconds = 3600;
+        config.display_timezone = Tz::UTC;
+
+        let earlier = now - Duration::minutes(30);
+        let normalized = normalize_at(earlier, now, &config).unwrap();
+        assert_eq!(normalized.timestamp, earlier);
+        assert_eq!(normalized.warning, None);
+
+        let normalized = normalize_at(now + Duration::hours(2), now, &config).unwrap();
+        assert_eq!(normalized.timestamp, now + Duration::minutes(5));
+        assert_eq!(
+            normalized.warning.unwrap().to_string(),
+            "2024-06-01 14:00:00 UTC is outside of the allowed clock skew and was recorded as 2024-06-01 12:05:00 UTC"
+        );
+        let normalized = normalize_at(now - Duration::days(2), now, &config).unwrap();
+        assert_eq!(normalized.timestamp, now - Duration::hours(1));
+
+        config.timestamp_skew_policy = SkewPolicy::Reject;
+        let error = normalize_at(now + Duration::hours(2), now, &config).unwrap_err();
+        assert_eq!(
+            error.message.to_string(),
+            "2024-06-01 14:00:00 UTC is more than 300 seconds ahead of the server's clock"
+        );
+        assert!(normalize_at(now - Duration::days(2), now, &config).is_err());
+        assert!(normalize_at(now + Duration::minutes(1), now, &config).is_ok());
+    }
+}
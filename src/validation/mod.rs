@@ -0,0 +1,257 @@
+//! Validation of names which does not depend on the database, shared by the models and usable on
+//! its own, e.g. to check a name before sending it.
+
+use crate::barcode::check_digit;
+use crate::barcode::signature;
+use crate::config::CONFIG;
+use crate::errors::FieldValidationError;
+use crate::i18n::Message;
+use crate::models::location::UNKNOWN_LOCATION_NAME;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// The longest a location name can be by default, in characters.
+pub const LOCATION_NAME_MAX_LENGTH: u64 = 60;
+
+/// The format location names follow by default: alphanumeric characters, hyphens, spaces, and
+/// parentheses.
+pub const LOCATION_NAME_PATTERN: &str = r"[\w\-\s()]+";
+
+/// A format names must match, e.g. `[A-Z]{3}-\d+`.
+#[derive(Debug, Clone)]
+pub struct NameFormat {
+    /// The pattern as it was configured
+    pub pattern: String,
+    /// The pattern, anchored so that it matches whole names
+    regex: Regex,
+}
+
+impl NameFormat {
+    /// Compiles a pattern, which must match the whole of a name rather than a part of it.
+    ///
+    /// # Examples
+    /// ```
+    /// use labwhere::validation::NameFormat;
+    /// let format = NameFormat::new(r"[A-Z]{3}-\d+").unwrap();
+    /// assert!(format.is_match("FRZ-1"));
+    /// assert!(!format.is_match("FRZ-1 top"));
+    /// ```
+    pub fn new(pattern: &str) -> Result<NameFormat, regex::Error> {
+        Ok(NameFormat {
+            pattern: pattern.to_string(),
+            regex: Regex::new(&format!(r"\A(?:{})\z", pattern))?,
+        })
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+impl PartialEq for NameFormat {
+    fn eq(&self, other: &NameFormat) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+/// The rules the names of an entity follow, which sites with other naming conventions can
+/// configure. See `Config::location_name_rules`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameRules {
+    /// The format names must match, if any
+    pub format: Option<NameFormat>,
+    /// The shortest a name can be, in characters
+    pub min_length: u64,
+    /// The longest a name can be, in characters
+    pub max_length: u64,
+    /// Names which cannot be used, regardless of case
+    pub reserved: Vec<String>,
+}
+
+impl NameRules {
+    /// The rules location names follow unless configured otherwise.
+    pub fn location() -> NameRules {
+        NameRules {
+            format: NameFormat::new(LOCATION_NAME_PATTERN).ok(),
+            ..NameRules::location_type()
+        }
+    }
+
+    /// The rules location type names follow unless configured otherwise.
+    pub fn location_type() -> NameRules {
+        NameRules {
+            format: None,
+            min_length: 1,
+            max_length: LOCATION_NAME_MAX_LENGTH,
+            // Nothing else may be called what the unknown location and its location type are
+            reserved: vec![UNKNOWN_LOCATION_NAME.to_string()],
+        }
+    }
+
+    /// Validates a name, reporting every rule it breaks on the `name` field.
+    ///
+    /// # Examples
+    /// ```
+    /// use labwhere::validation::NameRules;
+    /// let rules = NameRules::location();
+    /// assert!(rules.validate("Freezer (1)").is_ok());
+    /// assert!(rules.validate("unknown").is_err());
+    /// ```
+    pub fn validate(&self, name: &str) -> Result<(), FieldValidationError> {
+        let mut messages = vec![];
+        let length = name.chars().count() as u64;
+        if length < self.min_length || length > self.max_length {
+            messages.push(
+                Message::new("validation-length")
+                    .arg("min", self.min_length)
+                    .arg("max", self.max_length),
+            );
+        }
+        if let Some(format) = self.format.as_ref().filter(|format| !format.is_match(name)) {
+            messages.push(if format.pattern == LOCATION_NAME_PATTERN {
+                Message::new("validation-location-name-format")
+            } else {
+                Message::new("validation-name-format").arg("format", &format.pattern)
+            });
+        }
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(name.trim()))
+        {
+            messages.push(Message::new("validation-name-reserved").arg("name", name));
+        }
+        if messages.is_empty() {
+            return Ok(());
+        }
+        Err(FieldValidationError {
+            fields: BTreeMap::from([("name".to_string(), messages)]),
+        })
+    }
+}
+
+impl Default for NameRules {
+    fn default() -> NameRules {
+        NameRules::location_type()
+    }
+}
+
+/// Validates the name of a location against the configured rules, with the same messages as
+/// `LocationBuilder::build`.
+///
+/// # Examples
+/// ```
+/// use labwhere::validation::location_name;
+/// assert!(location_name("Freezer (1)").is_ok());
+/// assert!(location_name("Freezer 1.2").is_err());
+/// ```
+pub fn location_name(name: &str) -> Result<(), FieldValidationError> {
+    CONFIG.location_name_rules.validate(name)
+}
+
+/// Splits a barcode in the format location barcodes are generated in, `lw-{name}-{id}` signed and
+/// with a check character if configured, into the unsigned barcode and the id of the location it
+/// was generated for. Labware must not carry such a barcode for a location which exists, see
+/// `Location::ensure_barcode_not_reserved`.
+///
+/// # Examples
+/// ```
+/// use labwhere::validation::generated_location_barcode;
+/// assert_eq!(generated_location_barcode("lw-freezer-1"), Some(("lw-freezer-1", 1)));
+/// assert_eq!(generated_location_barcode("LW-shelf-a-12-3f9a2c1b.e"), Some(("LW-shelf-a-12", 12)));
+/// assert_eq!(generated_location_barcode("lw-1"), None);
+/// assert_eq!(generated_location_barcode("trac-1"), None);
+/// ```
+pub fn generated_location_barcode(barcode: &str) -> Option<(&str, u32)> {
+    let barcode = check_digit::split(barcode).map_or(barcode, |(payload, _)| payload);
+    let (rest, last) = barcode.rsplit_once(signature::SEPARATOR)?;
+    let signed = last.len() == signature::SIGNATURE_LENGTH
+        && last.chars().all(|c| c.is_ascii_hexdigit())
+        && rest
+            .rsplit_once(signature::SEPARATOR)
+            .is_some_and(|(_, id)| id.parse::<u32>().is_ok());
+    let unsigned = if signed { rest } else { barcode };
+    let (name, id) = unsigned.rsplit_once('-')?;
+    let id = id.parse().ok()?;
+    let prefix = name.get(..3)?;
+    if !prefix.eq_ignore_ascii_case("lw-") || name.len() == prefix.len() {
+        return None;
+    }
+    Some((unsigned, id))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::validation::*;
+    use proptest::prelude::*;
+
+    /// Names which are valid for a location.
+    pub(crate) fn location_names() -> impl Strategy<Value = String> {
+        "[\\w\\-\\s()]{1,60}"
+    }
+
+    #[test]
+    fn test_location_name() {
+        assert!(location_name("Shelf 1 (top)").is_ok());
+        assert!(location_name(&"a".repeat(60)).is_ok());
+
+        let error = location_name("").unwrap_err();
+        assert_eq!(error.fields["name"][0].key, "validation-length");
+        let error = location_name(&"a".repeat(61)).unwrap_err();
+        assert_eq!(error.fields["name"][0].key, "validation-length");
+        let error = location_name("shelf/1").unwrap_err();
+        assert_eq!(
+            error.fields["name"][0].key,
+            "validation-location-name-format"
+        );
+    }
+
+    #[test]
+    fn test_name_rules() {
+        let rules = NameRules {
+            format: NameFormat::new(r"[A-Z]{3}-\d+").ok(),
+            min_length: 5,
+            max_length: 10,
+            reserved: vec!["BIN-0".to_string()],
+        };
+        assert!(rules.validate("FRZ-12").is_ok());
+
+        let error = rules.validate("frz-1").unwrap_err();
+        assert_eq!(error.fields["name"][0].key, "validation-name-format");
+        assert_eq!(error.to_string(), r"Name must match [A-Z]{3}-\d+");
+        let error = rules.validate("FRZ").unwrap_err();
+        assert_eq!(error.fields["name"].len(), 2);
+        assert_eq!(error.fields["name"][0].key, "validation-length");
+        let error = rules.validate("bin-0").unwrap_err();
+        assert_eq!(error.fields["name"][0].key, "validation-name-format");
+        assert_eq!(error.fields["name"][1].key, "validation-name-reserved");
+
+        let error = NameRules::location().validate(" Unknown").unwrap_err();
+        assert_eq!(error.fields["name"][0].key, "validation-name-reserved");
+        assert!(NameRules::location_type()
+            .validate("Freezer/Fridge")
+            .is_ok());
+    }
+
+    proptest! {
+        #[test]
+        fn test_valid_names_are_accepted(name in location_names()) {
+            prop_assert!(location_name(&name).is_ok());
+        }
+
+        #[test]
+        fn test_names_with_other_characters_are_rejected(
+            before in "[\\w ]{0,20}",
+            other in "[^\\w\\-\\s()]",
+            after in "[\\w ]{0,20}",
+        ) {
+            let name = format!("{}{}{}", before, other, after);
+            prop_assert!(location_name(&name).is_err());
+        }
+
+        #[test]
+        fn test_long_names_are_rejected(name in "[a-z]{61,100}") {
+            prop_assert!(location_name(&name).is_err());
+        }
+    }
+}